@@ -0,0 +1,70 @@
+use criterion::{BenchmarkId, black_box, Criterion, criterion_group, criterion_main};
+
+use hafen_bot::bot::bench::{PathFindingFixture, Vec2i};
+
+const CONFIG_PATH: &str = "etc/config.yaml";
+const MAX_ITERATIONS: usize = 1000000;
+const MAX_SHORTCUT_LENGTH: f64 = 25.0;
+
+struct Scenario {
+    name: &'static str,
+    updates_path: &'static str,
+    profile: &'static str,
+    destinations: &'static [(&'static str, Vec2i)],
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "lake",
+        updates_path: "tests/input/init_session_lake.json",
+        profile: "water",
+        destinations: &[("shore", Vec2i::new(-890, -977))],
+    },
+    Scenario {
+        name: "start",
+        updates_path: "tests/input/init_session_start.json",
+        profile: "water",
+        destinations: &[("north_east", Vec2i::new(-894, -916)), ("south_east", Vec2i::new(-884, -886))],
+    },
+];
+
+fn path_finding(c: &mut Criterion) {
+    let mut find_group = c.benchmark_group("find_reversed_tiles_path");
+    let fixtures: Vec<(&Scenario, PathFindingFixture)> = SCENARIOS.iter()
+        .map(|scenario| (scenario, PathFindingFixture::new(CONFIG_PATH, scenario.updates_path, scenario.profile).unwrap()))
+        .collect();
+    for (scenario, fixture) in fixtures.iter() {
+        for (name, dst) in scenario.destinations.iter() {
+            let src = fixture.player_tile_pos();
+            find_group.bench_with_input(BenchmarkId::new(scenario.name, name), dst, |b, dst| {
+                b.iter(|| fixture.find_reversed_tiles_path(black_box(src), black_box(*dst), MAX_ITERATIONS))
+            });
+        }
+    }
+    find_group.finish();
+    let mut shorten_group = c.benchmark_group("shorten_reversed_tiles_path");
+    for (scenario, fixture) in fixtures.iter() {
+        for (name, dst) in scenario.destinations.iter() {
+            let path = fixture.find_reversed_tiles_path(fixture.player_tile_pos(), *dst, MAX_ITERATIONS);
+            assert!(!path.is_empty(), "Path for {}/{} is not found", scenario.name, name);
+            shorten_group.bench_with_input(BenchmarkId::new(scenario.name, name), &path, |b, path| {
+                b.iter(|| fixture.shorten_reversed_tiles_path(black_box(path.clone()), MAX_SHORTCUT_LENGTH))
+            });
+        }
+    }
+    shorten_group.finish();
+    let mut areas_group = c.benchmark_group("make_areas");
+    for (scenario, fixture) in fixtures.iter() {
+        areas_group.bench_function(BenchmarkId::new(scenario.name, scenario.profile), |b| {
+            b.iter(|| fixture.make_areas())
+        });
+    }
+    areas_group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = path_finding
+}
+criterion_main!(benches);
@@ -3,3 +3,4 @@ pub mod use_item;
 pub mod take_item;
 pub mod put_item;
 pub mod move_item;
+pub mod use_object;
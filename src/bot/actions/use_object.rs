@@ -0,0 +1,156 @@
+use std::time::{Duration, Instant};
+
+use crate::bot::map::pos_to_map_pos;
+use crate::bot::protocol::{Button, Event, Message, Modifier, Update, Value};
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::PlayerWorld;
+
+pub struct UseObject {
+    object_id: i64,
+    position: Vec2f,
+    action_name: String,
+    timeout: Duration,
+    last_message: Option<Instant>,
+    action: Option<UseObjectAction>,
+    missing_action: Option<i32>,
+    ready: bool,
+    done: bool,
+    failed: bool,
+    locked: bool,
+}
+
+impl UseObject {
+    pub fn new(object_id: i64, position: Vec2f, action_name: String, timeout: Duration) -> Self {
+        debug!("UseObject object_id={} action_name={}", object_id, action_name);
+        Self {
+            object_id,
+            position,
+            action_name,
+            timeout,
+            last_message: None,
+            action: None,
+            missing_action: None,
+            ready: false,
+            done: false,
+            failed: false,
+            locked: false,
+        }
+    }
+
+    pub fn get_next_message(&mut self, world: &PlayerWorld) -> Option<Message> {
+        if self.done {
+            debug!("UseObject object_id={} action_name={}: done", self.object_id, self.action_name);
+            return Some(Message::Done { task: String::from("UseObject") });
+        }
+        if self.failed {
+            return Some(Message::Error { message: format!("action {:?} is not found", self.action_name) });
+        }
+        if let Some(menu_id) = self.missing_action.take() {
+            self.failed = true;
+            debug!("UseObject object_id={} action_name={}: close menu", self.object_id, self.action_name);
+            return Some(Message::WidgetMessage {
+                sender: menu_id,
+                kind: String::from("cl"),
+                arguments: vec![Value::from(-1i32)],
+            });
+        }
+        let now = Instant::now();
+        if let Some(action) = self.action.as_ref() {
+            if !self.ready {
+                debug!("UseObject object_id={} action_name={}: not ready", self.object_id, self.action_name);
+                return None;
+            }
+            if self.last_message.map(|v| now - v < self.timeout).unwrap_or(false) {
+                debug!("UseObject object_id={} action_name={}: wait apply action", self.object_id, self.action_name);
+                return None;
+            }
+            self.last_message = Some(now);
+            debug!("UseObject object_id={} action_name={}: apply action", self.object_id, self.action_name);
+            Some(Message::WidgetMessage {
+                sender: action.id,
+                kind: String::from("cl"),
+                arguments: vec![Value::from(action.index), Value::from(0i32)],
+            })
+        } else {
+            if !self.locked {
+                self.locked = true;
+                debug!("UseObject object_id={} action_name={}: lock sm", self.object_id, self.action_name);
+                return Some(Message::LockWidget { value: String::from("sm") });
+            }
+            if self.last_message.map(|v| now - v < self.timeout).unwrap_or(false) {
+                debug!("UseObject object_id={} action_name={}: wait get action", self.object_id, self.action_name);
+                return None;
+            }
+            self.last_message = Some(now);
+            debug!("UseObject object_id={} action_name={}: get action", self.object_id, self.action_name);
+            Some(Message::WidgetMessage {
+                sender: world.map_view_id(),
+                kind: String::from("click"),
+                arguments: vec![
+                    Value::from(Vec2i::zero()),
+                    Value::from(pos_to_map_pos(self.position)),
+                    Value::from(Button::RightClick),
+                    Value::from(Modifier::None),
+                    Value::from(0i32),
+                    Value::from(self.object_id as i32),
+                    Value::from(pos_to_map_pos(self.position)),
+                    Value::from(0i32),
+                    Value::from(0i32),
+                ],
+            })
+        }
+    }
+
+    pub fn update(&mut self, update: &Update) {
+        if self.done || self.failed {
+            return;
+        }
+        match &update.event {
+            Event::NewWidget { id, kind, parent: _, pargs: _, cargs } => {
+                if kind == "sm" && !cargs.is_empty() && self.last_message.is_some() {
+                    self.action = cargs.iter()
+                        .enumerate()
+                        .find(|(_, v)| **v == self.action_name)
+                        .map(|(i, _)| UseObjectAction { id: *id, index: i as i32 });
+                    if self.action.is_none() {
+                        self.missing_action = Some(*id);
+                    }
+                    self.ready = false;
+                    self.last_message = None;
+                    debug!("UseObject object_id={} action_name={}: choose action={:?}", self.object_id, self.action_name, self.action);
+                }
+            }
+            Event::AddWidget { id, parent: _, pargs: _ } => {
+                if self.action.as_ref().map(|v| v.id == *id).unwrap_or(false) {
+                    self.ready = true;
+                    self.last_message = None;
+                    debug!("UseObject object_id={} action_name={}: ready", self.object_id, self.action_name);
+                }
+            }
+            Event::UIMessage { id, msg, args: _ } => {
+                if self.action.as_ref().map(|v| v.id == *id).unwrap_or(false) {
+                    match msg.as_str() {
+                        "act" => {
+                            debug!("UseObject object_id={} action_name={}: set done", self.object_id, self.action_name);
+                            self.done = true;
+                        }
+                        "cancel" => {
+                            debug!("UseObject object_id={} action_name={}: cancel", self.object_id, self.action_name);
+                            self.action = None;
+                            self.ready = false;
+                            self.last_message = None;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct UseObjectAction {
+    id: i32,
+    index: i32,
+}
@@ -0,0 +1,176 @@
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::bot::protocol::Message;
+use crate::bot::rate_limiter::{RateLimitConfig, RateLimiter};
+use crate::bot::stuck_recovery::StuckRecoveryOutcome;
+
+#[derive(Clone, Deserialize)]
+pub struct AlertingConfig {
+    pub webhooks: Vec<WebhookConfig>,
+    pub rate_limit: RateLimitConfig,
+    pub connection_lost_timeout: f64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub format: WebhookFormat,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum WebhookFormat {
+    Discord,
+    Slack,
+    Generic,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum AlertKind {
+    PlayerStuck,
+    HostileSpotted,
+    TaskFailed,
+    ConnectionLost,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Alert {
+    pub session: i64,
+    pub kind: AlertKind,
+    pub message: String,
+}
+
+pub struct Alerter {
+    sender: Mutex<Sender<Alert>>,
+    connection_lost_timeout: Duration,
+}
+
+impl Alerter {
+    pub fn connection_lost_timeout(&self) -> Duration {
+        self.connection_lost_timeout
+    }
+
+    pub fn send(&self, alert: Alert) {
+        debug!("Alerter: send {:?}", alert);
+        if let Err(e) = self.sender.lock().unwrap().send(alert) {
+            error!("Failed to send alert: {}", e);
+        }
+    }
+
+    pub fn send_message(&self, session_id: i64, message: &Message) {
+        if let Some(alert) = make_alert(session_id, message) {
+            self.send(alert);
+        }
+    }
+}
+
+pub fn start_alerting(config: AlertingConfig) -> Alerter {
+    let (sender, receiver) = channel();
+    let connection_lost_timeout = Duration::from_secs_f64(config.connection_lost_timeout);
+    spawn(move || post_alerts(receiver, config));
+    Alerter { sender: Mutex::new(sender), connection_lost_timeout }
+}
+
+fn post_alerts(receiver: Receiver<Alert>, config: AlertingConfig) {
+    let client = reqwest::blocking::Client::new();
+    let mut rate_limiter = RateLimiter::new(&config.rate_limit);
+    while let Ok(alert) = receiver.recv() {
+        if config.webhooks.is_empty() {
+            continue;
+        }
+        let now = Instant::now();
+        if !rate_limiter.is_available(now) {
+            warn!("Alert is throttled: {:?}", alert);
+            continue;
+        }
+        rate_limiter.add_message(now);
+        for webhook in config.webhooks.iter() {
+            let result = client.post(webhook.url.as_str())
+                .json(&make_webhook_body(webhook.format, &alert))
+                .send()
+                .and_then(|v| v.error_for_status());
+            if let Err(e) = result {
+                warn!("Failed to post alert to {}: {}", webhook.url, e);
+            }
+        }
+    }
+}
+
+pub fn make_alert(session_id: i64, message: &Message) -> Option<Alert> {
+    let (kind, message) = match message {
+        Message::Alert { object_id, name, distance } => {
+            (AlertKind::HostileSpotted, format!("{} {} is spotted at distance {:.1}", name, object_id, distance))
+        }
+        Message::StuckRecovery { outcome: StuckRecoveryOutcome::Failed, actions } => {
+            (AlertKind::PlayerStuck, format!("player is stuck after {:?}", actions))
+        }
+        Message::Error { message } => (AlertKind::TaskFailed, message.clone()),
+        _ => return None,
+    };
+    Some(Alert { session: session_id, kind, message })
+}
+
+fn make_webhook_body(format: WebhookFormat, alert: &Alert) -> Value {
+    let text = format!("Session {}: {:?}: {}", alert.session, alert.kind, alert.message);
+    match format {
+        WebhookFormat::Discord => json!({"content": text}),
+        WebhookFormat::Slack => json!({"text": text}),
+        WebhookFormat::Generic => serde_json::to_value(alert).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::stuck_recovery::StuckRecoveryAction;
+
+    use super::*;
+
+    #[test]
+    fn make_alert_should_convert_only_alerting_messages() {
+        assert_eq!(
+            make_alert(1, &Message::Alert { object_id: 2, name: String::from("gfx/kritter/bear/bear"), distance: 100.0 }),
+            Some(Alert {
+                session: 1,
+                kind: AlertKind::HostileSpotted,
+                message: String::from("gfx/kritter/bear/bear 2 is spotted at distance 100.0"),
+            })
+        );
+        assert_eq!(
+            make_alert(1, &Message::StuckRecovery {
+                outcome: StuckRecoveryOutcome::Failed,
+                actions: vec![StuckRecoveryAction::RandomMove],
+            }).map(|v| v.kind),
+            Some(AlertKind::PlayerStuck)
+        );
+        assert_eq!(
+            make_alert(1, &Message::StuckRecovery {
+                outcome: StuckRecoveryOutcome::Recovered,
+                actions: vec![StuckRecoveryAction::RandomMove],
+            }),
+            None
+        );
+        assert_eq!(make_alert(1, &Message::Ok), None);
+    }
+
+    #[test]
+    fn make_webhook_body_should_match_format() {
+        let alert = Alert { session: 1, kind: AlertKind::ConnectionLost, message: String::from("no updates") };
+        assert_eq!(
+            make_webhook_body(WebhookFormat::Discord, &alert),
+            json!({"content": "Session 1: ConnectionLost: no updates"})
+        );
+        assert_eq!(
+            make_webhook_body(WebhookFormat::Slack, &alert),
+            json!({"text": "Session 1: ConnectionLost: no updates"})
+        );
+        assert_eq!(
+            make_webhook_body(WebhookFormat::Generic, &alert),
+            json!({"session": 1, "kind": "ConnectionLost", "message": "no updates"})
+        );
+    }
+}
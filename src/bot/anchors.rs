@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::map::{grid_pos_to_pos, Map};
+use crate::bot::map_db::MapDb;
+use crate::bot::vec2::Vec2f;
+
+#[derive(Clone, Deserialize)]
+pub struct AnchorsConfig {
+    pub grids: Vec<i64>,
+    pub locations: BTreeMap<String, Vec2f>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Anchor {
+    pub grid_id: i64,
+    pub offset: Vec2f,
+}
+
+pub struct Anchors {
+    session: Vec<Anchor>,
+    global: Vec<Anchor>,
+    locations: BTreeMap<String, Vec2f>,
+}
+
+impl Anchors {
+    pub fn new(config: &AnchorsConfig) -> Self {
+        Self {
+            session: Vec::new(),
+            global: config.grids.iter()
+                .map(|grid_id| Anchor { grid_id: *grid_id, offset: Vec2f::zero() })
+                .collect(),
+            locations: config.locations.clone(),
+        }
+    }
+
+    pub fn from_anchors_data(data: AnchorsData, config: &AnchorsConfig) -> Self {
+        Self {
+            session: data.session,
+            ..Self::new(config)
+        }
+    }
+
+    pub fn as_anchors_data(&self) -> AnchorsData {
+        AnchorsData {
+            session: self.session.clone(),
+        }
+    }
+
+    pub fn add(&mut self, map: &Map, anchor: Anchor) {
+        if let Some(segment_id) = map.get_grid_by_id(anchor.grid_id).map(|v| v.segment_id) {
+            self.session.retain(|v| {
+                map.get_grid_by_id(v.grid_id).map(|v| v.segment_id != segment_id).unwrap_or(true)
+            });
+        }
+        self.session.insert(0, anchor);
+    }
+
+    pub fn get_position(&self, map: &Map, segment_id: i64) -> Option<Vec2f> {
+        self.session.iter().chain(self.global.iter())
+            .find_map(|anchor| {
+                map.get_grid_position(segment_id, anchor.grid_id)
+                    .map(|grid_pos| grid_pos_to_pos(grid_pos) + anchor.offset)
+            })
+    }
+
+    // Stored segments are not bound to a session so only global anchors apply to them
+    pub fn get_segment_position(&self, map_db: &dyn MapDb, segment_id: i64) -> Option<Vec2f> {
+        self.global.iter()
+            .find_map(|anchor| {
+                map_db.get_grid_by_id(anchor.grid_id)
+                    .map(|grid| grid.lock().unwrap().clone())
+                    .filter(|grid| grid.segment_id == segment_id)
+                    .map(|grid| grid_pos_to_pos(grid.position) + anchor.offset)
+            })
+    }
+
+    pub fn get_location(&self, name: &str) -> Option<Vec2f> {
+        self.locations.get(name).cloned()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct AnchorsData {
+    session: Vec<Anchor>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::repeat;
+    use std::sync::{Arc, Mutex};
+
+    use rusqlite::Connection;
+
+    use crate::bot::map::{Grid, GRID_SIZE, GridNeighbour, TILE_SIZE};
+    use crate::bot::sqlite_map_db::SqliteMapDb;
+    use crate::bot::vec2::Vec2i;
+
+    use super::*;
+
+    fn make_grid(id: i64) -> Grid {
+        Grid {
+            id,
+            revision: 1,
+            segment_id: id,
+            position: Vec2i::zero(),
+            heights: Vec::new(),
+            tiles: repeat(1).take((GRID_SIZE * GRID_SIZE) as usize).collect(),
+        }
+    }
+
+    fn make_map() -> Map {
+        Map::new(Arc::new(Mutex::new(SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default()))), 20.0)
+    }
+
+    #[test]
+    fn anchor_position_should_follow_segment_merge() {
+        let mut map = make_map();
+        let mut anchors = Anchors::new(&AnchorsConfig { grids: Vec::new(), locations: BTreeMap::new() });
+        map.add_grid(make_grid(1), Vec::new());
+        map.add_grid(make_grid(2), Vec::new());
+        anchors.add(&map, Anchor { grid_id: 2, offset: Vec2f::new(1.0, 2.0) });
+        assert_eq!(anchors.get_position(&map, 2), Some(Vec2f::new(1.0, 2.0)));
+        assert_eq!(anchors.get_position(&map, 1), None);
+        map.add_grid(make_grid(3), vec![
+            GridNeighbour { id: 1, offset: Vec2i::new(1, 0) },
+            GridNeighbour { id: 2, offset: Vec2i::new(-1, 0) },
+        ]);
+        let segment_id = map.get_grid_by_id(3).unwrap().segment_id;
+        let grid_pos = map.get_grid_by_id(2).unwrap().position;
+        assert_eq!(
+            anchors.get_position(&map, segment_id),
+            Some(grid_pos_to_pos(grid_pos) + Vec2f::new(1.0, 2.0))
+        );
+    }
+
+    #[test]
+    fn session_anchor_should_override_global_anchor_in_same_segment() {
+        let mut map = make_map();
+        let mut anchors = Anchors::new(&AnchorsConfig { grids: vec![1], locations: BTreeMap::new() });
+        map.add_grid(make_grid(1), Vec::new());
+        map.add_grid(make_grid(2), vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        assert_eq!(anchors.get_position(&map, 1), Some(Vec2f::zero()));
+        anchors.add(&map, Anchor { grid_id: 2, offset: Vec2f::zero() });
+        assert_eq!(anchors.get_position(&map, 1), Some(Vec2f::new(GRID_SIZE as f64 * TILE_SIZE, 0.0)));
+    }
+
+    #[test]
+    fn stored_segment_position_should_be_defined_by_global_anchor() {
+        let map_db = SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default());
+        let tiles = repeat(1).take((GRID_SIZE * GRID_SIZE) as usize).collect();
+        map_db.add_grid(1, &Vec::new(), &tiles, &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &tiles, &vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        map_db.add_grid(3, &Vec::new(), &tiles, &Vec::new());
+        let segment_id = map_db.get_grid_by_id(1).unwrap().lock().unwrap().segment_id;
+        let grid_pos = map_db.get_grid_by_id(2).unwrap().lock().unwrap().position;
+        let anchors = Anchors::new(&AnchorsConfig { grids: vec![2], locations: BTreeMap::new() });
+        assert_eq!(anchors.get_segment_position(&map_db, segment_id), Some(grid_pos_to_pos(grid_pos)));
+        assert_eq!(anchors.get_segment_position(&map_db, 3), None);
+    }
+
+    #[test]
+    fn session_anchors_should_be_restored_from_anchors_data() {
+        let mut map = make_map();
+        let config = AnchorsConfig { grids: Vec::new(), locations: BTreeMap::new() };
+        let mut anchors = Anchors::new(&config);
+        map.add_grid(make_grid(1), Vec::new());
+        anchors.add(&map, Anchor { grid_id: 1, offset: Vec2f::new(1.0, 2.0) });
+        let data: AnchorsData = serde_json::from_str(&serde_json::to_string(&anchors.as_anchors_data()).unwrap()).unwrap();
+        let restored = Anchors::from_anchors_data(data, &config);
+        assert_eq!(restored.get_position(&map, 1), Some(Vec2f::new(1.0, 2.0)));
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::bot::vec2::Vec2f;
+
+pub struct GridAreas {
+    pub labels: Vec<usize>,
+    pub centers: Vec<Vec2f>,
+}
+
+pub struct AreaCache {
+    grids: Mutex<BTreeMap<i64, CachedGrid>>,
+}
+
+struct CachedGrid {
+    revision: i64,
+    values: BTreeMap<Vec<i32>, Arc<GridAreas>>,
+}
+
+impl AreaCache {
+    pub fn new() -> Self {
+        Self { grids: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.grids.lock().unwrap().len()
+    }
+
+    pub fn get_or_make<F: FnOnce() -> GridAreas>(&self, grid_id: i64, revision: i64, allowed_tiles: &[i32], make: F) -> Arc<GridAreas> {
+        if let Some(cached) = self.grids.lock().unwrap().get(&grid_id)
+            .filter(|v| v.revision == revision)
+            .and_then(|v| v.values.get(allowed_tiles)) {
+            return cached.clone();
+        }
+        let value = Arc::new(make());
+        let mut grids = self.grids.lock().unwrap();
+        let cached = grids.entry(grid_id).or_insert_with(|| CachedGrid { revision, values: BTreeMap::new() });
+        if cached.revision != revision {
+            *cached = CachedGrid { revision, values: BTreeMap::new() };
+        }
+        cached.values.insert(allowed_tiles.to_vec(), value.clone());
+        value
+    }
+
+    pub fn invalidate(&self, grid_id: i64) -> bool {
+        self.grids.lock().unwrap().remove(&grid_id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_grid_areas(areas: usize) -> GridAreas {
+        GridAreas { labels: Vec::new(), centers: vec![Vec2f::zero(); areas] }
+    }
+
+    #[test]
+    fn get_or_make_should_reuse_areas_for_same_grid_revision_and_tiles() {
+        let cache = AreaCache::new();
+        assert_eq!(cache.get_or_make(1, 1, &[1, 2], || make_grid_areas(1)).centers.len(), 1);
+        assert_eq!(cache.get_or_make(1, 1, &[1, 2], || make_grid_areas(2)).centers.len(), 1);
+        assert_eq!(cache.get_or_make(1, 1, &[1], || make_grid_areas(3)).centers.len(), 3);
+        assert_eq!(cache.get_or_make(1, 2, &[1, 2], || make_grid_areas(4)).centers.len(), 4);
+        assert_eq!(cache.get_or_make(1, 2, &[1], || make_grid_areas(5)).centers.len(), 5);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.invalidate(1));
+        assert!(!cache.invalidate(1));
+        assert_eq!(cache.get_or_make(1, 2, &[1, 2], || make_grid_areas(6)).centers.len(), 6);
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::geometry::{get_polygon_area, is_inside_polygon, Rect};
+use crate::bot::map::TILE_SIZE;
+use crate::bot::objects::Object;
+use crate::bot::vec2::Vec2f;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum Area {
+    Rectangle {
+        min: Vec2f,
+        max: Vec2f,
+    },
+    Polygon {
+        points: Vec<Vec2f>,
+    },
+}
+
+impl Area {
+    pub fn contains(&self, position: Vec2f) -> bool {
+        match self {
+            Area::Rectangle { min, max } => Rect::new(*min, *max).contains(position),
+            Area::Polygon { points } => is_inside_polygon(points, position),
+        }
+    }
+
+    pub fn bounds(&self) -> (Vec2f, Vec2f) {
+        match self {
+            Area::Rectangle { min, max } => (*min, *max),
+            Area::Polygon { points } => Rect::from_points(points.iter())
+                .map(|v| (v.min, v.max))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn map_points(&self, f: impl Fn(Vec2f) -> Vec2f) -> Self {
+        match self {
+            Area::Rectangle { min, max } => Area::Rectangle { min: f(*min), max: f(*max) },
+            Area::Polygon { points } => Area::Polygon { points: points.iter().map(|v| f(*v)).collect() },
+        }
+    }
+
+    pub fn size(&self) -> f64 {
+        match self {
+            Area::Rectangle { min, max } => Rect::new(*min, *max).area(),
+            Area::Polygon { points } => get_polygon_area(points),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AreaObjects {
+    pub total: usize,
+    pub tiles: f64,
+    pub density: f64,
+    pub names: BTreeMap<String, NameObjects>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NameObjects {
+    pub count: usize,
+    pub density: f64,
+}
+
+pub fn count_area_objects<'a>(objects: impl Iterator<Item=&'a Object>, area: &Area) -> AreaObjects {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for object in objects.filter(|v| area.contains(v.position)) {
+        if let Some(name) = object.name.as_ref() {
+            *counts.entry(name.clone()).or_insert(0) += 1;
+        }
+    }
+    let tiles = area.size() / (TILE_SIZE * TILE_SIZE);
+    let get_density = |count: usize| if tiles > 0.0 { count as f64 / tiles } else { 0.0 };
+    let total = counts.values().sum();
+    AreaObjects {
+        total,
+        tiles,
+        density: get_density(total),
+        names: counts.into_iter()
+            .map(|(name, count)| (name, NameObjects { count, density: get_density(count) }))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::objects::make_object;
+
+    use super::*;
+
+    #[test]
+    fn polygon_should_contain_only_inner_positions() {
+        let area = Area::Polygon {
+            points: vec![Vec2f::new(0.0, 0.0), Vec2f::new(22.0, 0.0), Vec2f::new(0.0, 22.0)],
+        };
+        assert!(area.contains(Vec2f::new(5.0, 5.0)));
+        assert!(!area.contains(Vec2f::new(15.0, 15.0)));
+        assert!(!area.contains(Vec2f::new(-1.0, 5.0)));
+        assert_eq!(area.size(), 242.0);
+        assert_eq!(area.bounds(), (Vec2f::new(0.0, 0.0), Vec2f::new(22.0, 22.0)));
+    }
+
+    #[test]
+    fn map_points_should_shift_area() {
+        let shift = |v: Vec2f| v + Vec2f::new(11.0, -11.0);
+        let rectangle = Area::Rectangle { min: Vec2f::new(0.0, 0.0), max: Vec2f::new(22.0, 22.0) };
+        assert_eq!(rectangle.map_points(shift).bounds(), (Vec2f::new(11.0, -11.0), Vec2f::new(33.0, 11.0)));
+        let polygon = Area::Polygon { points: vec![Vec2f::new(0.0, 0.0), Vec2f::new(22.0, 0.0), Vec2f::new(0.0, 22.0)] };
+        assert!(polygon.map_points(shift).contains(Vec2f::new(16.0, -6.0)));
+    }
+
+    #[test]
+    fn count_area_objects_should_group_objects_inside_area_by_name() {
+        let objects = vec![
+            make_object(1, 1.0, 1.0, Some("gfx/terobjs/trees/spruce")),
+            make_object(2, 20.0, 20.0, Some("gfx/terobjs/trees/spruce")),
+            make_object(3, 5.0, 10.0, Some("gfx/terobjs/trees/birch")),
+            make_object(4, 30.0, 1.0, Some("gfx/terobjs/trees/spruce")),
+            make_object(5, 2.0, 2.0, None),
+        ];
+        let area = Area::Rectangle { min: Vec2f::new(0.0, 0.0), max: Vec2f::new(22.0, 22.0) };
+        let result = count_area_objects(objects.iter(), &area);
+        assert_eq!(result.total, 3);
+        assert_eq!(result.tiles, 4.0);
+        assert_eq!(result.density, 0.75);
+        assert_eq!(
+            result.names.iter().map(|(k, v)| (k.as_str(), v.count, v.density)).collect::<Vec<_>>(),
+            vec![("gfx/terobjs/trees/birch", 1, 0.25), ("gfx/terobjs/trees/spruce", 2, 0.5)]
+        );
+    }
+}
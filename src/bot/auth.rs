@@ -0,0 +1,88 @@
+use actix_web::http::Method;
+use serde::Deserialize;
+
+const BEARER_PREFIX: &str = "Bearer ";
+
+const READ_ONLY_ENDPOINTS: &[&str] = &[
+    "/ping",
+    "/sessions",
+    "/task_status",
+    "/get_session",
+    "/session_diff",
+    "/visualization",
+    "/session_stats",
+    "/session_log",
+    "/map/changes",
+    "/player_position",
+    "/area_objects",
+    "/find_path",
+    "/line_of_sight",
+    "/world_snapshot",
+    "/fields",
+    "/containers",
+    "/export_map",
+    "/map/grids",
+    "/map/tile",
+    "/map/tile_stats",
+    "/metrics",
+];
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AuthConfig {
+    pub tokens: Vec<String>,
+    pub open_read_only: bool,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    pub fn check(&self, method: &Method, path: &str, authorization: Option<&str>) -> Result<(), String> {
+        if !self.is_enabled() || (self.open_read_only && is_read_only(method, path)) {
+            return Ok(());
+        }
+        let token = match authorization.and_then(|v| v.strip_prefix(BEARER_PREFIX)) {
+            Some(v) => v,
+            None => return Err(format!("Authorization token is required for {} {}", method, path)),
+        };
+        if self.tokens.iter().any(|v| v == token) {
+            Ok(())
+        } else {
+            Err(format!("Authorization token is invalid for {} {}", method, path))
+        }
+    }
+}
+
+pub fn make_authorization(token: &String) -> String {
+    format!("{}{}", BEARER_PREFIX, token)
+}
+
+fn is_read_only(method: &Method, path: &str) -> bool {
+    if path == "/zones" || path == "/bookmarks" || path == "/profiles" {
+        return method == Method::GET;
+    }
+    READ_ONLY_ENDPOINTS.contains(&path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_should_require_token_for_mutating_endpoints() {
+        let config = AuthConfig { tokens: vec![String::from("secret")], open_read_only: true };
+        assert_eq!(config.check(&Method::GET, "/ping", None), Ok(()));
+        assert_eq!(config.check(&Method::GET, "/zones", None), Ok(()));
+        assert!(config.check(&Method::PUT, "/zones", None).is_err());
+        assert_eq!(config.check(&Method::GET, "/bookmarks", None), Ok(()));
+        assert!(config.check(&Method::DELETE, "/bookmarks", None).is_err());
+        assert!(config.check(&Method::DELETE, "/profiles", None).is_err());
+        assert!(config.check(&Method::PUT, "/push", Some("Bearer other")).is_err());
+        assert!(config.check(&Method::PUT, "/push", Some("secret")).is_err());
+        assert_eq!(config.check(&Method::PUT, "/push", Some("Bearer secret")), Ok(()));
+        let closed = AuthConfig { open_read_only: false, ..config };
+        assert!(closed.check(&Method::GET, "/ping", None).is_err());
+        assert_eq!(AuthConfig::default().check(&Method::POST, "/cancel", None), Ok(()));
+    }
+}
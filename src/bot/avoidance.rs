@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::bot::objects::{Object, Objects};
+use crate::bot::obstacles::get_covered_tiles;
+use crate::bot::vec2::Vec2i;
+
+#[derive(Clone, Deserialize)]
+pub struct AvoidanceConfig {
+    pub objects: Vec<String>,
+    pub radius: f64,
+    pub weight: f64,
+}
+
+pub struct Avoidance {
+    revision: u64,
+    objects: BTreeMap<i64, Vec<Vec2i>>,
+    tiles: BTreeMap<Vec2i, usize>,
+    config: AvoidanceConfig,
+}
+
+impl Avoidance {
+    pub fn new(config: AvoidanceConfig) -> Self {
+        Self {
+            revision: 0,
+            objects: BTreeMap::new(),
+            tiles: BTreeMap::new(),
+            config,
+        }
+    }
+
+    pub fn from_objects(objects: &Objects, config: AvoidanceConfig) -> Self {
+        let mut result = Self::new(config);
+        for object in objects.iter() {
+            result.add(object);
+        }
+        result
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn get_weight(&self, tile_pos: Vec2i) -> f64 {
+        if self.tiles.contains_key(&tile_pos) {
+            self.config.weight
+        } else {
+            0.0
+        }
+    }
+
+    pub fn add(&mut self, object: &Object) {
+        let is_avoided = object.name.as_ref()
+            .map(|name| self.config.objects.iter().any(|v| name.starts_with(v.as_str())))
+            .unwrap_or(false);
+        if !is_avoided {
+            self.remove(object.id);
+            return;
+        }
+        let tiles = get_covered_tiles(object.position, self.config.radius);
+        if self.objects.get(&object.id) == Some(&tiles) {
+            return;
+        }
+        self.remove(object.id);
+        for tile_pos in tiles.iter() {
+            *self.tiles.entry(*tile_pos).or_insert(0) += 1;
+        }
+        self.objects.insert(object.id, tiles);
+        self.revision += 1;
+    }
+
+    pub fn remove(&mut self, object_id: i64) {
+        if let Some(tiles) = self.objects.remove(&object_id) {
+            for tile_pos in tiles.iter() {
+                if let Some(count) = self.tiles.get_mut(tile_pos) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.tiles.remove(tile_pos);
+                    }
+                }
+            }
+            self.revision += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::objects::make_object;
+
+    use super::*;
+
+    #[test]
+    fn avoidance_should_follow_moving_objects() {
+        let mut avoidance = Avoidance::new(AvoidanceConfig {
+            objects: vec![String::from("gfx/kritter/bear")],
+            radius: 12.0,
+            weight: 20.0,
+        });
+        avoidance.add(&make_object(1, 16.5, 16.5, Some("gfx/kritter/bear/bear")));
+        avoidance.add(&make_object(2, 110.0, 110.0, Some("gfx/kritter/rabbit/rabbit")));
+        assert_eq!(avoidance.len(), 1);
+        assert_eq!(avoidance.get_weight(Vec2i::new(1, 1)), 20.0);
+        assert_eq!(avoidance.get_weight(Vec2i::new(2, 2)), 20.0);
+        assert_eq!(avoidance.get_weight(Vec2i::new(10, 10)), 0.0);
+        let revision = avoidance.revision();
+        avoidance.add(&make_object(1, 16.6, 16.5, Some("gfx/kritter/bear/bear")));
+        assert_eq!(avoidance.revision(), revision);
+        avoidance.add(&make_object(1, 110.0, 110.0, Some("gfx/kritter/bear/bear")));
+        assert!(avoidance.revision() > revision);
+        assert_eq!(avoidance.get_weight(Vec2i::new(1, 1)), 0.0);
+        assert_eq!(avoidance.get_weight(Vec2i::new(10, 10)), 20.0);
+        avoidance.remove(1);
+        assert_eq!(avoidance.get_weight(Vec2i::new(10, 10)), 0.0);
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rusqlite::Connection;
+
+use crate::bot::map::pos_to_tile_pos;
+use crate::bot::map_db::MapDb;
+use crate::bot::metrics::Metrics;
+use crate::bot::player::{Player, PlayerConfig};
+use crate::bot::protocol::Update;
+use crate::bot::sqlite_map_db::SqliteMapDb;
+use crate::bot::tasks::path_finder::get_tile_costs_by_profile;
+use crate::bot::tile_profiles::TileProfiles;
+pub use crate::bot::vec2::Vec2i;
+use crate::bot::world::{BTreeMapTileWeights, PlayerWorld, World, WorldConfig};
+
+pub struct PathFindingFixture {
+    world: World,
+    player: Player,
+    tile_weights: BTreeMap<i32, f64>,
+}
+
+impl PathFindingFixture {
+    pub fn new<C: AsRef<Path>, U: AsRef<Path>>(config_path: C, updates_path: U, profile: &str) -> Result<Self, String> {
+        let config: serde_yaml::Value = serde_yaml::from_reader(File::open(config_path).map_err(|e| e.to_string())?)
+            .map_err(|e| format!("Failed to parse config: {}", e))?;
+        let world_config: WorldConfig = serde_yaml::from_value(config["session"]["world"].clone())
+            .map_err(|e| format!("Failed to parse world config: {}", e))?;
+        let player_config: PlayerConfig = serde_yaml::from_value(config["session"]["player"].clone())
+            .map_err(|e| format!("Failed to parse player config: {}", e))?;
+        let connection = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        let map_db: Arc<Mutex<dyn MapDb + Send>> = Arc::new(Mutex::new(SqliteMapDb::new(connection, Duration::from_secs(3600))));
+        let tile_profiles = Arc::new(TileProfiles::new(&world_config));
+        let mut world = World::new(world_config, map_db, Arc::new(Metrics::new()), tile_profiles);
+        let mut player = Player::new(player_config);
+        let updates = File::open(updates_path).map_err(|e| e.to_string())?;
+        for line in BufReader::new(updates).lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            let update = serde_json::from_str::<Update>(line.as_str())
+                .map_err(|e| format!("Failed to parse update: {}", e))?;
+            player.update(&world, &update);
+            world.update(update);
+        }
+        let tile_weights = {
+            let player_world = match world.for_player(&player) {
+                Some(v) => v,
+                None => return Err(String::from("World is not configured")),
+            };
+            let tile_costs = match get_tile_costs_by_profile(profile, &player_world) {
+                Some(v) => v,
+                None => return Err(format!("Movement profile {:?} is not found", profile)),
+            };
+            tile_costs.iter()
+                .filter_map(|(name, weight)| player_world.get_tile_id_by_name(name).map(|id| (id, *weight)))
+                .collect()
+        };
+        Ok(Self { world, player, tile_weights })
+    }
+
+    pub fn player_tile_pos(&self) -> Vec2i {
+        pos_to_tile_pos(self.player_world().player_position())
+    }
+
+    pub fn find_reversed_tiles_path(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i, max_iterations: usize) -> Vec<Vec2i> {
+        self.player_world().bench_find_reversed_tiles_path(
+            src_tile_pos,
+            dst_tile_pos,
+            &BTreeMapTileWeights(&self.tile_weights),
+            max_iterations,
+        )
+    }
+
+    pub fn shorten_reversed_tiles_path(&self, reversed_tiles_path: Vec<Vec2i>, max_shortcut_length: f64) -> Vec<Vec2i> {
+        self.player_world().bench_shorten_reversed_tiles_path(
+            reversed_tiles_path,
+            &BTreeMapTileWeights(&self.tile_weights),
+            max_shortcut_length,
+        )
+    }
+
+    pub fn make_areas(&self) -> usize {
+        self.player_world().bench_make_areas(&BTreeMapTileWeights(&self.tile_weights)).len()
+    }
+
+    fn player_world(&self) -> PlayerWorld {
+        self.world.for_player(&self.player).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_PATH: &str = "etc/config.yaml";
+
+    #[test]
+    fn find_reversed_tiles_path_should_find_canned_paths_within_iterations_limit() {
+        let cases = [
+            ("tests/input/init_session_lake.json", Vec2i::new(-890, -977), 50, 100, 86),
+            ("tests/input/init_session_start.json", Vec2i::new(-894, -916), 200, 500, 10),
+            ("tests/input/init_session_start.json", Vec2i::new(-884, -886), 1000, 2000, 20),
+        ];
+        for (updates_path, dst, not_enough_iterations, enough_iterations, length) in cases.iter() {
+            let fixture = PathFindingFixture::new(CONFIG_PATH, updates_path, "water").unwrap();
+            let src = fixture.player_tile_pos();
+            assert_eq!(
+                fixture.find_reversed_tiles_path(src, *dst, *not_enough_iterations), Vec::new(),
+                "{} {:?}", updates_path, dst
+            );
+            let path = fixture.find_reversed_tiles_path(src, *dst, *enough_iterations);
+            assert_eq!(path.len(), *length, "{} {:?}", updates_path, dst);
+            assert_eq!(path.first(), Some(dst), "{} {:?}", updates_path, dst);
+            let shortened = fixture.shorten_reversed_tiles_path(path, 25.0);
+            assert_eq!(shortened.last(), Some(dst), "{} {:?}", updates_path, dst);
+        }
+    }
+}
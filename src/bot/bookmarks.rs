@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bot::vec2::Vec2f;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub segment_id: i64,
+    pub name: String,
+    pub position: Vec2f,
+}
+
+impl Bookmark {
+    pub fn shifted(&self, segment_id: i64, shift: Vec2f) -> Self {
+        Self {
+            segment_id,
+            name: self.name.clone(),
+            position: self.position + shift,
+        }
+    }
+}
+
+pub fn validate_bookmark(bookmark: &Bookmark) -> Result<(), String> {
+    if bookmark.name.is_empty() {
+        return Err(String::from("Bookmark name is empty"));
+    }
+    if !bookmark.position.x().is_finite() || !bookmark.position.y().is_finite() {
+        return Err(format!("Bookmark {} has invalid position {:?}", bookmark.name, bookmark.position));
+    }
+    Ok(())
+}
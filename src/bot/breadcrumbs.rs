@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::vec2::Vec2f;
+
+#[derive(Clone, Deserialize)]
+pub struct BreadcrumbsConfig {
+    pub min_distance: f64,
+    pub max_len: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Breadcrumb {
+    pub segment_id: i64,
+    pub position: Vec2f,
+}
+
+pub struct Breadcrumbs {
+    trail: VecDeque<Breadcrumb>,
+    config: BreadcrumbsConfig,
+}
+
+impl Breadcrumbs {
+    pub fn new(config: BreadcrumbsConfig) -> Self {
+        Self {
+            trail: VecDeque::new(),
+            config,
+        }
+    }
+
+    pub fn from_breadcrumbs_data(data: BreadcrumbsData, config: BreadcrumbsConfig) -> Self {
+        Self {
+            trail: data.trail.into_iter().collect(),
+            config,
+        }
+    }
+
+    pub fn as_breadcrumbs_data(&self) -> BreadcrumbsData {
+        BreadcrumbsData {
+            trail: self.trail.iter().cloned().collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.trail.len()
+    }
+
+    pub fn add(&mut self, segment_id: i64, position: Vec2f) -> bool {
+        if let Some(last) = self.trail.back() {
+            if last.segment_id == segment_id && last.position.distance(position) < self.config.min_distance {
+                return false;
+            }
+        }
+        self.trail.push_back(Breadcrumb { segment_id, position });
+        while self.trail.len() > self.config.max_len {
+            self.trail.pop_front();
+        }
+        true
+    }
+
+    pub fn get_backtrack(&self, segment_id: i64) -> Vec<Vec2f> {
+        self.trail.iter().rev()
+            .take_while(|v| v.segment_id == segment_id)
+            .map(|v| v.position)
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct BreadcrumbsData {
+    trail: Vec<Breadcrumb>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_should_skip_close_positions_and_limit_trail_length() {
+        let mut breadcrumbs = Breadcrumbs::new(BreadcrumbsConfig { min_distance: 10.0, max_len: 3 });
+        assert!(breadcrumbs.add(1, Vec2f::new(0.0, 0.0)));
+        assert!(!breadcrumbs.add(1, Vec2f::new(5.0, 0.0)));
+        assert!(breadcrumbs.add(2, Vec2f::new(5.0, 0.0)));
+        assert!(breadcrumbs.add(2, Vec2f::new(20.0, 0.0)));
+        assert!(breadcrumbs.add(2, Vec2f::new(40.0, 0.0)));
+        assert_eq!(breadcrumbs.len(), 3);
+    }
+
+    #[test]
+    fn get_backtrack_should_return_latest_positions_in_segment() {
+        let mut breadcrumbs = Breadcrumbs::new(BreadcrumbsConfig { min_distance: 10.0, max_len: 10 });
+        breadcrumbs.add(1, Vec2f::new(0.0, 0.0));
+        breadcrumbs.add(2, Vec2f::new(0.0, 0.0));
+        breadcrumbs.add(2, Vec2f::new(20.0, 0.0));
+        breadcrumbs.add(2, Vec2f::new(40.0, 0.0));
+        assert_eq!(
+            breadcrumbs.get_backtrack(2),
+            vec![Vec2f::new(40.0, 0.0), Vec2f::new(20.0, 0.0), Vec2f::new(0.0, 0.0)]
+        );
+        assert_eq!(breadcrumbs.get_backtrack(1), Vec::<Vec2f>::new());
+    }
+}
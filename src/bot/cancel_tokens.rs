@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub struct CancelTokens {
+    session: Arc<AtomicBool>,
+    tasks: Mutex<BTreeMap<i64, Arc<AtomicBool>>>,
+    queries: Mutex<Vec<Weak<AtomicBool>>>,
+}
+
+impl CancelTokens {
+    pub fn new() -> Self {
+        Self {
+            session: Arc::new(AtomicBool::new(false)),
+            tasks: Mutex::new(BTreeMap::new()),
+            queries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn session(&self) -> &Arc<AtomicBool> {
+        &self.session
+    }
+
+    pub fn add_task(&self, task_id: i64) -> Arc<AtomicBool> {
+        self.tasks.lock().unwrap()
+            .entry(task_id)
+            .or_insert_with(|| Arc::new(AtomicBool::new(false)))
+            .clone()
+    }
+
+    // Query tokens are not reset and live only while the query holds them
+    pub fn add_query(&self) -> Arc<AtomicBool> {
+        let token = Arc::new(AtomicBool::new(false));
+        let mut queries = self.queries.lock().unwrap();
+        queries.retain(|v| v.strong_count() > 0);
+        queries.push(Arc::downgrade(&token));
+        token
+    }
+
+    pub fn remove_task(&self, task_id: i64) {
+        self.tasks.lock().unwrap().remove(&task_id);
+    }
+
+    pub fn clear_tasks(&self) {
+        self.tasks.lock().unwrap().clear();
+    }
+
+    pub fn cancel_task(&self, task_id: i64) -> bool {
+        match self.tasks.lock().unwrap().get(&task_id) {
+            Some(token) => {
+                token.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        self.session.store(true, Ordering::Relaxed);
+        for token in self.tasks.lock().unwrap().values() {
+            token.store(true, Ordering::Relaxed);
+        }
+        for token in self.queries.lock().unwrap().iter().filter_map(Weak::upgrade) {
+            token.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn reset(&self) {
+        self.session.store(false, Ordering::Relaxed);
+        for token in self.tasks.lock().unwrap().values() {
+            token.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_task_should_affect_only_given_task() {
+        let tokens = CancelTokens::new();
+        let first = tokens.add_task(1);
+        let second = tokens.add_task(2);
+        assert!(tokens.cancel_task(1));
+        assert!(!tokens.cancel_task(3));
+        assert!(first.load(Ordering::Relaxed));
+        assert!(!second.load(Ordering::Relaxed));
+        assert!(!tokens.session().load(Ordering::Relaxed));
+        tokens.reset();
+        assert!(!first.load(Ordering::Relaxed));
+        tokens.cancel_all();
+        assert!(first.load(Ordering::Relaxed));
+        assert!(second.load(Ordering::Relaxed));
+        assert!(tokens.session().load(Ordering::Relaxed));
+        tokens.remove_task(1);
+        assert!(!tokens.cancel_task(1));
+    }
+
+    #[test]
+    fn cancel_all_should_affect_live_queries() {
+        let tokens = CancelTokens::new();
+        let query = tokens.add_query();
+        drop(tokens.add_query());
+        tokens.cancel_task(1);
+        assert!(!query.load(Ordering::Relaxed));
+        tokens.cancel_all();
+        assert!(query.load(Ordering::Relaxed));
+        tokens.reset();
+        assert!(query.load(Ordering::Relaxed));
+        assert_eq!(tokens.queries.lock().unwrap().len(), 2);
+        assert!(!tokens.add_query().load(Ordering::Relaxed));
+        assert_eq!(tokens.queries.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeSet;
+
+use crate::bot::protocol::Message;
+
+pub const SUPPORTED_PROTOCOL_VERSIONS: [u32; 1] = [1];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Capability {
+    GridsOfInterest,
+    StuckRecovery,
+    Alerts,
+}
+
+impl Capability {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "GridsOfInterest" => Some(Capability::GridsOfInterest),
+            "StuckRecovery" => Some(Capability::StuckRecovery),
+            "Alerts" => Some(Capability::Alerts),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::GridsOfInterest => "GridsOfInterest",
+            Capability::StuckRecovery => "StuckRecovery",
+            Capability::Alerts => "Alerts",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Negotiated {
+    pub version: u32,
+    pub capabilities: BTreeSet<Capability>,
+}
+
+pub fn negotiate(version: u32, capabilities: &Vec<String>) -> Result<Negotiated, String> {
+    if !SUPPORTED_PROTOCOL_VERSIONS.contains(&version) {
+        return Err(format!("Protocol version {} is not supported, supported versions: {:?}",
+                           version, SUPPORTED_PROTOCOL_VERSIONS));
+    }
+    Ok(Negotiated {
+        version,
+        capabilities: capabilities.iter().filter_map(|v| Capability::from_name(v.as_str())).collect(),
+    })
+}
+
+pub fn get_required_capability(message: &Message) -> Option<Capability> {
+    match message {
+        Message::GridOfInterestChanged { .. } => Some(Capability::GridsOfInterest),
+        Message::StuckRecovery { .. } => Some(Capability::StuckRecovery),
+        Message::Alert { .. } => Some(Capability::Alerts),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_should_keep_only_known_capabilities() {
+        let capabilities = vec![String::from("Alerts"), String::from("Teleport")];
+        assert_eq!(
+            negotiate(1, &capabilities),
+            Ok(Negotiated { version: 1, capabilities: vec![Capability::Alerts].into_iter().collect() })
+        );
+        assert!(negotiate(0, &capabilities).is_err());
+    }
+}
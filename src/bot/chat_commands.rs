@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::bot::player::Widget;
+use crate::bot::protocol::{Event, Message, Value};
+
+#[derive(Clone, Deserialize)]
+pub struct ChatCommandsConfig {
+    pub prefix: String,
+    pub widget_kinds: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ChatCommand {
+    AddTask { name: String, params: Vec<u8> },
+    RemoveTask { id: i64 },
+    ClearTasks,
+    ListTasks,
+}
+
+pub fn get_chat_command_text<'a>(event: &'a Event, widgets: &BTreeMap<i32, Widget>,
+                                 config: &ChatCommandsConfig) -> Option<(i32, &'a str)> {
+    if let Event::WidgetMessage { id, msg, args } = event {
+        if msg.as_str() != "msg" {
+            return None;
+        }
+        if !widgets.get(id).map(|v| config.widget_kinds.contains(&v.kind)).unwrap_or(false) {
+            return None;
+        }
+        match (args.get(0), args.get(1)) {
+            (Some(Value::Str { value: direction }), Some(Value::Str { value }))
+                if direction.as_str() == "out" && value.starts_with(config.prefix.as_str()) => {
+                return Some((*id, &value[config.prefix.len()..]));
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+pub fn parse_chat_command(text: &str) -> Result<ChatCommand, String> {
+    let text = text.trim();
+    let (group, text) = split_word(text);
+    if group != "task" {
+        return Err(format!("Unknown command: {:?}", group));
+    }
+    let (action, text) = split_word(text);
+    match action {
+        "add" => {
+            let (name, params) = split_word(text);
+            if name.is_empty() {
+                return Err(String::from("Task name is not specified"));
+            }
+            Ok(ChatCommand::AddTask { name: String::from(name), params: Vec::from(params.as_bytes()) })
+        }
+        "remove" => match text.parse::<i64>() {
+            Ok(id) => Ok(ChatCommand::RemoveTask { id }),
+            Err(e) => Err(format!("Invalid task id {:?}: {}", text, e)),
+        },
+        "clear" => Ok(ChatCommand::ClearTasks),
+        "list" => Ok(ChatCommand::ListTasks),
+        _ => Err(format!("Unknown task command: {:?}", action)),
+    }
+}
+
+pub fn make_chat_reply(widget_id: i32, text: String) -> Message {
+    Message::UIMessage {
+        id: widget_id,
+        kind: String::from("msg"),
+        arguments: vec![Value::from(String::from("in")), Value::from(text)],
+    }
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_chat_command_should_support_task_commands() {
+        assert_eq!(
+            parse_chat_command("task add Explorer"),
+            Ok(ChatCommand::AddTask { name: String::from("Explorer"), params: Vec::new() })
+        );
+        assert_eq!(
+            parse_chat_command(" task  add Forager {\"radius\": 10}"),
+            Ok(ChatCommand::AddTask { name: String::from("Forager"), params: Vec::from(&b"{\"radius\": 10}"[..]) })
+        );
+        assert_eq!(parse_chat_command("task remove 42"), Ok(ChatCommand::RemoveTask { id: 42 }));
+        assert_eq!(parse_chat_command("task clear"), Ok(ChatCommand::ClearTasks));
+        assert_eq!(parse_chat_command("task list"), Ok(ChatCommand::ListTasks));
+        assert!(parse_chat_command("task add").is_err());
+        assert!(parse_chat_command("task remove x").is_err());
+        assert!(parse_chat_command("walk home").is_err());
+    }
+
+    #[test]
+    fn get_chat_command_text_should_accept_only_outgoing_messages() {
+        let config = ChatCommandsConfig { prefix: String::from("!"), widget_kinds: vec![String::from("pmchat")] };
+        let mut widgets = BTreeMap::new();
+        widgets.insert(1, Widget {
+            id: 1,
+            parent: 0,
+            kind: String::from("pmchat"),
+            pargs: Vec::new(),
+            cargs: Vec::new(),
+            pargs_add: Vec::new(),
+        });
+        let message = |direction: &str, text: &str| Event::WidgetMessage {
+            id: 1,
+            msg: String::from("msg"),
+            args: vec![Value::from(String::from(direction)), Value::from(String::from(text))],
+        };
+        assert_eq!(get_chat_command_text(&message("out", "!task list"), &widgets, &config), Some((1, "task list")));
+        assert_eq!(get_chat_command_text(&message("in", "!task list"), &widgets, &config), None);
+        assert_eq!(get_chat_command_text(&message("out", "task list"), &widgets, &config), None);
+    }
+}
@@ -35,3 +35,50 @@ pub fn is_adjacent(lhs: Vec2i, rhs: Vec2i) -> bool {
     lhs.x() + 1 == rhs.x() || lhs.x() == rhs.x() + 1
         || lhs.y() + 1 == rhs.y() || lhs.y() == rhs.y() + 1
 }
+
+pub fn make_distance_clusters(positions: &Vec<Vec2f>, max_distance: f64) -> Vec<Vec<usize>> {
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    let mut cluster_by_position: Vec<Option<usize>> = vec![None; positions.len()];
+    for index in 0..positions.len() {
+        if cluster_by_position[index].is_some() {
+            continue;
+        }
+        let cluster_index = clusters.len();
+        let mut cluster = vec![index];
+        cluster_by_position[index] = Some(cluster_index);
+        let mut next = 0;
+        while next < cluster.len() {
+            let position = positions[cluster[next]];
+            for other in 0..positions.len() {
+                if cluster_by_position[other].is_none() && positions[other].distance(position) <= max_distance {
+                    cluster_by_position[other] = Some(cluster_index);
+                    cluster.push(other);
+                }
+            }
+            next += 1;
+        }
+        clusters.push(cluster);
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_distance_clusters_should_group_positions_by_chain_distance() {
+        let positions = vec![
+            Vec2f::new(0.0, 0.0),
+            Vec2f::new(100.0, 0.0),
+            Vec2f::new(10.0, 0.0),
+            Vec2f::new(20.0, 0.0),
+            Vec2f::new(105.0, 0.0),
+            Vec2f::new(300.0, 0.0),
+        ];
+        assert_eq!(
+            make_distance_clusters(&positions, 10.0),
+            vec![vec![0, 2, 3], vec![1, 4], vec![5]]
+        );
+    }
+}
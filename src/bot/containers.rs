@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::objects::{Object, Objects};
+use crate::bot::vec2::Vec2f;
+
+#[derive(Clone, Deserialize)]
+pub struct ContainersConfig {
+    pub objects: Vec<String>,
+    pub interaction_timeout: f64,
+}
+
+impl ContainersConfig {
+    pub fn is_container(&self, name: &str) -> bool {
+        self.objects.iter().any(|v| name.starts_with(v.as_str()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Container {
+    pub object_id: i64,
+    pub name: String,
+    pub position: Vec2f,
+    pub free_slots: Option<usize>,
+}
+
+struct OpenInventory {
+    object_id: i64,
+    window_id: i32,
+}
+
+pub struct Containers {
+    last_interaction: Option<(i64, Instant)>,
+    inventories: BTreeMap<i32, OpenInventory>,
+    values: BTreeMap<i64, Container>,
+    config: ContainersConfig,
+}
+
+impl Containers {
+    pub fn new(config: ContainersConfig) -> Self {
+        Self {
+            last_interaction: None,
+            inventories: BTreeMap::new(),
+            values: BTreeMap::new(),
+            config,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Container> {
+        self.values.values()
+    }
+
+    pub fn open_inventory_ids(&self) -> impl Iterator<Item=i32> + '_ {
+        self.inventories.keys().cloned()
+    }
+
+    pub fn interact(&mut self, object: &Object, now: Instant) -> bool {
+        match object.name.as_ref() {
+            Some(name) if self.config.is_container(name) => {
+                debug!("Containers: interact with {} {:?}", object.id, name);
+                self.last_interaction = Some((object.id, now));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn open(&mut self, inventory_id: i32, window_id: i32, objects: &Objects, now: Instant) -> bool {
+        let object_id = match self.last_interaction.take() {
+            Some((object_id, time)) if now - time <= Duration::from_secs_f64(self.config.interaction_timeout) => object_id,
+            _ => return false,
+        };
+        let object = match objects.get_by_id(object_id) {
+            Some(v) => v,
+            None => return false,
+        };
+        debug!("Containers: inventory {} belongs to {} {:?}", inventory_id, object.id, object.name);
+        self.inventories.insert(inventory_id, OpenInventory { object_id, window_id });
+        let container = self.values.entry(object_id).or_insert_with(|| Container {
+            object_id,
+            name: object.name.clone().unwrap_or_default(),
+            position: object.position,
+            free_slots: None,
+        });
+        container.position = object.position;
+        true
+    }
+
+    pub fn close(&mut self, widget_id: i32) -> bool {
+        let size = self.inventories.len();
+        self.inventories.retain(|inventory_id, v| *inventory_id != widget_id && v.window_id != widget_id);
+        size != self.inventories.len()
+    }
+
+    pub fn set_free_slots(&mut self, inventory_id: i32, free_slots: usize) -> bool {
+        let object_id = match self.inventories.get(&inventory_id) {
+            Some(v) => v.object_id,
+            None => return false,
+        };
+        match self.values.get_mut(&object_id) {
+            Some(container) if container.free_slots != Some(free_slots) => {
+                container.free_slots = Some(free_slots);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn find_nearest_non_full(&self, position: Vec2f) -> Option<&Container> {
+        self.values.values()
+            .filter(|v| v.free_slots.map(|slots| slots > 0).unwrap_or(false))
+            .min_by(|lhs, rhs| {
+                lhs.position.distance(position).partial_cmp(&rhs.position.distance(position)).unwrap()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::objects::make_object;
+
+    use super::*;
+
+    #[test]
+    fn open_should_map_inventory_to_recently_clicked_container() {
+        let mut containers = Containers::new(ContainersConfig {
+            objects: vec![String::from("gfx/terobjs/cupboard"), String::from("gfx/terobjs/chest")],
+            interaction_timeout: 5.0,
+        });
+        let now = Instant::now();
+        let cupboard = make_object(1, 100.0, 0.0, Some("gfx/terobjs/cupboard"));
+        let chest = make_object(2, 10.0, 0.0, Some("gfx/terobjs/chest"));
+        let tree = make_object(3, 0.0, 0.0, Some("gfx/terobjs/trees/oak"));
+        let mut objects = Objects::new();
+        objects.add(cupboard.clone());
+        objects.add(chest.clone());
+        objects.add(tree.clone());
+        assert!(!containers.interact(&tree, now));
+        assert!(!containers.open(20, 19, &objects, now));
+        assert!(containers.interact(&cupboard, now));
+        assert!(containers.open(20, 19, &objects, now));
+        assert!(containers.set_free_slots(20, 3));
+        assert!(containers.interact(&chest, now));
+        assert!(!containers.open(30, 29, &objects, now + Duration::from_secs(6)));
+        assert!(containers.interact(&chest, now));
+        assert!(containers.open(30, 29, &objects, now));
+        assert!(containers.set_free_slots(30, 0));
+        assert_eq!(containers.find_nearest_non_full(Vec2f::zero()).map(|v| v.object_id), Some(1));
+        assert!(containers.close(19));
+        assert!(!containers.set_free_slots(20, 5));
+        assert_eq!(containers.open_inventory_ids().collect::<Vec<_>>(), vec![30]);
+        assert_eq!(containers.iter().count(), 2);
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::player::Player;
+use crate::bot::protocol::{Event, Update, Value};
+
+#[derive(Clone, Deserialize)]
+pub struct CooldownsConfig {
+    pub buffs: HashMap<String, f64>,
+}
+
+pub struct Cooldowns {
+    values: BTreeMap<String, Instant>,
+    buff_widgets: BTreeMap<i32, String>,
+    config: CooldownsConfig,
+}
+
+impl Cooldowns {
+    pub fn new(config: CooldownsConfig) -> Self {
+        Self {
+            values: BTreeMap::new(),
+            buff_widgets: BTreeMap::new(),
+            config,
+        }
+    }
+
+    pub fn start(&mut self, name: &str, duration: Duration, now: Instant) {
+        debug!("Cooldowns: start {} for {:?}", name, duration);
+        self.values.insert(String::from(name), now + duration);
+    }
+
+    pub fn end(&mut self, name: &str) {
+        if self.values.remove(name).is_some() {
+            debug!("Cooldowns: end {}", name);
+        }
+    }
+
+    pub fn is_active(&self, name: &str, now: Instant) -> bool {
+        self.values.get(name).map(|v| *v > now).unwrap_or(false)
+    }
+
+    pub fn remaining(&self, name: &str, now: Instant) -> Duration {
+        self.values.get(name).map(|v| v.saturating_duration_since(now)).unwrap_or(Duration::ZERO)
+    }
+
+    pub fn update(&mut self, player: &Player, update: &Update, now: Instant) {
+        self.values.retain(|_, v| *v > now);
+        match &update.event {
+            Event::NewWidget { id, kind, cargs, .. } => {
+                if kind.as_str() != "buff" || cargs.is_empty() {
+                    return;
+                }
+                if let Value::Int { value } = &cargs[0] {
+                    if let Some(resource) = player.resources().get(value) {
+                        if let Some(duration) = self.config.buffs.get(&resource.name).cloned() {
+                            self.buff_widgets.insert(*id, resource.name.clone());
+                            self.start(&resource.name, Duration::from_secs_f64(duration), now);
+                        }
+                    }
+                }
+            }
+            Event::Destroy { id } => {
+                if let Some(name) = self.buff_widgets.remove(id) {
+                    self.end(name.as_str());
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn started_cooldown_should_be_active_until_duration_passes() {
+        let mut cooldowns = Cooldowns::new(CooldownsConfig { buffs: HashMap::new() });
+        let now = Instant::now();
+        cooldowns.start("sip", Duration::from_secs(2), now);
+        assert!(cooldowns.is_active("sip", now + Duration::from_secs(1)));
+        assert_eq!(cooldowns.remaining("sip", now + Duration::from_secs(1)), Duration::from_secs(1));
+        assert!(!cooldowns.is_active("sip", now + Duration::from_secs(2)));
+        assert!(!cooldowns.is_active("drink", now));
+    }
+
+    #[test]
+    fn ended_cooldown_should_not_be_active() {
+        let mut cooldowns = Cooldowns::new(CooldownsConfig { buffs: HashMap::new() });
+        let now = Instant::now();
+        cooldowns.start("sip", Duration::from_secs(2), now);
+        cooldowns.end("sip");
+        assert!(!cooldowns.is_active("sip", now));
+    }
+}
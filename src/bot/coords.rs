@@ -0,0 +1,32 @@
+//! Coordinate conversions shared with external tools. Changes here follow crate versioning,
+//! see `CHANGELOG.md`.
+//!
+//! Positions (`pos`) are in world units, map positions (`map_pos`) are in `RESOLUTION` units,
+//! tile positions (`tile_pos`) are in `TILE_SIZE` units and grid positions (`grid_pos`) are in
+//! `GRID_SIZE` tiles.
+
+/// Conversions between world, map, tile and grid coordinates.
+pub use crate::bot::map::{
+    GRID_SIZE,
+    grid_pos_to_pos,
+    grid_pos_to_tile_pos,
+    make_tile_pos,
+    map_pos_to_pos,
+    map_pos_to_tile_pos,
+    pos_to_grid_pos,
+    pos_to_map_pos,
+    pos_to_rel_tile_pos,
+    pos_to_tile_pos,
+    rel_tile_pos_to_pos,
+    RESOLUTION,
+    tile_index_to_tile_pos,
+    TILE_SIZE,
+    tile_pos_to_grid_pos,
+    tile_pos_to_pos,
+};
+/// Integer division rounding towards negative infinity as used by the conversions.
+pub use crate::bot::math::floor_div_i32;
+/// Vector types for positions in world (`Vec2f`) and tile, grid or map (`Vec2i`) coordinates.
+pub use crate::bot::vec2::{Vec2f, Vec2i};
+/// Visits unit cells crossed by a segment until the callback returns false.
+pub use crate::bot::walk_grid::walk_grid;
@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::geometry::Segment;
+use crate::bot::objects::{Object, Objects};
+use crate::bot::player::Player;
+use crate::bot::protocol::{Event, Update, Value};
+use crate::bot::vec2::Vec2f;
+
+#[derive(Clone, Deserialize)]
+pub struct DangerZonesConfig {
+    pub animals: Vec<String>,
+    pub combat_widget: String,
+    pub aggro_distance: f64,
+    pub min_approach: f64,
+    pub radius: f64,
+    pub ttl: f64,
+    pub weight: f64,
+    pub color: [f32; 4],
+}
+
+#[derive(Clone, Debug)]
+pub struct DangerZone {
+    pub position: Vec2f,
+    pub radius: f64,
+    pub expire_at: Instant,
+}
+
+impl DangerZone {
+    pub fn contains(&self, position: Vec2f) -> bool {
+        self.position.distance(position) <= self.radius
+    }
+
+    pub fn intersects(&self, segment: &Segment) -> bool {
+        segment.distance(self.position) <= self.radius
+    }
+}
+
+#[derive(Clone)]
+pub struct DangerZones {
+    revision: u64,
+    zones: BTreeMap<i64, DangerZone>,
+    config: DangerZonesConfig,
+}
+
+impl DangerZones {
+    pub fn new(config: DangerZonesConfig) -> Self {
+        Self {
+            revision: 0,
+            zones: BTreeMap::new(),
+            config,
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn config(&self) -> &DangerZonesConfig {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.zones.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&DangerZone> {
+        self.zones.values()
+    }
+
+    pub fn get_weight(&self, position: Vec2f) -> f64 {
+        if self.zones.values().any(|v| v.contains(position)) {
+            self.config.weight
+        } else {
+            0.0
+        }
+    }
+
+    pub fn intersects(&self, segment: &Segment) -> bool {
+        self.zones.values().any(|v| !v.contains(segment.begin) && v.intersects(segment))
+    }
+
+    pub fn update(&mut self, objects: &Objects, player: &Player, update: &Update, now: Instant) -> bool {
+        let mut updated = self.remove_expired(now);
+        match &update.event {
+            Event::GobMove { id, position, .. } => {
+                if self.update_object_position(objects, player, *id, *position, now) {
+                    updated = true;
+                }
+            }
+            Event::UIMessage { id, msg, args } => {
+                let is_combat = player.widgets().get(id)
+                    .map(|v| v.kind == self.config.combat_widget)
+                    .unwrap_or(false);
+                if is_combat && msg.as_str() == "new" && args.len() >= 1 {
+                    if let Value::Int { value } = &args[0] {
+                        if let Some(object) = objects.get_by_id(*value as i64) {
+                            debug!("DangerZones: combat with object {} {:?} at {:?}", object.id, object.name, object.position);
+                            if self.add(object.id, object.position, now) {
+                                updated = true;
+                            }
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+        if updated {
+            self.revision += 1;
+        }
+        updated
+    }
+
+    fn update_object_position(&mut self, objects: &Objects, player: &Player, object_id: i64,
+                              position: Vec2f, now: Instant) -> bool {
+        if player.object_id() == Some(object_id) {
+            return false;
+        }
+        let object = match objects.get_by_id(object_id) {
+            Some(v) => v,
+            None => return false,
+        };
+        if !self.is_animal(object) {
+            return false;
+        }
+        let player_position = match player.object_id().and_then(|id| objects.get_by_id(id)) {
+            Some(v) => v.position,
+            None => return false,
+        };
+        let distance = position.distance(player_position);
+        if distance > self.config.aggro_distance
+            || object.position.distance(player_position) - distance < self.config.min_approach {
+            return false;
+        }
+        debug!("DangerZones: object {} {:?} approaches player at {:?}", object.id, object.name, position);
+        self.add(object_id, position, now)
+    }
+
+    fn is_animal(&self, object: &Object) -> bool {
+        object.name.as_ref()
+            .map(|name| self.config.animals.iter().any(|v| name.starts_with(v)))
+            .unwrap_or(false)
+    }
+
+    fn add(&mut self, object_id: i64, position: Vec2f, now: Instant) -> bool {
+        let expire_at = now + Duration::from_secs_f64(self.config.ttl);
+        if let Some(zone) = self.zones.get_mut(&object_id) {
+            zone.expire_at = expire_at;
+            if zone.contains(position) {
+                return false;
+            }
+            zone.position = position;
+            return true;
+        }
+        self.zones.insert(object_id, DangerZone {
+            position,
+            radius: self.config.radius,
+            expire_at,
+        });
+        true
+    }
+
+    fn remove_expired(&mut self, now: Instant) -> bool {
+        let len = self.zones.len();
+        self.zones.retain(|_, v| v.expire_at > now);
+        len != self.zones.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+    use std::sync::{Arc, Mutex};
+
+    use rusqlite::Connection;
+
+    use crate::bot::metrics::Metrics;
+    use crate::bot::player::PlayerConfig;
+    use crate::bot::sqlite_map_db::SqliteMapDb;
+    use crate::bot::tile_profiles::TileProfiles;
+    use crate::bot::world::{World, WorldConfig};
+
+    use super::*;
+
+    const ANIMAL_ID: i64 = i32::MAX as i64;
+    const COMBAT_WIDGET_ID: i32 = 13;
+
+    fn make_world() -> (World, Player) {
+        let config: serde_yaml::Value = serde_yaml::from_reader(File::open("etc/config.yaml").unwrap()).unwrap();
+        let world_config: WorldConfig = serde_yaml::from_value(config["session"]["world"].clone()).unwrap();
+        let player_config: PlayerConfig = serde_yaml::from_value(config["session"]["player"].clone()).unwrap();
+        let map_db = Arc::new(Mutex::new(SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default())));
+        let tile_profiles = Arc::new(TileProfiles::new(&world_config));
+        let mut world = World::new(world_config, map_db, Arc::new(Metrics::new()), tile_profiles);
+        let mut player = Player::new(player_config);
+        for line in BufReader::new(File::open("tests/input/init_session_start.json").unwrap()).lines() {
+            let update = serde_json::from_str::<Update>(line.unwrap().as_str()).unwrap();
+            player.update(&world, &update);
+            world.update(update);
+        }
+        (world, player)
+    }
+
+    fn make_config() -> DangerZonesConfig {
+        DangerZonesConfig {
+            animals: vec![String::from("gfx/kritter/")],
+            combat_widget: String::from("frv"),
+            aggro_distance: 110.0,
+            min_approach: 1.0,
+            radius: 55.0,
+            ttl: 60.0,
+            weight: 10.0,
+            color: [1.0, 0.0, 0.0, 0.8],
+        }
+    }
+
+    fn make_update(event: Event) -> Update {
+        Update { session: 0, number: 0, event }
+    }
+
+    fn get_player_position(world: &World, player: &Player) -> Vec2f {
+        world.objects().get_by_id(player.object_id().unwrap()).unwrap().position
+    }
+
+    fn add_animal(world: &mut World, position: Vec2f) {
+        world.update(make_update(Event::GobAdd {
+            id: ANIMAL_ID,
+            position,
+            angle: 0.0,
+            name: Some(String::from("gfx/kritter/bear/bear")),
+        }));
+    }
+
+    fn move_animal(danger_zones: &mut DangerZones, world: &mut World, player: &Player, position: Vec2f,
+                   now: Instant) -> bool {
+        let update = make_update(Event::GobMove { id: ANIMAL_ID, position, angle: 0.0 });
+        let updated = danger_zones.update(world.objects(), player, &update, now);
+        world.update(update);
+        updated
+    }
+
+    fn start_combat(danger_zones: &mut DangerZones, world: &World, player: &Player, now: Instant) -> bool {
+        let update = make_update(Event::UIMessage {
+            id: COMBAT_WIDGET_ID,
+            msg: String::from("new"),
+            args: vec![Value::Int { value: ANIMAL_ID as i32 }],
+        });
+        danger_zones.update(world.objects(), player, &update, now)
+    }
+
+    fn make_zone() -> DangerZone {
+        DangerZone {
+            position: Vec2f::new(10.0, 10.0),
+            radius: 5.0,
+            expire_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn danger_zone_intersects_should_find_segment_crossing_zone() {
+        assert!(make_zone().intersects(&Segment::new(Vec2f::new(0.0, 10.0), Vec2f::new(20.0, 10.0))));
+        assert!(make_zone().intersects(&Segment::new(Vec2f::new(0.0, 0.0), Vec2f::new(20.0, 20.0))));
+    }
+
+    #[test]
+    fn danger_zone_intersects_should_ignore_segment_outside_zone() {
+        assert!(!make_zone().intersects(&Segment::new(Vec2f::new(0.0, 0.0), Vec2f::new(20.0, 0.0))));
+        assert!(!make_zone().intersects(&Segment::new(Vec2f::new(0.0, 10.0), Vec2f::new(4.0, 10.0))));
+    }
+
+    #[test]
+    fn danger_zones_should_add_refresh_and_expire_zone_of_approaching_animal() {
+        let (mut world, player) = make_world();
+        let player_position = get_player_position(&world, &player);
+        let mut danger_zones = DangerZones::new(make_config());
+        let now = Instant::now();
+        add_animal(&mut world, player_position + Vec2f::new(200.0, 0.0));
+        assert!(!move_animal(&mut danger_zones, &mut world, &player, player_position + Vec2f::new(150.0, 0.0), now));
+        assert_eq!(danger_zones.len(), 0);
+        assert!(move_animal(&mut danger_zones, &mut world, &player, player_position + Vec2f::new(100.0, 0.0), now));
+        assert_eq!(danger_zones.len(), 1);
+        assert_eq!(danger_zones.revision(), 1);
+        assert_eq!(danger_zones.iter().next().unwrap().position, player_position + Vec2f::new(100.0, 0.0));
+        assert!(!move_animal(&mut danger_zones, &mut world, &player, player_position + Vec2f::new(100.5, 0.0), now));
+        let refreshed_at = now + Duration::from_secs(30);
+        assert!(!move_animal(&mut danger_zones, &mut world, &player, player_position + Vec2f::new(99.0, 0.0), refreshed_at));
+        assert_eq!(danger_zones.iter().next().unwrap().expire_at, refreshed_at + Duration::from_secs(60));
+        assert!(!danger_zones.update(world.objects(), &player, &make_update(Event::Close), now + Duration::from_secs(61)));
+        assert_eq!(danger_zones.len(), 1);
+        assert!(danger_zones.update(world.objects(), &player, &make_update(Event::Close), refreshed_at + Duration::from_secs(61)));
+        assert_eq!(danger_zones.len(), 0);
+    }
+
+    #[test]
+    fn danger_zones_should_add_refresh_and_expire_zone_of_combat_opponent() {
+        let (mut world, player) = make_world();
+        let animal_position = get_player_position(&world, &player) + Vec2f::new(200.0, 0.0);
+        let mut danger_zones = DangerZones::new(make_config());
+        let now = Instant::now();
+        add_animal(&mut world, animal_position);
+        assert!(start_combat(&mut danger_zones, &world, &player, now));
+        assert_eq!(danger_zones.len(), 1);
+        assert_eq!(danger_zones.iter().next().unwrap().position, animal_position);
+        let refreshed_at = now + Duration::from_secs(30);
+        assert!(!start_combat(&mut danger_zones, &world, &player, refreshed_at));
+        assert_eq!(danger_zones.iter().next().unwrap().expire_at, refreshed_at + Duration::from_secs(60));
+        assert!(!danger_zones.update(world.objects(), &player, &make_update(Event::Close), now + Duration::from_secs(61)));
+        assert!(danger_zones.update(world.objects(), &player, &make_update(Event::Close), refreshed_at + Duration::from_secs(60)));
+        assert_eq!(danger_zones.len(), 0);
+    }
+}
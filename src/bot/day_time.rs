@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bot::protocol::Value;
+
+#[derive(Clone, Deserialize)]
+pub struct NightConfig {
+    pub paused_tasks: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DayTime {
+    pub value: f64,
+    pub night: bool,
+}
+
+impl NightConfig {
+    pub fn is_paused(&self, task_name: &str, day_time: Option<DayTime>) -> bool {
+        day_time.map(|v| v.night).unwrap_or(false) && self.paused_tasks.iter().any(|v| v == task_name)
+    }
+}
+
+pub fn parse_day_time(args: &[Value]) -> Option<DayTime> {
+    let value = match args.first() {
+        Some(Value::Float64 { value }) => *value,
+        Some(Value::Float32 { value }) => *value as f64,
+        _ => return None,
+    };
+    let night = match args.get(1) {
+        Some(Value::Int { value }) => *value != 0,
+        _ => return None,
+    };
+    Some(DayTime { value: value.rem_euclid(1.0), night })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_day_time_should_read_day_fraction_and_night_flag() {
+        assert_eq!(
+            parse_day_time(&[Value::from(0.75f64), Value::from(1i32)]),
+            Some(DayTime { value: 0.75, night: true })
+        );
+        assert_eq!(
+            parse_day_time(&[Value::from(1.25f64), Value::from(0i32)]),
+            Some(DayTime { value: 0.25, night: false })
+        );
+        assert_eq!(parse_day_time(&[Value::from(0.5f64)]), None);
+        assert_eq!(parse_day_time(&[Value::from(1i32), Value::from(1i32)]), None);
+    }
+
+    #[test]
+    fn night_config_should_pause_only_listed_tasks_at_night() {
+        let config = NightConfig { paused_tasks: vec![String::from("Explorer")] };
+        let night = Some(DayTime { value: 0.9, night: true });
+        let day = Some(DayTime { value: 0.4, night: false });
+        assert!(config.is_paused("Explorer", night));
+        assert!(!config.is_paused("Idler", night));
+        assert!(!config.is_paused("Explorer", day));
+        assert!(!config.is_paused("Explorer", None));
+    }
+}
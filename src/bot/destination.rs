@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bot::map::{map_pos_to_pos, rel_tile_pos_to_pos};
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Destination {
+    Position(Vec2f),
+    TilePosition(Vec2i),
+    MapPosition(Vec2i),
+    WorldPosition(Vec2f),
+    Location(String),
+    Bookmark(String),
+}
+
+impl Destination {
+    pub fn get_position(&self, world: &PlayerWorld) -> Result<Vec2f, String> {
+        match self {
+            Destination::WorldPosition(position) => Ok(world.import_position(*position)),
+            Destination::Location(name) => world.get_location_position(name.as_str())
+                .ok_or_else(|| format!("Location {:?} is not found", name)),
+            Destination::Bookmark(name) => world.get_bookmark_position(name.as_str())
+                .ok_or_else(|| format!("Bookmark {:?} is not found", name)),
+            _ => self.get_local_position().ok_or_else(|| format!("Invalid destination {:?}", self)),
+        }
+    }
+
+    fn get_local_position(&self) -> Option<Vec2f> {
+        match self {
+            Destination::Position(position) => Some(*position),
+            Destination::TilePosition(tile_pos) => Some(rel_tile_pos_to_pos(tile_pos.center())),
+            Destination::MapPosition(map_pos) => Some(map_pos_to_pos(*map_pos)),
+            Destination::WorldPosition(..) | Destination::Location(..) | Destination::Bookmark(..) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::map::{pos_to_map_pos, pos_to_tile_pos};
+
+    use super::*;
+
+    #[test]
+    fn get_local_position_should_convert_tile_and_map_coordinates() {
+        let tile_pos = Vec2i::new(-10, 42);
+        let position = Destination::TilePosition(tile_pos).get_local_position().unwrap();
+        assert_eq!(pos_to_tile_pos(position), tile_pos);
+        let map_pos = Vec2i::new(-1000, 4200);
+        let position = Destination::MapPosition(map_pos).get_local_position().unwrap();
+        assert_eq!(pos_to_map_pos(position), map_pos);
+        assert_eq!(Destination::Position(Vec2f::new(1.0, 2.0)).get_local_position(), Some(Vec2f::new(1.0, 2.0)));
+        assert_eq!(Destination::Location(String::from("home")).get_local_position(), None);
+    }
+
+    #[test]
+    fn destination_should_be_parsed_from_json() {
+        assert_eq!(
+            serde_json::from_str::<Destination>(r#"{"TilePosition": {"x": 1, "y": 2}}"#).unwrap(),
+            Destination::TilePosition(Vec2i::new(1, 2))
+        );
+        assert_eq!(
+            serde_json::from_str::<Destination>(r#"{"Location": "home"}"#).unwrap(),
+            Destination::Location(String::from("home"))
+        );
+        assert_eq!(
+            serde_json::from_str::<Destination>(r#"{"Bookmark": "mine"}"#).unwrap(),
+            Destination::Bookmark(String::from("mine"))
+        );
+    }
+}
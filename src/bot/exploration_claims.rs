@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::bot::vec2::Vec2i;
+
+struct Claim {
+    session_id: i64,
+    until: Instant,
+}
+
+pub struct ExplorationClaims {
+    values: Mutex<BTreeMap<(i64, Vec2i), Claim>>,
+}
+
+impl ExplorationClaims {
+    pub fn new() -> Self {
+        Self { values: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn claim(&self, session_id: i64, segment_id: i64, grid_pos: Vec2i, until: Instant, now: Instant) -> bool {
+        let mut values = self.values.lock().unwrap();
+        values.retain(|_, v| v.until > now);
+        match values.get_mut(&(segment_id, grid_pos)) {
+            Some(claim) if claim.session_id != session_id => false,
+            Some(claim) => {
+                claim.until = until;
+                true
+            }
+            None => {
+                debug!("ExplorationClaims: session {} claims grid {:?} in segment {}", session_id, grid_pos, segment_id);
+                values.insert((segment_id, grid_pos), Claim { session_id, until });
+                true
+            }
+        }
+    }
+
+    pub fn release(&self, session_id: i64, segment_id: i64, grid_pos: Vec2i) {
+        let mut values = self.values.lock().unwrap();
+        if values.get(&(segment_id, grid_pos)).map(|v| v.session_id == session_id).unwrap_or(false) {
+            debug!("ExplorationClaims: session {} releases grid {:?} in segment {}", session_id, grid_pos, segment_id);
+            values.remove(&(segment_id, grid_pos));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn claimed_grid_should_not_be_available_for_other_session_until_released_or_expired() {
+        let claims = ExplorationClaims::new();
+        let now = Instant::now();
+        let until = now + Duration::from_secs(10);
+        assert!(claims.claim(1, 1, Vec2i::new(1, 2), until, now));
+        assert!(claims.claim(1, 1, Vec2i::new(1, 2), until, now));
+        assert!(!claims.claim(2, 1, Vec2i::new(1, 2), until, now));
+        assert!(claims.claim(2, 2, Vec2i::new(1, 2), until, now));
+        assert!(claims.claim(2, 1, Vec2i::new(1, 3), until, now));
+        claims.release(2, 1, Vec2i::new(1, 2));
+        assert!(!claims.claim(2, 1, Vec2i::new(1, 2), until, now));
+        claims.release(1, 1, Vec2i::new(1, 2));
+        assert!(claims.claim(2, 1, Vec2i::new(1, 2), until, now));
+        assert!(claims.claim(1, 1, Vec2i::new(1, 3), now + Duration::from_secs(20), until));
+    }
+}
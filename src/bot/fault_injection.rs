@@ -0,0 +1,345 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::bookmarks::Bookmark;
+use crate::bot::map::{Grid, GridNeighbour, MapObject, Tile};
+use crate::bot::map_db::{MapDb, MapDbCacheStats};
+use crate::bot::map_retention::{MapPruneStats, MapRetentionConfig};
+use crate::bot::player::Resource;
+use crate::bot::protocol::{Event, Update};
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::zones::Zone;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct FaultsParams {
+    pub drop_updates: usize,
+    pub delay_updates: f64,
+    pub corrupt_grids: usize,
+    pub fail_map_db_writes: usize,
+    pub stall_messages: f64,
+}
+
+pub struct Faults {
+    state: Mutex<FaultsState>,
+}
+
+struct FaultsState {
+    drop_updates: usize,
+    delay_updates: Duration,
+    corrupt_grids: usize,
+    fail_map_db_writes: usize,
+    stall_messages_until: Option<Instant>,
+}
+
+impl Faults {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(FaultsState {
+                drop_updates: 0,
+                delay_updates: Duration::ZERO,
+                corrupt_grids: 0,
+                fail_map_db_writes: 0,
+                stall_messages_until: None,
+            }),
+        }
+    }
+
+    pub fn set(&self, params: &FaultsParams, now: Instant) {
+        warn!("Inject faults: {:?}", params);
+        let mut state = self.state.lock().unwrap();
+        state.drop_updates = params.drop_updates;
+        state.delay_updates = Duration::from_secs_f64(params.delay_updates);
+        state.corrupt_grids = params.corrupt_grids;
+        state.fail_map_db_writes = params.fail_map_db_writes;
+        state.stall_messages_until = if params.stall_messages > 0.0 {
+            Some(now + Duration::from_secs_f64(params.stall_messages))
+        } else {
+            None
+        };
+    }
+
+    pub fn get_update_delay(&self) -> Option<Duration> {
+        let delay = self.state.lock().unwrap().delay_updates;
+        if delay.is_zero() {
+            None
+        } else {
+            Some(delay)
+        }
+    }
+
+    pub fn filter_update(&self, mut update: Update) -> Option<Update> {
+        let mut state = self.state.lock().unwrap();
+        if state.drop_updates > 0 {
+            state.drop_updates -= 1;
+            warn!("Drop update {} for session {}", update.number, update.session);
+            return None;
+        }
+        if state.corrupt_grids > 0 {
+            match &mut update.event {
+                Event::MapGridAdd { grid, .. } | Event::MapGridUpdate { grid } => {
+                    state.corrupt_grids -= 1;
+                    warn!("Corrupt grid {} in update {} for session {}", grid.id, update.number, update.session);
+                    grid.heights.truncate(grid.heights.len() / 2);
+                    grid.tiles.truncate(grid.tiles.len() / 2);
+                }
+                _ => (),
+            }
+        }
+        Some(update)
+    }
+
+    pub fn is_messages_stalled(&self, now: Instant) -> bool {
+        self.get_messages_stall(now).is_some()
+    }
+
+    pub fn get_messages_stall(&self, now: Instant) -> Option<Duration> {
+        self.state.lock().unwrap().stall_messages_until
+            .filter(|v| now < *v)
+            .map(|v| v - now)
+    }
+
+    fn fail_map_db_write(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.fail_map_db_writes > 0 {
+            state.fail_map_db_writes -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct FaultyMapDb {
+    map_db: Arc<Mutex<dyn MapDb + Send>>,
+    faults: Arc<Faults>,
+}
+
+impl FaultyMapDb {
+    pub fn new(map_db: Arc<Mutex<dyn MapDb + Send>>, faults: Arc<Faults>) -> Self {
+        Self { map_db, faults }
+    }
+}
+
+impl MapDb for FaultyMapDb {
+    fn get_tiles(&self) -> Vec<Tile> {
+        self.map_db.lock().unwrap().get_tiles()
+    }
+
+    fn get_tile_id_by_name(&self, name: &String) -> Option<i32> {
+        self.map_db.lock().unwrap().get_tile_id_by_name(name)
+    }
+
+    fn set_tile(&self, tile: &Tile) {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to set tile {}: injected fault", tile.id);
+            return;
+        }
+        self.map_db.lock().unwrap().set_tile(tile)
+    }
+
+    fn get_grids(&self) -> Vec<Grid> {
+        self.map_db.lock().unwrap().get_grids()
+    }
+
+    fn get_grid_ids_by_segment_id(&self, segment_id: i64) -> Vec<i64> {
+        self.map_db.lock().unwrap().get_grid_ids_by_segment_id(segment_id)
+    }
+
+    fn get_grid_by_id(&self, grid_id: i64) -> Option<Arc<Mutex<Grid>>> {
+        self.map_db.lock().unwrap().get_grid_by_id(grid_id)
+    }
+
+    fn get_grid(&self, segment_id: i64, position: Vec2i) -> Option<Arc<Mutex<Grid>>> {
+        self.map_db.lock().unwrap().get_grid(segment_id, position)
+    }
+
+    fn add_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>, neighbours: &Vec<GridNeighbour>) {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to add grid {}: injected fault", grid_id);
+            return;
+        }
+        self.map_db.lock().unwrap().add_grid(grid_id, heights, tiles, neighbours)
+    }
+
+    fn update_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>) -> bool {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to update grid {}: injected fault", grid_id);
+            return true;
+        }
+        self.map_db.lock().unwrap().update_grid(grid_id, heights, tiles)
+    }
+
+    fn set_grid_revision(&self, grid_id: i64, revision: i64) {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to set grid {} revision: injected fault", grid_id);
+            return;
+        }
+        self.map_db.lock().unwrap().set_grid_revision(grid_id, revision)
+    }
+
+    fn get_grid_changes(&self, since_change_id: i64, limit: usize) -> Vec<(i64, Grid)> {
+        self.map_db.lock().unwrap().get_grid_changes(since_change_id, limit)
+    }
+
+    fn add_object(&self, object: &MapObject) {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to add object {}: injected fault", object.id);
+            return;
+        }
+        self.map_db.lock().unwrap().add_object(object)
+    }
+
+    fn remove_object(&self, object_id: i64) {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to remove object {}: injected fault", object_id);
+            return;
+        }
+        self.map_db.lock().unwrap().remove_object(object_id)
+    }
+
+    fn get_objects_in_rect(&self, segment_id: i64, min: Vec2f, max: Vec2f) -> Vec<(Vec2f, MapObject)> {
+        self.map_db.lock().unwrap().get_objects_in_rect(segment_id, min, max)
+    }
+
+    fn set_grid_last_seen(&self, grid_id: i64, last_seen: i64) {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to set grid {} last seen: injected fault", grid_id);
+            return;
+        }
+        self.map_db.lock().unwrap().set_grid_last_seen(grid_id, last_seen)
+    }
+
+    fn get_grids_older_than(&self, last_seen: i64) -> Vec<i64> {
+        self.map_db.lock().unwrap().get_grids_older_than(last_seen)
+    }
+
+    fn merge_segments(&self, src_segment_id: i64, dst_segment_id: i64, shift: Vec2i) -> Result<usize, String> {
+        if self.faults.fail_map_db_write() {
+            return Err(format!("Failed to merge segment {} into {}: injected fault", src_segment_id, dst_segment_id));
+        }
+        self.map_db.lock().unwrap().merge_segments(src_segment_id, dst_segment_id, shift)
+    }
+
+    fn split_segment(&self, segment_id: i64, grid_ids: &Vec<i64>) -> Result<i64, String> {
+        if self.faults.fail_map_db_write() {
+            return Err(format!("Failed to split segment {}: injected fault", segment_id));
+        }
+        self.map_db.lock().unwrap().split_segment(segment_id, grid_ids)
+    }
+
+    fn get_cache_stats(&self) -> MapDbCacheStats {
+        self.map_db.lock().unwrap().get_cache_stats()
+    }
+
+    fn set_pinned_grids(&self, owner: u64, coords: BTreeSet<(i64, Vec2i)>) {
+        self.map_db.lock().unwrap().set_pinned_grids(owner, coords);
+    }
+
+    fn set_zone(&self, zone: &Zone) {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to set zone {}: injected fault", zone.name);
+            return;
+        }
+        self.map_db.lock().unwrap().set_zone(zone)
+    }
+
+    fn remove_zone(&self, segment_id: i64, name: &String) -> bool {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to remove zone {}: injected fault", name);
+            return false;
+        }
+        self.map_db.lock().unwrap().remove_zone(segment_id, name)
+    }
+
+    fn get_zones(&self, segment_id: i64) -> Vec<Zone> {
+        self.map_db.lock().unwrap().get_zones(segment_id)
+    }
+
+    fn set_bookmark(&self, bookmark: &Bookmark) {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to set bookmark {}: injected fault", bookmark.name);
+            return;
+        }
+        self.map_db.lock().unwrap().set_bookmark(bookmark)
+    }
+
+    fn remove_bookmark(&self, segment_id: i64, name: &String) -> bool {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to remove bookmark {}: injected fault", name);
+            return false;
+        }
+        self.map_db.lock().unwrap().remove_bookmark(segment_id, name)
+    }
+
+    fn get_bookmarks(&self, segment_id: i64) -> Vec<Bookmark> {
+        self.map_db.lock().unwrap().get_bookmarks(segment_id)
+    }
+
+    fn get_resources(&self) -> Vec<Resource> {
+        self.map_db.lock().unwrap().get_resources()
+    }
+
+    fn set_resource(&self, resource: &Resource) {
+        if self.faults.fail_map_db_write() {
+            error!("Failed to set resource {}: injected fault", resource.name);
+            return;
+        }
+        self.map_db.lock().unwrap().set_resource(resource)
+    }
+
+    fn prune(&self, config: &MapRetentionConfig, now: i64) -> Result<MapPruneStats, String> {
+        if self.faults.fail_map_db_write() {
+            return Err(String::from("Failed to prune map: injected fault"));
+        }
+        self.map_db.lock().unwrap().prune(config, now)
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        self.map_db.lock().unwrap().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::protocol::MapGrid;
+
+    use super::*;
+
+    fn make_grid_update(number: i64) -> Update {
+        Update {
+            session: 1,
+            number,
+            event: Event::MapGridUpdate {
+                grid: MapGrid { id: 1, position: Vec2i::zero(), heights: vec![1.0; 4], tiles: vec![1; 4] },
+            },
+        }
+    }
+
+    #[test]
+    fn filter_update_should_drop_and_corrupt_requested_number_of_updates() {
+        let faults = Faults::new();
+        faults.set(&FaultsParams { drop_updates: 1, corrupt_grids: 1, ..Default::default() }, Instant::now());
+        assert!(faults.filter_update(make_grid_update(1)).is_none());
+        match faults.filter_update(make_grid_update(2)).map(|v| v.event) {
+            Some(Event::MapGridUpdate { grid }) => assert_eq!((grid.heights.len(), grid.tiles.len()), (2, 2)),
+            v => panic!("Unexpected event: {:?}", v),
+        }
+        match faults.filter_update(make_grid_update(3)).map(|v| v.event) {
+            Some(Event::MapGridUpdate { grid }) => assert_eq!((grid.heights.len(), grid.tiles.len()), (4, 4)),
+            v => panic!("Unexpected event: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn messages_should_be_stalled_for_requested_duration() {
+        let faults = Faults::new();
+        let now = Instant::now();
+        faults.set(&FaultsParams { stall_messages: 2.0, ..Default::default() }, now);
+        assert!(faults.is_messages_stalled(now + Duration::from_secs(1)));
+        assert!(!faults.is_messages_stalled(now + Duration::from_secs(2)));
+        assert_eq!(faults.get_messages_stall(now + Duration::from_secs(1)), Some(Duration::from_secs(1)));
+    }
+}
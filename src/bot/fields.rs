@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::map::TileSet;
+use crate::bot::vec2::Vec2i;
+use crate::bot::zones::Zone;
+
+#[derive(Clone, Deserialize)]
+pub struct FieldsConfig {
+    pub tiles: Vec<String>,
+    pub min_tiles: usize,
+}
+
+impl FieldsConfig {
+    pub fn is_field_tile(&self, name: &str) -> bool {
+        self.tiles.iter().any(|v| name.starts_with(v.as_str()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Field {
+    pub zone: Zone,
+    pub from: Vec2i,
+    pub to: Vec2i,
+    pub tiles: usize,
+}
+
+pub struct FieldTiles(pub BTreeSet<i32>);
+
+impl TileSet for FieldTiles {
+    fn contains(&self, tile: i32) -> bool {
+        self.0.contains(&tile)
+    }
+}
+
+// Navigator areas exclude tiles next to not allowed ones so a component covers only the inner part of a field
+// and bounds are grown back by one tile.
+pub fn make_field(segment_id: i64, component: &[Vec2i], shift: Vec2i,
+                  is_field_tile: impl Fn(Vec2i) -> bool) -> Option<Field> {
+    let first = *component.first()?;
+    let (min, max) = component.iter().fold((first, first), |(min, max), v| (
+        Vec2i::new(min.x().min(v.x()), min.y().min(v.y())),
+        Vec2i::new(max.x().max(v.x()), max.y().max(v.y())),
+    ));
+    let (min, max) = (min - Vec2i::new(1, 1), max + Vec2i::new(1, 1));
+    let tiles = (min.y()..=max.y())
+        .flat_map(|y| (min.x()..=max.x()).map(move |x| Vec2i::new(x, y)))
+        .filter(|v| is_field_tile(*v))
+        .count();
+    Some(Field {
+        zone: Zone {
+            segment_id,
+            name: String::from("field"),
+            points: vec![min, Vec2i::new(max.x() + 1, min.y()), max + Vec2i::new(1, 1), Vec2i::new(min.x(), max.y() + 1)],
+        },
+        from: min - shift,
+        to: max - shift,
+        tiles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_field_should_grow_component_bounds_to_field_border() {
+        let component = vec![Vec2i::new(11, 21), Vec2i::new(12, 21), Vec2i::new(11, 22), Vec2i::new(12, 22)];
+        let field = make_field(1, &component, Vec2i::new(10, 20), |v| v.x() >= 10 && v.x() <= 13 && v.y() >= 20 && v.y() <= 22).unwrap();
+        assert_eq!(field.zone.segment_id, 1);
+        assert_eq!(field.from, Vec2i::new(0, 0));
+        assert_eq!(field.to, Vec2i::new(3, 3));
+        assert_eq!(field.tiles, 12);
+        assert_eq!(field.zone.iter_tiles().count(), 16);
+        assert!(field.zone.contains(Vec2i::new(10, 20)));
+        assert!(field.zone.contains(Vec2i::new(13, 23)));
+        assert!(!field.zone.contains(Vec2i::new(14, 23)));
+        assert_eq!(make_field(1, &[], Vec2i::zero(), |_| true), None);
+    }
+
+    #[test]
+    fn fields_config_should_match_tile_name_prefix() {
+        let config = FieldsConfig { tiles: vec![String::from("gfx/tiles/field")], min_tiles: 4 };
+        assert!(config.is_field_tile("gfx/tiles/field"));
+        assert!(config.is_field_tile("gfx/tiles/fieldx"));
+        assert!(!config.is_field_tile("gfx/tiles/grass"));
+    }
+}
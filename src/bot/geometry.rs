@@ -0,0 +1,166 @@
+use crate::bot::vec2::Vec2f;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub min: Vec2f,
+    pub max: Vec2f,
+}
+
+impl Rect {
+    pub fn new(min: Vec2f, max: Vec2f) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_points<'a>(mut points: impl Iterator<Item=&'a Vec2f>) -> Option<Self> {
+        let first = *points.next()?;
+        Some(points.fold(Self::new(first, first), |rect, v| Self::new(
+            Vec2f::new(rect.min.x().min(v.x()), rect.min.y().min(v.y())),
+            Vec2f::new(rect.max.x().max(v.x()), rect.max.y().max(v.y())),
+        )))
+    }
+
+    pub fn contains(&self, position: Vec2f) -> bool {
+        self.min.x() <= position.x() && position.x() <= self.max.x()
+            && self.min.y() <= position.y() && position.y() <= self.max.y()
+    }
+
+    pub fn area(&self) -> f64 {
+        ((self.max.x() - self.min.x()) * (self.max.y() - self.min.y())).max(0.0)
+    }
+
+    pub fn closest_point(&self, position: Vec2f) -> Vec2f {
+        Vec2f::new(
+            position.x().max(self.min.x()).min(self.max.x()),
+            position.y().max(self.min.y()).min(self.max.y()),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment {
+    pub begin: Vec2f,
+    pub end: Vec2f,
+}
+
+impl Segment {
+    pub fn new(begin: Vec2f, end: Vec2f) -> Self {
+        Self { begin, end }
+    }
+
+    #[allow(dead_code)]
+    pub fn length(&self) -> f64 {
+        self.begin.distance(self.end)
+    }
+
+    pub fn closest_point(&self, position: Vec2f) -> Vec2f {
+        let direction = self.end - self.begin;
+        let length = direction.dot(direction);
+        if length == 0.0 {
+            return self.begin;
+        }
+        let factor = ((position - self.begin).dot(direction) / length).max(0.0).min(1.0);
+        self.begin + direction * factor
+    }
+
+    pub fn distance(&self, position: Vec2f) -> f64 {
+        self.closest_point(position).distance(position)
+    }
+
+    #[allow(dead_code)]
+    pub fn intersection(&self, other: &Segment) -> Option<Vec2f> {
+        let direction = self.end - self.begin;
+        let other_direction = other.end - other.begin;
+        let denominator = direction.cross(other_direction);
+        let shift = other.begin - self.begin;
+        if denominator == 0.0 {
+            if shift.cross(direction) != 0.0 {
+                return None;
+            }
+            return [other.begin, other.end, self.begin, self.end].iter()
+                .find(|v| self.closest_point(**v) == **v && other.closest_point(**v) == **v)
+                .cloned();
+        }
+        let factor = shift.cross(other_direction) / denominator;
+        let other_factor = shift.cross(direction) / denominator;
+        if (0.0..=1.0).contains(&factor) && (0.0..=1.0).contains(&other_factor) {
+            Some(self.begin + direction * factor)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn is_inside_polygon(points: &[Vec2f], position: Vec2f) -> bool {
+    if points.len() < 3 {
+        return false;
+    }
+    let mut result = false;
+    let mut prev = points[points.len() - 1];
+    for &point in points.iter() {
+        if (point.y() > position.y()) != (prev.y() > position.y()) {
+            let x = point.x() + (position.y() - point.y()) * (prev.x() - point.x()) / (prev.y() - point.y());
+            if position.x() < x {
+                result = !result;
+            }
+        }
+        prev = point;
+    }
+    result
+}
+
+pub fn get_polygon_area(points: &[Vec2f]) -> f64 {
+    if points.len() < 3 {
+        return 0.0;
+    }
+    let mut doubled = 0.0;
+    let mut prev = points[points.len() - 1];
+    for &point in points.iter() {
+        doubled += prev.cross(point);
+        prev = point;
+    }
+    doubled.abs() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rect_should_contain_points_inside_bounds_and_clamp_closest_point() {
+        let points = vec![Vec2f::new(2.0, 1.0), Vec2f::new(0.0, 3.0), Vec2f::new(1.0, 0.0)];
+        let rect = Rect::from_points(points.iter()).unwrap();
+        assert_eq!(rect, Rect::new(Vec2f::new(0.0, 0.0), Vec2f::new(2.0, 3.0)));
+        assert_eq!(rect.area(), 6.0);
+        assert!(rect.contains(Vec2f::new(2.0, 3.0)));
+        assert!(!rect.contains(Vec2f::new(2.5, 1.0)));
+        assert_eq!(rect.closest_point(Vec2f::new(5.0, -1.0)), Vec2f::new(2.0, 0.0));
+        assert_eq!(Rect::from_points(Vec::new().iter()), None);
+    }
+
+    #[test]
+    fn segment_closest_point_should_be_clamped_to_ends() {
+        let segment = Segment::new(Vec2f::new(0.0, 0.0), Vec2f::new(10.0, 0.0));
+        assert_eq!(segment.closest_point(Vec2f::new(5.0, 3.0)), Vec2f::new(5.0, 0.0));
+        assert_eq!(segment.closest_point(Vec2f::new(-5.0, 3.0)), Vec2f::new(0.0, 0.0));
+        assert_eq!(segment.distance(Vec2f::new(13.0, 4.0)), 5.0);
+        assert_eq!(Segment::new(Vec2f::new(1.0, 1.0), Vec2f::new(1.0, 1.0)).distance(Vec2f::new(4.0, 5.0)), 5.0);
+    }
+
+    #[test]
+    fn segment_intersection_should_find_crossing_point() {
+        let segment = Segment::new(Vec2f::new(0.0, 0.0), Vec2f::new(10.0, 10.0));
+        assert_eq!(segment.intersection(&Segment::new(Vec2f::new(0.0, 10.0), Vec2f::new(10.0, 0.0))), Some(Vec2f::new(5.0, 5.0)));
+        assert_eq!(segment.intersection(&Segment::new(Vec2f::new(0.0, 10.0), Vec2f::new(4.0, 6.0))), None);
+        assert_eq!(segment.intersection(&Segment::new(Vec2f::new(0.0, 1.0), Vec2f::new(10.0, 11.0))), None);
+        assert_eq!(segment.intersection(&Segment::new(Vec2f::new(10.0, 10.0), Vec2f::new(20.0, 20.0))), Some(Vec2f::new(10.0, 10.0)));
+    }
+
+    #[test]
+    fn polygon_should_contain_only_inner_positions_and_have_area() {
+        let points = vec![Vec2f::new(0.0, 0.0), Vec2f::new(10.0, 0.0), Vec2f::new(0.0, 10.0)];
+        assert!(is_inside_polygon(&points, Vec2f::new(2.0, 2.0)));
+        assert!(!is_inside_polygon(&points, Vec2f::new(8.0, 8.0)));
+        assert!(!is_inside_polygon(&points[0..2], Vec2f::new(2.0, 0.0)));
+        assert_eq!(get_polygon_area(&points), 50.0);
+    }
+}
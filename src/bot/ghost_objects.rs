@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::bot::vec2::Vec2i;
+
+#[derive(Clone, Deserialize)]
+pub struct GhostObjectsConfig {
+    pub enabled: bool,
+    pub grace_updates: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GhostCheck {
+    pub grid_id: i64,
+    pub position: Vec2i,
+    pub last_seen_before: i64,
+}
+
+struct PendingCheck {
+    check: GhostCheck,
+    due: i64,
+}
+
+pub struct GhostObjects {
+    streamed_grids: BTreeMap<i64, Vec2i>,
+    removed_grids: BTreeMap<i64, i64>,
+    pending: Vec<PendingCheck>,
+    config: GhostObjectsConfig,
+}
+
+impl GhostObjects {
+    pub fn new(config: GhostObjectsConfig) -> Self {
+        Self {
+            streamed_grids: BTreeMap::new(),
+            removed_grids: BTreeMap::new(),
+            pending: Vec::new(),
+            config,
+        }
+    }
+
+    pub fn streamed_grids(&self) -> usize {
+        self.streamed_grids.len()
+    }
+
+    pub fn add_grid(&mut self, grid_id: i64, position: Vec2i, update: i64) {
+        let already_streamed = self.streamed_grids.insert(grid_id, position) == Some(position);
+        if !self.config.enabled || already_streamed {
+            return;
+        }
+        self.pending.retain(|v| v.check.grid_id != grid_id);
+        self.pending.push(PendingCheck {
+            check: GhostCheck {
+                grid_id,
+                position,
+                last_seen_before: self.removed_grids.remove(&grid_id).map(|v| v + 1).unwrap_or(1),
+            },
+            due: update + self.config.grace_updates,
+        });
+    }
+
+    pub fn remove_grid(&mut self, grid_id: i64, update: i64) {
+        if self.streamed_grids.remove(&grid_id).is_some() {
+            self.removed_grids.insert(grid_id, update);
+        }
+        self.pending.retain(|v| v.check.grid_id != grid_id);
+    }
+
+    pub fn take_due(&mut self, update: i64) -> Vec<GhostCheck> {
+        let (due, pending): (Vec<PendingCheck>, Vec<PendingCheck>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|v| v.due <= update);
+        self.pending = pending;
+        due.into_iter().map(|v| v.check).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_ghost_objects() -> GhostObjects {
+        GhostObjects::new(GhostObjectsConfig { enabled: true, grace_updates: 10 })
+    }
+
+    #[test]
+    fn take_due_should_return_check_after_grace_updates() {
+        let mut ghosts = make_ghost_objects();
+        ghosts.add_grid(1, Vec2i::new(1, 2), 5);
+        assert_eq!(ghosts.take_due(14), Vec::new());
+        assert_eq!(ghosts.take_due(15), vec![GhostCheck { grid_id: 1, position: Vec2i::new(1, 2), last_seen_before: 1 }]);
+        assert_eq!(ghosts.take_due(16), Vec::new());
+    }
+
+    #[test]
+    fn refreshed_grid_should_check_objects_not_seen_since_removal() {
+        let mut ghosts = make_ghost_objects();
+        ghosts.add_grid(1, Vec2i::new(1, 2), 5);
+        ghosts.add_grid(1, Vec2i::new(1, 2), 7);
+        assert_eq!(ghosts.take_due(15).len(), 1);
+        ghosts.remove_grid(1, 20);
+        assert_eq!(ghosts.streamed_grids(), 0);
+        ghosts.add_grid(1, Vec2i::new(1, 2), 30);
+        assert_eq!(ghosts.streamed_grids(), 1);
+        assert_eq!(ghosts.take_due(40), vec![GhostCheck { grid_id: 1, position: Vec2i::new(1, 2), last_seen_before: 21 }]);
+    }
+
+    #[test]
+    fn removed_grid_should_cancel_pending_check() {
+        let mut ghosts = make_ghost_objects();
+        ghosts.add_grid(1, Vec2i::new(1, 2), 5);
+        ghosts.remove_grid(1, 6);
+        assert_eq!(ghosts.take_due(100), Vec::new());
+        assert_eq!(ghosts.streamed_grids(), 0);
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::bot::map::Grid;
+use crate::bot::vec2::Vec2i;
+
+pub struct GridsOfInterest {
+    revision: u64,
+    positions: BTreeSet<Vec2i>,
+    grid_revisions: BTreeMap<Vec2i, (i64, i64)>,
+}
+
+impl GridsOfInterest {
+    pub fn new() -> Self {
+        Self {
+            revision: 0,
+            positions: BTreeSet::new(),
+            grid_revisions: BTreeMap::new(),
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Vec2i> {
+        self.positions.iter()
+    }
+
+    pub fn contains(&self, grid_pos: Vec2i) -> bool {
+        self.positions.contains(&grid_pos)
+    }
+
+    pub fn set(&mut self, positions: Vec<Vec2i>) {
+        self.positions = positions.into_iter().collect();
+        let positions = &self.positions;
+        self.grid_revisions.retain(|position, _| positions.contains(position));
+        self.revision += 1;
+    }
+
+    pub fn update(&mut self, grid_pos: Vec2i, grid: &Grid) -> bool {
+        if !self.positions.contains(&grid_pos) {
+            return false;
+        }
+        let value = (grid.id, grid.revision);
+        if self.grid_revisions.get(&grid_pos) == Some(&value) {
+            return false;
+        }
+        self.grid_revisions.insert(grid_pos, value);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_grid(revision: i64) -> Grid {
+        Grid {
+            id: 1,
+            revision,
+            segment_id: 1,
+            position: Vec2i::zero(),
+            heights: Vec::new(),
+            tiles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn update_should_report_only_changed_grids_of_interest() {
+        let mut grids_of_interest = GridsOfInterest::new();
+        grids_of_interest.set(vec![Vec2i::new(1, 2)]);
+        assert!(grids_of_interest.update(Vec2i::new(1, 2), &make_grid(1)));
+        assert!(!grids_of_interest.update(Vec2i::new(1, 2), &make_grid(1)));
+        assert!(grids_of_interest.update(Vec2i::new(1, 2), &make_grid(2)));
+        assert!(!grids_of_interest.update(Vec2i::new(2, 1), &make_grid(3)));
+    }
+
+    #[test]
+    fn set_should_replace_positions_and_bump_revision() {
+        let mut grids_of_interest = GridsOfInterest::new();
+        grids_of_interest.set(vec![Vec2i::new(1, 2)]);
+        grids_of_interest.update(Vec2i::new(1, 2), &make_grid(1));
+        grids_of_interest.set(vec![Vec2i::new(1, 2), Vec2i::new(3, 4)]);
+        assert_eq!(grids_of_interest.revision(), 2);
+        assert_eq!(grids_of_interest.len(), 2);
+        assert!(!grids_of_interest.update(Vec2i::new(1, 2), &make_grid(1)));
+        grids_of_interest.set(Vec::new());
+        assert!(!grids_of_interest.contains(Vec2i::new(1, 2)));
+    }
+}
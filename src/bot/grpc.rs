@@ -0,0 +1,219 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use actix_web::http::Method;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio::time::delay_for;
+use tonic::{Request, Response, Status, Streaming};
+use tonic::transport::Server;
+
+use crate::bot::auth::AuthConfig;
+use crate::bot::protocol::{Message, Update};
+use crate::bot::server::{add_session_task, cancel_session, cancel_session_task, get_sessions, handle_update, poll_message, remove_session_task, State, update_session_task};
+use crate::bot::session_stats::DeliveryChannel;
+
+mod proto {
+    tonic::include_proto!("hafen_bot");
+}
+
+use proto::hafen_bot_server::{HafenBot, HafenBotServer};
+
+const MESSAGES_BUFFER_SIZE: usize = 16;
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct HafenBotService {
+    state: State,
+    poll_interval: Duration,
+    stop: Arc<AtomicBool>,
+}
+
+#[tonic::async_trait]
+impl HafenBot for HafenBotService {
+    async fn push(&self, request: Request<proto::Update>) -> Result<Response<proto::Reply>, Status> {
+        let update = make_update(request.into_inner())?;
+        Ok(Response::new(make_reply(handle_update(&self.state, update))))
+    }
+
+    async fn push_stream(&self, request: Request<Streaming<proto::Update>>) -> Result<Response<proto::Reply>, Status> {
+        let mut stream = request.into_inner();
+        while let Some(value) = stream.message().await? {
+            let message = handle_update(&self.state, make_update(value)?);
+            if let Message::Error { .. } = &message {
+                return Ok(Response::new(make_reply(message)));
+            }
+        }
+        Ok(Response::new(make_reply(Message::Ok)))
+    }
+
+    async fn poll(&self, request: Request<proto::SessionRequest>) -> Result<Response<proto::Reply>, Status> {
+        let session_id = request.into_inner().session;
+        Ok(Response::new(make_reply(poll_message(&self.state, session_id, DeliveryChannel::Grpc))))
+    }
+
+    type MessagesStream = mpsc::Receiver<Result<proto::Reply, Status>>;
+
+    async fn messages(&self, request: Request<proto::SessionRequest>) -> Result<Response<Self::MessagesStream>, Status> {
+        let session_id = request.into_inner().session;
+        let (mut sender, receiver) = mpsc::channel(MESSAGES_BUFFER_SIZE);
+        let state = self.state.clone();
+        let poll_interval = self.poll_interval;
+        let stop = self.stop.clone();
+        debug!("gRPC messages stream for session {} is connected", session_id);
+        tokio::spawn(async move {
+            while !stop.load(Ordering::Relaxed) {
+                let reply = match poll_message(&state, session_id, DeliveryChannel::Grpc) {
+                    Message::Ok => {
+                        delay_for(poll_interval).await;
+                        continue;
+                    }
+                    Message::Error { message } => Err(Status::not_found(message)),
+                    message => Ok(make_reply(message)),
+                };
+                let is_error = reply.is_err();
+                if sender.send(reply).await.is_err() || is_error {
+                    break;
+                }
+            }
+            debug!("gRPC messages stream for session {} is disconnected", session_id);
+        });
+        Ok(Response::new(receiver))
+    }
+
+    async fn add_task(&self, request: Request<proto::AddTaskRequest>) -> Result<Response<proto::Reply>, Status> {
+        let value = request.into_inner();
+        Ok(Response::new(make_reply(add_session_task(&self.state, value.session, value.name.as_str(), &value.params))))
+    }
+
+    async fn remove_task(&self, request: Request<proto::TaskRequest>) -> Result<Response<proto::Reply>, Status> {
+        let value = request.into_inner();
+        Ok(Response::new(make_reply(remove_session_task(&self.state, value.session, value.task_id))))
+    }
+
+    async fn update_task(&self, request: Request<proto::UpdateTaskRequest>) -> Result<Response<proto::Reply>, Status> {
+        let value = request.into_inner();
+        Ok(Response::new(make_reply(update_session_task(&self.state, value.session, value.task_id, &value.params))))
+    }
+
+    async fn cancel(&self, request: Request<proto::SessionRequest>) -> Result<Response<proto::Reply>, Status> {
+        let session_id = request.into_inner().session;
+        Ok(Response::new(make_reply(cancel_session(&self.state, session_id))))
+    }
+
+    async fn cancel_task(&self, request: Request<proto::TaskRequest>) -> Result<Response<proto::Reply>, Status> {
+        let value = request.into_inner();
+        Ok(Response::new(make_reply(cancel_session_task(&self.state, value.session, value.task_id))))
+    }
+
+    async fn sessions(&self, _: Request<proto::SessionsRequest>) -> Result<Response<proto::SessionsReply>, Status> {
+        let sessions = match get_sessions(&self.state) {
+            Message::Sessions { value } => value,
+            _ => Vec::new(),
+        };
+        Ok(Response::new(proto::SessionsReply {
+            sessions: sessions.into_iter()
+                .map(|session| proto::SessionInfo {
+                    id: session.id,
+                    tasks: session.task_statuses.into_iter()
+                        .map(|task| proto::TaskInfo { id: task.id, name: task.name, state: task.status.state })
+                        .collect(),
+                    updates: session.updates as u64,
+                    messages: session.messages as u64,
+                })
+                .collect(),
+        }))
+    }
+}
+
+pub fn start_grpc_server(state: State, bind_addr: String, auth: AuthConfig, poll_interval: Duration,
+                         stop: Arc<AtomicBool>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let addr = match bind_addr.parse() {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to parse gRPC bind address {}: {}", bind_addr, e);
+                return;
+            }
+        };
+        let service = HafenBotService { state, poll_interval, stop: stop.clone() };
+        let interceptor = move |request: Request<()>| {
+            let authorization = request.metadata().get("authorization").and_then(|v| v.to_str().ok());
+            match auth.check(&Method::POST, "/grpc", authorization) {
+                Ok(_) => Ok(request),
+                Err(e) => {
+                    warn!("Reject gRPC request: {}", e);
+                    Err(Status::unauthenticated(e))
+                }
+            }
+        };
+        let shutdown = async move {
+            while !stop.load(Ordering::Relaxed) {
+                delay_for(SHUTDOWN_POLL_INTERVAL).await;
+            }
+        };
+        let mut runtime = match Runtime::new() {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to create gRPC server runtime: {}", e);
+                return;
+            }
+        };
+        info!("Start gRPC server on {}", addr);
+        let result = runtime.block_on(
+            Server::builder()
+                .add_service(HafenBotServer::with_interceptor(service, interceptor))
+                .serve_with_shutdown(addr, shutdown)
+        );
+        match result {
+            Ok(_) => info!("gRPC server is stopped"),
+            Err(e) => error!("gRPC server failed: {}", e),
+        }
+    })
+}
+
+fn make_update(value: proto::Update) -> Result<Update, Status> {
+    match serde_json::from_str(&value.event) {
+        Ok(event) => Ok(Update { session: value.session, number: value.number, event }),
+        Err(e) => Err(Status::invalid_argument(format!("Failed to parse event: {}", e))),
+    }
+}
+
+fn make_reply(message: Message) -> proto::Reply {
+    let value = serde_json::to_value(&message).unwrap();
+    proto::Reply {
+        kind: value["type"].as_str().map(String::from).unwrap_or_default(),
+        error: match message {
+            Message::Error { message } => message,
+            _ => String::new(),
+        },
+        message: value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::protocol::Event;
+
+    use super::*;
+
+    #[test]
+    fn make_update_should_parse_json_event() {
+        let update = make_update(proto::Update {
+            session: 1,
+            number: 2,
+            event: String::from(r#"{"type":"GobRemove","id":42}"#),
+        });
+        assert_eq!(update.ok(), Some(Update { session: 1, number: 2, event: Event::GobRemove { id: 42 } }));
+        assert!(make_update(proto::Update { session: 1, number: 2, event: String::from("{}") }).is_err());
+    }
+
+    #[test]
+    fn make_reply_should_keep_message_type_and_error() {
+        let reply = make_reply(Message::Error { message: String::from("Session is not found") });
+        assert_eq!(reply.kind, "Error");
+        assert_eq!(reply.error, "Session is not found");
+        assert_eq!(reply.message, r#"{"message":"Session is not found","type":"Error"}"#);
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::protocol::{Event, Message, Modifier, Value};
+
+#[derive(Clone, Deserialize)]
+pub struct HumanControlConfig {
+    pub idle_timeout: f64,
+    pub bot_message_ttl: f64,
+    pub actions: Vec<String>,
+}
+
+pub struct HumanControl {
+    idle_timeout: Duration,
+    bot_message_ttl: Duration,
+    actions: Vec<String>,
+    bot_messages: VecDeque<BotMessage>,
+    last_human_action: Option<Instant>,
+}
+
+struct BotMessage {
+    sent_at: Instant,
+    sender: i32,
+    kind: String,
+    arguments: Vec<Value>,
+}
+
+impl HumanControl {
+    pub fn new(config: HumanControlConfig) -> Self {
+        Self {
+            idle_timeout: Duration::from_secs_f64(config.idle_timeout),
+            bot_message_ttl: Duration::from_secs_f64(config.bot_message_ttl),
+            actions: config.actions,
+            bot_messages: VecDeque::new(),
+            last_human_action: None,
+        }
+    }
+
+    pub fn is_active(&self, now: Instant) -> bool {
+        self.last_human_action.map(|v| now - v < self.idle_timeout).unwrap_or(false)
+    }
+
+    pub fn add_bot_message(&mut self, message: &Message, now: Instant) {
+        if let Message::WidgetMessage { sender, kind, arguments } = message {
+            self.remove_expired(now);
+            if let Some(last) = self.bot_messages.back_mut() {
+                if last.sender == *sender && last.kind == *kind && last.arguments == *arguments {
+                    last.sent_at = now;
+                    return;
+                }
+            }
+            self.bot_messages.push_back(BotMessage {
+                sent_at: now,
+                sender: *sender,
+                kind: kind.clone(),
+                arguments: arguments.clone(),
+            });
+        }
+    }
+
+    pub fn update(&mut self, event: &Event, now: Instant) -> bool {
+        if let Event::WidgetMessage { id, msg, args } = event {
+            self.remove_expired(now);
+            let bot_message = self.bot_messages.iter()
+                .position(|v| v.sender == *id && v.kind == *msg && v.arguments == *args);
+            if let Some(index) = bot_message {
+                self.bot_messages.remove(index);
+                return false;
+            }
+            if self.actions.contains(msg) && !is_bot_command(msg, args) {
+                debug!("HumanControl: human action {} for widget {}", msg, id);
+                self.last_human_action = Some(now);
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn is_bot_message(&self, event: &Event, now: Instant) -> bool {
+        if let Event::WidgetMessage { id, msg, args } = event {
+            return is_bot_command(msg, args) || self.bot_messages.iter()
+                .any(|v| now - v.sent_at < self.bot_message_ttl && v.sender == *id && v.kind == *msg && v.arguments == *args);
+        }
+        false
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        while let Some(message) = self.bot_messages.front() {
+            if now - message.sent_at < self.bot_message_ttl {
+                break;
+            }
+            self.bot_messages.pop_front();
+        }
+    }
+}
+
+fn is_bot_command(msg: &String, args: &Vec<Value>) -> bool {
+    msg == "click" && args.len() >= 4 && args[3] == Value::from(Modifier::Alt)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::protocol::Button;
+    use crate::bot::vec2::Vec2i;
+
+    use super::*;
+
+    fn make_human_control() -> HumanControl {
+        HumanControl::new(HumanControlConfig {
+            idle_timeout: 3.0,
+            bot_message_ttl: 5.0,
+            actions: vec![String::from("click")],
+        })
+    }
+
+    fn make_click_args(modifier: Modifier) -> Vec<Value> {
+        vec![
+            Value::from(Vec2i::zero()),
+            Value::from(Vec2i::new(1, 2)),
+            Value::from(Button::LeftClick),
+            Value::from(modifier),
+        ]
+    }
+
+    #[test]
+    fn human_click_should_activate_human_control_until_idle_timeout() {
+        let mut human_control = make_human_control();
+        let now = Instant::now();
+        let event = Event::WidgetMessage { id: 7, msg: String::from("click"), args: make_click_args(Modifier::None) };
+        assert!(human_control.update(&event, now));
+        assert!(human_control.is_active(now + Duration::from_secs(1)));
+        assert!(!human_control.is_active(now + Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn echo_of_bot_message_should_not_activate_human_control() {
+        let mut human_control = make_human_control();
+        let now = Instant::now();
+        let args = make_click_args(Modifier::None);
+        human_control.add_bot_message(&Message::WidgetMessage { sender: 7, kind: String::from("click"), arguments: args.clone() }, now);
+        let event = Event::WidgetMessage { id: 7, msg: String::from("click"), args };
+        assert!(!human_control.update(&event, now));
+        assert!(!human_control.is_active(now));
+    }
+
+    #[test]
+    fn bot_command_should_not_activate_human_control() {
+        let mut human_control = make_human_control();
+        let now = Instant::now();
+        let event = Event::WidgetMessage { id: 7, msg: String::from("click"), args: make_click_args(Modifier::Alt) };
+        assert!(!human_control.update(&event, now));
+        assert!(!human_control.is_active(now));
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::vec2::Vec2f;
+
+#[derive(Clone, Deserialize)]
+pub struct LegTimeoutConfig {
+    pub default_speed: f64,
+    pub eta_factor: f64,
+    pub min_timeout: f64,
+    pub max_replans: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LegCheck {
+    InTime,
+    Replan,
+    Abort,
+}
+
+struct Leg {
+    target: Vec2f,
+    started: Instant,
+    timeout: Duration,
+}
+
+pub struct LegTimer {
+    leg: Option<Leg>,
+    replans: usize,
+    config: LegTimeoutConfig,
+}
+
+impl LegTimer {
+    pub fn new(config: LegTimeoutConfig) -> Self {
+        Self {
+            leg: None,
+            replans: 0,
+            config,
+        }
+    }
+
+    pub fn replans(&self) -> usize {
+        self.replans
+    }
+
+    pub fn reset(&mut self) {
+        self.leg = None;
+        self.replans = 0;
+    }
+
+    pub fn check(&mut self, target: Vec2f, position: Vec2f, speed: Option<f64>, now: Instant) -> LegCheck {
+        if let Some(leg) = self.leg.as_ref() {
+            if leg.target == target {
+                if now - leg.started <= leg.timeout {
+                    return LegCheck::InTime;
+                }
+                debug!("LegTimer: leg to {:?} took more than {:?}", target, leg.timeout);
+                self.leg = None;
+                self.replans += 1;
+                return if self.replans > self.config.max_replans {
+                    LegCheck::Abort
+                } else {
+                    LegCheck::Replan
+                };
+            }
+            self.replans = 0;
+        }
+        let speed = speed.filter(|v| *v > 0.0).unwrap_or(self.config.default_speed);
+        let eta = position.distance(target) / speed;
+        self.leg = Some(Leg {
+            target,
+            started: now,
+            timeout: Duration::from_secs_f64((eta * self.config.eta_factor).max(self.config.min_timeout)),
+        });
+        LegCheck::InTime
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_should_replan_and_then_abort_when_leg_takes_longer_than_eta() {
+        let mut timer = LegTimer::new(LegTimeoutConfig { default_speed: 10.0, eta_factor: 2.0, min_timeout: 1.0, max_replans: 1 });
+        let now = Instant::now();
+        let target = Vec2f::new(100.0, 0.0);
+        let position = Vec2f::zero();
+        assert_eq!(timer.check(target, position, Some(20.0), now), LegCheck::InTime);
+        assert_eq!(timer.check(target, position, Some(20.0), now + Duration::from_secs(10)), LegCheck::InTime);
+        assert_eq!(timer.check(target, position, Some(20.0), now + Duration::from_secs(11)), LegCheck::Replan);
+        assert_eq!(timer.check(target, position, None, now + Duration::from_secs(11)), LegCheck::InTime);
+        assert_eq!(timer.check(target, position, None, now + Duration::from_secs(32)), LegCheck::Abort);
+        assert_eq!(timer.replans(), 2);
+        timer.reset();
+        assert_eq!(timer.check(target, position, None, now + Duration::from_secs(32)), LegCheck::InTime);
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+pub struct LruCache<K, V> {
+    capacity: usize,
+    tick: u64,
+    values: BTreeMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+    pinned: BTreeSet<K>,
+}
+
+impl<K: Ord + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            values: BTreeMap::new(),
+            order: BTreeMap::new(),
+            pinned: BTreeSet::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) -> usize {
+        self.capacity = capacity;
+        self.evict()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.values.get(key).map(|(value, _)| value)
+    }
+
+    pub fn is_pinned(&self, key: &K) -> bool {
+        self.pinned.contains(key)
+    }
+
+    pub fn pin(&mut self, key: K) {
+        self.pinned.insert(key);
+    }
+
+    // Pinned values are never evicted and don't count towards capacity
+    pub fn set_pinned(&mut self, keys: BTreeSet<K>) -> usize {
+        self.pinned = keys;
+        self.evict()
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.values.get_mut(key) {
+            Some((value, used)) => {
+                self.order.remove(used);
+                self.order.insert(tick, key.clone());
+                *used = tick;
+                Some(value)
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> usize {
+        self.tick += 1;
+        if let Some((_, used)) = self.values.insert(key.clone(), (value, self.tick)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.tick, key);
+        self.evict()
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.order.clear();
+    }
+
+    fn evict(&mut self) -> usize {
+        let mut evicted = 0;
+        let values = &self.values;
+        let mut len = values.len() - self.pinned.iter().filter(|key| values.contains_key(key)).count();
+        while len > self.capacity {
+            let pinned = &self.pinned;
+            let used = match self.order.iter().find(|(_, key)| !pinned.contains(key)) {
+                Some((used, _)) => *used,
+                None => break,
+            };
+            if let Some(key) = self.order.remove(&used) {
+                self.values.remove(&key);
+                evicted += 1;
+                len -= 1;
+            }
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_cache_should_evict_least_recently_used_values() {
+        let mut cache = LruCache::new(2);
+        assert_eq!(cache.insert(1, "a"), 0);
+        assert_eq!(cache.insert(2, "b"), 0);
+        assert_eq!(cache.get_mut(&1), Some(&mut "a"));
+        assert_eq!(cache.insert(3, "c"), 1);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get_mut(&2), None);
+        assert_eq!(cache.get_mut(&1), Some(&mut "a"));
+        assert_eq!(cache.get_mut(&3), Some(&mut "c"));
+        assert_eq!(cache.insert(3, "d"), 0);
+        assert_eq!(cache.set_capacity(1), 1);
+        assert_eq!(cache.get_mut(&1), None);
+        assert_eq!(cache.get_mut(&3), Some(&mut "d"));
+    }
+
+    #[test]
+    fn lru_cache_should_not_evict_pinned_values() {
+        let mut cache = LruCache::new(1);
+        assert_eq!(cache.insert(1, "a"), 0);
+        assert_eq!(cache.set_pinned(vec![1].into_iter().collect()), 0);
+        assert_eq!(cache.insert(2, "b"), 0);
+        assert_eq!(cache.insert(3, "c"), 1);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), Some(&"a"));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.set_pinned(BTreeSet::new()), 1);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&3), Some(&"c"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::protocol::{Event, Value};
+use crate::bot::vec2::Vec2i;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MacroWidget {
+    MapView,
+    Id(i32),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MacroStep {
+    pub delay: f64,
+    pub widget: MacroWidget,
+    pub kind: String,
+    pub arguments: Vec<Value>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Macro {
+    pub origin: Vec2i,
+    pub steps: Vec<MacroStep>,
+}
+
+impl MacroStep {
+    pub fn arguments_with_offset(&self, offset: Vec2i) -> Vec<Value> {
+        let mut result = self.arguments.clone();
+        if self.widget == MacroWidget::MapView {
+            if let Some(Value::Coord { value }) = result.get_mut(1) {
+                *value += offset;
+            }
+        }
+        result
+    }
+}
+
+pub struct Recorder {
+    name: String,
+    map_view_id: i32,
+    last_step: Instant,
+    value: Macro,
+}
+
+impl Recorder {
+    pub fn new(name: String, map_view_id: i32, origin: Vec2i, now: Instant) -> Self {
+        Self {
+            name,
+            map_view_id,
+            last_step: now,
+            value: Macro { origin, steps: Vec::new() },
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn update(&mut self, event: &Event, now: Instant) {
+        if let Event::WidgetMessage { id, msg, args } = event {
+            debug!("Recorder: record {} for widget {} into macro {:?}", msg, id, self.name);
+            self.value.steps.push(MacroStep {
+                delay: (now - self.last_step).as_secs_f64(),
+                widget: if *id == self.map_view_id { MacroWidget::MapView } else { MacroWidget::Id(*id) },
+                kind: msg.clone(),
+                arguments: args.clone(),
+            });
+            self.last_step = now;
+        }
+    }
+
+    pub fn into_macro(self) -> Macro {
+        self.value
+    }
+}
+
+pub fn read_macro(path: &String, name: &String) -> Result<Macro, String> {
+    let macro_path = get_macro_path(path, name)?;
+    let content = match std::fs::read(&macro_path) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to read macro {}: {}", macro_path, e)),
+    };
+    serde_json::from_slice(&content).map_err(|e| format!("Failed to parse macro {}: {}", macro_path, e))
+}
+
+pub fn write_macro(path: &String, name: &String, value: &Macro) -> Result<(), String> {
+    let macro_path = get_macro_path(path, name)?;
+    std::fs::create_dir_all(path).map_err(|e| format!("Failed to create dir {}: {}", path, e))?;
+    std::fs::write(&macro_path, serde_json::to_vec(value).unwrap())
+        .map_err(|e| format!("Failed to write macro {}: {}", macro_path, e))
+}
+
+fn get_macro_path(path: &String, name: &String) -> Result<String, String> {
+    if name.is_empty() || !name.chars().all(|v| v.is_ascii_alphanumeric() || v == '_' || v == '-') {
+        return Err(format!("Invalid macro name: {:?}", name));
+    }
+    Ok(format!("{}/{}.macro.json", path, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::bot::protocol::{Button, Modifier};
+
+    use super::*;
+
+    #[test]
+    fn recorded_map_view_click_should_be_replayed_relative_to_origin() {
+        let now = Instant::now();
+        let mut recorder = Recorder::new(String::from("test"), 7, Vec2i::new(100, 200), now);
+        recorder.update(&Event::WidgetMessage {
+            id: 7,
+            msg: String::from("click"),
+            args: vec![
+                Value::from(Vec2i::new(1, 2)),
+                Value::from(Vec2i::new(110, 220)),
+                Value::from(Button::LeftClick),
+                Value::from(Modifier::None),
+            ],
+        }, now + Duration::from_secs(2));
+        recorder.update(&Event::WidgetMessage {
+            id: 8,
+            msg: String::from("drop"),
+            args: vec![Value::from(Vec2i::new(1, 0))],
+        }, now + Duration::from_secs(3));
+        let value = recorder.into_macro();
+        assert_eq!(value.steps.len(), 2);
+        assert_eq!(value.steps[0].delay, 2.0);
+        assert_eq!(value.steps[0].widget, MacroWidget::MapView);
+        assert_eq!(value.steps[1].widget, MacroWidget::Id(8));
+        assert_eq!(value.steps[0].arguments_with_offset(Vec2i::new(-10, 5))[1], Value::from(Vec2i::new(100, 225)));
+        assert_eq!(value.steps[1].arguments_with_offset(Vec2i::new(-10, 5)), vec![Value::from(Vec2i::new(1, 0))]);
+    }
+}
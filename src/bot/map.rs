@@ -1,46 +1,68 @@
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 
+use crate::bot::bookmarks::Bookmark;
 use crate::bot::map_db::MapDb;
+use crate::bot::player::Resource;
 use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::zones::Zone;
 
 pub const GRID_SIZE: i32 = 100;
 pub const TILE_SIZE: f64 = 11.0;
 pub const RESOLUTION: f64 = hexf64!("0x1.0p-10") * TILE_SIZE;
 
 pub struct Map {
+    revision: u64,
     tiles: BTreeMap<i32, Tile>,
     tiles_by_name: BTreeMap<String, i32>,
     grids: BTreeMap<i64, Grid>,
     grids_by_coord: BTreeMap<i64, BTreeMap<Vec2i, i64>>,
     db: Arc<Mutex<dyn MapDb + Send>>,
+    max_height_delta: f64,
 }
 
 impl Map {
-    pub fn new(db: Arc<Mutex<dyn MapDb + Send>>) -> Self {
+    pub fn new(db: Arc<Mutex<dyn MapDb + Send>>, max_height_delta: f64) -> Self {
         let tiles = db.lock().unwrap().get_tiles();
         Self {
+            revision: 0,
             tiles_by_name: tiles.iter().map(|v| (v.name.clone(), v.id)).collect(),
             tiles: tiles.into_iter().map(|v| (v.id, v)).collect(),
             grids_by_coord: BTreeMap::new(),
             grids: BTreeMap::new(),
             db,
+            max_height_delta,
         }
     }
 
-    pub fn from_map_data(map_data: MapData, db: Arc<Mutex<dyn MapDb + Send>>) -> Self {
+    pub fn from_map_data(map_data: MapData, db: Arc<Mutex<dyn MapDb + Send>>, max_height_delta: f64) -> Self {
         let MapData { tiles, grids } = map_data;
         Self {
+            revision: 0,
             tiles_by_name: tiles.iter().map(|v| (v.name.clone(), v.id)).collect(),
             tiles: tiles.into_iter().map(|v| (v.id, v)).collect(),
             grids_by_coord: make_grids_by_coord(&grids),
             grids: grids.into_iter().map(|v| (v.id, v)).collect(),
             db,
+            max_height_delta,
         }
     }
 
+    pub fn snapshot(&self) -> Self {
+        Self {
+            revision: self.revision,
+            ..Self::from_map_data(self.as_map_data(), self.db.clone(), self.max_height_delta)
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
     pub fn as_map_data(&self) -> MapData {
         MapData {
             tiles: self.tiles.values().cloned().collect(),
@@ -52,6 +74,15 @@ impl Map {
         self.db.lock().unwrap().set_tile(&tile);
         self.tiles_by_name.insert(tile.name.clone(), tile.id);
         self.tiles.insert(tile.id, tile);
+        self.revision += 1;
+    }
+
+    pub fn set_resource(&self, resource: &Resource) {
+        self.db.lock().unwrap().set_resource(resource);
+    }
+
+    pub fn get_resources(&self) -> Vec<Resource> {
+        self.db.lock().unwrap().get_resources()
     }
 
     pub fn add_grid(&mut self, mut grid: Grid, neighbours: Vec<GridNeighbour>) {
@@ -81,14 +112,21 @@ impl Map {
             }
         }
         self.db.lock().unwrap().add_grid(grid.id, &grid.heights, &grid.tiles, &neighbours);
+        self.reconcile_seam_heights(&mut grid);
         self.grids_by_coord.entry(grid.segment_id)
             .or_insert_with(|| BTreeMap::new())
             .insert(grid.position, grid.id);
         self.grids.insert(grid.id, grid);
+        self.revision += 1;
     }
 
-    pub fn update_grid(&mut self, grid: Grid) {
-        if let Some(position) = self.grids.get(&grid.id).map(|v| v.position) {
+    pub fn update_grid(&mut self, mut grid: Grid) -> bool {
+        let existing_position = self.grids.get(&grid.id).map(|v| v.position);
+        let changed = self.db.lock().unwrap().update_grid(grid.id, &grid.heights, &grid.tiles);
+        if !changed && existing_position == Some(grid.position) {
+            return false;
+        }
+        if let Some(position) = existing_position {
             let shift = grid.position - position;
             if shift != Vec2i::zero() {
                 for existing in self.grids.values_mut().filter(|v| v.segment_id == grid.segment_id) {
@@ -99,8 +137,76 @@ impl Map {
                 }
             }
         }
-        self.db.lock().unwrap().update_grid(grid.id, &grid.heights, &grid.tiles);
+        self.reconcile_seam_heights(&mut grid);
         self.grids.insert(grid.id, grid);
+        self.revision += 1;
+        true
+    }
+
+    // Stored heights are kept as reported, only border tiles of adjacent grids are blended in memory.
+    // Difference above max height delta is a real cliff and is kept as is.
+    fn reconcile_seam_heights(&mut self, grid: &mut Grid) {
+        if grid.heights.len() != (GRID_SIZE * GRID_SIZE) as usize {
+            return;
+        }
+        for &offset in &[Vec2i::only_x(1), Vec2i::only_x(-1), Vec2i::only_y(1), Vec2i::only_y(-1)] {
+            let neighbour_id = match self.grids_by_coord.get(&grid.segment_id).and_then(|v| v.get(&(grid.position + offset))) {
+                Some(v) => *v,
+                None => continue,
+            };
+            if neighbour_id == grid.id {
+                continue;
+            }
+            let existing = self.grids.get(&grid.id)
+                .filter(|v| v.heights.len() == grid.heights.len())
+                .map(|v| v.heights.clone());
+            let neighbour = self.grids.get_mut(&neighbour_id).unwrap();
+            if neighbour.heights.len() != grid.heights.len() {
+                continue;
+            }
+            let mut blended = false;
+            for i in 0..GRID_SIZE {
+                let (own, other) = get_seam_tile_positions(offset, i);
+                let (own, other) = (get_grid_tile_index(own), get_grid_tile_index(other));
+                // Seam reconciled before is kept, otherwise each update of the same grid would blend it again
+                if let Some(existing) = existing.as_ref() {
+                    if existing[own] == neighbour.heights[other] {
+                        grid.heights[own] = existing[own];
+                        continue;
+                    }
+                }
+                if (grid.heights[own] - neighbour.heights[other]).abs() as f64 > self.max_height_delta {
+                    continue;
+                }
+                let height = (grid.heights[own] + neighbour.heights[other]) / 2.0;
+                blended |= neighbour.heights[other] != height;
+                grid.heights[own] = height;
+                neighbour.heights[other] = height;
+            }
+            if blended {
+                neighbour.revision += 1;
+            }
+        }
+    }
+
+    pub fn set_grid_last_seen(&self, grid_id: i64, time: SystemTime) {
+        self.db.lock().unwrap().set_grid_last_seen(grid_id, as_unix_time(time));
+    }
+
+    pub fn get_grids_older_than(&self, segment_id: i64, time: SystemTime) -> Vec<Vec2i> {
+        self.db.lock().unwrap().get_grids_older_than(as_unix_time(time)).into_iter()
+            .filter_map(|grid_id| self.grids.get(&grid_id))
+            .filter(|grid| grid.segment_id == segment_id)
+            .map(|grid| grid.position)
+            .collect()
+    }
+
+    pub fn get_height(&self, segment_id: i64, tile_pos: Vec2i) -> Option<f32> {
+        let grid_pos = tile_pos_to_grid_pos(tile_pos);
+        self.get_grid(segment_id, grid_pos).and_then(|grid| {
+            let relative_tile_pos = tile_pos_to_relative_tile_pos(tile_pos, grid_pos);
+            grid.heights.get(get_grid_tile_index(relative_tile_pos)).cloned()
+        })
     }
 
     pub fn get_tile(&self, segment_id: i64, tile_pos: Vec2i) -> Option<i32> {
@@ -127,7 +233,128 @@ impl Map {
         })
     }
 
-    fn get_grid(&self, segment_id: i64, grid_pos: Vec2i) -> Option<&Grid> {
+    pub fn get_grid_tiles(&self, segment_id: i64, grid_pos: Vec2i) -> Option<Vec<i32>> {
+        if let Some(grid) = self.get_grid(segment_id, grid_pos) {
+            return Some(grid.tiles.clone());
+        }
+        self.grids.get(&segment_id).and_then(|local_grid| {
+            let db = self.db.lock().unwrap();
+            let (db_segment_id, shift) = db.get_grid_by_id(segment_id).map(|db_grid| {
+                let locked_db_grid = db_grid.lock().unwrap();
+                (locked_db_grid.segment_id, locked_db_grid.position - local_grid.position)
+            })?;
+            db.get_grid(db_segment_id, grid_pos + shift).map(|grid| grid.lock().unwrap().tiles.clone())
+        })
+    }
+
+    pub fn add_object(&self, object: &MapObject) {
+        self.db.lock().unwrap().add_object(object);
+    }
+
+    pub fn remove_object(&self, object_id: i64) {
+        self.db.lock().unwrap().remove_object(object_id);
+    }
+
+    pub fn get_objects_in_rect(&self, segment_id: i64, min: Vec2f, max: Vec2f) -> Vec<(Vec2f, MapObject)> {
+        self.grids.get(&segment_id).and_then(|local_grid| {
+            let db = self.db.lock().unwrap();
+            let (db_segment_id, shift) = db.get_grid_by_id(segment_id).map(|db_grid| {
+                let locked_db_grid = db_grid.lock().unwrap();
+                (locked_db_grid.segment_id, grid_pos_to_pos(locked_db_grid.position - local_grid.position))
+            })?;
+            Some(
+                db.get_objects_in_rect(db_segment_id, min + shift, max + shift).into_iter()
+                    .map(|(position, object)| (position - shift, object))
+                    .collect()
+            )
+        }).unwrap_or_default()
+    }
+
+    pub fn get_zones(&self, segment_id: i64) -> Vec<Zone> {
+        self.grids.get(&segment_id).and_then(|local_grid| {
+            let db = self.db.lock().unwrap();
+            let (db_segment_id, shift) = db.get_grid_by_id(segment_id).map(|db_grid| {
+                let locked_db_grid = db_grid.lock().unwrap();
+                (locked_db_grid.segment_id, grid_pos_to_tile_pos(locked_db_grid.position - local_grid.position))
+            })?;
+            Some(
+                db.get_zones(db_segment_id).into_iter()
+                    .map(|zone| zone.shifted(segment_id, Vec2i::zero() - shift))
+                    .collect()
+            )
+        }).unwrap_or_default()
+    }
+
+    pub fn get_bookmarks(&self, segment_id: i64) -> Vec<Bookmark> {
+        self.grids.get(&segment_id).and_then(|local_grid| {
+            let db = self.db.lock().unwrap();
+            let (db_segment_id, shift) = db.get_grid_by_id(segment_id).map(|db_grid| {
+                let locked_db_grid = db_grid.lock().unwrap();
+                (locked_db_grid.segment_id, grid_pos_to_pos(locked_db_grid.position - local_grid.position))
+            })?;
+            Some(
+                db.get_bookmarks(db_segment_id).into_iter()
+                    .map(|bookmark| bookmark.shifted(segment_id, Vec2f::zero() - shift))
+                    .collect()
+            )
+        }).unwrap_or_default()
+    }
+
+    pub fn get_grid_position(&self, segment_id: i64, grid_id: i64) -> Option<Vec2i> {
+        if let Some(grid) = self.grids.get(&grid_id) {
+            return if grid.segment_id == segment_id { Some(grid.position) } else { None };
+        }
+        self.grids.get(&segment_id).and_then(|local_grid| {
+            let db = self.db.lock().unwrap();
+            let (db_segment_id, shift) = db.get_grid_by_id(segment_id).map(|db_grid| {
+                let locked_db_grid = db_grid.lock().unwrap();
+                (locked_db_grid.segment_id, locked_db_grid.position - local_grid.position)
+            })?;
+            db.get_grid_by_id(grid_id).and_then(|db_grid| {
+                let locked_db_grid = db_grid.lock().unwrap();
+                if locked_db_grid.segment_id == db_segment_id {
+                    Some(locked_db_grid.position - shift)
+                } else {
+                    None
+                }
+            })
+        })
+    }
+
+    pub fn prefetch_grid(&self, segment_id: i64, grid_pos: Vec2i) -> bool {
+        if self.get_grid(segment_id, grid_pos).is_some() {
+            return true;
+        }
+        let db = self.db.lock().unwrap();
+        self.get_db_segment(&*db, segment_id)
+            .and_then(|(db_segment_id, shift)| db.get_grid(db_segment_id, grid_pos + shift))
+            .is_some()
+    }
+
+    pub fn set_pinned_grids(&self, owner: u64, segment_id: i64, grid_positions: Vec<Vec2i>) -> bool {
+        let db = self.db.lock().unwrap();
+        if grid_positions.is_empty() {
+            db.set_pinned_grids(owner, BTreeSet::new());
+            return true;
+        }
+        match self.get_db_segment(&*db, segment_id) {
+            Some((db_segment_id, shift)) => {
+                db.set_pinned_grids(owner, grid_positions.into_iter().map(|v| (db_segment_id, v + shift)).collect());
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Local segment id is an id of one of its grids but stored segment may differ after merges and splits
+    fn get_db_segment(&self, db: &(dyn MapDb + Send), segment_id: i64) -> Option<(i64, Vec2i)> {
+        let local_grid = self.grids.get(&segment_id)?;
+        let db_grid = db.get_grid_by_id(segment_id)?;
+        let locked_db_grid = db_grid.lock().unwrap();
+        Some((locked_db_grid.segment_id, locked_db_grid.position - local_grid.position))
+    }
+
+    pub fn get_grid(&self, segment_id: i64, grid_pos: Vec2i) -> Option<&Grid> {
         self.grids_by_coord.get(&segment_id)
             .and_then(|v| v.get(&grid_pos))
             .and_then(|id| self.grids.get(&id))
@@ -146,10 +373,25 @@ impl Map {
         self.tiles.get(&id)
     }
 
+    pub fn iter_tiles(&self) -> impl Iterator<Item=&Tile> {
+        self.tiles.values()
+    }
+
     pub fn iter_grids(&self) -> impl Iterator<Item=&Grid> {
         self.grids.values()
     }
 
+    pub fn iter_segment_grids(&self, segment_id: i64) -> impl Iterator<Item=&Grid> {
+        self.grids_by_coord.get(&segment_id).into_iter()
+            .flat_map(move |v| v.values().map(move |id| &self.grids[id]))
+    }
+
+    pub fn get_segment_fingerprint(&self, segment_id: i64) -> Vec<(Vec2i, i64, i64)> {
+        self.iter_segment_grids(segment_id)
+            .map(|v| (v.position, v.id, v.revision))
+            .collect()
+    }
+
     pub fn find_border_tiles(&self, segment_id: i64, allowed_tiles: &impl TileSet) -> Vec<Vec2i> {
         let mut result = Vec::new();
         if let Some(segment_grids) = self.grids_by_coord.get(&segment_id) {
@@ -197,6 +439,32 @@ impl Map {
     }
 }
 
+pub struct TilesSnapshot {
+    grids: RefCell<BTreeMap<(i64, Vec2i), Option<Vec<i32>>>>,
+}
+
+impl TilesSnapshot {
+    pub fn new() -> Self {
+        Self { grids: RefCell::new(BTreeMap::new()) }
+    }
+
+    pub fn get_tile(&self, map: &Map, segment_id: i64, tile_pos: Vec2i) -> Option<i32> {
+        let grid_pos = tile_pos_to_grid_pos(tile_pos);
+        let mut grids = self.grids.borrow_mut();
+        let tiles = grids.entry((segment_id, grid_pos))
+            .or_insert_with(|| map.get_grid_tiles(segment_id, grid_pos));
+        tiles.as_ref().map(|v| v[get_grid_tile_index(tile_pos_to_relative_tile_pos(tile_pos, grid_pos))])
+    }
+
+    pub fn len(&self) -> usize {
+        self.grids.borrow().len()
+    }
+}
+
+pub fn as_unix_time(value: SystemTime) -> i64 {
+    value.duration_since(UNIX_EPOCH).map(|v| v.as_secs() as i64).unwrap_or(0)
+}
+
 pub fn rel_tile_pos_to_pos(tile_pos: Vec2f) -> Vec2f {
     tile_pos * TILE_SIZE
 }
@@ -245,6 +513,15 @@ fn get_grid_tile_index(tile_pos: Vec2i) -> usize {
     tile_pos.x() as usize + tile_pos.y() as usize * GRID_SIZE as usize
 }
 
+fn get_seam_tile_positions(offset: Vec2i, i: i32) -> (Vec2i, Vec2i) {
+    match (offset.x(), offset.y()) {
+        (1, _) => (Vec2i::new(GRID_SIZE - 1, i), Vec2i::new(0, i)),
+        (-1, _) => (Vec2i::new(0, i), Vec2i::new(GRID_SIZE - 1, i)),
+        (_, 1) => (Vec2i::new(i, GRID_SIZE - 1), Vec2i::new(i, 0)),
+        _ => (Vec2i::new(i, 0), Vec2i::new(i, GRID_SIZE - 1)),
+    }
+}
+
 pub fn tile_index_to_tile_pos(index: usize) -> Vec2i {
     Vec2i::new((index % GRID_SIZE as usize) as i32, (index / GRID_SIZE as usize) as i32)
 }
@@ -291,12 +568,26 @@ pub struct GridNeighbour {
     pub offset: Vec2i,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialOrd, PartialEq)]
+pub struct MapObject {
+    pub id: i64,
+    pub name: String,
+    pub grid_id: i64,
+    pub offset: Vec2f,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialOrd, PartialEq)]
 pub struct MapData {
     tiles: Vec<Tile>,
     grids: Vec<Grid>,
 }
 
+impl MapData {
+    pub fn grids(&self) -> &[Grid] {
+        &self.grids
+    }
+}
+
 pub trait TileSet {
     fn contains(&self, tile: i32) -> bool;
 }
@@ -304,6 +595,10 @@ pub trait TileSet {
 #[cfg(test)]
 mod tests {
     use std::iter::repeat;
+    use std::time::Duration;
+
+    use crate::bot::map_db::MapDbCacheStats;
+    use crate::bot::map_retention::{MapPruneStats, MapRetentionConfig};
 
     use super::*;
 
@@ -311,6 +606,8 @@ mod tests {
     struct FakeMapDb {
         grids_by_id: BTreeMap<i64, Arc<Mutex<Grid>>>,
         grids_by_segment_id_and_position: BTreeMap<(i64, Vec2i), Arc<Mutex<Grid>>>,
+        grids_last_seen: Mutex<BTreeMap<i64, i64>>,
+        written_grids: Mutex<BTreeMap<i64, (Vec<f32>, Vec<i32>)>>,
     }
 
     impl MapDb for FakeMapDb {
@@ -340,14 +637,92 @@ mod tests {
             self.grids_by_segment_id_and_position.get(&(segment_id, position)).map(|v| v.clone())
         }
 
-        fn add_grid(&self, _grid_id: i64, _heights: &Vec<f32>, _tiles: &Vec<i32>, _neighbours: &Vec<GridNeighbour>) {}
+        fn add_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>, _neighbours: &Vec<GridNeighbour>) {
+            self.written_grids.lock().unwrap().insert(grid_id, (heights.clone(), tiles.clone()));
+        }
+
+        fn update_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>) -> bool {
+            let value = (heights.clone(), tiles.clone());
+            self.written_grids.lock().unwrap().insert(grid_id, value.clone()) != Some(value)
+        }
+
+        fn set_grid_revision(&self, _grid_id: i64, _revision: i64) {}
+
+        fn get_grid_changes(&self, _since_change_id: i64, _limit: usize) -> Vec<(i64, Grid)> {
+            Vec::new()
+        }
+
+        fn add_object(&self, _object: &MapObject) {}
+
+        fn remove_object(&self, _object_id: i64) {}
+
+        fn get_objects_in_rect(&self, _segment_id: i64, _min: Vec2f, _max: Vec2f) -> Vec<(Vec2f, MapObject)> {
+            Vec::new()
+        }
+
+        fn set_grid_last_seen(&self, grid_id: i64, last_seen: i64) {
+            self.grids_last_seen.lock().unwrap().insert(grid_id, last_seen);
+        }
+
+        fn get_grids_older_than(&self, last_seen: i64) -> Vec<i64> {
+            self.grids_last_seen.lock().unwrap().iter()
+                .filter(|(_, v)| **v < last_seen)
+                .map(|(k, _)| *k)
+                .collect()
+        }
+
+        fn merge_segments(&self, _src_segment_id: i64, _dst_segment_id: i64, _shift: Vec2i) -> Result<usize, String> {
+            Ok(0)
+        }
+
+        fn split_segment(&self, segment_id: i64, _grid_ids: &Vec<i64>) -> Result<i64, String> {
+            Ok(segment_id)
+        }
+
+        fn set_zone(&self, _zone: &Zone) {}
+
+        fn remove_zone(&self, _segment_id: i64, _name: &String) -> bool {
+            false
+        }
+
+        fn get_zones(&self, _segment_id: i64) -> Vec<Zone> {
+            Vec::new()
+        }
+
+        fn set_bookmark(&self, _bookmark: &Bookmark) {}
+
+        fn remove_bookmark(&self, _segment_id: i64, _name: &String) -> bool {
+            false
+        }
+
+        fn get_bookmarks(&self, _segment_id: i64) -> Vec<Bookmark> {
+            Vec::new()
+        }
+
+        fn get_resources(&self) -> Vec<Resource> {
+            Vec::new()
+        }
+
+        fn set_resource(&self, _resource: &Resource) {}
 
-        fn update_grid(&self, _grid_id: i64, _heights: &Vec<f32>, _tiles: &Vec<i32>) {}
+        fn prune(&self, _config: &MapRetentionConfig, _now: i64) -> Result<MapPruneStats, String> {
+            Ok(MapPruneStats::default())
+        }
+
+        fn get_cache_stats(&self) -> MapDbCacheStats {
+            MapDbCacheStats::default()
+        }
+
+        fn set_pinned_grids(&self, _owner: u64, _coords: BTreeSet<(i64, Vec2i)>) {}
+
+        fn flush(&self) -> Result<(), String> {
+            Ok(())
+        }
     }
 
     #[test]
     fn added_tile_should_be_accessible() {
-        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())));
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
         let grid = Grid {
             id: 1,
             revision: 1,
@@ -364,7 +739,7 @@ mod tests {
 
     #[test]
     fn adjacent_grids_should_be_stored_in_a_single_segment() {
-        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())));
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
         let grid1 = Grid {
             id: 1,
             revision: 1,
@@ -389,7 +764,7 @@ mod tests {
 
     #[test]
     fn separate_grids_should_be_stored_in_different_segments() {
-        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())));
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
         let grid1 = Grid {
             id: 1,
             revision: 1,
@@ -414,7 +789,7 @@ mod tests {
 
     #[test]
     fn adjacent_grid_to_separated_segments_should_merge_them() {
-        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())));
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
         let grid1 = Grid {
             id: 1,
             revision: 1,
@@ -462,7 +837,7 @@ mod tests {
 
     #[test]
     fn get_grid_should_return_none_for_absent_grid() {
-        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())));
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
         let grid = Grid {
             id: 1,
             revision: 1,
@@ -477,7 +852,7 @@ mod tests {
 
     #[test]
     fn get_grid_should_return_none_for_absent_segment() {
-        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())));
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
         let grid = Grid {
             id: 1,
             revision: 1,
@@ -492,7 +867,7 @@ mod tests {
 
     #[test]
     fn get_tile_should_return_none_for_absent_grid() {
-        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())));
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
         let grid = Grid {
             id: 1,
             revision: 1,
@@ -507,7 +882,7 @@ mod tests {
 
     #[test]
     fn get_tile_should_return_none_for_absent_segment() {
-        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())));
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
         let grid = Grid {
             id: 1,
             revision: 1,
@@ -522,7 +897,7 @@ mod tests {
 
     #[test]
     fn update_grid_should_change_grid_position() {
-        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())));
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
         let mut grid = Grid {
             id: 1,
             revision: 1,
@@ -555,7 +930,7 @@ mod tests {
         let mut map_db = FakeMapDb::default();
         map_db.grids_by_id.insert(1, grid_arc.clone());
         map_db.grids_by_segment_id_and_position.insert((grid.segment_id, grid.position), grid_arc);
-        let mut map = Map::new(Arc::new(Mutex::new(map_db)));
+        let mut map = Map::new(Arc::new(Mutex::new(map_db)), 20.0);
         map.add_grid(grid.clone(), Vec::new());
         let tile_pos = grid_pos_to_tile_pos(Vec2i::new(42, 13));
         assert_eq!(map.get_tile(1, tile_pos), Some(146));
@@ -586,7 +961,7 @@ mod tests {
         map_db.grids_by_segment_id_and_position.insert((base_grid.segment_id, base_grid.position), base_grid_arc);
         map_db.grids_by_id.insert(2, other_grid_arc.clone());
         map_db.grids_by_segment_id_and_position.insert((other_grid.segment_id, other_grid.position), other_grid_arc);
-        let mut map = Map::new(Arc::new(Mutex::new(map_db)));
+        let mut map = Map::new(Arc::new(Mutex::new(map_db)), 20.0);
         map.add_grid(base_grid, Vec::new());
         let tile_pos = grid_pos_to_tile_pos(Vec2i::new(43, 13));
         assert_eq!(map.get_tile(1, tile_pos), Some(147));
@@ -622,7 +997,7 @@ mod tests {
         map_db.grids_by_segment_id_and_position.insert((db_base_grid.segment_id, db_base_grid.position), db_base_grid_arc);
         map_db.grids_by_id.insert(2, db_other_grid_arc.clone());
         map_db.grids_by_segment_id_and_position.insert((db_other_grid.segment_id, db_other_grid.position), db_other_grid_arc);
-        let mut map = Map::new(Arc::new(Mutex::new(map_db)));
+        let mut map = Map::new(Arc::new(Mutex::new(map_db)), 20.0);
         map.add_grid(base_grid, Vec::new());
         let tile_pos = grid_pos_to_tile_pos(Vec2i::new(43, 13));
         assert_eq!(map.get_tile(1, tile_pos), Some(147));
@@ -646,7 +1021,7 @@ mod tests {
         map_db.grids_by_id.insert(1, db_base_grid_arc.clone());
         map_db.grids_by_segment_id_and_position.insert((db_base_grid.segment_id, db_base_grid.position), db_base_grid_arc.clone());
         let map_db_arc = Arc::new(Mutex::new(map_db));
-        let mut map = Map::new(map_db_arc.clone());
+        let mut map = Map::new(map_db_arc.clone(), 20.0);
         map.add_grid(grid.clone(), Vec::new());
         map_db_arc.lock().unwrap().grids_by_segment_id_and_position.remove(&(db_base_grid.segment_id, db_base_grid.position));
         db_base_grid_arc.lock().unwrap().position = Vec2i::zero();
@@ -655,4 +1030,89 @@ mod tests {
         let tile_pos = grid_pos_to_tile_pos(Vec2i::zero());
         assert_eq!(map.get_tile(1, tile_pos), Some(146));
     }
+
+    fn make_grid_with_height(id: i64, height: f32) -> Grid {
+        Grid {
+            id,
+            revision: 1,
+            segment_id: id,
+            position: Vec2i::zero(),
+            heights: repeat(height).take((GRID_SIZE * GRID_SIZE) as usize).collect(),
+            tiles: repeat(1).take((GRID_SIZE * GRID_SIZE) as usize).collect(),
+        }
+    }
+
+    #[test]
+    fn adjacent_grids_heights_should_be_blended_at_seam() {
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
+        map.add_grid(make_grid_with_height(1, 1.0), Vec::new());
+        map.add_grid(make_grid_with_height(2, 3.0), vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        let heights = (GRID_SIZE - 2..GRID_SIZE + 2)
+            .map(|x| map.get_height(1, Vec2i::new(x, 42)))
+            .collect::<Vec<_>>();
+        assert_eq!(heights, vec![Some(1.0), Some(2.0), Some(2.0), Some(3.0)]);
+        assert_eq!(map.get_grid_by_id(1).map(|v| v.revision), Some(2));
+        let grid = Grid { segment_id: 1, position: Vec2i::only_x(1), ..make_grid_with_height(2, 3.0) };
+        assert!(!map.update_grid(grid.clone()));
+        let mut changed = grid.clone();
+        changed.tiles[0] = 2;
+        assert!(map.update_grid(changed));
+        assert_eq!(map.get_height(1, Vec2i::new(GRID_SIZE - 1, 42)), Some(2.0));
+        assert_eq!(map.get_height(1, Vec2i::new(GRID_SIZE, 42)), Some(2.0));
+        assert_eq!(map.get_grid_by_id(1).map(|v| v.revision), Some(2));
+    }
+
+    #[test]
+    fn adjacent_grids_heights_should_not_be_blended_at_cliff() {
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
+        map.add_grid(make_grid_with_height(1, 1.0), Vec::new());
+        map.add_grid(make_grid_with_height(2, 41.0), vec![GridNeighbour { id: 1, offset: Vec2i::new(0, -1) }]);
+        assert_eq!(map.get_height(1, Vec2i::new(42, GRID_SIZE - 1)), Some(1.0));
+        assert_eq!(map.get_height(1, Vec2i::new(42, GRID_SIZE)), Some(41.0));
+        assert_eq!(map.get_grid_by_id(1).map(|v| v.revision), Some(1));
+    }
+
+    #[test]
+    fn reported_heights_should_be_written_unchanged_for_adjacent_grids() {
+        let map_db = Arc::new(Mutex::new(FakeMapDb::default()));
+        let mut map = Map::new(map_db.clone(), 20.0);
+        let (grid_1, grid_2) = (make_grid_with_height(1, 1.0), make_grid_with_height(2, 3.0));
+        map.add_grid(grid_1.clone(), Vec::new());
+        map.add_grid(grid_2.clone(), vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        let written = map_db.lock().unwrap().written_grids.lock().unwrap().clone();
+        assert_eq!(written.get(&1).map(|v| &v.0), Some(&grid_1.heights));
+        assert_eq!(written.get(&2).map(|v| &v.0), Some(&grid_2.heights));
+        assert_eq!(map.get_grid_by_id(1).map(|v| v.heights[get_grid_tile_index(Vec2i::new(GRID_SIZE - 1, 42))]), Some(2.0));
+        assert_eq!(map.get_grid_by_id(2).map(|v| v.heights[get_grid_tile_index(Vec2i::new(0, 42))]), Some(2.0));
+    }
+
+    #[test]
+    fn tiles_snapshot_should_keep_tiles_after_grid_update() {
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
+        let mut grid = make_grid_with_height(1, 1.0);
+        map.add_grid(grid.clone(), Vec::new());
+        let snapshot = TilesSnapshot::new();
+        assert_eq!(snapshot.get_tile(&map, 1, Vec2i::new(1, 2)), Some(1));
+        grid.tiles = repeat(2).take((GRID_SIZE * GRID_SIZE) as usize).collect();
+        map.update_grid(grid);
+        assert_eq!(snapshot.get_tile(&map, 1, Vec2i::new(3, 4)), Some(1));
+        assert_eq!(map.get_tile(1, Vec2i::new(3, 4)), Some(2));
+        assert_eq!(snapshot.get_tile(&map, 1, Vec2i::new(-1, 0)), None);
+        assert_eq!(snapshot.len(), 2);
+    }
+
+    #[test]
+    fn get_grids_older_than_should_return_positions_of_stale_grids_in_segment() {
+        let mut map = Map::new(Arc::new(Mutex::new(FakeMapDb::default())), 20.0);
+        map.add_grid(make_grid_with_height(1, 1.0), Vec::new());
+        map.add_grid(make_grid_with_height(2, 1.0), vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        map.add_grid(make_grid_with_height(3, 1.0), Vec::new());
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        map.set_grid_last_seen(1, now - Duration::from_secs(100));
+        map.set_grid_last_seen(2, now);
+        map.set_grid_last_seen(3, now - Duration::from_secs(100));
+        assert_eq!(map.get_grids_older_than(1, now - Duration::from_secs(10)), vec![Vec2i::zero()]);
+        assert_eq!(map.get_grids_older_than(1, now + Duration::from_secs(10)), vec![Vec2i::zero(), Vec2i::new(1, 0)]);
+        assert_eq!(map.get_grids_older_than(2, now), Vec::<Vec2i>::new());
+    }
 }
@@ -1,7 +1,52 @@
+use std::collections::BTreeSet;
 use std::sync::{Arc, Mutex};
 
-use crate::bot::map::{Grid, GridNeighbour, Tile};
-use crate::bot::vec2::Vec2i;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+use crate::bot::bookmarks::Bookmark;
+use crate::bot::map::{Grid, GridNeighbour, MapObject, Tile};
+use crate::bot::map_retention::{MapPruneStats, MapRetentionConfig};
+use crate::bot::player::Resource;
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::zones::Zone;
+
+pub const GRID_FORMAT_JSON: i64 = 0;
+pub const GRID_FORMAT_BINCODE_ZSTD: i64 = 1;
+
+const GRID_ZSTD_LEVEL: i32 = 3;
+
+#[derive(Clone, Deserialize, PartialEq)]
+pub enum MapDbBackend {
+    Sqlite,
+    Postgres,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MapDbConfig {
+    pub backend: MapDbBackend,
+    pub path: String,
+    pub url: String,
+    pub cache_ttl: f64,
+    pub cache_capacity: usize,
+    pub write_behind: Option<MapDbWriteBehindConfig>,
+    pub retention: Option<MapRetentionConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MapDbWriteBehindConfig {
+    pub flush_interval: f64,
+    pub batch_size: usize,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct MapDbCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub grids: usize,
+    pub capacity: usize,
+}
 
 pub trait MapDb {
     fn get_tiles(&self) -> Vec<Tile>;
@@ -20,5 +65,87 @@ pub trait MapDb {
 
     fn add_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>, neighbours: &Vec<GridNeighbour>);
 
-    fn update_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>);
+    fn update_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>) -> bool;
+
+    fn set_grid_revision(&self, grid_id: i64, revision: i64);
+
+    fn get_grid_changes(&self, since_change_id: i64, limit: usize) -> Vec<(i64, Grid)>;
+
+    fn add_object(&self, object: &MapObject);
+
+    fn remove_object(&self, object_id: i64);
+
+    fn get_objects_in_rect(&self, segment_id: i64, min: Vec2f, max: Vec2f) -> Vec<(Vec2f, MapObject)>;
+
+    fn set_grid_last_seen(&self, grid_id: i64, last_seen: i64);
+
+    fn get_grids_older_than(&self, last_seen: i64) -> Vec<i64>;
+
+    fn merge_segments(&self, src_segment_id: i64, dst_segment_id: i64, shift: Vec2i) -> Result<usize, String>;
+
+    fn split_segment(&self, segment_id: i64, grid_ids: &Vec<i64>) -> Result<i64, String>;
+
+    fn set_zone(&self, zone: &Zone);
+
+    fn remove_zone(&self, segment_id: i64, name: &String) -> bool;
+
+    fn get_zones(&self, segment_id: i64) -> Vec<Zone>;
+
+    fn set_bookmark(&self, bookmark: &Bookmark);
+
+    fn remove_bookmark(&self, segment_id: i64, name: &String) -> bool;
+
+    fn get_bookmarks(&self, segment_id: i64) -> Vec<Bookmark>;
+
+    fn get_resources(&self) -> Vec<Resource>;
+
+    fn set_resource(&self, resource: &Resource);
+
+    fn prune(&self, config: &MapRetentionConfig, now: i64) -> Result<MapPruneStats, String>;
+
+    fn get_cache_stats(&self) -> MapDbCacheStats;
+
+    fn set_pinned_grids(&self, owner: u64, coords: BTreeSet<(i64, Vec2i)>);
+
+    fn flush(&self) -> Result<(), String>;
+}
+
+pub fn get_grid_hash(heights: &Vec<f32>, tiles: &Vec<i32>) -> i64 {
+    // FNV-1a is used because stored hashes have to be stable across builds
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let values = heights.iter().map(|v| v.to_bits())
+        .chain(std::iter::once(u32::MAX))
+        .chain(tiles.iter().map(|v| *v as u32));
+    for value in values {
+        for byte in value.to_le_bytes().iter() {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash as i64
+}
+
+pub fn encode_grid_values<T: Serialize>(values: &Vec<T>) -> Vec<u8> {
+    zstd::encode_all(bincode::serialize(values).unwrap().as_slice(), GRID_ZSTD_LEVEL).unwrap()
+}
+
+pub fn decode_grid_values<T: DeserializeOwned>(format: i64, data: &[u8]) -> Vec<T> {
+    match format {
+        GRID_FORMAT_JSON => serde_json::from_slice(data).unwrap(),
+        _ => bincode::deserialize(&zstd::decode_all(data).unwrap()).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_grid_hash_should_depend_on_heights_and_tiles() {
+        let hash = get_grid_hash(&vec![1.0, 2.0], &vec![3]);
+        assert_eq!(get_grid_hash(&vec![1.0, 2.0], &vec![3]), hash);
+        assert_ne!(get_grid_hash(&vec![1.0, 2.5], &vec![3]), hash);
+        assert_ne!(get_grid_hash(&vec![1.0, 2.0], &vec![4]), hash);
+        assert_ne!(get_grid_hash(&vec![1.0], &vec![2, 3]), get_grid_hash(&vec![1.0, 2.0], &vec![3]));
+    }
 }
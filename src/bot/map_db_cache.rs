@@ -0,0 +1,287 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::DerefMut;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+use crate::bot::lru_cache::LruCache;
+use crate::bot::map::{Grid, Tile};
+use crate::bot::map_db::MapDbCacheStats;
+use crate::bot::vec2::Vec2i;
+
+const DEFAULT_GRIDS_CACHE_CAPACITY: usize = 10000;
+
+pub struct MapDbCache {
+    tiles: RefCell<BTreeMap<String, CachedTile>>,
+    grids_by_id: RefCell<LruCache<i64, CachedGrid>>,
+    grids_by_coord: RefCell<LruCache<(i64, Vec2i), CachedGrid>>,
+    pinned_grids: RefCell<BTreeMap<u64, BTreeSet<(i64, Vec2i)>>>,
+    rng: RefCell<SmallRng>,
+    ttl: Option<Uniform<Duration>>,
+    stats: Cell<MapDbCacheStats>,
+}
+
+impl MapDbCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            tiles: RefCell::new(BTreeMap::new()),
+            grids_by_id: RefCell::new(LruCache::new(DEFAULT_GRIDS_CACHE_CAPACITY)),
+            grids_by_coord: RefCell::new(LruCache::new(DEFAULT_GRIDS_CACHE_CAPACITY)),
+            pinned_grids: RefCell::new(BTreeMap::new()),
+            rng: RefCell::new(SeedableRng::from_entropy()),
+            ttl: if ttl.is_zero() {
+                None
+            } else {
+                Some(Uniform::new(ttl / 2, ttl.saturating_add(ttl / 2)))
+            },
+            stats: Cell::new(MapDbCacheStats::default()),
+        }
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        let evicted = self.grids_by_id.borrow_mut().set_capacity(capacity)
+            + self.grids_by_coord.borrow_mut().set_capacity(capacity);
+        self.add_evictions(evicted);
+    }
+
+    // Pinned grids are kept regardless of capacity until owner replaces its pinned set
+    pub fn set_pinned_grids(&self, owner: u64, coords: BTreeSet<(i64, Vec2i)>) {
+        let coords: BTreeSet<(i64, Vec2i)> = {
+            let mut pinned_grids = self.pinned_grids.borrow_mut();
+            if coords.is_empty() {
+                pinned_grids.remove(&owner);
+            } else {
+                pinned_grids.insert(owner, coords);
+            }
+            pinned_grids.values().flat_map(|v| v.iter().cloned()).collect()
+        };
+        let grid_ids = {
+            let grids_by_coord = self.grids_by_coord.borrow();
+            coords.iter()
+                .filter_map(|coord| grids_by_coord.get(coord))
+                .filter_map(|grid| grid.value.as_ref().map(|v| v.lock().unwrap().id))
+                .collect()
+        };
+        let evicted = self.grids_by_coord.borrow_mut().set_pinned(coords)
+            + self.grids_by_id.borrow_mut().set_pinned(grid_ids);
+        self.add_evictions(evicted);
+    }
+
+    pub fn set_tiles(&self, tiles: Vec<Tile>) {
+        let now = Instant::now();
+        let mut cached_tiles = self.tiles.borrow_mut();
+        for tile in tiles {
+            cached_tiles.insert(tile.name.clone(), CachedTile { cached_at: now, value: Some(tile.id) });
+        }
+    }
+
+    pub fn get_tile_id_by_name<F>(&self, name: &String, get_tile: F) -> Option<i32>
+        where F: FnOnce() -> Option<Tile> {
+        if let Some(tile) = self.tiles.borrow().get(name) {
+            if Instant::now() - tile.cached_at < self.sample_ttl() {
+                self.add_lookup(true);
+                return tile.value;
+            }
+        }
+        self.add_lookup(false);
+        let value = get_tile().map(|v| v.id);
+        self.tiles.borrow_mut().insert(name.clone(), CachedTile { cached_at: Instant::now(), value });
+        value
+    }
+
+    pub fn clear_tiles(&self) {
+        self.tiles.borrow_mut().clear();
+    }
+
+    pub fn get_grid_by_id<R, G>(&self, grid_id: i64, get_revision: R, get_grid: G) -> Option<Arc<Mutex<Grid>>>
+        where R: FnOnce() -> Option<i64>,
+              G: FnOnce() -> Option<Grid> {
+        let cached = self.grids_by_id.borrow_mut().get_mut(&grid_id)
+            .and_then(|grid| self.validate(grid, get_revision));
+        if let Some(grid) = cached {
+            self.add_lookup(true);
+            return grid;
+        }
+        self.add_lookup(false);
+        match get_grid() {
+            Some(grid) => Some(self.add_grid(grid)),
+            None => {
+                self.add_evictions(self.grids_by_id.borrow_mut().insert(grid_id, CachedGrid::new(None)));
+                None
+            }
+        }
+    }
+
+    pub fn get_grid<R, G>(&self, segment_id: i64, position: Vec2i, get_revision: R,
+                          get_grid: G) -> Option<Arc<Mutex<Grid>>>
+        where R: FnOnce() -> Option<i64>,
+              G: FnOnce() -> Option<Grid> {
+        let coord = (segment_id, position);
+        let cached = self.grids_by_coord.borrow_mut().get_mut(&coord)
+            .and_then(|grid| self.validate(grid, get_revision));
+        if let Some(grid) = cached {
+            self.add_lookup(true);
+            return grid;
+        }
+        self.add_lookup(false);
+        match get_grid() {
+            Some(grid) => Some(self.add_grid(grid)),
+            None => {
+                self.add_evictions(self.grids_by_coord.borrow_mut().insert(coord, CachedGrid::new(None)));
+                None
+            }
+        }
+    }
+
+    pub fn clear_grids(&self) {
+        self.grids_by_id.borrow_mut().clear();
+        self.grids_by_coord.borrow_mut().clear();
+    }
+
+    pub fn clear_grids_by_coord(&self) {
+        self.grids_by_coord.borrow_mut().clear();
+    }
+
+    pub fn get_stats(&self) -> MapDbCacheStats {
+        MapDbCacheStats {
+            grids: self.grids_by_id.borrow().len(),
+            capacity: self.grids_by_id.borrow().capacity(),
+            ..self.stats.get()
+        }
+    }
+
+    fn add_grid(&self, grid: Grid) -> Arc<Mutex<Grid>> {
+        let coord = (grid.segment_id, grid.position);
+        let grid_id = grid.id;
+        let value = Arc::new(Mutex::new(grid));
+        if self.grids_by_coord.borrow().is_pinned(&coord) {
+            self.grids_by_id.borrow_mut().pin(grid_id);
+        }
+        let evicted = self.grids_by_coord.borrow_mut().insert(coord, CachedGrid::new(Some(Arc::clone(&value))))
+            + self.grids_by_id.borrow_mut().insert(grid_id, CachedGrid::new(Some(Arc::clone(&value))));
+        self.add_evictions(evicted);
+        value
+    }
+
+    fn validate<R>(&self, grid: &mut CachedGrid, get_revision: R) -> Option<Option<Arc<Mutex<Grid>>>>
+        where R: FnOnce() -> Option<i64> {
+        if Instant::now() - grid.cached_at < self.sample_ttl() {
+            return Some(grid.value.as_ref().map(Arc::clone));
+        }
+        let value = grid.value.as_ref().map(Arc::clone)?;
+        match get_revision() {
+            Some(revision) if value.lock().unwrap().revision != revision => None,
+            Some(_) => {
+                grid.cached_at = Instant::now();
+                Some(Some(value))
+            }
+            None => {
+                grid.cached_at = Instant::now();
+                Some(None)
+            }
+        }
+    }
+
+    fn sample_ttl(&self) -> Duration {
+        self.ttl.map(|v| v.sample(self.rng.borrow_mut().deref_mut())).unwrap_or(Duration::ZERO)
+    }
+
+    fn add_lookup(&self, hit: bool) {
+        let mut stats = self.stats.get();
+        if hit {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
+        }
+        self.stats.set(stats);
+    }
+
+    fn add_evictions(&self, evicted: usize) {
+        if evicted > 0 {
+            let mut stats = self.stats.get();
+            stats.evictions += evicted as u64;
+            self.stats.set(stats);
+        }
+    }
+}
+
+struct CachedTile {
+    cached_at: Instant,
+    value: Option<i32>,
+}
+
+struct CachedGrid {
+    cached_at: Instant,
+    value: Option<Arc<Mutex<Grid>>>,
+}
+
+impl CachedGrid {
+    fn new(value: Option<Arc<Mutex<Grid>>>) -> Self {
+        Self { cached_at: Instant::now(), value }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_grid(id: i64, revision: i64) -> Grid {
+        Grid { id, revision, segment_id: 1, position: Vec2i::new(id as i32, 0), heights: Vec::new(), tiles: Vec::new() }
+    }
+
+    #[test]
+    fn get_grid_by_id_should_not_get_grid_within_ttl() {
+        let cache = MapDbCache::new(Duration::from_secs(3600));
+        assert!(cache.get_grid_by_id(1, || unreachable!(), || Some(make_grid(1, 1))).is_some());
+        assert!(cache.get_grid_by_id(1, || unreachable!(), || unreachable!()).is_some());
+        assert!(cache.get_grid(1, Vec2i::new(1, 0), || unreachable!(), || unreachable!()).is_some());
+        let stats = cache.get_stats();
+        assert_eq!((stats.hits, stats.misses, stats.grids), (2, 1, 1));
+    }
+
+    #[test]
+    fn get_grid_by_id_should_get_grid_with_changed_revision_after_ttl() {
+        let cache = MapDbCache::new(Duration::ZERO);
+        cache.get_grid_by_id(1, || unreachable!(), || Some(make_grid(1, 1)));
+        let grid = cache.get_grid_by_id(1, || Some(1), || unreachable!()).unwrap();
+        assert_eq!(grid.lock().unwrap().revision, 1);
+        let grid = cache.get_grid_by_id(1, || Some(2), || Some(make_grid(1, 2))).unwrap();
+        assert_eq!(grid.lock().unwrap().revision, 2);
+        assert!(cache.get_grid_by_id(1, || None, || unreachable!()).is_none());
+    }
+
+    #[test]
+    fn pinned_grids_should_not_be_evicted_by_capacity() {
+        let cache = MapDbCache::new(Duration::from_secs(3600));
+        cache.set_capacity(1);
+        cache.set_pinned_grids(1, vec![(1, Vec2i::new(1, 0))].into_iter().collect());
+        cache.set_pinned_grids(2, vec![(1, Vec2i::new(2, 0))].into_iter().collect());
+        for id in 1..=4 {
+            cache.get_grid(1, Vec2i::new(id as i32, 0), || unreachable!(), || Some(make_grid(id, 1)));
+        }
+        assert!(cache.get_grid(1, Vec2i::new(1, 0), || unreachable!(), || unreachable!()).is_some());
+        assert!(cache.get_grid_by_id(2, || unreachable!(), || unreachable!()).is_some());
+        assert!(cache.get_grid(1, Vec2i::new(3, 0), || unreachable!(), || None).is_none());
+        assert!(cache.get_grid_by_id(4, || unreachable!(), || unreachable!()).is_some());
+        cache.set_pinned_grids(1, BTreeSet::new());
+        assert!(cache.get_grid_by_id(2, || unreachable!(), || unreachable!()).is_some());
+        cache.set_pinned_grids(2, BTreeSet::new());
+        assert!(cache.get_grid_by_id(1, || unreachable!(), || None).is_none());
+        assert_eq!(cache.get_stats().grids, 1);
+    }
+
+    #[test]
+    fn get_tile_id_by_name_should_cache_missing_tile() {
+        let cache = MapDbCache::new(Duration::from_secs(3600));
+        let name = String::from("gfx/tiles/water");
+        assert_eq!(cache.get_tile_id_by_name(&name, || None), None);
+        assert_eq!(cache.get_tile_id_by_name(&name, || unreachable!()), None);
+        cache.clear_tiles();
+        let tile = Tile { id: 1, version: 1, name: name.clone(), color: 0 };
+        assert_eq!(cache.get_tile_id_by_name(&name, || Some(tile)), Some(1));
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+
+use crate::bot::map::{Grid, GRID_SIZE, tile_index_to_tile_pos};
+use crate::bot::map_db::MapDb;
+use crate::bot::vec2::Vec2i;
+use crate::bot::visualization::make_rgba_color;
+
+pub fn export_map_png(map_db: &dyn MapDb, segment_id: i64) -> Result<Vec<u8>, String> {
+    let segment_id = map_db.get_grid_by_id(segment_id)
+        .map(|v| v.lock().unwrap().segment_id)
+        .unwrap_or(segment_id);
+    let grids: Vec<Grid> = map_db.get_grid_ids_by_segment_id(segment_id).into_iter()
+        .filter_map(|grid_id| map_db.get_grid_by_id(grid_id))
+        .map(|v| v.lock().unwrap().clone())
+        .collect();
+    if grids.is_empty() {
+        return Err(format!("Segment {} is not found", segment_id));
+    }
+    let colors: BTreeMap<i32, [u8; 4]> = map_db.get_tiles().into_iter()
+        .map(|v| (v.id, make_rgba_color(v.color)))
+        .collect();
+    let min = grids.iter().fold(grids[0].position, |r, v| Vec2i::new(r.x().min(v.position.x()), r.y().min(v.position.y())));
+    let max = grids.iter().fold(grids[0].position, |r, v| Vec2i::new(r.x().max(v.position.x()), r.y().max(v.position.y())));
+    let size = (max - min + Vec2i::new(1, 1)) * GRID_SIZE;
+    let mut image = RgbaImage::new(size.x() as u32, size.y() as u32);
+    for grid in grids.iter() {
+        let shift = (grid.position - min) * GRID_SIZE;
+        for (index, tile_id) in grid.tiles.iter().enumerate() {
+            let position = tile_index_to_tile_pos(index) + shift;
+            let color = colors.get(tile_id).cloned().unwrap_or([255, 255, 255, 255]);
+            image.put_pixel(position.x() as u32, position.y() as u32, Rgba(color));
+        }
+    }
+    debug!("Export {} grids of segment {} into {}x{} image", grids.len(), segment_id, size.x(), size.y());
+    let mut result = Vec::new();
+    DynamicImage::ImageRgba8(image).write_to(&mut result, ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to encode map image: {}", e))?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::bot::map::{GridNeighbour, Tile};
+    use crate::bot::sqlite_map_db::SqliteMapDb;
+
+    use super::*;
+
+    #[test]
+    fn export_map_png_should_render_segment_grids_by_tile_colors() {
+        let map_db = SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default());
+        map_db.set_tile(&Tile { id: 1, version: 1, name: String::from("water"), color: 0x7F0000FF });
+        map_db.set_tile(&Tile { id: 2, version: 1, name: String::from("grass"), color: 0x7F00FF00 });
+        let tiles = |id| vec![id; (GRID_SIZE * GRID_SIZE) as usize];
+        map_db.add_grid(1, &Vec::new(), &tiles(1), &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &tiles(2), &vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        let image = image::load_from_memory(&export_map_png(&map_db, 2).unwrap()).unwrap().to_rgba();
+        assert_eq!(image.dimensions(), (2 * GRID_SIZE as u32, GRID_SIZE as u32));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 255, 127]));
+        assert_eq!(image.get_pixel(GRID_SIZE as u32, 0), &Rgba([0, 255, 0, 127]));
+        assert!(export_map_png(&map_db, 3).is_err());
+    }
+}
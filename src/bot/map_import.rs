@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Serialize};
+
+use crate::bot::map::{GRID_SIZE, GridNeighbour};
+use crate::bot::map_db::MapDb;
+use crate::bot::vec2::Vec2i;
+
+const GRID_FILE_PREFIX: &str = "grid-";
+const SEGMENT_FILE_PREFIX: &str = "seg-";
+const MAX_GRID_VERSION: u8 = 4;
+const MAX_SEGMENT_VERSION: u8 = 1;
+
+const NEIGHBOUR_OFFSETS: &[Vec2i] = &[
+    Vec2i::new(-1, -1),
+    Vec2i::new(-1, 0),
+    Vec2i::new(-1, 1),
+    Vec2i::new(0, -1),
+    Vec2i::new(0, 1),
+    Vec2i::new(1, -1),
+    Vec2i::new(1, 0),
+    Vec2i::new(1, 1),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapDumpFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MapImportStats {
+    pub segments: usize,
+    pub grids: usize,
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct DumpGrid {
+    id: i64,
+    tilesets: Vec<String>,
+    tiles: Vec<u8>,
+    heights: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct DumpSegment {
+    id: i64,
+    grids: BTreeMap<Vec2i, i64>,
+}
+
+pub fn read_map_dump_dir(path: &String) -> Result<Vec<MapDumpFile>, String> {
+    let entries = std::fs::read_dir(path)
+        .map_err(|e| format!("Failed to read map dump directory {}: {}", path, e))?;
+    let mut result = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read map dump directory {}: {}", path, e))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(GRID_FILE_PREFIX) && !name.starts_with(SEGMENT_FILE_PREFIX) {
+            continue;
+        }
+        let data = std::fs::read(entry.path())
+            .map_err(|e| format!("Failed to read map dump file {}: {}", name, e))?;
+        result.push(MapDumpFile { name, data });
+    }
+    Ok(result)
+}
+
+pub fn import_map_dump(map_db: &dyn MapDb, files: &Vec<MapDumpFile>) -> Result<MapImportStats, String> {
+    let mut grids = BTreeMap::new();
+    let mut segments = Vec::new();
+    for file in files.iter() {
+        if let Some(id) = parse_file_id(&file.name, GRID_FILE_PREFIX) {
+            let grid = decode_grid(&file.data).map_err(|e| format!("Failed to decode grid file {}: {}", file.name, e))?;
+            if grid.id != id {
+                return Err(format!("Grid file {} contains grid {}", file.name, grid.id));
+            }
+            grids.insert(grid.id, grid);
+        } else if let Some(id) = parse_file_id(&file.name, SEGMENT_FILE_PREFIX) {
+            let segment = decode_segment(&file.data).map_err(|e| format!("Failed to decode segment file {}: {}", file.name, e))?;
+            if segment.id != id {
+                return Err(format!("Segment file {} contains segment {}", file.name, segment.id));
+            }
+            segments.push(segment);
+        }
+    }
+    if segments.is_empty() {
+        return Err(String::from("Map dump has no segments"));
+    }
+    let tile_ids: BTreeMap<String, i32> = map_db.get_tiles().into_iter().map(|v| (v.name, v.id)).collect();
+    let mut stats = MapImportStats::default();
+    for segment in segments.iter() {
+        let mut imported = 0;
+        for (position, grid_id) in segment.grids.iter() {
+            let grid = match grids.get(grid_id) {
+                Some(v) => v,
+                None => {
+                    debug!("Grid {} of segment {} is missing in map dump", grid_id, segment.id);
+                    stats.skipped += 1;
+                    continue;
+                }
+            };
+            if map_db.get_grid_by_id(grid.id).is_some() {
+                stats.skipped += 1;
+                continue;
+            }
+            let tiles = match get_tile_ids(grid, &tile_ids) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Skip grid {} of segment {}: {}", grid.id, segment.id, e);
+                    stats.skipped += 1;
+                    continue;
+                }
+            };
+            let neighbours: Vec<GridNeighbour> = NEIGHBOUR_OFFSETS.iter()
+                .filter_map(|offset| {
+                    segment.grids.get(&(*position + *offset)).map(|id| GridNeighbour { id: *id, offset: *offset })
+                })
+                .collect();
+            map_db.add_grid(grid.id, &grid.heights, &tiles, &neighbours);
+            imported += 1;
+        }
+        debug!("Imported {} of {} grids of segment {}", imported, segment.grids.len(), segment.id);
+        if imported > 0 {
+            stats.segments += 1;
+            stats.grids += imported;
+        }
+    }
+    Ok(stats)
+}
+
+fn parse_file_id(name: &String, prefix: &str) -> Option<i64> {
+    if !name.starts_with(prefix) {
+        return None;
+    }
+    let id = &name[prefix.len()..];
+    let id = &id[0..id.find('.').unwrap_or(id.len())];
+    u64::from_str_radix(id, 16).ok().map(|v| v as i64)
+}
+
+fn get_tile_ids(grid: &DumpGrid, tile_ids: &BTreeMap<String, i32>) -> Result<Vec<i32>, String> {
+    let tilesets = grid.tilesets.iter()
+        .map(|name| tile_ids.get(name).cloned().ok_or_else(|| format!("tile {} is unknown", name)))
+        .collect::<Result<Vec<i32>, String>>()?;
+    grid.tiles.iter()
+        .map(|v| tilesets.get(*v as usize).cloned().ok_or_else(|| format!("tileset index {} is out of range", v)))
+        .collect()
+}
+
+// Grid file: version byte followed by zlib stream with id, mtime (since version 2), tilesets, tiles
+// and heights (since version 3). Anything after heights is ignored.
+fn decode_grid(data: &[u8]) -> Result<DumpGrid, String> {
+    let (version, mut reader) = open_dump(data, MAX_GRID_VERSION)?;
+    let id = reader.int64()?;
+    if version >= 2 {
+        reader.int64()?;
+    }
+    let mut tilesets = Vec::new();
+    for _ in 0..reader.uint8()? {
+        let name = reader.string()?;
+        reader.uint16()?;
+        reader.uint8()?;
+        tilesets.push(name);
+    }
+    let size = (GRID_SIZE * GRID_SIZE) as usize;
+    let tiles = reader.bytes(size)?.to_vec();
+    let mut heights = Vec::new();
+    if version >= 3 {
+        heights.reserve(size);
+        for _ in 0..size {
+            heights.push(reader.float32()?);
+        }
+    }
+    Ok(DumpGrid { id, tilesets, tiles, heights })
+}
+
+// Segment file: version byte followed by zlib stream with id and a list of grid positions with ids.
+fn decode_segment(data: &[u8]) -> Result<DumpSegment, String> {
+    let (_, mut reader) = open_dump(data, MAX_SEGMENT_VERSION)?;
+    let id = reader.int64()?;
+    let mut grids = BTreeMap::new();
+    for _ in 0..reader.int32()? {
+        let position = Vec2i::new(reader.int32()?, reader.int32()?);
+        grids.insert(position, reader.int64()?);
+    }
+    Ok(DumpSegment { id, grids })
+}
+
+fn open_dump(data: &[u8], max_version: u8) -> Result<(u8, DumpReader), String> {
+    let version = *data.first().ok_or_else(|| String::from("file is empty"))?;
+    if version < 1 || version > max_version {
+        return Err(format!("version {} is not supported", version));
+    }
+    let mut content = Vec::new();
+    ZlibDecoder::new(&data[1..]).read_to_end(&mut content)
+        .map_err(|e| format!("failed to decompress: {}", e))?;
+    Ok((version, DumpReader { data: content, position: 0 }))
+}
+
+struct DumpReader {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl DumpReader {
+    fn bytes(&mut self, size: usize) -> Result<&[u8], String> {
+        if self.data.len() - self.position < size {
+            return Err(format!("unexpected end of data at {}", self.position));
+        }
+        let result = &self.data[self.position..self.position + size];
+        self.position += size;
+        Ok(result)
+    }
+
+    fn uint8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn uint16(&mut self) -> Result<u16, String> {
+        let mut value = [0; 2];
+        value.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_le_bytes(value))
+    }
+
+    fn int32(&mut self) -> Result<i32, String> {
+        let mut value = [0; 4];
+        value.copy_from_slice(self.bytes(4)?);
+        Ok(i32::from_le_bytes(value))
+    }
+
+    fn int64(&mut self) -> Result<i64, String> {
+        let mut value = [0; 8];
+        value.copy_from_slice(self.bytes(8)?);
+        Ok(i64::from_le_bytes(value))
+    }
+
+    fn float32(&mut self) -> Result<f32, String> {
+        let mut value = [0; 4];
+        value.copy_from_slice(self.bytes(4)?);
+        Ok(f32::from_le_bytes(value))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.data[self.position..].iter().position(|v| *v == 0)
+            .ok_or_else(|| format!("unterminated string at {}", self.position))?;
+        let value = String::from_utf8(self.bytes(length)?.to_vec())
+            .map_err(|e| format!("invalid string: {}", e))?;
+        self.position += 1;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use rusqlite::Connection;
+
+    use crate::bot::map::Tile;
+    use crate::bot::sqlite_map_db::SqliteMapDb;
+
+    use super::*;
+
+    fn make_dump(version: u8, content: &Vec<u8>) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(vec![version], Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn make_grid_file(id: i64, tilesets: &[&str], tile: u8) -> MapDumpFile {
+        let mut content = Vec::new();
+        content.extend_from_slice(&id.to_le_bytes());
+        content.extend_from_slice(&0i64.to_le_bytes());
+        content.push(tilesets.len() as u8);
+        for name in tilesets.iter() {
+            content.extend_from_slice(name.as_bytes());
+            content.push(0);
+            content.extend_from_slice(&1u16.to_le_bytes());
+            content.push(0);
+        }
+        content.extend(vec![tile; (GRID_SIZE * GRID_SIZE) as usize]);
+        MapDumpFile { name: format!("grid-{:016x}", id), data: make_dump(2, &content) }
+    }
+
+    fn make_segment_file(id: i64, grids: &[(i32, i32, i64)]) -> MapDumpFile {
+        let mut content = Vec::new();
+        content.extend_from_slice(&id.to_le_bytes());
+        content.extend_from_slice(&(grids.len() as i32).to_le_bytes());
+        for (x, y, grid_id) in grids.iter() {
+            content.extend_from_slice(&x.to_le_bytes());
+            content.extend_from_slice(&y.to_le_bytes());
+            content.extend_from_slice(&grid_id.to_le_bytes());
+        }
+        MapDumpFile { name: format!("seg-{:016x}", id), data: make_dump(1, &content) }
+    }
+
+    #[test]
+    fn import_map_dump_should_add_segment_grids_with_positions() {
+        let map_db = SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default());
+        map_db.set_tile(&Tile { id: 3, version: 1, name: String::from("gfx/tiles/water"), color: 0 });
+        map_db.set_tile(&Tile { id: 7, version: 1, name: String::from("gfx/tiles/grass"), color: 0 });
+        let files = vec![
+            make_grid_file(11, &["gfx/tiles/grass", "gfx/tiles/water"], 1),
+            make_grid_file(12, &["gfx/tiles/grass"], 0),
+            make_grid_file(13, &["gfx/tiles/lava"], 0),
+            make_segment_file(42, &[(5, 5, 11), (6, 5, 12), (5, 6, 13)]),
+        ];
+        assert_eq!(
+            import_map_dump(&map_db, &files),
+            Ok(MapImportStats { segments: 1, grids: 2, skipped: 1 })
+        );
+        let first = map_db.get_grid_by_id(11).unwrap().lock().unwrap().clone();
+        let second = map_db.get_grid_by_id(12).unwrap().lock().unwrap().clone();
+        assert_eq!(first.segment_id, second.segment_id);
+        assert_eq!(second.position - first.position, Vec2i::new(1, 0));
+        assert_eq!(first.tiles, vec![3; (GRID_SIZE * GRID_SIZE) as usize]);
+        assert_eq!(second.tiles, vec![7; (GRID_SIZE * GRID_SIZE) as usize]);
+        assert!(map_db.get_grid_by_id(13).is_none());
+        assert_eq!(
+            import_map_dump(&map_db, &files),
+            Ok(MapImportStats { segments: 0, grids: 0, skipped: 3 })
+        );
+    }
+
+    #[test]
+    fn decode_grid_should_reject_unsupported_version() {
+        assert_eq!(decode_grid(&make_dump(9, &Vec::new())), Err(String::from("version 9 is not supported")));
+        assert_eq!(decode_grid(&make_dump(2, &vec![0; 4])), Err(String::from("unexpected end of data at 0")));
+    }
+}
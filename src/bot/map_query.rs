@@ -0,0 +1,166 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::map::{make_tile_pos, tile_index_to_tile_pos, tile_pos_to_grid_pos};
+use crate::bot::map_db::MapDb;
+use crate::bot::reachability::get_tile_index;
+use crate::bot::vec2::Vec2i;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GridInfo {
+    pub id: i64,
+    pub revision: i64,
+    pub segment_id: i64,
+    pub position: Vec2i,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TileInfo {
+    pub grid_id: i64,
+    pub position: Vec2i,
+    pub id: i32,
+    pub name: Option<String>,
+    pub height: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TileStats {
+    pub id: i32,
+    pub name: Option<String>,
+    pub count: usize,
+    pub min: Vec2i,
+    pub max: Vec2i,
+}
+
+pub fn get_segment_grids(map_db: &dyn MapDb, segment_id: i64) -> Result<Vec<GridInfo>, String> {
+    let mut grids: Vec<GridInfo> = map_db.get_grid_ids_by_segment_id(segment_id).into_iter()
+        .filter_map(|grid_id| map_db.get_grid_by_id(grid_id))
+        .map(|v| {
+            let grid = v.lock().unwrap();
+            GridInfo {
+                id: grid.id,
+                revision: grid.revision,
+                segment_id: grid.segment_id,
+                position: grid.position,
+            }
+        })
+        .collect();
+    if grids.is_empty() {
+        return Err(format!("Segment {} is not found", segment_id));
+    }
+    grids.sort_by_key(|v| (v.position.y(), v.position.x()));
+    Ok(grids)
+}
+
+pub fn get_segment_tile(map_db: &dyn MapDb, segment_id: i64, tile_pos: Vec2i) -> Result<TileInfo, String> {
+    let grid_pos = tile_pos_to_grid_pos(tile_pos);
+    let grid = map_db.get_grid(segment_id, grid_pos)
+        .ok_or_else(|| format!("Grid {:?} is not found in segment {}", grid_pos, segment_id))?;
+    let grid = grid.lock().unwrap();
+    let index = get_tile_index(tile_pos - make_tile_pos(grid_pos, Vec2i::zero()));
+    let id = grid.tiles.get(index).cloned()
+        .ok_or_else(|| format!("Tile {:?} is not found in grid {}", tile_pos, grid.id))?;
+    Ok(TileInfo {
+        grid_id: grid.id,
+        position: tile_pos,
+        id,
+        name: map_db.get_tiles().into_iter().find(|v| v.id == id).map(|v| v.name),
+        height: grid.heights.get(index).cloned(),
+    })
+}
+
+pub fn get_segment_tile_stats(map_db: &dyn MapDb, segment_id: i64) -> Result<Vec<TileStats>, String> {
+    let grid_ids = map_db.get_grid_ids_by_segment_id(segment_id);
+    if grid_ids.is_empty() {
+        return Err(format!("Segment {} is not found", segment_id));
+    }
+    let names: BTreeMap<i32, String> = map_db.get_tiles().into_iter().map(|v| (v.id, v.name)).collect();
+    let mut stats: BTreeMap<i32, TileStats> = BTreeMap::new();
+    for grid in grid_ids.into_iter().filter_map(|grid_id| map_db.get_grid_by_id(grid_id)) {
+        let grid = grid.lock().unwrap();
+        for (index, &id) in grid.tiles.iter().enumerate() {
+            let tile_pos = make_tile_pos(grid.position, tile_index_to_tile_pos(index));
+            let value = stats.entry(id).or_insert_with(|| TileStats {
+                id,
+                name: names.get(&id).cloned(),
+                count: 0,
+                min: tile_pos,
+                max: tile_pos,
+            });
+            value.count += 1;
+            value.min = Vec2i::new(value.min.x().min(tile_pos.x()), value.min.y().min(tile_pos.y()));
+            value.max = Vec2i::new(value.max.x().max(tile_pos.x()), value.max.y().max(tile_pos.y()));
+        }
+    }
+    let mut result: Vec<TileStats> = stats.into_iter().map(|(_, v)| v).collect();
+    result.sort_by_key(|v| (Reverse(v.count), v.id));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::bot::map::{GRID_SIZE, GridNeighbour, Tile};
+    use crate::bot::sqlite_map_db::SqliteMapDb;
+
+    use super::*;
+
+    #[test]
+    fn map_query_should_return_segment_grids_and_tiles() {
+        let map_db = SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default());
+        map_db.set_tile(&Tile { id: 1, version: 1, name: String::from("water"), color: 0x7F0000FF });
+        map_db.set_tile(&Tile { id: 2, version: 1, name: String::from("grass"), color: 0x7F00FF00 });
+        let tiles = |id| vec![id; (GRID_SIZE * GRID_SIZE) as usize];
+        map_db.add_grid(1, &Vec::new(), &tiles(1), &Vec::new());
+        map_db.add_grid(2, &vec![3.0; (GRID_SIZE * GRID_SIZE) as usize], &tiles(2),
+                        &vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        let segment_id = map_db.get_grid_by_id(1).unwrap().lock().unwrap().segment_id;
+        let grids = get_segment_grids(&map_db, segment_id).unwrap();
+        assert_eq!(grids.iter().map(|v| v.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(grids[1].position - grids[0].position, Vec2i::new(1, 0));
+        let tile_pos = make_tile_pos(grids[1].position, Vec2i::new(3, 5));
+        assert_eq!(get_segment_tile(&map_db, segment_id, tile_pos), Ok(TileInfo {
+            grid_id: 2,
+            position: tile_pos,
+            id: 2,
+            name: Some(String::from("grass")),
+            height: Some(3.0),
+        }));
+        let tile = get_segment_tile(&map_db, segment_id, make_tile_pos(grids[0].position, Vec2i::zero())).unwrap();
+        assert_eq!((tile.grid_id, tile.name, tile.height), (1, Some(String::from("water")), None));
+        assert!(get_segment_grids(&map_db, segment_id + 1).is_err());
+        assert!(get_segment_tile(&map_db, segment_id, make_tile_pos(grids[1].position, Vec2i::new(GRID_SIZE, 0))).is_err());
+    }
+
+    #[test]
+    fn get_segment_tile_stats_should_count_tiles_and_bounds_by_type() {
+        let map_db = SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default());
+        map_db.set_tile(&Tile { id: 1, version: 1, name: String::from("water"), color: 0x7F0000FF });
+        let mut tiles = vec![2; (GRID_SIZE * GRID_SIZE) as usize];
+        tiles[get_tile_index(Vec2i::new(3, 5))] = 1;
+        tiles[get_tile_index(Vec2i::new(7, 2))] = 1;
+        map_db.add_grid(1, &Vec::new(), &tiles, &Vec::new());
+        let segment_id = map_db.get_grid_by_id(1).unwrap().lock().unwrap().segment_id;
+        let origin = make_tile_pos(map_db.get_grid_by_id(1).unwrap().lock().unwrap().position, Vec2i::zero());
+        assert_eq!(get_segment_tile_stats(&map_db, segment_id), Ok(vec![
+            TileStats {
+                id: 2,
+                name: None,
+                count: (GRID_SIZE * GRID_SIZE) as usize - 2,
+                min: origin,
+                max: origin + Vec2i::new(GRID_SIZE - 1, GRID_SIZE - 1),
+            },
+            TileStats {
+                id: 1,
+                name: Some(String::from("water")),
+                count: 2,
+                min: origin + Vec2i::new(3, 2),
+                max: origin + Vec2i::new(7, 5),
+            },
+        ]));
+        assert!(get_segment_tile_stats(&map_db, segment_id + 1).is_err());
+    }
+}
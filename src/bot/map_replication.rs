@@ -0,0 +1,359 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{JoinHandle, sleep, spawn};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::auth::make_authorization;
+use crate::bot::map::{Grid, GridNeighbour, Tile};
+use crate::bot::map_db::{get_grid_hash, MapDb};
+use crate::bot::protocol::Message;
+use crate::bot::vec2::Vec2i;
+
+#[derive(Clone, Deserialize, PartialEq)]
+pub enum MapReplicationRole {
+    Standalone,
+    Master,
+    Replica,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MapReplicationConfig {
+    pub role: MapReplicationRole,
+    pub master_url: String,
+    pub sync_interval: f64,
+    pub batch_size: usize,
+    pub token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapChanges {
+    pub change_id: i64,
+    pub tiles: Vec<Tile>,
+    pub grids: Vec<ReplicatedGrid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplicatedGrid {
+    pub id: i64,
+    pub revision: i64,
+    pub heights: Vec<f32>,
+    pub tiles: Vec<i32>,
+    pub neighbours: Vec<GridNeighbour>,
+}
+
+const NEIGHBOUR_OFFSETS: &[Vec2i] = &[
+    Vec2i::new(-1, -1),
+    Vec2i::new(-1, 0),
+    Vec2i::new(-1, 1),
+    Vec2i::new(0, -1),
+    Vec2i::new(0, 1),
+    Vec2i::new(1, -1),
+    Vec2i::new(1, 0),
+    Vec2i::new(1, 1),
+];
+
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn get_map_changes(map_db: &dyn MapDb, since_change_id: i64, limit: usize) -> MapChanges {
+    let changes = map_db.get_grid_changes(since_change_id, limit);
+    let tile_ids: BTreeSet<i32> = changes.iter()
+        .flat_map(|(_, grid)| grid.tiles.iter().cloned())
+        .collect();
+    MapChanges {
+        change_id: changes.last().map(|(change_id, _)| *change_id).unwrap_or(since_change_id),
+        tiles: map_db.get_tiles().into_iter()
+            .filter(|tile| tile_ids.contains(&tile.id))
+            .collect(),
+        grids: changes.into_iter()
+            .map(|(_, grid)| ReplicatedGrid {
+                neighbours: get_grid_neighbours(map_db, &grid),
+                id: grid.id,
+                revision: grid.revision,
+                heights: grid.heights,
+                tiles: grid.tiles,
+            })
+            .collect(),
+    }
+}
+
+pub fn apply_map_changes(map_db: &dyn MapDb, changes: &MapChanges) -> usize {
+    for tile in changes.tiles.iter() {
+        map_db.set_tile(tile);
+    }
+    let mut applied = 0;
+    for grid in changes.grids.iter() {
+        let existing = map_db.get_grid_by_id(grid.id).map(|v| {
+            let existing = v.lock().unwrap();
+            (existing.revision, get_grid_hash(&existing.heights, &existing.tiles))
+        });
+        match existing {
+            None => map_db.add_grid(grid.id, &grid.heights, &grid.tiles, &grid.neighbours),
+            Some((revision, hash)) if revision < grid.revision
+                && hash != get_grid_hash(&grid.heights, &grid.tiles) => {
+                map_db.update_grid(grid.id, &grid.heights, &grid.tiles);
+            }
+            Some(_) => continue,
+        }
+        // Revisions are compared to resolve conflicts so the stored one should match the master
+        map_db.set_grid_revision(grid.id, grid.revision);
+        applied += 1;
+    }
+    applied
+}
+
+pub fn skip_replicated_grids(changes: &mut MapChanges, replicated: &HashMap<i64, i64>) {
+    changes.grids.retain(|grid| replicated.get(&grid.id) != Some(&get_grid_hash(&grid.heights, &grid.tiles)));
+}
+
+fn get_grid_neighbours(map_db: &dyn MapDb, grid: &Grid) -> Vec<GridNeighbour> {
+    NEIGHBOUR_OFFSETS.iter()
+        .filter_map(|offset| {
+            map_db.get_grid(grid.segment_id, grid.position + *offset)
+                .map(|v| GridNeighbour { id: v.lock().unwrap().id, offset: *offset })
+        })
+        .collect()
+}
+
+pub fn start_map_replication(map_db: Arc<Mutex<dyn MapDb + Send>>, config: MapReplicationConfig,
+                             stop: Arc<AtomicBool>) -> Option<JoinHandle<()>> {
+    if config.role != MapReplicationRole::Replica {
+        return None;
+    }
+    Some(spawn(move || replicate_map(map_db, config, stop)))
+}
+
+fn replicate_map(map_db: Arc<Mutex<dyn MapDb + Send>>, config: MapReplicationConfig, stop: Arc<AtomicBool>) {
+    info!("Start map replication from {}", config.master_url);
+    let client = match make_client(&config.token) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to create map replication client: {}", e);
+            return;
+        }
+    };
+    let sync_interval = Duration::from_secs_f64(config.sync_interval);
+    let mut pushed_change_id = 0;
+    let mut pulled_change_id = 0;
+    let mut replicated = HashMap::new();
+    while !stop.load(Ordering::Relaxed) {
+        let mut local_changes = get_map_changes(map_db.lock().unwrap().deref(), pushed_change_id, config.batch_size);
+        skip_replicated_grids(&mut local_changes, &replicated);
+        if local_changes.grids.is_empty() {
+            pushed_change_id = local_changes.change_id;
+        } else {
+            match push_map_changes(&client, &config.master_url, &local_changes) {
+                Ok(_) => {
+                    debug!("Pushed {} grids to map master", local_changes.grids.len());
+                    pushed_change_id = local_changes.change_id;
+                }
+                Err(e) => warn!("Failed to push map changes: {}", e),
+            }
+        }
+        match pull_map_changes(&client, &config.master_url, pulled_change_id, config.batch_size) {
+            Ok(remote_changes) => {
+                let applied = apply_map_changes(map_db.lock().unwrap().deref(), &remote_changes);
+                for grid in remote_changes.grids.iter() {
+                    replicated.insert(grid.id, get_grid_hash(&grid.heights, &grid.tiles));
+                }
+                debug!("Pulled {} grids from map master, applied {}", remote_changes.grids.len(), applied);
+                pulled_change_id = remote_changes.change_id;
+                if remote_changes.grids.len() >= config.batch_size {
+                    continue;
+                }
+            }
+            Err(e) => warn!("Failed to pull map changes: {}", e),
+        }
+        wait_stop(&stop, sync_interval);
+    }
+    info!("Stop map replication");
+}
+
+fn wait_stop(stop: &AtomicBool, duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        sleep((deadline - now).min(STOP_CHECK_INTERVAL));
+    }
+}
+
+fn make_client(token: &Option<String>) -> Result<reqwest::blocking::Client, String> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(token) = token {
+        let value = reqwest::header::HeaderValue::from_str(make_authorization(token).as_str())
+            .map_err(|e| e.to_string())?;
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+    reqwest::blocking::Client::builder()
+        .default_headers(headers)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+fn push_map_changes(client: &reqwest::blocking::Client, master_url: &String, changes: &MapChanges) -> Result<(), String> {
+    let response = client.put(format!("{}/map/push", master_url).as_str())
+        .json(changes)
+        .send()
+        .map_err(|e| e.to_string())?;
+    match response.json::<Message>().map_err(|e| e.to_string())? {
+        Message::Ok => Ok(()),
+        Message::Error { message } => Err(message),
+        v => Err(format!("Unexpected response: {:?}", v)),
+    }
+}
+
+fn pull_map_changes(client: &reqwest::blocking::Client, master_url: &String, since_change_id: i64,
+                    limit: usize) -> Result<MapChanges, String> {
+    let response = client.get(format!("{}/map/changes", master_url).as_str())
+        .query(&[("since", since_change_id), ("limit", limit as i64)])
+        .send()
+        .map_err(|e| e.to_string())?;
+    match response.json::<Message>().map_err(|e| e.to_string())? {
+        Message::MapChanges { value } => Ok(value),
+        Message::Error { message } => Err(message),
+        v => Err(format!("Unexpected response: {:?}", v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_file;
+
+    use rusqlite::Connection;
+
+    use crate::bot::sqlite_map_db::SqliteMapDb;
+
+    use super::*;
+
+    struct RemovePath(&'static str);
+
+    impl Drop for RemovePath {
+        fn drop(&mut self) {
+            match remove_file(self.0) { _ => () }
+        }
+    }
+
+    fn make_map_db(path: &RemovePath) -> SqliteMapDb {
+        match remove_file(path.0) { _ => () };
+        SqliteMapDb::new(Connection::open(path.0).unwrap(), Duration::new(0, 0))
+    }
+
+    #[test]
+    fn applied_changes_should_reproduce_segment() {
+        let src_path = RemovePath("applied_changes_should_reproduce_segment_src.db");
+        let dst_path = RemovePath("applied_changes_should_reproduce_segment_dst.db");
+        let src = make_map_db(&src_path);
+        let dst = make_map_db(&dst_path);
+        src.set_tile(&Tile { id: 1, version: 1, name: String::from("water"), color: 0 });
+        src.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        src.add_grid(2, &vec![2.0], &vec![1], &vec![GridNeighbour { id: 1, offset: Vec2i::new(1, 0) }]);
+        let changes = get_map_changes(&src, 0, 10);
+        assert_eq!(changes.change_id, 2);
+        assert_eq!(apply_map_changes(&dst, &changes), 2);
+        assert_eq!(dst.get_tiles(), src.get_tiles());
+        assert_eq!(
+            dst.get_grids().iter().map(|v| (v.id, v.segment_id, v.position, v.heights.clone())).collect::<Vec<_>>(),
+            vec![(1, 1, Vec2i::zero(), vec![1.0]), (2, 1, Vec2i::new(-1, 0), vec![2.0])]
+        );
+    }
+
+    #[test]
+    fn changes_with_older_revision_should_be_ignored() {
+        let src_path = RemovePath("changes_with_older_revision_should_be_ignored_src.db");
+        let dst_path = RemovePath("changes_with_older_revision_should_be_ignored_dst.db");
+        let src = make_map_db(&src_path);
+        let dst = make_map_db(&dst_path);
+        src.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        dst.add_grid(1, &vec![2.0], &vec![2], &Vec::new());
+        dst.update_grid(1, &vec![3.0], &vec![3]);
+        assert_eq!(apply_map_changes(&dst, &get_map_changes(&src, 0, 10)), 0);
+        assert_eq!(dst.get_grids()[0].heights, vec![3.0]);
+    }
+
+    #[test]
+    fn changes_with_newer_revision_should_be_applied() {
+        let src_path = RemovePath("changes_with_newer_revision_should_be_applied_src.db");
+        let dst_path = RemovePath("changes_with_newer_revision_should_be_applied_dst.db");
+        let src = make_map_db(&src_path);
+        let dst = make_map_db(&dst_path);
+        src.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        src.update_grid(1, &vec![3.0], &vec![3]);
+        dst.add_grid(1, &vec![2.0], &vec![2], &Vec::new());
+        assert_eq!(apply_map_changes(&dst, &get_map_changes(&src, 0, 10)), 1);
+        assert_eq!(dst.get_grids()[0].heights, vec![3.0]);
+    }
+
+    #[test]
+    fn changes_should_be_replicated_back_after_round_trip() {
+        let master_path = RemovePath("changes_should_be_replicated_back_after_round_trip_master.db");
+        let replica_path = RemovePath("changes_should_be_replicated_back_after_round_trip_replica.db");
+        let master = make_map_db(&master_path);
+        let replica = make_map_db(&replica_path);
+        master.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        master.update_grid(1, &vec![2.0], &vec![2]);
+        master.update_grid(1, &vec![3.0], &vec![3]);
+        replica.add_grid(1, &vec![4.0], &vec![4], &Vec::new());
+        assert_eq!(apply_map_changes(&replica, &get_map_changes(&master, 0, 10)), 1);
+        assert_eq!(replica.get_grids()[0].revision, master.get_grids()[0].revision);
+        replica.update_grid(1, &vec![5.0], &vec![5]);
+        assert_eq!(apply_map_changes(&master, &get_map_changes(&replica, 0, 10)), 1);
+        assert_eq!(master.get_grids()[0].heights, vec![5.0]);
+        assert_eq!(master.get_grids()[0].revision, replica.get_grids()[0].revision);
+    }
+
+    #[test]
+    fn changes_with_same_content_should_be_ignored() {
+        let src_path = RemovePath("changes_with_same_content_should_be_ignored_src.db");
+        let dst_path = RemovePath("changes_with_same_content_should_be_ignored_dst.db");
+        let src = make_map_db(&src_path);
+        let dst = make_map_db(&dst_path);
+        src.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        src.update_grid(1, &vec![2.0], &vec![2]);
+        dst.add_grid(1, &vec![2.0], &vec![2], &Vec::new());
+        assert_eq!(apply_map_changes(&dst, &get_map_changes(&src, 0, 10)), 0);
+        assert_eq!(dst.get_grid_changes(0, 10).len(), 1);
+    }
+
+    #[test]
+    fn changes_should_contain_only_tiles_used_by_grids() {
+        let path = RemovePath("changes_should_contain_only_tiles_used_by_grids.db");
+        let map_db = make_map_db(&path);
+        map_db.set_tile(&Tile { id: 1, version: 1, name: String::from("water"), color: 0 });
+        map_db.set_tile(&Tile { id: 2, version: 1, name: String::from("grass"), color: 0 });
+        assert_eq!(get_map_changes(&map_db, 0, 10).tiles, Vec::new());
+        map_db.add_grid(1, &vec![1.0], &vec![2], &Vec::new());
+        assert_eq!(
+            get_map_changes(&map_db, 0, 10).tiles.iter().map(|v| v.id).collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn pulled_grids_should_not_be_pushed_back() {
+        let src_path = RemovePath("pulled_grids_should_not_be_pushed_back_src.db");
+        let dst_path = RemovePath("pulled_grids_should_not_be_pushed_back_dst.db");
+        let src = make_map_db(&src_path);
+        let dst = make_map_db(&dst_path);
+        src.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        dst.add_grid(2, &vec![2.0], &vec![2], &Vec::new());
+        let remote_changes = get_map_changes(&src, 0, 10);
+        assert_eq!(apply_map_changes(&dst, &remote_changes), 1);
+        let replicated = remote_changes.grids.iter()
+            .map(|v| (v.id, get_grid_hash(&v.heights, &v.tiles)))
+            .collect();
+        let mut local_changes = get_map_changes(&dst, 0, 10);
+        skip_replicated_grids(&mut local_changes, &replicated);
+        assert_eq!(local_changes.change_id, 2);
+        assert_eq!(local_changes.grids.iter().map(|v| v.id).collect::<Vec<_>>(), vec![2]);
+        dst.update_grid(1, &vec![3.0], &vec![3]);
+        let mut local_changes = get_map_changes(&dst, 2, 10);
+        skip_replicated_grids(&mut local_changes, &replicated);
+        assert_eq!(local_changes.grids.iter().map(|v| v.id).collect::<Vec<_>>(), vec![1]);
+    }
+}
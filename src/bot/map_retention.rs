@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::thread::{JoinHandle, sleep, spawn};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::map::as_unix_time;
+use crate::bot::map_db::MapDb;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MapRetentionConfig {
+    pub interval: f64,
+    pub max_grids_per_segment: Option<usize>,
+    pub max_age: Option<f64>,
+    pub pinned_segments: Vec<i64>,
+    pub vacuum: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MapPruneStats {
+    pub grids: usize,
+    pub segments: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridRetentionInfo {
+    pub grid_id: i64,
+    pub segment_id: i64,
+    pub last_seen: i64,
+    pub change_id: i64,
+}
+
+pub fn select_grids_to_prune(grids: &Vec<GridRetentionInfo>, pinned_segments: &BTreeSet<i64>,
+                             config: &MapRetentionConfig, now: i64) -> Vec<i64> {
+    let mut segments: BTreeMap<i64, Vec<&GridRetentionInfo>> = BTreeMap::new();
+    for grid in grids.iter().filter(|v| !pinned_segments.contains(&v.segment_id)) {
+        segments.entry(grid.segment_id).or_insert_with(Vec::new).push(grid);
+    }
+    let min_last_seen = config.max_age.map(|v| now - v as i64);
+    let mut result = Vec::new();
+    for segment_grids in segments.values_mut() {
+        segment_grids.sort_by_key(|v| (-v.last_seen, -v.change_id, v.grid_id));
+        let max_grids = config.max_grids_per_segment.unwrap_or(segment_grids.len());
+        for (index, grid) in segment_grids.iter().enumerate() {
+            if index >= max_grids || min_last_seen.map(|v| grid.last_seen < v).unwrap_or(false) {
+                result.push(grid.grid_id);
+            }
+        }
+    }
+    result.sort();
+    result
+}
+
+pub fn prune_map(map_db: &dyn MapDb, config: &MapRetentionConfig) -> Result<MapPruneStats, String> {
+    let stats = map_db.prune(config, as_unix_time(SystemTime::now()))?;
+    if stats.grids > 0 {
+        info!("Pruned {} grids from {} segments", stats.grids, stats.segments);
+    }
+    Ok(stats)
+}
+
+pub fn start_map_pruning(map_db: Arc<Mutex<dyn MapDb + Send>>, config: Option<MapRetentionConfig>) -> Option<JoinHandle<()>> {
+    let config = config?;
+    Some(spawn(move || {
+        let interval = Duration::from_secs_f64(config.interval);
+        loop {
+            sleep(interval);
+            if let Err(e) = prune_map(map_db.lock().unwrap().deref(), &config) {
+                warn!("Failed to prune map: {}", e);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_grids_to_prune_should_keep_recent_grids_of_not_pinned_segments() {
+        let make_grid = |grid_id, segment_id, last_seen| GridRetentionInfo { grid_id, segment_id, last_seen, change_id: grid_id };
+        let grids = vec![
+            make_grid(1, 1, 100),
+            make_grid(2, 1, 300),
+            make_grid(3, 1, 200),
+            make_grid(4, 4, 10),
+            make_grid(5, 4, 20),
+            make_grid(6, 6, 0),
+        ];
+        let config = MapRetentionConfig {
+            interval: 1.0,
+            max_grids_per_segment: Some(2),
+            max_age: Some(250.0),
+            pinned_segments: vec![6],
+            vacuum: false,
+        };
+        let pinned = config.pinned_segments.iter().cloned().collect();
+        assert_eq!(select_grids_to_prune(&grids, &pinned, &config, 400), vec![1, 4, 5]);
+        assert_eq!(select_grids_to_prune(&grids, &pinned, &MapRetentionConfig { max_age: None, ..config.clone() }, 400), vec![1]);
+    }
+}
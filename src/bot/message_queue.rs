@@ -0,0 +1,212 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use serde::Deserialize;
+
+use crate::bot::capabilities::{Capability, get_required_capability};
+use crate::bot::protocol::Message;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct MessageQueueConfig {
+    pub max_size: Option<usize>,
+    pub deduplicate_kinds: Vec<String>,
+    pub coalesce_repeated: bool,
+}
+
+pub struct MessageQueue {
+    messages: VecDeque<Message>,
+    dropped: u64,
+    deduplicated: u64,
+    // Messages requiring a capability are sent only to clients advertised it with Hello
+    capabilities: BTreeSet<Capability>,
+    config: MessageQueueConfig,
+    subscribers: Vec<Sender<()>>,
+}
+
+impl MessageQueue {
+    pub fn new(config: MessageQueueConfig) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            dropped: 0,
+            deduplicated: 0,
+            capabilities: BTreeSet::new(),
+            config,
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn deduplicated(&self) -> u64 {
+        self.deduplicated
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: BTreeSet<Capability>) {
+        self.messages.retain(|v| is_supported(v, &capabilities));
+        self.capabilities = capabilities;
+    }
+
+    pub fn is_supported(&self, message: &Message) -> bool {
+        is_supported(message, &self.capabilities)
+    }
+
+    pub fn is_repeated(&self, message: &Message) -> bool {
+        self.config.coalesce_repeated && self.messages.back() == Some(message)
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<()> {
+        let (sender, receiver) = channel(0);
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn push_back(&mut self, message: Message) -> bool {
+        if !self.is_supported(&message) || self.is_repeated(&message) {
+            return false;
+        }
+        if let Some(key) = get_deduplication_key(&message) {
+            if self.config.deduplicate_kinds.iter().any(|v| v == key.kind) {
+                let len = self.messages.len();
+                self.messages.retain(|v| get_deduplication_key(v) != Some(key));
+                self.deduplicated += (len - self.messages.len()) as u64;
+            }
+        }
+        if let Some(max_size) = self.config.max_size {
+            while self.messages.len() >= max_size.max(1) {
+                self.messages.pop_front();
+                self.dropped += 1;
+            }
+        }
+        self.messages.push_back(message);
+        self.notify();
+        true
+    }
+
+    pub fn pop_front(&mut self) -> Option<Message> {
+        self.messages.pop_front()
+    }
+
+    pub fn drain(&mut self) -> Vec<Message> {
+        self.messages.drain(..).collect()
+    }
+
+    fn notify(&mut self) {
+        self.subscribers.retain(|v| !v.is_closed());
+        for subscriber in self.subscribers.iter_mut() {
+            // Full channel already has a pending notification
+            let _ = subscriber.try_send(());
+        }
+    }
+}
+
+fn is_supported(message: &Message, capabilities: &BTreeSet<Capability>) -> bool {
+    get_required_capability(message).map(|v| capabilities.contains(&v)).unwrap_or(true)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct DeduplicationKey<'a> {
+    widget: bool,
+    id: i32,
+    kind: &'a str,
+}
+
+fn get_deduplication_key(message: &Message) -> Option<DeduplicationKey> {
+    match message {
+        Message::WidgetMessage { sender, kind, .. } => Some(DeduplicationKey { widget: true, id: *sender, kind: kind.as_str() }),
+        Message::UIMessage { id, kind, .. } => Some(DeduplicationKey { widget: false, id: *id, kind: kind.as_str() }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{FutureExt, StreamExt};
+
+    use crate::bot::protocol::Value;
+
+    use super::*;
+
+    fn make_click(sender: i32, x: i32) -> Message {
+        Message::WidgetMessage { sender, kind: String::from("click"), arguments: vec![Value::from(x)] }
+    }
+
+    #[test]
+    fn push_back_should_keep_only_latest_message_of_deduplicated_kind() {
+        let mut queue = MessageQueue::new(MessageQueueConfig {
+            max_size: None,
+            deduplicate_kinds: vec![String::from("click")],
+            coalesce_repeated: false,
+        });
+        assert!(queue.push_back(make_click(1, 1)));
+        assert!(queue.push_back(Message::GetSessionData));
+        assert!(queue.push_back(make_click(2, 2)));
+        assert!(queue.push_back(make_click(1, 3)));
+        assert_eq!(queue.deduplicated(), 1);
+        assert_eq!(queue.drain(), vec![Message::GetSessionData, make_click(2, 2), make_click(1, 3)]);
+    }
+
+    #[test]
+    fn push_back_should_coalesce_repeated_messages() {
+        let mut queue = MessageQueue::new(MessageQueueConfig {
+            max_size: None,
+            deduplicate_kinds: Vec::new(),
+            coalesce_repeated: true,
+        });
+        assert!(queue.push_back(make_click(1, 1)));
+        assert!(!queue.push_back(make_click(1, 1)));
+        assert!(queue.push_back(Message::GetSessionData));
+        assert!(queue.push_back(make_click(1, 1)));
+        assert_eq!(queue.len(), 3);
+    }
+
+    #[test]
+    fn push_back_should_skip_messages_without_negotiated_capability() {
+        let make_alert = |object_id| Message::Alert { object_id, name: String::from("gfx/kritter/bear/bear"), distance: 100.0 };
+        let mut queue = MessageQueue::new(MessageQueueConfig::default());
+        assert!(!queue.push_back(make_alert(1)));
+        queue.set_capabilities(vec![Capability::Alerts].into_iter().collect());
+        assert!(queue.push_back(make_alert(1)));
+        queue.set_capabilities(BTreeSet::new());
+        assert_eq!(queue.len(), 0);
+        assert!(!queue.push_back(make_alert(2)));
+        assert!(queue.push_back(make_click(1, 1)));
+        queue.set_capabilities(vec![Capability::Alerts].into_iter().collect());
+        assert!(queue.push_back(make_alert(3)));
+        assert_eq!(queue.drain(), vec![make_click(1, 1), make_alert(3)]);
+    }
+
+    #[test]
+    fn push_back_should_drop_oldest_messages_exceeding_max_size() {
+        let mut queue = MessageQueue::new(MessageQueueConfig {
+            max_size: Some(2),
+            deduplicate_kinds: Vec::new(),
+            coalesce_repeated: false,
+        });
+        for x in 0..4 {
+            assert!(queue.push_back(make_click(1, x)));
+        }
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(queue.pop_front(), Some(make_click(1, 2)));
+        assert_eq!(queue.pop_front(), Some(make_click(1, 3)));
+        assert_eq!(queue.pop_front(), None);
+    }
+
+    #[test]
+    fn push_back_should_notify_subscribers_once_until_received() {
+        let mut queue = MessageQueue::new(MessageQueueConfig::default());
+        let mut receiver = queue.subscribe();
+        assert_eq!(receiver.next().now_or_never(), None);
+        assert!(queue.push_back(make_click(1, 1)));
+        assert!(queue.push_back(make_click(1, 2)));
+        assert_eq!(receiver.next().now_or_never(), Some(Some(())));
+        assert_eq!(receiver.next().now_or_never(), None);
+        assert!(queue.push_back(make_click(1, 3)));
+        assert_eq!(receiver.next().now_or_never(), Some(Some(())));
+    }
+}
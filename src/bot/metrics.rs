@@ -0,0 +1,133 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const PATH_FINDER_ITERATIONS_BUCKETS: &[f64] = &[100.0, 1000.0, 10000.0, 100000.0, 1000000.0, 10000000.0];
+const PATH_FINDER_DURATION_BUCKETS: &[f64] = &[0.001, 0.01, 0.1, 1.0, 10.0, 60.0];
+
+pub struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bucket {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn write(&self, name: &str, help: &str, output: &mut String) {
+        write_header(name, help, "histogram", output);
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter()) {
+            writeln!(output, "{}_bucket{{le=\"{}\"}} {}", name, bucket, count).unwrap();
+        }
+        writeln!(output, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count).unwrap();
+        writeln!(output, "{}_sum {}", name, self.sum).unwrap();
+        writeln!(output, "{}_count {}", name, self.count).unwrap();
+    }
+}
+
+pub struct Metrics {
+    path_finder_iterations: Mutex<Histogram>,
+    path_finder_duration: Mutex<Histogram>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            path_finder_iterations: Mutex::new(Histogram::new(PATH_FINDER_ITERATIONS_BUCKETS)),
+            path_finder_duration: Mutex::new(Histogram::new(PATH_FINDER_DURATION_BUCKETS)),
+        }
+    }
+
+    pub fn add_path_finder_iterations(&self, iterations: usize) {
+        self.path_finder_iterations.lock().unwrap().observe(iterations as f64);
+    }
+
+    pub fn add_path_finder_duration(&self, duration: Duration) {
+        self.path_finder_duration.lock().unwrap().observe(duration.as_secs_f64());
+    }
+
+    pub fn write(&self, output: &mut String) {
+        self.path_finder_iterations.lock().unwrap().write(
+            "hafen_bot_path_finder_iterations",
+            "Number of A* iterations per path search",
+            output,
+        );
+        self.path_finder_duration.lock().unwrap().write(
+            "hafen_bot_path_finder_duration_seconds",
+            "Duration of path search",
+            output,
+        );
+    }
+}
+
+pub fn write_session_values<T: std::fmt::Display>(name: &str, help: &str, kind: &str, values: &[(i64, T)],
+                                                  output: &mut String) {
+    write_header(name, help, kind, output);
+    for (session_id, value) in values.iter() {
+        writeln!(output, "{}{{session=\"{}\"}} {}", name, session_id, value).unwrap();
+    }
+}
+
+pub fn write_value<T: std::fmt::Display>(name: &str, help: &str, kind: &str, value: T, output: &mut String) {
+    write_header(name, help, kind, output);
+    writeln!(output, "{} {}", name, value).unwrap();
+}
+
+fn write_header(name: &str, help: &str, kind: &str, output: &mut String) {
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} {}", name, kind).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_should_be_written_in_prometheus_text_format() {
+        let metrics = Metrics::new();
+        metrics.add_path_finder_iterations(50);
+        metrics.add_path_finder_iterations(5000);
+        metrics.add_path_finder_duration(Duration::from_millis(20));
+        let mut output = String::new();
+        metrics.write(&mut output);
+        write_session_values("hafen_bot_session_updates_total", "Processed updates", "counter",
+                             &[(1, 10u64), (2, 3u64)], &mut output);
+        write_value("hafen_bot_map_db_cache_hits_total", "Map db cache hits", "counter", 7u64, &mut output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(&lines[0..11], &[
+            "# HELP hafen_bot_path_finder_iterations Number of A* iterations per path search",
+            "# TYPE hafen_bot_path_finder_iterations histogram",
+            "hafen_bot_path_finder_iterations_bucket{le=\"100\"} 1",
+            "hafen_bot_path_finder_iterations_bucket{le=\"1000\"} 1",
+            "hafen_bot_path_finder_iterations_bucket{le=\"10000\"} 2",
+            "hafen_bot_path_finder_iterations_bucket{le=\"100000\"} 2",
+            "hafen_bot_path_finder_iterations_bucket{le=\"1000000\"} 2",
+            "hafen_bot_path_finder_iterations_bucket{le=\"10000000\"} 2",
+            "hafen_bot_path_finder_iterations_bucket{le=\"+Inf\"} 2",
+            "hafen_bot_path_finder_iterations_sum 5050",
+            "hafen_bot_path_finder_iterations_count 2",
+        ]);
+        assert!(lines.contains(&"hafen_bot_path_finder_duration_seconds_bucket{le=\"0.01\"} 0"));
+        assert!(lines.contains(&"hafen_bot_path_finder_duration_seconds_bucket{le=\"0.1\"} 1"));
+        assert!(lines.contains(&"hafen_bot_session_updates_total{session=\"2\"} 3"));
+        assert_eq!(lines.last(), Some(&"hafen_bot_map_db_cache_hits_total 7"));
+    }
+}
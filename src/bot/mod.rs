@@ -1,4 +1,4 @@
-pub use crate::bot::server::{read_config, run_server, ServerConfig};
+pub use crate::bot::server::{read_config, run_server, ServerConfig, Shutdown};
 
 mod session;
 mod protocol;
@@ -12,10 +12,74 @@ mod clusterization;
 mod player;
 mod objects;
 mod stuck_detector;
+#[macro_use]
+mod task_log;
 mod tasks;
 mod process;
 mod visualization;
 mod scene;
 mod map_db;
+mod map_db_cache;
 mod sqlite_map_db;
 mod actions;
+mod human_control;
+mod danger_zones;
+mod session_stats;
+mod cooldowns;
+mod map_replication;
+mod map_retention;
+mod rate_limiter;
+mod grids_of_interest;
+pub mod coords;
+pub mod sim;
+mod anchors;
+mod area_objects;
+mod reachability;
+mod navigator;
+mod map_export;
+mod map_import;
+mod websocket;
+mod stuck_tiles;
+mod obstacles;
+mod map_query;
+mod metrics;
+mod offscreen;
+mod exploration_claims;
+mod task_scheduler;
+mod macros;
+mod geometry;
+mod stuck_recovery;
+mod alerting;
+mod message_queue;
+mod breadcrumbs;
+mod zones;
+mod capabilities;
+mod speed_meter;
+mod leg_timer;
+mod update_journal;
+mod auth;
+mod traversal;
+mod chat_commands;
+mod avoidance;
+mod texture_pool;
+mod lru_cache;
+mod cancel_tokens;
+mod day_time;
+mod tile_profiles;
+mod roads;
+mod containers;
+mod session_diff;
+mod area_cache;
+mod destination;
+mod bookmarks;
+mod ghost_objects;
+mod schedule;
+mod fields;
+#[cfg(feature = "postgres_map_db")]
+mod postgres_map_db;
+#[cfg(feature = "fault_injection")]
+mod fault_injection;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "bench")]
+pub mod bench;
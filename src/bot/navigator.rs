@@ -0,0 +1,340 @@
+use std::collections::{BinaryHeap, BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::bot::area_cache::{AreaCache, GridAreas};
+use crate::bot::map::{GRID_SIZE, grid_pos_to_tile_pos, Map, tile_pos_to_grid_pos, TileSet};
+use crate::bot::math::as_score;
+use crate::bot::reachability::{find_root, get_allowed_tile_ids, get_relative_tile_pos, get_tile_index, NEIGHBOURS, SegmentTiles};
+use crate::bot::vec2::{Vec2f, Vec2i};
+
+pub struct Navigator {
+    segments: Mutex<BTreeMap<(i64, Vec<i32>), Areas>>,
+    area_cache: Arc<AreaCache>,
+}
+
+impl Navigator {
+    pub fn new(area_cache: Arc<AreaCache>) -> Self {
+        Self { segments: Mutex::new(BTreeMap::new()), area_cache }
+    }
+
+    pub fn find_corridor(&self, map: &Map, segment_id: i64, src_tile_pos: Vec2i, dst_tile_pos: Vec2i,
+                         allowed_tiles: &impl TileSet) -> Option<BTreeSet<Vec2i>> {
+        self.with_areas(map, segment_id, allowed_tiles, |areas| areas.find_corridor(src_tile_pos, dst_tile_pos))
+    }
+
+    pub fn get_path_costs(&self, map: &Map, segment_id: i64, src_tile_pos: Vec2i, dst_tile_positions: &[Vec2i],
+                          allowed_tiles: &impl TileSet) -> Vec<Option<f64>> {
+        self.with_areas(map, segment_id, allowed_tiles, |areas| areas.get_path_costs(src_tile_pos, dst_tile_positions))
+    }
+
+    pub fn get_components(&self, map: &Map, segment_id: i64, allowed_tiles: &impl TileSet) -> Vec<Vec<Vec2i>> {
+        self.with_areas(map, segment_id, allowed_tiles, |areas| areas.get_components())
+    }
+
+    fn with_areas<R, F: FnOnce(&Areas) -> R>(&self, map: &Map, segment_id: i64, allowed_tiles: &impl TileSet, f: F) -> R {
+        let key = (segment_id, get_allowed_tile_ids(map, allowed_tiles));
+        let fingerprint = map.get_segment_fingerprint(segment_id);
+        let mut segments = self.segments.lock().unwrap();
+        segments.retain(|(id, _), v| *id != segment_id || v.fingerprint == fingerprint);
+        let area_cache = &self.area_cache;
+        let areas = segments.entry(key)
+            .or_insert_with(|| {
+                let areas = make_areas(map, segment_id, fingerprint, allowed_tiles, area_cache);
+                debug!("Navigator: built {} areas for segment {}", areas.len(), segment_id);
+                areas
+            });
+        f(areas)
+    }
+}
+
+struct Area {
+    grid_pos: Vec2i,
+    center: Vec2f,
+}
+
+pub struct Areas {
+    fingerprint: Vec<(Vec2i, i64, i64)>,
+    labels: BTreeMap<Vec2i, Vec<usize>>,
+    areas: Vec<Area>,
+    edges: Vec<BTreeMap<usize, f64>>,
+}
+
+impl Areas {
+    pub fn len(&self) -> usize {
+        self.areas.len() - 1
+    }
+
+    pub fn find_corridor(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i) -> Option<BTreeSet<Vec2i>> {
+        let dst_label = match self.get_label(dst_tile_pos) {
+            Some(0) | None => return None,
+            Some(v) => v,
+        };
+        let mut costs: BTreeMap<usize, f64> = BTreeMap::new();
+        let mut backtrack: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut ordered = BinaryHeap::new();
+        for tile_pos in std::iter::once(src_tile_pos).chain(NEIGHBOURS.iter().map(|v| src_tile_pos + *v)) {
+            match self.get_label(tile_pos) {
+                Some(0) => (),
+                Some(v) => if !costs.contains_key(&v) {
+                    costs.insert(v, 0.0);
+                    ordered.push((-as_score(self.get_distance(v, dst_label)), v));
+                },
+                None => return None,
+            }
+        }
+        while let Some((_, label)) = ordered.pop() {
+            if label == dst_label {
+                let mut result = BTreeSet::new();
+                let mut current = label;
+                result.insert(self.areas[current].grid_pos);
+                while let Some(&prev) = backtrack.get(&current) {
+                    result.insert(self.areas[prev].grid_pos);
+                    current = prev;
+                }
+                return Some(result);
+            }
+            for (&next, &distance) in self.edges[label].iter() {
+                let next_cost = costs[&label] + distance;
+                if next_cost < *costs.get(&next).unwrap_or(&std::f64::MAX) {
+                    costs.insert(next, next_cost);
+                    backtrack.insert(next, label);
+                    ordered.push((-as_score(next_cost + self.get_distance(next, dst_label)), next));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn get_path_costs(&self, src_tile_pos: Vec2i, dst_tile_positions: &[Vec2i]) -> Vec<Option<f64>> {
+        let mut costs: BTreeMap<usize, f64> = BTreeMap::new();
+        let mut ordered = BinaryHeap::new();
+        for tile_pos in std::iter::once(src_tile_pos).chain(NEIGHBOURS.iter().map(|v| src_tile_pos + *v)) {
+            if let Some(label) = self.get_label(tile_pos).filter(|v| *v != 0) {
+                let cost = src_tile_pos.center().distance(self.areas[label].center);
+                if cost < *costs.get(&label).unwrap_or(&std::f64::MAX) {
+                    costs.insert(label, cost);
+                    ordered.push((-as_score(cost), label));
+                }
+            }
+        }
+        while let Some((score, label)) = ordered.pop() {
+            if -score > as_score(costs[&label]) {
+                continue;
+            }
+            for (&next, &distance) in self.edges[label].iter() {
+                let next_cost = costs[&label] + distance;
+                if next_cost < *costs.get(&next).unwrap_or(&std::f64::MAX) {
+                    costs.insert(next, next_cost);
+                    ordered.push((-as_score(next_cost), next));
+                }
+            }
+        }
+        dst_tile_positions.iter()
+            .map(|&tile_pos| {
+                self.get_label(tile_pos)
+                    .and_then(|label| costs.get(&label).map(|cost| (label, *cost)))
+                    .map(|(label, cost)| cost + self.areas[label].center.distance(tile_pos.center()))
+            })
+            .collect()
+    }
+
+    fn get_label(&self, tile_pos: Vec2i) -> Option<usize> {
+        let grid_pos = tile_pos_to_grid_pos(tile_pos);
+        self.labels.get(&grid_pos).map(|v| v[get_tile_index(tile_pos - grid_pos_to_tile_pos(grid_pos))])
+    }
+
+    // Groups tiles of areas connected across grid borders, each group is ordered by grid and tile index
+    pub fn get_components(&self) -> Vec<Vec<Vec2i>> {
+        let mut parents: Vec<usize> = (0..self.areas.len()).collect();
+        for (label, edges) in self.edges.iter().enumerate() {
+            for &next in edges.keys() {
+                let (a, b) = (find_root(&mut parents, label), find_root(&mut parents, next));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+        let mut components: BTreeMap<usize, Vec<Vec2i>> = BTreeMap::new();
+        for (grid_pos, labels) in self.labels.iter() {
+            for (index, &label) in labels.iter().enumerate() {
+                if label != 0 {
+                    let root = find_root(&mut parents, label);
+                    components.entry(root).or_insert_with(Vec::new)
+                        .push(grid_pos_to_tile_pos(*grid_pos) + get_relative_tile_pos(index));
+                }
+            }
+        }
+        components.into_iter().map(|(_, v)| v).collect()
+    }
+
+    fn get_distance(&self, src: usize, dst: usize) -> f64 {
+        self.areas[src].center.distance(self.areas[dst].center)
+    }
+}
+
+pub fn make_areas(map: &Map, segment_id: i64, fingerprint: Vec<(Vec2i, i64, i64)>, allowed_tiles: &impl TileSet,
+                  area_cache: &AreaCache) -> Areas {
+    let tiles = SegmentTiles::new(map, segment_id, allowed_tiles);
+    let allowed_tile_ids = get_allowed_tile_ids(map, allowed_tiles);
+    let mut labels = BTreeMap::new();
+    let mut areas = vec![Area { grid_pos: Vec2i::zero(), center: Vec2f::zero() }];
+    let mut grids = Vec::new();
+    for grid in map.iter_segment_grids(segment_id) {
+        let grid_areas = area_cache.get_or_make(grid.id, grid.revision, &allowed_tile_ids, || make_grid_areas(&tiles, grid.position));
+        let offset = areas.len() - 1;
+        labels.insert(grid.position, grid_areas.labels.iter().map(|&v| if v == 0 { 0 } else { v + offset }).collect::<Vec<_>>());
+        areas.extend(grid_areas.centers.iter().map(|&center| Area { grid_pos: grid.position, center }));
+        grids.push(grid.position);
+    }
+    let mut edges = vec![BTreeMap::new(); areas.len()];
+    let get_label = |tile_pos: Vec2i| {
+        let grid_pos = tile_pos_to_grid_pos(tile_pos);
+        labels.get(&grid_pos).map(|v| v[get_tile_index(tile_pos - grid_pos_to_tile_pos(grid_pos))]).unwrap_or(0)
+    };
+    for &grid_pos in grids.iter() {
+        for index in get_border_indices() {
+            let tile_pos = grid_pos_to_tile_pos(grid_pos) + get_relative_tile_pos(index);
+            let label = get_label(tile_pos);
+            if label == 0 {
+                continue;
+            }
+            for &shift in NEIGHBOURS.iter() {
+                let next_tile_pos = tile_pos + shift;
+                let next_grid_pos = tile_pos_to_grid_pos(next_tile_pos);
+                if next_grid_pos == grid_pos || !tiles.contains_grid(next_grid_pos) || !tiles.is_valid_move(tile_pos, shift) {
+                    continue;
+                }
+                let next_label = get_label(next_tile_pos);
+                if next_label != 0 {
+                    let distance = areas[label].center.distance(areas[next_label].center);
+                    edges[label].insert(next_label, distance);
+                }
+            }
+        }
+    }
+    Areas { fingerprint, labels, areas, edges }
+}
+
+fn make_grid_areas<T: TileSet>(tiles: &SegmentTiles<T>, grid_pos: Vec2i) -> GridAreas {
+    let mut labels = vec![0; (GRID_SIZE * GRID_SIZE) as usize];
+    let mut centers = Vec::new();
+    let mut queue = VecDeque::new();
+    for index in 0..labels.len() {
+        let tile_pos = grid_pos_to_tile_pos(grid_pos) + get_relative_tile_pos(index);
+        if labels[index] != 0 || !tiles.is_enterable(tile_pos) {
+            continue;
+        }
+        let label = centers.len() + 1;
+        let mut sum = Vec2f::zero();
+        let mut size = 0;
+        labels[index] = label;
+        queue.push_back(tile_pos);
+        while let Some(tile_pos) = queue.pop_front() {
+            sum = sum + tile_pos.center();
+            size += 1;
+            for &shift in NEIGHBOURS.iter() {
+                let next_tile_pos = tile_pos + shift;
+                if tile_pos_to_grid_pos(next_tile_pos) != grid_pos || !tiles.is_valid_move(tile_pos, shift) {
+                    continue;
+                }
+                let next_index = get_tile_index(next_tile_pos - grid_pos_to_tile_pos(grid_pos));
+                if labels[next_index] != 0 {
+                    continue;
+                }
+                labels[next_index] = label;
+                queue.push_back(next_tile_pos);
+            }
+        }
+        centers.push(sum / size as f64);
+    }
+    GridAreas { labels, centers }
+}
+
+
+fn get_border_indices() -> impl Iterator<Item=usize> {
+    (0..(GRID_SIZE * GRID_SIZE) as usize)
+        .filter(|&index| {
+            let position = get_relative_tile_pos(index);
+            position.x() == 0 || position.y() == 0 || position.x() == GRID_SIZE - 1 || position.y() == GRID_SIZE - 1
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rusqlite::Connection;
+
+    use crate::bot::map::{Grid, GridNeighbour, Tile};
+    use crate::bot::sqlite_map_db::SqliteMapDb;
+    use crate::bot::world::BTreeMapTileWeights;
+
+    use super::*;
+
+    fn make_grid(id: i64, position: Vec2i, is_wall: impl Fn(i32, i32) -> bool) -> Grid {
+        Grid {
+            id,
+            revision: 1,
+            segment_id: 1,
+            position,
+            heights: Vec::new(),
+            tiles: (0..GRID_SIZE * GRID_SIZE)
+                .map(|index| if is_wall(index % GRID_SIZE, index / GRID_SIZE) { 2 } else { 1 })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn find_corridor_should_go_around_wall_through_neighbour_grids() {
+        let mut map = Map::new(Arc::new(Mutex::new(SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default()))), 20.0);
+        map.set_tile(Tile { id: 1, version: 1, name: String::from("grass"), color: 0 });
+        map.set_tile(Tile { id: 2, version: 1, name: String::from("water"), color: 0 });
+        map.add_grid(make_grid(1, Vec2i::new(0, 0), |x, _| x == 50), Vec::new());
+        map.add_grid(make_grid(2, Vec2i::new(0, 1), |_, _| false), vec![GridNeighbour { id: 1, offset: Vec2i::new(0, -1) }]);
+        map.add_grid(make_grid(3, Vec2i::new(0, -1), |_, _| true), vec![GridNeighbour { id: 1, offset: Vec2i::new(0, 1) }]);
+        let weights: BTreeMap<i32, f64> = vec![(1, 1.0)].into_iter().collect();
+        let areas = make_areas(&map, 1, Vec::new(), &BTreeMapTileWeights(&weights), &AreaCache::new());
+        assert_eq!(areas.len(), 3);
+        assert_eq!(
+            areas.find_corridor(Vec2i::new(10, 10), Vec2i::new(90, 10)),
+            Some(vec![Vec2i::new(0, 0), Vec2i::new(0, 1)].into_iter().collect())
+        );
+        assert_eq!(
+            areas.find_corridor(Vec2i::new(10, 10), Vec2i::new(20, 10)),
+            Some(vec![Vec2i::new(0, 0)].into_iter().collect())
+        );
+        assert_eq!(areas.find_corridor(Vec2i::new(10, 10), Vec2i::new(10, -10)), None);
+        assert_eq!(areas.find_corridor(Vec2i::new(10, 10), Vec2i::new(10, 500)), None);
+    }
+
+    #[test]
+    fn get_path_costs_should_follow_area_graph_around_wall() {
+        let mut map = Map::new(Arc::new(Mutex::new(SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default()))), 20.0);
+        map.set_tile(Tile { id: 1, version: 1, name: String::from("grass"), color: 0 });
+        map.set_tile(Tile { id: 2, version: 1, name: String::from("water"), color: 0 });
+        map.add_grid(make_grid(1, Vec2i::new(0, 0), |x, _| x == 50), Vec::new());
+        map.add_grid(make_grid(2, Vec2i::new(0, 1), |_, _| false), vec![GridNeighbour { id: 1, offset: Vec2i::new(0, -1) }]);
+        let weights: BTreeMap<i32, f64> = vec![(1, 1.0)].into_iter().collect();
+        let areas = make_areas(&map, 1, Vec::new(), &BTreeMapTileWeights(&weights), &AreaCache::new());
+        let costs = areas.get_path_costs(Vec2i::new(10, 10), &[Vec2i::new(20, 10), Vec2i::new(90, 10), Vec2i::new(10, 500)]);
+        assert!(costs[0].unwrap() < costs[1].unwrap());
+        assert!(costs[1].unwrap() > Vec2i::new(10, 10).center().distance(Vec2i::new(90, 10).center()) + 50.0);
+        assert_eq!(costs[2], None);
+    }
+
+    #[test]
+    fn get_components_should_merge_areas_connected_through_neighbour_grids() {
+        let mut map = Map::new(Arc::new(Mutex::new(SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default()))), 20.0);
+        map.set_tile(Tile { id: 1, version: 1, name: String::from("grass"), color: 0 });
+        map.set_tile(Tile { id: 2, version: 1, name: String::from("water"), color: 0 });
+        map.add_grid(make_grid(1, Vec2i::new(0, 0), |x, y| x == 50 || y < 90), Vec::new());
+        map.add_grid(make_grid(2, Vec2i::new(0, 1), |x, y| y > 10 || x < 10 || x > 90), vec![GridNeighbour { id: 1, offset: Vec2i::new(0, -1) }]);
+        let weights: BTreeMap<i32, f64> = vec![(1, 1.0)].into_iter().collect();
+        let areas = make_areas(&map, 1, Vec::new(), &BTreeMapTileWeights(&weights), &AreaCache::new());
+        assert_eq!(areas.len(), 3);
+        let components = areas.get_components();
+        assert_eq!(components.len(), 1);
+        assert!(components[0].contains(&Vec2i::new(20, 95)));
+        assert!(components[0].contains(&Vec2i::new(80, 95)));
+        assert!(components[0].contains(&Vec2i::new(50, 105)));
+        assert!(!components[0].contains(&Vec2i::new(50, 95)));
+    }
+}
@@ -2,11 +2,26 @@ use std::collections::{BTreeMap, VecDeque};
 
 use serde::{Deserialize, Serialize};
 
-use crate::bot::vec2::Vec2f;
+use crate::bot::map::pos_to_grid_pos;
+use crate::bot::vec2::{Vec2f, Vec2i};
 
+#[derive(Clone, Deserialize)]
+pub struct PersistentObjectsConfig {
+    pub names: Vec<String>,
+    pub remove_distance: f64,
+}
+
+impl PersistentObjectsConfig {
+    pub fn is_persistent(&self, name: &str) -> bool {
+        self.names.iter().any(|v| name.starts_with(v.as_str()))
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct Objects {
     objects: BTreeMap<i64, VecDeque<Object>>,
     objects_by_name: BTreeMap<String, i64>,
+    last_seen: BTreeMap<i64, i64>,
 }
 
 impl Objects {
@@ -14,6 +29,7 @@ impl Objects {
         Self {
             objects: BTreeMap::new(),
             objects_by_name: BTreeMap::new(),
+            last_seen: BTreeMap::new(),
         }
     }
 
@@ -31,6 +47,7 @@ impl Objects {
                     })
                 })
                 .collect(),
+            last_seen: BTreeMap::new(),
         }
     }
 
@@ -69,6 +86,7 @@ impl Objects {
                 self.objects_by_name.remove(&name);
             }
             self.objects.remove(&object_id);
+            self.last_seen.remove(&object_id);
             return true;
         }
         false
@@ -85,6 +103,38 @@ impl Objects {
         false
     }
 
+    pub fn purge(&mut self, object_id: i64) -> bool {
+        let values = match self.objects.remove(&object_id) {
+            Some(v) => v,
+            None => return false,
+        };
+        for name in values.into_iter().filter_map(|v| v.name) {
+            if self.objects_by_name.get(&name) == Some(&object_id) {
+                self.objects_by_name.remove(&name);
+            }
+        }
+        self.last_seen.remove(&object_id);
+        true
+    }
+
+    pub fn set_last_seen(&mut self, object_id: i64, update: i64) {
+        if self.objects.contains_key(&object_id) {
+            self.last_seen.insert(object_id, update);
+        }
+    }
+
+    pub fn get_last_seen(&self, object_id: i64) -> Option<i64> {
+        self.last_seen.get(&object_id).cloned()
+    }
+
+    pub fn get_ghosts(&self, grid_pos: Vec2i, last_seen_before: i64) -> Vec<i64> {
+        self.iter()
+            .filter(|v| pos_to_grid_pos(v.position) == grid_pos)
+            .filter(|v| self.get_last_seen(v.id).unwrap_or(0) < last_seen_before)
+            .map(|v| v.id)
+            .collect()
+    }
+
     pub fn len(&self) -> usize {
         self.objects.len()
     }
@@ -99,6 +149,12 @@ pub struct ObjectsData {
     objects: Vec<Object>,
 }
 
+impl ObjectsData {
+    pub fn iter(&self) -> impl Iterator<Item=&Object> {
+        self.objects.iter()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Object {
     pub id: i64,
@@ -106,3 +162,14 @@ pub struct Object {
     pub angle: f64,
     pub name: Option<String>,
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectCluster {
+    pub centroid: Vec2f,
+    pub object_ids: Vec<i64>,
+}
+
+#[cfg(test)]
+pub fn make_object(id: i64, x: f64, y: f64, name: Option<&str>) -> Object {
+    Object { id, position: Vec2f::new(x, y), angle: 0.0, name: name.map(String::from) }
+}
@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::bot::geometry::Rect;
+use crate::bot::map::{pos_to_tile_pos, tile_pos_to_pos, TILE_SIZE};
+use crate::bot::objects::{Object, Objects};
+use crate::bot::vec2::{Vec2f, Vec2i};
+
+pub struct Obstacles {
+    revision: u64,
+    objects: BTreeMap<i64, (Vec<Vec2i>, Option<f64>)>,
+    tiles: BTreeMap<Vec2i, usize>,
+    openable_tiles: BTreeMap<Vec2i, BTreeMap<i64, f64>>,
+    radii: Vec<(String, f64)>,
+    open_costs: Vec<(String, f64)>,
+}
+
+impl Obstacles {
+    pub fn new(radii: &HashMap<String, f64>, open_costs: &HashMap<String, f64>) -> Self {
+        Self {
+            revision: 0,
+            objects: BTreeMap::new(),
+            tiles: BTreeMap::new(),
+            openable_tiles: BTreeMap::new(),
+            radii: sort_by_prefix(radii),
+            open_costs: sort_by_prefix(open_costs),
+        }
+    }
+
+    pub fn from_objects(objects: &Objects, radii: &HashMap<String, f64>, open_costs: &HashMap<String, f64>) -> Self {
+        let mut result = Self::new(radii, open_costs);
+        for object in objects.iter() {
+            result.add(object);
+        }
+        result
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn contains(&self, tile_pos: Vec2i) -> bool {
+        self.tiles.contains_key(&tile_pos) || self.openable_tiles.contains_key(&tile_pos)
+    }
+
+    pub fn is_passable(&self, tile_pos: Vec2i) -> bool {
+        !self.tiles.contains_key(&tile_pos)
+    }
+
+    pub fn get_open_cost(&self, tile_pos: Vec2i) -> Option<f64> {
+        if self.tiles.contains_key(&tile_pos) {
+            return None;
+        }
+        self.openable_tiles.get(&tile_pos)
+            .and_then(|objects| objects.values().cloned().max_by(|lhs, rhs| lhs.partial_cmp(rhs).unwrap()))
+    }
+
+    pub fn get_openable_objects(&self, tile_pos: Vec2i) -> impl Iterator<Item=i64> + '_ {
+        self.openable_tiles.get(&tile_pos).into_iter().flat_map(|objects| objects.keys().cloned())
+    }
+
+    pub fn add(&mut self, object: &Object) {
+        let radius = match object.name.as_ref().and_then(|name| find_by_prefix(&self.radii, name)) {
+            Some(v) => v,
+            None => return self.remove(object.id),
+        };
+        let tiles = get_covered_tiles(object.position, radius);
+        let open_cost = object.name.as_ref().and_then(|name| find_by_prefix(&self.open_costs, name));
+        if self.objects.get(&object.id).map(|(t, c)| (t, *c)) == Some((&tiles, open_cost)) {
+            return;
+        }
+        self.remove(object.id);
+        match open_cost {
+            Some(open_cost) => {
+                for tile_pos in tiles.iter() {
+                    self.openable_tiles.entry(*tile_pos).or_insert_with(BTreeMap::new).insert(object.id, open_cost);
+                }
+            }
+            None => {
+                for tile_pos in tiles.iter() {
+                    *self.tiles.entry(*tile_pos).or_insert(0) += 1;
+                }
+            }
+        }
+        self.objects.insert(object.id, (tiles, open_cost));
+        self.revision += 1;
+    }
+
+    pub fn remove(&mut self, object_id: i64) {
+        if let Some((tiles, _)) = self.objects.remove(&object_id) {
+            for tile_pos in tiles.iter() {
+                if let Some(objects) = self.openable_tiles.get_mut(tile_pos) {
+                    if objects.remove(&object_id).is_some() {
+                        if objects.is_empty() {
+                            self.openable_tiles.remove(tile_pos);
+                        }
+                        continue;
+                    }
+                }
+                if let Some(count) = self.tiles.get_mut(tile_pos) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.tiles.remove(tile_pos);
+                    }
+                }
+            }
+            self.revision += 1;
+        }
+    }
+}
+
+fn sort_by_prefix(values: &HashMap<String, f64>) -> Vec<(String, f64)> {
+    let mut result: Vec<(String, f64)> = values.iter().map(|(k, v)| (k.clone(), *v)).collect();
+    result.sort_by(|(lhs, _), (rhs, _)| rhs.len().cmp(&lhs.len()).then_with(|| lhs.cmp(rhs)));
+    result
+}
+
+fn find_by_prefix(values: &Vec<(String, f64)>, name: &String) -> Option<f64> {
+    values.iter()
+        .find(|(prefix, _)| name.starts_with(prefix.as_str()))
+        .map(|(_, value)| *value)
+}
+
+pub fn get_covered_tiles(position: Vec2f, radius: f64) -> Vec<Vec2i> {
+    let min = pos_to_tile_pos(position - Vec2f::new(radius, radius));
+    let max = pos_to_tile_pos(position + Vec2f::new(radius, radius));
+    let mut result = Vec::new();
+    for x in min.x()..=max.x() {
+        for y in min.y()..=max.y() {
+            let tile_min = tile_pos_to_pos(Vec2i::new(x, y));
+            let tile = Rect::new(tile_min, tile_min + Vec2f::new(TILE_SIZE, TILE_SIZE));
+            if tile.closest_point(position).distance(position) < radius {
+                result.push(Vec2i::new(x, y));
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::objects::make_object;
+
+    use super::*;
+
+    #[test]
+    fn obstacles_should_cover_tiles_intersecting_object_radius() {
+        let radii = vec![
+            (String::from("gfx/terobjs/trees/"), 5.0),
+            (String::from("gfx/terobjs/trees/oak"), 12.0),
+        ].into_iter().collect();
+        let mut obstacles = Obstacles::new(&radii, &HashMap::new());
+        obstacles.add(&make_object(1, 16.5, 16.5, Some("gfx/terobjs/trees/spruce")));
+        obstacles.add(&make_object(2, 110.0, 110.0, Some("gfx/terobjs/trees/oak")));
+        obstacles.add(&make_object(3, 220.0, 220.0, Some("gfx/borka/body")));
+        assert_eq!(obstacles.len(), 2);
+        assert!(obstacles.contains(Vec2i::new(1, 1)));
+        assert!(!obstacles.contains(Vec2i::new(0, 1)));
+        assert!(obstacles.contains(Vec2i::new(9, 9)));
+        assert!(obstacles.contains(Vec2i::new(10, 10)));
+        assert!(!obstacles.contains(Vec2i::new(20, 20)));
+        obstacles.remove(2);
+        assert!(!obstacles.contains(Vec2i::new(10, 10)));
+        obstacles.add(&make_object(1, 38.5, 16.5, Some("gfx/terobjs/trees/spruce")));
+        assert!(!obstacles.contains(Vec2i::new(1, 1)));
+        assert!(obstacles.contains(Vec2i::new(3, 1)));
+    }
+
+    #[test]
+    fn obstacles_revision_should_change_only_when_covered_tiles_change() {
+        let radii = vec![(String::from("gfx/terobjs/trees/"), 5.0)].into_iter().collect();
+        let mut obstacles = Obstacles::new(&radii, &HashMap::new());
+        obstacles.add(&make_object(1, 16.5, 16.5, Some("gfx/terobjs/trees/spruce")));
+        assert_eq!(obstacles.revision(), 1);
+        obstacles.add(&make_object(1, 16.5, 16.5, Some("gfx/terobjs/trees/spruce")));
+        obstacles.add(&make_object(2, 110.0, 110.0, Some("gfx/borka/body")));
+        obstacles.add(&make_object(2, 120.0, 110.0, Some("gfx/borka/body")));
+        obstacles.remove(2);
+        assert_eq!(obstacles.revision(), 1);
+        obstacles.add(&make_object(1, 38.5, 16.5, Some("gfx/terobjs/trees/spruce")));
+        assert_eq!(obstacles.revision(), 3);
+        obstacles.remove(1);
+        assert_eq!(obstacles.revision(), 4);
+    }
+
+    #[test]
+    fn obstacles_should_keep_openable_tiles_passable_unless_covered_by_other_obstacle() {
+        let radii = vec![(String::from("gfx/terobjs/arch/palisade"), 6.0)].into_iter().collect();
+        let open_costs = vec![(String::from("gfx/terobjs/arch/palisadegate"), 3.0)].into_iter().collect();
+        let mut obstacles = Obstacles::new(&radii, &open_costs);
+        obstacles.add(&make_object(1, 16.5, 16.5, Some("gfx/terobjs/arch/palisadegate")));
+        assert!(obstacles.contains(Vec2i::new(1, 1)));
+        assert!(obstacles.is_passable(Vec2i::new(1, 1)));
+        assert_eq!(obstacles.get_open_cost(Vec2i::new(1, 1)), Some(3.0));
+        assert_eq!(obstacles.get_openable_objects(Vec2i::new(1, 1)).collect::<Vec<_>>(), vec![1]);
+        obstacles.add(&make_object(2, 20.0, 16.5, Some("gfx/terobjs/arch/palisadeseg")));
+        assert!(!obstacles.is_passable(Vec2i::new(1, 1)));
+        assert_eq!(obstacles.get_open_cost(Vec2i::new(1, 1)), None);
+        obstacles.remove(2);
+        assert!(obstacles.is_passable(Vec2i::new(1, 1)));
+        obstacles.remove(1);
+        assert!(!obstacles.contains(Vec2i::new(1, 1)));
+        assert_eq!(obstacles.get_openable_objects(Vec2i::new(1, 1)).count(), 0);
+    }
+}
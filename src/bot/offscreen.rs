@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use graphics::{DrawState, Graphics, ImageSize, Viewport};
+use graphics::glyph_cache::rusttype::GlyphCache;
+use graphics::types::Color;
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use opengl_graphics::{CreateTexture, Format, TextureOp, TextureSettings, UpdateTexture};
+
+use crate::bot::scene::{SceneImage, SceneTexture};
+
+pub struct OffscreenTexture {
+    image: Arc<RgbaImage>,
+}
+
+impl OffscreenTexture {
+    pub fn new(image: Arc<RgbaImage>) -> Self {
+        Self { image }
+    }
+}
+
+impl ImageSize for OffscreenTexture {
+    fn get_size(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+}
+
+impl SceneTexture for OffscreenTexture {
+    fn with_scene_image<F: FnOnce(&Self)>(image: &SceneImage, f: F) {
+        f(&OffscreenTexture::new(image.image().clone()))
+    }
+}
+
+impl TextureOp<()> for OffscreenTexture {
+    type Error = String;
+}
+
+impl CreateTexture<()> for OffscreenTexture {
+    fn create<S: Into<[u32; 2]>>(_factory: &mut (), _format: Format, memory: &[u8], size: S,
+                                 _settings: &TextureSettings) -> Result<Self, String> {
+        let [width, height] = size.into();
+        RgbaImage::from_raw(width, height, memory.to_vec())
+            .map(|image| Self::new(Arc::new(image)))
+            .ok_or_else(|| format!("Invalid texture memory size {} for {}x{}", memory.len(), width, height))
+    }
+}
+
+impl UpdateTexture<()> for OffscreenTexture {
+    fn update<O: Into<[u32; 2]>, S: Into<[u32; 2]>>(&mut self, _factory: &mut (), _format: Format, memory: &[u8],
+                                                     offset: O, size: S) -> Result<(), String> {
+        let [x, y] = offset.into();
+        let [width, height] = size.into();
+        let update = RgbaImage::from_raw(width, height, memory.to_vec())
+            .ok_or_else(|| format!("Invalid texture memory size {} for {}x{}", memory.len(), width, height))?;
+        let image = Arc::make_mut(&mut self.image);
+        for (dx, dy, pixel) in update.enumerate_pixels() {
+            if x + dx < image.width() && y + dy < image.height() {
+                image.put_pixel(x + dx, y + dy, *pixel);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub type OffscreenGlyphCache = GlyphCache<'static, (), OffscreenTexture>;
+
+pub struct OffscreenGraphics {
+    frame: RgbaImage,
+}
+
+impl OffscreenGraphics {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { frame: RgbaImage::new(width, height) }
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        let (width, height) = self.frame.dimensions();
+        Viewport {
+            rect: [0, 0, width as i32, height as i32],
+            draw_size: [width, height],
+            window_size: [width as f64, height as f64],
+        }
+    }
+
+    pub fn into_frame(self) -> RgbaImage {
+        self.frame
+    }
+
+    fn fill_triangle<F: FnMut([f32; 3]) -> Color>(&mut self, vertices: &[[f32; 2]], mut get_color: F) {
+        let (width, height) = self.frame.dimensions();
+        let points: Vec<[f32; 2]> = vertices.iter()
+            .map(|v| [(v[0] + 1.0) * 0.5 * width as f32, (1.0 - v[1]) * 0.5 * height as f32])
+            .collect();
+        let area = edge(points[0], points[1], points[2]);
+        if area == 0.0 {
+            return;
+        }
+        let min_x = points.iter().map(|v| v[0]).fold(f32::INFINITY, f32::min).max(0.0).floor() as u32;
+        let min_y = points.iter().map(|v| v[1]).fold(f32::INFINITY, f32::min).max(0.0).floor() as u32;
+        let max_x = points.iter().map(|v| v[0]).fold(f32::NEG_INFINITY, f32::max).min(width as f32).ceil() as u32;
+        let max_y = points.iter().map(|v| v[1]).fold(f32::NEG_INFINITY, f32::max).min(height as f32).ceil() as u32;
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let p = [x as f32 + 0.5, y as f32 + 0.5];
+                let weights = [
+                    edge(points[1], points[2], p) / area,
+                    edge(points[2], points[0], p) / area,
+                    edge(points[0], points[1], p) / area,
+                ];
+                if weights.iter().all(|v| *v >= 0.0) {
+                    blend(self.frame.get_pixel_mut(x, y), get_color(weights));
+                }
+            }
+        }
+    }
+}
+
+impl Graphics for OffscreenGraphics {
+    type Texture = OffscreenTexture;
+
+    fn clear_color(&mut self, color: Color) {
+        let value = Rgba([to_u8(color[0]), to_u8(color[1]), to_u8(color[2]), to_u8(color[3])]);
+        for pixel in self.frame.pixels_mut() {
+            *pixel = value;
+        }
+    }
+
+    fn clear_stencil(&mut self, _value: u8) {}
+
+    fn tri_list<F>(&mut self, _draw_state: &DrawState, color: &[f32; 4], mut f: F)
+        where F: FnMut(&mut dyn FnMut(&[[f32; 2]])) {
+        f(&mut |vertices| {
+            for triangle in vertices.chunks_exact(3) {
+                self.fill_triangle(triangle, |_| *color);
+            }
+        });
+    }
+
+    fn tri_list_uv<F>(&mut self, _draw_state: &DrawState, color: &[f32; 4], texture: &OffscreenTexture, mut f: F)
+        where F: FnMut(&mut dyn FnMut(&[[f32; 2]], &[[f32; 2]])) {
+        f(&mut |vertices, uvs| {
+            for (triangle, uvs) in vertices.chunks_exact(3).zip(uvs.chunks_exact(3)) {
+                self.fill_triangle(triangle, |weights| multiply(sample(texture, uvs, weights), *color));
+            }
+        });
+    }
+}
+
+pub fn encode_png(frame: RgbaImage) -> Result<Vec<u8>, String> {
+    let mut result = Vec::new();
+    DynamicImage::ImageRgba8(frame).write_to(&mut result, ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to encode frame: {}", e))?;
+    Ok(result)
+}
+
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+fn sample(texture: &OffscreenTexture, uvs: &[[f32; 2]], weights: [f32; 3]) -> Color {
+    let (width, height) = texture.image.dimensions();
+    let u = uvs[0][0] * weights[0] + uvs[1][0] * weights[1] + uvs[2][0] * weights[2];
+    let v = uvs[0][1] * weights[0] + uvs[1][1] * weights[1] + uvs[2][1] * weights[2];
+    let x = ((u * width as f32) as u32).min(width - 1);
+    let y = ((v * height as f32) as u32).min(height - 1);
+    let Rgba(pixel) = *texture.image.get_pixel(x, y);
+    [pixel[0] as f32 / 255.0, pixel[1] as f32 / 255.0, pixel[2] as f32 / 255.0, pixel[3] as f32 / 255.0]
+}
+
+fn multiply(lhs: Color, rhs: Color) -> Color {
+    [lhs[0] * rhs[0], lhs[1] * rhs[1], lhs[2] * rhs[2], lhs[3] * rhs[3]]
+}
+
+fn blend(target: &mut Rgba<u8>, color: Color) {
+    let alpha = color[3].max(0.0).min(1.0);
+    for i in 0..3 {
+        target.0[i] = to_u8(color[i] * alpha + target.0[i] as f32 / 255.0 * (1.0 - alpha));
+    }
+    target.0[3] = to_u8(alpha + target.0[3] as f32 / 255.0 * (1.0 - alpha));
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use graphics::{clear, Context, Image, Rectangle, Text, Transformed};
+    use graphics::rectangle::square;
+    use image::GenericImageView;
+
+    use super::*;
+
+    #[test]
+    fn offscreen_graphics_should_rasterize_rectangles_and_images() {
+        let mut g = OffscreenGraphics::new(8, 4);
+        let context = Context::new_viewport(g.viewport());
+        clear([0.0, 0.0, 0.0, 1.0], &mut g);
+        Rectangle::new([1.0, 0.0, 0.0, 1.0]).draw([0.0, 0.0, 4.0, 2.0], &context.draw_state, context.transform, &mut g);
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, Rgba([0, 255, 0, 255]));
+        image.put_pixel(1, 0, Rgba([0, 0, 255, 255]));
+        let texture = OffscreenTexture::new(Arc::new(image));
+        Image::new().rect(square(4.0, 2.0, 2.0)).draw(&texture, &context.draw_state, context.transform, &mut g);
+        Rectangle::new([1.0, 1.0, 1.0, 0.5]).draw([6.0, 0.0, 2.0, 1.0], &context.draw_state, context.transform, &mut g);
+        let frame = g.into_frame();
+        assert_eq!(frame.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(frame.get_pixel(3, 1), &Rgba([255, 0, 0, 255]));
+        assert_eq!(frame.get_pixel(4, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(frame.get_pixel(4, 2), &Rgba([0, 255, 0, 255]));
+        assert_eq!(frame.get_pixel(5, 3), &Rgba([0, 0, 255, 255]));
+        assert_eq!(frame.get_pixel(6, 0), &Rgba([128, 128, 128, 255]));
+        let png = encode_png(frame).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        assert_eq!(image::load_from_memory(&png).unwrap().dimensions(), (8, 4));
+    }
+
+    #[test]
+    fn offscreen_glyph_cache_should_render_text() {
+        let mut glyphs = OffscreenGlyphCache::new("fonts/UbuntuMono-R.ttf", (), TextureSettings::new()).unwrap();
+        let mut g = OffscreenGraphics::new(32, 16);
+        let context = Context::new_viewport(g.viewport());
+        clear([0.0, 0.0, 0.0, 1.0], &mut g);
+        Text::new_color([1.0, 1.0, 1.0, 1.0], 10)
+            .draw("W", &mut glyphs, &context.draw_state, context.transform.trans(2.0, 12.0), &mut g)
+            .unwrap();
+        let frame = g.into_frame();
+        assert!(frame.pixels().any(|v| v[0] > 128));
+        assert!(frame.pixels().all(|v| v[0] == v[1] && v[1] == v[2]));
+        assert_eq!(frame.get_pixel(31, 0), &Rgba([0, 0, 0, 255]));
+    }
+}
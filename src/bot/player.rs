@@ -1,11 +1,13 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 
+use crate::bot::day_time::{DayTime, parse_day_time};
 use crate::bot::map::pos_to_grid_pos;
 use crate::bot::protocol::{Event, Update, Value};
-use crate::bot::stuck_detector::StuckDetector;
+use crate::bot::speed_meter::SpeedMeter;
+use crate::bot::stuck_detector::{StuckDetector, StuckDetectorConfig};
 use crate::bot::vec2::{Vec2f, Vec2i};
 use crate::bot::world::World;
 
@@ -14,6 +16,7 @@ pub struct PlayerConfig {
     pub meters: MetersConfig,
     pub equipment: EquipmentConfig,
     pub items: ItemsConfig,
+    pub stuck_detector: StuckDetectorConfig,
 }
 
 #[derive(Clone, Deserialize)]
@@ -33,6 +36,7 @@ pub struct EquipmentConfig {
     pub belt: usize,
 }
 
+#[derive(Clone)]
 pub struct PlayerEquipment<'a> {
     config: &'a EquipmentConfig,
     slots: &'a BTreeMap<usize, Option<i32>>,
@@ -59,12 +63,16 @@ pub struct Player {
     resources: BTreeMap<i32, Resource>,
     stuck_detector: StuckDetector,
     is_stuck: bool,
+    speed_meter: SpeedMeter,
     meters: Meters,
     items: Items,
     stamina: Stamina,
     equipment: Equipment,
     widget_inventories: BTreeMap<i32, BTreeMap<i32, Item>>,
+    inventory_sizes: BTreeMap<i32, Vec2i>,
     hand: Option<Item>,
+    calendar_id: Option<i32>,
+    day_time: Option<DayTime>,
 }
 
 impl Player {
@@ -82,14 +90,18 @@ impl Player {
             widgets: BTreeMap::new(),
             map_grids: Vec::new(),
             resources: BTreeMap::new(),
-            stuck_detector: StuckDetector::new(),
+            stuck_detector: StuckDetector::new(config.stuck_detector.clone()),
             is_stuck: false,
+            speed_meter: SpeedMeter::new(),
             meters: Meters::new(config.meters.clone()),
             items: Items::new(config.items.clone()),
             stamina: Stamina::default(),
             equipment: Equipment::new(config.equipment.clone()),
             widget_inventories: BTreeMap::new(),
+            inventory_sizes: BTreeMap::new(),
             hand: None,
+            calendar_id: None,
+            day_time: None,
         }
     }
 
@@ -129,14 +141,37 @@ impl Player {
         &self.resources
     }
 
+    pub fn preload_resources(&mut self, stored: Vec<Resource>) -> usize {
+        let stored: BTreeMap<i32, Resource> = stored.into_iter().map(|v| (v.id, v)).collect();
+        let mut preloaded = 0;
+        for (id, resource) in stored.into_iter() {
+            if self.resources.contains_key(&id) {
+                continue;
+            }
+            self.meters.update(&resource);
+            self.items.update(&resource);
+            self.resources.insert(id, resource);
+            preloaded += 1;
+        }
+        preloaded
+    }
+
     pub fn is_stuck(&self) -> bool {
         self.is_stuck
     }
 
+    pub fn speed(&self) -> Option<f64> {
+        self.speed_meter.speed()
+    }
+
     pub fn stamina(&self) -> Option<i32> {
         self.stamina.value
     }
 
+    pub fn day_time(&self) -> Option<DayTime> {
+        self.day_time
+    }
+
     pub fn equipment(&self) -> Option<PlayerEquipment> {
         self.equipment.widget_id.map(|_| PlayerEquipment {
             config: &self.equipment.config,
@@ -152,6 +187,28 @@ impl Player {
         &self.hand
     }
 
+    pub fn free_slots(&self) -> Option<usize> {
+        self.inventory_id.and_then(|id| self.inventory_free_slots(id))
+    }
+
+    pub fn inventory_free_slots(&self, inventory_id: i32) -> Option<usize> {
+        match (self.inventory_sizes.get(&inventory_id), self.widget_inventories.get(&inventory_id)) {
+            (Some(size), Some(items)) => Some(count_free_slots(*size, items)),
+            _ => None,
+        }
+    }
+
+    pub fn can_fit(&self, item_size: Vec2i) -> bool {
+        self.inventory_id
+            .and_then(|id| {
+                match (self.inventory_sizes.get(&id), self.widget_inventories.get(&id)) {
+                    (Some(size), Some(items)) => Some(find_free_slot(*size, items, item_size).is_some()),
+                    _ => None,
+                }
+            })
+            .unwrap_or(true)
+    }
+
     pub fn from_player_data(data: PlayerData, config: PlayerConfig) -> Self {
         let belt_inventory_id = data.widgets.iter()
             .find(|v| v.kind == "inv" && Some(v.parent) == data.belt_id)
@@ -187,11 +244,18 @@ impl Player {
                 .filter(|v| v.kind == "inv")
                 .map(|v| (v.id, make_inventory(Some(v.id), &widgets, &items)))
                 .collect(),
+            inventory_sizes: widgets.values()
+                .filter(|v| v.kind == "inv")
+                .filter_map(|v| get_inventory_size(&v.cargs).map(|size| (v.id, size)))
+                .collect(),
+            calendar_id: widgets.values().find(|v| v.kind == "cal").map(|v| v.id),
+            day_time: None,
             widgets,
             map_grids: data.map_grids,
             resources,
-            stuck_detector: StuckDetector::new(),
+            stuck_detector: StuckDetector::new(config.stuck_detector.clone()),
             is_stuck: false,
+            speed_meter: SpeedMeter::new(),
         }
     }
 
@@ -248,6 +312,9 @@ impl Player {
                     "epry" => {
                         self.equipment.widget_id = Some(*id);
                     }
+                    "cal" => {
+                        self.calendar_id = Some(*id);
+                    }
                     "item" => {
                         if Some(*parent) == self.equipment.widget_id {
                             self.equipment.add_item(*id, pargs);
@@ -273,6 +340,9 @@ impl Player {
                             self.belt_inventory_id = Some(*id);
                         }
                         self.widget_inventories.insert(*id, BTreeMap::new());
+                        if let Some(size) = get_inventory_size(cargs) {
+                            self.inventory_sizes.insert(*id, size);
+                        }
                     }
                     _ => (),
                 }
@@ -308,6 +378,31 @@ impl Player {
                     "set" => {
                         self.stamina.update_value(*id, args)
                     }
+                    "astro" => {
+                        if Some(*id) != self.calendar_id {
+                            return false;
+                        }
+                        match parse_day_time(args) {
+                            Some(day_time) => {
+                                if self.day_time.map(|v| v.night) != Some(day_time.night) {
+                                    debug!("Player: night={} at {}", day_time.night, day_time.value);
+                                }
+                                self.day_time = Some(day_time);
+                                true
+                            }
+                            None => false,
+                        }
+                    }
+                    "sz" => {
+                        match (self.inventory_sizes.get_mut(id), get_inventory_size(args)) {
+                            (Some(size), Some(new_size)) => {
+                                debug!("Player: set inventory {} size {:?}", id, new_size);
+                                *size = new_size;
+                                true
+                            }
+                            _ => false,
+                        }
+                    }
                     "tt" => {
                         let items = &self.items;
                         self.hand.as_mut().map(|item| item.id == *id && update_item(args, items, item)).unwrap_or(false)
@@ -334,8 +429,11 @@ impl Player {
                     self.equipment.widget_id = None;
                 } else if Some(*id) == self.belt_inventory_id {
                     self.belt_inventory_id = None;
+                } else if Some(*id) == self.calendar_id {
+                    self.calendar_id = None;
                 }
                 if let Some(widget) = self.widgets.remove(id) {
+                    self.inventory_sizes.remove(&widget.id);
                     if self.hand.as_ref().map(|item| item.id == widget.id).unwrap_or(false) {
                         self.hand = None;
                     } else if self.widget_inventories.remove(&widget.id).is_none() {
@@ -370,8 +468,9 @@ impl Player {
                 if Some(*id) == self.object_id {
                     self.position = None;
                     self.grid_id = None;
-                    self.stuck_detector = StuckDetector::new();
+                    self.stuck_detector.reset();
                     self.is_stuck = false;
+                    self.speed_meter = SpeedMeter::new();
                     debug!("Player: reset");
                     true
                 } else {
@@ -400,8 +499,8 @@ impl Player {
                 self.grid_id = Some(grid.id);
             }
             let now = Instant::now();
-            self.is_stuck = self.stuck_detector.check(object_position, now);
-            self.stuck_detector.update(object_position, now);
+            self.is_stuck = self.stuck_detector.update(object_position, now);
+            self.speed_meter.update(object_position, now);
             if self.is_stuck {
                 debug!("Player is stuck at {:?}", object_position);
             }
@@ -573,6 +672,39 @@ fn add_inventory_item(id: i32, pargs: &Vec<Value>, cargs: &Vec<Value>, inventory
     }
 }
 
+fn get_inventory_size(cargs: &Vec<Value>) -> Option<Vec2i> {
+    match cargs.first() {
+        Some(Value::Coord { value }) => Some(*value),
+        _ => None,
+    }
+}
+
+fn get_occupied_slots(size: Vec2i, items: &BTreeMap<i32, Item>) -> BTreeSet<Vec2i> {
+    items.values()
+        .filter_map(|v| v.position)
+        .filter(|v| 0 <= v.x() && v.x() < size.x() && 0 <= v.y() && v.y() < size.y())
+        .collect()
+}
+
+fn count_free_slots(size: Vec2i, items: &BTreeMap<i32, Item>) -> usize {
+    (size.x() * size.y()).max(0) as usize - get_occupied_slots(size, items).len()
+}
+
+fn find_free_slot(size: Vec2i, items: &BTreeMap<i32, Item>, item_size: Vec2i) -> Option<Vec2i> {
+    let occupied = get_occupied_slots(size, items);
+    for y in 0..=size.y() - item_size.y() {
+        for x in 0..=size.x() - item_size.x() {
+            let fits = (0..item_size.y()).all(|dy| {
+                (0..item_size.x()).all(|dx| !occupied.contains(&Vec2i::new(x + dx, y + dy)))
+            });
+            if fits {
+                return Some(Vec2i::new(x, y));
+            }
+        }
+    }
+    None
+}
+
 fn update_inventory_item(id: i32, args: &Vec<Value>, items: &Items, inventory: &mut BTreeMap<i32, Item>) -> bool {
     if let Some(item) = inventory.get_mut(&id) {
         return update_item(args, items, item);
@@ -628,6 +760,12 @@ pub struct PlayerData {
     items: Vec<Item>,
 }
 
+impl PlayerData {
+    pub fn widgets(&self) -> &[Widget] {
+        &self.widgets
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Widget {
     pub id: i32,
@@ -706,3 +844,35 @@ fn update_item(args: &Vec<Value>, items: &Items, item: &mut Item) -> bool {
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_inventory_item(id: i32, x: i32, y: i32) -> (i32, Item) {
+        (id, Item { id, resource: 1, content: None, position: Some(Vec2i::new(x, y)) })
+    }
+
+    #[test]
+    fn count_free_slots_should_ignore_items_outside_inventory() {
+        let items: BTreeMap<i32, Item> = vec![
+            make_inventory_item(1, 0, 0),
+            make_inventory_item(2, 1, 0),
+            make_inventory_item(3, 5, 5),
+        ].into_iter().collect();
+        assert_eq!(count_free_slots(Vec2i::new(2, 2), &items), 2);
+        assert_eq!(count_free_slots(Vec2i::new(2, 1), &items), 0);
+    }
+
+    #[test]
+    fn find_free_slot_should_return_first_position_where_item_fits() {
+        let items: BTreeMap<i32, Item> = vec![
+            make_inventory_item(1, 0, 0),
+            make_inventory_item(2, 1, 1),
+        ].into_iter().collect();
+        assert_eq!(find_free_slot(Vec2i::new(3, 3), &items, Vec2i::new(1, 1)), Some(Vec2i::new(1, 0)));
+        assert_eq!(find_free_slot(Vec2i::new(3, 3), &items, Vec2i::new(2, 1)), Some(Vec2i::new(1, 0)));
+        assert_eq!(find_free_slot(Vec2i::new(3, 3), &items, Vec2i::new(2, 2)), None);
+        assert_eq!(find_free_slot(Vec2i::new(3, 3), &items, Vec2i::new(4, 1)), None);
+    }
+}
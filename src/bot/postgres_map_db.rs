@@ -0,0 +1,1029 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::DerefMut;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use postgres::{Client, GenericClient, NoTls, Row};
+
+use crate::bot::bookmarks::Bookmark;
+use crate::bot::map::{Grid, grid_pos_to_pos, GridNeighbour, MapObject, pos_to_grid_pos, Tile};
+use crate::bot::map_db::{decode_grid_values, encode_grid_values, get_grid_hash, GRID_FORMAT_BINCODE_ZSTD, GRID_FORMAT_JSON, MapDb,
+                         MapDbCacheStats};
+use crate::bot::map_db_cache::MapDbCache;
+use crate::bot::map_retention::{GridRetentionInfo, MapPruneStats, MapRetentionConfig, select_grids_to_prune};
+use crate::bot::player::Resource;
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::zones::Zone;
+
+const CREATE_DB_QUERY: &'static str = r"
+    CREATE TABLE IF NOT EXISTS tiles (
+        tile_id INTEGER PRIMARY KEY,
+        version INTEGER NOT NULL,
+        name TEXT NOT NULL UNIQUE,
+        color INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS grids (
+        grid_id BIGINT PRIMARY KEY,
+        revision BIGINT NOT NULL,
+        segment_id BIGINT NOT NULL,
+        position_x INTEGER NOT NULL,
+        position_y INTEGER NOT NULL,
+        heights BYTEA NOT NULL,
+        tiles BYTEA NOT NULL,
+        hash BIGINT NOT NULL DEFAULT 0,
+        format BIGINT NOT NULL DEFAULT 0
+    );
+
+    ALTER TABLE grids ADD COLUMN IF NOT EXISTS hash BIGINT NOT NULL DEFAULT 0;
+
+    ALTER TABLE grids ADD COLUMN IF NOT EXISTS format BIGINT NOT NULL DEFAULT 0;
+
+    CREATE INDEX IF NOT EXISTS i_grids_coord
+        ON grids (segment_id, position_x, position_y);
+
+    CREATE INDEX IF NOT EXISTS i_grids_segment
+        ON grids (segment_id);
+
+    CREATE SEQUENCE IF NOT EXISTS grid_change_ids;
+
+    CREATE TABLE IF NOT EXISTS grid_changes (
+        grid_id BIGINT PRIMARY KEY,
+        change_id BIGINT NOT NULL UNIQUE
+    );
+
+    CREATE TABLE IF NOT EXISTS objects (
+        object_id BIGINT PRIMARY KEY,
+        name TEXT NOT NULL,
+        grid_id BIGINT NOT NULL,
+        offset_x DOUBLE PRECISION NOT NULL,
+        offset_y DOUBLE PRECISION NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS i_objects_grid
+        ON objects (grid_id);
+
+    CREATE TABLE IF NOT EXISTS grids_last_seen (
+        grid_id BIGINT PRIMARY KEY,
+        last_seen BIGINT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS i_grids_last_seen
+        ON grids_last_seen (last_seen);
+
+    CREATE TABLE IF NOT EXISTS zones (
+        segment_id BIGINT NOT NULL,
+        name TEXT NOT NULL,
+        points TEXT NOT NULL,
+        PRIMARY KEY (segment_id, name)
+    );
+
+    CREATE TABLE IF NOT EXISTS bookmarks (
+        segment_id BIGINT NOT NULL,
+        name TEXT NOT NULL,
+        x DOUBLE PRECISION NOT NULL,
+        y DOUBLE PRECISION NOT NULL,
+        PRIMARY KEY (segment_id, name)
+    );
+
+    CREATE SEQUENCE IF NOT EXISTS resource_seen_ids;
+
+    CREATE TABLE IF NOT EXISTS resources (
+        name TEXT NOT NULL,
+        version INTEGER NOT NULL,
+        resource_id INTEGER NOT NULL,
+        seen BIGINT NOT NULL,
+        PRIMARY KEY (name, version)
+    );
+";
+
+const LOCK_GRIDS_QUERY: &'static str = r"
+    LOCK TABLE grids IN SHARE ROW EXCLUSIVE MODE
+";
+
+const GET_GRIDS_BY_FORMAT: &'static str = r"
+    SELECT grid_id, heights, tiles
+      FROM grids
+     WHERE format = $1
+";
+
+const SET_GRID_DATA_QUERY: &'static str = r"
+    UPDATE grids
+       SET heights = $2,
+           tiles = $3,
+           format = $4
+     WHERE grid_id = $1
+";
+
+const GET_TILES: &'static str = r"
+    SELECT tile_id, version, name, color
+      FROM tiles
+     ORDER BY tile_id
+";
+
+const GET_GRIDS: &'static str = r"
+    SELECT grid_id, revision, segment_id, position_x, position_y, heights, tiles, format
+      FROM grids
+     ORDER BY grid_id
+";
+
+const GET_GRID_IDS_BY_SEGMENT_ID: &'static str = r"
+    SELECT grid_id
+      FROM grids
+     WHERE segment_id = $1
+";
+
+const INSERT_TILE_QUERY: &'static str = r"
+    INSERT INTO tiles (tile_id, version, name, color)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (tile_id) DO UPDATE SET
+        version = excluded.version,
+        name = excluded.name
+    WHERE tiles.version < excluded.version
+";
+
+const GET_TILE_BY_NAME_QUERY: &'static str = r"
+    SELECT tile_id, version, name, color
+      FROM tiles
+     WHERE name = $1
+";
+
+const INSERT_GRID_QUERY: &'static str = r"
+    INSERT INTO grids (grid_id, revision, segment_id, position_x, position_y, heights, tiles, hash, format)
+    VALUES ($1, 1, $2, $3, $4, $5, $6, $7, $8)
+";
+
+const UPDATE_GRID_QUERY: &'static str = r"
+    UPDATE grids
+       SET revision = revision + 1,
+           heights = $2,
+           tiles = $3,
+           hash = $4,
+           format = $5
+     WHERE grid_id = $1
+       AND hash <> $4
+";
+
+const SET_GRID_REVISION_QUERY: &'static str = r"
+    UPDATE grids
+       SET revision = $2
+     WHERE grid_id = $1
+       AND revision < $2
+";
+
+const GET_GRID_BY_ID: &'static str = r"
+    SELECT grid_id, revision, segment_id, position_x, position_y, heights, tiles, format
+      FROM grids
+     WHERE grid_id = $1
+";
+
+const GET_GRID_REVISION_BY_ID: &'static str = r"
+    SELECT revision
+      FROM grids
+     WHERE grid_id = $1
+";
+
+const GET_GRID_BY_COORD: &'static str = r"
+    SELECT grid_id, revision, segment_id, position_x, position_y, heights, tiles, format
+      FROM grids
+     WHERE segment_id = $1 AND position_x = $2 AND position_y = $3
+";
+
+const GET_GRID_REVISION_BY_COORD: &'static str = r"
+    SELECT revision
+      FROM grids
+     WHERE segment_id = $1 AND position_x = $2 AND position_y = $3
+";
+
+const GET_GRID_COORD: &'static str = r"
+    SELECT segment_id, position_x, position_y
+      FROM grids
+     WHERE grid_id = $1
+";
+
+const INSERT_GRID_CHANGE_QUERY: &'static str = r"
+    INSERT INTO grid_changes (grid_id, change_id)
+    VALUES ($1, nextval('grid_change_ids'))
+    ON CONFLICT (grid_id) DO UPDATE SET
+        change_id = excluded.change_id
+";
+
+const GET_GRID_CHANGES: &'static str = r"
+    SELECT grid_changes.change_id, grids.grid_id, grids.revision, grids.segment_id, grids.position_x,
+           grids.position_y, grids.heights, grids.tiles, grids.format
+      FROM grid_changes
+      JOIN grids ON grids.grid_id = grid_changes.grid_id
+     WHERE grid_changes.change_id > $1
+     ORDER BY grid_changes.change_id
+     LIMIT $2
+";
+
+const GET_SEGMENT_SIZES: &'static str = r"
+    SELECT segment_id, COUNT(1)
+      FROM grids
+     GROUP BY segment_id
+";
+
+const MOVE_SEGMENT_GRIDS: &'static str = r"
+   UPDATE grids
+      SET revision = revision + 1,
+          segment_id = $2,
+          position_x = position_x + $3,
+          position_y = position_y + $4
+    WHERE segment_id = $1
+   RETURNING grid_id
+";
+
+const COUNT_SEGMENTS_OVERLAP: &'static str = r"
+    SELECT COUNT(1)
+      FROM grids AS src
+      JOIN grids AS dst
+        ON dst.segment_id = $2
+       AND dst.position_x = src.position_x + $3
+       AND dst.position_y = src.position_y + $4
+     WHERE src.segment_id = $1
+";
+
+const MOVE_GRID_QUERY: &'static str = r"
+   UPDATE grids
+      SET revision = revision + 1,
+          segment_id = $2,
+          position_x = position_x + $3,
+          position_y = position_y + $4
+    WHERE grid_id = $1
+";
+
+const INSERT_OBJECT_QUERY: &'static str = r"
+    INSERT INTO objects (object_id, name, grid_id, offset_x, offset_y)
+    VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT (object_id) DO UPDATE SET
+        name = excluded.name,
+        grid_id = excluded.grid_id,
+        offset_x = excluded.offset_x,
+        offset_y = excluded.offset_y
+";
+
+const DELETE_OBJECT_QUERY: &'static str = r"
+    DELETE FROM objects
+     WHERE object_id = $1
+";
+
+const GET_OBJECTS_BY_GRID_RECT: &'static str = r"
+    SELECT objects.object_id, objects.name, objects.grid_id, objects.offset_x, objects.offset_y,
+           grids.position_x, grids.position_y
+      FROM objects
+      JOIN grids ON grids.grid_id = objects.grid_id
+     WHERE grids.segment_id = $1
+       AND grids.position_x BETWEEN $2 AND $3
+       AND grids.position_y BETWEEN $4 AND $5
+     ORDER BY objects.object_id
+";
+
+const SET_GRID_LAST_SEEN_QUERY: &'static str = r"
+    INSERT INTO grids_last_seen (grid_id, last_seen)
+    VALUES ($1, $2)
+    ON CONFLICT (grid_id) DO UPDATE SET
+        last_seen = excluded.last_seen
+";
+
+const GET_GRIDS_OLDER_THAN: &'static str = r"
+    SELECT grid_id
+      FROM grids_last_seen
+     WHERE last_seen < $1
+     ORDER BY last_seen, grid_id
+";
+
+const INSERT_ZONE_QUERY: &'static str = r"
+    INSERT INTO zones (segment_id, name, points)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (segment_id, name) DO UPDATE SET
+        points = excluded.points
+";
+
+const DELETE_ZONE_QUERY: &'static str = r"
+    DELETE FROM zones
+     WHERE segment_id = $1 AND name = $2
+";
+
+const GET_ZONES_BY_SEGMENT_ID: &'static str = r"
+    SELECT segment_id, name, points
+      FROM zones
+     WHERE segment_id = $1
+     ORDER BY name
+";
+
+const INSERT_BOOKMARK_QUERY: &'static str = r"
+    INSERT INTO bookmarks (segment_id, name, x, y)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (segment_id, name) DO UPDATE SET
+        x = excluded.x,
+        y = excluded.y
+";
+
+const DELETE_BOOKMARK_QUERY: &'static str = r"
+    DELETE FROM bookmarks
+     WHERE segment_id = $1 AND name = $2
+";
+
+const GET_BOOKMARKS_BY_SEGMENT_ID: &'static str = r"
+    SELECT segment_id, name, x, y
+      FROM bookmarks
+     WHERE segment_id = $1
+     ORDER BY name
+";
+
+const INSERT_RESOURCE_QUERY: &'static str = r"
+    INSERT INTO resources (name, version, resource_id, seen)
+    VALUES ($1, $2, $3, nextval('resource_seen_ids'))
+    ON CONFLICT (name, version) DO UPDATE SET
+        resource_id = excluded.resource_id,
+        seen = excluded.seen
+";
+
+const GET_RESOURCES: &'static str = r"
+    SELECT resource_id, version, name
+      FROM resources
+     ORDER BY seen
+";
+
+const GET_GRIDS_RETENTION_INFO: &'static str = r"
+    SELECT grids.grid_id, grids.segment_id, COALESCE(grids_last_seen.last_seen, 0), COALESCE(grid_changes.change_id, 0)
+      FROM grids
+      LEFT JOIN grids_last_seen ON grids_last_seen.grid_id = grids.grid_id
+      LEFT JOIN grid_changes ON grid_changes.grid_id = grids.grid_id
+";
+
+const DELETE_GRID_QUERY: &'static str = r"
+    DELETE FROM grids
+     WHERE grid_id = $1
+";
+
+const DELETE_GRID_CHANGE_QUERY: &'static str = r"
+    DELETE FROM grid_changes
+     WHERE grid_id = $1
+";
+
+const DELETE_GRID_LAST_SEEN_QUERY: &'static str = r"
+    DELETE FROM grids_last_seen
+     WHERE grid_id = $1
+";
+
+const DELETE_GRID_OBJECTS_QUERY: &'static str = r"
+    DELETE FROM objects
+     WHERE grid_id = $1
+";
+
+pub struct PostgresMapDb {
+    client: RefCell<Client>,
+    cache: MapDbCache,
+}
+
+impl PostgresMapDb {
+    pub fn new(mut client: Client, cache_ttl: Duration) -> Self {
+        client.batch_execute(CREATE_DB_QUERY).unwrap();
+        let migrated = migrate_grids_format(&mut client).unwrap();
+        if migrated > 0 {
+            info!("Migrated {} grids to format {}", migrated, GRID_FORMAT_BINCODE_ZSTD);
+        }
+        let cache = MapDbCache::new(cache_ttl);
+        cache.set_tiles(get_tiles(&mut client).unwrap());
+        Self { client: RefCell::new(client), cache }
+    }
+
+    pub fn connect(url: &str, cache_ttl: Duration) -> Self {
+        Self::new(Client::connect(url, NoTls).unwrap(), cache_ttl)
+    }
+
+    pub fn with_cache_capacity(self, capacity: usize) -> Self {
+        self.cache.set_capacity(capacity);
+        self
+    }
+}
+
+impl MapDb for PostgresMapDb {
+    fn get_tiles(&self) -> Vec<Tile> {
+        get_tiles(self.client.borrow_mut().deref_mut()).unwrap()
+    }
+
+    fn get_tile_id_by_name(&self, name: &String) -> Option<i32> {
+        self.cache.get_tile_id_by_name(name, || {
+            self.client.borrow_mut().query_opt(GET_TILE_BY_NAME_QUERY, &[name]).unwrap()
+                .map(|row| Tile::from_postgres_row(&row))
+        })
+    }
+
+    fn set_tile(&self, tile: &Tile) {
+        let updated = self.client.borrow_mut()
+            .execute(INSERT_TILE_QUERY, &[&tile.id, &tile.version, &tile.name, &tile.color])
+            .unwrap();
+        if updated > 0 {
+            self.cache.clear_tiles();
+        }
+    }
+
+    fn get_grids(&self) -> Vec<Grid> {
+        self.client.borrow_mut().query(GET_GRIDS, &[]).unwrap()
+            .iter()
+            .map(Grid::from_postgres_row)
+            .collect()
+    }
+
+    fn get_grid_ids_by_segment_id(&self, segment_id: i64) -> Vec<i64> {
+        self.client.borrow_mut().query(GET_GRID_IDS_BY_SEGMENT_ID, &[&segment_id]).unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect()
+    }
+
+    fn get_grid_by_id(&self, grid_id: i64) -> Option<Arc<Mutex<Grid>>> {
+        self.cache.get_grid_by_id(
+            grid_id,
+            || {
+                self.client.borrow_mut().query_opt(GET_GRID_REVISION_BY_ID, &[&grid_id]).unwrap()
+                    .map(|row| row.get(0))
+            },
+            || {
+                self.client.borrow_mut().query_opt(GET_GRID_BY_ID, &[&grid_id]).unwrap()
+                    .map(|row| Grid::from_postgres_row(&row))
+            },
+        )
+    }
+
+    fn get_grid(&self, segment_id: i64, position: Vec2i) -> Option<Arc<Mutex<Grid>>> {
+        self.cache.get_grid(
+            segment_id,
+            position,
+            || {
+                self.client.borrow_mut()
+                    .query_opt(GET_GRID_REVISION_BY_COORD, &[&segment_id, &position.x(), &position.y()]).unwrap()
+                    .map(|row| row.get(0))
+            },
+            || {
+                self.client.borrow_mut()
+                    .query_opt(GET_GRID_BY_COORD, &[&segment_id, &position.x(), &position.y()]).unwrap()
+                    .map(|row| Grid::from_postgres_row(&row))
+            },
+        )
+    }
+
+    fn add_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>,
+                neighbours: &Vec<GridNeighbour>) {
+        add_grid(self.client.borrow_mut().deref_mut(), grid_id, heights, tiles, neighbours).unwrap();
+        self.cache.clear_grids_by_coord();
+    }
+
+    fn update_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>) -> bool {
+        let updated = {
+            let mut client = self.client.borrow_mut();
+            let mut tx = client.transaction().unwrap();
+            let updated = update_grid(&mut tx, grid_id, heights, tiles).unwrap();
+            tx.commit().unwrap();
+            updated
+        };
+        if updated == 0 {
+            return false;
+        }
+        self.cache.clear_grids_by_coord();
+        true
+    }
+
+    fn set_grid_revision(&self, grid_id: i64, revision: i64) {
+        self.client.borrow_mut().execute(SET_GRID_REVISION_QUERY, &[&grid_id, &revision]).unwrap();
+    }
+
+    fn get_grid_changes(&self, since_change_id: i64, limit: usize) -> Vec<(i64, Grid)> {
+        self.client.borrow_mut().query(GET_GRID_CHANGES, &[&since_change_id, &(limit as i64)]).unwrap()
+            .iter()
+            .map(|row| {
+                (
+                    row.get(0),
+                    Grid {
+                        id: row.get(1),
+                        revision: row.get(2),
+                        segment_id: row.get(3),
+                        position: Vec2i::new(row.get(4), row.get(5)),
+                        heights: decode_grid_values(row.get(8), row.get(6)),
+                        tiles: decode_grid_values(row.get(8), row.get(7)),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn add_object(&self, object: &MapObject) {
+        self.client.borrow_mut().execute(
+            INSERT_OBJECT_QUERY,
+            &[&object.id, &object.name, &object.grid_id, &object.offset.x(), &object.offset.y()],
+        ).unwrap();
+    }
+
+    fn remove_object(&self, object_id: i64) {
+        self.client.borrow_mut().execute(DELETE_OBJECT_QUERY, &[&object_id]).unwrap();
+    }
+
+    fn get_objects_in_rect(&self, segment_id: i64, min: Vec2f, max: Vec2f) -> Vec<(Vec2f, MapObject)> {
+        let min_grid_pos = pos_to_grid_pos(min);
+        let max_grid_pos = pos_to_grid_pos(max);
+        self.client.borrow_mut().query(
+            GET_OBJECTS_BY_GRID_RECT,
+            &[&segment_id, &min_grid_pos.x(), &max_grid_pos.x(), &min_grid_pos.y(), &max_grid_pos.y()],
+        ).unwrap()
+            .iter()
+            .map(|row| {
+                let object = MapObject {
+                    id: row.get(0),
+                    name: row.get(1),
+                    grid_id: row.get(2),
+                    offset: Vec2f::new(row.get(3), row.get(4)),
+                };
+                let position = grid_pos_to_pos(Vec2i::new(row.get(5), row.get(6))) + object.offset;
+                (position, object)
+            })
+            .filter(|(position, _)| {
+                min.x() <= position.x() && position.x() <= max.x() && min.y() <= position.y() && position.y() <= max.y()
+            })
+            .collect()
+    }
+
+    fn set_grid_last_seen(&self, grid_id: i64, last_seen: i64) {
+        self.client.borrow_mut().execute(SET_GRID_LAST_SEEN_QUERY, &[&grid_id, &last_seen]).unwrap();
+    }
+
+    fn get_grids_older_than(&self, last_seen: i64) -> Vec<i64> {
+        self.client.borrow_mut().query(GET_GRIDS_OLDER_THAN, &[&last_seen]).unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect()
+    }
+
+    fn merge_segments(&self, src_segment_id: i64, dst_segment_id: i64, shift: Vec2i) -> Result<usize, String> {
+        let moved = merge_segments(self.client.borrow_mut().deref_mut(), src_segment_id, dst_segment_id, shift)?;
+        self.cache.clear_grids();
+        Ok(moved)
+    }
+
+    fn split_segment(&self, segment_id: i64, grid_ids: &Vec<i64>) -> Result<i64, String> {
+        let new_segment_id = split_segment(self.client.borrow_mut().deref_mut(), segment_id, grid_ids)?;
+        self.cache.clear_grids();
+        Ok(new_segment_id)
+    }
+
+    fn set_zone(&self, zone: &Zone) {
+        self.client.borrow_mut().execute(
+            INSERT_ZONE_QUERY,
+            &[&zone.segment_id, &zone.name, &serde_json::to_string(&zone.points).unwrap()],
+        ).unwrap();
+    }
+
+    fn remove_zone(&self, segment_id: i64, name: &String) -> bool {
+        self.client.borrow_mut().execute(DELETE_ZONE_QUERY, &[&segment_id, name]).unwrap() > 0
+    }
+
+    fn get_zones(&self, segment_id: i64) -> Vec<Zone> {
+        self.client.borrow_mut().query(GET_ZONES_BY_SEGMENT_ID, &[&segment_id]).unwrap()
+            .iter()
+            .map(|row| Zone {
+                segment_id: row.get(0),
+                name: row.get(1),
+                points: serde_json::from_str(row.get(2)).unwrap(),
+            })
+            .collect()
+    }
+
+    fn set_bookmark(&self, bookmark: &Bookmark) {
+        self.client.borrow_mut().execute(
+            INSERT_BOOKMARK_QUERY,
+            &[&bookmark.segment_id, &bookmark.name, &bookmark.position.x(), &bookmark.position.y()],
+        ).unwrap();
+    }
+
+    fn remove_bookmark(&self, segment_id: i64, name: &String) -> bool {
+        self.client.borrow_mut().execute(DELETE_BOOKMARK_QUERY, &[&segment_id, name]).unwrap() > 0
+    }
+
+    fn get_bookmarks(&self, segment_id: i64) -> Vec<Bookmark> {
+        self.client.borrow_mut().query(GET_BOOKMARKS_BY_SEGMENT_ID, &[&segment_id]).unwrap()
+            .iter()
+            .map(|row| Bookmark {
+                segment_id: row.get(0),
+                name: row.get(1),
+                position: Vec2f::new(row.get(2), row.get(3)),
+            })
+            .collect()
+    }
+
+    fn get_resources(&self) -> Vec<Resource> {
+        self.client.borrow_mut().query(GET_RESOURCES, &[]).unwrap()
+            .iter()
+            .map(|row| Resource {
+                id: row.get(0),
+                version: row.get(1),
+                name: row.get(2),
+            })
+            .collect()
+    }
+
+    fn set_resource(&self, resource: &Resource) {
+        self.client.borrow_mut()
+            .execute(INSERT_RESOURCE_QUERY, &[&resource.name, &resource.version, &resource.id])
+            .unwrap();
+    }
+
+    fn prune(&self, config: &MapRetentionConfig, now: i64) -> Result<MapPruneStats, String> {
+        let stats = prune_grids(self.client.borrow_mut().deref_mut(), config, now)?;
+        self.cache.clear_grids();
+        if config.vacuum && stats.grids > 0 {
+            self.client.borrow_mut().batch_execute("VACUUM").map_err(|e| format!("Failed to vacuum map db: {}", e))?;
+        }
+        Ok(stats)
+    }
+
+    fn get_cache_stats(&self) -> MapDbCacheStats {
+        self.cache.get_stats()
+    }
+
+    fn set_pinned_grids(&self, owner: u64, coords: BTreeSet<(i64, Vec2i)>) {
+        self.cache.set_pinned_grids(owner, coords);
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fn get_tiles(client: &mut impl GenericClient) -> Result<Vec<Tile>, postgres::Error> {
+    Ok(client.query(GET_TILES, &[])?
+        .iter()
+        .map(Tile::from_postgres_row)
+        .collect())
+}
+
+fn migrate_grids_format(client: &mut Client) -> Result<usize, postgres::Error> {
+    let mut tx = client.transaction()?;
+    let grids = tx.query(GET_GRIDS_BY_FORMAT, &[&GRID_FORMAT_JSON])?;
+    for row in grids.iter() {
+        let grid_id: i64 = row.get(0);
+        tx.execute(
+            SET_GRID_DATA_QUERY,
+            &[
+                &grid_id,
+                &encode_grid_values(&decode_grid_values::<f32>(GRID_FORMAT_JSON, row.get(1))),
+                &encode_grid_values(&decode_grid_values::<i32>(GRID_FORMAT_JSON, row.get(2))),
+                &GRID_FORMAT_BINCODE_ZSTD,
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(grids.len())
+}
+
+fn add_grid(client: &mut Client, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>,
+            neighbours: &Vec<GridNeighbour>) -> Result<(), postgres::Error> {
+    let mut tx = client.transaction()?;
+    tx.execute(LOCK_GRIDS_QUERY, &[])?;
+    if let Some(_) = get_grid_coord(&mut tx, grid_id)? {
+        update_grid(&mut tx, grid_id, heights, tiles)?;
+        return tx.commit();
+    }
+    let mut segments = get_segments(&mut tx, neighbours)?;
+    let (segment_id, position) = if !segments.is_empty() {
+        segments.sort_by_key(|v| v.segment_id);
+        segments.dedup_by_key(|v| v.segment_id);
+        if segments.len() > 1 {
+            sort_segments_by_size(&mut segments, &get_segment_sizes(&mut tx)?);
+        }
+        let GridSegment {
+            segment_id: target_segment,
+            offset: target_offset,
+            position: target_position,
+        } = segments[0];
+        if segments.len() > 1 {
+            for i in 1..segments.len() {
+                let GridSegment { segment_id, offset, position } = segments[i];
+                let shift = target_position - target_offset + offset - position;
+                move_segment_grids(&mut tx, segment_id, target_segment, shift)?;
+            }
+        }
+        (target_segment, target_position - target_offset)
+    } else {
+        (grid_id, Vec2i::zero())
+    };
+    tx.execute(
+        INSERT_GRID_QUERY,
+        &[
+            &grid_id,
+            &segment_id,
+            &position.x(),
+            &position.y(),
+            &encode_grid_values(heights),
+            &encode_grid_values(tiles),
+            &get_grid_hash(heights, tiles),
+            &GRID_FORMAT_BINCODE_ZSTD,
+        ],
+    )?;
+    tx.execute(INSERT_GRID_CHANGE_QUERY, &[&grid_id])?;
+    tx.commit()
+}
+
+fn update_grid(client: &mut impl GenericClient, grid_id: i64, heights: &Vec<f32>,
+               tiles: &Vec<i32>) -> Result<u64, postgres::Error> {
+    let updated = client.execute(
+        UPDATE_GRID_QUERY,
+        &[
+            &grid_id,
+            &encode_grid_values(heights),
+            &encode_grid_values(tiles),
+            &get_grid_hash(heights, tiles),
+            &GRID_FORMAT_BINCODE_ZSTD,
+        ],
+    )?;
+    if updated > 0 {
+        client.execute(INSERT_GRID_CHANGE_QUERY, &[&grid_id])?;
+    }
+    Ok(updated)
+}
+
+fn get_segments(client: &mut impl GenericClient, neighbours: &Vec<GridNeighbour>) -> Result<Vec<GridSegment>, postgres::Error> {
+    let mut result = Vec::new();
+    for neighbour in neighbours.iter() {
+        if let Some((segment_id, position)) = get_grid_coord(client, neighbour.id)? {
+            result.push(GridSegment { segment_id, offset: neighbour.offset, position });
+        }
+    }
+    Ok(result)
+}
+
+struct GridSegment {
+    segment_id: i64,
+    offset: Vec2i,
+    position: Vec2i,
+}
+
+fn get_grid_coord(client: &mut impl GenericClient, grid_id: i64) -> Result<Option<(i64, Vec2i)>, postgres::Error> {
+    Ok(client.query_opt(GET_GRID_COORD, &[&grid_id])?
+        .map(|row| (row.get(0), Vec2i::new(row.get(1), row.get(2)))))
+}
+
+fn get_segment_sizes(client: &mut impl GenericClient) -> Result<HashMap<i64, i64>, postgres::Error> {
+    Ok(client.query(GET_SEGMENT_SIZES, &[])?
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect())
+}
+
+// The largest segment is the merge target so the least grids are moved
+fn sort_segments_by_size(segments: &mut Vec<GridSegment>, sizes: &HashMap<i64, i64>) {
+    segments.sort_by_key(|v| (-sizes.get(&v.segment_id).copied().unwrap_or(0), v.segment_id));
+}
+
+fn move_segment_grids(client: &mut impl GenericClient, src_segment_id: i64, dst_segment_id: i64,
+                      shift: Vec2i) -> Result<usize, postgres::Error> {
+    let moved = client.query(MOVE_SEGMENT_GRIDS, &[&src_segment_id, &dst_segment_id, &shift.x(), &shift.y()])?;
+    for row in moved.iter() {
+        client.execute(INSERT_GRID_CHANGE_QUERY, &[&row.get::<usize, i64>(0)])?;
+    }
+    Ok(moved.len())
+}
+
+fn merge_segments(client: &mut Client, src_segment_id: i64, dst_segment_id: i64,
+                  shift: Vec2i) -> Result<usize, String> {
+    if src_segment_id == dst_segment_id {
+        return Err(format!("Segment {} can't be merged into itself", src_segment_id));
+    }
+    let mut tx = client.transaction().map_err(|e| e.to_string())?;
+    tx.execute(LOCK_GRIDS_QUERY, &[]).map_err(|e| e.to_string())?;
+    let segment_sizes = get_segment_sizes(&mut tx).map_err(|e| e.to_string())?;
+    for segment_id in [src_segment_id, dst_segment_id].iter() {
+        if !segment_sizes.contains_key(segment_id) {
+            return Err(format!("Segment {} is not found", segment_id));
+        }
+    }
+    let overlap: i64 = tx.query_one(COUNT_SEGMENTS_OVERLAP, &[&src_segment_id, &dst_segment_id, &shift.x(), &shift.y()])
+        .map_err(|e| e.to_string())?
+        .get(0);
+    if overlap > 0 {
+        return Err(format!("{} grids of segment {} overlap segment {} with shift {:?}",
+                           overlap, src_segment_id, dst_segment_id, shift));
+    }
+    let moved = move_segment_grids(&mut tx, src_segment_id, dst_segment_id, shift).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(moved)
+}
+
+fn split_segment(client: &mut Client, segment_id: i64, grid_ids: &Vec<i64>) -> Result<i64, String> {
+    let mut grid_ids = grid_ids.clone();
+    grid_ids.sort();
+    grid_ids.dedup();
+    let new_segment_id = *grid_ids.first().ok_or_else(|| String::from("No grids to split"))?;
+    let mut tx = client.transaction().map_err(|e| e.to_string())?;
+    tx.execute(LOCK_GRIDS_QUERY, &[]).map_err(|e| e.to_string())?;
+    let segment_sizes = get_segment_sizes(&mut tx).map_err(|e| e.to_string())?;
+    match segment_sizes.get(&segment_id) {
+        Some(&size) if size > grid_ids.len() as i64 => (),
+        Some(_) => return Err(format!("Segment {} can't be split without remaining grids", segment_id)),
+        None => return Err(format!("Segment {} is not found", segment_id)),
+    }
+    if segment_sizes.contains_key(&new_segment_id) {
+        return Err(format!("Segment {} already exists", new_segment_id));
+    }
+    let mut origin = Vec2i::zero();
+    for &grid_id in grid_ids.iter() {
+        match get_grid_coord(&mut tx, grid_id).map_err(|e| e.to_string())? {
+            Some((grid_segment_id, position)) if grid_segment_id == segment_id => if grid_id == new_segment_id {
+                origin = position;
+            },
+            _ => return Err(format!("Grid {} is not found in segment {}", grid_id, segment_id)),
+        }
+    }
+    for &grid_id in grid_ids.iter() {
+        tx.execute(MOVE_GRID_QUERY, &[&grid_id, &new_segment_id, &-origin.x(), &-origin.y()])
+            .map_err(|e| e.to_string())?;
+        tx.execute(INSERT_GRID_CHANGE_QUERY, &[&grid_id]).map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(new_segment_id)
+}
+
+fn prune_grids(client: &mut Client, config: &MapRetentionConfig, now: i64) -> Result<MapPruneStats, String> {
+    let mut tx = client.transaction().map_err(|e| e.to_string())?;
+    tx.execute(LOCK_GRIDS_QUERY, &[]).map_err(|e| e.to_string())?;
+    let grids: Vec<GridRetentionInfo> = tx.query(GET_GRIDS_RETENTION_INFO, &[]).map_err(|e| e.to_string())?
+        .iter()
+        .map(|row| GridRetentionInfo {
+            grid_id: row.get(0),
+            segment_id: row.get(1),
+            last_seen: row.get(2),
+            change_id: row.get(3),
+        })
+        .collect();
+    let segments: BTreeMap<i64, i64> = grids.iter().map(|v| (v.grid_id, v.segment_id)).collect();
+    let pinned_segments: BTreeSet<i64> = config.pinned_segments.iter()
+        .map(|v| segments.get(v).cloned().unwrap_or(*v))
+        .collect();
+    let grid_ids = select_grids_to_prune(&grids, &pinned_segments, config, now);
+    for &grid_id in grid_ids.iter() {
+        for query in &[DELETE_GRID_QUERY, DELETE_GRID_CHANGE_QUERY, DELETE_GRID_LAST_SEEN_QUERY, DELETE_GRID_OBJECTS_QUERY] {
+            tx.execute(*query, &[&grid_id]).map_err(|e| e.to_string())?;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    let pruned_segments: BTreeSet<i64> = grid_ids.iter().filter_map(|v| segments.get(v).cloned()).collect();
+    Ok(MapPruneStats { grids: grid_ids.len(), segments: pruned_segments.len() })
+}
+
+impl Tile {
+    fn from_postgres_row(row: &Row) -> Self {
+        Tile {
+            id: row.get(0),
+            version: row.get(1),
+            name: row.get(2),
+            color: row.get(3),
+        }
+    }
+}
+
+impl Grid {
+    fn from_postgres_row(row: &Row) -> Self {
+        Grid {
+            id: row.get(0),
+            revision: row.get(1),
+            segment_id: row.get(2),
+            position: Vec2i::new(row.get(3), row.get(4)),
+            heights: decode_grid_values(row.get(7), row.get(5)),
+            tiles: decode_grid_values(row.get(7), row.get(6)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_map_db(schema: &str) -> PostgresMapDb {
+        let url = std::env::var("HAFEN_BOT_TEST_POSTGRES_URL")
+            .expect("HAFEN_BOT_TEST_POSTGRES_URL is not set");
+        let mut client = Client::connect(url.as_str(), NoTls).unwrap();
+        client.batch_execute(format!(
+            "DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0}; SET search_path TO {0};", schema
+        ).as_str()).unwrap();
+        PostgresMapDb::new(client, Duration::ZERO)
+    }
+
+    #[test]
+    #[ignore]
+    fn postgres_add_grid_should_store_grid() {
+        let map_db = make_map_db("postgres_add_grid_should_store_grid");
+        let heights = vec![1.0, 2.0, 3.0];
+        let tiles = vec![4, 5, 6];
+        map_db.add_grid(1, &heights, &tiles, &Vec::new());
+        assert_eq!(map_db.get_grids(), vec![
+            Grid {
+                id: 1,
+                revision: 1,
+                segment_id: 1,
+                position: Vec2i::zero(),
+                heights,
+                tiles,
+            }
+        ]);
+    }
+
+    #[test]
+    #[ignore]
+    fn postgres_adjacent_grid_to_separated_segments_should_merge_them() {
+        let map_db = make_map_db("postgres_adjacent_grid_to_separated_segments_should_merge_them");
+        map_db.add_grid(1, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(3, &Vec::new(), &Vec::new(), &vec![
+            GridNeighbour { id: 1, offset: Vec2i::new(-1, -1) },
+            GridNeighbour { id: 2, offset: Vec2i::new(0, 1) },
+        ]);
+        assert_eq!(
+            map_db.get_grids().iter().map(|v| (v.id, v.segment_id, v.position)).collect::<Vec<_>>(),
+            vec![
+                (1, 1, Vec2i::zero()),
+                (2, 1, Vec2i::new(1, 2)),
+                (3, 1, Vec2i::new(1, 1)),
+            ]
+        );
+        assert_eq!(
+            map_db.get_grid_changes(0, 10).iter().map(|(_, v)| v.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn postgres_set_tile_should_update_stored_tile_with_greater_version() {
+        let map_db = make_map_db("postgres_set_tile_should_update_stored_tile_with_greater_version");
+        map_db.set_tile(&Tile { id: 1, version: 2, name: String::from("foo"), color: 42 });
+        map_db.set_tile(&Tile { id: 1, version: 1, name: String::from("bar"), color: 42 });
+        assert_eq!(map_db.get_tile_id_by_name(&String::from("foo")), Some(1));
+        map_db.set_tile(&Tile { id: 1, version: 3, name: String::from("bar"), color: 42 });
+        assert_eq!(map_db.get_tile_id_by_name(&String::from("foo")), None);
+        assert_eq!(map_db.get_tiles(), vec![Tile { id: 1, version: 3, name: String::from("bar"), color: 42 }]);
+    }
+
+    #[test]
+    #[ignore]
+    fn postgres_adjacent_grid_should_merge_into_largest_segment() {
+        let map_db = make_map_db("postgres_adjacent_grid_should_merge_into_largest_segment");
+        map_db.add_grid(1, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(3, &Vec::new(), &Vec::new(), &vec![GridNeighbour { id: 2, offset: Vec2i::new(-1, 0) }]);
+        map_db.add_grid(4, &Vec::new(), &Vec::new(), &vec![
+            GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) },
+            GridNeighbour { id: 2, offset: Vec2i::new(1, 0) },
+        ]);
+        assert_eq!(
+            map_db.get_grids().iter().map(|v| (v.id, v.segment_id, v.position)).collect::<Vec<_>>(),
+            vec![
+                (1, 2, Vec2i::new(-2, 0)),
+                (2, 2, Vec2i::zero()),
+                (3, 2, Vec2i::new(1, 0)),
+                (4, 2, Vec2i::new(-1, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn postgres_prune_should_remove_least_recently_seen_grids_except_pinned_segments() {
+        let map_db = make_map_db("postgres_prune_should_remove_least_recently_seen_grids_except_pinned_segments");
+        map_db.add_grid(1, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &Vec::new(), &vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        map_db.add_grid(3, &Vec::new(), &Vec::new(), &vec![GridNeighbour { id: 2, offset: Vec2i::new(-1, 0) }]);
+        map_db.add_grid(4, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(5, &Vec::new(), &Vec::new(), &vec![GridNeighbour { id: 4, offset: Vec2i::new(-1, 0) }]);
+        for &(grid_id, last_seen) in &[(1, 300), (2, 100), (3, 200), (4, 10), (5, 20)] {
+            map_db.set_grid_last_seen(grid_id, last_seen);
+        }
+        let config = MapRetentionConfig {
+            interval: 1.0,
+            max_grids_per_segment: Some(2),
+            max_age: None,
+            pinned_segments: vec![5],
+            vacuum: true,
+        };
+        assert_eq!(map_db.prune(&config, 400), Ok(MapPruneStats { grids: 1, segments: 1 }));
+        assert_eq!(map_db.get_grids().iter().map(|v| v.id).collect::<Vec<_>>(), vec![1, 3, 4, 5]);
+        assert_eq!(map_db.get_grids_older_than(1000), vec![4, 5, 3, 1]);
+    }
+
+    #[test]
+    #[ignore]
+    fn postgres_json_grids_should_be_migrated_to_binary_format() {
+        let map_db = make_map_db("postgres_json_grids_should_be_migrated_to_binary_format");
+        map_db.client.borrow_mut().batch_execute(r"
+            INSERT INTO grids (grid_id, revision, segment_id, position_x, position_y, heights, tiles, hash, format)
+            VALUES (1, 1, 1, 0, 0, CAST('[1.0,2.0,3.0]' AS BYTEA), CAST('[4,5,6]' AS BYTEA), 0, 0);
+        ").unwrap();
+        assert_eq!(migrate_grids_format(map_db.client.borrow_mut().deref_mut()).unwrap(), 1);
+        let format: i64 = map_db.client.borrow_mut().query_one("SELECT format FROM grids WHERE grid_id = 1", &[]).unwrap().get(0);
+        assert_eq!(format, GRID_FORMAT_BINCODE_ZSTD);
+        let grid = map_db.get_grid_by_id(1).unwrap();
+        assert_eq!(grid.lock().unwrap().heights, vec![1.0, 2.0, 3.0]);
+        assert_eq!(grid.lock().unwrap().tiles, vec![4, 5, 6]);
+    }
+}
@@ -4,33 +4,47 @@ use std::io::Write;
 use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver};
-use std::thread::{JoinHandle, spawn};
-use std::time::Duration;
+use std::thread::{JoinHandle, sleep, spawn};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use crate::bot::alerting::{Alert, Alerter, AlertKind};
+use crate::bot::cancel_tokens::CancelTokens;
 use crate::bot::map_db::MapDb;
+use crate::bot::message_queue::{MessageQueue, MessageQueueConfig};
 use crate::bot::protocol::{Event, Message, Update};
-use crate::bot::session::Session;
-use crate::bot::visualization::{start_visualize_session, VisualizationConfig};
+use crate::bot::session::{Session, SessionData};
+use crate::bot::session_stats::{write_session_stats, write_session_stats_snapshot};
+use crate::bot::task_log::{set_task_log_writer, TaskLogWriter};
+use crate::bot::update_journal::UpdateJournals;
+use crate::bot::visualization::{CombinedVisualization, start_visualize_session, VisualizationConfig};
 
 #[derive(Clone, Deserialize)]
 pub struct ProcessConfig {
     pub sessions_path: String,
     pub write_updates_log: bool,
+    pub write_task_log: bool,
+    pub write_session_stats: bool,
     pub poll_timeout: f64,
+    pub autosave_session: bool,
+    pub autosave_interval: f64,
+    pub journal_updates: bool,
+    pub message_queue: MessageQueueConfig,
 }
 
 pub fn start_process_session(session_id: i64, session: Arc<RwLock<Session>>, updates: Arc<UpdatesQueue>,
-                             messages: Arc<Mutex<VecDeque<Message>>>,
-                             visualizers: Arc<Mutex<Vec<JoinHandle<()>>>>, map_db: Arc<Mutex<dyn MapDb + Send>>,
-                             cancel: Arc<AtomicBool>, config: ProcessConfig, visualization_config: VisualizationConfig) -> JoinHandle<()> {
-    spawn(move || process_session(session_id, session, updates, messages, visualizers, map_db, cancel, config, visualization_config))
+                             messages: Arc<Mutex<MessageQueue>>,
+                             visualizers: Arc<Mutex<Vec<JoinHandle<()>>>>, combined_visualization: Arc<CombinedVisualization>,
+                             map_db: Arc<Mutex<dyn MapDb + Send>>, cancel: Arc<CancelTokens>, stop: Arc<AtomicBool>, alerter: Arc<Alerter>,
+                             journals: Arc<UpdateJournals>, config: ProcessConfig, visualization_config: VisualizationConfig) -> JoinHandle<()> {
+    spawn(move || process_session(session_id, session, updates, messages, visualizers, combined_visualization, map_db, cancel, stop, alerter, journals, config, visualization_config))
 }
 
 fn process_session(session_id: i64, session: Arc<RwLock<Session>>, updates: Arc<UpdatesQueue>,
-                   messages: Arc<Mutex<VecDeque<Message>>>, visualizers: Arc<Mutex<Vec<JoinHandle<()>>>>,
-                   map_db: Arc<Mutex<dyn MapDb + Send>>, cancel: Arc<AtomicBool>, config: ProcessConfig,
+                   messages: Arc<Mutex<MessageQueue>>, visualizers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+                   combined_visualization: Arc<CombinedVisualization>, map_db: Arc<Mutex<dyn MapDb + Send>>, cancel: Arc<CancelTokens>, stop: Arc<AtomicBool>,
+                   alerter: Arc<Alerter>, journals: Arc<UpdateJournals>, config: ProcessConfig,
                    visualization_config: VisualizationConfig) {
     info!("Start process session {}", session_id);
     messages.lock().unwrap().push_back(Message::GetSessionData);
@@ -41,17 +55,40 @@ fn process_session(session_id: i64, session: Arc<RwLock<Session>>, updates: Arc<
     } else {
         (None, None)
     };
+    if config.write_task_log {
+        match TaskLogWriter::open(&config.sessions_path, session_id) {
+            Ok(v) => set_task_log_writer(Some(v)),
+            Err(e) => error!("Failed to open task log for session {}: {}", session_id, e),
+        }
+    }
     let poll_timeout = Duration::from_secs_f64(config.poll_timeout);
+    let autosave_interval = Duration::from_secs_f64(config.autosave_interval);
+    let mut last_autosave = Instant::now();
+    let mut last_update = Instant::now();
+    let mut connection_lost = false;
     loop {
+        if stop.load(Ordering::Relaxed) {
+            info!("Finish session {} on shutdown", session_id);
+            finish_session(session_id, &session, &journals, &config);
+            break;
+        }
         if let Some(update) = poll_update(&updates, poll_timeout) {
+            last_update = Instant::now();
+            if connection_lost {
+                session.read().unwrap().resume();
+                connection_lost = false;
+            }
             if let Some(sender) = updates_sender.as_ref() {
-                sender.send(Some(update.clone())).unwrap();
+                sender.send(Some(LoggedUpdate { time: get_unix_time(), update: update.clone() })).unwrap();
             }
             match &update.event {
-                Event::Close => break,
+                Event::Close => {
+                    close_session(session_id, &session, &journals, &config);
+                    break;
+                }
                 Event::VisualizationAdd => {
-                    add_session_visualization(session_id, &session, &updates, &messages, &visualizers, map_db.clone(),
-                                              visualization_config.clone());
+                    add_session_visualization(session_id, &session, &updates, &messages, &visualizers,
+                                              &combined_visualization, map_db.clone(), visualization_config.clone());
                 }
                 Event::GetSessionData => {
                     let session_data = session.read().unwrap().as_session_data();
@@ -63,22 +100,29 @@ fn process_session(session_id: i64, session: Arc<RwLock<Session>>, updates: Arc<
             if session.write().unwrap().update(update) {
                 debug!("Session {} is updated", session_id);
             }
+        } else if !connection_lost && Instant::now() - last_update >= alerter.connection_lost_timeout() {
+            connection_lost = true;
+            alerter.send(Alert {
+                session: session_id,
+                kind: AlertKind::ConnectionLost,
+                message: format!("no updates for {:?}", Instant::now() - last_update),
+            });
+            let locked = session.read().unwrap();
+            locked.finish();
+            // Session may be resumed, stats row is appended once it is closed or server is shut down
+            write_stats_snapshot(session_id, &locked, &config);
         }
         while let Some(message) = session.read().unwrap().get_existing_message() {
-            let mut locked_messages = messages.lock().unwrap();
-            if locked_messages.is_empty() || *locked_messages.back().unwrap() != message {
-                debug!("Add next message for session {}: {:?}", session_id, message);
-                locked_messages.push_back(message);
-            }
+            add_message(session_id, message, &messages, &alerter);
         }
         if let Some(message) = session.read().unwrap().get_next_message() {
-            let mut locked_messages = messages.lock().unwrap();
-            if locked_messages.is_empty() || *locked_messages.back().unwrap() != message {
-                debug!("Add next message for session {}: {:?}", session_id, message);
-                locked_messages.push_back(message);
-            }
+            add_message(session_id, message, &messages, &alerter);
         }
-        cancel.store(false, Ordering::Relaxed);
+        if config.autosave_session && Instant::now() - last_autosave >= autosave_interval {
+            autosave_session(session_id, &session, &journals, &config);
+            last_autosave = Instant::now();
+        }
+        cancel.reset();
     }
     if let Some(sender) = updates_sender.as_ref() {
         sender.send(None).unwrap();
@@ -86,10 +130,115 @@ fn process_session(session_id: i64, session: Arc<RwLock<Session>>, updates: Arc<
     if let Some(writer) = updates_writer {
         writer.join().unwrap();
     }
+    set_task_log_writer(None);
     info!("Stop process session {}", session_id);
 }
 
-fn write_updates(session_id: i64, receiver: Receiver<Option<Update>>, path: String) {
+fn add_message(session_id: i64, message: Message, messages: &Arc<Mutex<MessageQueue>>, alerter: &Alerter) {
+    let mut locked_messages = messages.lock().unwrap();
+    if locked_messages.is_repeated(&message) {
+        return;
+    }
+    debug!("Add next message for session {}: {:?}", session_id, message);
+    alerter.send_message(session_id, &message);
+    locked_messages.push_back(message);
+}
+
+fn close_session(session_id: i64, session: &Arc<RwLock<Session>>, journals: &UpdateJournals, config: &ProcessConfig) {
+    finish_session(session_id, session, journals, config);
+    if let Err(e) = journals.remove(session_id) {
+        error!("Failed to remove updates journal for session {}: {}", session_id, e);
+    }
+}
+
+// Journal is kept so updates missing from saved session can be replayed after restart
+fn finish_session(session_id: i64, session: &Arc<RwLock<Session>>, journals: &UpdateJournals, config: &ProcessConfig) {
+    if config.autosave_session {
+        autosave_session(session_id, session, journals, config);
+    }
+    let locked = session.read().unwrap();
+    locked.finish();
+    write_stats(session_id, &locked, config);
+}
+
+fn write_stats(session_id: i64, session: &Session, config: &ProcessConfig) {
+    if config.write_session_stats {
+        match write_session_stats(&config.sessions_path, &session.get_stats()) {
+            Ok(_) => (),
+            Err(e) => error!("Failed to write stats for session {}: {}", session_id, e),
+        }
+    }
+}
+
+fn write_stats_snapshot(session_id: i64, session: &Session, config: &ProcessConfig) {
+    if config.write_session_stats {
+        match write_session_stats_snapshot(&config.sessions_path, &session.get_stats()) {
+            Ok(_) => (),
+            Err(e) => error!("Failed to write stats snapshot for session {}: {}", session_id, e),
+        }
+    }
+}
+
+fn autosave_session(session_id: i64, session: &Arc<RwLock<Session>>, journals: &UpdateJournals, config: &ProcessConfig) {
+    let (session_data, last_update) = {
+        let locked = session.read().unwrap();
+        (locked.as_session_data(), locked.last_update())
+    };
+    match write_session_data(&config.sessions_path, session_id, &session_data) {
+        Ok(_) => debug!("Session {} is saved", session_id),
+        Err(e) => {
+            error!("Failed to save session {}: {}", session_id, e);
+            return;
+        }
+    }
+    match journals.compact(session_id, last_update) {
+        Ok(pending) => debug!("Updates journal for session {} is compacted, {} updates are pending", session_id, pending),
+        Err(e) => error!("Failed to compact updates journal for session {}: {}", session_id, e),
+    }
+}
+
+fn write_session_data(path: &String, session_id: i64, session_data: &SessionData) -> std::io::Result<()> {
+    std::fs::create_dir_all(path)?;
+    let tmp_path = format!("{}/{}.session.json.tmp", path, session_id);
+    std::fs::write(&tmp_path, serde_json::to_vec(session_data).unwrap())?;
+    std::fs::rename(tmp_path, get_session_data_path(path, session_id))
+}
+
+pub fn read_session_data(session_id: i64, config: &ProcessConfig) -> Option<SessionData> {
+    if !config.autosave_session {
+        return None;
+    }
+    let path = get_session_data_path(&config.sessions_path, session_id);
+    let content = match std::fs::read(&path) {
+        Ok(v) => v,
+        Err(_) => return None,
+    };
+    match serde_json::from_slice(&content) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            error!("Failed to parse saved session {} from {}: {}", session_id, path, e);
+            None
+        }
+    }
+}
+
+fn get_session_data_path(path: &String, session_id: i64) -> String {
+    format!("{}/{}.session.json", path, session_id)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoggedUpdate {
+    #[serde(default)]
+    pub time: f64,
+    #[serde(flatten)]
+    pub update: Update,
+}
+
+fn get_unix_time() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|v| v.as_secs_f64()).unwrap_or(0.0)
+}
+
+fn write_updates(session_id: i64, receiver: Receiver<Option<LoggedUpdate>>, path: String) {
     match std::fs::create_dir_all(&path) {
         Ok(_) => (),
         Err(e) => {
@@ -97,7 +246,7 @@ fn write_updates(session_id: i64, receiver: Receiver<Option<Update>>, path: Stri
             return;
         }
     }
-    let mut file = match OpenOptions::new().create(true).append(true).open(format!("{}/{}.json", path, session_id)) {
+    let mut file = match OpenOptions::new().create(true).append(true).open(get_updates_log_path(&path, session_id)) {
         Ok(v) => v,
         Err(e) => {
             error!("Failed open updates log for session {}: {}", session_id, e);
@@ -122,6 +271,59 @@ fn write_updates(session_id: i64, receiver: Receiver<Option<Update>>, path: Stri
     }
 }
 
+pub fn read_updates_log(session_id: i64, from: i64, config: &ProcessConfig) -> Result<Vec<Update>, String> {
+    read_logged_updates(session_id, from, config).map(|v| v.into_iter().map(|v| v.update).collect())
+}
+
+pub fn read_logged_updates(session_id: i64, from: i64, config: &ProcessConfig) -> Result<Vec<LoggedUpdate>, String> {
+    let path = get_updates_log_path(&config.sessions_path, session_id);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to read updates log {}: {}", path, e)),
+    };
+    let written = &content[0..content.rfind('\n').map(|v| v + 1).unwrap_or(0)];
+    let mut result = Vec::new();
+    for line in written.lines().filter(|v| !v.is_empty()) {
+        match serde_json::from_str::<LoggedUpdate>(line) {
+            Ok(v) => if v.update.number >= from {
+                result.push(v);
+            },
+            Err(e) => return Err(format!("Failed to parse update from {}: {}", path, e)),
+        }
+    }
+    Ok(result)
+}
+
+const REPLAY_STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Updates are pushed with the recorded pauses between them divided by speed
+pub fn start_replay(session_id: i64, log: Vec<LoggedUpdate>, updates: Arc<UpdatesQueue>, speed: f64,
+                    stop: Arc<AtomicBool>) -> JoinHandle<()> {
+    spawn(move || {
+        let mut last_time: Option<f64> = None;
+        for LoggedUpdate { time, update } in log.into_iter() {
+            if let Some(last_time) = last_time {
+                let delay = Duration::from_secs_f64((time - last_time).max(0.0) / speed);
+                let deadline = Instant::now() + delay;
+                while !stop.load(Ordering::Relaxed) && Instant::now() < deadline {
+                    sleep((deadline - Instant::now()).min(REPLAY_STOP_CHECK_INTERVAL));
+                }
+            }
+            if stop.load(Ordering::Relaxed) {
+                info!("Stop replay into session {} on shutdown", session_id);
+                return;
+            }
+            last_time = Some(time);
+            push_update(&updates, Update { session: session_id, number: update.number, event: update.event });
+        }
+        info!("Replay into session {} is finished", session_id);
+    })
+}
+
+fn get_updates_log_path(path: &String, session_id: i64) -> String {
+    format!("{}/{}.json", path, session_id)
+}
+
 pub struct UpdatesQueue {
     has_value: Condvar,
     values: Mutex<VecDeque<Update>>,
@@ -160,10 +362,15 @@ pub fn count_updates(updates: &Arc<UpdatesQueue>) -> usize {
 }
 
 pub fn add_session_visualization(session_id: i64, session: &Arc<RwLock<Session>>, updates: &Arc<UpdatesQueue>,
-                                 messages: &Arc<Mutex<VecDeque<Message>>>,
+                                 messages: &Arc<Mutex<MessageQueue>>,
                                  visualizers: &Arc<Mutex<Vec<JoinHandle<()>>>>,
+                                 combined_visualization: &Arc<CombinedVisualization>,
                                  map_db: Arc<Mutex<dyn MapDb + Send>>, config: VisualizationConfig) {
     let scene = session.read().unwrap().scene().clone();
+    if config.combined() {
+        combined_visualization.add_session(session_id, session.clone(), scene, updates.clone(), messages.clone(), map_db, config);
+        return;
+    }
     visualizers.lock().unwrap()
         .push(start_visualize_session(session_id, session.clone(), scene, updates.clone(), messages.clone(), map_db, config));
 }
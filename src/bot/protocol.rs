@@ -1,19 +1,33 @@
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
+use crate::bot::area_objects::AreaObjects;
+use crate::bot::containers::Container;
+use crate::bot::fields::Field;
 use crate::bot::map::GridNeighbour;
+use crate::bot::map_import::MapImportStats;
+use crate::bot::map_query::{GridInfo, TileInfo, TileStats};
+use crate::bot::bookmarks::Bookmark;
+use crate::bot::map_replication::MapChanges;
+use crate::bot::map_retention::MapPruneStats;
 use crate::bot::session::SessionData;
+use crate::bot::session_diff::SessionDiff;
+use crate::bot::session_stats::SessionStats;
+use crate::bot::stuck_recovery::{StuckRecoveryAction, StuckRecoveryOutcome};
 use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::WorldSnapshot;
+use crate::bot::zones::ZoneInfo;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Update {
     pub session: i64,
     pub number: i64,
     pub event: Event,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "type")]
 pub enum Event {
     NewWidget {
@@ -88,6 +102,13 @@ pub enum Event {
     SessionData { value: Option<String> },
     GetSessionData,
     Cancel,
+    GridsOfInterest {
+        positions: Vec<Vec2i>,
+    },
+    Hello {
+        version: u32,
+        capabilities: Vec<String>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialOrd, PartialEq, Clone)]
@@ -204,7 +225,13 @@ impl PartialEq<Vec2i> for Value {
 pub enum Message {
     Ok,
     Error { message: String },
+    Hello {
+        version: u32,
+        versions: Vec<u32>,
+        capabilities: Vec<String>,
+    },
     Sessions { value: Vec<SessionInfo> },
+    TaskStatuses { value: Vec<TaskInfo> },
     WidgetMessage {
         sender: i32,
         kind: String,
@@ -220,6 +247,43 @@ pub enum Message {
     SessionData { value: String },
     GetSessionData,
     LockWidget { value: String },
+    SessionStats { value: SessionStats },
+    MapChanges { value: MapChanges },
+    GridOfInterestChanged {
+        position: Vec2i,
+        grid_id: i64,
+        revision: i64,
+    },
+    PlayerPosition {
+        segment_id: i64,
+        position: Vec2f,
+        anchored: Option<Vec2f>,
+    },
+    AreaObjects { value: AreaObjects },
+    MapGrids { value: Vec<GridInfo> },
+    ImportedMap { value: MapImportStats },
+    MapPruned { value: MapPruneStats },
+    MapTile { value: TileInfo },
+    TileStats { value: Vec<TileStats> },
+    Zones { value: Vec<ZoneInfo> },
+    Bookmarks { value: Vec<Bookmark> },
+    TileProfiles { value: BTreeMap<String, BTreeMap<String, f64>> },
+    SessionLog { value: Vec<Update> },
+    SessionDiff { value: SessionDiff },
+    FoundPath { value: Vec<Vec2i> },
+    LineOfSight { value: bool, distance: f64 },
+    WorldSnapshot { value: WorldSnapshot },
+    Fields { value: Vec<Field> },
+    Containers { value: Vec<Container>, nearest_non_full: Option<i64> },
+    StuckRecovery {
+        outcome: StuckRecoveryOutcome,
+        actions: Vec<StuckRecoveryAction>,
+    },
+    Alert {
+        object_id: i64,
+        name: String,
+        distance: f64,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialOrd, PartialEq, Clone)]
@@ -248,11 +312,67 @@ pub enum Modifier {
 pub struct SessionInfo {
     pub id: i64,
     pub tasks: Vec<String>,
+    pub task_statuses: Vec<TaskInfo>,
     pub updates: usize,
     pub messages: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskInfo {
+    pub id: i64,
+    pub name: String,
+    pub status: TaskStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskStatus {
+    pub state: String,
+    pub target: Option<String>,
+    pub percent: Option<f64>,
+    pub counters: BTreeMap<String, usize>,
+    pub stage: Option<Box<TaskStage>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskStage {
+    pub index: usize,
+    pub task: String,
+    pub status: TaskStatus,
+}
+
+impl TaskStatus {
+    pub fn new(state: &str) -> Self {
+        Self {
+            state: String::from(state),
+            target: None,
+            percent: None,
+            counters: BTreeMap::new(),
+            stage: None,
+        }
+    }
+
+    pub fn with_target(mut self, target: String) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn with_percent(mut self, done: usize, total: usize) -> Self {
+        self.percent = Some(if total > 0 { 100.0 * done as f64 / total as f64 } else { 100.0 });
+        self
+    }
+
+    pub fn with_counter(mut self, name: &str, value: usize) -> Self {
+        self.counters.insert(String::from(name), value);
+        self
+    }
+
+    pub fn with_stage(mut self, index: usize, task: String, status: TaskStatus) -> Self {
+        self.stage = Some(Box::new(TaskStage { index, task, status }));
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct MapGrid {
     pub id: i64,
     pub position: Vec2i,
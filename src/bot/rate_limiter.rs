@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RateLimitConfig {
+    pub max_messages: usize,
+    pub interval: f64,
+}
+
+#[derive(Deserialize)]
+struct RateLimitParams {
+    rate_limit: Option<RateLimitConfig>,
+}
+
+pub struct RateLimiter {
+    max_messages: usize,
+    interval: Duration,
+    sent: VecDeque<Instant>,
+    throttled: u64,
+    throttling: bool,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            max_messages: config.max_messages,
+            interval: Duration::from_secs_f64(config.interval),
+            sent: VecDeque::new(),
+            throttled: 0,
+            throttling: false,
+        }
+    }
+
+    pub fn throttled(&self) -> u64 {
+        self.throttled
+    }
+
+    pub fn is_available(&mut self, now: Instant) -> bool {
+        while let Some(sent_at) = self.sent.front() {
+            if now - *sent_at < self.interval {
+                break;
+            }
+            self.sent.pop_front();
+        }
+        self.sent.len() < self.max_messages
+    }
+
+    pub fn add_message(&mut self, now: Instant) {
+        self.sent.push_back(now);
+        self.throttling = false;
+    }
+
+    // Task is polled on every tick while throttled so count only once until the next message is sent
+    pub fn add_throttled(&mut self) {
+        if !self.throttling {
+            self.throttled += 1;
+            self.throttling = true;
+        }
+    }
+}
+
+pub fn get_task_rate_limit(params: &[u8], config: Option<&RateLimitConfig>) -> Option<RateLimitConfig> {
+    serde_json::from_slice::<RateLimitParams>(params).ok()
+        .and_then(|v| v.rate_limit)
+        .or_else(|| config.cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_should_throttle_messages_exceeding_limit_within_interval() {
+        let mut rate_limiter = RateLimiter::new(&RateLimitConfig { max_messages: 2, interval: 1.0 });
+        let now = Instant::now();
+        assert!(rate_limiter.is_available(now));
+        rate_limiter.add_message(now);
+        assert!(rate_limiter.is_available(now));
+        rate_limiter.add_message(now);
+        assert!(!rate_limiter.is_available(now + Duration::from_millis(500)));
+        assert!(!rate_limiter.is_available(now + Duration::from_millis(900)));
+        assert_eq!(rate_limiter.throttled(), 0);
+        rate_limiter.add_throttled();
+        rate_limiter.add_throttled();
+        assert_eq!(rate_limiter.throttled(), 1);
+        assert!(rate_limiter.is_available(now + Duration::from_secs(1)));
+        rate_limiter.add_message(now + Duration::from_secs(1));
+        rate_limiter.add_throttled();
+        assert_eq!(rate_limiter.throttled(), 2);
+    }
+
+    #[test]
+    fn task_rate_limit_from_params_should_override_config() {
+        let config = RateLimitConfig { max_messages: 2, interval: 1.0 };
+        assert_eq!(
+            get_task_rate_limit(br#"{"rate_limit": {"max_messages": 5, "interval": 2}}"#, Some(&config)),
+            Some(RateLimitConfig { max_messages: 5, interval: 2.0 })
+        );
+        assert_eq!(get_task_rate_limit(b"{}", Some(&config)), Some(config.clone()));
+        assert_eq!(get_task_rate_limit(b"", Some(&config)), Some(config));
+        assert_eq!(get_task_rate_limit(b"", None), None);
+    }
+}
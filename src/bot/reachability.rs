@@ -0,0 +1,551 @@
+use std::collections::{BinaryHeap, BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::bot::map::{Grid, GRID_SIZE, grid_pos_to_tile_pos, Map, tile_pos_to_grid_pos, TileSet};
+use crate::bot::math::as_score;
+use crate::bot::vec2::Vec2i;
+
+const LANDMARKS_COUNT: usize = 3;
+
+pub struct Reachability {
+    segments: Mutex<BTreeMap<(i64, Vec<i32>), Components>>,
+}
+
+impl Reachability {
+    pub fn new() -> Self {
+        Self { segments: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn is_reachable(&self, map: &Map, revision: u64, segment_id: i64, src_tile_pos: Vec2i, dst_tile_pos: Vec2i,
+                        allowed_tiles: &impl TileSet) -> bool {
+        self.with_components(map, revision, segment_id, allowed_tiles, |components| {
+            components.is_reachable(src_tile_pos, dst_tile_pos)
+        })
+    }
+
+    pub fn filter_reachable(&self, map: &Map, revision: u64, segment_id: i64, src_tile_pos: Vec2i,
+                            dst_tile_positions: &[Vec2i], allowed_tiles: &impl TileSet) -> Vec<Vec2i> {
+        self.with_components(map, revision, segment_id, allowed_tiles, |components| {
+            dst_tile_positions.iter()
+                .filter(|&&dst_tile_pos| components.is_reachable(src_tile_pos, dst_tile_pos))
+                .cloned()
+                .collect()
+        })
+    }
+
+    pub fn get_landmarks(&self, map: &Map, revision: u64, segment_id: i64, allowed_tiles: &impl TileSet) -> Arc<Landmarks> {
+        self.with_components(map, revision, segment_id, allowed_tiles, |components| components.get_landmarks())
+    }
+
+    fn with_components<R, F: FnOnce(&mut Components) -> R>(&self, map: &Map, revision: u64, segment_id: i64,
+                                                          allowed_tiles: &impl TileSet, f: F) -> R {
+        let key = (segment_id, get_allowed_tile_ids(map, allowed_tiles));
+        let mut segments = self.segments.lock().unwrap();
+        let components = segments.entry(key)
+            .or_insert_with(|| {
+                let components = Components::new(map, revision, segment_id, allowed_tiles);
+                debug!("Reachability: built {} components for segment {}", components.parents.len() - 1, segment_id);
+                components
+            });
+        components.update(map, revision, segment_id, allowed_tiles);
+        f(components)
+    }
+}
+
+// Labels are merged with union-find. A changed grid is relabeled and merged with its neighbours again, but
+// connections made through its previous tiles are kept until the segment is rebuilt, so the result may be
+// reachable for tiles that are not connected anymore. Grids removed from the segment or moved trigger a rebuild.
+struct Components {
+    revision: u64,
+    grids: BTreeMap<i64, (Vec2i, i64)>,
+    labels: BTreeMap<Vec2i, Vec<usize>>,
+    parents: Vec<usize>,
+    open: Vec<bool>,
+    landmarks: Option<Arc<Landmarks>>,
+}
+
+impl Components {
+    fn new(map: &Map, revision: u64, segment_id: i64, allowed_tiles: &impl TileSet) -> Self {
+        let mut result = Self {
+            revision,
+            grids: get_segment_grids(map, segment_id),
+            labels: BTreeMap::new(),
+            parents: vec![0],
+            open: vec![false],
+            landmarks: None,
+        };
+        let grids: Vec<Vec2i> = result.grids.values().map(|(position, _)| *position).collect();
+        result.relabel(&SegmentTiles::new(map, segment_id, allowed_tiles), &grids);
+        result
+    }
+
+    fn update(&mut self, map: &Map, revision: u64, segment_id: i64, allowed_tiles: &impl TileSet) {
+        if self.revision == revision {
+            return;
+        }
+        let grids = get_segment_grids(map, segment_id);
+        let changed = match get_changed_grids(&self.grids, &grids) {
+            Some(v) => v,
+            None => {
+                *self = Self::new(map, revision, segment_id, allowed_tiles);
+                debug!("Reachability: rebuilt {} components for segment {}", self.parents.len() - 1, segment_id);
+                return;
+            }
+        };
+        self.revision = revision;
+        self.grids = grids;
+        if changed.is_empty() {
+            return;
+        }
+        self.relabel(&SegmentTiles::new(map, segment_id, allowed_tiles), &changed);
+        self.landmarks = None;
+        debug!("Reachability: relabeled {} grids for segment {}", changed.len(), segment_id);
+    }
+
+    fn relabel<T: TileSet>(&mut self, tiles: &SegmentTiles<T>, grids: &[Vec2i]) {
+        for &grid_pos in grids.iter() {
+            self.label_grid(tiles, grid_pos);
+        }
+        let mut border = Vec::new();
+        for &grid_pos in grids.iter() {
+            let origin = grid_pos_to_tile_pos(grid_pos);
+            for i in -1..=GRID_SIZE {
+                for &relative_tile_pos in [Vec2i::new(i, -1), Vec2i::new(i, 0), Vec2i::new(i, GRID_SIZE - 1), Vec2i::new(i, GRID_SIZE),
+                                           Vec2i::new(-1, i), Vec2i::new(0, i), Vec2i::new(GRID_SIZE - 1, i), Vec2i::new(GRID_SIZE, i)].iter() {
+                    border.push(origin + relative_tile_pos);
+                }
+            }
+        }
+        border.sort();
+        border.dedup();
+        for &tile_pos in border.iter() {
+            match (self.get_label(tile_pos), tiles.is_enterable(tile_pos)) {
+                (Some(0), true) => {
+                    let label = self.add_label();
+                    self.set_label(tile_pos, label);
+                }
+                (Some(v), false) if v != 0 => self.set_label(tile_pos, 0),
+                _ => (),
+            }
+        }
+        for &tile_pos in border.iter() {
+            self.connect(tiles, tile_pos);
+        }
+    }
+
+    fn label_grid<T: TileSet>(&mut self, tiles: &SegmentTiles<T>, grid_pos: Vec2i) {
+        let mut labels = vec![0; (GRID_SIZE * GRID_SIZE) as usize];
+        let mut queue = VecDeque::new();
+        for index in 0..labels.len() {
+            let tile_pos = grid_pos_to_tile_pos(grid_pos) + get_relative_tile_pos(index);
+            if labels[index] != 0 || !tiles.is_enterable(tile_pos) {
+                continue;
+            }
+            let label = self.add_label();
+            labels[index] = label;
+            queue.push_back(tile_pos);
+            while let Some(tile_pos) = queue.pop_front() {
+                for &shift in NEIGHBOURS.iter() {
+                    let next_tile_pos = tile_pos + shift;
+                    let next_grid_pos = tile_pos_to_grid_pos(next_tile_pos);
+                    if !tiles.contains_grid(next_grid_pos) {
+                        self.open[label] = true;
+                        continue;
+                    }
+                    if next_grid_pos != grid_pos || !tiles.is_valid_move(tile_pos, shift) {
+                        continue;
+                    }
+                    let next_index = get_tile_index(next_tile_pos - grid_pos_to_tile_pos(next_grid_pos));
+                    if labels[next_index] != 0 {
+                        continue;
+                    }
+                    labels[next_index] = label;
+                    queue.push_back(next_tile_pos);
+                }
+            }
+        }
+        self.labels.insert(grid_pos, labels);
+    }
+
+    fn connect<T: TileSet>(&mut self, tiles: &SegmentTiles<T>, tile_pos: Vec2i) {
+        let label = match self.get_label(tile_pos) {
+            Some(0) | None => return,
+            Some(v) => v,
+        };
+        for &shift in NEIGHBOURS.iter() {
+            match self.get_label(tile_pos + shift) {
+                None => {
+                    let root = find_root(&mut self.parents, label);
+                    self.open[root] = true;
+                }
+                Some(0) => (),
+                Some(next_label) => if tiles.is_valid_move(tile_pos, shift) {
+                    self.merge(label, next_label);
+                },
+            }
+        }
+    }
+
+    fn add_label(&mut self) -> usize {
+        let label = self.parents.len();
+        self.parents.push(label);
+        self.open.push(false);
+        label
+    }
+
+    fn merge(&mut self, lhs: usize, rhs: usize) {
+        let (lhs, rhs) = (find_root(&mut self.parents, lhs), find_root(&mut self.parents, rhs));
+        if lhs != rhs {
+            let (root, child) = (lhs.min(rhs), lhs.max(rhs));
+            self.parents[child] = root;
+            self.open[root] = self.open[root] || self.open[child];
+        }
+    }
+
+    fn get_label(&self, tile_pos: Vec2i) -> Option<usize> {
+        get_label(&self.labels, tile_pos)
+    }
+
+    fn set_label(&mut self, tile_pos: Vec2i, label: usize) {
+        let grid_pos = tile_pos_to_grid_pos(tile_pos);
+        if let Some(labels) = self.labels.get_mut(&grid_pos) {
+            labels[get_tile_index(tile_pos - grid_pos_to_tile_pos(grid_pos))] = label;
+        }
+    }
+
+    fn get_root(&mut self, tile_pos: Vec2i) -> Option<usize> {
+        match self.get_label(tile_pos) {
+            Some(0) => Some(0),
+            Some(v) => Some(find_root(&mut self.parents, v)),
+            None => None,
+        }
+    }
+
+    fn is_reachable(&mut self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i) -> bool {
+        if src_tile_pos == dst_tile_pos {
+            return true;
+        }
+        let dst_root = match self.get_root(dst_tile_pos) {
+            Some(0) => return false,
+            Some(v) => v,
+            None => return true,
+        };
+        let mut src_roots = Vec::with_capacity(NEIGHBOURS.len() + 1);
+        for tile_pos in std::iter::once(src_tile_pos).chain(NEIGHBOURS.iter().map(|v| src_tile_pos + *v)) {
+            match self.get_root(tile_pos) {
+                Some(0) => (),
+                Some(v) => src_roots.push(v),
+                None => return true,
+            }
+        }
+        src_roots.iter().any(|&v| v == dst_root || self.open[v] && self.open[dst_root])
+    }
+
+    fn get_landmarks(&mut self) -> Arc<Landmarks> {
+        if self.landmarks.is_none() {
+            let landmarks = Landmarks::new(&self.labels);
+            debug!("Reachability: found {} landmarks", landmarks.distances.len());
+            self.landmarks = Some(Arc::new(landmarks));
+        }
+        self.landmarks.clone().unwrap()
+    }
+}
+
+// Differential heuristic: the difference of distances to a landmark is a lower bound of the distance between
+// two tiles over the labeled tiles. A path going through unknown tiles or over tiles with weight less than 1
+// may cost less than the estimate, then a found path is not guaranteed to be the shortest one.
+pub struct Landmarks {
+    distances: Vec<BTreeMap<Vec2i, Vec<f32>>>,
+}
+
+impl Landmarks {
+    fn new(labels: &BTreeMap<Vec2i, Vec<usize>>) -> Self {
+        let mut distances: Vec<BTreeMap<Vec2i, Vec<f32>>> = Vec::new();
+        let mut landmark = labels.iter()
+            .find_map(|(grid_pos, labels)| {
+                labels.iter().position(|v| *v != 0).map(|index| grid_pos_to_tile_pos(*grid_pos) + get_relative_tile_pos(index))
+            });
+        while let Some(tile_pos) = landmark {
+            distances.push(get_distances(labels, tile_pos));
+            if distances.len() >= LANDMARKS_COUNT {
+                break;
+            }
+            landmark = find_farthest_tile(&distances);
+        }
+        Self { distances }
+    }
+
+    pub fn estimate(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i) -> Option<f64> {
+        self.distances.iter()
+            .filter_map(|distances| {
+                match (get_distance(distances, src_tile_pos), get_distance(distances, dst_tile_pos)) {
+                    (Some(src), Some(dst)) => Some((src - dst).abs() as f64),
+                    _ => None,
+                }
+            })
+            .fold(None, |result: Option<f64>, v| Some(result.map(|result| result.max(v)).unwrap_or(v)))
+    }
+}
+
+fn get_distances(labels: &BTreeMap<Vec2i, Vec<usize>>, landmark: Vec2i) -> BTreeMap<Vec2i, Vec<f32>> {
+    let mut result: BTreeMap<Vec2i, Vec<f32>> = labels.keys()
+        .map(|grid_pos| (*grid_pos, vec![std::f32::INFINITY; (GRID_SIZE * GRID_SIZE) as usize]))
+        .collect();
+    let mut ordered = BinaryHeap::new();
+    set_distance(&mut result, landmark, 0.0);
+    ordered.push((0, landmark));
+    while let Some((score, tile_pos)) = ordered.pop() {
+        let distance = match get_distance(&result, tile_pos) {
+            Some(v) => v,
+            None => continue,
+        };
+        if -score > as_score(distance as f64) {
+            continue;
+        }
+        for &shift in NEIGHBOURS.iter() {
+            let next_tile_pos = tile_pos + shift;
+            if get_label(labels, next_tile_pos).unwrap_or(0) == 0 {
+                continue;
+            }
+            let next_distance = distance + if shift.x() != 0 && shift.y() != 0 { std::f32::consts::SQRT_2 } else { 1.0 };
+            if next_distance < get_distance(&result, next_tile_pos).unwrap_or(std::f32::INFINITY) {
+                set_distance(&mut result, next_tile_pos, next_distance);
+                ordered.push((-as_score(next_distance as f64), next_tile_pos));
+            }
+        }
+    }
+    result
+}
+
+fn find_farthest_tile(distances: &[BTreeMap<Vec2i, Vec<f32>>]) -> Option<Vec2i> {
+    let mut result: Option<(f32, Vec2i)> = None;
+    for (grid_pos, values) in distances[0].iter() {
+        for index in 0..values.len() {
+            let distance = distances.iter()
+                .map(|v| v[grid_pos][index])
+                .fold(std::f32::INFINITY, f32::min);
+            if distance.is_finite() && distance > 0.0 && result.map(|(v, _)| distance > v).unwrap_or(true) {
+                result = Some((distance, grid_pos_to_tile_pos(*grid_pos) + get_relative_tile_pos(index)));
+            }
+        }
+    }
+    result.map(|(_, tile_pos)| tile_pos)
+}
+
+fn get_distance(distances: &BTreeMap<Vec2i, Vec<f32>>, tile_pos: Vec2i) -> Option<f32> {
+    let grid_pos = tile_pos_to_grid_pos(tile_pos);
+    distances.get(&grid_pos)
+        .map(|v| v[get_tile_index(tile_pos - grid_pos_to_tile_pos(grid_pos))])
+        .filter(|v| v.is_finite())
+}
+
+fn set_distance(distances: &mut BTreeMap<Vec2i, Vec<f32>>, tile_pos: Vec2i, value: f32) {
+    let grid_pos = tile_pos_to_grid_pos(tile_pos);
+    if let Some(v) = distances.get_mut(&grid_pos) {
+        v[get_tile_index(tile_pos - grid_pos_to_tile_pos(grid_pos))] = value;
+    }
+}
+
+fn get_label(labels: &BTreeMap<Vec2i, Vec<usize>>, tile_pos: Vec2i) -> Option<usize> {
+    let grid_pos = tile_pos_to_grid_pos(tile_pos);
+    labels.get(&grid_pos).map(|v| v[get_tile_index(tile_pos - grid_pos_to_tile_pos(grid_pos))])
+}
+
+fn get_segment_grids(map: &Map, segment_id: i64) -> BTreeMap<i64, (Vec2i, i64)> {
+    map.iter_segment_grids(segment_id).map(|v| (v.id, (v.position, v.revision))).collect()
+}
+
+// Returns positions of added and updated grids or None when a grid is removed or moved
+fn get_changed_grids(old: &BTreeMap<i64, (Vec2i, i64)>, new: &BTreeMap<i64, (Vec2i, i64)>) -> Option<Vec<Vec2i>> {
+    if old.iter().any(|(id, (position, _))| new.get(id).map(|(v, _)| v != position).unwrap_or(true)) {
+        return None;
+    }
+    Some(
+        new.iter()
+            .filter(|(id, (_, revision))| old.get(id).map(|(_, v)| v != revision).unwrap_or(true))
+            .map(|(_, (position, _))| *position)
+            .collect()
+    )
+}
+
+pub struct SegmentTiles<'a, T: TileSet> {
+    grids: BTreeMap<Vec2i, &'a Grid>,
+    allowed_tiles: &'a T,
+}
+
+impl<'a, T: TileSet> SegmentTiles<'a, T> {
+    pub fn new(map: &'a Map, segment_id: i64, allowed_tiles: &'a T) -> Self {
+        Self {
+            grids: map.iter_segment_grids(segment_id).map(|v| (v.position, v)).collect(),
+            allowed_tiles,
+        }
+    }
+
+    pub fn contains_grid(&self, grid_pos: Vec2i) -> bool {
+        self.grids.contains_key(&grid_pos)
+    }
+
+    pub fn is_enterable(&self, tile_pos: Vec2i) -> bool {
+        match self.get_tile(tile_pos) {
+            Some(Some(tile)) => self.allowed_tiles.contains(tile)
+                && self.is_reachable(tile_pos + Vec2i::only_x(1))
+                && self.is_reachable(tile_pos - Vec2i::only_x(1))
+                && self.is_reachable(tile_pos + Vec2i::only_y(1))
+                && self.is_reachable(tile_pos - Vec2i::only_y(1)),
+            _ => false,
+        }
+    }
+
+    pub fn is_valid_move(&self, tile_pos: Vec2i, shift: Vec2i) -> bool {
+        if shift.x() != 0 && shift.y() != 0
+            && (!self.is_reachable(tile_pos + shift.with_x(0)) || !self.is_reachable(tile_pos + shift.with_y(0))) {
+            return false;
+        }
+        self.is_enterable(tile_pos + shift)
+    }
+
+    fn is_reachable(&self, tile_pos: Vec2i) -> bool {
+        match self.get_tile(tile_pos) {
+            Some(Some(tile)) => self.allowed_tiles.contains(tile),
+            _ => true,
+        }
+    }
+
+    fn get_tile(&self, tile_pos: Vec2i) -> Option<Option<i32>> {
+        let grid_pos = tile_pos_to_grid_pos(tile_pos);
+        self.grids.get(&grid_pos)
+            .map(|grid| grid.tiles.get(get_tile_index(tile_pos - grid_pos_to_tile_pos(grid_pos))).cloned())
+    }
+}
+
+pub const NEIGHBOURS: &[Vec2i] = &[
+    Vec2i::new(-1, -1),
+    Vec2i::new(-1, 0),
+    Vec2i::new(-1, 1),
+    Vec2i::new(0, -1),
+    Vec2i::new(0, 1),
+    Vec2i::new(1, -1),
+    Vec2i::new(1, 0),
+    Vec2i::new(1, 1),
+];
+
+pub fn get_tile_index(relative_tile_pos: Vec2i) -> usize {
+    relative_tile_pos.x() as usize + relative_tile_pos.y() as usize * GRID_SIZE as usize
+}
+
+pub fn get_relative_tile_pos(index: usize) -> Vec2i {
+    Vec2i::new(index as i32 % GRID_SIZE, index as i32 / GRID_SIZE)
+}
+
+pub fn get_allowed_tile_ids(map: &Map, allowed_tiles: &impl TileSet) -> Vec<i32> {
+    map.iter_tiles().map(|v| v.id).filter(|v| allowed_tiles.contains(*v)).collect()
+}
+
+pub fn find_root(parents: &mut Vec<usize>, mut label: usize) -> usize {
+    while parents[label] != label {
+        parents[label] = parents[parents[label]];
+        label = parents[label];
+    }
+    label
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use rusqlite::Connection;
+
+    use crate::bot::map::{GridNeighbour, Tile};
+    use crate::bot::sqlite_map_db::SqliteMapDb;
+    use crate::bot::world::BTreeMapTileWeights;
+
+    use super::*;
+
+    fn make_grid(revision: i64, with_ring: bool) -> Grid {
+        make_grid_with_water(1, Vec2i::zero(), revision, |x, y| {
+            with_ring && ((x == 40 || x == 60) && (40..=60).contains(&y) || (y == 40 || y == 60) && (40..=60).contains(&x))
+        })
+    }
+
+    fn make_grid_with_water(id: i64, position: Vec2i, revision: i64, is_water: impl Fn(i32, i32) -> bool) -> Grid {
+        Grid {
+            id,
+            revision,
+            segment_id: id,
+            position,
+            heights: Vec::new(),
+            tiles: (0..GRID_SIZE * GRID_SIZE)
+                .map(|index| if is_water(index % GRID_SIZE, index / GRID_SIZE) { 2 } else { 1 })
+                .collect(),
+        }
+    }
+
+    fn make_map() -> Map {
+        let mut map = Map::new(Arc::new(Mutex::new(SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default()))), 20.0);
+        map.set_tile(Tile { id: 1, version: 1, name: String::from("grass"), color: 0 });
+        map.set_tile(Tile { id: 2, version: 1, name: String::from("water"), color: 0 });
+        map
+    }
+
+    #[test]
+    fn enclosed_tiles_should_not_be_reachable_from_outside() {
+        let mut map = make_map();
+        map.add_grid(make_grid(1, true), Vec::new());
+        let weights: BTreeMap<i32, f64> = vec![(1, 1.0)].into_iter().collect();
+        let reachability = Reachability::new();
+        let is_reachable = |map: &Map, revision: u64, src: Vec2i, dst: Vec2i| {
+            reachability.is_reachable(map, revision, 1, src, dst, &BTreeMapTileWeights(&weights))
+        };
+        assert!(!is_reachable(&map, 1, Vec2i::new(50, 50), Vec2i::new(10, 10)));
+        assert!(!is_reachable(&map, 1, Vec2i::new(10, 10), Vec2i::new(50, 50)));
+        assert!(!is_reachable(&map, 1, Vec2i::new(10, 10), Vec2i::new(40, 50)));
+        assert!(is_reachable(&map, 1, Vec2i::new(45, 45), Vec2i::new(55, 55)));
+        assert!(is_reachable(&map, 1, Vec2i::new(10, 10), Vec2i::new(90, 90)));
+        assert!(is_reachable(&map, 1, Vec2i::new(10, 10), Vec2i::new(150, 10)));
+        map.update_grid(make_grid(2, false));
+        assert!(!is_reachable(&map, 1, Vec2i::new(50, 50), Vec2i::new(10, 10)));
+        assert!(is_reachable(&map, 2, Vec2i::new(50, 50), Vec2i::new(10, 10)));
+    }
+
+    #[test]
+    fn added_and_updated_grids_should_be_relabeled() {
+        let mut map = make_map();
+        map.add_grid(make_grid_with_water(1, Vec2i::zero(), 1, |_, _| false), Vec::new());
+        let weights: BTreeMap<i32, f64> = vec![(1, 1.0)].into_iter().collect();
+        let reachability = Reachability::new();
+        let is_reachable = |map: &Map, revision: u64, src: Vec2i, dst: Vec2i| {
+            reachability.is_reachable(map, revision, 1, src, dst, &BTreeMapTileWeights(&weights))
+        };
+        assert!(is_reachable(&map, 1, Vec2i::new(10, 10), Vec2i::new(50, GRID_SIZE + 50)));
+        let is_ring = |x: i32, y: i32| {
+            (x == 40 || x == 60) && (40..=60).contains(&y) || (y == 40 || y == 60) && (40..=60).contains(&x)
+        };
+        map.add_grid(
+            make_grid_with_water(2, Vec2i::zero(), 1, |x, y| y == 0 || is_ring(x, y)),
+            vec![GridNeighbour { id: 1, offset: Vec2i::new(0, -1) }],
+        );
+        assert!(!is_reachable(&map, 2, Vec2i::new(10, 10), Vec2i::new(50, GRID_SIZE + 50)));
+        assert!(!is_reachable(&map, 2, Vec2i::new(10, 10), Vec2i::new(10, GRID_SIZE - 1)));
+        assert!(is_reachable(&map, 2, Vec2i::new(10, 10), Vec2i::new(10, GRID_SIZE + 10)));
+        map.update_grid(Grid { segment_id: 1, ..make_grid_with_water(2, Vec2i::new(0, 1), 2, is_ring) });
+        assert!(is_reachable(&map, 3, Vec2i::new(10, 10), Vec2i::new(10, GRID_SIZE - 1)));
+        assert!(is_reachable(&map, 3, Vec2i::new(10, GRID_SIZE - 1), Vec2i::new(10, GRID_SIZE + 10)));
+        assert!(!is_reachable(&map, 3, Vec2i::new(10, 10), Vec2i::new(50, GRID_SIZE + 50)));
+    }
+
+    #[test]
+    fn landmarks_should_estimate_path_cost_not_greater_than_path_length() {
+        let mut map = make_map();
+        map.add_grid(make_grid_with_water(1, Vec2i::zero(), 1, |x, y| x == 50 && y < 90), Vec::new());
+        let weights: BTreeMap<i32, f64> = vec![(1, 1.0)].into_iter().collect();
+        let reachability = Reachability::new();
+        let landmarks = reachability.get_landmarks(&map, 1, 1, &BTreeMapTileWeights(&weights));
+        let straight = landmarks.estimate(Vec2i::new(10, 10), Vec2i::new(10, 50)).unwrap();
+        assert!((straight - 40.0).abs() < 1e-3, "{}", straight);
+        let around_wall = landmarks.estimate(Vec2i::new(45, 10), Vec2i::new(55, 10)).unwrap();
+        assert!(around_wall > 10.0, "{}", around_wall);
+        assert!(around_wall <= 2.0 * 81.0 + 10.0, "{}", around_wall);
+        assert_eq!(landmarks.estimate(Vec2i::new(10, 10), Vec2i::new(50, 10)), None);
+        assert!(Arc::ptr_eq(&landmarks, &reachability.get_landmarks(&map, 1, 1, &BTreeMapTileWeights(&weights))));
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+pub const ROAD_PROFILE: &'static str = "road";
+
+#[derive(Clone, Deserialize)]
+pub struct RoadsConfig {
+    pub tiles: Vec<String>,
+    pub base_profile: String,
+    pub cost_factor: f64,
+    pub min_travel_distance: f64,
+    pub max_snap_distance: f64,
+}
+
+impl RoadsConfig {
+    pub fn is_road_tile(&self, name: &str) -> bool {
+        self.tiles.iter().any(|v| name.starts_with(v.as_str()))
+    }
+}
+
+pub fn make_road_profile<'a>(base: &HashMap<String, f64>, tile_names: impl Iterator<Item=&'a String>,
+                             config: &RoadsConfig) -> HashMap<String, f64> {
+    let min_cost = base.values().cloned().fold(f64::INFINITY, f64::min);
+    let road_cost = if min_cost.is_finite() { min_cost } else { 1.0 };
+    let mut result = base.clone();
+    for name in tile_names.filter(|v| config.is_road_tile(v.as_str())) {
+        let cost = base.get(name).cloned().unwrap_or(road_cost);
+        result.insert(name.clone(), cost * config.cost_factor);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_road_profile_should_discount_matching_tiles() {
+        let config = RoadsConfig {
+            tiles: vec![String::from("gfx/tiles/paving/"), String::from("gfx/tiles/road")],
+            base_profile: String::from("walk"),
+            cost_factor: 0.5,
+            min_travel_distance: 1100.0,
+            max_snap_distance: 220.0,
+        };
+        let base: HashMap<String, f64> = vec![
+            (String::from("gfx/tiles/grass"), 1.0),
+            (String::from("gfx/tiles/mountain"), 2.0),
+            (String::from("gfx/tiles/roadmud"), 3.0),
+        ].into_iter().collect();
+        let names = vec![
+            String::from("gfx/tiles/grass"),
+            String::from("gfx/tiles/paving/cobble"),
+            String::from("gfx/tiles/roadmud"),
+        ];
+        let profile = make_road_profile(&base, names.iter(), &config);
+        assert_eq!(profile.get("gfx/tiles/grass"), Some(&1.0));
+        assert_eq!(profile.get("gfx/tiles/mountain"), Some(&2.0));
+        assert_eq!(profile.get("gfx/tiles/paving/cobble"), Some(&0.5));
+        assert_eq!(profile.get("gfx/tiles/roadmud"), Some(&1.5));
+    }
+}
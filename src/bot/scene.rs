@@ -3,13 +3,14 @@ use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use graphics::{Ellipse, Image, Line, Polygon, Rectangle, Transformed};
+use graphics::{Ellipse, Graphics, Image, ImageSize, Line, Polygon, Rectangle, Transformed};
 use graphics::character::CharacterCache;
 use graphics::math::{Matrix2d, Vec2d};
 use graphics::rectangle::rectangle_by_corners;
 use graphics::text::Text;
 use graphics::types;
-use opengl_graphics::{GlGraphics, GlyphCache, Texture};
+use image::RgbaImage;
+use opengl_graphics::{Filter, Texture, TextureSettings};
 
 use crate::bot::math::as_score;
 use crate::bot::vec2::Vec2f;
@@ -18,6 +19,7 @@ use crate::bot::vec2::Vec2f;
 pub struct Scene {
     id_counter: Arc<AtomicUsize>,
     nodes: Arc<Mutex<BTreeMap<usize, Arc<Mutex<Node>>>>>,
+    frame: Arc<Mutex<Option<RgbaImage>>>,
 }
 
 impl Scene {
@@ -25,6 +27,7 @@ impl Scene {
         Self {
             id_counter: Arc::new(AtomicUsize::new(0)),
             nodes: Arc::new(Mutex::new(BTreeMap::new())),
+            frame: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -41,6 +44,14 @@ impl Scene {
     pub fn nodes(&self) -> Arc<Mutex<BTreeMap<usize, Arc<Mutex<Node>>>>> {
         self.nodes.clone()
     }
+
+    pub fn set_frame(&self, frame: RgbaImage) {
+        *self.frame.lock().unwrap() = Some(frame);
+    }
+
+    pub fn get_frame(&self) -> Option<RgbaImage> {
+        self.frame.lock().unwrap().clone()
+    }
 }
 
 pub struct Layer {
@@ -88,7 +99,8 @@ pub enum Node {
 }
 
 impl Node {
-    pub fn draw(&self, context: &Context, transform: Matrix2d, cache: &mut GlyphCache, g: &mut GlGraphics) -> usize {
+    pub fn draw<G, C>(&self, context: &Context, transform: Matrix2d, cache: &mut C, g: &mut G) -> usize
+        where G: Graphics, G::Texture: SceneTexture, C: CharacterCache<Texture = G::Texture> {
         match self {
             Node::Empty => 0,
             Node::CompositeVec(v) => v.draw(context, transform, cache, g),
@@ -140,7 +152,8 @@ pub struct CompositeVecNode {
 }
 
 impl CompositeVecNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, cache: &mut GlyphCache, g: &mut GlGraphics) -> usize {
+    fn draw<G, C>(&self, context: &Context, transform: Matrix2d, cache: &mut C, g: &mut G) -> usize
+        where G: Graphics, G::Texture: SceneTexture, C: CharacterCache<Texture = G::Texture> {
         self.nodes.iter().map(|node| node.draw(context, transform, cache, g)).sum()
     }
 }
@@ -151,7 +164,8 @@ pub struct CompositeBTreeMapNode {
 }
 
 impl CompositeBTreeMapNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, cache: &mut GlyphCache, g: &mut GlGraphics) -> usize {
+    fn draw<G, C>(&self, context: &Context, transform: Matrix2d, cache: &mut C, g: &mut G) -> usize
+        where G: Graphics, G::Texture: SceneTexture, C: CharacterCache<Texture = G::Texture> {
         self.nodes.values().map(|node| node.draw(context, transform, cache, g)).sum()
     }
 }
@@ -171,7 +185,8 @@ pub struct MapTransformVecNode {
 trait AsMapTransformNode {
     fn with_node<F: FnMut(&Node) -> usize>(&self, f: F) -> usize;
 
-    fn draw(&self, context: &Context, transform: Matrix2d, cache: &mut GlyphCache, g: &mut GlGraphics) -> usize {
+    fn draw<G, C>(&self, context: &Context, transform: Matrix2d, cache: &mut C, g: &mut G) -> usize
+        where G: Graphics, G::Texture: SceneTexture, C: CharacterCache<Texture = G::Texture> {
         if let Some(viewport) = context.base.viewport.as_ref() {
             let viewport_shift = Vec2f::new(viewport.window_size[0], viewport.window_size[1]) / 2.0;
             self.with_node(|node| {
@@ -210,7 +225,8 @@ impl AsMapTransformNode for MapTransformVecNode {
 }
 
 impl MapTransformBoxNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, cache: &mut GlyphCache, g: &mut GlGraphics) -> usize {
+    fn draw<G, C>(&self, context: &Context, transform: Matrix2d, cache: &mut C, g: &mut G) -> usize
+        where G: Graphics, G::Texture: SceneTexture, C: CharacterCache<Texture = G::Texture> {
         if let Some(viewport) = context.base.viewport.as_ref() {
             let viewport_shift = Vec2f::new(viewport.window_size[0], viewport.window_size[1]) / 2.0;
             self.node.draw(
@@ -237,9 +253,10 @@ pub struct DebugTextNode {
 }
 
 impl DebugTextNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, cache: &mut GlyphCache, g: &mut GlGraphics) -> usize {
+    fn draw<G, C>(&self, context: &Context, transform: Matrix2d, cache: &mut C, g: &mut G) -> usize
+        where G: Graphics, G::Texture: SceneTexture, C: CharacterCache<Texture = G::Texture> {
         let max_width = self.lines.iter()
-            .map(|line| cache.width(self.value.font_size, line.as_str()).unwrap())
+            .map(|line| cache.width(self.value.font_size, line.as_str()).unwrap_or(0.0))
             .max_by_key(|v| as_score(*v));
         if let Some(width) = max_width {
             let transform = transform.append_transform(self.transform);
@@ -262,7 +279,7 @@ impl DebugTextNode {
                     &context.base.draw_state,
                     transform.trans(0.0, ((self.value.font_size + self.margin) * (n + 1) as u32) as f64),
                     g,
-                ).unwrap();
+                ).ok();
                 count += 1;
             }
             count
@@ -279,7 +296,7 @@ pub struct RectangleNode {
 }
 
 impl RectangleNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, g: &mut GlGraphics) -> usize {
+    fn draw<G: Graphics>(&self, context: &Context, transform: Matrix2d, g: &mut G) -> usize {
         self.value.draw(
             self.rectangle,
             &context.base.draw_state,
@@ -297,7 +314,7 @@ pub struct EllipseNode {
 }
 
 impl EllipseNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, g: &mut GlGraphics) -> usize {
+    fn draw<G: Graphics>(&self, context: &Context, transform: Matrix2d, g: &mut G) -> usize {
         self.value.draw(
             self.rectangle,
             &context.base.draw_state,
@@ -315,14 +332,15 @@ pub struct TextNode {
 }
 
 impl TextNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, cache: &mut GlyphCache, g: &mut GlGraphics) -> usize {
+    fn draw<G, C>(&self, context: &Context, transform: Matrix2d, cache: &mut C, g: &mut G) -> usize
+        where G: Graphics, G::Texture: SceneTexture, C: CharacterCache<Texture = G::Texture> {
         self.value.draw(
             self.text.as_str(),
             cache,
             &context.base.draw_state,
             transform.append_transform(self.transform),
             g,
-        ).unwrap();
+        ).ok();
         1
     }
 }
@@ -334,7 +352,7 @@ pub struct LineNode {
 }
 
 impl LineNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, g: &mut GlGraphics) -> usize {
+    fn draw<G: Graphics>(&self, context: &Context, transform: Matrix2d, g: &mut G) -> usize {
         self.value.draw(
             self.line,
             &context.base.draw_state,
@@ -353,7 +371,7 @@ pub struct ArrowNode {
 }
 
 impl ArrowNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, g: &mut GlGraphics) -> usize {
+    fn draw<G: Graphics>(&self, context: &Context, transform: Matrix2d, g: &mut G) -> usize {
         self.value.draw_arrow(
             self.line,
             self.head_size,
@@ -367,22 +385,56 @@ impl ArrowNode {
 
 pub struct ImageNode {
     pub value: Image,
-    pub texture: Arc<Mutex<Texture>>,
+    pub image: Arc<SceneImage>,
     pub transform: Matrix2d,
 }
 
 impl ImageNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, g: &mut GlGraphics) -> usize {
-        self.value.draw(
-            self.texture.lock().unwrap().deref(),
-            &context.base.draw_state,
-            transform.append_transform(self.transform),
-            g,
-        );
+    fn draw<G>(&self, context: &Context, transform: Matrix2d, g: &mut G) -> usize
+        where G: Graphics, G::Texture: SceneTexture {
+        G::Texture::with_scene_image(&self.image, |texture| {
+            self.value.draw(
+                texture,
+                &context.base.draw_state,
+                transform.append_transform(self.transform),
+                g,
+            )
+        });
         1
     }
 }
 
+pub struct SceneImage {
+    image: Arc<RgbaImage>,
+    texture: Mutex<Option<Texture>>,
+}
+
+impl SceneImage {
+    pub fn new(image: RgbaImage) -> Self {
+        Self {
+            image: Arc::new(image),
+            texture: Mutex::new(None),
+        }
+    }
+
+    pub fn image(&self) -> &Arc<RgbaImage> {
+        &self.image
+    }
+}
+
+pub trait SceneTexture: ImageSize + Sized {
+    fn with_scene_image<F: FnOnce(&Self)>(image: &SceneImage, f: F);
+}
+
+impl SceneTexture for Texture {
+    fn with_scene_image<F: FnOnce(&Self)>(image: &SceneImage, f: F) {
+        let mut texture = image.texture.lock().unwrap();
+        f(texture.get_or_insert_with(|| {
+            Texture::from_image(&image.image, &TextureSettings::new().filter(Filter::Nearest))
+        }))
+    }
+}
+
 pub struct PolygonNode {
     pub value: Polygon,
     pub polygon: Vec<Vec2d>,
@@ -390,7 +442,7 @@ pub struct PolygonNode {
 }
 
 impl PolygonNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, g: &mut GlGraphics) -> usize {
+    fn draw<G: Graphics>(&self, context: &Context, transform: Matrix2d, g: &mut G) -> usize {
         self.value.draw(
             self.polygon.as_slice(),
             &context.base.draw_state,
@@ -408,7 +460,7 @@ pub struct TriangleNode {
 }
 
 impl TriangleNode {
-    fn draw(&self, context: &Context, transform: Matrix2d, g: &mut GlGraphics) -> usize {
+    fn draw<G: Graphics>(&self, context: &Context, transform: Matrix2d, g: &mut G) -> usize {
         self.value.draw(
             &self.triangle,
             &context.base.draw_state,
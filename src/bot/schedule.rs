@@ -0,0 +1,256 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{JoinHandle, sleep, spawn};
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+
+use crate::bot::map::as_unix_time;
+
+const CONTINUOUS: &str = "@continuous";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScheduleConfig {
+    pub interval: f64,
+    pub entries: Vec<ScheduleEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScheduleEntry {
+    pub name: String,
+    pub session: i64,
+    pub task: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+    pub schedule: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schedule {
+    Continuous,
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value.trim() == CONTINUOUS {
+            return Ok(Schedule::Continuous);
+        }
+        CronSchedule::parse(value).map(Schedule::Cron)
+    }
+}
+
+// Classic 5 fields cron expression evaluated in UTC: minute, hour, day of month, month, day of week.
+// Unlike cron all fields have to match, including day of month and day of week.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+}
+
+impl CronSchedule {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("Cron expression \"{}\" has {} fields instead of 5", value, fields.len()));
+        }
+        Ok(Self {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days: parse_cron_field(fields[2], 1, 31)?,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            weekdays: parse_cron_field(fields[4], 0, 6)?,
+        })
+    }
+
+    pub fn matches(&self, unix_time: i64) -> bool {
+        let days = unix_time.div_euclid(86400);
+        let seconds = unix_time.rem_euclid(86400);
+        let (_, month, day) = civil_from_days(days);
+        // 1970-01-01 is Thursday
+        let weekday = (days + 4).rem_euclid(7);
+        has_bit(self.minutes, seconds / 60 % 60)
+            && has_bit(self.hours, seconds / 3600)
+            && has_bit(self.days, day)
+            && has_bit(self.months, month)
+            && has_bit(self.weekdays, weekday)
+    }
+}
+
+pub struct Scheduler {
+    entries: Vec<ScheduledEntry>,
+}
+
+struct ScheduledEntry {
+    entry: ScheduleEntry,
+    schedule: Schedule,
+    last_minute: Option<i64>,
+}
+
+impl Scheduler {
+    pub fn new(entries: Vec<ScheduleEntry>) -> Result<Self, String> {
+        let mut result = Vec::with_capacity(entries.len());
+        for entry in entries.into_iter() {
+            let schedule = Schedule::parse(entry.schedule.as_str())
+                .map_err(|e| format!("Invalid schedule entry \"{}\": {}", entry.name, e))?;
+            result.push(ScheduledEntry { entry, schedule, last_minute: None });
+        }
+        Ok(Self { entries: result })
+    }
+
+    pub fn get_due(&mut self, unix_time: i64) -> Vec<&ScheduleEntry> {
+        let minute = unix_time.div_euclid(60);
+        let mut result = Vec::new();
+        for scheduled in self.entries.iter_mut() {
+            let due = match &scheduled.schedule {
+                Schedule::Continuous => true,
+                Schedule::Cron(cron) => scheduled.last_minute != Some(minute) && cron.matches(unix_time),
+            };
+            if due {
+                scheduled.last_minute = Some(minute);
+                result.push(&scheduled.entry);
+            }
+        }
+        result
+    }
+}
+
+pub fn start_schedule<F>(config: ScheduleConfig, stop: Arc<AtomicBool>, run: F) -> Result<Option<JoinHandle<()>>, String>
+    where F: Fn(&ScheduleEntry) + Send + 'static {
+    if config.entries.is_empty() {
+        return Ok(None);
+    }
+    let mut scheduler = Scheduler::new(config.entries)?;
+    let interval = Duration::from_secs_f64(config.interval);
+    Ok(Some(spawn(move || {
+        while !stop.load(Ordering::Relaxed) {
+            for entry in scheduler.get_due(as_unix_time(SystemTime::now())) {
+                run(entry);
+            }
+            sleep(interval);
+        }
+    })))
+}
+
+fn parse_cron_field(value: &str, min: i64, max: i64) -> Result<u64, String> {
+    let mut result = 0;
+    for part in value.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(index) => (&part[0..index], parse_cron_number(&part[index + 1..])?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format!("Cron field \"{}\" has zero step", value));
+        }
+        let (begin, end) = if range == "*" {
+            (min, max)
+        } else {
+            match range.find('-') {
+                Some(index) => (parse_cron_number(&range[0..index])?, parse_cron_number(&range[index + 1..])?),
+                None => {
+                    let begin = parse_cron_number(range)?;
+                    (begin, if step == 1 { begin } else { max })
+                }
+            }
+        };
+        if begin < min || end > max || begin > end {
+            return Err(format!("Cron field \"{}\" is out of range {}-{}", value, min, max));
+        }
+        let mut current = begin;
+        while current <= end {
+            result |= 1 << current;
+            current += step;
+        }
+    }
+    Ok(result)
+}
+
+fn parse_cron_number(value: &str) -> Result<i64, String> {
+    value.parse::<i64>().map_err(|e| format!("Invalid cron number \"{}\": {}", value, e))
+}
+
+fn has_bit(mask: u64, index: i64) -> bool {
+    mask & (1 << index) != 0
+}
+
+// Converts days since 1970-01-01 into (year, month, day) of the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_entry(name: &str, schedule: &str) -> ScheduleEntry {
+        ScheduleEntry {
+            name: String::from(name),
+            session: 1,
+            task: String::from("UiJanitor"),
+            params: serde_json::Value::Null,
+            schedule: String::from(schedule),
+        }
+    }
+
+    #[test]
+    fn civil_from_days_should_convert_unix_days_to_date() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(951868740 / 86400), (2000, 2, 29));
+        assert_eq!(civil_from_days(1615730400 / 86400), (2021, 3, 14));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn cron_schedule_should_match_time_fields() {
+        // 2021-03-14 14:00 UTC, Sunday
+        let time = 1615730400;
+        assert!(CronSchedule::parse("0 */2 * * *").unwrap().matches(time));
+        assert!(!CronSchedule::parse("0 */2 * * *").unwrap().matches(time + 3600));
+        assert!(!CronSchedule::parse("0 */2 * * *").unwrap().matches(time + 60));
+        assert!(CronSchedule::parse("0-5,30 14 14 3 0").unwrap().matches(time + 5 * 60));
+        assert!(!CronSchedule::parse("* * * * 1-6").unwrap().matches(time));
+        // 2000-02-29 23:59 UTC, Tuesday
+        assert!(CronSchedule::parse("59 23 29 2 2").unwrap().matches(951868740));
+    }
+
+    #[test]
+    fn cron_schedule_should_reject_invalid_expression() {
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("5-1 * * * *").is_err());
+        assert!(CronSchedule::parse("a * * * *").is_err());
+        assert_eq!(Schedule::parse("@continuous"), Ok(Schedule::Continuous));
+    }
+
+    #[test]
+    fn scheduler_should_run_cron_entry_once_per_matching_minute() {
+        let mut scheduler = Scheduler::new(vec![
+            make_entry("water", "0 */2 * * *"),
+            make_entry("janitor", "@continuous"),
+        ]).unwrap();
+        let time = 1615730400;
+        let names = |entries: Vec<&ScheduleEntry>| entries.into_iter().map(|v| v.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(scheduler.get_due(time)), vec!["water", "janitor"]);
+        assert_eq!(names(scheduler.get_due(time + 30)), vec!["janitor"]);
+        assert_eq!(names(scheduler.get_due(time + 60)), vec!["janitor"]);
+        assert_eq!(names(scheduler.get_due(time + 2 * 3600)), vec!["water", "janitor"]);
+    }
+
+    #[test]
+    fn scheduler_should_reject_invalid_entry() {
+        assert!(Scheduler::new(vec![make_entry("water", "0 25 * * *")]).is_err());
+    }
+}
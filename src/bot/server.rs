@@ -1,85 +1,353 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Deref;
 use std::path::Path;
 use std::sync::{Arc, Mutex, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::JoinHandle;
 use std::time::Duration;
 
-use actix_web::{Error, HttpResponse, web};
+use actix_service::Service;
+use actix_web::{Error, HttpRequest, HttpResponse, web};
 use actix_web::dev::Server;
+use actix_web::http::header;
+use futures::future::{Either, ok, pending, select};
 use futures::StreamExt;
 use rusqlite::Connection;
 use serde::Deserialize;
 
-use crate::bot::map_db::MapDb;
-use crate::bot::process::{add_session_visualization, count_updates, ProcessConfig, push_update, start_process_session, UpdatesQueue};
+use crate::bot::alerting::{Alerter, AlertingConfig, start_alerting};
+use crate::bot::anchors::Anchors;
+use crate::bot::area_objects::Area;
+use crate::bot::auth::AuthConfig;
+use crate::bot::bookmarks::{Bookmark, validate_bookmark};
+use crate::bot::cancel_tokens::CancelTokens;
+use crate::bot::capabilities::{negotiate, SUPPORTED_PROTOCOL_VERSIONS};
+use crate::bot::exploration_claims::ExplorationClaims;
+#[cfg(feature = "fault_injection")]
+use crate::bot::fault_injection::{Faults, FaultsParams, FaultyMapDb};
+#[cfg(feature = "grpc")]
+use crate::bot::grpc::start_grpc_server;
+use crate::bot::map::{pos_to_grid_pos, pos_to_tile_pos};
+use crate::bot::map_db::{MapDb, MapDbBackend, MapDbConfig};
+use crate::bot::map_export::export_map_png;
+use crate::bot::map_import::{import_map_dump, MapDumpFile, read_map_dump_dir};
+use crate::bot::map_query::{get_segment_grids, get_segment_tile, get_segment_tile_stats, GridInfo, TileInfo, TileStats};
+use crate::bot::map_replication::{apply_map_changes, get_map_changes, MapChanges, MapReplicationConfig, MapReplicationRole, start_map_replication};
+use crate::bot::map_retention::{MapRetentionConfig, prune_map, start_map_pruning};
+use crate::bot::message_queue::MessageQueue;
+use crate::bot::metrics::{Metrics, write_session_values, write_value};
+use crate::bot::offscreen::encode_png;
+#[cfg(feature = "postgres_map_db")]
+use crate::bot::postgres_map_db::PostgresMapDb;
+use crate::bot::process::{add_session_visualization, count_updates, ProcessConfig, push_update, read_logged_updates, read_session_data, read_updates_log, start_process_session, start_replay, UpdatesQueue};
 use crate::bot::protocol::{Event, Message, SessionInfo, Update};
-use crate::bot::session::{Session, SessionConfig, SessionData};
+use crate::bot::schedule::{ScheduleConfig, ScheduleEntry, start_schedule};
+use crate::bot::session::{FindPathParams, LineOfSightParams, Session, SessionConfig, SessionData};
+use crate::bot::session_diff::make_session_diff;
+use crate::bot::session_stats::{DeliveryChannel, SessionStats};
 use crate::bot::sqlite_map_db::SqliteMapDb;
-use crate::bot::visualization::VisualizationConfig;
+use crate::bot::tile_profiles::TileProfiles;
+use crate::bot::update_journal::UpdateJournals;
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::visualization::{CombinedVisualization, VisualizationConfig};
+use crate::bot::websocket::WebSocketConnection;
+use crate::bot::zones::{validate_zone, Zone, ZoneInfo};
+
+const MAX_WORLD_SNAPSHOT_RADIUS: i32 = 250;
+const DEFAULT_MAX_OBJECT_DISTANCE: f64 = 1.0;
 
 #[derive(Clone)]
-struct State {
+pub(crate) struct State {
     updates: Arc<Mutex<HashMap<i64, Arc<UpdatesQueue>>>>,
-    messages: Arc<Mutex<HashMap<i64, Arc<Mutex<VecDeque<Message>>>>>>,
+    messages: Arc<Mutex<HashMap<i64, Arc<Mutex<MessageQueue>>>>>,
     sessions: Arc<Mutex<HashMap<i64, Arc<RwLock<Session>>>>>,
+    replays: Arc<Mutex<BTreeSet<i64>>>,
     processors: Arc<Mutex<HashMap<i64, JoinHandle<()>>>>,
     visualizers: Arc<Mutex<HashMap<i64, Arc<Mutex<Vec<JoinHandle<()>>>>>>>,
+    combined_visualization: Arc<CombinedVisualization>,
     map_db: Arc<Mutex<dyn MapDb + Send>>,
-    cancels: Arc<Mutex<HashMap<i64, Arc<AtomicBool>>>>,
+    cancels: Arc<Mutex<HashMap<i64, Arc<CancelTokens>>>>,
+    stop: Arc<AtomicBool>,
     process_config: ProcessConfig,
     session_config: SessionConfig,
     visualization_config: VisualizationConfig,
+    map_replication_config: MapReplicationConfig,
+    map_retention_config: Option<MapRetentionConfig>,
+    metrics: Arc<Metrics>,
+    exploration_claims: Arc<ExplorationClaims>,
+    tile_profiles: Arc<TileProfiles>,
+    alerter: Arc<Alerter>,
+    update_journals: Arc<UpdateJournals>,
+    #[cfg(feature = "fault_injection")]
+    faults: Arc<Faults>,
 }
 
-pub fn run_server(config: ServerConfig) -> std::io::Result<Server> {
+pub fn run_server(config: ServerConfig) -> std::io::Result<(Server, Shutdown)> {
     use actix_web::{middleware, App, HttpServer};
 
+    let map_db = make_map_db(&config.map_db);
+    #[cfg(feature = "fault_injection")]
+    let faults = Arc::new(Faults::new());
+    #[cfg(feature = "fault_injection")]
+    let map_db: Arc<Mutex<dyn MapDb + Send>> = Arc::new(Mutex::new(FaultyMapDb::new(map_db, faults.clone())));
+    let stop = Arc::new(AtomicBool::new(false));
+    let map_replication = start_map_replication(map_db.clone(), config.map_replication.clone(), stop.clone());
+    start_map_pruning(map_db.clone(), config.map_db.retention.clone());
+    let update_journals = Arc::new(UpdateJournals::new(config.process.sessions_path.clone(), config.process.journal_updates));
+    let tile_profiles = Arc::new(TileProfiles::new(config.session.world()));
     let state = State {
         updates: Arc::new(Mutex::new(HashMap::new())),
         messages: Arc::new(Mutex::new(HashMap::new())),
         sessions: Arc::new(Mutex::new(HashMap::new())),
+        replays: Arc::new(Mutex::new(BTreeSet::new())),
         processors: Arc::new(Mutex::new(HashMap::new())),
         visualizers: Arc::new(Mutex::new(HashMap::new())),
-        map_db: Arc::new(Mutex::new(SqliteMapDb::new(
-            Connection::open(config.map_db_path).unwrap(),
-            Duration::from_secs_f64(config.map_cache_ttl),
-        ))),
+        combined_visualization: Arc::new(CombinedVisualization::new()),
+        map_db,
         cancels: Arc::new(Mutex::new(HashMap::new())),
+        stop,
         process_config: config.process,
         session_config: config.session,
         visualization_config: config.visualization,
+        map_replication_config: config.map_replication,
+        map_retention_config: config.map_db.retention.clone(),
+        metrics: Arc::new(Metrics::new()),
+        exploration_claims: Arc::new(ExplorationClaims::new()),
+        tile_profiles,
+        alerter: Arc::new(start_alerting(config.alerting)),
+        update_journals,
+        #[cfg(feature = "fault_injection")]
+        faults,
+    };
+
+    #[cfg(feature = "grpc")]
+    let grpc_server = match config.grpc.bind_addr {
+        Some(bind_addr) => Some(start_grpc_server(
+            state.clone(),
+            bind_addr,
+            config.auth.clone(),
+            Duration::from_secs_f64(config.grpc.poll_interval),
+            state.stop.clone(),
+        )),
+        None => None,
+    };
+    #[cfg(not(feature = "grpc"))]
+    let grpc_server: Option<JoinHandle<()>> = match config.grpc.bind_addr {
+        Some(bind_addr) => panic!("Failed to start gRPC server on {}: grpc feature is disabled", bind_addr),
+        None => None,
     };
 
-    Ok(HttpServer::new(move || {
-        App::new()
+    let schedule_state = state.clone();
+    let schedule = match start_schedule(config.schedule, state.stop.clone(), move |entry| run_schedule_entry(&schedule_state, entry)) {
+        Ok(v) => v,
+        Err(e) => panic!("Failed to start schedule: {}", e),
+    };
+
+    let shutdown = Shutdown { state: state.clone(), grpc_server, schedule, map_replication };
+    let auth = config.auth;
+
+    let server = HttpServer::new(move || {
+        let auth = auth.clone();
+        let app = App::new()
             .data(state.clone())
+            .wrap_fn(move |req, srv| {
+                let authorization = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+                match auth.check(req.method(), req.path(), authorization) {
+                    Ok(_) => Either::Left(srv.call(req)),
+                    Err(e) => {
+                        warn!("Reject request: {}", e);
+                        Either::Right(ok(req.into_response(HttpResponse::Unauthorized().json(&Message::Error { message: e }))))
+                    }
+                }
+            })
             .wrap(middleware::Logger::default())
             .service(web::resource("/ping").route(web::get().to(ping)))
             .service(web::resource("/push").route(web::put().to(push)))
             .service(web::resource("/poll").route(web::get().to(poll)))
+            .service(web::resource("/ws").route(web::get().to(ws)))
             .service(web::resource("/add_task").route(web::post().to(add_task)))
             .service(web::resource("/remove_task").route(web::post().to(remove_task)))
+            .service(web::resource("/update_task").route(web::post().to(update_task)))
             .service(web::resource("/clear_tasks").route(web::get().to(clear_tasks)))
             .service(web::resource("/sessions").route(web::get().to(sessions)))
+            .service(web::resource("/task_status").route(web::get().to(task_status)))
             .service(web::resource("/set_session").route(web::get().to(set_session)))
             .service(web::resource("/get_session").route(web::get().to(get_session)))
+            .service(web::resource("/session_diff").route(web::post().to(session_diff)))
             .service(web::resource("/add_visualization").route(web::get().to(add_visualization)))
+            .service(web::resource("/visualization").route(web::get().to(visualization)))
             .service(web::resource("/cancel").route(web::post().to(cancel)))
-            .default_service(web::resource("").to(HttpResponse::NotFound))
+            .service(web::resource("/cancel_task").route(web::post().to(cancel_task)))
+            .service(web::resource("/session_stats").route(web::get().to(session_stats)))
+            .service(web::resource("/session_log").route(web::get().to(session_log)))
+            .service(web::resource("/replay").route(web::post().to(replay)))
+            .service(web::resource("/map/changes").route(web::get().to(map_changes)))
+            .service(web::resource("/map/push").route(web::put().to(map_push)))
+            .service(web::resource("/add_anchor").route(web::post().to(add_anchor)))
+            .service(web::resource("/start_recording").route(web::post().to(start_recording)))
+            .service(web::resource("/stop_recording").route(web::post().to(stop_recording)))
+            .service(web::resource("/player_position").route(web::get().to(player_position)))
+            .service(web::resource("/area_objects").route(web::post().to(area_objects)))
+            .service(web::resource("/find_path").route(web::post().to(find_path)))
+            .service(web::resource("/line_of_sight").route(web::post().to(line_of_sight)))
+            .service(web::resource("/world_snapshot").route(web::get().to(world_snapshot)))
+            .service(web::resource("/fields").route(web::get().to(fields)))
+            .service(web::resource("/containers").route(web::get().to(containers)))
+            .service(web::resource("/export_map").route(web::get().to(export_map)))
+            .service(web::resource("/import_map").route(web::post().to(import_map)))
+            .service(web::resource("/map/grids").route(web::get().to(map_grids)))
+            .service(web::resource("/map/tile").route(web::get().to(map_tile)))
+            .service(web::resource("/map/tile_stats").route(web::get().to(map_tile_stats)))
+            .service(web::resource("/map/merge_segments").route(web::post().to(map_merge_segments)))
+            .service(web::resource("/map/split_segment").route(web::post().to(map_split_segment)))
+            .service(web::resource("/map/prune").route(web::post().to(map_prune)))
+            .service(web::resource("/zones")
+                .route(web::get().to(zones))
+                .route(web::put().to(set_zone))
+                .route(web::delete().to(remove_zone)))
+            .service(web::resource("/bookmarks")
+                .route(web::get().to(bookmarks))
+                .route(web::put().to(set_bookmark))
+                .route(web::delete().to(remove_bookmark)))
+            .service(web::resource("/profiles")
+                .route(web::get().to(profiles))
+                .route(web::put().to(set_profiles))
+                .route(web::delete().to(remove_profile)))
+            .service(web::resource("/metrics").route(web::get().to(metrics)));
+        #[cfg(feature = "fault_injection")]
+        let app = app.service(web::resource("/inject_faults").route(web::post().to(inject_faults)));
+        app.default_service(web::resource("").to(HttpResponse::NotFound))
     })
         .bind(config.bind_addr)?
-        .run())
+        .disable_signals()
+        .run();
+
+    actix_rt::spawn(stop_on_signal(server.clone(), shutdown.state.stop.clone()));
+
+    Ok((server, shutdown))
+}
+
+async fn stop_on_signal(server: Server, stop: Arc<AtomicBool>) {
+    #[cfg(unix)]
+    let terminate = async {
+        match actix_rt::signal::unix::signal(actix_rt::signal::unix::SignalKind::terminate()) {
+            Ok(mut v) => {
+                v.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = pending::<()>();
+    let interrupt = async {
+        if let Err(e) = actix_rt::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            pending::<()>().await;
+        }
+    };
+    select(Box::pin(interrupt), Box::pin(terminate)).await;
+    stop_server(&server, &stop).await;
+}
+
+async fn stop_server(server: &Server, stop: &AtomicBool) {
+    info!("Stop accepting updates");
+    stop.store(true, Ordering::Relaxed);
+    server.stop(true).await;
+}
+
+pub struct Shutdown {
+    state: State,
+    grpc_server: Option<JoinHandle<()>>,
+    schedule: Option<JoinHandle<()>>,
+    map_replication: Option<JoinHandle<()>>,
+}
+
+impl Shutdown {
+    pub async fn stop_server(&self, server: &Server) {
+        stop_server(server, &self.state.stop).await;
+    }
+
+    pub fn run(self) {
+        info!("Shutdown server");
+        self.state.stop.store(true, Ordering::Relaxed);
+        if let Some(schedule) = self.schedule {
+            if let Err(e) = schedule.join() {
+                error!("Schedule failed: {:?}", e);
+            }
+        }
+        if let Some(map_replication) = self.map_replication {
+            if let Err(e) = map_replication.join() {
+                error!("Map replication failed: {:?}", e);
+            }
+        }
+        for cancel in self.state.cancels.lock().unwrap().values() {
+            cancel.cancel_all();
+        }
+        let processors: Vec<(i64, JoinHandle<()>)> = self.state.processors.lock().unwrap().drain().collect();
+        for (session_id, processor) in processors {
+            if let Err(e) = processor.join() {
+                error!("Session {} processor failed: {:?}", session_id, e);
+            }
+        }
+        if let Some(grpc_server) = self.grpc_server {
+            if let Err(e) = grpc_server.join() {
+                error!("gRPC server failed: {:?}", e);
+            }
+        }
+        if let Err(e) = self.state.map_db.lock().unwrap().flush() {
+            error!("Failed to flush map db: {}", e);
+        }
+        info!("Server is shut down");
+    }
 }
 
 #[derive(Deserialize)]
 pub struct ServerConfig {
     bind_addr: String,
-    map_db_path: String,
-    map_cache_ttl: f64,
+    grpc: GrpcConfig,
+    map_db: MapDbConfig,
     process: ProcessConfig,
     session: SessionConfig,
     visualization: VisualizationConfig,
+    map_replication: MapReplicationConfig,
+    alerting: AlertingConfig,
+    auth: AuthConfig,
+    schedule: ScheduleConfig,
+}
+
+#[derive(Deserialize)]
+pub struct GrpcConfig {
+    bind_addr: Option<String>,
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    poll_interval: f64,
+}
+
+fn make_map_db(config: &MapDbConfig) -> Arc<Mutex<dyn MapDb + Send>> {
+    match config.backend {
+        MapDbBackend::Sqlite => {
+            let map_db = SqliteMapDb::new(
+                Connection::open(&config.path).unwrap(),
+                Duration::from_secs_f64(config.cache_ttl),
+            ).with_cache_capacity(config.cache_capacity);
+            match config.write_behind.as_ref() {
+                Some(write_behind) => Arc::new(Mutex::new(map_db.with_write_behind(
+                    Connection::open(&config.path).unwrap(),
+                    write_behind.clone(),
+                ))),
+                None => Arc::new(Mutex::new(map_db)),
+            }
+        }
+        #[cfg(feature = "postgres_map_db")]
+        MapDbBackend::Postgres => Arc::new(Mutex::new(
+            PostgresMapDb::connect(config.url.as_str(), Duration::from_secs_f64(config.cache_ttl))
+                .with_cache_capacity(config.cache_capacity)
+        )),
+        #[cfg(not(feature = "postgres_map_db"))]
+        MapDbBackend::Postgres => panic!("Failed to use Postgres map db {}: postgres_map_db feature is disabled", config.url),
+    }
 }
 
 pub fn read_config<T: AsRef<Path>>(path: T) -> std::io::Result<ServerConfig> {
@@ -102,21 +370,41 @@ async fn push(state: web::Data<State>, payload: web::Payload) -> Result<HttpResp
             return Ok(HttpResponse::Ok().json(&Message::Error { message: String::from("Failed to parse update") }));
         }
     };
+    #[cfg(feature = "fault_injection")]
+    let update = {
+        if let Some(delay) = state.faults.get_update_delay() {
+            actix_rt::time::delay_for(delay).await;
+        }
+        match state.faults.filter_update(update) {
+            Some(v) => v,
+            None => return Ok(HttpResponse::Ok().json(&Message::Ok)),
+        }
+    };
+    Ok(HttpResponse::Ok().json(&handle_update(&state, update)))
+}
+
+pub(crate) fn handle_update(state: &State, update: Update) -> Message {
+    if state.stop.load(Ordering::Relaxed) {
+        return Message::Error { message: String::from("Server is shutting down") };
+    }
     let session_id = update.session;
+    if state.replays.lock().unwrap().contains(&session_id) {
+        return Message::Error { message: String::from("Session is a replay") };
+    }
     let (new_session, cancel) = match &update.event {
         Event::SessionData { value: Some(value) } => {
             match serde_json::from_str(&value) {
                 Ok(v) => {
                     let cancel = state.cancels.lock().unwrap()
                         .entry(session_id)
-                        .or_insert_with(|| Arc::new(AtomicBool::new(false)))
+                        .or_insert_with(|| Arc::new(CancelTokens::new()))
                         .clone();
-                    match Session::from_session_data(v, state.map_db.clone(), &state.session_config, cancel.clone()) {
+                    match Session::from_session_data(v, state.map_db.clone(), &state.session_config, cancel.clone(), state.metrics.clone(), state.exploration_claims.clone(), state.tile_profiles.clone()) {
                         Ok(v) => {
                             if let Some(session) = state.sessions.lock().unwrap().get(&session_id).map(Arc::clone) {
                                 info!("Set session data {}", session_id);
                                 *session.write().unwrap() = v;
-                                return Ok(HttpResponse::Ok().json(&Message::Ok));
+                                return Message::Ok;
                             } else {
                                 info!("Use session data {}", session_id);
                                 (v, cancel)
@@ -124,33 +412,57 @@ async fn push(state: web::Data<State>, payload: web::Payload) -> Result<HttpResp
                         }
                         Err(e) => {
                             error!("Failed to create session from data: {}", e);
-                            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to create session from data") }));
+                            return Message::Error { message: String::from("Failed to create session from data") };
                         }
                     }
                 }
                 Err(e) => {
                     error!("Failed to parse session data {}: {}", value, e);
-                    return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse session data") }));
+                    return Message::Error { message: String::from("Failed to parse session data") };
                 }
             }
         }
+        Event::Hello { version, capabilities } => {
+            let negotiated = match negotiate(*version, capabilities) {
+                Ok(v) => v,
+                Err(e) => {
+                    warn!("Failed to negotiate protocol for session {}: {}", session_id, e);
+                    return Message::Error { message: e };
+                }
+            };
+            info!("Session {} uses protocol version {} with capabilities {:?}", session_id, negotiated.version, negotiated.capabilities);
+            state.messages.lock().unwrap()
+                .entry(session_id)
+                .or_insert_with(|| Arc::new(Mutex::new(MessageQueue::new(state.process_config.message_queue.clone()))))
+                .lock().unwrap()
+                .set_capabilities(negotiated.capabilities.clone());
+            return Message::Hello {
+                version: negotiated.version,
+                versions: SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+                capabilities: negotiated.capabilities.iter().map(|v| String::from(v.name())).collect(),
+            };
+        }
         Event::Cancel => {
             state.cancels.lock().unwrap()
                 .get(&session_id)
-                .map(|cancel| cancel.store(true, Ordering::Relaxed));
-            return Ok(HttpResponse::Ok().json(&Message::Ok));
+                .map(|cancel| cancel.cancel_all());
+            return Message::Ok;
         }
-        _ => if let Some(updates) = state.updates.lock().unwrap().get(&session_id).map(Arc::clone) {
-            push_update(&updates, update);
-            return Ok(HttpResponse::Ok().json(&Message::Ok));
-        } else {
+        _ => {
+            if let Err(e) = state.update_journals.append(&update) {
+                error!("Failed to journal update for session {}: {}", session_id, e);
+                return Message::Error { message: String::from("Failed to journal update") };
+            }
+            if let Some(updates) = state.updates.lock().unwrap().get(&session_id).map(Arc::clone) {
+                push_update(&updates, update);
+                return Message::Ok;
+            }
             let cancel = state.cancels.lock().unwrap()
                 .entry(session_id)
-                .or_insert_with(|| Arc::new(AtomicBool::new(false)))
+                .or_insert_with(|| Arc::new(CancelTokens::new()))
                 .clone();
-            info!("Create new session {}", session_id);
-            (Session::new(session_id, state.map_db.clone(), &state.session_config, cancel.clone()), cancel)
-        },
+            (make_session(state, session_id, cancel.clone()), cancel)
+        }
     };
     let session = state.sessions.lock().unwrap()
         .entry(session_id)
@@ -162,23 +474,58 @@ async fn push(state: web::Data<State>, payload: web::Payload) -> Result<HttpResp
         .clone();
     let messages = state.messages.lock().unwrap()
         .entry(session_id)
-        .or_insert_with(|| Arc::new(Mutex::new(VecDeque::new())))
+        .or_insert_with(|| Arc::new(Mutex::new(MessageQueue::new(state.process_config.message_queue.clone()))))
         .clone();
     let visualizers = state.visualizers.lock().unwrap()
         .entry(session_id)
         .or_insert_with(|| Arc::new(Mutex::new(Vec::new())))
         .clone();
-    if !matches!(update.event, Event::SessionData { .. }) {
+    let replayed = replay_update_journal(state, session_id, &session, &updates);
+    if !matches!(update.event, Event::SessionData { .. }) && update.number > replayed {
         push_update(&updates, update);
     }
     state.processors.lock().unwrap()
         .entry(session_id)
         .or_insert_with(|| {
             start_process_session(session_id, session, updates, messages, visualizers,
-                                  state.map_db.clone(), cancel, state.process_config.clone(),
+                                  state.combined_visualization.clone(), state.map_db.clone(), cancel, state.stop.clone(), state.alerter.clone(), state.update_journals.clone(), state.process_config.clone(),
                                   state.visualization_config.clone())
         });
-    Ok(HttpResponse::Ok().json(&Message::Ok))
+    Message::Ok
+}
+
+fn make_session(state: &State, session_id: i64, cancel: Arc<CancelTokens>) -> Session {
+    if let Some(session_data) = read_session_data(session_id, &state.process_config) {
+        match Session::from_session_data(session_data, state.map_db.clone(), &state.session_config, cancel.clone(), state.metrics.clone(), state.exploration_claims.clone(), state.tile_profiles.clone()) {
+            Ok(v) => {
+                info!("Restore saved session {}", session_id);
+                return v;
+            }
+            Err(e) => error!("Failed to restore saved session {}: {}", session_id, e),
+        }
+    }
+    info!("Create new session {}", session_id);
+    Session::new(session_id, state.map_db.clone(), &state.session_config, cancel, state.metrics.clone(), state.exploration_claims.clone(), state.tile_profiles.clone())
+}
+
+fn replay_update_journal(state: &State, session_id: i64, session: &Arc<RwLock<Session>>, updates: &Arc<UpdatesQueue>) -> i64 {
+    let last_update = session.read().unwrap().last_update();
+    let journaled = match state.update_journals.read(session_id, last_update) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to read updates journal for session {}: {}", session_id, e);
+            return last_update;
+        }
+    };
+    if !journaled.is_empty() {
+        info!("Replay {} journaled updates for session {} after update {}", journaled.len(), session_id, last_update);
+    }
+    let mut replayed = last_update;
+    for update in journaled.into_iter() {
+        replayed = update.number;
+        push_update(updates, update);
+    }
+    replayed
 }
 
 #[derive(Deserialize)]
@@ -187,13 +534,48 @@ struct Poll {
 }
 
 async fn poll(state: web::Data<State>, query: web::Query<Poll>) -> HttpResponse {
-    HttpResponse::Ok().json(
-        state.messages.lock().unwrap()
-            .get(&query.session)
-            .map(Arc::clone)
-            .map(|messages| messages.lock().unwrap().pop_front().unwrap_or(Message::Ok))
-            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
-    )
+    #[cfg(feature = "fault_injection")]
+    {
+        if state.faults.is_messages_stalled(std::time::Instant::now()) {
+            return HttpResponse::Ok().json(&Message::Ok);
+        }
+    }
+    HttpResponse::Ok().json(poll_message(&state, query.session, DeliveryChannel::Poll))
+}
+
+pub(crate) fn poll_message(state: &State, session_id: i64, channel: DeliveryChannel) -> Message {
+    let message = state.messages.lock().unwrap()
+        .get(&session_id)
+        .map(Arc::clone)
+        .map(|messages| messages.lock().unwrap().pop_front());
+    match message {
+        Some(Some(message)) => {
+            if let Some(session) = state.sessions.lock().unwrap().get(&session_id) {
+                session.read().unwrap().add_delivered_messages(channel, 1);
+            }
+            message
+        }
+        Some(None) => Message::Ok,
+        None => Message::Error { message: String::from("Session is not found") },
+    }
+}
+
+async fn ws(state: web::Data<State>, query: web::Query<Poll>, request: HttpRequest,
+            payload: web::Payload) -> Result<HttpResponse, Error> {
+    let session = state.sessions.lock().unwrap().get(&query.session).map(Arc::clone);
+    let messages = state.messages.lock().unwrap().get(&query.session).map(Arc::clone);
+    let (session, messages) = match (session, messages) {
+        (Some(session), Some(messages)) => (session, messages),
+        _ => return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Session is not found") })),
+    };
+    let connection = WebSocketConnection::new(
+        query.session,
+        session,
+        messages,
+        #[cfg(feature = "fault_injection")]
+        state.faults.clone(),
+    );
+    actix_web_actors::ws::start(connection, &request, payload)
 }
 
 #[derive(Deserialize)]
@@ -204,49 +586,69 @@ struct AddTask {
 
 async fn add_task(state: web::Data<State>, query: web::Query<AddTask>, payload: web::Payload) -> Result<HttpResponse, Error> {
     let body = collect(payload).await?;
-    Ok(HttpResponse::Ok().json(
-        state.sessions.lock().unwrap()
-            .get(&query.session)
-            .map(Arc::clone)
-            .map(|session| {
-                match session.write().unwrap().add_task(query.name.as_str(), &body) {
-                    Ok(_) => Message::Ok,
-                    Err(e) => Message::Error { message: e },
-                }
-            })
-            .unwrap_or_else(|| {
-                let session_id = query.session;
-                let cancel = state.cancels.lock().unwrap()
-                    .entry(session_id)
-                    .or_insert_with(|| Arc::new(AtomicBool::new(false)))
-                    .clone();
-                let new_session = Session::new(session_id, state.map_db.clone(), &state.session_config, cancel.clone());
-                let session = state.sessions.lock().unwrap()
-                    .entry(session_id)
-                    .or_insert_with(|| Arc::new(RwLock::new(new_session)))
-                    .clone();
-                let updates = state.updates.lock().unwrap()
-                    .entry(session_id)
-                    .or_insert_with(|| Arc::new(UpdatesQueue::new()))
-                    .clone();
-                let messages = state.messages.lock().unwrap()
-                    .entry(session_id)
-                    .or_insert_with(|| Arc::new(Mutex::new(VecDeque::new())))
-                    .clone();
-                let visualizers = state.visualizers.lock().unwrap()
-                    .entry(session_id)
-                    .or_insert_with(|| Arc::new(Mutex::new(Vec::new())))
-                    .clone();
-                state.processors.lock().unwrap()
-                    .entry(session_id)
-                    .or_insert_with(|| {
-                        start_process_session(session_id, session, updates, messages, visualizers,
-                                              state.map_db.clone(), cancel, state.process_config.clone(),
-                                              state.visualization_config.clone())
-                    });
-                Message::Ok
-            })
-    ))
+    Ok(HttpResponse::Ok().json(add_session_task(&state, query.session, query.name.as_str(), &body)))
+}
+
+pub(crate) fn add_session_task(state: &State, session_id: i64, name: &str, params: &[u8]) -> Message {
+    state.sessions.lock().unwrap()
+        .get(&session_id)
+        .map(Arc::clone)
+        .map(|session| {
+            match session.write().unwrap().add_task(name, params) {
+                Ok(_) => Message::Ok,
+                Err(e) => Message::Error { message: e },
+            }
+        })
+        .unwrap_or_else(|| {
+            let cancel = state.cancels.lock().unwrap()
+                .entry(session_id)
+                .or_insert_with(|| Arc::new(CancelTokens::new()))
+                .clone();
+            let new_session = make_session(state, session_id, cancel.clone());
+            let session = state.sessions.lock().unwrap()
+                .entry(session_id)
+                .or_insert_with(|| Arc::new(RwLock::new(new_session)))
+                .clone();
+            let updates = state.updates.lock().unwrap()
+                .entry(session_id)
+                .or_insert_with(|| Arc::new(UpdatesQueue::new()))
+                .clone();
+            let messages = state.messages.lock().unwrap()
+                .entry(session_id)
+                .or_insert_with(|| Arc::new(Mutex::new(MessageQueue::new(state.process_config.message_queue.clone()))))
+                .clone();
+            let visualizers = state.visualizers.lock().unwrap()
+                .entry(session_id)
+                .or_insert_with(|| Arc::new(Mutex::new(Vec::new())))
+                .clone();
+            replay_update_journal(state, session_id, &session, &updates);
+            state.processors.lock().unwrap()
+                .entry(session_id)
+                .or_insert_with(|| {
+                    start_process_session(session_id, session, updates, messages, visualizers,
+                                          state.combined_visualization.clone(), state.map_db.clone(), cancel, state.stop.clone(), state.alerter.clone(), state.update_journals.clone(), state.process_config.clone(),
+                                          state.visualization_config.clone())
+                });
+            Message::Ok
+        })
+}
+
+fn run_schedule_entry(state: &State, entry: &ScheduleEntry) {
+    let running = state.sessions.lock().unwrap()
+        .get(&entry.session)
+        .map(|session| session.read().unwrap().get_tasks().contains(&entry.task))
+        .unwrap_or(false);
+    if running {
+        return;
+    }
+    info!("Start scheduled task {} for session {} by \"{}\"", entry.task, entry.session, entry.name);
+    let params = match &entry.params {
+        serde_json::Value::Null => Vec::new(),
+        v => serde_json::to_vec(v).unwrap(),
+    };
+    if let Message::Error { message } = add_session_task(state, entry.session, entry.task.as_str(), &params) {
+        warn!("Failed to start scheduled task {} for session {} by \"{}\": {}", entry.task, entry.session, entry.name, message);
+    }
 }
 
 #[derive(Deserialize)]
@@ -256,16 +658,42 @@ struct RemoveTask {
 }
 
 async fn remove_task(state: web::Data<State>, query: web::Query<RemoveTask>) -> HttpResponse {
-    HttpResponse::Ok().json(
-        state.sessions.lock().unwrap()
-            .get(&query.session)
-            .map(Arc::clone)
-            .map(|session| {
-                session.write().unwrap().remove_task(query.task_id);
-                Message::Ok
-            })
-            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
-    )
+    HttpResponse::Ok().json(remove_session_task(&state, query.session, query.task_id))
+}
+
+pub(crate) fn remove_session_task(state: &State, session_id: i64, task_id: i64) -> Message {
+    state.sessions.lock().unwrap()
+        .get(&session_id)
+        .map(Arc::clone)
+        .map(|session| {
+            session.write().unwrap().remove_task(task_id);
+            Message::Ok
+        })
+        .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+}
+
+#[derive(Deserialize)]
+struct UpdateTask {
+    session: i64,
+    task_id: i64,
+}
+
+async fn update_task(state: web::Data<State>, query: web::Query<UpdateTask>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    Ok(HttpResponse::Ok().json(update_session_task(&state, query.session, query.task_id, &body)))
+}
+
+pub(crate) fn update_session_task(state: &State, session_id: i64, task_id: i64, params: &[u8]) -> Message {
+    state.sessions.lock().unwrap()
+        .get(&session_id)
+        .map(Arc::clone)
+        .map(|session| {
+            match session.write().unwrap().update_task(task_id, params) {
+                Ok(_) => Message::Ok,
+                Err(e) => Message::Error { message: e },
+            }
+        })
+        .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
 }
 
 #[derive(Deserialize)]
@@ -287,29 +715,52 @@ async fn clear_tasks(state: web::Data<State>, query: web::Query<ClearTasks>) ->
 }
 
 async fn sessions(state: web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok().json(&get_sessions(&state))
+}
+
+pub(crate) fn get_sessions(state: &State) -> Message {
     let session_ids = state.sessions.lock().unwrap().keys().cloned().collect::<Vec<_>>();
-    HttpResponse::Ok().json(&Message::Sessions {
+    Message::Sessions {
         value: session_ids.iter()
-            .map(|session_id| SessionInfo {
-                id: *session_id,
-                tasks: state.sessions.lock().unwrap()
-                    .get(session_id)
-                    .map(Arc::clone)
-                    .map(|session| session.read().unwrap().get_tasks())
-                    .unwrap_or_else(Vec::new),
-                updates: state.updates.lock().unwrap()
-                    .get(session_id)
-                    .map(Arc::clone)
-                    .map(|updates| count_updates(&updates))
-                    .unwrap_or(0),
-                messages: state.messages.lock().unwrap()
-                    .get(session_id)
-                    .map(Arc::clone)
-                    .map(|messages| messages.lock().unwrap().len())
-                    .unwrap_or(0),
+            .map(|session_id| {
+                let session = state.sessions.lock().unwrap().get(session_id).map(Arc::clone);
+                SessionInfo {
+                    id: *session_id,
+                    tasks: session.as_ref()
+                        .map(|session| session.read().unwrap().get_tasks())
+                        .unwrap_or_else(Vec::new),
+                    task_statuses: session.as_ref()
+                        .map(|session| session.read().unwrap().get_task_statuses())
+                        .unwrap_or_else(Vec::new),
+                    updates: state.updates.lock().unwrap()
+                        .get(session_id)
+                        .map(Arc::clone)
+                        .map(|updates| count_updates(&updates))
+                        .unwrap_or(0),
+                    messages: state.messages.lock().unwrap()
+                        .get(session_id)
+                        .map(Arc::clone)
+                        .map(|messages| messages.lock().unwrap().len())
+                        .unwrap_or(0),
+                }
             })
             .collect()
-    })
+    }
+}
+
+#[derive(Deserialize)]
+struct GetTaskStatus {
+    session: i64,
+}
+
+async fn task_status(state: web::Data<State>, query: web::Query<GetTaskStatus>) -> HttpResponse {
+    HttpResponse::Ok().json(
+        state.sessions.lock().unwrap()
+            .get(&query.session)
+            .map(Arc::clone)
+            .map(|session| Message::TaskStatuses { value: session.read().unwrap().get_task_statuses() })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    )
 }
 
 #[derive(Deserialize)]
@@ -328,9 +779,9 @@ async fn set_session(state: web::Data<State>, query: web::Query<SetSession>, pay
     };
     let cancel = state.cancels.lock().unwrap()
         .entry(query.session)
-        .or_insert_with(|| Arc::new(AtomicBool::new(false)))
+        .or_insert_with(|| Arc::new(CancelTokens::new()))
         .clone();
-    let session = match Session::from_session_data(session_data, state.map_db.clone(), &state.session_config, cancel) {
+    let session = match Session::from_session_data(session_data, state.map_db.clone(), &state.session_config, cancel, state.metrics.clone(), state.exploration_claims.clone(), state.tile_profiles.clone()) {
         Ok(v) => v,
         Err(e) => {
             error!("Failed to create session from data: {}", e);
@@ -358,6 +809,33 @@ async fn get_session(state: web::Data<State>, query: web::Query<GetSession>) ->
     )
 }
 
+#[derive(Deserialize)]
+struct GetSessionDiff {
+    session: i64,
+    max_object_distance: Option<f64>,
+}
+
+async fn session_diff(state: web::Data<State>, query: web::Query<GetSessionDiff>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    let client_data = match serde_json::from_slice::<SessionData>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse session data: {}", e);
+            return Ok(HttpResponse::Ok().json(&Message::Error { message: String::from("Failed to parse session data") }));
+        }
+    };
+    let session = match state.sessions.lock().unwrap().get(&query.session).map(Arc::clone) {
+        Some(v) => v,
+        None => return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Session is not found") })),
+    };
+    let server_data = session.read().unwrap().as_session_data();
+    let diff = make_session_diff(&server_data, &client_data, query.max_object_distance.unwrap_or(DEFAULT_MAX_OBJECT_DISTANCE));
+    if !diff.is_empty() {
+        warn!("Session {} state differs from client snapshot", query.session);
+    }
+    Ok(HttpResponse::Ok().json(Message::SessionDiff { value: diff }))
+}
+
 async fn collect(mut payload: web::Payload) -> Result<web::BytesMut, Error> {
     let mut body = web::BytesMut::new();
     while let Some(chunk) = payload.next().await {
@@ -395,26 +873,768 @@ async fn add_visualization(state: web::Data<State>, query: web::Query<AddVisuali
             })
             .map(|(session, updates, messages, visualizers)| {
                 add_session_visualization(session_id, &session, &updates, &messages, &visualizers,
-                                          state.map_db.clone(), state.visualization_config.clone());
+                                          &state.combined_visualization, state.map_db.clone(), state.visualization_config.clone());
                 Message::Ok
             })
             .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
     )
 }
 
+#[derive(Deserialize)]
+struct GetVisualization {
+    session: i64,
+}
+
+async fn visualization(state: web::Data<State>, query: web::Query<GetVisualization>) -> HttpResponse {
+    let session = match state.sessions.lock().unwrap().get(&query.session) {
+        Some(v) => v.clone(),
+        None => return HttpResponse::Ok().json(Message::Error { message: String::from("Session is not found") }),
+    };
+    let frame = session.read().unwrap().scene().get_frame();
+    match frame.ok_or_else(|| String::from("Visualization frame is not rendered")).and_then(encode_png) {
+        Ok(v) => HttpResponse::Ok().content_type("image/png").body(v),
+        Err(message) => HttpResponse::Ok().json(Message::Error { message }),
+    }
+}
+
 #[derive(Deserialize)]
 struct Cancel {
     session: i64,
 }
 
 async fn cancel(state: web::Data<State>, query: web::Query<Cancel>) -> HttpResponse {
+    HttpResponse::Ok().json(cancel_session(&state, query.session))
+}
+
+pub(crate) fn cancel_session(state: &State, session_id: i64) -> Message {
+    state.cancels.lock().unwrap()
+        .get(&session_id)
+        .map(|cancel| {
+            cancel.cancel_all();
+            Message::Ok
+        })
+        .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+}
+
+#[derive(Deserialize)]
+struct CancelTask {
+    session: i64,
+    task_id: i64,
+}
+
+async fn cancel_task(state: web::Data<State>, query: web::Query<CancelTask>) -> HttpResponse {
+    HttpResponse::Ok().json(cancel_session_task(&state, query.session, query.task_id))
+}
+
+pub(crate) fn cancel_session_task(state: &State, session_id: i64, task_id: i64) -> Message {
+    state.cancels.lock().unwrap()
+        .get(&session_id)
+        .map(|cancel| {
+            if cancel.cancel_task(task_id) {
+                Message::Ok
+            } else {
+                Message::Error { message: format!("Task {} is not found", task_id) }
+            }
+        })
+        .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+}
+
+#[derive(Deserialize)]
+struct GetSessionStats {
+    session: i64,
+}
+
+async fn session_stats(state: web::Data<State>, query: web::Query<GetSessionStats>) -> HttpResponse {
     HttpResponse::Ok().json(
-        state.cancels.lock().unwrap()
+        state.sessions.lock().unwrap()
             .get(&query.session)
-            .map(|cancel| {
-                cancel.store(true, Ordering::Relaxed);
-                Message::Ok
+            .map(Arc::clone)
+            .map(|session| Message::SessionStats {
+                value: session.read().unwrap().get_stats(),
             })
             .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
     )
 }
+
+#[derive(Deserialize)]
+struct GetSessionLog {
+    session: i64,
+    from: Option<i64>,
+}
+
+async fn session_log(state: web::Data<State>, query: web::Query<GetSessionLog>) -> HttpResponse {
+    HttpResponse::Ok().json(
+        match read_updates_log(query.session, query.from.unwrap_or(0), &state.process_config) {
+            Ok(value) => Message::SessionLog { value },
+            Err(e) => Message::Error { message: e },
+        }
+    )
+}
+
+#[derive(Deserialize)]
+struct Replay {
+    session: i64,
+    target: i64,
+    speed: Option<f64>,
+}
+
+async fn replay(state: web::Data<State>, query: web::Query<Replay>) -> HttpResponse {
+    let session_id = query.target;
+    if state.sessions.lock().unwrap().contains_key(&session_id) {
+        return HttpResponse::Ok().json(Message::Error { message: String::from("Target session already exists") });
+    }
+    let speed = query.speed.unwrap_or(1.0);
+    if !(speed > 0.0) {
+        return HttpResponse::Ok().json(Message::Error { message: String::from("Replay speed should be positive") });
+    }
+    let log = match read_logged_updates(query.session, 0, &state.process_config) {
+        Ok(v) => v,
+        Err(e) => return HttpResponse::Ok().json(Message::Error { message: e }),
+    };
+    if !state.replays.lock().unwrap().insert(session_id) {
+        return HttpResponse::Ok().json(Message::Error { message: String::from("Target session already exists") });
+    }
+    info!("Replay {} updates of session {} into session {} with speed {}", log.len(), query.session, session_id, speed);
+    let cancel = state.cancels.lock().unwrap()
+        .entry(session_id)
+        .or_insert_with(|| Arc::new(CancelTokens::new()))
+        .clone();
+    // Replayed session must not change the shared map and saved sessions
+    let map_db: Arc<Mutex<dyn MapDb + Send>> = Arc::new(Mutex::new(
+        SqliteMapDb::new(Connection::open_in_memory().unwrap(), Duration::new(0, 0))
+    ));
+    let process_config = ProcessConfig {
+        write_updates_log: false,
+        write_task_log: false,
+        write_session_stats: false,
+        autosave_session: false,
+        ..state.process_config.clone()
+    };
+    let new_session = Session::new(session_id, map_db.clone(), &state.session_config, cancel.clone(), state.metrics.clone(), Arc::new(ExplorationClaims::new()), state.tile_profiles.clone());
+    let session = state.sessions.lock().unwrap()
+        .entry(session_id)
+        .or_insert_with(|| Arc::new(RwLock::new(new_session)))
+        .clone();
+    let updates = state.updates.lock().unwrap()
+        .entry(session_id)
+        .or_insert_with(|| Arc::new(UpdatesQueue::new()))
+        .clone();
+    let messages = state.messages.lock().unwrap()
+        .entry(session_id)
+        .or_insert_with(|| Arc::new(Mutex::new(MessageQueue::new(state.process_config.message_queue.clone()))))
+        .clone();
+    let visualizers = state.visualizers.lock().unwrap()
+        .entry(session_id)
+        .or_insert_with(|| Arc::new(Mutex::new(Vec::new())))
+        .clone();
+    let log = log.into_iter().filter(|v| !matches!(v.update.event, Event::Close)).collect();
+    start_replay(session_id, log, updates.clone(), speed, state.stop.clone());
+    state.processors.lock().unwrap()
+        .entry(session_id)
+        .or_insert_with(|| {
+            start_process_session(session_id, session, updates, messages, visualizers,
+                                  state.combined_visualization.clone(), map_db, cancel, state.stop.clone(), state.alerter.clone(), state.update_journals.clone(), process_config,
+                                  state.visualization_config.clone())
+        });
+    HttpResponse::Ok().json(Message::Ok)
+}
+
+#[derive(Deserialize)]
+struct GetMapChanges {
+    since: i64,
+    limit: usize,
+}
+
+async fn map_changes(state: web::Data<State>, query: web::Query<GetMapChanges>) -> HttpResponse {
+    if state.map_replication_config.role != MapReplicationRole::Master {
+        return HttpResponse::Ok().json(Message::Error { message: String::from("Server is not a map master") });
+    }
+    HttpResponse::Ok().json(Message::MapChanges {
+        value: get_map_changes(state.map_db.lock().unwrap().deref(), query.since, query.limit),
+    })
+}
+
+async fn map_push(state: web::Data<State>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    if state.map_replication_config.role != MapReplicationRole::Master {
+        return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Server is not a map master") }));
+    }
+    let body = collect(payload).await?;
+    let changes = match serde_json::from_slice::<MapChanges>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse map changes: {}", e);
+            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse map changes") }));
+        }
+    };
+    let applied = apply_map_changes(state.map_db.lock().unwrap().deref(), &changes);
+    debug!("Applied {} of {} pushed grids", applied, changes.grids.len());
+    Ok(HttpResponse::Ok().json(Message::Ok))
+}
+
+#[derive(Deserialize)]
+struct AddAnchor {
+    session: i64,
+    grid_id: Option<i64>,
+    object_id: Option<i64>,
+}
+
+async fn add_anchor(state: web::Data<State>, query: web::Query<AddAnchor>) -> HttpResponse {
+    HttpResponse::Ok().json(
+        state.sessions.lock().unwrap()
+            .get(&query.session)
+            .map(Arc::clone)
+            .map(|session| {
+                match session.write().unwrap().add_anchor(query.grid_id, query.object_id) {
+                    Ok(_) => Message::Ok,
+                    Err(e) => Message::Error { message: e },
+                }
+            })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    )
+}
+
+#[derive(Deserialize)]
+struct StartRecording {
+    session: i64,
+    name: String,
+}
+
+async fn start_recording(state: web::Data<State>, query: web::Query<StartRecording>) -> HttpResponse {
+    HttpResponse::Ok().json(
+        state.sessions.lock().unwrap()
+            .get(&query.session)
+            .map(Arc::clone)
+            .map(|session| {
+                match session.write().unwrap().start_recording(query.name.clone()) {
+                    Ok(_) => Message::Ok,
+                    Err(e) => Message::Error { message: e },
+                }
+            })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    )
+}
+
+#[derive(Deserialize)]
+struct StopRecording {
+    session: i64,
+}
+
+async fn stop_recording(state: web::Data<State>, query: web::Query<StopRecording>) -> HttpResponse {
+    HttpResponse::Ok().json(
+        state.sessions.lock().unwrap()
+            .get(&query.session)
+            .map(Arc::clone)
+            .map(|session| {
+                match session.write().unwrap().stop_recording() {
+                    Ok(_) => Message::Ok,
+                    Err(e) => Message::Error { message: e },
+                }
+            })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    )
+}
+
+#[derive(Deserialize)]
+struct GetPlayerPosition {
+    session: i64,
+}
+
+async fn player_position(state: web::Data<State>, query: web::Query<GetPlayerPosition>) -> HttpResponse {
+    HttpResponse::Ok().json(
+        state.sessions.lock().unwrap()
+            .get(&query.session)
+            .map(Arc::clone)
+            .map(|session| {
+                session.read().unwrap().get_player_position()
+                    .unwrap_or_else(|| Message::Error { message: String::from("World is not configured") })
+            })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    )
+}
+
+#[derive(Deserialize)]
+struct GetAreaObjects {
+    session: i64,
+}
+
+async fn area_objects(state: web::Data<State>, query: web::Query<GetAreaObjects>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    let area = match serde_json::from_slice::<Area>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse area: {}", e);
+            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse area") }));
+        }
+    };
+    Ok(HttpResponse::Ok().json(
+        state.sessions.lock().unwrap()
+            .get(&query.session)
+            .map(Arc::clone)
+            .map(|session| {
+                session.read().unwrap().count_area_objects(&area)
+                    .unwrap_or_else(|| Message::Error { message: String::from("World is not configured") })
+            })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    ))
+}
+
+#[derive(Deserialize)]
+struct FindPath {
+    session: i64,
+}
+
+async fn find_path(state: web::Data<State>, query: web::Query<FindPath>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    let params = match serde_json::from_slice::<FindPathParams>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse find path params: {}", e);
+            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse find path params") }));
+        }
+    };
+    let session = match state.sessions.lock().unwrap().get(&query.session).map(Arc::clone) {
+        Some(v) => v,
+        None => return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Session is not found") })),
+    };
+    let path_query = session.read().unwrap().make_path_query(&state.session_config);
+    let cancel = match state.cancels.lock().unwrap().get(&query.session) {
+        Some(v) => v.add_query(),
+        None => Arc::new(AtomicBool::new(false)),
+    };
+    let result = web::block(move || -> Result<Message, ()> {
+        Ok(path_query.find_path(&params, &cancel)
+            .unwrap_or_else(|| Message::Error { message: String::from("World is not configured") }))
+    }).await;
+    Ok(HttpResponse::Ok().json(
+        result.unwrap_or_else(|e| Message::Error { message: format!("Failed to find path: {}", e) })
+    ))
+}
+
+async fn line_of_sight(state: web::Data<State>, query: web::Query<FindPath>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    let params = match serde_json::from_slice::<LineOfSightParams>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse line of sight params: {}", e);
+            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse line of sight params") }));
+        }
+    };
+    let session = state.sessions.lock().unwrap().get(&query.session).map(Arc::clone);
+    Ok(HttpResponse::Ok().json(
+        session
+            .map(|session| {
+                session.read().unwrap().line_of_sight(&params)
+                    .unwrap_or_else(|| Message::Error { message: String::from("World is not configured") })
+            })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    ))
+}
+
+#[derive(Deserialize)]
+struct GetWorldSnapshot {
+    session: i64,
+    radius: i32,
+    profile: Option<String>,
+}
+
+async fn world_snapshot(state: web::Data<State>, query: web::Query<GetWorldSnapshot>) -> HttpResponse {
+    if query.radius < 0 || query.radius > MAX_WORLD_SNAPSHOT_RADIUS {
+        return HttpResponse::Ok().json(Message::Error {
+            message: format!("Radius should be in range [0, {}]", MAX_WORLD_SNAPSHOT_RADIUS),
+        });
+    }
+    let session = state.sessions.lock().unwrap().get(&query.session).map(Arc::clone);
+    HttpResponse::Ok().json(
+        session
+            .map(|session| {
+                session.read().unwrap().get_world_snapshot(query.radius, query.profile.as_ref())
+                    .unwrap_or_else(|| Message::Error { message: String::from("World is not configured") })
+            })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    )
+}
+
+#[derive(Deserialize)]
+struct GetFields {
+    session: i64,
+}
+
+async fn fields(state: web::Data<State>, query: web::Query<GetFields>) -> HttpResponse {
+    let session = state.sessions.lock().unwrap().get(&query.session).map(Arc::clone);
+    HttpResponse::Ok().json(
+        session
+            .map(|session| {
+                session.read().unwrap().find_fields()
+                    .unwrap_or_else(|| Message::Error { message: String::from("World is not configured") })
+            })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    )
+}
+
+#[derive(Deserialize)]
+struct GetContainers {
+    session: i64,
+}
+
+async fn containers(state: web::Data<State>, query: web::Query<GetContainers>) -> HttpResponse {
+    let session = state.sessions.lock().unwrap().get(&query.session).map(Arc::clone);
+    HttpResponse::Ok().json(
+        session
+            .map(|session| {
+                session.read().unwrap().find_containers()
+                    .unwrap_or_else(|| Message::Error { message: String::from("World is not configured") })
+            })
+            .unwrap_or_else(|| Message::Error { message: String::from("Session is not found") })
+    )
+}
+
+#[derive(Deserialize)]
+struct ExportMap {
+    segment_id: i64,
+}
+
+async fn export_map(state: web::Data<State>, query: web::Query<ExportMap>) -> HttpResponse {
+    match export_map_png(state.map_db.lock().unwrap().deref(), query.segment_id) {
+        Ok(v) => HttpResponse::Ok().content_type("image/png").body(v),
+        Err(e) => HttpResponse::Ok().json(Message::Error { message: e }),
+    }
+}
+
+#[derive(Deserialize)]
+struct ImportMap {
+    path: Option<String>,
+}
+
+async fn import_map(state: web::Data<State>, query: web::Query<ImportMap>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let files = match query.path.as_ref() {
+        Some(path) => read_map_dump_dir(path),
+        None => {
+            let body = collect(payload).await?;
+            serde_json::from_slice::<Vec<MapDumpFile>>(&body).map_err(|e| {
+                error!("Failed to parse map dump files: {}", e);
+                String::from("Failed to parse map dump files")
+            })
+        }
+    };
+    let result = files.and_then(|files| import_map_dump(state.map_db.lock().unwrap().deref(), &files));
+    Ok(HttpResponse::Ok().json(match result {
+        Ok(v) => {
+            info!("Imported {} grids of {} segments, skipped {} grids", v.grids, v.segments, v.skipped);
+            Message::ImportedMap { value: v }
+        }
+        Err(e) => Message::Error { message: e },
+    }))
+}
+
+#[derive(Deserialize)]
+struct GetMapGrids {
+    segment: i64,
+}
+
+async fn map_grids(state: web::Data<State>, query: web::Query<GetMapGrids>) -> HttpResponse {
+    let map_db = state.map_db.lock().unwrap();
+    match get_segment_grids(map_db.deref(), query.segment) {
+        Ok(v) => HttpResponse::Ok().json(Message::MapGrids { value: export_grids(&state, map_db.deref(), query.segment, v) }),
+        Err(e) => HttpResponse::Ok().json(Message::Error { message: e }),
+    }
+}
+
+#[derive(Deserialize)]
+struct GetMapTile {
+    segment: i64,
+    x: i32,
+    y: i32,
+}
+
+async fn map_tile(state: web::Data<State>, query: web::Query<GetMapTile>) -> HttpResponse {
+    let map_db = state.map_db.lock().unwrap();
+    let tile_pos = Vec2i::new(query.x, query.y);
+    let shift = pos_to_tile_pos(get_segment_anchor(&state, map_db.deref(), query.segment));
+    match get_segment_tile(map_db.deref(), query.segment, tile_pos + shift) {
+        Ok(v) => HttpResponse::Ok().json(Message::MapTile { value: TileInfo { position: tile_pos, ..v } }),
+        Err(e) => HttpResponse::Ok().json(Message::Error { message: e }),
+    }
+}
+
+async fn map_tile_stats(state: web::Data<State>, query: web::Query<GetMapGrids>) -> HttpResponse {
+    let map_db = state.map_db.lock().unwrap();
+    let shift = pos_to_tile_pos(get_segment_anchor(&state, map_db.deref(), query.segment));
+    match get_segment_tile_stats(map_db.deref(), query.segment) {
+        Ok(v) => HttpResponse::Ok().json(Message::TileStats {
+            value: v.into_iter().map(|v| TileStats { min: v.min - shift, max: v.max - shift, ..v }).collect(),
+        }),
+        Err(e) => HttpResponse::Ok().json(Message::Error { message: e }),
+    }
+}
+
+#[derive(Deserialize)]
+struct MergeMapSegments {
+    src: i64,
+    dst: i64,
+    shift_x: i32,
+    shift_y: i32,
+}
+
+async fn map_merge_segments(state: web::Data<State>, query: web::Query<MergeMapSegments>) -> HttpResponse {
+    let map_db = state.map_db.lock().unwrap();
+    let result = map_db.merge_segments(query.src, query.dst, Vec2i::new(query.shift_x, query.shift_y))
+        .and_then(|moved| {
+            info!("Merged {} grids of segment {} into {}", moved, query.src, query.dst);
+            get_segment_grids(map_db.deref(), query.dst)
+                .map(|v| export_grids(&state, map_db.deref(), query.dst, v))
+        });
+    match result {
+        Ok(v) => HttpResponse::Ok().json(Message::MapGrids { value: v }),
+        Err(e) => HttpResponse::Ok().json(Message::Error { message: e }),
+    }
+}
+
+#[derive(Deserialize)]
+struct SplitMapSegment {
+    segment: i64,
+}
+
+async fn map_split_segment(state: web::Data<State>, query: web::Query<SplitMapSegment>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    let grid_ids = match serde_json::from_slice::<Vec<i64>>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse grid ids: {}", e);
+            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse grid ids") }));
+        }
+    };
+    let map_db = state.map_db.lock().unwrap();
+    let result = map_db.split_segment(query.segment, &grid_ids)
+        .and_then(|segment_id| {
+            info!("Split {} grids of segment {} into {}", grid_ids.len(), query.segment, segment_id);
+            get_segment_grids(map_db.deref(), segment_id)
+                .map(|v| export_grids(&state, map_db.deref(), segment_id, v))
+        });
+    Ok(match result {
+        Ok(v) => HttpResponse::Ok().json(Message::MapGrids { value: v }),
+        Err(e) => HttpResponse::Ok().json(Message::Error { message: e }),
+    })
+}
+
+async fn map_prune(state: web::Data<State>) -> HttpResponse {
+    let config = match state.map_retention_config.as_ref() {
+        Some(v) => v,
+        None => return HttpResponse::Ok().json(Message::Error { message: String::from("Map retention policy is not configured") }),
+    };
+    match prune_map(state.map_db.lock().unwrap().deref(), config) {
+        Ok(v) => HttpResponse::Ok().json(Message::MapPruned { value: v }),
+        Err(e) => HttpResponse::Ok().json(Message::Error { message: e }),
+    }
+}
+
+#[derive(Deserialize)]
+struct GetZones {
+    segment: i64,
+}
+
+async fn zones(state: web::Data<State>, query: web::Query<GetZones>) -> HttpResponse {
+    let map_db = state.map_db.lock().unwrap();
+    let shift = pos_to_tile_pos(get_segment_anchor(&state, map_db.deref(), query.segment));
+    let zones = map_db.get_zones(query.segment).into_iter()
+        .map(|zone| ZoneInfo::new(Zone { points: zone.points.iter().map(|v| *v - shift).collect(), ..zone }));
+    HttpResponse::Ok().json(Message::Zones { value: zones.collect() })
+}
+
+async fn set_zone(state: web::Data<State>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    let zone = match serde_json::from_slice::<Zone>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse zone: {}", e);
+            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse zone") }));
+        }
+    };
+    if let Err(e) = validate_zone(&zone) {
+        return Ok(HttpResponse::Ok().json(Message::Error { message: e }));
+    }
+    info!("Set zone {} with {} points for segment {}", zone.name, zone.points.len(), zone.segment_id);
+    let map_db = state.map_db.lock().unwrap();
+    let shift = pos_to_tile_pos(get_segment_anchor(&state, map_db.deref(), zone.segment_id));
+    map_db.set_zone(&Zone { points: zone.points.iter().map(|v| *v + shift).collect(), ..zone });
+    Ok(HttpResponse::Ok().json(Message::Ok))
+}
+
+#[derive(Deserialize)]
+struct RemoveZone {
+    segment: i64,
+    name: String,
+}
+
+async fn remove_zone(state: web::Data<State>, query: web::Query<RemoveZone>) -> HttpResponse {
+    if state.map_db.lock().unwrap().remove_zone(query.segment, &query.name) {
+        info!("Remove zone {} for segment {}", query.name, query.segment);
+        HttpResponse::Ok().json(Message::Ok)
+    } else {
+        HttpResponse::Ok().json(Message::Error { message: format!("Zone {} is not found", query.name) })
+    }
+}
+
+#[derive(Deserialize)]
+struct GetBookmarks {
+    segment: i64,
+}
+
+async fn bookmarks(state: web::Data<State>, query: web::Query<GetBookmarks>) -> HttpResponse {
+    let map_db = state.map_db.lock().unwrap();
+    let shift = get_segment_anchor(&state, map_db.deref(), query.segment);
+    let bookmarks = map_db.get_bookmarks(query.segment).into_iter()
+        .map(|bookmark| Bookmark { position: bookmark.position - shift, ..bookmark });
+    HttpResponse::Ok().json(Message::Bookmarks { value: bookmarks.collect() })
+}
+
+async fn set_bookmark(state: web::Data<State>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    let bookmark = match serde_json::from_slice::<Bookmark>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse bookmark: {}", e);
+            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse bookmark") }));
+        }
+    };
+    if let Err(e) = validate_bookmark(&bookmark) {
+        return Ok(HttpResponse::Ok().json(Message::Error { message: e }));
+    }
+    info!("Set bookmark {} at {:?} for segment {}", bookmark.name, bookmark.position, bookmark.segment_id);
+    let map_db = state.map_db.lock().unwrap();
+    let shift = get_segment_anchor(&state, map_db.deref(), bookmark.segment_id);
+    map_db.set_bookmark(&Bookmark { position: bookmark.position + shift, ..bookmark });
+    Ok(HttpResponse::Ok().json(Message::Ok))
+}
+
+#[derive(Deserialize)]
+struct RemoveBookmark {
+    segment: i64,
+    name: String,
+}
+
+async fn remove_bookmark(state: web::Data<State>, query: web::Query<RemoveBookmark>) -> HttpResponse {
+    if state.map_db.lock().unwrap().remove_bookmark(query.segment, &query.name) {
+        info!("Remove bookmark {} for segment {}", query.name, query.segment);
+        HttpResponse::Ok().json(Message::Ok)
+    } else {
+        HttpResponse::Ok().json(Message::Error { message: format!("Bookmark {} is not found", query.name) })
+    }
+}
+
+// Stored segment coordinates are reported relative to a global anchor when the segment has one
+fn get_segment_anchor(state: &State, map_db: &dyn MapDb, segment_id: i64) -> Vec2f {
+    Anchors::new(&state.session_config.world().anchors).get_segment_position(map_db, segment_id)
+        .unwrap_or(Vec2f::zero())
+}
+
+fn export_grids(state: &State, map_db: &dyn MapDb, segment_id: i64, grids: Vec<GridInfo>) -> Vec<GridInfo> {
+    let shift = pos_to_grid_pos(get_segment_anchor(state, map_db, segment_id));
+    grids.into_iter().map(|v| GridInfo { position: v.position - shift, ..v }).collect()
+}
+
+async fn profiles(state: web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok().json(Message::TileProfiles { value: state.tile_profiles.get_all() })
+}
+
+async fn set_profiles(state: web::Data<State>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    let profiles = match serde_json::from_slice::<BTreeMap<String, HashMap<String, f64>>>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse tile profiles: {}", e);
+            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse tile profiles") }));
+        }
+    };
+    for (name, tiles) in profiles.into_iter() {
+        info!("Set tile profile {} with {} tiles", name, tiles.len());
+        if let Err(e) = state.tile_profiles.set(name.as_str(), tiles) {
+            return Ok(HttpResponse::Ok().json(Message::Error { message: e }));
+        }
+    }
+    Ok(HttpResponse::Ok().json(Message::Ok))
+}
+
+#[derive(Deserialize)]
+struct RemoveProfile {
+    name: String,
+}
+
+async fn remove_profile(state: web::Data<State>, query: web::Query<RemoveProfile>) -> HttpResponse {
+    match state.tile_profiles.remove(query.name.as_str()) {
+        Ok(_) => {
+            info!("Remove tile profile {}", query.name);
+            HttpResponse::Ok().json(Message::Ok)
+        }
+        Err(message) => HttpResponse::Ok().json(Message::Error { message }),
+    }
+}
+
+async fn metrics(state: web::Data<State>) -> HttpResponse {
+    let sessions: Vec<(i64, Arc<RwLock<Session>>)> = state.sessions.lock().unwrap().iter()
+        .map(|(id, session)| (*id, session.clone()))
+        .collect();
+    let stats: Vec<SessionStats> = sessions.iter()
+        .map(|(_, session)| session.read().unwrap().get_stats())
+        .collect();
+    let updates: Vec<(i64, usize)> = state.updates.lock().unwrap().iter()
+        .map(|(id, updates)| (*id, count_updates(updates)))
+        .collect();
+    let messages: Vec<(i64, usize, u64, u64)> = state.messages.lock().unwrap().iter()
+        .map(|(id, messages)| {
+            let locked_messages = messages.lock().unwrap();
+            (*id, locked_messages.len(), locked_messages.dropped(), locked_messages.deduplicated())
+        })
+        .collect();
+    let cache_stats = state.map_db.lock().unwrap().get_cache_stats();
+    let mut output = String::new();
+    write_session_values(
+        "hafen_bot_session_updates_total", "Number of processed updates", "counter",
+        &stats.iter().map(|v| (v.session, v.updates)).collect::<Vec<_>>(), &mut output,
+    );
+    write_session_values(
+        "hafen_bot_session_messages_total", "Number of messages emitted by tasks", "counter",
+        &stats.iter().map(|v| (v.session, v.messages)).collect::<Vec<_>>(), &mut output,
+    );
+    write_session_values(
+        "hafen_bot_session_updates_queue_depth", "Number of updates waiting for processing", "gauge",
+        &updates, &mut output,
+    );
+    write_session_values(
+        "hafen_bot_session_messages_queue_depth", "Number of messages waiting for delivery", "gauge",
+        &messages.iter().map(|v| (v.0, v.1)).collect::<Vec<_>>(), &mut output,
+    );
+    write_session_values(
+        "hafen_bot_session_messages_dropped_total", "Number of messages dropped due to queue size limit", "counter",
+        &messages.iter().map(|v| (v.0, v.2)).collect::<Vec<_>>(), &mut output,
+    );
+    write_session_values(
+        "hafen_bot_session_messages_deduplicated_total", "Number of messages replaced by a later message of the same kind", "counter",
+        &messages.iter().map(|v| (v.0, v.3)).collect::<Vec<_>>(), &mut output,
+    );
+    state.metrics.write(&mut output);
+    write_value("hafen_bot_map_db_cache_hits_total", "Number of map db cache hits", "counter",
+                cache_stats.hits, &mut output);
+    write_value("hafen_bot_map_db_cache_misses_total", "Number of map db cache misses", "counter",
+                cache_stats.misses, &mut output);
+    write_value("hafen_bot_map_db_cache_evictions_total", "Number of grids evicted from map db cache", "counter",
+                cache_stats.evictions, &mut output);
+    write_value("hafen_bot_map_db_cache_grids", "Number of grids in map db cache", "gauge",
+                cache_stats.grids, &mut output);
+    write_value("hafen_bot_map_db_cache_capacity", "Max number of grids in map db cache", "gauge",
+                cache_stats.capacity, &mut output);
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(output)
+}
+
+#[cfg(feature = "fault_injection")]
+async fn inject_faults(state: web::Data<State>, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let body = collect(payload).await?;
+    let params = match serde_json::from_slice::<FaultsParams>(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to parse faults params: {}", e);
+            return Ok(HttpResponse::Ok().json(Message::Error { message: String::from("Failed to parse faults params") }));
+        }
+    };
+    state.faults.set(&params, std::time::Instant::now());
+    Ok(HttpResponse::Ok().json(Message::Ok))
+}
@@ -1,33 +1,103 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::sync::{Arc, Mutex, RwLock};
 use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 
+use crate::bot::area_objects::{Area, count_area_objects};
+use crate::bot::cancel_tokens::CancelTokens;
+use crate::bot::chat_commands::{ChatCommand, ChatCommandsConfig, get_chat_command_text, make_chat_reply, parse_chat_command};
+use crate::bot::containers::Container;
+use crate::bot::cooldowns::{Cooldowns, CooldownsConfig};
+use crate::bot::day_time::NightConfig;
+use crate::bot::exploration_claims::ExplorationClaims;
+use crate::bot::fields::Field;
+use crate::bot::human_control::{HumanControl, HumanControlConfig};
+use crate::bot::macros::{read_macro, Recorder, write_macro};
+use crate::bot::map::pos_to_map_pos;
 use crate::bot::map_db::MapDb;
+use crate::bot::metrics::Metrics;
 use crate::bot::player::{Player, PlayerConfig, PlayerData};
-use crate::bot::protocol::{Event, Message, Update, Value};
+use crate::bot::protocol::{Button, Event, Message, Modifier, TaskInfo, TaskStatus, Update, Value};
+use crate::bot::rate_limiter::{get_task_rate_limit, RateLimitConfig, RateLimiter};
 use crate::bot::scene::Scene;
+use crate::bot::session_stats::{DeliveryChannel, SessionStats, SessionStatsCollector, TaskOutcome};
+use crate::bot::stuck_recovery::{StuckRecovery, StuckRecoveryAction, StuckRecoveryConfig, StuckRecoveryStep};
+use crate::bot::task_log::{TaskLogContext, TaskLogScope};
+use crate::bot::task_scheduler::TaskScheduler;
+use crate::bot::tasks::backtrack::{Backtrack, BacktrackParams};
+use crate::bot::tasks::crafter::{Crafter, CrafterConfig, CrafterParams, read_recipe};
 use crate::bot::tasks::drinker::{Drinker, DrinkerConfig};
-use crate::bot::tasks::exp_wnd_closer::ExpWndCloser;
 use crate::bot::tasks::explorer::{Explorer, ExplorerConfig};
+use crate::bot::tasks::farmer::{Farmer, FarmerConfig, FarmerParams};
+use crate::bot::tasks::fleer::{Fleer, FleerConfig, FleerParams};
+use crate::bot::tasks::follower::{Follower, FollowerConfig, FollowerParams};
+use crate::bot::tasks::forager::{Forager, ForagerConfig, ForagerParams};
+use crate::bot::tasks::idler::{Idler, IdlerConfig};
+use crate::bot::tasks::logout::{Logout, LogoutConfig, LogoutParams};
+use crate::bot::tasks::macro_player::{MacroPlayer, MacroPlayerConfig, MacroPlayerParams};
+use crate::bot::tasks::miner::{Miner, MinerConfig, MinerParams};
 use crate::bot::tasks::new_character::{NewCharacter, NewCharacterParams};
-use crate::bot::tasks::path_finder::{PathFinder, PathFinderConfig};
+use crate::bot::tasks::path_finder::{get_tile_costs_by_profile, PathFinder, PathFinderConfig, PathFinderParams};
+use crate::bot::tasks::pipeline::{Pipeline, PipelineParams};
 use crate::bot::tasks::task::Task;
-use crate::bot::world::{PlayerWorld, World, WorldConfig, WorldData};
+use crate::bot::tasks::transferrer::{Transferrer, TransferrerConfig, TransferrerParams};
+use crate::bot::tasks::ui_janitor::{UiJanitor, UiJanitorConfig, UiJanitorParams};
+use crate::bot::tasks::wasm_task::{is_wasm_task, WasmTask, WasmTaskConfig};
+use crate::bot::tile_profiles::TileProfiles;
+use crate::bot::vec2::Vec2i;
+use crate::bot::world::{BTreeMapTileWeights, make_find_path_node, PlayerWorld, World, WorldConfig, WorldData, WorldSnapshot};
 
 #[derive(Clone, Deserialize)]
 pub struct SessionConfig {
     world: WorldConfig,
     player: PlayerConfig,
+    human_control: HumanControlConfig,
+    cooldowns: CooldownsConfig,
+    stuck_recovery: StuckRecoveryConfig,
+    chat_commands: ChatCommandsConfig,
+    night: NightConfig,
     tasks: TaskConfigs,
 }
 
+impl SessionConfig {
+    pub fn world(&self) -> &WorldConfig {
+        &self.world
+    }
+}
+
 #[derive(Clone, Deserialize)]
 pub struct TaskConfigs {
     path_finder: PathFinderConfig,
     explorer: ExplorerConfig,
     drinker: DrinkerConfig,
+    forager: ForagerConfig,
+    follower: FollowerConfig,
+    transferrer: TransferrerConfig,
+    macro_player: MacroPlayerConfig,
+    farmer: FarmerConfig,
+    fleer: FleerConfig,
+    ui_janitor: UiJanitorConfig,
+    idler: IdlerConfig,
+    crafter: CrafterConfig,
+    logout: LogoutConfig,
+    miner: MinerConfig,
+    wasm: WasmTaskConfig,
+    rate_limits: HashMap<String, RateLimitConfig>,
+}
+
+#[derive(Deserialize)]
+pub struct FindPathParams {
+    pub src: Vec2i,
+    pub dst: Vec2i,
+    pub tiles: String,
+}
+
+#[derive(Deserialize)]
+pub struct LineOfSightParams {
+    pub src: Vec2i,
+    pub dst: Vec2i,
 }
 
 pub struct Session {
@@ -40,7 +110,17 @@ pub struct Session {
     scene: Scene,
     messages: Arc<Mutex<VecDeque<Message>>>,
     task_configs: TaskConfigs,
-    cancel: Arc<AtomicBool>,
+    cancel: Arc<CancelTokens>,
+    cooldowns: Arc<Mutex<Cooldowns>>,
+    claims: Arc<ExplorationClaims>,
+    human_control: Mutex<HumanControl>,
+    stats: Mutex<SessionStatsCollector>,
+    scheduler: Mutex<TaskScheduler>,
+    recorder: Option<Recorder>,
+    stuck_recovery: StuckRecovery,
+    chat_commands: ChatCommandsConfig,
+    night: NightConfig,
+    inventory_full: bool,
 }
 
 struct TaskWithParams {
@@ -48,42 +128,68 @@ struct TaskWithParams {
     name: String,
     params: Vec<u8>,
     value: Arc<Mutex<dyn Task>>,
+    rate_limiter: Option<Mutex<RateLimiter>>,
+    status: Mutex<TaskStatus>,
 }
 
 impl Session {
-    pub fn new(id: i64, map_db: Arc<Mutex<dyn MapDb + Send>>, config: &SessionConfig, cancel: Arc<AtomicBool>) -> Self {
+    pub fn new(id: i64, map_db: Arc<Mutex<dyn MapDb + Send>>, config: &SessionConfig, cancel: Arc<CancelTokens>,
+               metrics: Arc<Metrics>, claims: Arc<ExplorationClaims>, tile_profiles: Arc<TileProfiles>) -> Self {
+        let world = World::new(config.world.clone(), map_db, metrics, tile_profiles);
+        let mut player = Player::new(config.player.clone());
+        preload_resources(id, &mut player, &world);
         Self {
             id,
             last_update: 0,
-            world: World::new(config.world.clone(), map_db),
-            player: Player::new(config.player.clone()),
+            world,
+            player,
             task_id_counter: 0,
             tasks: Arc::new(RwLock::new(Vec::new())),
             scene: Scene::new(),
             messages: Arc::new(Mutex::new(VecDeque::new())),
             task_configs: config.tasks.clone(),
             cancel,
+            cooldowns: Arc::new(Mutex::new(Cooldowns::new(config.cooldowns.clone()))),
+            claims,
+            human_control: Mutex::new(HumanControl::new(config.human_control.clone())),
+            stats: Mutex::new(SessionStatsCollector::new()),
+            scheduler: Mutex::new(TaskScheduler::new()),
+            recorder: None,
+            stuck_recovery: StuckRecovery::new(config.stuck_recovery.clone()),
+            chat_commands: config.chat_commands.clone(),
+            night: config.night.clone(),
+            inventory_full: false,
         }
     }
 
     pub fn from_session_data(session_data: SessionData, map_db: Arc<Mutex<dyn MapDb + Send>>,
-                             config: &SessionConfig, cancel: Arc<AtomicBool>) -> Result<Self, String> {
-        let player = Player::from_player_data(session_data.player, config.player.clone());
-        let world = World::from_world_data(session_data.world, config.world.clone(), map_db);
+                             config: &SessionConfig, cancel: Arc<CancelTokens>, metrics: Arc<Metrics>,
+                             claims: Arc<ExplorationClaims>, tile_profiles: Arc<TileProfiles>) -> Result<Self, String> {
+        let mut player = Player::from_player_data(session_data.player, config.player.clone());
+        let world = World::from_world_data(session_data.world, config.world.clone(), map_db, metrics, tile_profiles);
+        preload_resources(session_data.id, &mut player, &world);
+        let mut stats = SessionStatsCollector::new();
+        let cooldowns = Arc::new(Mutex::new(Cooldowns::new(config.cooldowns.clone())));
         Ok(Self {
             id: session_data.id,
-            last_update: 0,
+            last_update: session_data.last_update,
             task_id_counter: session_data.task_id_counter,
             tasks: {
                 let mut tasks = Vec::new();
                 for task in session_data.tasks.into_iter() {
-                    let value = make_task(task.name.as_str(), task.params.as_slice(), &config.tasks, &cancel)?;
+                    let _log_scope = TaskLogScope::enter(make_task_log_context(session_data.id, task.id, task.name.as_str(), 0));
+                    let value = make_task(task.name.as_str(), task.params.as_slice(), &config.tasks, &cancel.add_task(task.id),
+                                          &cooldowns, session_data.id, &claims)?;
                     if let Some(player_world) = world.for_player(&player) {
                         value.lock().unwrap().restore(&player_world);
                     }
+                    stats.add_task(task.id, task.name.as_str());
+                    let status = Mutex::new(value.lock().unwrap().status());
                     tasks.push(Arc::new(RwLock::new(TaskWithParams {
                         id: task.id,
                         value,
+                        status,
+                        rate_limiter: make_rate_limiter(task.name.as_str(), task.params.as_slice(), &config.tasks),
                         name: task.name,
                         params: task.params,
                     })));
@@ -96,9 +202,23 @@ impl Session {
             messages: Arc::new(Mutex::new(VecDeque::new())),
             task_configs: config.tasks.clone(),
             cancel,
+            cooldowns,
+            claims,
+            human_control: Mutex::new(HumanControl::new(config.human_control.clone())),
+            stats: Mutex::new(stats),
+            scheduler: Mutex::new(TaskScheduler::new()),
+            recorder: None,
+            stuck_recovery: StuckRecovery::new(config.stuck_recovery.clone()),
+            chat_commands: config.chat_commands.clone(),
+            night: config.night.clone(),
+            inventory_full: false,
         })
     }
 
+    pub fn last_update(&self) -> i64 {
+        self.last_update
+    }
+
     pub fn as_session_data(&self) -> SessionData {
         SessionData {
             id: self.id,
@@ -126,19 +246,66 @@ impl Session {
             .collect()
     }
 
+    pub fn get_task_statuses(&self) -> Vec<TaskInfo> {
+        self.tasks.read().unwrap().iter()
+            .map(|v| {
+                let locked = v.read().unwrap();
+                let status = locked.status.lock().unwrap().clone();
+                TaskInfo {
+                    id: locked.id,
+                    name: locked.name.clone(),
+                    status,
+                }
+            })
+            .collect()
+    }
+
     pub fn scene(&self) -> &Scene {
         &self.scene
     }
 
+    pub fn finish(&self) {
+        self.stats.lock().unwrap().finish();
+    }
+
+    pub fn resume(&self) {
+        self.stats.lock().unwrap().resume();
+    }
+
+    pub fn get_stats(&self) -> SessionStats {
+        self.stats.lock().unwrap().get(self.id)
+    }
+
+    pub fn add_delivered_messages(&self, channel: DeliveryChannel, count: usize) {
+        self.stats.lock().unwrap().add_delivered_messages(channel, count);
+    }
+
+    pub fn is_human_in_control(&self) -> bool {
+        self.human_control.lock().unwrap().is_active(Instant::now())
+    }
+
     pub fn add_task(&mut self, name: &str, params: &[u8]) -> Result<(), String> {
         self.task_id_counter += 1;
         let id = self.task_id_counter;
+        let task_cancel = self.cancel.add_task(id);
+        let _log_scope = TaskLogScope::enter(make_task_log_context(self.id, id, name, self.last_update));
+        let value = match make_task(name, params, &self.task_configs, &task_cancel, &self.cooldowns, self.id, &self.claims) {
+            Ok(v) => v,
+            Err(e) => {
+                self.cancel.remove_task(id);
+                return Err(e);
+            }
+        };
+        let status = Mutex::new(value.lock().unwrap().status());
         self.tasks.write().unwrap().push(Arc::new(RwLock::new(TaskWithParams {
             id,
             name: String::from(name),
             params: Vec::from(params),
-            value: make_task(name, params, &self.task_configs, &self.cancel)?,
+            value,
+            rate_limiter: make_rate_limiter(name, params, &self.task_configs),
+            status,
         })));
+        self.stats.get_mut().unwrap().add_task(id, name);
         if let Some(game_ui_id) = self.player.game_ui_id() {
             self.messages.lock().unwrap().push_back(Message::UIMessage {
                 id: game_ui_id,
@@ -164,6 +331,9 @@ impl Session {
             }
         });
         if removed {
+            self.cancel.remove_task(id);
+            self.scheduler.get_mut().unwrap().release(id);
+            self.stats.get_mut().unwrap().set_task_outcome(id, TaskOutcome::Removed);
             if let Some(world) = self.world.for_player(&self.player) {
                 self.messages.lock().unwrap().push_back(Message::UIMessage {
                     id: world.game_ui_id(),
@@ -174,8 +344,30 @@ impl Session {
         }
     }
 
+    pub fn update_task(&mut self, id: i64, params: &[u8]) -> Result<(), String> {
+        let task = match self.tasks.read().unwrap().iter().find(|task| task.read().unwrap().id == id) {
+            Some(v) => Arc::clone(v),
+            None => return Err(format!("Task {} is not found", id)),
+        };
+        let mut locked = task.write().unwrap();
+        let status = {
+            let _log_scope = self.enter_task_log(&locked);
+            let mut value = locked.value.lock().unwrap();
+            value.reconfigure(params)?;
+            value.status()
+        };
+        locked.params = Vec::from(params);
+        locked.rate_limiter = make_rate_limiter(locked.name.as_str(), params, &self.task_configs);
+        locked.status = Mutex::new(status);
+        debug!("Session {} task {} {} is reconfigured", self.id, id, locked.name);
+        Ok(())
+    }
+
     pub fn clear_tasks(&self) {
         let mut locked = self.tasks.write().unwrap();
+        for task in locked.iter() {
+            self.stats.lock().unwrap().set_task_outcome(task.read().unwrap().id, TaskOutcome::Removed);
+        }
         if let Some(world) = self.world.for_player(&self.player) {
             for task in locked.iter() {
                 self.messages.lock().unwrap().push_back(Message::UIMessage {
@@ -186,6 +378,8 @@ impl Session {
             }
         }
         locked.clear();
+        self.cancel.clear_tasks();
+        self.scheduler.lock().unwrap().clear();
     }
 
     pub fn update(&mut self, update: Update) -> bool {
@@ -197,6 +391,7 @@ impl Session {
             warn!("Missed {} updates for session {}", update.number - self.last_update - 1, self.id);
         }
         self.last_update = update.number;
+        self.stats.get_mut().unwrap().add_update();
         debug!("Got new update for session {}: {:?}", self.id, update);
         match &update.event {
             Event::TaskAdd { name, params } => {
@@ -208,32 +403,227 @@ impl Session {
             Event::TaskRemove { id } => {
                 self.remove_task(*id);
             }
+            Event::MapGridAdd { grid, .. } => {
+                self.stats.get_mut().unwrap().add_grid(grid.id);
+            }
+            Event::GridsOfInterest { positions } => {
+                debug!("Set grids of interest for session {}: {:?}", self.id, positions);
+                self.world.set_grids_of_interest(&self.player, positions.clone());
+            }
+            Event::WidgetMessage { .. } => {
+                self.handle_chat_command(&update.event);
+            }
             _ => (),
         }
+        self.cooldowns.lock().unwrap().update(&self.player, &update, Instant::now());
+        if let Some(recorder) = self.recorder.as_mut() {
+            if !self.human_control.get_mut().unwrap().is_bot_message(&update.event, Instant::now()) {
+                recorder.update(&update.event, Instant::now());
+            }
+        }
+        if self.human_control.get_mut().unwrap().update(&update.event, Instant::now()) {
+            debug!("Human took control over session {}", self.id);
+        }
         if let Some(world) = self.world.for_player(&self.player) {
             for task in self.tasks.read().unwrap().iter().map(Arc::clone) {
-                task.read().unwrap().value.lock().unwrap().update(&world, &update);
+                let locked_task = task.read().unwrap();
+                let _log_scope = self.enter_task_log(&locked_task);
+                let mut locked_value = locked_task.value.lock().unwrap();
+                locked_value.update(&world, &update);
+                *locked_task.status.lock().unwrap() = locked_value.status();
             }
         }
         let mut updated = false;
+        self.world.persist_objects(&self.player, &update);
+        if self.world.update_danger_zones(&self.player, &update) {
+            updated = true;
+        }
         if self.player.update(&self.world, &update) {
+            self.update_inventory_full();
+            updated = true;
+        }
+        if self.world.update_containers(&self.player, &update) {
+            updated = true;
+        }
+        if self.world.update_stuck_tiles(&self.player, &update) {
+            updated = true;
+        }
+        if self.world.update_breadcrumbs(&self.player) {
+            updated = true;
+        }
+        if self.update_stuck_recovery() {
             updated = true;
         }
         if self.world.update(update) {
             updated = true;
         }
+        for (position, grid_id, revision) in self.world.update_grids_of_interest(&self.player) {
+            self.messages.lock().unwrap().push_back(Message::GridOfInterestChanged { position, grid_id, revision });
+        }
+        if let Some(world) = self.world.for_player(&self.player) {
+            self.stats.lock().unwrap().update_player_position(world.player_segment_id(), world.player_position());
+        }
         updated
     }
 
+    fn handle_chat_command(&mut self, event: &Event) {
+        let (widget_id, text) = match get_chat_command_text(event, self.player.widgets(), &self.chat_commands) {
+            Some(v) => v,
+            None => return,
+        };
+        debug!("Got chat command for session {}: {:?}", self.id, text);
+        let reply = match parse_chat_command(text) {
+            Ok(ChatCommand::AddTask { name, params }) => match self.add_task(name.as_str(), &params) {
+                Ok(_) => format!("Added task {} as {}", name, self.task_id_counter),
+                Err(e) => format!("Failed to add task {}: {}", name, e),
+            },
+            Ok(ChatCommand::RemoveTask { id }) => {
+                self.remove_task(id);
+                format!("Removed task {}", id)
+            }
+            Ok(ChatCommand::ClearTasks) => {
+                self.clear_tasks();
+                String::from("Cleared tasks")
+            }
+            Ok(ChatCommand::ListTasks) => {
+                let tasks: Vec<String> = self.get_task_statuses().into_iter()
+                    .map(|v| format!("{} {}: {}", v.id, v.name, v.status.state))
+                    .collect();
+                if tasks.is_empty() {
+                    String::from("No tasks")
+                } else {
+                    tasks.join(", ")
+                }
+            }
+            Err(e) => e,
+        };
+        self.messages.lock().unwrap().push_back(make_chat_reply(widget_id, reply));
+    }
+
+    fn update_inventory_full(&mut self) {
+        let inventory_full = self.player.free_slots() == Some(0);
+        if inventory_full == self.inventory_full {
+            return;
+        }
+        self.inventory_full = inventory_full;
+        if !inventory_full {
+            return;
+        }
+        if let Some(world) = self.world.for_player(&self.player) {
+            debug!("Player inventory is full for session {}", self.id);
+            for task in self.tasks.read().unwrap().iter() {
+                let locked_task = task.read().unwrap();
+                let _log_scope = self.enter_task_log(&locked_task);
+                locked_task.value.lock().unwrap().on_inventory_full(&world);
+            }
+        }
+    }
+
+    fn update_stuck_recovery(&mut self) -> bool {
+        let now = Instant::now();
+        let is_stuck = self.player.is_stuck()
+            && !self.human_control.get_mut().unwrap().is_active(now)
+            && (self.stuck_recovery.is_active() || !self.tasks.read().unwrap().is_empty());
+        match self.stuck_recovery.update(is_stuck, now) {
+            Some(StuckRecoveryStep::Apply(StuckRecoveryAction::RandomMove)) => {
+                if let Some(world) = self.world.for_player(&self.player) {
+                    let position = self.stuck_recovery.get_random_move(world.player_position());
+                    let message = Message::WidgetMessage {
+                        sender: world.map_view_id(),
+                        kind: String::from("click"),
+                        arguments: vec![
+                            Value::from(Vec2i::zero()),
+                            Value::from(pos_to_map_pos(position)),
+                            Value::from(Button::LeftClick),
+                            Value::from(Modifier::None),
+                        ],
+                    };
+                    self.human_control.get_mut().unwrap().add_bot_message(&message, now);
+                    self.messages.lock().unwrap().push_back(message);
+                }
+                false
+            }
+            Some(StuckRecoveryStep::Apply(StuckRecoveryAction::Replan)) => {
+                self.world.saturate_stuck_tiles(&self.player)
+            }
+            Some(StuckRecoveryStep::Apply(StuckRecoveryAction::CancelTask)) => {
+                let task_id = self.scheduler.get_mut().unwrap().holder()
+                    .or_else(|| self.tasks.read().unwrap().last().map(|task| task.read().unwrap().id));
+                if let Some(id) = task_id {
+                    debug!("Cancel task {} for stuck session {}", id, self.id);
+                    self.remove_task(id);
+                }
+                false
+            }
+            Some(StuckRecoveryStep::Finish { outcome, actions }) => {
+                self.messages.lock().unwrap().push_back(Message::StuckRecovery { outcome, actions });
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn enter_task_log(&self, task: &TaskWithParams) -> TaskLogScope {
+        TaskLogScope::enter(make_task_log_context(self.id, task.id, task.name.as_str(), self.last_update))
+    }
+
     pub fn get_existing_message(&self) -> Option<Message> {
         self.messages.lock().unwrap().pop_front()
     }
 
     pub fn get_next_message(&self) -> Option<Message> {
+        let now = Instant::now();
+        let mut human_control = self.human_control.lock().unwrap();
+        if human_control.is_active(now) {
+            debug!("Human is in control for session {}", self.id);
+            return None;
+        }
         if let Some(world) = self.world.for_player(&self.player) {
             let mut message = None;
-            for task in self.tasks.read().unwrap().iter().map(Arc::clone) {
-                if let Some(v) = task.read().unwrap().value.lock().unwrap().get_next_message(&world, &self.scene) {
+            let mut task_id = None;
+            let mut tasks: Vec<(i32, Arc<RwLock<TaskWithParams>>)> = self.tasks.read().unwrap().iter()
+                .map(|task| (task.read().unwrap().value.lock().unwrap().priority(&world), Arc::clone(task)))
+                .collect();
+            tasks.sort_by_key(|(priority, _)| -priority);
+            let mut scheduler = self.scheduler.lock().unwrap();
+            for (priority, task) in tasks.into_iter() {
+                let locked_task = task.read().unwrap();
+                if self.night.is_paused(locked_task.name.as_str(), world.day_time()) {
+                    debug!("Task {} {} is paused at night for session {}", locked_task.id, locked_task.name, self.id);
+                    scheduler.release(locked_task.id);
+                    continue;
+                }
+                if locked_task.value.lock().unwrap().is_exclusive() && !scheduler.is_allowed(locked_task.id, priority) {
+                    debug!("Task {} {} waits for task {:?} for session {}", locked_task.id, locked_task.name, scheduler.holder(), self.id);
+                    continue;
+                }
+                if let Some(rate_limiter) = &locked_task.rate_limiter {
+                    let mut locked_rate_limiter = rate_limiter.lock().unwrap();
+                    if !locked_rate_limiter.is_available(now) {
+                        debug!("Task {} {} is throttled for session {}", locked_task.id, locked_task.name, self.id);
+                        locked_rate_limiter.add_throttled();
+                        self.stats.lock().unwrap().set_task_throttled(locked_task.id, locked_rate_limiter.throttled());
+                        continue;
+                    }
+                }
+                let (next_message, exclusive) = {
+                    let _log_scope = self.enter_task_log(&locked_task);
+                    let mut locked_value = locked_task.value.lock().unwrap();
+                    let next_message = locked_value.get_next_message(&world, &self.scene);
+                    *locked_task.status.lock().unwrap() = locked_value.status();
+                    (next_message, locked_value.is_exclusive())
+                };
+                match &next_message {
+                    Some(Message::Done { .. }) => scheduler.release(locked_task.id),
+                    Some(_) if exclusive => scheduler.acquire(locked_task.id, priority),
+                    _ if !exclusive => scheduler.release(locked_task.id),
+                    _ => (),
+                }
+                if let Some(v) = next_message {
+                    if let Some(rate_limiter) = &locked_task.rate_limiter {
+                        rate_limiter.lock().unwrap().add_message(now);
+                    }
+                    task_id = Some(locked_task.id);
                     if !matches!(v, Message::Done { .. }) {
                         message = Some(v);
                         break;
@@ -241,7 +631,14 @@ impl Session {
                     message = Some(v);
                 }
             }
+            if let (Some(Message::Done { .. }), Some(id)) = (&message, task_id) {
+                self.stats.lock().unwrap().set_task_outcome(id, TaskOutcome::Done);
+            }
             debug!("Next message for session {}: {:?}", self.id, message);
+            if let Some(v) = &message {
+                human_control.add_bot_message(v, now);
+                self.stats.lock().unwrap().add_message();
+            }
             message
         } else {
             debug!("World is not configured for session {}", self.id);
@@ -249,27 +646,310 @@ impl Session {
         }
     }
 
+    pub fn start_recording(&mut self, name: String) -> Result<(), String> {
+        if let Some(recorder) = self.recorder.as_ref() {
+            return Err(format!("Macro {:?} is already recording", recorder.name()));
+        }
+        let world = match self.world.for_player(&self.player) {
+            Some(v) => v,
+            None => return Err(String::from("World is not configured")),
+        };
+        debug!("Start recording macro {:?} for session {}", name, self.id);
+        self.recorder = Some(Recorder::new(name, world.map_view_id(), pos_to_map_pos(world.player_position()), Instant::now()));
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<(), String> {
+        let recorder = match self.recorder.take() {
+            Some(v) => v,
+            None => return Err(String::from("Macro is not recording")),
+        };
+        let name = recorder.name().clone();
+        let value = recorder.into_macro();
+        debug!("Stop recording macro {:?} with {} steps for session {}", name, value.steps.len(), self.id);
+        write_macro(&self.task_configs.macro_player.macros_path, &name, &value)
+    }
+
+    pub fn add_anchor(&mut self, grid_id: Option<i64>, object_id: Option<i64>) -> Result<(), String> {
+        match (grid_id, object_id) {
+            (Some(grid_id), None) => {
+                self.world.add_grid_anchor(grid_id);
+                Ok(())
+            }
+            (None, Some(object_id)) => self.world.add_object_anchor(&self.player, object_id),
+            _ => Err(String::from("Either grid_id or object_id should be set")),
+        }
+    }
+
+    pub fn get_player_position(&self) -> Option<Message> {
+        self.world.for_player(&self.player).map(|world| Message::PlayerPosition {
+            segment_id: world.player_segment_id(),
+            position: world.player_position(),
+            anchored: world.get_anchored_position(world.player_position()),
+        })
+    }
+
+    pub fn count_area_objects(&self, area: &Area) -> Option<Message> {
+        self.world.for_player(&self.player).map(|world| {
+            let area = area.map_points(|v| world.import_position(v));
+            let (min, max) = area.bounds();
+            let persistent = world.get_persistent_objects_in_rect(min, max);
+            let live_ids: BTreeSet<i64> = world.iter_objects().map(|v| v.id).collect();
+            let objects = world.iter_objects()
+                .chain(persistent.iter().filter(|v| !live_ids.contains(&v.id)));
+            Message::AreaObjects { value: count_area_objects(objects, &area) }
+        })
+    }
+
+    pub fn make_path_query(&self, config: &SessionConfig) -> PathQuery {
+        PathQuery {
+            world: self.world.snapshot(),
+            player: Player::from_player_data(self.player.as_player_data(), config.player.clone()),
+            config: self.task_configs.path_finder.clone(),
+        }
+    }
+
+    pub fn find_fields(&self) -> Option<Message> {
+        self.world.for_player(&self.player).map(|world| Message::Fields {
+            value: world.find_fields().into_iter()
+                .map(|field| Field {
+                    from: world.export_tile_pos(field.from),
+                    to: world.export_tile_pos(field.to),
+                    ..field
+                })
+                .collect(),
+        })
+    }
+
+    pub fn find_containers(&self) -> Option<Message> {
+        self.world.for_player(&self.player).map(|world| Message::Containers {
+            value: world.known_containers()
+                .map(|container| Container { position: world.export_position(container.position), ..container.clone() })
+                .collect(),
+            nearest_non_full: world.find_nearest_non_full_container().map(|v| v.object_id),
+        })
+    }
+
+    pub fn get_world_snapshot(&self, radius: i32, profile: Option<&String>) -> Option<Message> {
+        self.world.for_player(&self.player).map(|world| {
+            let tile_weights: Option<BTreeMap<i32, f64>> = match profile {
+                Some(profile) => match get_tile_costs_by_profile(profile.as_str(), &world) {
+                    Some(tile_costs) => Some(
+                        tile_costs.iter()
+                            .filter_map(|(name, weight)| world.get_tile_id_by_name(name).map(|id| (id, *weight)))
+                            .collect()
+                    ),
+                    None => return Message::Error { message: format!("Tile weights profile {:?} is not found", profile) },
+                },
+                None => None,
+            };
+            let weights = tile_weights.as_ref().map(BTreeMapTileWeights);
+            let snapshot = world.get_snapshot(radius, weights.as_ref());
+            Message::WorldSnapshot {
+                value: WorldSnapshot {
+                    center: world.export_tile_pos(snapshot.center),
+                    min: world.export_tile_pos(snapshot.min),
+                    ..snapshot
+                },
+            }
+        })
+    }
+
+    pub fn line_of_sight(&self, params: &LineOfSightParams) -> Option<Message> {
+        self.world.for_player(&self.player).map(|world| Message::LineOfSight {
+            value: world.has_line_of_sight(world.import_tile_pos(params.src), world.import_tile_pos(params.dst)),
+            distance: params.src.center().distance(params.dst.center()),
+        })
+    }
+
     pub fn get_player_world(&self) -> Option<PlayerWorld> {
         self.world.for_player(&self.player)
     }
 }
 
-fn make_task(name: &str, params: &[u8], bot_configs: &TaskConfigs, cancel: &Arc<AtomicBool>) -> Result<Arc<Mutex<dyn Task>>, String> {
+pub struct PathQuery {
+    world: World,
+    player: Player,
+    config: PathFinderConfig,
+}
+
+impl PathQuery {
+    pub fn find_path(&self, params: &FindPathParams, cancel: &Arc<AtomicBool>) -> Option<Message> {
+        self.world.for_player(&self.player).map(|world| {
+            let tile_costs = match get_tile_costs_by_profile(params.tiles.as_str(), &world) {
+                Some(v) => v,
+                None => return Message::Error { message: format!("Tile weights profile {:?} is not found", params.tiles) },
+            };
+            let tile_weights: BTreeMap<i32, f64> = tile_costs.iter()
+                .filter_map(|(name, weight)| world.get_tile_id_by_name(name).map(|id| (id, *weight)))
+                .collect();
+            Message::FoundPath {
+                value: world.find_path(
+                    world.import_tile_pos(params.src),
+                    world.import_tile_pos(params.dst),
+                    &BTreeMapTileWeights(&tile_weights),
+                    self.config.find_path_max_shortcut_length,
+                    self.config.find_path_max_total_iterations
+                        .unwrap_or(self.config.find_path_max_iterations),
+                    &make_find_path_node(),
+                    cancel,
+                ).into_iter()
+                    .map(|v| world.export_tile_pos(v))
+                    .collect(),
+            }
+        })
+    }
+}
+
+fn make_task(name: &str, params: &[u8], bot_configs: &TaskConfigs, cancel: &Arc<AtomicBool>,
+             cooldowns: &Arc<Mutex<Cooldowns>>, session_id: i64,
+             claims: &Arc<ExplorationClaims>) -> Result<Arc<Mutex<dyn Task>>, String> {
     match name {
-        "Explorer" => Ok(Arc::new(Mutex::new(Explorer::new(bot_configs.explorer.clone(), cancel.clone())))),
-        "ExpWndCloser" => Ok(Arc::new(Mutex::new(ExpWndCloser::new()))),
+        "Explorer" => Ok(Arc::new(Mutex::new(Explorer::new(session_id, claims.clone(), bot_configs.explorer.clone(), cancel.clone())))),
+        "ExpWndCloser" => Ok(Arc::new(Mutex::new(UiJanitor::new(UiJanitorParams::exp_wnd_closer(), bot_configs.ui_janitor.clone())))),
+        "Idler" => Ok(Arc::new(Mutex::new(Idler::new(bot_configs.idler.clone())))),
+        "UiJanitor" => {
+            if params.is_empty() {
+                return Ok(Arc::new(Mutex::new(UiJanitor::new(UiJanitorParams::default(), bot_configs.ui_janitor.clone()))));
+            }
+            match serde_json::from_slice::<UiJanitorParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(UiJanitor::new(parsed, bot_configs.ui_janitor.clone())))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
         "NewCharacter" => {
             match serde_json::from_slice::<NewCharacterParams>(params) {
                 Ok(parsed) => Ok(Arc::new(Mutex::new(NewCharacter::new(parsed)))),
                 Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
             }
         }
-        "PathFinder" => Ok(Arc::new(Mutex::new(PathFinder::new(bot_configs.path_finder.clone(), cancel.clone())))),
-        "Drinker" => Ok(Arc::new(Mutex::new(Drinker::new(bot_configs.drinker.clone())))),
+        "PathFinder" => {
+            if params.is_empty() {
+                return Ok(Arc::new(Mutex::new(PathFinder::new(PathFinderParams::default(), bot_configs.path_finder.clone(), cancel.clone()))));
+            }
+            match serde_json::from_slice::<PathFinderParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(PathFinder::from_task_params(parsed, bot_configs.path_finder.clone(), cancel.clone())))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Drinker" => Ok(Arc::new(Mutex::new(Drinker::new(bot_configs.drinker.clone(), bot_configs.path_finder.clone(), cooldowns.clone(), cancel.clone())))),
+        "Forager" => {
+            if params.is_empty() {
+                return Ok(Arc::new(Mutex::new(Forager::new(ForagerParams::default(), bot_configs.forager.clone(), cancel.clone()))));
+            }
+            match serde_json::from_slice::<ForagerParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(Forager::new(parsed, bot_configs.forager.clone(), cancel.clone())))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Follower" => {
+            match serde_json::from_slice::<FollowerParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(Follower::new(parsed, bot_configs.follower.clone(), cancel.clone())))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Transferrer" => {
+            match serde_json::from_slice::<TransferrerParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(Transferrer::new(parsed, bot_configs.transferrer.clone())))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "MacroPlayer" => {
+            match serde_json::from_slice::<MacroPlayerParams>(params) {
+                Ok(parsed) => {
+                    let value = read_macro(&bot_configs.macro_player.macros_path, &parsed.name)?;
+                    Ok(Arc::new(Mutex::new(MacroPlayer::new(parsed.name, value, bot_configs.macro_player.clone()))))
+                }
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Farmer" => {
+            match serde_json::from_slice::<FarmerParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(Farmer::new(parsed, bot_configs.farmer.clone(), bot_configs.path_finder.clone(), cancel.clone())?))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Miner" => {
+            match serde_json::from_slice::<MinerParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(Miner::new(parsed, bot_configs.miner.clone(), bot_configs.drinker.clone(),
+                                                                bot_configs.path_finder.clone(), cancel.clone())?))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Fleer" => {
+            if params.is_empty() {
+                return Ok(Arc::new(Mutex::new(Fleer::new(FleerParams::default(), bot_configs.fleer.clone(), bot_configs.path_finder.clone(), cancel.clone()))));
+            }
+            match serde_json::from_slice::<FleerParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(Fleer::new(parsed, bot_configs.fleer.clone(), bot_configs.path_finder.clone(), cancel.clone())))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Backtrack" => {
+            if params.is_empty() {
+                return Ok(Arc::new(Mutex::new(Backtrack::new(BacktrackParams::default(), bot_configs.path_finder.clone(), cancel.clone()))));
+            }
+            match serde_json::from_slice::<BacktrackParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(Backtrack::new(parsed, bot_configs.path_finder.clone(), cancel.clone())))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Crafter" => {
+            match serde_json::from_slice::<CrafterParams>(params) {
+                Ok(parsed) => {
+                    let recipe = read_recipe(&bot_configs.crafter.recipes_path, &parsed.recipe)?;
+                    Ok(Arc::new(Mutex::new(Crafter::new(parsed, recipe, bot_configs.crafter.clone()))))
+                }
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Logout" => {
+            if params.is_empty() {
+                return Ok(Arc::new(Mutex::new(Logout::new(LogoutParams::default(), bot_configs.logout.clone(), bot_configs.path_finder.clone(), cancel.clone()))));
+            }
+            match serde_json::from_slice::<LogoutParams>(params) {
+                Ok(parsed) => Ok(Arc::new(Mutex::new(Logout::new(parsed, bot_configs.logout.clone(), bot_configs.path_finder.clone(), cancel.clone())))),
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        "Pipeline" => {
+            match serde_json::from_slice::<PipelineParams>(params) {
+                Ok(parsed) => {
+                    let bot_configs = bot_configs.clone();
+                    let cancel = cancel.clone();
+                    let cooldowns = cooldowns.clone();
+                    let claims = claims.clone();
+                    let make_stage_task = move |name: &str, params: &[u8]| {
+                        make_task(name, params, &bot_configs, &cancel, &cooldowns, session_id, &claims)
+                    };
+                    Ok(Arc::new(Mutex::new(Pipeline::new(parsed, Box::new(make_stage_task))?)))
+                }
+                Err(e) => Err(format!("Failed to parse {} bot params: {}", name, e)),
+            }
+        }
+        _ if is_wasm_task(&bot_configs.wasm.modules_path, name) => {
+            Ok(Arc::new(Mutex::new(WasmTask::new(name, params, bot_configs.wasm.clone())?)))
+        }
         _ => Err(String::from("Task is not found")),
     }
 }
 
+fn make_task_log_context(session_id: i64, task_id: i64, task_name: &str, update: i64) -> TaskLogContext {
+    TaskLogContext { session_id, task_id, task_name: String::from(task_name), update }
+}
+
+fn make_rate_limiter(name: &str, params: &[u8], bot_configs: &TaskConfigs) -> Option<Mutex<RateLimiter>> {
+    get_task_rate_limit(params, bot_configs.rate_limits.get(name))
+        .map(|v| Mutex::new(RateLimiter::new(&v)))
+}
+
+fn preload_resources(session_id: i64, player: &mut Player, world: &World) {
+    let preloaded = player.preload_resources(world.get_stored_resources());
+    if preloaded > 0 {
+        debug!("Preloaded {} stored resources for session {}", preloaded, session_id);
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct SessionData {
     id: i64,
@@ -280,6 +960,20 @@ pub struct SessionData {
     tasks: Vec<TaskParams>,
 }
 
+impl SessionData {
+    pub fn last_update(&self) -> i64 {
+        self.last_update
+    }
+
+    pub fn world(&self) -> &WorldData {
+        &self.world
+    }
+
+    pub fn player(&self) -> &PlayerData {
+        &self.player
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct TaskParams {
     id: i64,
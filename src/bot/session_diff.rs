@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::map::Grid;
+use crate::bot::objects::Object;
+use crate::bot::player::Widget;
+use crate::bot::session::SessionData;
+use crate::bot::vec2::Vec2f;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct SessionDiff {
+    pub server_last_update: i64,
+    pub client_last_update: i64,
+    pub missing_widgets: Vec<WidgetInfo>,
+    pub unexpected_widgets: Vec<WidgetInfo>,
+    pub divergent_widgets: Vec<WidgetDiff>,
+    pub missing_objects: Vec<i64>,
+    pub unexpected_objects: Vec<i64>,
+    pub divergent_objects: Vec<ObjectDiff>,
+    pub missing_grids: Vec<i64>,
+    pub grid_revision_mismatches: Vec<GridRevisionDiff>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct WidgetInfo {
+    pub id: i32,
+    pub parent: i32,
+    pub kind: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct WidgetDiff {
+    pub id: i32,
+    pub server: WidgetInfo,
+    pub client: WidgetInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ObjectDiff {
+    pub id: i64,
+    pub server_position: Vec2f,
+    pub client_position: Vec2f,
+    pub distance: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct GridRevisionDiff {
+    pub id: i64,
+    pub server_revision: i64,
+    pub client_revision: i64,
+}
+
+impl SessionDiff {
+    pub fn is_empty(&self) -> bool {
+        self.server_last_update == self.client_last_update
+            && self.missing_widgets.is_empty()
+            && self.unexpected_widgets.is_empty()
+            && self.divergent_widgets.is_empty()
+            && self.missing_objects.is_empty()
+            && self.unexpected_objects.is_empty()
+            && self.divergent_objects.is_empty()
+            && self.missing_grids.is_empty()
+            && self.grid_revision_mismatches.is_empty()
+    }
+}
+
+pub fn make_session_diff(server: &SessionData, client: &SessionData, max_object_distance: f64) -> SessionDiff {
+    let mut result = SessionDiff {
+        server_last_update: server.last_update(),
+        client_last_update: client.last_update(),
+        ..Default::default()
+    };
+    diff_widgets(server.player().widgets(), client.player().widgets(), &mut result);
+    diff_objects(server.world().objects().iter(), client.world().objects().iter(), max_object_distance, &mut result);
+    diff_grids(server.world().map().grids(), client.world().map().grids(), &mut result);
+    result
+}
+
+fn diff_widgets(server: &[Widget], client: &[Widget], result: &mut SessionDiff) {
+    let server: BTreeMap<i32, &Widget> = server.iter().map(|v| (v.id, v)).collect();
+    let client: BTreeMap<i32, &Widget> = client.iter().map(|v| (v.id, v)).collect();
+    for (id, client_widget) in client.iter() {
+        match server.get(id) {
+            Some(server_widget) => {
+                if server_widget.kind != client_widget.kind || server_widget.parent != client_widget.parent {
+                    result.divergent_widgets.push(WidgetDiff {
+                        id: *id,
+                        server: make_widget_info(server_widget),
+                        client: make_widget_info(client_widget),
+                    });
+                }
+            }
+            None => result.missing_widgets.push(make_widget_info(client_widget)),
+        }
+    }
+    result.unexpected_widgets.extend(server.iter()
+        .filter(|(id, _)| !client.contains_key(*id))
+        .map(|(_, v)| make_widget_info(v)));
+}
+
+fn diff_objects<'a>(server: impl Iterator<Item=&'a Object>, client: impl Iterator<Item=&'a Object>,
+                    max_distance: f64, result: &mut SessionDiff) {
+    let server: BTreeMap<i64, &Object> = server.map(|v| (v.id, v)).collect();
+    let client: BTreeMap<i64, &Object> = client.map(|v| (v.id, v)).collect();
+    for (id, client_object) in client.iter() {
+        match server.get(id) {
+            Some(server_object) => {
+                let distance = server_object.position.distance(client_object.position);
+                if distance > max_distance {
+                    result.divergent_objects.push(ObjectDiff {
+                        id: *id,
+                        server_position: server_object.position,
+                        client_position: client_object.position,
+                        distance,
+                    });
+                }
+            }
+            None => result.missing_objects.push(*id),
+        }
+    }
+    result.unexpected_objects.extend(server.keys().filter(|id| !client.contains_key(*id)));
+}
+
+fn diff_grids(server: &[Grid], client: &[Grid], result: &mut SessionDiff) {
+    let server: BTreeMap<i64, i64> = server.iter().map(|v| (v.id, v.revision)).collect();
+    for grid in client.iter() {
+        match server.get(&grid.id) {
+            Some(revision) => {
+                if *revision != grid.revision {
+                    result.grid_revision_mismatches.push(GridRevisionDiff {
+                        id: grid.id,
+                        server_revision: *revision,
+                        client_revision: grid.revision,
+                    });
+                }
+            }
+            None => result.missing_grids.push(grid.id),
+        }
+    }
+}
+
+fn make_widget_info(widget: &Widget) -> WidgetInfo {
+    WidgetInfo { id: widget.id, parent: widget.parent, kind: widget.kind.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::objects::make_object;
+
+    use super::*;
+
+    fn make_widget(id: i32, parent: i32, kind: &str) -> Widget {
+        Widget {
+            id,
+            parent,
+            kind: String::from(kind),
+            pargs: Vec::new(),
+            cargs: Vec::new(),
+            pargs_add: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn diff_widgets_should_find_missing_unexpected_and_divergent_widgets() {
+        let mut result = SessionDiff::default();
+        diff_widgets(
+            &[make_widget(1, 0, "gameui"), make_widget(2, 1, "inv"), make_widget(3, 1, "wnd")],
+            &[make_widget(1, 0, "gameui"), make_widget(2, 1, "epry"), make_widget(4, 1, "cal")],
+            &mut result,
+        );
+        assert_eq!(result.missing_widgets, vec![WidgetInfo { id: 4, parent: 1, kind: String::from("cal") }]);
+        assert_eq!(result.unexpected_widgets, vec![WidgetInfo { id: 3, parent: 1, kind: String::from("wnd") }]);
+        assert_eq!(result.divergent_widgets.iter().map(|v| v.id).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn diff_objects_should_report_positions_further_than_max_distance() {
+        let mut result = SessionDiff::default();
+        let server = vec![make_object(1, 0.0, 0.0, None), make_object(2, 10.0, 0.0, None), make_object(3, 0.0, 0.0, None)];
+        let client = vec![make_object(1, 0.5, 0.0, None), make_object(2, 20.0, 0.0, None), make_object(4, 0.0, 0.0, None)];
+        diff_objects(server.iter(), client.iter(), 1.0, &mut result);
+        assert_eq!(result.divergent_objects, vec![ObjectDiff {
+            id: 2,
+            server_position: Vec2f::new(10.0, 0.0),
+            client_position: Vec2f::new(20.0, 0.0),
+            distance: 10.0,
+        }]);
+        assert_eq!(result.missing_objects, vec![4]);
+        assert_eq!(result.unexpected_objects, vec![3]);
+        assert!(!result.is_empty());
+    }
+}
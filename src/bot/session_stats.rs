@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::vec2::Vec2f;
+
+const STATS_CSV_HEADER: &'static str = "session,started,finished,duration,distance,grids,tasks,done_tasks,removed_tasks,throttled_messages\n";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TaskOutcome {
+    Running,
+    Done,
+    Removed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeliveryChannel {
+    Poll,
+    WebSocket,
+    Grpc,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskStats {
+    pub id: i64,
+    pub name: String,
+    pub outcome: TaskOutcome,
+    pub throttled: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionStats {
+    pub session: i64,
+    pub started: f64,
+    pub finished: Option<f64>,
+    pub duration: f64,
+    pub distance: f64,
+    pub grids: usize,
+    pub tasks: Vec<TaskStats>,
+    pub delivered_messages: BTreeMap<DeliveryChannel, u64>,
+    pub updates: u64,
+    pub messages: u64,
+}
+
+pub struct SessionStatsCollector {
+    started: SystemTime,
+    finished: Option<SystemTime>,
+    distance: f64,
+    last_player_position: Option<(i64, Vec2f)>,
+    grids: BTreeSet<i64>,
+    tasks: BTreeMap<i64, TaskStats>,
+    delivered_messages: BTreeMap<DeliveryChannel, u64>,
+    updates: u64,
+    messages: u64,
+}
+
+impl SessionStatsCollector {
+    pub fn new() -> Self {
+        Self {
+            started: SystemTime::now(),
+            finished: None,
+            distance: 0.0,
+            last_player_position: None,
+            grids: BTreeSet::new(),
+            tasks: BTreeMap::new(),
+            delivered_messages: BTreeMap::new(),
+            updates: 0,
+            messages: 0,
+        }
+    }
+
+    pub fn add_task(&mut self, id: i64, name: &str) {
+        self.tasks.insert(id, TaskStats { id, name: String::from(name), outcome: TaskOutcome::Running, throttled: 0 });
+    }
+
+    pub fn set_task_outcome(&mut self, id: i64, outcome: TaskOutcome) {
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.outcome = outcome;
+        }
+    }
+
+    pub fn set_task_throttled(&mut self, id: i64, throttled: u64) {
+        if let Some(task) = self.tasks.get_mut(&id) {
+            task.throttled = throttled;
+        }
+    }
+
+    pub fn add_delivered_messages(&mut self, channel: DeliveryChannel, count: usize) {
+        *self.delivered_messages.entry(channel).or_insert(0) += count as u64;
+    }
+
+    pub fn add_update(&mut self) {
+        self.updates += 1;
+    }
+
+    pub fn add_message(&mut self) {
+        self.messages += 1;
+    }
+
+    pub fn add_grid(&mut self, id: i64) {
+        self.grids.insert(id);
+    }
+
+    pub fn update_player_position(&mut self, segment_id: i64, position: Vec2f) {
+        if let Some((last_segment_id, last_position)) = self.last_player_position {
+            if last_segment_id == segment_id {
+                self.distance += last_position.distance(position);
+            }
+        }
+        self.last_player_position = Some((segment_id, position));
+    }
+
+    pub fn finish(&mut self) {
+        if self.finished.is_none() {
+            self.finished = Some(SystemTime::now());
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.finished = None;
+    }
+
+    pub fn get(&self, session_id: i64) -> SessionStats {
+        let end = self.finished.unwrap_or_else(SystemTime::now);
+        SessionStats {
+            session: session_id,
+            started: as_unix_time(self.started),
+            finished: self.finished.map(as_unix_time),
+            duration: end.duration_since(self.started).map(|v| v.as_secs_f64()).unwrap_or(0.0),
+            distance: self.distance,
+            grids: self.grids.len(),
+            tasks: self.tasks.values().cloned().collect(),
+            delivered_messages: self.delivered_messages.clone(),
+            updates: self.updates,
+            messages: self.messages,
+        }
+    }
+}
+
+pub fn write_session_stats_snapshot(path: &String, stats: &SessionStats) -> std::io::Result<()> {
+    std::fs::create_dir_all(path)?;
+    std::fs::write(format!("{}/{}.stats.json", path, stats.session), serde_json::to_vec(stats).unwrap())
+}
+
+pub fn write_session_stats(path: &String, stats: &SessionStats) -> std::io::Result<()> {
+    write_session_stats_snapshot(path, stats)?;
+    let csv_path = format!("{}/stats.csv", path);
+    rotate_outdated_stats_csv(path, &csv_path)?;
+    let write_header = !Path::new(&csv_path).exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(csv_path)?;
+    if write_header {
+        file.write_all(STATS_CSV_HEADER.as_bytes())?;
+    }
+    let count_tasks = |outcome| stats.tasks.iter().filter(|v| v.outcome == outcome).count();
+    file.write_all(format!(
+        "{},{},{},{},{},{},{},{},{},{}\n",
+        stats.session,
+        stats.started,
+        stats.finished.map(|v| v.to_string()).unwrap_or_default(),
+        stats.duration,
+        stats.distance,
+        stats.grids,
+        stats.tasks.len(),
+        count_tasks(TaskOutcome::Done),
+        count_tasks(TaskOutcome::Removed),
+        stats.tasks.iter().map(|v| v.throttled).sum::<u64>(),
+    ).as_bytes())
+}
+
+// Rows of a different header can't be appended, old file is kept aside with its own header
+fn rotate_outdated_stats_csv(path: &String, csv_path: &String) -> std::io::Result<()> {
+    let file = match File::open(csv_path) {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
+    };
+    let mut header = String::new();
+    BufReader::new(file).read_line(&mut header)?;
+    if header == STATS_CSV_HEADER {
+        return Ok(());
+    }
+    std::fs::rename(csv_path, format!("{}/stats.{}.csv", path, as_unix_time(SystemTime::now()) as i64))
+}
+
+fn as_unix_time(value: SystemTime) -> f64 {
+    value.duration_since(UNIX_EPOCH).map(|v| v.as_secs_f64()).unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumed_session_stats_should_not_be_finished() {
+        let mut stats = SessionStatsCollector::new();
+        stats.finish();
+        assert!(stats.get(1).finished.is_some());
+        stats.resume();
+        assert_eq!(stats.get(1).finished, None);
+    }
+
+    #[test]
+    fn write_session_stats_snapshot_should_not_append_csv_row() {
+        let path = String::from("write_session_stats_snapshot_should_not_append_csv_row");
+        match std::fs::remove_dir_all(&path) { _ => () };
+        let stats = SessionStatsCollector::new().get(42);
+        write_session_stats_snapshot(&path, &stats).unwrap();
+        write_session_stats_snapshot(&path, &stats).unwrap();
+        let has_csv = Path::new(&format!("{}/stats.csv", path)).exists();
+        let json = std::fs::read(format!("{}/42.stats.json", path)).unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+        assert!(!has_csv);
+        assert_eq!(serde_json::from_slice::<SessionStats>(&json).unwrap(), stats);
+    }
+
+    #[test]
+    fn write_session_stats_should_rotate_csv_with_outdated_header() {
+        let path = String::from("write_session_stats_should_rotate_csv_with_outdated_header");
+        match std::fs::remove_dir_all(&path) { _ => () };
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(format!("{}/stats.csv", path), "session,started\n1,2\n").unwrap();
+        let stats = SessionStats {
+            session: 42,
+            started: 1.0,
+            finished: Some(2.0),
+            duration: 1.0,
+            distance: 0.0,
+            grids: 0,
+            tasks: vec![TaskStats { id: 1, name: String::from("Explorer"), outcome: TaskOutcome::Done, throttled: 3 }],
+            delivered_messages: BTreeMap::new(),
+            updates: 0,
+            messages: 0,
+        };
+        write_session_stats(&path, &stats).unwrap();
+        let rotated = std::fs::read_dir(&path).unwrap()
+            .map(|v| v.unwrap().file_name().into_string().unwrap())
+            .filter(|v| v.starts_with("stats.") && v.ends_with(".csv") && v != "stats.csv")
+            .collect::<Vec<_>>();
+        let csv = std::fs::read_to_string(format!("{}/stats.csv", path)).unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(csv, format!("{}42,1,2,1,0,0,1,1,0,3\n", STATS_CSV_HEADER));
+    }
+}
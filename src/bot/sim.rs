@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::Value as JsonValue;
+
+use crate::bot::map::{GRID_SIZE, GridNeighbour, map_pos_to_pos, pos_to_grid_pos, pos_to_tile_pos, tile_pos_to_pos};
+use crate::bot::protocol::{Color, Event, MapGrid, Message, Update, Value};
+use crate::bot::vec2::{Vec2f, Vec2i};
+
+const GAME_UI_ID: i32 = 6;
+const MAP_VIEW_ID: i32 = 7;
+const INVENTORY_ID: i32 = 8;
+const EQUIPMENT_ID: i32 = 9;
+const STAMINA_METER_ID: i32 = 10;
+const STAMINA_RESOURCE_ID: i32 = 1;
+const STAMINA_RESOURCE: &'static str = "gfx/hud/meter/stam";
+const PLAYER_OBJECT_ID: i64 = 1;
+const DEFAULT_TILE: &'static str = "gfx/tiles/grass";
+
+pub struct Simulator {
+    session: i64,
+    number: i64,
+    player_name: String,
+    position: Vec2f,
+    target: Option<Vec2f>,
+    speed: f64,
+    grids: Vec<Vec2i>,
+    tile_ids: BTreeMap<String, i32>,
+    tiles: BTreeMap<Vec2i, i32>,
+    blocked_tiles: BTreeSet<i32>,
+    objects: BTreeMap<i64, (String, Vec2f)>,
+}
+
+impl Simulator {
+    pub fn new(session: i64) -> Self {
+        let mut tile_ids = BTreeMap::new();
+        tile_ids.insert(String::from(DEFAULT_TILE), 1);
+        Self {
+            session,
+            number: 0,
+            player_name: String::from("sim"),
+            position: tile_pos_to_pos(Vec2i::new(GRID_SIZE / 2, GRID_SIZE / 2)) + Vec2f::new(0.5, 0.5),
+            target: None,
+            speed: 50.0,
+            grids: (-1..=1).flat_map(|y| (-1..=1).map(move |x| Vec2i::new(x, y))).collect(),
+            tile_ids,
+            tiles: BTreeMap::new(),
+            blocked_tiles: BTreeSet::new(),
+            objects: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_player_position(mut self, position: Vec2f) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_tiles(mut self, name: &str, min: Vec2i, max: Vec2i, blocked: bool) -> Self {
+        let next_id = self.tile_ids.len() as i32 + 1;
+        let tile_id = *self.tile_ids.entry(String::from(name)).or_insert(next_id);
+        if blocked {
+            self.blocked_tiles.insert(tile_id);
+        }
+        for y in min.y()..=max.y() {
+            for x in min.x()..=max.x() {
+                self.tiles.insert(Vec2i::new(x, y), tile_id);
+            }
+        }
+        self
+    }
+
+    pub fn with_object(mut self, id: i64, name: &str, position: Vec2f) -> Self {
+        self.objects.insert(id, (String::from(name), position));
+        self
+    }
+
+    pub fn session(&self) -> i64 {
+        self.session
+    }
+
+    pub fn next_number(&mut self) -> i64 {
+        self.number += 1;
+        self.number
+    }
+
+    pub fn player_position(&self) -> Vec2f {
+        self.position
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.target.is_some()
+    }
+
+    pub fn start(&mut self) -> Vec<JsonValue> {
+        let mut events = vec![
+            Event::ResourceAdd { id: STAMINA_RESOURCE_ID, version: 1, name: String::from(STAMINA_RESOURCE) },
+            Event::NewWidget {
+                id: GAME_UI_ID,
+                kind: String::from("gameui"),
+                parent: 0,
+                pargs: vec![Value::from(Vec2i::zero())],
+                cargs: vec![Value::from(self.player_name.clone()), Value::from(PLAYER_OBJECT_ID as i32)],
+            },
+            Event::NewWidget {
+                id: MAP_VIEW_ID,
+                kind: String::from("mapview"),
+                parent: GAME_UI_ID,
+                pargs: vec![Value::from(String::from("mapview"))],
+                cargs: vec![Value::from(Vec2i::new(800, 600)), Value::from(Vec2i::zero()), Value::from(PLAYER_OBJECT_ID as i32)],
+            },
+            Event::NewWidget {
+                id: INVENTORY_ID,
+                kind: String::from("inv"),
+                parent: GAME_UI_ID,
+                pargs: vec![Value::from(String::from("inv"))],
+                cargs: vec![Value::from(Vec2i::new(4, 4))],
+            },
+            Event::NewWidget {
+                id: EQUIPMENT_ID,
+                kind: String::from("epry"),
+                parent: GAME_UI_ID,
+                pargs: vec![Value::from(String::from("equ"))],
+                cargs: vec![Value::from(PLAYER_OBJECT_ID as i32)],
+            },
+            Event::NewWidget {
+                id: STAMINA_METER_ID,
+                kind: String::from("im"),
+                parent: GAME_UI_ID,
+                pargs: vec![Value::from(String::from("meter"))],
+                cargs: vec![Value::from(STAMINA_RESOURCE_ID)],
+            },
+            Event::UIMessage {
+                id: STAMINA_METER_ID,
+                msg: String::from("set"),
+                args: vec![Value::from(Color { r: 64, g: 64, b: 255, a: 255 }), Value::from(100i32)],
+            },
+        ];
+        for (name, id) in self.tile_ids.iter() {
+            events.push(Event::MapTile { id: *id, version: 1, name: name.clone(), color: make_tile_color(*id) });
+        }
+        for (index, grid_pos) in self.grids.iter().enumerate() {
+            events.push(Event::MapGridAdd {
+                grid: self.make_grid(*grid_pos),
+                neighbours: self.grids[..index].iter()
+                    .map(|v| GridNeighbour { id: make_grid_id(*v), offset: *v - *grid_pos })
+                    .collect(),
+            });
+        }
+        events.push(Event::GobAdd { id: PLAYER_OBJECT_ID, position: self.position, angle: 0.0, name: None });
+        for (id, (name, position)) in self.objects.iter() {
+            events.push(Event::GobAdd { id: *id, position: *position, angle: 0.0, name: Some(name.clone()) });
+        }
+        events.into_iter().map(|v| self.make_update(v)).collect()
+    }
+
+    pub fn handle(&mut self, message: &str) -> Result<(), String> {
+        let message: Message = serde_json::from_str(message)
+            .map_err(|e| format!("Failed to parse message: {}", e))?;
+        if let Message::WidgetMessage { sender, kind, arguments } = message {
+            if sender == MAP_VIEW_ID && kind == "click" {
+                match arguments.get(1) {
+                    Some(Value::Coord { value }) => self.target = Some(map_pos_to_pos(*value)),
+                    v => return Err(format!("Invalid click target: {:?}", v)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn step(&mut self, duration: f64) -> Vec<JsonValue> {
+        let target = match self.target {
+            Some(v) => v,
+            None => return Vec::new(),
+        };
+        let distance = self.position.distance(target);
+        let max_distance = self.speed * duration;
+        let next = if distance <= max_distance {
+            target
+        } else {
+            self.position + (target - self.position) * (max_distance / distance)
+        };
+        if !self.is_passable(next) {
+            self.target = None;
+            return Vec::new();
+        }
+        self.position = next;
+        if next == target {
+            self.target = None;
+        }
+        let event = Event::GobMove { id: PLAYER_OBJECT_ID, position: next, angle: 0.0 };
+        vec![self.make_update(event)]
+    }
+
+    fn is_passable(&self, position: Vec2f) -> bool {
+        let tile_pos = pos_to_tile_pos(position);
+        self.grids.contains(&pos_to_grid_pos(position))
+            && !self.tiles.get(&tile_pos).map(|v| self.blocked_tiles.contains(v)).unwrap_or(false)
+    }
+
+    fn make_grid(&self, grid_pos: Vec2i) -> MapGrid {
+        let default_tile_id = self.tile_ids[DEFAULT_TILE];
+        let mut tiles = Vec::with_capacity((GRID_SIZE * GRID_SIZE) as usize);
+        for y in 0..GRID_SIZE {
+            for x in 0..GRID_SIZE {
+                let tile_pos = grid_pos * GRID_SIZE + Vec2i::new(x, y);
+                tiles.push(self.tiles.get(&tile_pos).cloned().unwrap_or(default_tile_id));
+            }
+        }
+        MapGrid {
+            id: make_grid_id(grid_pos),
+            position: grid_pos,
+            heights: vec![0.0; (GRID_SIZE * GRID_SIZE) as usize],
+            tiles,
+        }
+    }
+
+    fn make_update(&mut self, event: Event) -> JsonValue {
+        let update = Update { session: self.session, number: self.next_number(), event };
+        serde_json::to_value(&update).unwrap()
+    }
+}
+
+fn make_grid_id(grid_pos: Vec2i) -> i64 {
+    ((grid_pos.x() as i64) << 32) | (grid_pos.y() as u32 as i64) | (1 << 62)
+}
+
+fn make_tile_color(tile_id: i32) -> i32 {
+    (0xFF << 24) | (tile_id.wrapping_mul(0x3F5A27) & 0x00FFFFFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulator_should_move_player_to_clicked_position() {
+        let mut simulator = Simulator::new(42)
+            .with_speed(5.0)
+            .with_tiles("gfx/tiles/deep", Vec2i::new(60, 40), Vec2i::new(60, 60), true);
+        let updates = simulator.start();
+        assert_eq!(updates.len(), 7 + 2 + 9 + 1);
+        assert_eq!(updates.last().unwrap()["event"]["type"], "GobAdd");
+        let click = r#"{"type":"WidgetMessage","sender":7,"kind":"click","arguments":[
+            {"type":"Coord","value":{"x":0,"y":0}},
+            {"type":"Coord","value":{"x":71680,"y":51200}},
+            {"type":"Int","value":1},
+            {"type":"Int","value":0}
+        ]}"#;
+        assert_eq!(simulator.handle(click), Ok(()));
+        assert!(simulator.is_moving());
+        let updates = simulator.step(1.0);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0]["event"]["type"], "GobMove");
+        assert!((simulator.player_position().distance(Vec2f::new(550.5, 550.5)) - 5.0).abs() < 1e-9);
+        while simulator.is_moving() {
+            simulator.step(1.0);
+        }
+        assert_eq!(pos_to_tile_pos(simulator.player_position()).x(), 59);
+    }
+}
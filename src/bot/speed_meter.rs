@@ -0,0 +1,61 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::bot::vec2::Vec2f;
+
+const WINDOW: Duration = Duration::from_secs(3);
+
+#[derive(Default)]
+pub struct SpeedMeter {
+    samples: VecDeque<(Vec2f, Instant)>,
+    speed: Option<f64>,
+}
+
+impl SpeedMeter {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            speed: None,
+        }
+    }
+
+    pub fn speed(&self) -> Option<f64> {
+        self.speed
+    }
+
+    pub fn update(&mut self, position: Vec2f, now: Instant) {
+        self.samples.push_back((position, now));
+        while self.samples.len() > 2 && now - self.samples[0].1 > WINDOW {
+            self.samples.pop_front();
+        }
+        let duration = now - self.samples[0].1;
+        if duration == Duration::ZERO {
+            return;
+        }
+        let distance: f64 = self.samples.iter().zip(self.samples.iter().skip(1))
+            .map(|((src, _), (dst, _))| src.distance(*dst))
+            .sum();
+        if distance > 0.0 {
+            self.speed = Some(distance / duration.as_secs_f64());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_should_be_measured_by_recent_moves_and_kept_while_standing() {
+        let mut meter = SpeedMeter::new();
+        let now = Instant::now();
+        meter.update(Vec2f::new(0.0, 0.0), now);
+        assert_eq!(meter.speed(), None);
+        meter.update(Vec2f::new(10.0, 0.0), now + Duration::from_secs(1));
+        meter.update(Vec2f::new(20.0, 0.0), now + Duration::from_secs(2));
+        assert_eq!(meter.speed(), Some(10.0));
+        meter.update(Vec2f::new(20.0, 0.0), now + Duration::from_secs(10));
+        meter.update(Vec2f::new(20.0, 0.0), now + Duration::from_secs(11));
+        assert_eq!(meter.speed(), Some(10.0));
+    }
+}
@@ -1,17 +1,22 @@
-use std::cell::RefCell;
-use std::collections::{BTreeMap, HashMap};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::ops::{Deref, DerefMut};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{JoinHandle, sleep, spawn};
 use std::time::{Duration, Instant};
 
-use rand::distributions::{Distribution, Uniform};
-use rand::rngs::SmallRng;
-use rand::SeedableRng;
-use rusqlite::{Connection, named_params, NO_PARAMS, OptionalExtension, Row, Transaction};
+use rusqlite::{Connection, named_params, NO_PARAMS, OptionalExtension, Row, Transaction, TransactionBehavior};
 
-use crate::bot::map::{Grid, GridNeighbour, Tile};
-use crate::bot::map_db::MapDb;
-use crate::bot::vec2::Vec2i;
+use crate::bot::bookmarks::Bookmark;
+use crate::bot::map::{Grid, grid_pos_to_pos, GridNeighbour, MapObject, pos_to_grid_pos, Tile};
+use crate::bot::map_db::{decode_grid_values, encode_grid_values, get_grid_hash, GRID_FORMAT_BINCODE_ZSTD, GRID_FORMAT_JSON, MapDb,
+                         MapDbCacheStats, MapDbWriteBehindConfig};
+use crate::bot::map_db_cache::MapDbCache;
+use crate::bot::map_retention::{GridRetentionInfo, MapPruneStats, MapRetentionConfig, select_grids_to_prune};
+use crate::bot::player::Resource;
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::zones::Zone;
 
 const CREATE_DB_QUERY: &'static str = r"
     BEGIN TRANSACTION;
@@ -33,7 +38,9 @@ const CREATE_DB_QUERY: &'static str = r"
         position_x INTEGER NOT NULL,
         position_y INTEGER NOT NULL,
         heights BLOB NOT NULL,
-        tiles BLOB NOT NULL
+        tiles BLOB NOT NULL,
+        format INTEGER NOT NULL DEFAULT 0,
+        hash INTEGER NOT NULL DEFAULT 0
     );
 
     CREATE INDEX IF NOT EXISTS i_grids_coord
@@ -42,9 +49,105 @@ const CREATE_DB_QUERY: &'static str = r"
     CREATE INDEX IF NOT EXISTS i_grids_segment
         ON grids (segment_id);
 
+    CREATE TABLE IF NOT EXISTS grid_changes (
+        grid_id INTEGER PRIMARY KEY,
+        change_id INTEGER NOT NULL
+    );
+
+    CREATE UNIQUE INDEX IF NOT EXISTS uq_grid_changes_change_id
+        ON grid_changes (change_id);
+
+    CREATE TABLE IF NOT EXISTS objects (
+        object_id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        grid_id INTEGER NOT NULL,
+        offset_x REAL NOT NULL,
+        offset_y REAL NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS i_objects_grid
+        ON objects (grid_id);
+
+    CREATE TABLE IF NOT EXISTS grids_last_seen (
+        grid_id INTEGER PRIMARY KEY,
+        last_seen INTEGER NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS i_grids_last_seen
+        ON grids_last_seen (last_seen);
+
+    CREATE TABLE IF NOT EXISTS zones (
+        segment_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        points TEXT NOT NULL,
+        PRIMARY KEY (segment_id, name)
+    );
+
+    CREATE TABLE IF NOT EXISTS bookmarks (
+        segment_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        x REAL NOT NULL,
+        y REAL NOT NULL,
+        PRIMARY KEY (segment_id, name)
+    );
+
+    CREATE TABLE IF NOT EXISTS resources (
+        name TEXT NOT NULL,
+        version INTEGER NOT NULL,
+        resource_id INTEGER NOT NULL,
+        seen INTEGER NOT NULL,
+        PRIMARY KEY (name, version)
+    );
+
+    CREATE TRIGGER IF NOT EXISTS t_grids_insert AFTER INSERT ON grids
+    BEGIN
+        INSERT OR REPLACE INTO grid_changes (grid_id, change_id)
+        VALUES (new.grid_id, (SELECT COALESCE(MAX(change_id), 0) + 1 FROM grid_changes));
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS t_grids_update AFTER UPDATE ON grids
+    BEGIN
+        INSERT OR REPLACE INTO grid_changes (grid_id, change_id)
+        VALUES (new.grid_id, (SELECT COALESCE(MAX(change_id), 0) + 1 FROM grid_changes));
+    END;
+
     COMMIT;
 ";
 
+const HAS_GRIDS_FORMAT_QUERY: &'static str = r"
+    SELECT COUNT(1)
+      FROM pragma_table_info('grids')
+     WHERE name = 'format'
+";
+
+const ADD_GRIDS_FORMAT_QUERY: &'static str = r"
+    ALTER TABLE grids ADD COLUMN format INTEGER NOT NULL DEFAULT 0
+";
+
+const HAS_GRIDS_HASH_QUERY: &'static str = r"
+    SELECT COUNT(1)
+      FROM pragma_table_info('grids')
+     WHERE name = 'hash'
+";
+
+const ADD_GRIDS_HASH_QUERY: &'static str = r"
+    ALTER TABLE grids ADD COLUMN hash INTEGER NOT NULL DEFAULT 0
+";
+
+const GET_GRIDS_BY_FORMAT: &'static str = r"
+    SELECT grid_id, heights, tiles
+      FROM grids
+     WHERE format = :format
+";
+
+const SET_GRID_DATA_QUERY: &'static str = r"
+    UPDATE grids
+       SET heights = :heights,
+           tiles = :tiles,
+           format = :format
+     WHERE grid_id = :grid_id
+";
+
 const GET_TILES: &'static str = r"
     SELECT tile_id, version, name, color
       FROM tiles
@@ -52,7 +155,7 @@ const GET_TILES: &'static str = r"
 ";
 
 const GET_GRIDS: &'static str = r"
-    SELECT grid_id, revision, segment_id, position_x, position_y, heights, tiles
+    SELECT grid_id, revision, segment_id, position_x, position_y, heights, tiles, format
       FROM grids
      ORDER BY grid_id
 ";
@@ -79,25 +182,41 @@ const GET_TILE_BY_NAME_QUERY: &'static str = r"
 ";
 
 const INSERT_NEW_SEGMENT_GRID_QUERY: &'static str = r"
-    INSERT INTO grids (grid_id, revision, segment_id, position_x, position_y, heights, tiles)
-    VALUES (:grid_id, 1, :grid_id, 0, 0, :heights, :tiles)
+    INSERT INTO grids (grid_id, revision, segment_id, position_x, position_y, heights, tiles, format, hash)
+    VALUES (:grid_id, 1, :grid_id, 0, 0, :heights, :tiles, :format, :hash)
 ";
 
 const INSERT_EXISTING_SEGMENT_GRID_QUERY: &'static str = r"
-    INSERT INTO grids (grid_id, revision, segment_id, position_x, position_y, heights, tiles)
-    VALUES (:grid_id, 1, :segment_id, :position_x, :position_y, :heights, :tiles)
+    INSERT INTO grids (grid_id, revision, segment_id, position_x, position_y, heights, tiles, format, hash)
+    VALUES (:grid_id, 1, :segment_id, :position_x, :position_y, :heights, :tiles, :format, :hash)
 ";
 
 const UPDATE_GRID_QUERY: &'static str = r"
     UPDATE grids
        SET revision = revision + 1,
            heights = :heights,
-           tiles = :tiles
+           tiles = :tiles,
+           format = :format,
+           hash = :hash
+     WHERE grid_id = :grid_id
+       AND hash != :hash
+";
+
+const SET_GRID_REVISION_QUERY: &'static str = r"
+    UPDATE grids
+       SET revision = :revision
      WHERE grid_id = :grid_id
+       AND revision < :revision
 ";
 
 const GET_GRID_BY_ID: &'static str = r"
-    SELECT grid_id, revision, segment_id, position_x, position_y, heights, tiles
+    SELECT grid_id, revision, segment_id, position_x, position_y, heights, tiles, format
+      FROM grids
+     WHERE grid_id = :grid_id
+";
+
+const GET_GRID_HASH_BY_ID: &'static str = r"
+    SELECT hash
       FROM grids
      WHERE grid_id = :grid_id
 ";
@@ -109,7 +228,7 @@ const GET_GRID_REVISION_BY_ID: &'static str = r"
 ";
 
 const GET_GRID_BY_COORD: &'static str = r"
-    SELECT grid_id, revision, segment_id, position_x, position_y, heights, tiles
+    SELECT grid_id, revision, segment_id, position_x, position_y, heights, tiles, format
       FROM grids
      WHERE segment_id = :segment_id AND position_x = :position_x AND position_y = :position_y
 ";
@@ -126,6 +245,16 @@ const GET_GRID_COORD: &'static str = r"
      WHERE grid_id = :grid_id
 ";
 
+const GET_GRID_CHANGES: &'static str = r"
+    SELECT grid_changes.change_id, grids.grid_id, grids.revision, grids.segment_id, grids.position_x,
+           grids.position_y, grids.heights, grids.tiles, grids.format
+      FROM grid_changes
+      JOIN grids ON grids.grid_id = grid_changes.grid_id
+     WHERE grid_changes.change_id > :since_change_id
+     ORDER BY grid_changes.change_id
+     LIMIT :limit
+";
+
 const GET_SEGMENT_SIZES: &'static str = r"
     SELECT segment_id, COUNT(1)
       FROM grids
@@ -141,215 +270,612 @@ const MOVE_SEGMENT_GRIDS: &'static str = r"
     WHERE segment_id = :src_segment_id
 ";
 
+const COUNT_SEGMENTS_OVERLAP: &'static str = r"
+    SELECT COUNT(1)
+      FROM grids AS src
+      JOIN grids AS dst
+        ON dst.segment_id = :dst_segment_id
+       AND dst.position_x = src.position_x + :shift_x
+       AND dst.position_y = src.position_y + :shift_y
+     WHERE src.segment_id = :src_segment_id
+";
+
+const MOVE_GRID_QUERY: &'static str = r"
+   UPDATE grids
+      SET revision = revision + 1,
+          segment_id = :segment_id,
+          position_x = position_x + :shift_x,
+          position_y = position_y + :shift_y
+    WHERE grid_id = :grid_id
+";
+
+const INSERT_OBJECT_QUERY: &'static str = r"
+    INSERT OR REPLACE INTO objects (object_id, name, grid_id, offset_x, offset_y)
+    VALUES (:object_id, :name, :grid_id, :offset_x, :offset_y)
+";
+
+const DELETE_OBJECT_QUERY: &'static str = r"
+    DELETE FROM objects
+     WHERE object_id = :object_id
+";
+
+const GET_OBJECTS_BY_GRID_RECT: &'static str = r"
+    SELECT objects.object_id, objects.name, objects.grid_id, objects.offset_x, objects.offset_y,
+           grids.position_x, grids.position_y
+      FROM objects
+      JOIN grids ON grids.grid_id = objects.grid_id
+     WHERE grids.segment_id = :segment_id
+       AND grids.position_x BETWEEN :min_x AND :max_x
+       AND grids.position_y BETWEEN :min_y AND :max_y
+     ORDER BY objects.object_id
+";
+
+const SET_GRID_LAST_SEEN_QUERY: &'static str = r"
+    INSERT OR REPLACE INTO grids_last_seen (grid_id, last_seen)
+    VALUES (:grid_id, :last_seen)
+";
+
+const GET_GRIDS_OLDER_THAN: &'static str = r"
+    SELECT grid_id
+      FROM grids_last_seen
+     WHERE last_seen < :last_seen
+     ORDER BY last_seen, grid_id
+";
+
+const INSERT_ZONE_QUERY: &'static str = r"
+    INSERT OR REPLACE INTO zones (segment_id, name, points)
+    VALUES (:segment_id, :name, :points)
+";
+
+const DELETE_ZONE_QUERY: &'static str = r"
+    DELETE FROM zones
+     WHERE segment_id = :segment_id AND name = :name
+";
+
+const GET_ZONES_BY_SEGMENT_ID: &'static str = r"
+    SELECT segment_id, name, points
+      FROM zones
+     WHERE segment_id = :segment_id
+     ORDER BY name
+";
+
+const INSERT_BOOKMARK_QUERY: &'static str = r"
+    INSERT OR REPLACE INTO bookmarks (segment_id, name, x, y)
+    VALUES (:segment_id, :name, :x, :y)
+";
+
+const DELETE_BOOKMARK_QUERY: &'static str = r"
+    DELETE FROM bookmarks
+     WHERE segment_id = :segment_id AND name = :name
+";
+
+const GET_BOOKMARKS_BY_SEGMENT_ID: &'static str = r"
+    SELECT segment_id, name, x, y
+      FROM bookmarks
+     WHERE segment_id = :segment_id
+     ORDER BY name
+";
+
+const INSERT_RESOURCE_QUERY: &'static str = r"
+    INSERT INTO resources (name, version, resource_id, seen)
+    VALUES (:name, :version, :resource_id, (SELECT COALESCE(MAX(seen), 0) + 1 FROM resources))
+    ON CONFLICT (name, version) DO UPDATE SET
+        resource_id = excluded.resource_id,
+        seen = excluded.seen
+";
+
+const GET_GRIDS_RETENTION_INFO: &'static str = r"
+    SELECT grids.grid_id, grids.segment_id, COALESCE(grids_last_seen.last_seen, 0), COALESCE(grid_changes.change_id, 0)
+      FROM grids
+      LEFT JOIN grids_last_seen ON grids_last_seen.grid_id = grids.grid_id
+      LEFT JOIN grid_changes ON grid_changes.grid_id = grids.grid_id
+";
+
+const DELETE_GRID_QUERY: &'static str = r"
+    DELETE FROM grids
+     WHERE grid_id = :grid_id
+";
+
+const DELETE_GRID_CHANGE_QUERY: &'static str = r"
+    DELETE FROM grid_changes
+     WHERE grid_id = :grid_id
+";
+
+const DELETE_GRID_LAST_SEEN_QUERY: &'static str = r"
+    DELETE FROM grids_last_seen
+     WHERE grid_id = :grid_id
+";
+
+const DELETE_GRID_OBJECTS_QUERY: &'static str = r"
+    DELETE FROM objects
+     WHERE grid_id = :grid_id
+";
+
+const GET_RESOURCES: &'static str = r"
+    SELECT resource_id, version, name
+      FROM resources
+     ORDER BY seen
+";
+
+const WRITE_BEHIND_BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_BEHIND_FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const WRITE_BEHIND_MAX_ATTEMPTS: usize = 3;
+const WRITE_BEHIND_RETRY_DELAY: Duration = Duration::from_millis(100);
+const WRITE_BEHIND_MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
 pub struct SqliteMapDb {
     conn: RefCell<Connection>,
-    tiles: RefCell<BTreeMap<String, CachedTile>>,
-    grids_by_id: RefCell<BTreeMap<i64, CachedGrid>>,
-    grids_by_coord: RefCell<BTreeMap<Coordi, CachedGrid>>,
-    rng: RefCell<SmallRng>,
-    cache_ttl: Option<Uniform<Duration>>,
+    cache: MapDbCache,
+    pending_grids: RefCell<BTreeMap<i64, PendingGrid>>,
+    writer: Option<MapDbWriter>,
 }
 
 impl SqliteMapDb {
-    pub fn new(conn: Connection, cache_ttl: Duration) -> Self {
+    pub fn new(mut conn: Connection, cache_ttl: Duration) -> Self {
         conn.execute_batch(CREATE_DB_QUERY).unwrap();
-        let tiles = {
-            let mut stmt = conn.prepare(GET_TILES).unwrap();
-            stmt.query_map(NO_PARAMS, Tile::from_sqlite_row).unwrap()
-                .map(|v| v.unwrap())
-                .map(|v| {
-                    (
-                        v.name.clone(),
-                        CachedTile { cached_at: Instant::now(), value: Some(Arc::new(Mutex::new(v))) },
-                    )
-                })
-                .collect()
-        };
-        let grids_by_coord: BTreeMap<Coordi, CachedGrid> = {
-            let mut stmt = conn.prepare(GET_GRIDS).unwrap();
-            stmt.query_map(NO_PARAMS, Grid::from_sqlite_row).unwrap()
-                .map(|v| v.unwrap())
-                .map(|v| {
-                    (
-                        Coordi { segment_id: v.segment_id, position: v.position },
-                        CachedGrid { cached_at: Instant::now(), value: Some(Arc::new(Mutex::new(v))) },
-                    )
-                })
-                .collect()
-        };
-        let grids_by_id = grids_by_coord.values().cloned()
-            .map(|v| {
-                let id = v.value.as_ref().unwrap().lock().unwrap().id;
-                (id, v)
-            }).collect();
+        add_grids_hash(&conn).unwrap();
+        let migrated = migrate_grids_format(&mut conn).unwrap();
+        if migrated > 0 {
+            info!("Migrated {} grids to format {}", migrated, GRID_FORMAT_BINCODE_ZSTD);
+        }
+        let cache = MapDbCache::new(cache_ttl);
+        cache.set_tiles(get_tiles(&conn).unwrap());
         Self {
             conn: RefCell::new(conn),
-            tiles: RefCell::new(tiles),
-            grids_by_id: RefCell::new(grids_by_id),
-            grids_by_coord: RefCell::new(grids_by_coord),
-            rng: RefCell::new(SeedableRng::from_entropy()),
-            cache_ttl: if cache_ttl.is_zero() {
-                None
-            } else {
-                Some(Uniform::new(cache_ttl / 2, cache_ttl.saturating_add(cache_ttl / 2)))
-            },
+            cache,
+            pending_grids: RefCell::new(BTreeMap::new()),
+            writer: None,
         }
     }
 
-    fn get_cached_grid_by_id(&self, grid_id: i64) -> Option<Option<Arc<Mutex<Grid>>>> {
-        if let Some(grid) = self.grids_by_id.borrow_mut().get_mut(&grid_id) {
-            let mut rng = self.rng.borrow_mut();
-            if Instant::now() - grid.cached_at < self.cache_ttl.map(|v| v.sample(rng.deref_mut())).unwrap_or(Duration::ZERO) {
-                return Some(grid.value.as_ref().map(Arc::clone));
+    pub fn with_cache_capacity(self, capacity: usize) -> Self {
+        self.cache.set_capacity(capacity);
+        self
+    }
+
+    pub fn with_write_behind(mut self, conn: Connection, config: MapDbWriteBehindConfig) -> Self {
+        self.conn.borrow().busy_timeout(WRITE_BEHIND_BUSY_TIMEOUT).unwrap();
+        conn.busy_timeout(WRITE_BEHIND_BUSY_TIMEOUT).unwrap();
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(())).unwrap();
+        let queue = Arc::new(WriteQueue {
+            state: Mutex::new(WriteQueueState { writes: VecDeque::new(), flush: false, stop: false }),
+            condvar: Condvar::new(),
+        });
+        let flushed = Arc::new(AtomicU64::new(0));
+        let error = Arc::new(Mutex::new(None));
+        let batch_size = config.batch_size;
+        let handle = {
+            let queue = queue.clone();
+            let flushed = flushed.clone();
+            let error = error.clone();
+            spawn(move || write_pending_grids(conn, queue, flushed, error, config))
+        };
+        self.writer = Some(MapDbWriter {
+            queue,
+            flushed,
+            error,
+            last_flushed: Cell::new(0),
+            last_seq: Cell::new(0),
+            batch_size,
+            handle: Some(handle),
+        });
+        self
+    }
+
+    fn sync_pending_grids(&self) {
+        if let Some(writer) = self.writer.as_ref() {
+            let flushed = writer.flushed.load(Ordering::SeqCst);
+            if flushed != writer.last_flushed.get() {
+                writer.last_flushed.set(flushed);
+                self.pending_grids.borrow_mut().retain(|_, v| v.seq > flushed);
+                self.cache.clear_grids();
             }
-            if let Some(value) = grid.value.as_ref().map(Arc::clone) {
-                if let Some(revision) = get_grid_revision_by_id(self.conn.borrow().deref(), grid_id).unwrap() {
-                    if value.lock().unwrap().revision == revision {
-                        grid.cached_at = Instant::now();
-                        return Some(Some(value));
+        }
+    }
+
+    fn check_no_pending_grids(&self) -> Result<(), String> {
+        self.sync_pending_grids();
+        let pending = self.pending_grids.borrow().len();
+        if pending > 0 {
+            return Err(format!("There are {} pending grid writes", pending));
+        }
+        Ok(())
+    }
+
+    fn get_pending_grid(&self, coord: &Coordi) -> Option<Arc<Mutex<Grid>>> {
+        self.pending_grids.borrow().values()
+            .find(|v| {
+                let grid = v.value.lock().unwrap();
+                grid.segment_id == coord.segment_id && grid.position == coord.position
+            })
+            .map(|v| Arc::clone(&v.value))
+    }
+
+    fn add_pending_grid(&self, seq: u64, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>,
+                        neighbours: &Vec<GridNeighbour>) {
+        if self.get_grid_by_id(grid_id).is_some() {
+            return self.update_pending_grid(seq, grid_id, heights, tiles);
+        }
+        let mut segments: Vec<GridSegment> = neighbours.iter()
+            .filter_map(|neighbour| {
+                self.get_grid_by_id(neighbour.id).map(|grid| {
+                    let grid = grid.lock().unwrap();
+                    GridSegment { segment_id: grid.segment_id, offset: neighbour.offset, position: grid.position }
+                })
+            })
+            .collect();
+        segments.sort_by_key(|v| v.segment_id);
+        segments.dedup_by_key(|v| v.segment_id);
+        if segments.len() > 1 {
+            sort_segments_by_size(&mut segments, &self.get_pending_segment_sizes());
+        }
+        let (segment_id, position) = match segments.first() {
+            Some(target) => {
+                for other in segments[1..].iter() {
+                    let shift = target.position - target.offset + other.offset - other.position;
+                    for other_grid_id in self.get_grid_ids_by_segment_id(other.segment_id) {
+                        if let Some(grid) = self.get_grid_by_id(other_grid_id) {
+                            let grid = {
+                                let grid = grid.lock().unwrap();
+                                Grid {
+                                    revision: grid.revision + 1,
+                                    segment_id: target.segment_id,
+                                    position: grid.position + shift,
+                                    ..grid.clone()
+                                }
+                            };
+                            self.pending_grids.borrow_mut().insert(other_grid_id, PendingGrid { seq, value: Arc::new(Mutex::new(grid)) });
+                        }
                     }
-                } else {
-                    grid.cached_at = Instant::now();
-                    return Some(None);
                 }
+                (target.segment_id, target.position - target.offset)
             }
+            None => (grid_id, Vec2i::zero()),
+        };
+        self.pending_grids.borrow_mut().insert(grid_id, PendingGrid {
+            seq,
+            value: Arc::new(Mutex::new(Grid {
+                id: grid_id,
+                revision: 1,
+                segment_id,
+                position,
+                heights: heights.clone(),
+                tiles: tiles.clone(),
+            })),
+        });
+    }
+
+    fn update_pending_grid(&self, seq: u64, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>) {
+        if let Some(grid) = self.get_grid_by_id(grid_id) {
+            let grid = {
+                let grid = grid.lock().unwrap();
+                Grid { revision: grid.revision + 1, heights: heights.clone(), tiles: tiles.clone(), ..*grid }
+            };
+            self.pending_grids.borrow_mut().insert(grid_id, PendingGrid { seq, value: Arc::new(Mutex::new(grid)) });
         }
-        None
     }
 
-    fn get_cached_grid(&self, coord: &Coordi) -> Option<Option<Arc<Mutex<Grid>>>> {
-        if let Some(grid) = self.grids_by_coord.borrow_mut().get_mut(&coord) {
-            let mut rng = self.rng.borrow_mut();
-            if Instant::now() - grid.cached_at < self.cache_ttl.map(|v| v.sample(rng.deref_mut())).unwrap_or(Duration::ZERO) {
-                return Some(grid.value.as_ref().map(Arc::clone));
-            }
-            if let Some(value) = grid.value.as_ref().map(Arc::clone) {
-                if let Some(revision) = get_grid_revision_by_coord(self.conn.borrow().deref(), coord.segment_id, coord.position).unwrap() {
-                    if value.lock().unwrap().revision == revision {
-                        grid.cached_at = Instant::now();
-                        return Some(Some(value));
-                    }
-                } else {
-                    grid.cached_at = Instant::now();
-                    return Some(None);
-                }
+    fn set_pending_grid_revision(&self, seq: u64, grid_id: i64, revision: i64) {
+        if let Some(grid) = self.get_grid_by_id(grid_id) {
+            let grid = {
+                let grid = grid.lock().unwrap();
+                Grid { revision: grid.revision.max(revision), ..grid.clone() }
+            };
+            self.pending_grids.borrow_mut().insert(grid_id, PendingGrid { seq, value: Arc::new(Mutex::new(grid)) });
+        }
+    }
+
+    fn get_pending_segment_sizes(&self) -> HashMap<i64, i64> {
+        let conn = self.conn.borrow();
+        let mut sizes = get_segment_sizes(conn.deref()).unwrap();
+        for pending_grid in self.pending_grids.borrow().values() {
+            let grid = pending_grid.value.lock().unwrap();
+            if let Some(coord) = get_grid_coord(conn.deref(), grid.id).unwrap() {
+                *sizes.entry(coord.segment_id).or_insert(0) -= 1;
             }
+            *sizes.entry(grid.segment_id).or_insert(0) += 1;
         }
-        None
+        sizes
     }
 
-    fn cache_grid(&self, grid: Arc<Mutex<Grid>>) {
-        let value = grid.clone();
-        let locked_grid = grid.lock().unwrap();
-        let coord = Coordi { segment_id: locked_grid.segment_id, position: locked_grid.position };
-        let cached_grid = CachedGrid {
-            cached_at: Instant::now(),
-            value: Some(value),
-        };
-        self.grids_by_coord.borrow_mut().insert(coord, cached_grid.clone());
-        self.grids_by_id.borrow_mut().insert(locked_grid.id, cached_grid);
+    fn get_grid_hash(&self, grid_id: i64) -> Option<i64> {
+        if let Some(grid) = self.pending_grids.borrow().get(&grid_id) {
+            let grid = grid.value.lock().unwrap();
+            return Some(get_grid_hash(&grid.heights, &grid.tiles));
+        }
+        get_grid_hash_by_id(self.conn.borrow().deref(), grid_id).unwrap()
     }
 }
 
 impl MapDb for SqliteMapDb {
     fn get_tiles(&self) -> Vec<Tile> {
-        let conn = self.conn.borrow();
-        let mut stmt = conn.prepare(GET_TILES).unwrap();
-        stmt.query_map(NO_PARAMS, |row| { Tile::from_sqlite_row(row) }).unwrap()
-            .map(|v| v.unwrap())
-            .collect()
+        get_tiles(self.conn.borrow().deref()).unwrap()
     }
 
     fn get_tile_id_by_name(&self, name: &String) -> Option<i32> {
-        if let Some(tile) = self.tiles.borrow().get(name) {
-            let mut rng = self.rng.borrow_mut();
-            if Instant::now() - tile.cached_at < self.cache_ttl.map(|v| v.sample(rng.deref_mut())).unwrap_or(Duration::ZERO) {
-                return tile.value.as_ref().map(|v| v.lock().unwrap().id);
-            }
-        }
-        if let Some(tile) = get_tile_by_name(self.conn.borrow().deref(), name).unwrap() {
-            self.tiles.borrow_mut().insert(name.clone(), CachedTile {
-                cached_at: Instant::now(),
-                value: Some(Arc::new(Mutex::new(tile))),
-            });
-            return self.tiles.borrow().get(name)
-                .and_then(|v| v.value.as_ref().map(|v| v.lock().unwrap().id));
-        }
-        self.tiles.borrow_mut().insert(name.clone(), CachedTile {
-            cached_at: Instant::now(),
-            value: None,
-        });
-        None
+        self.cache.get_tile_id_by_name(name, || get_tile_by_name(self.conn.borrow().deref(), name).unwrap())
     }
 
     fn set_tile(&self, tile: &Tile) {
         let updated = set_tile(self.conn.borrow().deref(), tile).unwrap();
         if updated > 0 {
-            self.tiles.borrow_mut().clear();
+            self.cache.clear_tiles();
         }
     }
 
     fn get_grids(&self) -> Vec<Grid> {
-        let conn = self.conn.borrow();
-        let mut stmt = conn.prepare(GET_GRIDS).unwrap();
-        stmt.query_map(NO_PARAMS, |row| { Grid::from_sqlite_row(row) }).unwrap()
-            .map(|v| v.unwrap())
-            .collect()
+        let mut grids: Vec<Grid> = {
+            let conn = self.conn.borrow();
+            let mut stmt = conn.prepare(GET_GRIDS).unwrap();
+            stmt.query_map(NO_PARAMS, |row| { Grid::from_sqlite_row(row) }).unwrap()
+                .map(|v| v.unwrap())
+                .collect()
+        };
+        self.sync_pending_grids();
+        let pending_grids = self.pending_grids.borrow();
+        if !pending_grids.is_empty() {
+            grids.retain(|v| !pending_grids.contains_key(&v.id));
+            grids.extend(pending_grids.values().map(|v| v.value.lock().unwrap().clone()));
+            grids.sort_by_key(|v| v.id);
+        }
+        grids
     }
 
     fn get_grid_ids_by_segment_id(&self, segment_id: i64) -> Vec<i64> {
-        let conn = self.conn.borrow();
-        let mut stmt = conn.prepare(GET_GRID_IDS_BY_SEGMENT_ID).unwrap();
-        stmt.query_map_named(
-            named_params! { ":segment_id": segment_id },
-            |row| { row.get::<usize, i64>(0) },
-        ).unwrap()
-            .map(|v| v.unwrap())
-            .collect()
+        let mut grid_ids: Vec<i64> = {
+            let conn = self.conn.borrow();
+            let mut stmt = conn.prepare(GET_GRID_IDS_BY_SEGMENT_ID).unwrap();
+            stmt.query_map_named(
+                named_params! { ":segment_id": segment_id },
+                |row| { row.get::<usize, i64>(0) },
+            ).unwrap()
+                .map(|v| v.unwrap())
+                .collect()
+        };
+        self.sync_pending_grids();
+        let pending_grids = self.pending_grids.borrow();
+        if !pending_grids.is_empty() {
+            grid_ids.retain(|v| !pending_grids.contains_key(v));
+            grid_ids.extend(pending_grids.values()
+                .map(|v| v.value.lock().unwrap())
+                .filter(|v| v.segment_id == segment_id)
+                .map(|v| v.id));
+            grid_ids.sort();
+        }
+        grid_ids
     }
 
     fn get_grid_by_id(&self, grid_id: i64) -> Option<Arc<Mutex<Grid>>> {
-        if let Some(grid) = self.get_cached_grid_by_id(grid_id) {
-            return grid;
+        self.sync_pending_grids();
+        if let Some(grid) = self.pending_grids.borrow().get(&grid_id) {
+            return Some(Arc::clone(&grid.value));
         }
-        if let Some(grid) = get_grid_by_id(self.conn.borrow().deref(), grid_id).unwrap() {
-            let grid_rc = Arc::new(Mutex::new(grid));
-            self.cache_grid(Arc::clone(&grid_rc));
-            return Some(grid_rc);
-        }
-        self.grids_by_id.borrow_mut().insert(grid_id, CachedGrid {
-            cached_at: Instant::now(),
-            value: None,
-        });
-        None
+        self.cache.get_grid_by_id(
+            grid_id,
+            || get_grid_revision_by_id(self.conn.borrow().deref(), grid_id).unwrap(),
+            || get_grid_by_id(self.conn.borrow().deref(), grid_id).unwrap(),
+        )
     }
 
     fn get_grid(&self, segment_id: i64, position: Vec2i) -> Option<Arc<Mutex<Grid>>> {
         let coord = Coordi { segment_id, position };
-        if let Some(grid) = self.get_cached_grid(&coord) {
-            return grid;
+        self.sync_pending_grids();
+        if let Some(grid) = self.get_pending_grid(&coord) {
+            return Some(grid);
         }
-        if let Some(grid) = get_grid_by_coord(self.conn.borrow().deref(), segment_id, position).unwrap() {
-            let grid_rc = Arc::new(Mutex::new(grid));
-            self.cache_grid(Arc::clone(&grid_rc));
-            return Some(grid_rc);
-        }
-        self.grids_by_coord.borrow_mut().insert(coord, CachedGrid {
-            cached_at: Instant::now(),
-            value: None,
-        });
-        None
+        self.cache.get_grid(
+            segment_id,
+            position,
+            || get_grid_revision_by_coord(self.conn.borrow().deref(), segment_id, position).unwrap(),
+            || get_grid_by_coord(self.conn.borrow().deref(), segment_id, position).unwrap(),
+        )
     }
 
     fn add_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>,
                 neighbours: &Vec<GridNeighbour>) {
+        if let Some(writer) = self.writer.as_ref() {
+            let seq = writer.next_seq();
+            self.add_pending_grid(seq, grid_id, heights, tiles, neighbours);
+            writer.push(PendingWrite {
+                seq,
+                value: GridWrite::Add {
+                    grid_id,
+                    heights: heights.clone(),
+                    tiles: tiles.clone(),
+                    neighbours: neighbours.clone(),
+                },
+            });
+            return;
+        }
         add_grid(self.conn.borrow_mut().deref_mut(), grid_id, heights, tiles, neighbours).unwrap();
-        self.grids_by_coord.borrow_mut().clear();
+        self.cache.clear_grids_by_coord();
+    }
+
+    fn update_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>) -> bool {
+        if let Some(writer) = self.writer.as_ref() {
+            if self.get_grid_hash(grid_id) == Some(get_grid_hash(heights, tiles)) {
+                return false;
+            }
+            let seq = writer.next_seq();
+            self.update_pending_grid(seq, grid_id, heights, tiles);
+            writer.push(PendingWrite {
+                seq,
+                value: GridWrite::Update { grid_id, heights: heights.clone(), tiles: tiles.clone() },
+            });
+            return true;
+        }
+        if update_grid(self.conn.borrow().deref(), grid_id, heights, tiles).unwrap() == 0 {
+            return false;
+        }
+        self.cache.clear_grids_by_coord();
+        true
+    }
+
+    fn set_grid_revision(&self, grid_id: i64, revision: i64) {
+        if let Some(writer) = self.writer.as_ref() {
+            let seq = writer.next_seq();
+            self.set_pending_grid_revision(seq, grid_id, revision);
+            writer.push(PendingWrite { seq, value: GridWrite::Revision { grid_id, revision } });
+            return;
+        }
+        set_grid_revision(self.conn.borrow().deref(), grid_id, revision).unwrap();
+    }
+
+    fn get_grid_changes(&self, since_change_id: i64, limit: usize) -> Vec<(i64, Grid)> {
+        get_grid_changes(self.conn.borrow().deref(), since_change_id, limit).unwrap()
+    }
+
+    fn add_object(&self, object: &MapObject) {
+        self.conn.borrow().execute_named(
+            INSERT_OBJECT_QUERY,
+            named_params! {
+                ":object_id": object.id,
+                ":name": object.name,
+                ":grid_id": object.grid_id,
+                ":offset_x": object.offset.x(),
+                ":offset_y": object.offset.y(),
+            },
+        ).unwrap();
+    }
+
+    fn remove_object(&self, object_id: i64) {
+        self.conn.borrow().execute_named(DELETE_OBJECT_QUERY, named_params! { ":object_id": object_id }).unwrap();
+    }
+
+    fn get_objects_in_rect(&self, segment_id: i64, min: Vec2f, max: Vec2f) -> Vec<(Vec2f, MapObject)> {
+        get_objects_in_rect(self.conn.borrow().deref(), segment_id, min, max).unwrap()
+    }
+
+    fn set_grid_last_seen(&self, grid_id: i64, last_seen: i64) {
+        if let Some(writer) = self.writer.as_ref() {
+            writer.push(PendingWrite {
+                seq: writer.next_seq(),
+                value: GridWrite::LastSeen { grid_id, last_seen },
+            });
+            return;
+        }
+        set_grid_last_seen(self.conn.borrow().deref(), grid_id, last_seen).unwrap();
+    }
+
+    fn get_grids_older_than(&self, last_seen: i64) -> Vec<i64> {
+        if let Err(e) = self.flush() {
+            warn!("Failed to flush map db before getting grids older than {}: {}", last_seen, e);
+        }
+        let conn = self.conn.borrow();
+        let mut stmt = conn.prepare(GET_GRIDS_OLDER_THAN).unwrap();
+        let grid_ids = stmt.query_map_named(
+            named_params! { ":last_seen": last_seen },
+            |row| { row.get::<usize, i64>(0) },
+        ).unwrap()
+            .map(|v| v.unwrap())
+            .collect();
+        grid_ids
+    }
+
+    fn merge_segments(&self, src_segment_id: i64, dst_segment_id: i64, shift: Vec2i) -> Result<usize, String> {
+        self.check_no_pending_grids()?;
+        let moved = merge_segments(self.conn.borrow_mut().deref_mut(), src_segment_id, dst_segment_id, shift)?;
+        self.cache.clear_grids();
+        Ok(moved)
+    }
+
+    fn split_segment(&self, segment_id: i64, grid_ids: &Vec<i64>) -> Result<i64, String> {
+        self.check_no_pending_grids()?;
+        let new_segment_id = split_segment(self.conn.borrow_mut().deref_mut(), segment_id, grid_ids)?;
+        self.cache.clear_grids();
+        Ok(new_segment_id)
+    }
+
+    fn set_zone(&self, zone: &Zone) {
+        self.conn.borrow().execute_named(
+            INSERT_ZONE_QUERY,
+            named_params! {
+                ":segment_id": zone.segment_id,
+                ":name": zone.name,
+                ":points": serde_json::to_string(&zone.points).unwrap(),
+            },
+        ).unwrap();
+    }
+
+    fn remove_zone(&self, segment_id: i64, name: &String) -> bool {
+        self.conn.borrow().execute_named(
+            DELETE_ZONE_QUERY,
+            named_params! { ":segment_id": segment_id, ":name": name },
+        ).unwrap() > 0
     }
 
-    fn update_grid(&self, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>) {
-        update_grid(self.conn.borrow().deref(), grid_id, heights, tiles).unwrap();
-        self.grids_by_coord.borrow_mut().clear();
+    fn get_zones(&self, segment_id: i64) -> Vec<Zone> {
+        get_zones(self.conn.borrow().deref(), segment_id).unwrap()
+    }
+
+    fn set_bookmark(&self, bookmark: &Bookmark) {
+        self.conn.borrow().execute_named(
+            INSERT_BOOKMARK_QUERY,
+            named_params! {
+                ":segment_id": bookmark.segment_id,
+                ":name": bookmark.name,
+                ":x": bookmark.position.x(),
+                ":y": bookmark.position.y(),
+            },
+        ).unwrap();
+    }
+
+    fn remove_bookmark(&self, segment_id: i64, name: &String) -> bool {
+        self.conn.borrow().execute_named(
+            DELETE_BOOKMARK_QUERY,
+            named_params! { ":segment_id": segment_id, ":name": name },
+        ).unwrap() > 0
+    }
+
+    fn get_bookmarks(&self, segment_id: i64) -> Vec<Bookmark> {
+        get_bookmarks(self.conn.borrow().deref(), segment_id).unwrap()
+    }
+
+    fn get_resources(&self) -> Vec<Resource> {
+        let conn = self.conn.borrow();
+        let mut stmt = conn.prepare(GET_RESOURCES).unwrap();
+        stmt.query_map(NO_PARAMS, |row| { Resource::from_sqlite_row(row) }).unwrap()
+            .map(|v| v.unwrap())
+            .collect()
+    }
+
+    fn set_resource(&self, resource: &Resource) {
+        self.conn.borrow().execute_named(
+            INSERT_RESOURCE_QUERY,
+            named_params! {
+                ":name": resource.name,
+                ":version": resource.version,
+                ":resource_id": resource.id,
+            },
+        ).unwrap();
+    }
+
+    fn prune(&self, config: &MapRetentionConfig, now: i64) -> Result<MapPruneStats, String> {
+        self.flush()?;
+        self.check_no_pending_grids()?;
+        let stats = prune_grids(self.conn.borrow_mut().deref_mut(), config, now)?;
+        self.cache.clear_grids();
+        if config.vacuum && stats.grids > 0 {
+            self.conn.borrow().execute_batch("VACUUM").map_err(|e| format!("Failed to vacuum map db: {}", e))?;
+        }
+        Ok(stats)
+    }
+
+    fn get_cache_stats(&self) -> MapDbCacheStats {
+        self.cache.get_stats()
+    }
+
+    fn set_pinned_grids(&self, owner: u64, coords: BTreeSet<(i64, Vec2i)>) {
+        self.cache.set_pinned_grids(owner, coords);
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        let result = match self.writer.as_ref() {
+            Some(writer) => writer.wait_flushed(),
+            None => Ok(()),
+        };
+        self.sync_pending_grids();
+        result
     }
 }
 
@@ -365,6 +891,16 @@ fn set_tile(conn: &Connection, tile: &Tile) -> rusqlite::Result<usize> {
     )
 }
 
+fn get_tiles(conn: &Connection) -> rusqlite::Result<Vec<Tile>> {
+    let mut stmt = conn.prepare(GET_TILES)?;
+    let iter = stmt.query_map(NO_PARAMS, Tile::from_sqlite_row)?;
+    let mut result = Vec::new();
+    for value in iter {
+        result.push(value?);
+    }
+    Ok(result)
+}
+
 fn get_tile_by_name(conn: &Connection, name: &String) -> rusqlite::Result<Option<Tile>> {
     conn.query_row_named(
         GET_TILE_BY_NAME_QUERY,
@@ -381,6 +917,14 @@ fn get_grid_by_id(conn: &Connection, grid_id: i64) -> rusqlite::Result<Option<Gr
     ).optional()
 }
 
+fn get_grid_hash_by_id(conn: &Connection, grid_id: i64) -> rusqlite::Result<Option<i64>> {
+    conn.query_row_named(
+        GET_GRID_HASH_BY_ID,
+        named_params! { ":grid_id": grid_id },
+        |v| v.get::<usize, i64>(0),
+    ).optional()
+}
+
 fn get_grid_revision_by_id(conn: &Connection, grid_id: i64) -> rusqlite::Result<Option<i64>> {
     conn.query_row_named(
         GET_GRID_REVISION_BY_ID,
@@ -418,53 +962,141 @@ fn get_grid_revision_by_coord(conn: &Connection, segment_id: i64,
 fn add_grid(conn: &mut Connection, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>,
             neighbours: &Vec<GridNeighbour>) -> rusqlite::Result<()> {
     let tx: Transaction = conn.transaction()?;
-    if let Some(_) = get_grid_coord(tx.deref(), grid_id).unwrap() {
-        update_grid(tx.deref(), grid_id, heights, tiles)?;
-        return tx.commit();
+    insert_grid(tx.deref(), grid_id, heights, tiles, neighbours)?;
+    tx.commit()
+}
+
+fn write_pending_grids(mut conn: Connection, queue: Arc<WriteQueue>, flushed: Arc<AtomicU64>,
+                       error: Arc<Mutex<Option<String>>>, config: MapDbWriteBehindConfig) {
+    let flush_interval = Duration::from_secs_f64(config.flush_interval);
+    loop {
+        let (writes, stop) = {
+            let deadline = Instant::now() + flush_interval;
+            let mut state = queue.state.lock().unwrap();
+            while !state.stop && !state.flush && state.writes.len() < config.batch_size {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                state = queue.condvar.wait_timeout(state, deadline - now).unwrap().0;
+            }
+            let size = state.writes.len().min(config.batch_size);
+            let writes = state.writes.drain(0..size).collect::<Vec<_>>();
+            if state.writes.is_empty() {
+                state.flush = false;
+            }
+            (writes, state.stop)
+        };
+        if let Some(last) = writes.last() {
+            let mut attempt = 1;
+            while let Err(e) = write_grids(&mut conn, &writes) {
+                if attempt >= WRITE_BEHIND_MAX_ATTEMPTS {
+                    let message = format!("Failed to write {} pending grids after {} attempts: {}", writes.len(), attempt, e);
+                    error!("{}", message);
+                    *error.lock().unwrap() = Some(message);
+                } else {
+                    warn!("Failed to write {} pending grids at attempt {}: {}", writes.len(), attempt, e);
+                }
+                let state = queue.state.lock().unwrap();
+                if state.stop && attempt >= WRITE_BEHIND_MAX_ATTEMPTS {
+                    error!("Drop {} pending grids on stop", writes.len());
+                    return;
+                }
+                let delay = WRITE_BEHIND_RETRY_DELAY.saturating_mul(1 << (attempt - 1).min(6) as u32)
+                    .min(WRITE_BEHIND_MAX_RETRY_DELAY);
+                drop(queue.condvar.wait_timeout(state, delay).unwrap());
+                attempt += 1;
+            }
+            if attempt > 1 {
+                *error.lock().unwrap() = None;
+            }
+            flushed.store(last.seq, Ordering::SeqCst);
+        } else if stop {
+            break;
+        }
+    }
+}
+
+fn write_grids(conn: &mut Connection, writes: &Vec<PendingWrite>) -> rusqlite::Result<()> {
+    let tx: Transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    for write in writes.iter() {
+        match &write.value {
+            GridWrite::Add { grid_id, heights, tiles, neighbours } => {
+                insert_grid(tx.deref(), *grid_id, heights, tiles, neighbours)?;
+            }
+            GridWrite::Update { grid_id, heights, tiles } => {
+                update_grid(tx.deref(), *grid_id, heights, tiles)?;
+            }
+            GridWrite::Revision { grid_id, revision } => {
+                set_grid_revision(tx.deref(), *grid_id, *revision)?;
+            }
+            GridWrite::LastSeen { grid_id, last_seen } => {
+                set_grid_last_seen(tx.deref(), *grid_id, *last_seen)?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+fn set_grid_last_seen(conn: &Connection, grid_id: i64, last_seen: i64) -> rusqlite::Result<usize> {
+    conn.execute_named(
+        SET_GRID_LAST_SEEN_QUERY,
+        named_params! { ":grid_id": grid_id, ":last_seen": last_seen },
+    )
+}
+
+fn insert_grid(conn: &Connection, grid_id: i64, heights: &Vec<f32>, tiles: &Vec<i32>,
+               neighbours: &Vec<GridNeighbour>) -> rusqlite::Result<()> {
+    if let Some(_) = get_grid_coord(conn, grid_id)? {
+        update_grid(conn, grid_id, heights, tiles)?;
+        return Ok(());
     }
-    let mut segments = get_segments(tx.deref(), neighbours)?;
+    let mut segments = get_segments(conn, neighbours)?;
     if !segments.is_empty() {
         segments.sort_by_key(|v| v.segment_id);
         segments.dedup_by_key(|v| v.segment_id);
+        if segments.len() > 1 {
+            sort_segments_by_size(&mut segments, &get_segment_sizes(conn)?);
+        }
         let GridSegment {
             segment_id: target_segment,
             offset: target_offset,
             position: target_position,
         } = segments[0];
         if segments.len() > 1 {
-            let segments_sizes = get_segment_sizes(tx.deref())?;
-            segments.sort_by_key(|v| {
-                (-segments_sizes[&v.segment_id], v.segment_id)
-            });
             for i in 1..segments.len() {
                 let GridSegment { segment_id, offset, position } = segments[i];
                 let shift = target_position - target_offset + offset - position;
-                move_segment_grids(tx.deref(), segment_id, target_segment, shift)?;
+                move_segment_grids(conn, segment_id, target_segment, shift)?;
             }
         }
         let position = target_position - target_offset;
-        tx.execute_named(
+        conn.execute_named(
             INSERT_EXISTING_SEGMENT_GRID_QUERY,
             named_params! {
                 ":grid_id": grid_id,
                 ":segment_id": target_segment,
                 ":position_x": position.x(),
                 ":position_y": position.y(),
-                ":heights": serde_json::to_vec(heights).unwrap(),
-                ":tiles": serde_json::to_vec(tiles).unwrap(),
+                ":heights": encode_grid_values(heights),
+                ":tiles": encode_grid_values(tiles),
+                ":format": GRID_FORMAT_BINCODE_ZSTD,
+                ":hash": get_grid_hash(heights, tiles),
             },
         )?;
     } else {
-        tx.execute_named(
+        conn.execute_named(
             INSERT_NEW_SEGMENT_GRID_QUERY,
             named_params! {
                 ":grid_id": grid_id,
-                ":heights": serde_json::to_vec(heights).unwrap(),
-                ":tiles": serde_json::to_vec(tiles).unwrap(),
+                ":heights": encode_grid_values(heights),
+                ":tiles": encode_grid_values(tiles),
+                ":format": GRID_FORMAT_BINCODE_ZSTD,
+                ":hash": get_grid_hash(heights, tiles),
             },
         )?;
     }
-    tx.commit()
+    Ok(())
 }
 
 fn update_grid(conn: &Connection, grid_id: i64, heights: &Vec<f32>,
@@ -473,12 +1105,144 @@ fn update_grid(conn: &Connection, grid_id: i64, heights: &Vec<f32>,
         UPDATE_GRID_QUERY,
         named_params! {
                 ":grid_id": grid_id,
-                ":heights": serde_json::to_vec(heights).unwrap(),
-                ":tiles": serde_json::to_vec(tiles).unwrap(),
+                ":heights": encode_grid_values(heights),
+                ":tiles": encode_grid_values(tiles),
+                ":format": GRID_FORMAT_BINCODE_ZSTD,
+                ":hash": get_grid_hash(heights, tiles),
             },
     )
 }
 
+fn set_grid_revision(conn: &Connection, grid_id: i64, revision: i64) -> rusqlite::Result<usize> {
+    conn.execute_named(SET_GRID_REVISION_QUERY, named_params! { ":grid_id": grid_id, ":revision": revision })
+}
+
+fn add_grids_hash(conn: &Connection) -> rusqlite::Result<()> {
+    if conn.query_row(HAS_GRIDS_HASH_QUERY, NO_PARAMS, |row| row.get::<usize, i64>(0))? == 0 {
+        conn.execute(ADD_GRIDS_HASH_QUERY, NO_PARAMS)?;
+    }
+    Ok(())
+}
+
+fn migrate_grids_format(conn: &mut Connection) -> rusqlite::Result<usize> {
+    let tx: Transaction = conn.transaction()?;
+    if tx.query_row(HAS_GRIDS_FORMAT_QUERY, NO_PARAMS, |row| row.get::<usize, i64>(0))? == 0 {
+        tx.execute(ADD_GRIDS_FORMAT_QUERY, NO_PARAMS)?;
+    }
+    let grids = {
+        let mut stmt = tx.prepare(GET_GRIDS_BY_FORMAT)?;
+        let iter = stmt.query_map_named(
+            named_params! { ":format": GRID_FORMAT_JSON },
+            |row| Ok((row.get::<usize, i64>(0)?, row.get::<usize, Vec<u8>>(1)?, row.get::<usize, Vec<u8>>(2)?)),
+        )?;
+        let mut result = Vec::new();
+        for value in iter {
+            result.push(value?);
+        }
+        result
+    };
+    for (grid_id, heights, tiles) in grids.iter() {
+        tx.execute_named(
+            SET_GRID_DATA_QUERY,
+            named_params! {
+                ":grid_id": grid_id,
+                ":heights": encode_grid_values(&decode_grid_values::<f32>(GRID_FORMAT_JSON, heights)),
+                ":tiles": encode_grid_values(&decode_grid_values::<i32>(GRID_FORMAT_JSON, tiles)),
+                ":format": GRID_FORMAT_BINCODE_ZSTD,
+            },
+        )?;
+    }
+    tx.commit()?;
+    Ok(grids.len())
+}
+
+fn get_grid_changes(conn: &Connection, since_change_id: i64, limit: usize) -> rusqlite::Result<Vec<(i64, Grid)>> {
+    let mut stmt = conn.prepare(GET_GRID_CHANGES)?;
+    let iter = stmt.query_map_named(
+        named_params! {
+            ":since_change_id": since_change_id,
+            ":limit": limit as i64,
+        },
+        |row| {
+            Ok((
+                row.get::<usize, i64>(0)?,
+                Grid {
+                    id: row.get(1)?,
+                    revision: row.get(2)?,
+                    segment_id: row.get(3)?,
+                    position: Vec2i::new(row.get(4)?, row.get(5)?),
+                    heights: decode_grid_values(row.get(8)?, &(row.get::<usize, Vec<u8>>(6)?)),
+                    tiles: decode_grid_values(row.get(8)?, &(row.get::<usize, Vec<u8>>(7)?)),
+                },
+            ))
+        },
+    )?;
+    let mut result = Vec::new();
+    for value in iter {
+        result.push(value?);
+    }
+    Ok(result)
+}
+
+fn get_objects_in_rect(conn: &Connection, segment_id: i64, min: Vec2f, max: Vec2f) -> rusqlite::Result<Vec<(Vec2f, MapObject)>> {
+    let min_grid_pos = pos_to_grid_pos(min);
+    let max_grid_pos = pos_to_grid_pos(max);
+    let mut stmt = conn.prepare(GET_OBJECTS_BY_GRID_RECT)?;
+    let iter = stmt.query_map_named(
+        named_params! {
+            ":segment_id": segment_id,
+            ":min_x": min_grid_pos.x(),
+            ":max_x": max_grid_pos.x(),
+            ":min_y": min_grid_pos.y(),
+            ":max_y": max_grid_pos.y(),
+        },
+        |row| {
+            let object = MapObject {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                grid_id: row.get(2)?,
+                offset: Vec2f::new(row.get(3)?, row.get(4)?),
+            };
+            let position = grid_pos_to_pos(Vec2i::new(row.get(5)?, row.get(6)?)) + object.offset;
+            Ok((position, object))
+        },
+    )?;
+    let mut result = Vec::new();
+    for value in iter {
+        let (position, object) = value?;
+        if min.x() <= position.x() && position.x() <= max.x() && min.y() <= position.y() && position.y() <= max.y() {
+            result.push((position, object));
+        }
+    }
+    Ok(result)
+}
+
+fn get_zones(conn: &Connection, segment_id: i64) -> rusqlite::Result<Vec<Zone>> {
+    let mut stmt = conn.prepare(GET_ZONES_BY_SEGMENT_ID)?;
+    let iter = stmt.query_map_named(
+        named_params! { ":segment_id": segment_id },
+        |row| Ok(Zone {
+            segment_id: row.get(0)?,
+            name: row.get(1)?,
+            points: serde_json::from_str(&row.get::<usize, String>(2)?).unwrap(),
+        }),
+    )?;
+    iter.collect()
+}
+
+fn get_bookmarks(conn: &Connection, segment_id: i64) -> rusqlite::Result<Vec<Bookmark>> {
+    let mut stmt = conn.prepare(GET_BOOKMARKS_BY_SEGMENT_ID)?;
+    let iter = stmt.query_map_named(
+        named_params! { ":segment_id": segment_id },
+        |row| Ok(Bookmark {
+            segment_id: row.get(0)?,
+            name: row.get(1)?,
+            position: Vec2f::new(row.get(2)?, row.get(3)?),
+        }),
+    )?;
+    iter.collect()
+}
+
 fn get_segments(conn: &Connection, neighbours: &Vec<GridNeighbour>) -> rusqlite::Result<Vec<GridSegment>> {
     let mut result = Vec::new();
     for neighbour in neighbours.iter() {
@@ -522,6 +1286,11 @@ fn get_segment_sizes(conn: &Connection) -> rusqlite::Result<HashMap<i64, i64>> {
     Ok(result)
 }
 
+// The largest segment is the merge target so the least grids are moved
+fn sort_segments_by_size(segments: &mut Vec<GridSegment>, sizes: &HashMap<i64, i64>) {
+    segments.sort_by_key(|v| (-sizes.get(&v.segment_id).copied().unwrap_or(0), v.segment_id));
+}
+
 fn move_segment_grids(conn: &Connection, src_segment_id: i64, dst_segment_id: i64,
                       shift: Vec2i) -> rusqlite::Result<usize> {
     conn.execute_named(
@@ -535,10 +1304,103 @@ fn move_segment_grids(conn: &Connection, src_segment_id: i64, dst_segment_id: i6
     )
 }
 
-#[derive(Debug)]
-struct CachedTile {
-    cached_at: Instant,
-    value: Option<Arc<Mutex<Tile>>>,
+fn merge_segments(conn: &mut Connection, src_segment_id: i64, dst_segment_id: i64,
+                  shift: Vec2i) -> Result<usize, String> {
+    if src_segment_id == dst_segment_id {
+        return Err(format!("Segment {} can't be merged into itself", src_segment_id));
+    }
+    let tx: Transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+    let segment_sizes = get_segment_sizes(tx.deref()).map_err(|e| e.to_string())?;
+    for segment_id in [src_segment_id, dst_segment_id].iter() {
+        if !segment_sizes.contains_key(segment_id) {
+            return Err(format!("Segment {} is not found", segment_id));
+        }
+    }
+    let overlap = tx.query_row_named(
+        COUNT_SEGMENTS_OVERLAP,
+        named_params! {
+            ":src_segment_id": src_segment_id,
+            ":dst_segment_id": dst_segment_id,
+            ":shift_x": shift.x(),
+            ":shift_y": shift.y(),
+        },
+        |row| row.get::<usize, i64>(0),
+    ).map_err(|e| e.to_string())?;
+    if overlap > 0 {
+        return Err(format!("{} grids of segment {} overlap segment {} with shift {:?}",
+                           overlap, src_segment_id, dst_segment_id, shift));
+    }
+    let moved = move_segment_grids(tx.deref(), src_segment_id, dst_segment_id, shift).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(moved)
+}
+
+fn split_segment(conn: &mut Connection, segment_id: i64, grid_ids: &Vec<i64>) -> Result<i64, String> {
+    let mut grid_ids = grid_ids.clone();
+    grid_ids.sort();
+    grid_ids.dedup();
+    let new_segment_id = *grid_ids.first().ok_or_else(|| String::from("No grids to split"))?;
+    let tx: Transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+    let segment_sizes = get_segment_sizes(tx.deref()).map_err(|e| e.to_string())?;
+    match segment_sizes.get(&segment_id) {
+        Some(&size) if size > grid_ids.len() as i64 => (),
+        Some(_) => return Err(format!("Segment {} can't be split without remaining grids", segment_id)),
+        None => return Err(format!("Segment {} is not found", segment_id)),
+    }
+    if segment_sizes.contains_key(&new_segment_id) {
+        return Err(format!("Segment {} already exists", new_segment_id));
+    }
+    let mut origin = Vec2i::zero();
+    for &grid_id in grid_ids.iter() {
+        match get_grid_coord(tx.deref(), grid_id).map_err(|e| e.to_string())? {
+            Some(coord) if coord.segment_id == segment_id => if grid_id == new_segment_id {
+                origin = coord.position;
+            },
+            _ => return Err(format!("Grid {} is not found in segment {}", grid_id, segment_id)),
+        }
+    }
+    for &grid_id in grid_ids.iter() {
+        tx.execute_named(
+            MOVE_GRID_QUERY,
+            named_params! {
+                ":grid_id": grid_id,
+                ":segment_id": new_segment_id,
+                ":shift_x": -origin.x(),
+                ":shift_y": -origin.y(),
+            },
+        ).map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(new_segment_id)
+}
+
+fn prune_grids(conn: &mut Connection, config: &MapRetentionConfig, now: i64) -> Result<MapPruneStats, String> {
+    let tx: Transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+    let grids: Vec<GridRetentionInfo> = {
+        let mut stmt = tx.prepare(GET_GRIDS_RETENTION_INFO).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            Ok(GridRetentionInfo {
+                grid_id: row.get(0)?,
+                segment_id: row.get(1)?,
+                last_seen: row.get(2)?,
+                change_id: row.get(3)?,
+            })
+        }).map_err(|e| e.to_string())?;
+        rows.collect::<rusqlite::Result<Vec<_>>>().map_err(|e| e.to_string())?
+    };
+    let segments: BTreeMap<i64, i64> = grids.iter().map(|v| (v.grid_id, v.segment_id)).collect();
+    let pinned_segments: BTreeSet<i64> = config.pinned_segments.iter()
+        .map(|v| segments.get(v).cloned().unwrap_or(*v))
+        .collect();
+    let grid_ids = select_grids_to_prune(&grids, &pinned_segments, config, now);
+    for &grid_id in grid_ids.iter() {
+        for query in &[DELETE_GRID_QUERY, DELETE_GRID_CHANGE_QUERY, DELETE_GRID_LAST_SEEN_QUERY, DELETE_GRID_OBJECTS_QUERY] {
+            tx.execute_named(*query, named_params! { ":grid_id": grid_id }).map_err(|e| e.to_string())?;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    let pruned_segments: BTreeSet<i64> = grid_ids.iter().filter_map(|v| segments.get(v).cloned()).collect();
+    Ok(MapPruneStats { grids: grid_ids.len(), segments: pruned_segments.len() })
 }
 
 impl Tile {
@@ -552,10 +1414,89 @@ impl Tile {
     }
 }
 
-#[derive(Clone, Debug)]
-struct CachedGrid {
-    cached_at: Instant,
-    value: Option<Arc<Mutex<Grid>>>,
+struct PendingGrid {
+    seq: u64,
+    value: Arc<Mutex<Grid>>,
+}
+
+enum GridWrite {
+    Add { grid_id: i64, heights: Vec<f32>, tiles: Vec<i32>, neighbours: Vec<GridNeighbour> },
+    Update { grid_id: i64, heights: Vec<f32>, tiles: Vec<i32> },
+    Revision { grid_id: i64, revision: i64 },
+    LastSeen { grid_id: i64, last_seen: i64 },
+}
+
+struct PendingWrite {
+    seq: u64,
+    value: GridWrite,
+}
+
+struct WriteQueueState {
+    writes: VecDeque<PendingWrite>,
+    flush: bool,
+    stop: bool,
+}
+
+struct WriteQueue {
+    state: Mutex<WriteQueueState>,
+    condvar: Condvar,
+}
+
+struct MapDbWriter {
+    queue: Arc<WriteQueue>,
+    flushed: Arc<AtomicU64>,
+    error: Arc<Mutex<Option<String>>>,
+    last_flushed: Cell<u64>,
+    last_seq: Cell<u64>,
+    batch_size: usize,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MapDbWriter {
+    fn next_seq(&self) -> u64 {
+        self.last_seq.set(self.last_seq.get() + 1);
+        self.last_seq.get()
+    }
+
+    fn push(&self, write: PendingWrite) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.writes.push_back(write);
+        if state.writes.len() >= self.batch_size {
+            self.queue.condvar.notify_one();
+        }
+    }
+
+    fn wait_flushed(&self) -> Result<(), String> {
+        self.queue.state.lock().unwrap().flush = true;
+        self.queue.condvar.notify_one();
+        while self.flushed.load(Ordering::SeqCst) < self.last_seq.get() {
+            if let Some(e) = self.error.lock().unwrap().take() {
+                return Err(e);
+            }
+            sleep(WRITE_BEHIND_FLUSH_POLL_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MapDbWriter {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().stop = true;
+        self.queue.condvar.notify_one();
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+    }
+}
+
+impl Resource {
+    fn from_sqlite_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Resource {
+            id: row.get(0)?,
+            version: row.get(1)?,
+            name: row.get(2)?,
+        })
+    }
 }
 
 impl Grid {
@@ -565,8 +1506,8 @@ impl Grid {
             revision: row.get(1)?,
             segment_id: row.get(2)?,
             position: Vec2i::new(row.get(3)?, row.get(4)?),
-            heights: serde_json::from_slice(&(row.get::<usize, Vec<u8>>(5)?)).unwrap(),
-            tiles: serde_json::from_slice(&(row.get::<usize, Vec<u8>>(6)?)).unwrap(),
+            heights: decode_grid_values(row.get(7)?, &(row.get::<usize, Vec<u8>>(5)?)),
+            tiles: decode_grid_values(row.get(7)?, &(row.get::<usize, Vec<u8>>(6)?)),
         })
     }
 }
@@ -582,6 +1523,8 @@ mod tests {
     use std::fs::remove_file;
     use std::path::Path;
 
+    use rusqlite::OpenFlags;
+
     use super::*;
 
     #[test]
@@ -657,6 +1600,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn merge_segments_should_move_grids_with_shift_and_bump_revision() {
+        let path = RemovePath("merge_segments_should_move_grids_with_shift_and_bump_revision.db");
+        let map_db = make_map_db(&path);
+        map_db.add_grid(1, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &Vec::new(), &Vec::new());
+        assert_eq!(
+            map_db.merge_segments(2, 1, Vec2i::zero()),
+            Err(String::from("1 grids of segment 2 overlap segment 1 with shift Vec2i { x: 0, y: 0 }"))
+        );
+        assert_eq!(map_db.merge_segments(1, 1, Vec2i::new(1, 0)), Err(String::from("Segment 1 can't be merged into itself")));
+        assert_eq!(map_db.merge_segments(3, 1, Vec2i::new(1, 0)), Err(String::from("Segment 3 is not found")));
+        assert_eq!(map_db.merge_segments(2, 1, Vec2i::new(1, 0)), Ok(1));
+        assert_eq!(
+            map_db.get_grids().iter().map(|v| (v.id, v.revision, v.segment_id, v.position)).collect::<Vec<_>>(),
+            vec![
+                (1, 1, 1, Vec2i::zero()),
+                (2, 2, 1, Vec2i::new(1, 0)),
+            ]
+        );
+        assert_eq!(map_db.get_grid(1, Vec2i::new(1, 0)).map(|v| v.lock().unwrap().id), Some(2));
+    }
+
+    #[test]
+    fn split_segment_should_move_grids_to_new_segment() {
+        let path = RemovePath("split_segment_should_move_grids_to_new_segment.db");
+        let map_db = make_map_db(&path);
+        map_db.add_grid(1, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &Vec::new(), &vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        map_db.add_grid(3, &Vec::new(), &Vec::new(), &vec![GridNeighbour { id: 2, offset: Vec2i::new(-1, 0) }]);
+        assert_eq!(map_db.split_segment(1, &vec![1, 2, 3]), Err(String::from("Segment 1 can't be split without remaining grids")));
+        assert_eq!(map_db.split_segment(1, &vec![3, 4]), Err(String::from("Grid 4 is not found in segment 1")));
+        assert_eq!(map_db.split_segment(4, &vec![3]), Err(String::from("Segment 4 is not found")));
+        assert_eq!(map_db.split_segment(1, &vec![3, 2]), Ok(2));
+        assert_eq!(
+            map_db.get_grids().iter().map(|v| (v.id, v.revision, v.segment_id, v.position)).collect::<Vec<_>>(),
+            vec![
+                (1, 1, 1, Vec2i::zero()),
+                (2, 2, 2, Vec2i::zero()),
+                (3, 2, 2, Vec2i::new(1, 0)),
+            ]
+        );
+        assert_eq!(map_db.get_grid(1, Vec2i::new(1, 0)).map(|v| v.lock().unwrap().id), None);
+    }
+
     #[test]
     fn update_grid_should_invalidate_cache() {
         let path = RemovePath("update_grid_should_invalidate_cache.db");
@@ -692,6 +1680,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_grids_older_than_should_return_grids_last_seen_before_time() {
+        let path = RemovePath("get_grids_older_than_should_return_grids_last_seen_before_time.db");
+        let map_db = make_map_db(&path);
+        map_db.set_grid_last_seen(1, 100);
+        map_db.set_grid_last_seen(2, 200);
+        map_db.set_grid_last_seen(3, 50);
+        assert_eq!(map_db.get_grids_older_than(150), vec![3, 1]);
+        map_db.set_grid_last_seen(3, 300);
+        assert_eq!(map_db.get_grids_older_than(150), vec![1]);
+        assert_eq!(map_db.get_grids_older_than(10), Vec::<i64>::new());
+    }
+
     #[test]
     fn get_grid_should_invalidate_cache_by_ttl() {
         let path = RemovePath("get_grid_should_invalidate_cache_by_ttl.db");
@@ -730,6 +1731,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn update_grid_should_skip_unchanged_grid() {
+        let path = RemovePath("update_grid_should_skip_unchanged_grid.db");
+        let map_db = make_map_db(&path);
+        let heights = vec![1.0, 2.0, 3.0];
+        let tiles = vec![4, 5, 6];
+        map_db.add_grid(1, &heights, &tiles, &Vec::new());
+        assert!(!map_db.update_grid(1, &heights, &tiles));
+        assert_eq!(map_db.get_grid_by_id(1).map(|v| v.lock().unwrap().revision), Some(1));
+        assert_eq!(map_db.get_grid_changes(0, 10).iter().map(|(change_id, _)| *change_id).collect::<Vec<_>>(), vec![1]);
+        assert!(map_db.update_grid(1, &heights, &vec![4, 5, 7]));
+        assert_eq!(map_db.get_grid_changes(0, 10).iter().map(|(change_id, grid)| (*change_id, grid.revision)).collect::<Vec<_>>(), vec![(2, 2)]);
+    }
+
     #[test]
     fn get_grid_should_get_cached_before_ttl_ends() {
         let path = RemovePath("get_grid_should_get_cached_before_ttl_ends.db");
@@ -777,6 +1792,41 @@ mod tests {
         assert_eq!(map_db.get_tiles(), vec![tile]);
     }
 
+    #[test]
+    fn prune_should_remove_least_recently_seen_grids_except_pinned_segments() {
+        let path = RemovePath("prune_should_remove_least_recently_seen_grids_except_pinned_segments.db");
+        let map_db = make_map_db(&path);
+        map_db.add_grid(1, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &Vec::new(), &vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        map_db.add_grid(3, &Vec::new(), &Vec::new(), &vec![GridNeighbour { id: 2, offset: Vec2i::new(-1, 0) }]);
+        map_db.add_grid(4, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(5, &Vec::new(), &Vec::new(), &vec![GridNeighbour { id: 4, offset: Vec2i::new(-1, 0) }]);
+        for &(grid_id, last_seen) in &[(1, 300), (2, 100), (3, 200), (4, 10), (5, 20)] {
+            map_db.set_grid_last_seen(grid_id, last_seen);
+        }
+        let config = MapRetentionConfig {
+            interval: 1.0,
+            max_grids_per_segment: Some(2),
+            max_age: None,
+            pinned_segments: vec![5],
+            vacuum: true,
+        };
+        assert_eq!(map_db.prune(&config, 400), Ok(MapPruneStats { grids: 1, segments: 1 }));
+        assert_eq!(map_db.get_grids().iter().map(|v| v.id).collect::<Vec<_>>(), vec![1, 3, 4, 5]);
+        assert_eq!(map_db.get_grids_older_than(1000), vec![4, 5, 3, 1]);
+    }
+
+    #[test]
+    fn set_resource_should_keep_last_seen_id_for_name_and_version() {
+        let path = RemovePath("set_resource_should_keep_last_seen_id_for_name_and_version.db");
+        let map_db = make_map_db(&path);
+        let make_resource = |id, version, name: &str| Resource { id, version, name: String::from(name) };
+        map_db.set_resource(&make_resource(1, 1, "gfx/invobjs/wood"));
+        map_db.set_resource(&make_resource(2, 3, "gfx/invobjs/stone"));
+        map_db.set_resource(&make_resource(5, 1, "gfx/invobjs/wood"));
+        assert_eq!(map_db.get_resources(), vec![make_resource(2, 3, "gfx/invobjs/stone"), make_resource(5, 1, "gfx/invobjs/wood")]);
+    }
+
     #[test]
     fn set_tile_should_update_stored_tile_with_greater_version() {
         let path = RemovePath("set_tile_should_update_stored_tile_with_greater_version.db");
@@ -844,6 +1894,220 @@ mod tests {
         assert_eq!(map_db.get_tile_id_by_name(&String::from("ground")), Some(tile.id));
     }
 
+    #[test]
+    fn get_grid_changes_should_return_grids_in_order_of_last_change() {
+        let path = RemovePath("get_grid_changes_should_return_grids_in_order_of_last_change.db");
+        let map_db = make_map_db(&path);
+        map_db.add_grid(1, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.update_grid(1, &vec![1.0], &vec![1]);
+        assert_eq!(
+            map_db.get_grid_changes(0, 10).iter().map(|(change_id, grid)| (*change_id, grid.id, grid.revision)).collect::<Vec<_>>(),
+            vec![(2, 2, 1), (3, 1, 2)]
+        );
+        assert_eq!(
+            map_db.get_grid_changes(2, 10).iter().map(|(change_id, grid)| (*change_id, grid.id)).collect::<Vec<_>>(),
+            vec![(3, 1)]
+        );
+        assert_eq!(
+            map_db.get_grid_changes(0, 1).iter().map(|(change_id, grid)| (*change_id, grid.id)).collect::<Vec<_>>(),
+            vec![(2, 2)]
+        );
+    }
+
+    #[test]
+    fn objects_should_follow_merged_segment_grids() {
+        let path = RemovePath("objects_should_follow_merged_segment_grids.db");
+        let map_db = make_map_db(&path);
+        map_db.add_grid(1, &Vec::new(), &Vec::new(), &Vec::new());
+        map_db.add_grid(2, &Vec::new(), &Vec::new(), &Vec::new());
+        let object = MapObject { id: 42, name: String::from("gfx/terobjs/trees/spruce"), grid_id: 2, offset: Vec2f::new(5.0, 7.0) };
+        map_db.add_object(&object);
+        map_db.add_grid(3, &Vec::new(), &Vec::new(), &vec![
+            GridNeighbour { id: 1, offset: Vec2i::new(1, 0) },
+            GridNeighbour { id: 2, offset: Vec2i::new(-1, 0) },
+        ]);
+        let grid = map_db.get_grids().into_iter().find(|v| v.id == 2).unwrap();
+        let position = grid_pos_to_pos(grid.position) + object.offset;
+        let shift = Vec2f::new(1.0, 1.0);
+        assert_eq!(
+            map_db.get_objects_in_rect(grid.segment_id, position - shift, position + shift),
+            vec![(position, object.clone())]
+        );
+        assert_eq!(map_db.get_objects_in_rect(grid.segment_id, position + shift, position + shift * 2.0), Vec::new());
+        map_db.remove_object(object.id);
+        assert_eq!(map_db.get_objects_in_rect(grid.segment_id, position - shift, position + shift), Vec::new());
+    }
+
+    #[test]
+    fn json_grids_should_be_migrated_to_binary_format() {
+        let path = RemovePath("json_grids_should_be_migrated_to_binary_format.db");
+        match remove_file(&path) { _ => () };
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(r"
+                CREATE TABLE grids (
+                    grid_id INTEGER PRIMARY KEY,
+                    revision INTEGER NOT NULL,
+                    segment_id INTEGER NOT NULL,
+                    position_x INTEGER NOT NULL,
+                    position_y INTEGER NOT NULL,
+                    heights BLOB NOT NULL,
+                    tiles BLOB NOT NULL
+                );
+                INSERT INTO grids (grid_id, revision, segment_id, position_x, position_y, heights, tiles)
+                VALUES (1, 1, 1, 0, 0, CAST('[1.0,2.0,3.0]' AS BLOB), CAST('[4,5,6]' AS BLOB));
+            ").unwrap();
+        }
+        let map_db = SqliteMapDb::new(Connection::open(&path).unwrap(), Duration::new(std::u64::MAX, 0));
+        let grids = map_db.get_grids();
+        assert_eq!(grids.len(), 1);
+        assert_eq!(grids[0].heights, vec![1.0, 2.0, 3.0]);
+        assert_eq!(grids[0].tiles, vec![4, 5, 6]);
+        let format: i64 = map_db.conn.borrow().query_row("SELECT format FROM grids WHERE grid_id = 1", NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(format, GRID_FORMAT_BINCODE_ZSTD);
+    }
+
+    #[test]
+    fn write_behind_grids_should_be_readable_before_and_after_flush() {
+        let path = RemovePath("write_behind_grids_should_be_readable_before_and_after_flush.db");
+        let config = MapDbWriteBehindConfig { flush_interval: 60.0, batch_size: 100 };
+        let map_db = make_map_db(&path).with_write_behind(Connection::open(&path).unwrap(), config);
+        map_db.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        map_db.add_grid(2, &vec![2.0], &vec![2], &vec![
+            GridNeighbour { id: 1, offset: Vec2i::new(1, 0) },
+        ]);
+        map_db.update_grid(1, &vec![3.0], &vec![3]);
+        let expected = vec![
+            Grid { id: 1, revision: 2, segment_id: 1, position: Vec2i::zero(), heights: vec![3.0], tiles: vec![3] },
+            Grid { id: 2, revision: 1, segment_id: 1, position: Vec2i::new(-1, 0), heights: vec![2.0], tiles: vec![2] },
+        ];
+        assert_eq!(map_db.get_grids(), expected);
+        assert_eq!(map_db.get_grid(1, Vec2i::new(-1, 0)).map(|v| v.lock().unwrap().id), Some(2));
+        drop(map_db);
+        let map_db = SqliteMapDb::new(Connection::open(&path).unwrap(), Duration::ZERO);
+        assert_eq!(map_db.get_grids(), expected);
+    }
+
+    #[test]
+    fn write_behind_grid_last_seen_should_be_written_in_batch() {
+        let path = RemovePath("write_behind_grid_last_seen_should_be_written_in_batch.db");
+        let config = MapDbWriteBehindConfig { flush_interval: 60.0, batch_size: 100 };
+        let map_db = make_map_db(&path).with_write_behind(Connection::open(&path).unwrap(), config);
+        map_db.set_grid_last_seen(1, 100);
+        map_db.set_grid_last_seen(2, 200);
+        let other = SqliteMapDb::new(Connection::open(&path).unwrap(), Duration::ZERO);
+        assert_eq!(other.get_grids_older_than(300), Vec::<i64>::new());
+        assert_eq!(map_db.get_grids_older_than(300), vec![1, 2]);
+        assert_eq!(other.get_grids_older_than(300), vec![1, 2]);
+    }
+
+    #[test]
+    fn flush_should_write_pending_grids_before_flush_interval() {
+        let path = RemovePath("flush_should_write_pending_grids_before_flush_interval.db");
+        let config = MapDbWriteBehindConfig { flush_interval: 60.0, batch_size: 100 };
+        let map_db = make_map_db(&path).with_write_behind(Connection::open(&path).unwrap(), config);
+        map_db.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        assert_eq!(map_db.flush(), Ok(()));
+        assert_eq!(map_db.pending_grids.borrow().len(), 0);
+        let other = SqliteMapDb::new(Connection::open(&path).unwrap(), Duration::ZERO);
+        assert_eq!(other.get_grids().len(), 1);
+    }
+
+    #[test]
+    fn write_behind_grids_should_merge_into_largest_segment_before_flush() {
+        let path = RemovePath("write_behind_grids_should_merge_into_largest_segment_before_flush.db");
+        let map_db = make_map_db(&path);
+        map_db.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        map_db.add_grid(2, &vec![2.0], &vec![2], &Vec::new());
+        map_db.add_grid(3, &vec![3.0], &vec![3], &vec![
+            GridNeighbour { id: 2, offset: Vec2i::new(-1, 0) },
+        ]);
+        let config = MapDbWriteBehindConfig { flush_interval: 60.0, batch_size: 100 };
+        let map_db = map_db.with_write_behind(Connection::open(&path).unwrap(), config);
+        map_db.add_grid(4, &vec![4.0], &vec![4], &vec![
+            GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) },
+            GridNeighbour { id: 2, offset: Vec2i::new(1, 0) },
+        ]);
+        let pending = map_db.get_grids();
+        assert_eq!(
+            pending.iter().map(|v| (v.id, v.revision, v.segment_id, v.position)).collect::<Vec<_>>(),
+            vec![
+                (1, 2, 2, Vec2i::new(-2, 0)),
+                (2, 1, 2, Vec2i::zero()),
+                (3, 1, 2, Vec2i::new(1, 0)),
+                (4, 1, 2, Vec2i::new(-1, 0)),
+            ]
+        );
+        assert_eq!(map_db.flush(), Ok(()));
+        assert_eq!(map_db.get_grids(), pending);
+    }
+
+    #[test]
+    fn flush_should_return_error_for_failed_pending_grids_write() {
+        let path = RemovePath("flush_should_return_error_for_failed_pending_grids_write.db");
+        let map_db = make_map_db(&path);
+        map_db.conn.borrow().query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(())).unwrap();
+        let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+        let config = MapDbWriteBehindConfig { flush_interval: 60.0, batch_size: 100 };
+        let map_db = map_db.with_write_behind(conn, config);
+        map_db.add_grid(1, &vec![1.0], &vec![1], &Vec::new());
+        assert!(map_db.flush().is_err());
+        assert_eq!(map_db.pending_grids.borrow().len(), 1);
+        assert!(map_db.get_grid_by_id(1).is_some());
+        assert!(map_db.flush().is_err());
+    }
+
+    #[test]
+    fn grids_cache_should_be_bounded_by_capacity() {
+        let path = RemovePath("grids_cache_should_be_bounded_by_capacity.db");
+        let map_db = make_map_db(&path).with_cache_capacity(2);
+        for grid_id in 1..=3 {
+            map_db.add_grid(grid_id, &vec![1.0], &vec![1], &Vec::new());
+            assert!(map_db.get_grid_by_id(grid_id).is_some());
+        }
+        let stats = map_db.get_cache_stats();
+        assert_eq!(stats.grids, 2);
+        assert_eq!(stats.capacity, 2);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(map_db.get_grid_by_id(1).map(|v| v.lock().unwrap().id), Some(1));
+        assert_eq!(map_db.get_cache_stats().misses, stats.misses + 1);
+    }
+
+    #[test]
+    fn zones_should_be_stored_per_segment() {
+        let path = RemovePath("zones_should_be_stored_per_segment.db");
+        let map_db = make_map_db(&path);
+        let zone = Zone { segment_id: 1, name: String::from("field"), points: vec![Vec2i::new(0, 0), Vec2i::new(4, 0), Vec2i::new(0, 4)] };
+        map_db.set_zone(&zone);
+        map_db.set_zone(&Zone { segment_id: 2, ..zone.clone() });
+        assert_eq!(map_db.get_zones(1), vec![zone.clone()]);
+        let updated = Zone { points: vec![Vec2i::new(0, 0), Vec2i::new(2, 0), Vec2i::new(0, 2)], ..zone.clone() };
+        map_db.set_zone(&updated);
+        assert_eq!(map_db.get_zones(1), vec![updated]);
+        assert!(map_db.remove_zone(1, &zone.name));
+        assert!(!map_db.remove_zone(1, &zone.name));
+        assert_eq!(map_db.get_zones(1), Vec::new());
+        assert_eq!(map_db.get_zones(2).len(), 1);
+    }
+
+    #[test]
+    fn bookmarks_should_be_stored_per_segment() {
+        let path = RemovePath("bookmarks_should_be_stored_per_segment.db");
+        let map_db = make_map_db(&path);
+        let bookmark = Bookmark { segment_id: 1, name: String::from("home"), position: Vec2f::new(-11.5, 42.0) };
+        map_db.set_bookmark(&bookmark);
+        map_db.set_bookmark(&Bookmark { segment_id: 2, ..bookmark.clone() });
+        assert_eq!(map_db.get_bookmarks(1), vec![bookmark.clone()]);
+        let updated = Bookmark { position: Vec2f::new(1.0, 2.0), ..bookmark.clone() };
+        map_db.set_bookmark(&updated);
+        assert_eq!(map_db.get_bookmarks(1), vec![updated]);
+        assert!(map_db.remove_bookmark(1, &bookmark.name));
+        assert!(!map_db.remove_bookmark(1, &bookmark.name));
+        assert_eq!(map_db.get_bookmarks(1), Vec::new());
+        assert_eq!(map_db.get_bookmarks(2).len(), 1);
+    }
+
     fn make_map_db<P: AsRef<Path> + Copy>(path: P) -> SqliteMapDb {
         make_map_db_with_cache_ttl(path, Duration::new(std::u64::MAX, 0))
     }
@@ -866,6 +2130,8 @@ mod tests {
     impl<'a> Drop for RemovePath<'a> {
         fn drop(&mut self) {
             match remove_file(self.0) { _ => () }
+            match remove_file(format!("{}-wal", self.0)) { _ => () }
+            match remove_file(format!("{}-shm", self.0)) { _ => () }
         }
     }
 }
@@ -1,31 +1,127 @@
+use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
+use serde::Deserialize;
+
 use crate::bot::vec2::Vec2f;
 
-const MIN_DURATION: Duration = Duration::from_secs(1);
+const SPEED_SMOOTHING: f64 = 0.5;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StuckDetectorConfig {
+    pub window: f64,
+    pub min_displacement: f64,
+    pub min_updates: usize,
+    pub max_update_gap: f64,
+    pub min_speed: f64,
+}
+
+struct Sample {
+    position: Vec2f,
+    time: Instant,
+    speed: f64,
+}
 
-#[derive(Default)]
 pub struct StuckDetector {
-    last_position: Option<Vec2f>,
-    last_update: Option<Instant>,
+    samples: VecDeque<Sample>,
+    speed: f64,
+    config: StuckDetectorConfig,
 }
 
 impl StuckDetector {
-    pub fn new() -> Self {
+    pub fn new(config: StuckDetectorConfig) -> Self {
         Self {
-            last_position: None,
-            last_update: None,
+            samples: VecDeque::new(),
+            speed: 0.0,
+            config,
         }
     }
 
-    pub fn update(&mut self, position: Vec2f, now: Instant) {
-        self.last_position = Some(position);
-        self.last_update = Some(now);
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.speed = 0.0;
     }
 
-    pub fn check(&self, position: Vec2f, now: Instant) -> bool {
-        self.last_update
-            .map(|update| self.last_position == Some(position) && now - update > MIN_DURATION)
-            .unwrap_or(false)
+    pub fn update(&mut self, position: Vec2f, now: Instant) -> bool {
+        if let Some((last_position, duration)) = self.samples.back().map(|v| (v.position, now - v.time)) {
+            if duration > Duration::from_secs_f64(self.config.max_update_gap) {
+                self.reset();
+            } else if duration > Duration::ZERO {
+                let speed = last_position.distance(position) / duration.as_secs_f64();
+                self.speed += (speed - self.speed) * SPEED_SMOOTHING;
+            }
+        }
+        self.samples.push_back(Sample { position, time: now, speed: self.speed });
+        let window = Duration::from_secs_f64(self.config.window);
+        while self.samples.len() > 2 && now - self.samples[1].time >= window {
+            self.samples.pop_front();
+        }
+        self.is_obstructed(position) || self.is_standing(position, now)
+    }
+
+    fn is_obstructed(&self, position: Vec2f) -> bool {
+        let min_updates = self.config.min_updates.max(2);
+        if self.samples.len() < min_updates {
+            return false;
+        }
+        let first = &self.samples[self.samples.len() - min_updates];
+        first.speed >= self.config.min_speed && first.position.distance(position) < self.config.min_displacement
+    }
+
+    fn is_standing(&self, position: Vec2f, now: Instant) -> bool {
+        let first = match self.samples.front() {
+            Some(v) => v,
+            None => return false,
+        };
+        self.samples.len() >= self.config.min_updates.max(2)
+            && now - first.time >= Duration::from_secs_f64(self.config.window)
+            && first.position.distance(position) < self.config.min_displacement
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_detector() -> StuckDetector {
+        StuckDetector::new(StuckDetectorConfig {
+            window: 2.0,
+            min_displacement: 1.0,
+            min_updates: 3,
+            max_update_gap: 1.0,
+            min_speed: 5.0,
+        })
+    }
+
+    #[test]
+    fn update_should_detect_obstruction_after_movement_without_waiting_for_window() {
+        let mut detector = make_detector();
+        let start = Instant::now();
+        let at = |seconds: f64| start + Duration::from_secs_f64(seconds);
+        for i in 0..5 {
+            assert!(!detector.update(Vec2f::new(i as f64 * 10.0, 0.0), at(i as f64 * 0.2)));
+        }
+        assert!(!detector.update(Vec2f::new(40.0, 0.0), at(1.2)));
+        assert!(detector.update(Vec2f::new(40.1, 0.0), at(1.4)));
+        assert!(!detector.update(Vec2f::new(50.0, 0.0), at(1.6)));
+    }
+
+    #[test]
+    fn update_should_ignore_sparse_updates_while_standing() {
+        let mut detector = make_detector();
+        let start = Instant::now();
+        for i in 0..5 {
+            assert!(!detector.update(Vec2f::new(1.0, 1.0), start + Duration::from_secs_f64(i as f64 * 1.5)));
+        }
+    }
+
+    #[test]
+    fn update_should_detect_standing_with_frequent_updates_for_window() {
+        let mut detector = make_detector();
+        let start = Instant::now();
+        for i in 0..4 {
+            assert!(!detector.update(Vec2f::new(1.0, 1.0), start + Duration::from_secs_f64(i as f64 * 0.5)));
+        }
+        assert!(detector.update(Vec2f::new(1.2, 1.0), start + Duration::from_secs_f64(2.0)));
     }
 }
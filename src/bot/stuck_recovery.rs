@@ -0,0 +1,172 @@
+use std::f64::consts::PI;
+use std::time::{Duration, Instant};
+
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use crate::bot::vec2::Vec2f;
+
+#[derive(Clone, Deserialize)]
+pub struct StuckRecoveryConfig {
+    pub delay: f64,
+    pub interval: f64,
+    pub move_distance: f64,
+    pub actions: Vec<StuckRecoveryAction>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum StuckRecoveryAction {
+    RandomMove,
+    Replan,
+    CancelTask,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum StuckRecoveryOutcome {
+    Recovered,
+    Failed,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StuckRecoveryStep {
+    Apply(StuckRecoveryAction),
+    Finish {
+        outcome: StuckRecoveryOutcome,
+        actions: Vec<StuckRecoveryAction>,
+    },
+}
+
+pub struct StuckRecovery {
+    stuck_since: Option<Instant>,
+    last_action: Option<Instant>,
+    actions: Vec<StuckRecoveryAction>,
+    failed: bool,
+    rng: SmallRng,
+    config: StuckRecoveryConfig,
+}
+
+impl StuckRecovery {
+    pub fn new(config: StuckRecoveryConfig) -> Self {
+        Self {
+            stuck_since: None,
+            last_action: None,
+            actions: Vec::new(),
+            failed: false,
+            rng: SeedableRng::from_entropy(),
+            config,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.stuck_since.is_some()
+    }
+
+    pub fn update(&mut self, is_stuck: bool, now: Instant) -> Option<StuckRecoveryStep> {
+        if !is_stuck {
+            let actions = std::mem::take(&mut self.actions);
+            let failed = self.failed;
+            self.stuck_since = None;
+            self.last_action = None;
+            self.failed = false;
+            if failed || actions.is_empty() {
+                return None;
+            }
+            debug!("StuckRecovery: recovered after {:?}", actions);
+            return Some(StuckRecoveryStep::Finish { outcome: StuckRecoveryOutcome::Recovered, actions });
+        }
+        let stuck_since = *self.stuck_since.get_or_insert(now);
+        if self.failed || now - stuck_since < Duration::from_secs_f64(self.config.delay) {
+            return None;
+        }
+        if let Some(last_action) = self.last_action {
+            if now - last_action < Duration::from_secs_f64(self.config.interval) {
+                return None;
+            }
+        }
+        self.last_action = Some(now);
+        match self.config.actions.get(self.actions.len()) {
+            Some(action) => {
+                debug!("StuckRecovery: apply {:?} after {:?} stuck", action, now - stuck_since);
+                self.actions.push(*action);
+                Some(StuckRecoveryStep::Apply(*action))
+            }
+            None => {
+                debug!("StuckRecovery: failed after {:?}", self.actions);
+                self.failed = true;
+                Some(StuckRecoveryStep::Finish {
+                    outcome: StuckRecoveryOutcome::Failed,
+                    actions: std::mem::take(&mut self.actions),
+                })
+            }
+        }
+    }
+
+    pub fn get_random_move(&mut self, position: Vec2f) -> Vec2f {
+        let angle = Uniform::new(-PI, PI).sample(&mut self.rng);
+        position + Vec2f::new(angle.cos(), angle.sin()) * self.config.move_distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stuck_recovery_should_apply_actions_in_order_and_report_failure() {
+        let mut stuck_recovery = StuckRecovery::new(StuckRecoveryConfig {
+            delay: 2.0,
+            interval: 1.0,
+            move_distance: 10.0,
+            actions: vec![StuckRecoveryAction::RandomMove, StuckRecoveryAction::CancelTask],
+        });
+        let now = Instant::now();
+        assert_eq!(stuck_recovery.update(true, now), None);
+        assert_eq!(stuck_recovery.update(true, now + Duration::from_secs(1)), None);
+        assert_eq!(
+            stuck_recovery.update(true, now + Duration::from_secs(2)),
+            Some(StuckRecoveryStep::Apply(StuckRecoveryAction::RandomMove))
+        );
+        assert_eq!(stuck_recovery.update(true, now + Duration::from_millis(2500)), None);
+        assert_eq!(
+            stuck_recovery.update(true, now + Duration::from_secs(3)),
+            Some(StuckRecoveryStep::Apply(StuckRecoveryAction::CancelTask))
+        );
+        assert_eq!(
+            stuck_recovery.update(true, now + Duration::from_secs(4)),
+            Some(StuckRecoveryStep::Finish {
+                outcome: StuckRecoveryOutcome::Failed,
+                actions: vec![StuckRecoveryAction::RandomMove, StuckRecoveryAction::CancelTask],
+            })
+        );
+        assert_eq!(stuck_recovery.update(true, now + Duration::from_secs(5)), None);
+        assert_eq!(stuck_recovery.update(false, now + Duration::from_secs(6)), None);
+        assert!(!stuck_recovery.is_active());
+    }
+
+    #[test]
+    fn stuck_recovery_should_report_recovery_when_player_moves() {
+        let mut stuck_recovery = StuckRecovery::new(StuckRecoveryConfig {
+            delay: 0.0,
+            interval: 1.0,
+            move_distance: 10.0,
+            actions: vec![StuckRecoveryAction::Replan],
+        });
+        let now = Instant::now();
+        assert_eq!(stuck_recovery.update(false, now), None);
+        assert_eq!(
+            stuck_recovery.update(true, now),
+            Some(StuckRecoveryStep::Apply(StuckRecoveryAction::Replan))
+        );
+        assert_eq!(
+            stuck_recovery.update(false, now + Duration::from_secs(1)),
+            Some(StuckRecoveryStep::Finish {
+                outcome: StuckRecoveryOutcome::Recovered,
+                actions: vec![StuckRecoveryAction::Replan],
+            })
+        );
+        let position = Vec2f::new(1.0, 2.0);
+        assert!((stuck_recovery.get_random_move(position).distance(position) - 10.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use crate::bot::vec2::Vec2i;
+
+#[derive(Clone, Deserialize)]
+pub struct StuckTilesConfig {
+    pub weight: f64,
+    pub max_weight: f64,
+    pub min_weight: f64,
+    pub radius: i32,
+    pub half_life: f64,
+}
+
+#[derive(Clone)]
+struct StuckTile {
+    weight: f64,
+    updated: Instant,
+}
+
+#[derive(Clone)]
+pub struct StuckTiles {
+    revision: u64,
+    tiles: BTreeMap<(i64, Vec2i), StuckTile>,
+    config: StuckTilesConfig,
+}
+
+impl StuckTiles {
+    pub fn new(config: StuckTilesConfig) -> Self {
+        Self {
+            revision: 0,
+            tiles: BTreeMap::new(),
+            config,
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn add(&mut self, segment_id: i64, tile_pos: Vec2i, now: Instant) {
+        let radius = self.config.radius;
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                let key = (segment_id, tile_pos + Vec2i::new(x, y));
+                let weight = self.tiles.get(&key)
+                    .map(|v| self.get_decayed_weight(v, now))
+                    .unwrap_or(0.0);
+                self.tiles.insert(key, StuckTile {
+                    weight: (weight + self.config.weight).min(self.config.max_weight),
+                    updated: now,
+                });
+            }
+        }
+        self.revision += 1;
+    }
+
+    pub fn saturate(&mut self, segment_id: i64, tile_pos: Vec2i, now: Instant) {
+        let radius = self.config.radius;
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                self.tiles.insert((segment_id, tile_pos + Vec2i::new(x, y)), StuckTile {
+                    weight: self.config.max_weight,
+                    updated: now,
+                });
+            }
+        }
+        self.revision += 1;
+    }
+
+    pub fn get_weight(&self, segment_id: i64, tile_pos: Vec2i, now: Instant) -> f64 {
+        self.tiles.get(&(segment_id, tile_pos))
+            .map(|v| self.get_decayed_weight(v, now))
+            .unwrap_or(0.0)
+    }
+
+    pub fn remove_expired(&mut self, now: Instant) -> bool {
+        let len = self.tiles.len();
+        let half_life = self.config.half_life;
+        let min_weight = self.config.min_weight;
+        self.tiles.retain(|_, v| get_decayed_weight(v, half_life, now) >= min_weight);
+        if len != self.tiles.len() {
+            self.revision += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn get_decayed_weight(&self, tile: &StuckTile, now: Instant) -> f64 {
+        get_decayed_weight(tile, self.config.half_life, now)
+    }
+}
+
+fn get_decayed_weight(tile: &StuckTile, half_life: f64, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(tile.updated).as_secs_f64();
+    tile.weight * 0.5f64.powf(elapsed / half_life)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn make_config() -> StuckTilesConfig {
+        StuckTilesConfig {
+            weight: 4.0,
+            max_weight: 6.0,
+            min_weight: 1.0,
+            radius: 1,
+            half_life: 10.0,
+        }
+    }
+
+    #[test]
+    fn stuck_tiles_weight_should_accumulate_and_decay() {
+        let mut stuck_tiles = StuckTiles::new(make_config());
+        let now = Instant::now();
+        stuck_tiles.add(1, Vec2i::new(5, 5), now);
+        assert_eq!(stuck_tiles.len(), 9);
+        assert_eq!(stuck_tiles.get_weight(1, Vec2i::new(6, 6), now), 4.0);
+        assert_eq!(stuck_tiles.get_weight(1, Vec2i::new(7, 7), now), 0.0);
+        assert_eq!(stuck_tiles.get_weight(2, Vec2i::new(5, 5), now), 0.0);
+        stuck_tiles.add(1, Vec2i::new(5, 5), now);
+        assert_eq!(stuck_tiles.get_weight(1, Vec2i::new(5, 5), now), 6.0);
+        assert_eq!(stuck_tiles.get_weight(1, Vec2i::new(5, 5), now + Duration::from_secs(10)), 3.0);
+        assert!(!stuck_tiles.remove_expired(now + Duration::from_secs(20)));
+        assert!(stuck_tiles.remove_expired(now + Duration::from_secs(30)));
+        assert_eq!(stuck_tiles.len(), 0);
+        assert_eq!(stuck_tiles.revision(), 3);
+        stuck_tiles.saturate(1, Vec2i::new(5, 5), now);
+        assert_eq!(stuck_tiles.get_weight(1, Vec2i::new(4, 4), now), 6.0);
+        assert_eq!(stuck_tiles.revision(), 4);
+    }
+}
@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::fmt::Arguments;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::Level;
+use serde::Serialize;
+
+thread_local! {
+    static CONTEXT: RefCell<Option<TaskLogContext>> = RefCell::new(None);
+    static WRITER: RefCell<Option<TaskLogWriter>> = RefCell::new(None);
+}
+
+macro_rules! task_error {
+    ($($arg:tt)+) => { crate::bot::task_log::log_task_record(::log::Level::Error, format_args!($($arg)+)) };
+}
+
+macro_rules! task_warn {
+    ($($arg:tt)+) => { crate::bot::task_log::log_task_record(::log::Level::Warn, format_args!($($arg)+)) };
+}
+
+macro_rules! task_debug {
+    ($($arg:tt)+) => { crate::bot::task_log::log_task_record(::log::Level::Debug, format_args!($($arg)+)) };
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaskLogContext {
+    pub session_id: i64,
+    pub task_id: i64,
+    pub task_name: String,
+    pub update: i64,
+}
+
+#[derive(Serialize)]
+struct TaskLogRecord<'a> {
+    time: f64,
+    level: &'a str,
+    session: i64,
+    task_id: i64,
+    task: &'a str,
+    update: i64,
+    message: &'a str,
+}
+
+pub struct TaskLogScope {
+    previous: Option<TaskLogContext>,
+}
+
+impl TaskLogScope {
+    pub fn enter(context: TaskLogContext) -> Self {
+        Self { previous: CONTEXT.with(|v| v.replace(Some(context))) }
+    }
+}
+
+impl Drop for TaskLogScope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CONTEXT.with(|v| *v.borrow_mut() = previous);
+    }
+}
+
+pub struct TaskLogWriter {
+    session_id: i64,
+    file: File,
+}
+
+impl TaskLogWriter {
+    pub fn open(path: &String, session_id: i64) -> Result<Self, String> {
+        std::fs::create_dir_all(path)
+            .map_err(|e| format!("Failed to create dir {}: {}", path, e))?;
+        let file_path = get_task_log_path(path, session_id);
+        let file = OpenOptions::new().create(true).append(true).open(&file_path)
+            .map_err(|e| format!("Failed to open task log {}: {}", file_path, e))?;
+        Ok(Self { session_id, file })
+    }
+
+    fn write(&mut self, line: &str) {
+        if let Err(e) = self.file.write_all(line.as_bytes()).and_then(|_| self.file.write_all(b"\n")) {
+            error!("Failed to write task log for session {}: {}", self.session_id, e);
+        }
+    }
+}
+
+pub fn set_task_log_writer(writer: Option<TaskLogWriter>) {
+    WRITER.with(|v| *v.borrow_mut() = writer);
+}
+
+pub fn log_task_record(level: Level, args: Arguments) {
+    CONTEXT.with(|context| {
+        let context = context.borrow();
+        let context = match context.as_ref() {
+            Some(v) => v,
+            None => {
+                log!(level, "{}", args);
+                return;
+            }
+        };
+        let has_writer = WRITER.with(|v| v.borrow().is_some());
+        if !has_writer && !log_enabled!(level) {
+            return;
+        }
+        let message = args.to_string();
+        log!(level, "[session={} task={} {} update={}] {}", context.session_id, context.task_id, context.task_name, context.update, message);
+        if has_writer {
+            let line = make_task_log_line(context, level, message.as_str(), get_unix_time());
+            WRITER.with(|v| if let Some(writer) = v.borrow_mut().as_mut() {
+                writer.write(line.as_str());
+            });
+        }
+    })
+}
+
+fn make_task_log_line(context: &TaskLogContext, level: Level, message: &str, time: f64) -> String {
+    serde_json::to_string(&TaskLogRecord {
+        time,
+        level: level.as_str(),
+        session: context.session_id,
+        task_id: context.task_id,
+        task: context.task_name.as_str(),
+        update: context.update,
+        message,
+    }).unwrap()
+}
+
+fn get_unix_time() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|v| v.as_secs_f64()).unwrap_or(0.0)
+}
+
+fn get_task_log_path(path: &String, session_id: i64) -> String {
+    format!("{}/{}.tasks.json", path, session_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_context(task_id: i64) -> TaskLogContext {
+        TaskLogContext { session_id: 1, task_id, task_name: String::from("Explorer"), update: 42 }
+    }
+
+    #[test]
+    fn make_task_log_line_should_include_context() {
+        assert_eq!(
+            make_task_log_line(&make_context(3), Level::Debug, "Explorer: \"done\"", 1.5),
+            r#"{"time":1.5,"level":"DEBUG","session":1,"task_id":3,"task":"Explorer","update":42,"message":"Explorer: \"done\""}"#
+        );
+    }
+
+    #[test]
+    fn task_log_scope_should_restore_previous_context() {
+        let get_task_id = || CONTEXT.with(|v| v.borrow().as_ref().map(|v| v.task_id));
+        {
+            let _outer = TaskLogScope::enter(make_context(1));
+            {
+                let _inner = TaskLogScope::enter(make_context(2));
+                assert_eq!(get_task_id(), Some(2));
+            }
+            assert_eq!(get_task_id(), Some(1));
+        }
+        assert_eq!(get_task_id(), None);
+    }
+}
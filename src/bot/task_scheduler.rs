@@ -0,0 +1,75 @@
+struct Holder {
+    task_id: i64,
+    priority: i32,
+}
+
+pub struct TaskScheduler {
+    holders: Vec<Holder>,
+}
+
+impl TaskScheduler {
+    pub fn new() -> Self {
+        Self { holders: Vec::new() }
+    }
+
+    pub fn holder(&self) -> Option<i64> {
+        self.holders.last().map(|v| v.task_id)
+    }
+
+    pub fn is_allowed(&self, task_id: i64, priority: i32) -> bool {
+        self.holders.last()
+            .map(|v| v.task_id == task_id || priority > v.priority)
+            .unwrap_or(true)
+    }
+
+    pub fn acquire(&mut self, task_id: i64, priority: i32) {
+        if let Some(holder) = self.holders.last_mut() {
+            if holder.task_id == task_id {
+                holder.priority = priority;
+                return;
+            }
+            debug!("TaskScheduler: task {} preempts task {}", task_id, holder.task_id);
+        }
+        self.holders.retain(|v| v.task_id != task_id);
+        self.holders.push(Holder { task_id, priority });
+    }
+
+    pub fn release(&mut self, task_id: i64) {
+        if self.holder() == Some(task_id) {
+            self.holders.pop();
+            if let Some(holder) = self.holders.last() {
+                debug!("TaskScheduler: task {} resumes after task {}", holder.task_id, task_id);
+            }
+        } else {
+            self.holders.retain(|v| v.task_id != task_id);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.holders.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn higher_priority_task_should_preempt_holder_and_resume_it_after_release() {
+        let mut scheduler = TaskScheduler::new();
+        assert!(scheduler.is_allowed(1, 0));
+        scheduler.acquire(1, 0);
+        assert!(scheduler.is_allowed(1, 0));
+        assert!(!scheduler.is_allowed(2, 0));
+        assert!(scheduler.is_allowed(3, 10));
+        scheduler.acquire(3, 10);
+        assert_eq!(scheduler.holder(), Some(3));
+        assert!(!scheduler.is_allowed(1, 0));
+        scheduler.release(3);
+        assert_eq!(scheduler.holder(), Some(1));
+        assert!(!scheduler.is_allowed(2, 0));
+        scheduler.release(1);
+        assert_eq!(scheduler.holder(), None);
+        assert!(scheduler.is_allowed(2, 0));
+    }
+}
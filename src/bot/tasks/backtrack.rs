@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use serde::Deserialize;
+
+use crate::bot::protocol::{Message, TaskStatus, Update};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::path_finder::{PathFinder, PathFinderConfig, PathFinderParams};
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::Vec2f;
+use crate::bot::world::PlayerWorld;
+
+#[derive(Default, Deserialize)]
+pub struct BacktrackParams {
+    pub steps: Option<usize>,
+    pub profile: Option<String>,
+}
+
+pub struct Backtrack {
+    steps: Option<usize>,
+    profile: Option<String>,
+    waypoints: usize,
+    path_finder: Option<PathFinder>,
+    path_finder_config: PathFinderConfig,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Backtrack {
+    pub fn new(params: BacktrackParams, path_finder_config: PathFinderConfig, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            steps: params.steps,
+            profile: params.profile,
+            waypoints: 0,
+            path_finder: None,
+            path_finder_config,
+            cancel,
+        }
+    }
+}
+
+impl Task for Backtrack {
+    fn name(&self) -> &str {
+        "Backtrack"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        if self.path_finder.is_none() {
+            let waypoints = get_backtrack_waypoints(world.get_backtrack(), world.player_position(), self.steps);
+            if waypoints.is_empty() {
+                task_debug!("Backtrack: no breadcrumbs to retrace");
+                return Some(Message::Done { task: String::from("Backtrack") });
+            }
+            task_debug!("Backtrack: retrace {} breadcrumbs to {:?}", waypoints.len(), waypoints.last());
+            self.waypoints = waypoints.len();
+            self.path_finder = Some(PathFinder::new(
+                PathFinderParams { waypoints: Some(waypoints), profile: self.profile.clone(), destinations: None },
+                self.path_finder_config.clone(),
+                self.cancel.clone(),
+            ));
+        }
+        let path_finder = self.path_finder.as_mut().unwrap();
+        match path_finder.get_next_message(world, scene) {
+            Some(Message::Done { .. }) => {
+                task_debug!("Backtrack: reached the last breadcrumb");
+                Some(Message::Done { task: String::from("Backtrack") })
+            }
+            None if !path_finder.has_destination() => {
+                task_debug!("Backtrack: path to breadcrumb is not found");
+                Some(Message::Error { message: String::from("path to breadcrumb is not found") })
+            }
+            v => v,
+        }
+    }
+
+    fn update(&mut self, _: &PlayerWorld, _: &Update) {}
+
+    fn restore(&mut self, _: &PlayerWorld) {
+        self.path_finder = None;
+    }
+
+    fn is_exclusive(&self) -> bool {
+        true
+    }
+
+    fn status(&self) -> TaskStatus {
+        match self.path_finder.as_ref() {
+            Some(..) => TaskStatus::new("Walk").with_counter("waypoints", self.waypoints),
+            None => TaskStatus::new("Search"),
+        }
+    }
+}
+
+fn get_backtrack_waypoints(backtrack: Vec<Vec2f>, player_position: Vec2f, steps: Option<usize>) -> Vec<Vec2f> {
+    let skip = backtrack.iter().take_while(|v| v.distance(player_position) == 0.0).count();
+    backtrack.into_iter()
+        .skip(skip)
+        .take(steps.unwrap_or(usize::MAX))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_backtrack_waypoints_should_skip_current_position_and_limit_steps() {
+        let backtrack = vec![Vec2f::new(40.0, 0.0), Vec2f::new(20.0, 0.0), Vec2f::new(0.0, 0.0)];
+        assert_eq!(
+            get_backtrack_waypoints(backtrack.clone(), Vec2f::new(40.0, 0.0), None),
+            vec![Vec2f::new(20.0, 0.0), Vec2f::new(0.0, 0.0)]
+        );
+        assert_eq!(
+            get_backtrack_waypoints(backtrack.clone(), Vec2f::new(45.0, 0.0), Some(2)),
+            vec![Vec2f::new(40.0, 0.0), Vec2f::new(20.0, 0.0)]
+        );
+        assert_eq!(get_backtrack_waypoints(Vec::new(), Vec2f::new(45.0, 0.0), None), Vec::<Vec2f>::new());
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bot::player::Item;
+use crate::bot::protocol::{Event, Message, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::task::Task;
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct CrafterConfig {
+    pub recipes_path: String,
+    pub menu_widget: String,
+    pub make_widget: String,
+    pub menu_timeout: f64,
+    pub craft_timeout: f64,
+}
+
+#[derive(Deserialize)]
+pub struct CrafterParams {
+    pub recipe: String,
+    pub count: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Recipe {
+    pub inputs: BTreeMap<String, usize>,
+    pub menu: Vec<String>,
+    pub output: String,
+}
+
+enum Step {
+    OpenMenu,
+    WaitMenu(Instant),
+    Make,
+    WaitProduct(Instant),
+    Failed,
+}
+
+pub struct Crafter {
+    name: String,
+    recipe: Recipe,
+    count: usize,
+    crafted: usize,
+    step: Step,
+    config: CrafterConfig,
+}
+
+impl Crafter {
+    pub fn new(params: CrafterParams, recipe: Recipe, config: CrafterConfig) -> Self {
+        Self {
+            name: params.recipe,
+            recipe,
+            count: params.count,
+            crafted: 0,
+            step: Step::OpenMenu,
+            config,
+        }
+    }
+
+    fn fail(&mut self, message: String) -> Option<Message> {
+        task_debug!("Crafter: {}", message);
+        self.step = Step::Failed;
+        Some(Message::Error { message })
+    }
+}
+
+impl Task for Crafter {
+    fn name(&self) -> &str {
+        "Crafter"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, _: &Scene) -> Option<Message> {
+        if self.crafted >= self.count {
+            task_debug!("Crafter: crafted {} {}", self.crafted, self.name);
+            return Some(Message::Done { task: String::from("Crafter") });
+        }
+        let now = Instant::now();
+        let make_widget_id = world.widgets().values()
+            .find(|widget| widget.kind == self.config.make_widget)
+            .map(|widget| widget.id);
+        match self.step {
+            Step::Failed => return Some(Message::Done { task: String::from("Crafter") }),
+            Step::WaitProduct(started) => {
+                if now - started < Duration::from_secs_f64(self.config.craft_timeout) {
+                    task_debug!("Crafter: wait for {} product", self.recipe.output);
+                    return None;
+                }
+                return self.fail(format!("product {} is not crafted in time", self.recipe.output));
+            }
+            Step::WaitMenu(started) => {
+                if make_widget_id.is_some() {
+                    self.step = Step::Make;
+                } else if now - started < Duration::from_secs_f64(self.config.menu_timeout) {
+                    task_debug!("Crafter: wait for {} widget", self.config.make_widget);
+                    return None;
+                } else {
+                    return self.fail(format!("recipe {} menu is not opened in time", self.name));
+                }
+            }
+            Step::OpenMenu | Step::Make => (),
+        }
+        let inventory = get_inventory_resources(world.player_inventory_items(), world);
+        if let Some((name, available, required)) = find_missing_input(&self.recipe, &inventory) {
+            return self.fail(format!("not enough {}: {} of {}", name, available, required));
+        }
+        match make_widget_id {
+            Some(id) if matches!(self.step, Step::Make) => {
+                task_debug!("Crafter: make {} {}/{}", self.recipe.output, self.crafted + 1, self.count);
+                self.step = Step::WaitProduct(now);
+                Some(Message::WidgetMessage {
+                    sender: id,
+                    kind: String::from("make"),
+                    arguments: vec![Value::from(0i32)],
+                })
+            }
+            _ => {
+                let menu_widget_id = match world.widgets().values().find(|widget| widget.kind == self.config.menu_widget) {
+                    Some(v) => v.id,
+                    None => {
+                        task_debug!("Crafter: {} widget is not found", self.config.menu_widget);
+                        return None;
+                    }
+                };
+                task_debug!("Crafter: open recipe {} menu {:?}", self.name, self.recipe.menu);
+                self.step = Step::WaitMenu(now);
+                Some(Message::WidgetMessage {
+                    sender: menu_widget_id,
+                    kind: String::from("act"),
+                    arguments: self.recipe.menu.iter().map(|v| Value::from(v.clone())).collect(),
+                })
+            }
+        }
+    }
+
+    fn update(&mut self, world: &PlayerWorld, update: &Update) {
+        if !matches!(self.step, Step::WaitProduct(_)) {
+            return;
+        }
+        if let Event::NewWidget { kind, parent, cargs, .. } = &update.event {
+            if kind != "item" || *parent != world.player_inventory_id() {
+                return;
+            }
+            let resource = match cargs.first() {
+                Some(Value::Int { value }) => world.resources().get(value),
+                _ => None,
+            };
+            if resource.map(|v| v.name == self.recipe.output).unwrap_or(false) {
+                self.crafted += 1;
+                self.step = Step::Make;
+                task_debug!("Crafter: got {} product {}/{}", self.recipe.output, self.crafted, self.count);
+            }
+        }
+    }
+
+    fn restore(&mut self, _: &PlayerWorld) {
+        if !matches!(self.step, Step::Failed) {
+            self.step = Step::OpenMenu;
+        }
+    }
+
+    fn status(&self) -> TaskStatus {
+        let state = match self.step {
+            Step::OpenMenu | Step::WaitMenu(_) => "OpenMenu",
+            Step::Make | Step::WaitProduct(_) => "Craft",
+            Step::Failed => "Failed",
+        };
+        TaskStatus::new(state)
+            .with_target(self.name.clone())
+            .with_counter("crafted", self.crafted)
+            .with_counter("count", self.count)
+    }
+}
+
+pub fn read_recipe(path: &String, name: &String) -> Result<Recipe, String> {
+    if name.is_empty() || !name.chars().all(|v| v.is_ascii_alphanumeric() || v == '_' || v == '-') {
+        return Err(format!("Invalid recipe name: {:?}", name));
+    }
+    let recipe_path = format!("{}/{}.recipe.json", path, name);
+    let content = match std::fs::read(&recipe_path) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to read recipe {}: {}", recipe_path, e)),
+    };
+    serde_json::from_slice(&content).map_err(|e| format!("Failed to parse recipe {}: {}", recipe_path, e))
+}
+
+fn get_inventory_resources<'a>(items: &BTreeMap<i32, Item>, world: &'a PlayerWorld) -> BTreeMap<&'a String, usize> {
+    let mut result = BTreeMap::new();
+    for item in items.values() {
+        if let Some(resource) = world.resources().get(&item.resource) {
+            *result.entry(&resource.name).or_insert(0) += 1;
+        }
+    }
+    result
+}
+
+fn find_missing_input<'a>(recipe: &'a Recipe, inventory: &BTreeMap<&String, usize>) -> Option<(&'a String, usize, usize)> {
+    recipe.inputs.iter()
+        .map(|(name, required)| (name, inventory.get(name).cloned().unwrap_or(0), *required))
+        .find(|(_, available, required)| available < required)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_missing_input_should_return_first_input_with_not_enough_items() {
+        let recipe: Recipe = serde_json::from_str(r#"{
+            "inputs": {"gfx/invobjs/bark": 1, "gfx/invobjs/branch": 3},
+            "menu": ["craft", "clogs"],
+            "output": "gfx/invobjs/clogs"
+        }"#).unwrap();
+        let bark = String::from("gfx/invobjs/bark");
+        let branch = String::from("gfx/invobjs/branch");
+        let inventory = vec![(&bark, 1), (&branch, 2)].into_iter().collect();
+        assert_eq!(find_missing_input(&recipe, &inventory), Some((&branch, 2, 3)));
+        let inventory = vec![(&bark, 2), (&branch, 3)].into_iter().collect();
+        assert_eq!(find_missing_input(&recipe, &inventory), None);
+    }
+}
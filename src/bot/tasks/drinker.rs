@@ -1,20 +1,32 @@
 use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
 use std::time::{Duration, Instant};
 
 use serde::Deserialize;
 
 use crate::bot::actions::open_belt::OpenBelt;
+use crate::bot::actions::put_item::PutItem;
+use crate::bot::actions::take_item::TakeItem;
 use crate::bot::actions::use_item::UseItem;
+use crate::bot::cooldowns::Cooldowns;
+use crate::bot::map::{pos_to_map_pos, pos_to_tile_pos, rel_tile_pos_to_pos};
 use crate::bot::player::Item;
-use crate::bot::protocol::{Message, Update};
+use crate::bot::protocol::{Message, Modifier, TaskStatus, Update, Value};
 use crate::bot::scene::Scene;
+use crate::bot::tasks::path_finder::{PathFinder, PathFinderConfig, PathFinderParams};
 use crate::bot::tasks::task::Task;
+use crate::bot::vec2::Vec2i;
 use crate::bot::world::PlayerWorld;
 
 #[derive(Clone, Deserialize)]
 pub struct DrinkerConfig {
     pub open_belt_timeout: f64,
     pub sip_timeout: f64,
+    pub refill_timeout: f64,
+    pub refill_retry_interval: f64,
+    pub refill_max_distance: i32,
+    pub refill_distance: f64,
     pub max_stamina: i32,
     pub stamina_threshold: i32,
     pub liquid_containers: BTreeSet<String>,
@@ -28,35 +40,171 @@ pub struct ContentConfig {
     pub wait_interval: f64,
 }
 
+const SIP_COOLDOWN: &'static str = "Drinker.sip";
+const DRINK_COOLDOWN: &'static str = "Drinker.drink";
+const REFILL_COOLDOWN: &'static str = "Drinker.refill";
+const DRINKER_PRIORITY: i32 = 10;
+
+struct Refill {
+    item_id: i32,
+    inventory_id: i32,
+    position: Vec2i,
+    water_tile_pos: Vec2i,
+    filled: bool,
+    state: RefillState,
+}
+
+enum RefillState {
+    Walk(PathFinder),
+    Take(TakeItem),
+    Fill { started: Instant },
+    Put(PutItem),
+}
+
 pub struct Drinker {
     open_belt: OpenBelt,
     sip: Option<UseItem>,
     wait_interval: Option<Duration>,
-    last_sip: Option<Instant>,
+    refill: Option<Refill>,
+    refilled: bool,
+    cooldowns: Arc<Mutex<Cooldowns>>,
     config: DrinkerConfig,
+    path_finder_config: PathFinderConfig,
+    cancel: Arc<AtomicBool>,
 }
 
 impl Drinker {
-    pub fn new(config: DrinkerConfig) -> Self {
+    pub fn new(config: DrinkerConfig, path_finder_config: PathFinderConfig, cooldowns: Arc<Mutex<Cooldowns>>,
+               cancel: Arc<AtomicBool>) -> Self {
         Self {
             open_belt: OpenBelt::new(Duration::from_secs_f64(config.open_belt_timeout)),
             sip: None,
             wait_interval: None,
-            last_sip: None,
+            refill: None,
+            refilled: false,
+            cooldowns,
             config,
+            path_finder_config,
+            cancel,
+        }
+    }
+
+    fn start_refill(&mut self, world: &PlayerWorld) -> bool {
+        let (inventory_id, item_id, position) = match find_empty_container(world, &self.config.liquid_containers) {
+            Some(v) => v,
+            None => return false,
+        };
+        let water_tile_pos = match find_water_tile(world, self.config.refill_max_distance) {
+            Some(v) => v,
+            None => {
+                task_debug!("Drinker: water is not found within {} tiles", self.config.refill_max_distance);
+                self.start_refill_cooldown();
+                return false;
+            }
+        };
+        task_debug!("Drinker: refill item {} from water at {:?}", item_id, water_tile_pos);
+        self.refill = Some(Refill {
+            item_id,
+            inventory_id,
+            position,
+            water_tile_pos,
+            filled: false,
+            state: RefillState::Walk(PathFinder::new(
+                PathFinderParams { waypoints: Some(vec![rel_tile_pos_to_pos(water_tile_pos.center())]), profile: None, destinations: None },
+                self.path_finder_config.clone(),
+                self.cancel.clone(),
+            )),
+        });
+        true
+    }
+
+    fn start_refill_cooldown(&self) {
+        let retry_interval = Duration::from_secs_f64(self.config.refill_retry_interval);
+        self.cooldowns.lock().unwrap().start(REFILL_COOLDOWN, retry_interval, Instant::now());
+    }
+
+    fn get_next_refill_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        let refill = self.refill.as_mut().unwrap();
+        let timeout = Duration::from_secs_f64(self.config.refill_timeout);
+        let water_pos = rel_tile_pos_to_pos(refill.water_tile_pos.center());
+        loop {
+            match &mut refill.state {
+                RefillState::Walk(path_finder) => {
+                    if water_pos.distance(world.player_position()) > self.config.refill_distance {
+                        match path_finder.get_next_message(world, scene) {
+                            Some(Message::Done { .. }) => (),
+                            None if !path_finder.has_destination() => {
+                                return Some(Message::Error { message: String::from("path to water is not found") });
+                            }
+                            v => return v,
+                        }
+                    }
+                    task_debug!("Drinker: take item {} to refill", refill.item_id);
+                    refill.state = RefillState::Take(TakeItem::new(refill.item_id, timeout));
+                }
+                RefillState::Take(take_item) => {
+                    match take_item.get_next_message(world) {
+                        Some(Message::Done { .. }) => (),
+                        v => return v,
+                    }
+                    task_debug!("Drinker: fill item {:?} at {:?}", take_item.new_item_id(), refill.water_tile_pos);
+                    refill.state = RefillState::Fill { started: Instant::now() };
+                    return Some(Message::WidgetMessage {
+                        sender: world.map_view_id(),
+                        kind: String::from("itemact"),
+                        arguments: vec![
+                            Value::from(Vec2i::zero()),
+                            Value::from(pos_to_map_pos(water_pos)),
+                            Value::from(Modifier::None),
+                        ],
+                    });
+                }
+                RefillState::Fill { started } => {
+                    let filled = world.player_hand().as_ref()
+                        .map(|item| item.content.is_some())
+                        .unwrap_or(false);
+                    if !filled && Instant::now() - *started < timeout {
+                        return None;
+                    }
+                    task_debug!("Drinker: put item back filled={}", filled);
+                    refill.filled = filled;
+                    refill.state = RefillState::Put(PutItem::new(refill.inventory_id, refill.position, timeout));
+                }
+                RefillState::Put(put_item) => return put_item.get_next_message(world),
+            }
         }
     }
 }
 
 impl Task for Drinker {
-    fn name(&self) -> &'static str {
+    fn name(&self) -> &str {
         "Drinker"
     }
 
-    fn get_next_message(&mut self, world: &PlayerWorld, _: &Scene) -> Option<Message> {
+    fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        if self.refill.is_some() {
+            match self.get_next_refill_message(world, scene) {
+                Some(Message::Done { .. }) if self.refill.as_ref().unwrap().filled => {
+                    task_debug!("Drinker: refilled");
+                    self.refilled = true;
+                }
+                Some(Message::Done { .. }) => {
+                    task_debug!("Drinker: refill failed: item is not filled");
+                    self.start_refill_cooldown();
+                }
+                Some(Message::Error { message }) => {
+                    task_debug!("Drinker: refill failed: {}", message);
+                    self.start_refill_cooldown();
+                }
+                v => return v,
+            }
+            self.refill = None;
+        }
         if world.player_stamina() >= self.config.max_stamina {
-            debug!("Drinker: max stamina");
-            self.sip = None;
+            task_debug!("Drinker: max stamina");
+            if self.sip.take().is_some() {
+                self.cooldowns.lock().unwrap().end(SIP_COOLDOWN);
+            }
             return Some(Message::Done { task: String::from("Drinker") });
         }
         let mut reset_sip = false;
@@ -72,25 +220,33 @@ impl Task for Drinker {
             reset_sip = true;
         }
         if reset_sip || world.player_stamina() > self.config.stamina_threshold {
-            debug!("Drinker: reset sip");
+            task_debug!("Drinker: reset sip");
             self.sip = None;
-            self.last_sip = Some(Instant::now());
+            let mut cooldowns = self.cooldowns.lock().unwrap();
+            cooldowns.end(SIP_COOLDOWN);
+            if let Some(wait_interval) = self.wait_interval {
+                cooldowns.start(DRINK_COOLDOWN, wait_interval, Instant::now());
+            }
             return None;
         }
         if self.sip.is_some() {
-            debug!("Drinker: sipping");
+            task_debug!("Drinker: sipping");
             return None;
         }
-        if self.last_sip.map(|v| self.wait_interval.map(|w| Instant::now() - v < w).unwrap_or(false)).unwrap_or(false) {
-            debug!("Drinker: wait");
-            return None;
+        {
+            let now = Instant::now();
+            let cooldowns = self.cooldowns.lock().unwrap();
+            if cooldowns.is_active(SIP_COOLDOWN, now) || cooldowns.is_active(DRINK_COOLDOWN, now) {
+                task_debug!("Drinker: wait {:?}", cooldowns.remaining(SIP_COOLDOWN, now).max(cooldowns.remaining(DRINK_COOLDOWN, now)));
+                return None;
+            }
         }
         match self.open_belt.get_next_message(world) {
             Some(Message::Done { .. }) => (),
-            Some(Message::Error { message }) => debug!("Drinker: {:?}", message),
+            Some(Message::Error { message }) => task_debug!("Drinker: {:?}", message),
             v => return v,
         }
-        debug!("Drinker: try drink");
+        task_debug!("Drinker: try drink");
         let (sip, wait_interval) = {
             find_container_with_content(world, &self.config.liquid_containers, &self.config.contents)
                 .map(|(id, action, wait_interval)| {
@@ -101,50 +257,201 @@ impl Task for Drinker {
                 })
                 .unwrap_or((None, None))
         };
+        if sip.is_some() {
+            let sip_timeout = Duration::from_secs_f64(self.config.sip_timeout);
+            self.cooldowns.lock().unwrap().start(SIP_COOLDOWN, sip_timeout, Instant::now());
+            self.refilled = false;
+        } else if !self.refilled && !self.cooldowns.lock().unwrap().is_active(REFILL_COOLDOWN, Instant::now())
+            && self.start_refill(world) {
+            return self.get_next_refill_message(world, scene);
+        }
         self.sip = sip;
         self.wait_interval = wait_interval;
         self.sip.as_mut().and_then(|v| v.get_next_message())
     }
 
-    fn update(&mut self, _: &PlayerWorld, update: &Update) {
+    fn update(&mut self, world: &PlayerWorld, update: &Update) {
         if let Some(sip) = self.sip.as_mut() {
             sip.update(update);
         }
+        if let Some(refill) = self.refill.as_mut() {
+            match &mut refill.state {
+                RefillState::Take(take_item) => take_item.update(world.game_ui_id(), &update.event),
+                RefillState::Put(put_item) => put_item.update(&update.event),
+                _ => (),
+            }
+        }
     }
 
     fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn priority(&self, world: &PlayerWorld) -> i32 {
+        if self.refill.is_some() || self.sip.is_some() || world.player_stamina() <= self.config.stamina_threshold {
+            DRINKER_PRIORITY
+        } else {
+            0
+        }
+    }
+
+    fn is_exclusive(&self) -> bool {
+        self.refill.is_some()
+    }
+
+    fn status(&self) -> TaskStatus {
+        if let Some(refill) = self.refill.as_ref() {
+            TaskStatus::new("Refill")
+                .with_target(format!("{:?}", refill.water_tile_pos))
+        } else if let Some(sip) = self.sip.as_ref() {
+            TaskStatus::new("Sip")
+                .with_target(format!("{}", sip.item_id()))
+        } else {
+            TaskStatus::new("Wait")
+        }
+    }
 }
 
 fn find_container_with_content<'a>(world: &PlayerWorld, liquid_containers: &BTreeSet<String>, contents: &'a Vec<ContentConfig>) -> Option<(i32, &'a String, Duration)> {
+    let items = world.player_belt_items().into_iter()
+        .flat_map(|belt_items| belt_items.values())
+        .chain(world.player_inventory_items().values())
+        .filter_map(|item| world.resources().get(&item.resource).map(|resource| (item, &resource.name)));
+    select_container_with_content(items, liquid_containers, contents)
+}
+
+fn find_empty_container(world: &PlayerWorld, liquid_containers: &BTreeSet<String>) -> Option<(i32, i32, Vec2i)> {
+    let items = world.player_belt_inventory_id().into_iter()
+        .chain(Some(world.player_inventory_id()))
+        .filter_map(|inventory_id| world.player_inventories().get(&inventory_id).map(|items| (inventory_id, items)))
+        .flat_map(|(inventory_id, items)| items.values().map(move |item| (inventory_id, item)))
+        .filter_map(|(inventory_id, item)| world.resources().get(&item.resource).map(|resource| (inventory_id, item, &resource.name)));
+    select_empty_container(items, liquid_containers)
+}
+
+fn select_empty_container<'a, I>(items: I, liquid_containers: &BTreeSet<String>) -> Option<(i32, i32, Vec2i)>
+    where I: Iterator<Item=(i32, &'a Item, &'a String)> {
+    items
+        .filter(|(_, item, resource)| liquid_containers.contains(*resource) && item.content.is_none())
+        .find_map(|(inventory_id, item, _)| item.position.map(|position| (inventory_id, item.id, position)))
+}
+
+pub fn find_water_tile(world: &PlayerWorld, max_distance: i32) -> Option<Vec2i> {
+    let player_pos = world.player_position();
+    let player_tile_pos = pos_to_tile_pos(player_pos);
+    let mut result: Option<(f64, Vec2i)> = None;
+    for y in -max_distance..=max_distance {
+        for x in -max_distance..=max_distance {
+            let tile_pos = player_tile_pos + Vec2i::new(x, y);
+            let is_water = world.get_tile(tile_pos)
+                .and_then(|tile| world.get_tile_by_id(tile))
+                .map(|tile| world.config().water_tiles.contains_key(&tile.name))
+                .unwrap_or(false);
+            if !is_water {
+                continue;
+            }
+            let distance = rel_tile_pos_to_pos(tile_pos.center()).distance(player_pos);
+            if result.map(|(v, _)| distance < v).unwrap_or(true) {
+                result = Some((distance, tile_pos));
+            }
+        }
+    }
+    result.map(|(_, tile_pos)| tile_pos)
+}
+
+fn select_container_with_content<'a, 'b, I>(items: I, liquid_containers: &BTreeSet<String>,
+                                            contents: &'a Vec<ContentConfig>) -> Option<(i32, &'a String, Duration)>
+    where I: Iterator<Item=(&'b Item, &'b String)> {
+    let containers = items
+        .filter(|(_, resource)| liquid_containers.contains(*resource))
+        .filter_map(|(item, _)| item.content.as_ref().map(|content| (item.id, content)))
+        .collect::<Vec<_>>();
     contents.iter()
         .find_map(|config| {
-            match world.player_belt_items().map(|belt_items| belt_items.iter()) {
-                Some(v) => find_container_with_content_iter(v, world, liquid_containers, config),
-                None => find_container_with_content_iter(std::iter::empty(), world, liquid_containers, config),
+            let mut best: Option<(i32, f32)> = None;
+            for (id, content) in containers.iter() {
+                if !content.name.contains(&config.name) {
+                    continue;
+                }
+                if best.map(|(_, quality)| content.quality > quality).unwrap_or(true) {
+                    best = Some((*id, content.quality));
+                }
             }
+            best.map(|(id, _)| (id, &config.action, Duration::from_secs_f64(config.wait_interval)))
         })
 }
 
-fn find_container_with_content_iter<'a, 'b, I>(iter: I, world: &'b PlayerWorld,
-                                               liquid_containers: &BTreeSet<String>,
-                                               config: &'a ContentConfig) -> Option<(i32, &'a String, Duration)>
-    where I: Iterator<Item=(&'b i32, &'b Item)> {
-    iter.chain(world.player_inventory_items().iter())
-        .find_map(|(_, item)| {
-            item.content.as_ref()
-                .and_then(|v| {
-                    if v.name.contains(&config.name) {
-                        world.resources().get(&item.resource)
-                    } else {
-                        None
-                    }
-                })
-                .and_then(|v| {
-                    if liquid_containers.contains(&v.name) {
-                        Some((item.id, &config.action, Duration::from_secs_f64(config.wait_interval)))
-                    } else {
-                        None
-                    }
-                })
-        })
+#[cfg(test)]
+mod tests {
+    use crate::bot::player::Content;
+
+    use super::*;
+
+    fn make_item(id: i32, content: Option<(&str, f32)>) -> Item {
+        Item {
+            id,
+            resource: 1,
+            content: content.map(|(name, quality)| Content { name: String::from(name), quality }),
+            position: None,
+        }
+    }
+
+    fn make_contents() -> Vec<ContentConfig> {
+        vec![
+            ContentConfig { name: String::from("Milk"), action: String::from("Sip"), wait_interval: 1.0 },
+            ContentConfig { name: String::from("Water"), action: String::from("Drink"), wait_interval: 3.0 },
+        ]
+    }
+
+    fn select(items: &Vec<(Item, String)>) -> Option<i32> {
+        let liquid_containers = vec![String::from("gfx/invobjs/waterskin")].into_iter().collect();
+        let contents = make_contents();
+        select_container_with_content(items.iter().map(|(item, resource)| (item, resource)), &liquid_containers, &contents)
+            .map(|(id, _, _)| id)
+    }
+
+    #[test]
+    fn select_container_should_prefer_highest_quality_content() {
+        let waterskin = String::from("gfx/invobjs/waterskin");
+        let items = vec![
+            (make_item(1, Some(("3.0 l of Water", 10.0))), waterskin.clone()),
+            (make_item(2, Some(("3.0 l of Water", 30.0))), waterskin.clone()),
+            (make_item(3, Some(("3.0 l of Water", 20.0))), waterskin.clone()),
+        ];
+        assert_eq!(select(&items), Some(2));
+    }
+
+    #[test]
+    fn select_container_should_prefer_configured_content_order_over_quality() {
+        let waterskin = String::from("gfx/invobjs/waterskin");
+        let items = vec![
+            (make_item(1, Some(("3.0 l of Water", 50.0))), waterskin.clone()),
+            (make_item(2, Some(("1.0 l of Milk", 5.0))), waterskin.clone()),
+        ];
+        assert_eq!(select(&items), Some(2));
+    }
+
+    #[test]
+    fn select_empty_container_should_skip_filled_and_unknown_containers() {
+        let liquid_containers = vec![String::from("gfx/invobjs/waterskin")].into_iter().collect();
+        let mut empty = make_item(3, None);
+        empty.position = Some(Vec2i::new(1, 2));
+        let items = vec![
+            (5, make_item(1, Some(("3.0 l of Water", 50.0))), String::from("gfx/invobjs/waterskin")),
+            (5, make_item(2, None), String::from("gfx/invobjs/bucket")),
+            (6, empty, String::from("gfx/invobjs/waterskin")),
+        ];
+        assert_eq!(
+            select_empty_container(items.iter().map(|(id, item, resource)| (*id, item, resource)), &liquid_containers),
+            Some((6, 3, Vec2i::new(1, 2)))
+        );
+    }
+
+    #[test]
+    fn select_container_should_skip_unknown_contents_and_containers() {
+        let items = vec![
+            (make_item(1, Some(("3.0 l of Tea", 50.0))), String::from("gfx/invobjs/waterskin")),
+            (make_item(2, Some(("3.0 l of Water", 50.0))), String::from("gfx/invobjs/bucket")),
+            (make_item(3, None), String::from("gfx/invobjs/waterskin")),
+        ];
+        assert_eq!(select(&items), None);
+    }
 }
@@ -1,6 +1,7 @@
 use std::collections::{BTreeMap, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use graphics::{Rectangle, Transformed};
 use graphics::math::identity;
@@ -8,9 +9,11 @@ use graphics::rectangle::square;
 use serde::Deserialize;
 
 use crate::bot::clusterization::{get_cluster_median, make_adjacent_tiles_clusters};
-use crate::bot::map::{pos_to_map_pos, pos_to_rel_tile_pos, pos_to_tile_pos, rel_tile_pos_to_pos, tile_pos_to_pos, TILE_SIZE};
+use crate::bot::exploration_claims::ExplorationClaims;
+use crate::bot::leg_timer::{LegCheck, LegTimeoutConfig, LegTimer};
+use crate::bot::map::{grid_pos_to_tile_pos, GRID_SIZE, pos_to_map_pos, pos_to_rel_tile_pos, pos_to_tile_pos, rel_tile_pos_to_pos, tile_pos_to_grid_pos, tile_pos_to_pos, TILE_SIZE};
 use crate::bot::math::as_score;
-use crate::bot::protocol::{Button, Message, Modifier, Update, Value};
+use crate::bot::protocol::{Button, Message, Modifier, TaskStatus, Update, Value};
 use crate::bot::scene::{CompositeVecNode, Layer, MapTransformArcNode, MapTransformBoxNode, Node, RectangleNode, Scene};
 use crate::bot::tasks::task::Task;
 use crate::bot::vec2::Vec2i;
@@ -21,32 +24,79 @@ pub struct ExplorerConfig {
     pub find_path_max_shortcut_length: f64,
     pub find_path_max_iterations: usize,
     pub max_next_point_shortcut_length: f64,
+    pub frontier_path_cost_weight: f64,
+    pub frontier_water_weight: f64,
+    pub claim_ttl: f64,
+    pub stale_grid_age: Option<f64>,
+    pub leg_timeout: Option<LegTimeoutConfig>,
 }
 
 pub struct Explorer {
     border_tiles: Vec<Vec2i>,
     tile_pos_path: VecDeque<Vec2i>,
     find_path_layer: Option<Layer>,
+    danger_zones_revision: u64,
+    stuck_tiles_revision: u64,
+    path_revision: u64,
+    grids_of_interest_revision: u64,
     border_tiles_layer: Option<Layer>,
+    session_id: i64,
+    claims: Arc<ExplorationClaims>,
+    claimed: Option<(i64, Vec2i)>,
+    leg_timer: Option<LegTimer>,
     config: ExplorerConfig,
     cancel: Arc<AtomicBool>,
 }
 
 impl Explorer {
-    pub fn new(config: ExplorerConfig, cancel: Arc<AtomicBool>) -> Self {
+    pub fn new(session_id: i64, claims: Arc<ExplorationClaims>, config: ExplorerConfig, cancel: Arc<AtomicBool>) -> Self {
         Self {
             border_tiles: Vec::new(),
             tile_pos_path: VecDeque::new(),
             find_path_layer: None,
+            danger_zones_revision: 0,
+            stuck_tiles_revision: 0,
+            path_revision: 0,
+            grids_of_interest_revision: 0,
             border_tiles_layer: None,
+            session_id,
+            claims,
+            claimed: None,
+            leg_timer: config.leg_timeout.clone().map(LegTimer::new),
             config,
             cancel,
         }
     }
+
+    fn claim(&mut self, segment_id: i64, grid_pos: Vec2i) -> bool {
+        let now = Instant::now();
+        let until = now + Duration::from_secs_f64(self.config.claim_ttl);
+        if !self.claims.claim(self.session_id, segment_id, grid_pos, until, now) {
+            return false;
+        }
+        if let Some((claimed_segment_id, claimed_grid_pos)) = self.claimed.replace((segment_id, grid_pos)) {
+            if (claimed_segment_id, claimed_grid_pos) != (segment_id, grid_pos) {
+                self.claims.release(self.session_id, claimed_segment_id, claimed_grid_pos);
+            }
+        }
+        true
+    }
+
+    fn release(&mut self) {
+        if let Some((segment_id, grid_pos)) = self.claimed.take() {
+            self.claims.release(self.session_id, segment_id, grid_pos);
+        }
+    }
+}
+
+impl Drop for Explorer {
+    fn drop(&mut self) {
+        self.release();
+    }
 }
 
 impl Task for Explorer {
-    fn name(&self) -> &'static str {
+    fn name(&self) -> &str {
         "Explorer"
     }
 
@@ -57,17 +107,73 @@ impl Task for Explorer {
                 world.get_tile_id_by_name(name).map(|id| (id, *weight))
             })
             .collect::<BTreeMap<i32, f64>>();
+        if self.grids_of_interest_revision != world.grids_of_interest().revision() {
+            self.grids_of_interest_revision = world.grids_of_interest().revision();
+            task_debug!("Explorer: grids of interest are changed, find new border tiles");
+            self.border_tiles.clear();
+            self.tile_pos_path.clear();
+        }
         if self.border_tiles.is_empty() {
             let border_tiles = world.find_border_tiles(&BTreeMapTileWeights(&water_tiles_cost));
             let clusters = make_adjacent_tiles_clusters(&border_tiles);
-            self.border_tiles = clusters.iter().filter_map(get_cluster_median).collect();
-            self.border_tiles.sort_by_key(|&tile_pos| {
-                -as_score(rel_tile_pos_to_pos(tile_pos.center()).distance(player_pos))
-            });
-            debug!("Explorer: found border tiles: {:?}", self.border_tiles);
+            let player_tile_pos = pos_to_tile_pos(player_pos);
+            let medians: Vec<Vec2i> = clusters.iter().filter_map(get_cluster_median).collect();
+            let border_tiles = world.filter_reachable(player_tile_pos, &medians, &BTreeMapTileWeights(&water_tiles_cost));
+            let path_costs = world.get_path_costs(player_tile_pos, &border_tiles, &BTreeMapTileWeights(&water_tiles_cost));
+            let mut scored: Vec<(Vec2i, f64)> = border_tiles.into_iter()
+                .zip(path_costs.into_iter())
+                .map(|(tile_pos, path_cost)| {
+                    let path_cost = path_cost
+                        .or_else(|| world.estimate_path_cost(player_tile_pos, tile_pos, &BTreeMapTileWeights(&water_tiles_cost)))
+                        .unwrap_or_else(|| player_tile_pos.center().distance(tile_pos.center()));
+                    let water_cost = world.get_crossing_cost(player_tile_pos, tile_pos, &BTreeMapTileWeights(&water_tiles_cost));
+                    (tile_pos, self.config.frontier_path_cost_weight * path_cost + self.config.frontier_water_weight * water_cost)
+                })
+                .collect();
+            scored.sort_by_key(|&(tile_pos, score)| (world.is_grid_of_interest(tile_pos), -as_score(score)));
+            task_debug!("Explorer: found border tiles with scores: {:?}", scored);
+            self.border_tiles = scored.into_iter().map(|(tile_pos, _)| tile_pos).collect();
+            if let Some(stale_grid_age) = self.config.stale_grid_age {
+                let player_grid_pos = tile_pos_to_grid_pos(player_tile_pos);
+                let stale_tiles: Vec<Vec2i> = world.get_stale_grids(Duration::from_secs_f64(stale_grid_age)).into_iter()
+                    .filter(|&grid_pos| grid_pos != player_grid_pos)
+                    .map(|grid_pos| grid_pos_to_tile_pos(grid_pos) + Vec2i::new(GRID_SIZE / 2, GRID_SIZE / 2))
+                    .collect();
+                let mut stale_tiles = world.filter_reachable(player_tile_pos, &stale_tiles, &BTreeMapTileWeights(&water_tiles_cost));
+                stale_tiles.sort_by_key(|tile_pos| -as_score(player_tile_pos.center().distance(tile_pos.center())));
+                task_debug!("Explorer: found stale grid tiles: {:?}", stale_tiles);
+                self.border_tiles.splice(0..0, stale_tiles);
+            }
             self.border_tiles_layer = Some(make_border_tiles_layer(scene.clone(), &self.border_tiles));
         }
-        while let (true, Some(dst_tile_pos)) = (self.tile_pos_path.is_empty(), self.border_tiles.last()) {
+        if self.danger_zones_revision != world.danger_zones().revision() {
+            self.danger_zones_revision = world.danger_zones().revision();
+            if world.is_dangerous_path(player_pos, self.tile_pos_path.iter()) {
+                task_debug!("Explorer: path crosses danger zone, replan");
+                self.tile_pos_path.clear();
+            }
+        }
+        if self.stuck_tiles_revision != world.stuck_tiles().revision() {
+            self.stuck_tiles_revision = world.stuck_tiles().revision();
+            if !self.tile_pos_path.is_empty() {
+                task_debug!("Explorer: stuck tiles are changed, replan");
+                self.tile_pos_path.clear();
+            }
+        }
+        if self.path_revision != world.revision() {
+            self.path_revision = world.revision();
+            if !world.is_valid_path(self.tile_pos_path.iter(), &BTreeMapTileWeights(&water_tiles_cost)) {
+                task_debug!("Explorer: path is not valid for world revision {}, replan", world.revision());
+                self.tile_pos_path.clear();
+            }
+        }
+        while let (true, Some(&dst_tile_pos)) = (self.tile_pos_path.is_empty(), self.border_tiles.last()) {
+            if !self.claim(world.player_segment_id(), world.get_global_grid_pos(dst_tile_pos)) {
+                task_debug!("Explorer: border tile {:?} is claimed by other session", dst_tile_pos);
+                self.border_tiles.pop();
+                self.border_tiles_layer = Some(make_border_tiles_layer(scene.clone(), &self.border_tiles));
+                continue;
+            }
             let find_path_node = make_find_path_node();
             self.find_path_layer = Some(Layer::new(
                 scene.clone(),
@@ -80,7 +186,7 @@ impl Task for Explorer {
             let src_tile_pos = pos_to_tile_pos(player_pos);
             self.tile_pos_path = VecDeque::from(world.find_path(
                 src_tile_pos,
-                *dst_tile_pos,
+                dst_tile_pos,
                 &BTreeMapTileWeights(&water_tiles_cost),
                 self.config.find_path_max_shortcut_length,
                 self.config.find_path_max_iterations,
@@ -92,11 +198,11 @@ impl Task for Explorer {
                 break;
             }
             if !self.tile_pos_path.is_empty() {
-                debug!("Explorer: found path from {:?} to {:?} by tiles {:?}: {:?}",
+                task_debug!("Explorer: found path from {:?} to {:?} by tiles {:?}: {:?}",
                        src_tile_pos, dst_tile_pos, water_tiles_cost, self.tile_pos_path);
                 break;
             }
-            debug!("Explorer: path from {:?} to {:?} is not found by tiles {:?}",
+            task_debug!("Explorer: path from {:?} to {:?} is not found by tiles {:?}",
                    src_tile_pos, dst_tile_pos, water_tiles_cost);
             self.border_tiles.pop();
             self.border_tiles_layer = Some(make_border_tiles_layer(scene.clone(), &self.border_tiles));
@@ -117,12 +223,35 @@ impl Task for Explorer {
         while let Some(&tile_pos) = self.tile_pos_path.front() {
             let distance = tile_pos_to_pos(tile_pos).distance(player_pos);
             if distance > (2.0 * TILE_SIZE).sqrt() && tile_pos != pos_to_tile_pos(player_pos) {
-                debug!("Explorer: distance to the next path point {:?}: {}", tile_pos, distance);
+                task_debug!("Explorer: distance to the next path point {:?}: {}", tile_pos, distance);
                 break;
             }
             self.tile_pos_path.pop_front();
         }
-        if let Some(tile_pos) = self.tile_pos_path.front() {
+        if let (Some(&tile_pos), Some(leg_timer)) = (self.tile_pos_path.front(), self.leg_timer.as_mut()) {
+            let target = rel_tile_pos_to_pos(tile_pos.center());
+            match leg_timer.check(target, player_pos, world.player_speed(), Instant::now()) {
+                LegCheck::InTime => (),
+                LegCheck::Replan => {
+                    task_debug!("Explorer: path point {:?} is not reached in time, replan", tile_pos);
+                    self.tile_pos_path.clear();
+                    return None;
+                }
+                LegCheck::Abort => {
+                    task_debug!("Explorer: path point {:?} is not reached after {} replans, skip border tile {:?}",
+                           tile_pos, leg_timer.replans(), self.border_tiles.last());
+                    leg_timer.reset();
+                    self.tile_pos_path.clear();
+                    self.border_tiles.pop();
+                    self.border_tiles_layer = Some(make_border_tiles_layer(scene.clone(), &self.border_tiles));
+                    return Some(Message::Error { message: format!("path point {:?} is not reached in time", tile_pos) });
+                }
+            }
+        }
+        if let Some(&tile_pos) = self.tile_pos_path.front() {
+            if let Some((segment_id, grid_pos)) = self.claimed {
+                self.claim(segment_id, grid_pos);
+            }
             return Some(Message::WidgetMessage {
                 sender: world.map_view_id(),
                 kind: String::from("click"),
@@ -135,12 +264,27 @@ impl Task for Explorer {
             });
         }
         self.border_tiles.clear();
+        self.release();
         None
     }
 
     fn update(&mut self, _: &PlayerWorld, _: &Update) {}
 
     fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn is_exclusive(&self) -> bool {
+        true
+    }
+
+    fn status(&self) -> TaskStatus {
+        let status = TaskStatus::new(if self.tile_pos_path.is_empty() { "Search" } else { "Walk" })
+            .with_counter("border_tiles", self.border_tiles.len())
+            .with_counter("path_tiles", self.tile_pos_path.len());
+        match self.tile_pos_path.back() {
+            Some(tile_pos) => status.with_target(format!("{:?}", tile_pos)),
+            None => status,
+        }
+    }
 }
 
 fn make_border_tiles_layer(scene: Scene, border_tiles: &Vec<Vec2i>) -> Layer {
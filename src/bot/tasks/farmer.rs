@@ -0,0 +1,393 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::actions::put_item::PutItem;
+use crate::bot::actions::take_item::TakeItem;
+use crate::bot::actions::use_object::UseObject;
+use crate::bot::map::{pos_to_map_pos, pos_to_tile_pos, rel_tile_pos_to_pos};
+use crate::bot::objects::Object;
+use crate::bot::player::Item;
+use crate::bot::protocol::{Message, Modifier, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::path_finder::{PathFinder, PathFinderConfig, PathFinderParams};
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::Vec2i;
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct FarmerConfig {
+    pub action_distance: f64,
+    pub action_timeout: f64,
+    pub crops: BTreeMap<String, CropConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct CropConfig {
+    pub object: String,
+    pub seed: String,
+    pub harvest_action: String,
+}
+
+#[derive(Deserialize)]
+pub struct FarmerParams {
+    pub from: Vec2i,
+    pub to: Vec2i,
+    pub crop: String,
+    pub zone: Option<String>,
+}
+
+struct Seed {
+    inventory_id: i32,
+    position: Vec2i,
+}
+
+enum FarmerState {
+    Walk(PathFinder),
+    Harvest { object_id: i64, use_object: UseObject },
+    WaitHarvest { object_id: i64, started: Instant },
+    TakeSeed { seed: Seed, take_item: TakeItem },
+    Plant { seed: Seed, started: Instant },
+    PutSeed(PutItem),
+}
+
+pub struct Farmer {
+    crop: CropConfig,
+    zone: Option<String>,
+    tiles: VecDeque<Vec2i>,
+    total_tiles: usize,
+    state: Option<FarmerState>,
+    config: FarmerConfig,
+    path_finder_config: PathFinderConfig,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Farmer {
+    pub fn new(params: FarmerParams, config: FarmerConfig, path_finder_config: PathFinderConfig,
+               cancel: Arc<AtomicBool>) -> Result<Self, String> {
+        let crop = match config.crops.get(&params.crop) {
+            Some(v) => v.clone(),
+            None => return Err(format!("Crop {:?} is not configured", params.crop)),
+        };
+        let tiles = make_tiles(params.from, params.to);
+        Ok(Self {
+            crop,
+            zone: params.zone,
+            total_tiles: tiles.len(),
+            tiles,
+            state: None,
+            config,
+            path_finder_config,
+            cancel,
+        })
+    }
+
+    fn next_tile(&mut self) {
+        if let Some(tile_pos) = self.tiles.pop_front() {
+            task_debug!("Farmer: finished tile {:?}, {} tiles left", tile_pos, self.tiles.len());
+        }
+        self.state = None;
+    }
+
+    fn start_tile(&mut self, world: &PlayerWorld, tile_pos: Vec2i) -> bool {
+        let timeout = Duration::from_secs_f64(self.config.action_timeout);
+        let tile_center = rel_tile_pos_to_pos(tile_pos.center());
+        if tile_center.distance(world.player_position()) > self.config.action_distance {
+            task_debug!("Farmer: walk to tile {:?}", tile_pos);
+            self.state = Some(FarmerState::Walk(PathFinder::new(
+                PathFinderParams { waypoints: Some(vec![tile_center]), profile: None, destinations: None },
+                self.path_finder_config.clone(),
+                self.cancel.clone(),
+            )));
+            return true;
+        }
+        if let Some(object) = find_crop(world.iter_objects(), &self.crop.object, tile_pos) {
+            task_debug!("Farmer: harvest crop {} at {:?}", object.id, tile_pos);
+            self.state = Some(FarmerState::Harvest {
+                object_id: object.id,
+                use_object: UseObject::new(object.id, object.position, self.crop.harvest_action.clone(), timeout),
+            });
+            return true;
+        }
+        let items = world.player_inventories().get(&world.player_inventory_id()).into_iter()
+            .flat_map(|items| items.values())
+            .filter_map(|item| world.resources().get(&item.resource).map(|resource| (item, &resource.name)));
+        match select_seed(items, &self.crop.seed) {
+            Some((item_id, position)) => {
+                task_debug!("Farmer: plant seed {} at {:?}", item_id, tile_pos);
+                self.state = Some(FarmerState::TakeSeed {
+                    seed: Seed { inventory_id: world.player_inventory_id(), position },
+                    take_item: TakeItem::new(item_id, timeout),
+                });
+                true
+            }
+            None => {
+                task_debug!("Farmer: no seeds {:?} to plant at {:?}", self.crop.seed, tile_pos);
+                false
+            }
+        }
+    }
+}
+
+impl Task for Farmer {
+    fn name(&self) -> &str {
+        "Farmer"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        let timeout = Duration::from_secs_f64(self.config.action_timeout);
+        loop {
+            let tile_pos = match self.tiles.front() {
+                Some(v) => *v,
+                None => {
+                    task_debug!("Farmer: all tiles are finished");
+                    return Some(Message::Done { task: String::from("Farmer") });
+                }
+            };
+            if self.state.is_none() && !is_in_zone(world, &self.zone, tile_pos) {
+                task_debug!("Farmer: tile {:?} is outside zone {:?}", tile_pos, self.zone);
+                self.next_tile();
+                continue;
+            }
+            if self.state.is_none() && !self.start_tile(world, tile_pos) {
+                self.next_tile();
+                continue;
+            }
+            match self.state.as_mut().unwrap() {
+                FarmerState::Walk(path_finder) => {
+                    match path_finder.get_next_message(world, scene) {
+                        Some(Message::Done { .. }) => self.state = None,
+                        None if !path_finder.has_destination() => {
+                            task_debug!("Farmer: path to tile {:?} is not found", tile_pos);
+                            self.next_tile();
+                        }
+                        v => {
+                            if rel_tile_pos_to_pos(tile_pos.center()).distance(world.player_position()) > self.config.action_distance {
+                                return v;
+                            }
+                            self.state = None;
+                        }
+                    }
+                }
+                FarmerState::Harvest { object_id, use_object } => {
+                    match use_object.get_next_message(world) {
+                        Some(Message::Done { .. }) => {
+                            let object_id = *object_id;
+                            self.state = Some(FarmerState::WaitHarvest { object_id, started: Instant::now() });
+                        }
+                        Some(Message::Error { message }) => {
+                            task_debug!("Farmer: crop {} is not harvested: {}", object_id, message);
+                            self.next_tile();
+                        }
+                        v => return v,
+                    }
+                }
+                FarmerState::WaitHarvest { object_id, started } => {
+                    if world.get_object_by_id(*object_id).is_none() {
+                        task_debug!("Farmer: harvested crop {}", object_id);
+                        self.state = None;
+                    } else if Instant::now() - *started >= timeout {
+                        task_debug!("Farmer: crop {} is not removed after harvest", object_id);
+                        self.next_tile();
+                    } else {
+                        return None;
+                    }
+                }
+                FarmerState::TakeSeed { seed, take_item } => {
+                    match take_item.get_next_message(world) {
+                        Some(Message::Done { .. }) => {
+                            let seed = Seed { inventory_id: seed.inventory_id, position: seed.position };
+                            self.state = Some(FarmerState::Plant { seed, started: Instant::now() });
+                            return Some(Message::WidgetMessage {
+                                sender: world.map_view_id(),
+                                kind: String::from("itemact"),
+                                arguments: vec![
+                                    Value::from(Vec2i::zero()),
+                                    Value::from(pos_to_map_pos(rel_tile_pos_to_pos(tile_pos.center()))),
+                                    Value::from(Modifier::None),
+                                ],
+                            });
+                        }
+                        Some(Message::Error { message }) => {
+                            task_debug!("Farmer: seed is not taken: {}", message);
+                            self.next_tile();
+                        }
+                        v => return v,
+                    }
+                }
+                FarmerState::Plant { seed, started } => {
+                    let planted = find_crop(world.iter_objects(), &self.crop.object, tile_pos).is_some();
+                    if !planted && Instant::now() - *started < timeout {
+                        return None;
+                    }
+                    task_debug!("Farmer: planted={} at {:?}", planted, tile_pos);
+                    if world.player_hand().is_none() {
+                        self.next_tile();
+                    } else {
+                        self.state = Some(FarmerState::PutSeed(PutItem::new(seed.inventory_id, seed.position, timeout)));
+                    }
+                }
+                FarmerState::PutSeed(put_item) => {
+                    match put_item.get_next_message(world) {
+                        Some(Message::Done { .. }) => self.next_tile(),
+                        Some(Message::Error { message }) => {
+                            task_debug!("Farmer: seed is not put back: {}", message);
+                            self.next_tile();
+                        }
+                        v => return v,
+                    }
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, world: &PlayerWorld, update: &Update) {
+        match self.state.as_mut() {
+            Some(FarmerState::Harvest { use_object, .. }) => use_object.update(update),
+            Some(FarmerState::TakeSeed { take_item, .. }) => take_item.update(world.game_ui_id(), &update.event),
+            Some(FarmerState::PutSeed(put_item)) => put_item.update(&update.event),
+            _ => (),
+        }
+    }
+
+    fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn is_exclusive(&self) -> bool {
+        true
+    }
+
+    fn reconfigure(&mut self, params: &[u8]) -> Result<(), String> {
+        let params = serde_json::from_slice::<FarmerParams>(params)
+            .map_err(|e| format!("Failed to parse Farmer params: {}", e))?;
+        let crop = match self.config.crops.get(&params.crop) {
+            Some(v) => v.clone(),
+            None => return Err(format!("Crop {:?} is not configured", params.crop)),
+        };
+        let mut tiles = make_tiles(params.from, params.to);
+        // A seed may be in hand, finish current tile to put it back
+        let current = match self.state {
+            Some(FarmerState::TakeSeed { .. }) | Some(FarmerState::Plant { .. }) | Some(FarmerState::PutSeed(..)) => self.tiles.front().cloned(),
+            _ => None,
+        };
+        match current {
+            Some(tile_pos) => {
+                tiles.retain(|v| *v != tile_pos);
+                tiles.push_front(tile_pos);
+            }
+            None => self.state = None,
+        }
+        task_debug!("Farmer: reconfigured crop={:?} from={:?} to={:?} zone={:?}", params.crop, params.from, params.to, params.zone);
+        self.crop = crop;
+        self.zone = params.zone;
+        self.total_tiles = tiles.len();
+        self.tiles = tiles;
+        Ok(())
+    }
+
+    fn status(&self) -> TaskStatus {
+        let state = match self.state {
+            Some(FarmerState::Walk(..)) => "Walk",
+            Some(FarmerState::Harvest { .. }) | Some(FarmerState::WaitHarvest { .. }) => "Harvest",
+            Some(FarmerState::TakeSeed { .. }) | Some(FarmerState::Plant { .. }) | Some(FarmerState::PutSeed(..)) => "Plant",
+            None => "Search",
+        };
+        let status = TaskStatus::new(state)
+            .with_percent(self.total_tiles - self.tiles.len(), self.total_tiles)
+            .with_counter("tiles_left", self.tiles.len());
+        match self.tiles.front() {
+            Some(tile_pos) => status.with_target(format!("{:?}", tile_pos)),
+            None => status,
+        }
+    }
+}
+
+fn is_in_zone(world: &PlayerWorld, zone: &Option<String>, tile_pos: Vec2i) -> bool {
+    match zone {
+        Some(name) => world.zone_at(tile_pos).as_ref() == Some(name),
+        None => true,
+    }
+}
+
+fn make_tiles(from: Vec2i, to: Vec2i) -> VecDeque<Vec2i> {
+    let mut result = VecDeque::new();
+    for y in from.y().min(to.y())..=from.y().max(to.y()) {
+        for x in from.x().min(to.x())..=from.x().max(to.x()) {
+            result.push_back(Vec2i::new(x, y));
+        }
+    }
+    result
+}
+
+fn find_crop<'a>(mut objects: impl Iterator<Item=&'a Object>, name: &String, tile_pos: Vec2i) -> Option<&'a Object> {
+    objects.find(|v| v.name.as_ref() == Some(name) && pos_to_tile_pos(v.position) == tile_pos)
+}
+
+fn select_seed<'a, I>(items: I, seed: &String) -> Option<(i32, Vec2i)>
+    where I: Iterator<Item=(&'a Item, &'a String)> {
+    items
+        .filter(|(_, resource)| *resource == seed)
+        .find_map(|(item, _)| item.position.map(|position| (item.id, position)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::vec2::Vec2f;
+
+    use super::*;
+
+    #[test]
+    fn make_tiles_should_iterate_area_row_by_row() {
+        assert_eq!(
+            Vec::from(make_tiles(Vec2i::new(2, 1), Vec2i::new(1, 2))),
+            vec![Vec2i::new(1, 1), Vec2i::new(2, 1), Vec2i::new(1, 2), Vec2i::new(2, 2)]
+        );
+    }
+
+    #[test]
+    fn find_crop_should_match_object_name_and_tile() {
+        let carrot = String::from("gfx/terobjs/plants/carrot");
+        let objects = vec![
+            Object { id: 1, position: Vec2f::new(12.0, 12.0), angle: 0.0, name: Some(String::from("gfx/terobjs/plants/wheat")) },
+            Object { id: 2, position: Vec2f::new(34.0, 12.0), angle: 0.0, name: Some(carrot.clone()) },
+            Object { id: 3, position: Vec2f::new(12.0, 12.0), angle: 0.0, name: Some(carrot.clone()) },
+        ];
+        assert_eq!(find_crop(objects.iter(), &carrot, pos_to_tile_pos(Vec2f::new(12.0, 12.0))).map(|v| v.id), Some(3));
+        assert_eq!(find_crop(objects.iter(), &carrot, pos_to_tile_pos(Vec2f::new(60.0, 60.0))).map(|v| v.id), None);
+    }
+
+    #[test]
+    fn reconfigure_should_replace_field_and_crop() {
+        let crop = CropConfig {
+            object: String::from("gfx/terobjs/plants/carrot"),
+            seed: String::from("gfx/invobjs/seed-carrot"),
+            harvest_action: String::from("Harvest"),
+        };
+        let config = FarmerConfig {
+            action_distance: 5.0,
+            action_timeout: 1.0,
+            crops: vec![(String::from("carrot"), crop)].into_iter().collect(),
+        };
+        let path_finder_config = PathFinderConfig {
+            find_path_max_shortcut_length: 25.0,
+            find_path_max_iterations: 1000,
+            find_path_max_total_iterations: None,
+            max_next_point_shortcut_length: 25.0,
+            leg_timeout: None,
+            look_ahead_distance: None,
+        };
+        let params = FarmerParams { from: Vec2i::new(0, 0), to: Vec2i::new(1, 1), crop: String::from("carrot"), zone: None };
+        let mut farmer = Farmer::new(params, config, path_finder_config, Arc::new(AtomicBool::new(false))).unwrap();
+        assert_eq!(farmer.tiles.len(), 4);
+        assert_eq!(farmer.reconfigure(br#"{"from": {"x": 2, "y": 3}, "to": {"x": 4, "y": 3}, "crop": "carrot"}"#), Ok(()));
+        assert_eq!(Vec::from(farmer.tiles.clone()), vec![Vec2i::new(2, 3), Vec2i::new(3, 3), Vec2i::new(4, 3)]);
+        assert_eq!(farmer.total_tiles, 3);
+        assert_eq!(
+            farmer.reconfigure(br#"{"from": {"x": 0, "y": 0}, "to": {"x": 1, "y": 1}, "crop": "beet"}"#),
+            Err(String::from("Crop \"beet\" is not configured"))
+        );
+        assert_eq!(farmer.tiles.len(), 3);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use serde::Deserialize;
+
+use crate::bot::map::{pos_to_map_pos, rel_tile_pos_to_pos};
+use crate::bot::math::as_score;
+use crate::bot::objects::Object;
+use crate::bot::protocol::{Button, Message, Modifier, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::drinker::find_water_tile;
+use crate::bot::tasks::path_finder::{PathFinder, PathFinderConfig, PathFinderParams};
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct FleerConfig {
+    pub hostile_objects: BTreeSet<String>,
+    pub player_object: String,
+    pub detect_distance: f64,
+    pub safe_distance: f64,
+    pub arrival_distance: f64,
+    pub water_max_distance: i32,
+    pub run_away_distance: f64,
+}
+
+#[derive(Default, Deserialize)]
+pub struct FleerParams {
+    pub safe_position: Option<Vec2f>,
+    pub whitelist: Option<BTreeSet<i64>>,
+}
+
+const FLEER_PRIORITY: i32 = 20;
+
+enum FleeState {
+    Walk(PathFinder),
+    RunAway(Vec2f),
+    Wait,
+}
+
+struct Flee {
+    object_id: i64,
+    state: FleeState,
+}
+
+pub struct Fleer {
+    safe_position: Option<Vec2f>,
+    whitelist: BTreeSet<i64>,
+    flee: Option<Flee>,
+    config: FleerConfig,
+    path_finder_config: PathFinderConfig,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Fleer {
+    pub fn new(params: FleerParams, config: FleerConfig, path_finder_config: PathFinderConfig,
+               cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            safe_position: params.safe_position,
+            whitelist: params.whitelist.unwrap_or_default(),
+            flee: None,
+            config,
+            path_finder_config,
+            cancel,
+        }
+    }
+
+    fn find_threat<'a>(&self, world: &'a PlayerWorld, max_distance: f64) -> Option<&'a Object> {
+        find_threat(world.iter_objects(), world.player_object_id(), world.player_position(), max_distance,
+                    &self.config, &self.whitelist)
+    }
+
+    fn start_flee(&self, world: &PlayerWorld, threat: &Object) -> FleeState {
+        let player_pos = world.player_position();
+        let destination = self.safe_position
+            .or_else(|| {
+                find_water_tile(world, self.config.water_max_distance)
+                    .map(|tile_pos| rel_tile_pos_to_pos(tile_pos.center()))
+            })
+            .filter(|position| position.distance(player_pos) > self.config.arrival_distance);
+        match destination {
+            Some(position) => {
+                task_debug!("Fleer: flee from {} to {:?}", threat.id, position);
+                FleeState::Walk(PathFinder::new(
+                    PathFinderParams { waypoints: Some(vec![position]), profile: None, destinations: None },
+                    self.path_finder_config.clone(),
+                    self.cancel.clone(),
+                ))
+            }
+            None => get_run_away_state(player_pos, threat, self.config.run_away_distance),
+        }
+    }
+}
+
+impl Task for Fleer {
+    fn name(&self) -> &str {
+        "Fleer"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        let flee = match self.flee.as_mut() {
+            Some(v) => v,
+            None => {
+                let threat = self.find_threat(world, self.config.detect_distance)?;
+                let distance = threat.position.distance(world.player_position());
+                task_debug!("Fleer: threat {} {:?} at distance {}", threat.id, threat.name, distance);
+                self.flee = Some(Flee { object_id: threat.id, state: self.start_flee(world, threat) });
+                return Some(Message::Alert {
+                    object_id: threat.id,
+                    name: threat.name.clone().unwrap_or_default(),
+                    distance,
+                });
+            }
+        };
+        let threat = match find_threat(world.iter_objects(), world.player_object_id(), world.player_position(),
+                                       self.config.safe_distance, &self.config, &self.whitelist) {
+            Some(v) => v,
+            None => {
+                task_debug!("Fleer: threat {} is gone", flee.object_id);
+                self.flee = None;
+                return None;
+            }
+        };
+        flee.object_id = threat.id;
+        loop {
+            match &mut flee.state {
+                FleeState::Walk(path_finder) => {
+                    match path_finder.get_next_message(world, scene) {
+                        Some(Message::Done { .. }) => {
+                            task_debug!("Fleer: reached safe position");
+                            flee.state = FleeState::Wait;
+                        }
+                        None if !path_finder.has_destination() => {
+                            task_debug!("Fleer: path to safe position is not found");
+                            flee.state = get_run_away_state(world.player_position(), threat, self.config.run_away_distance);
+                        }
+                        v => return v,
+                    }
+                }
+                FleeState::RunAway(position) => {
+                    let position = *position;
+                    flee.state = FleeState::Wait;
+                    return Some(Message::WidgetMessage {
+                        sender: world.map_view_id(),
+                        kind: String::from("click"),
+                        arguments: vec![
+                            Value::from(Vec2i::zero()),
+                            Value::from(pos_to_map_pos(position)),
+                            Value::from(Button::LeftClick),
+                            Value::from(Modifier::None),
+                        ],
+                    });
+                }
+                FleeState::Wait => {
+                    if threat.position.distance(world.player_position()) > self.config.detect_distance {
+                        return None;
+                    }
+                    task_debug!("Fleer: threat {} is still close", threat.id);
+                    flee.state = get_run_away_state(world.player_position(), threat, self.config.run_away_distance);
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, _: &PlayerWorld, _: &Update) {}
+
+    fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn priority(&self, world: &PlayerWorld) -> i32 {
+        if self.flee.is_some() || self.find_threat(world, self.config.detect_distance).is_some() {
+            FLEER_PRIORITY
+        } else {
+            0
+        }
+    }
+
+    fn is_exclusive(&self) -> bool {
+        self.flee.is_some()
+    }
+
+    fn status(&self) -> TaskStatus {
+        match self.flee.as_ref() {
+            Some(flee) => {
+                let state = match flee.state {
+                    FleeState::Walk(..) => "Walk",
+                    FleeState::RunAway(..) => "RunAway",
+                    FleeState::Wait => "Wait",
+                };
+                TaskStatus::new(state).with_target(format!("{}", flee.object_id))
+            }
+            None => TaskStatus::new("Watch"),
+        }
+    }
+}
+
+fn find_threat<'a>(objects: impl Iterator<Item=&'a Object>, player_object_id: i64, player_pos: Vec2f,
+                   max_distance: f64, config: &FleerConfig, whitelist: &BTreeSet<i64>) -> Option<&'a Object> {
+    objects
+        .filter(|v| v.id != player_object_id && v.position.distance(player_pos) <= max_distance)
+        .filter(|v| {
+            v.name.as_ref()
+                .map(|name| {
+                    config.hostile_objects.contains(name)
+                        || (*name == config.player_object && !whitelist.contains(&v.id))
+                })
+                .unwrap_or(false)
+        })
+        .min_by_key(|v| as_score(v.position.distance(player_pos)))
+}
+
+fn get_run_away_state(player_pos: Vec2f, threat: &Object, distance: f64) -> FleeState {
+    let position = get_run_away_position(player_pos, threat.position, distance);
+    task_debug!("Fleer: run away from {} to {:?}", threat.id, position);
+    FleeState::RunAway(position)
+}
+
+fn get_run_away_position(player_pos: Vec2f, threat_pos: Vec2f, distance: f64) -> Vec2f {
+    let direction = player_pos - threat_pos;
+    let norm = direction.norm();
+    if norm == 0.0 {
+        return player_pos + Vec2f::new(distance, 0.0);
+    }
+    player_pos + direction * (distance / norm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_threat_should_return_closest_hostile_object_except_whitelisted_players() {
+        let config = FleerConfig {
+            hostile_objects: vec![String::from("gfx/kritter/bear/bear")].into_iter().collect(),
+            player_object: String::from("gfx/borka/body"),
+            detect_distance: 100.0,
+            safe_distance: 200.0,
+            arrival_distance: 22.0,
+            water_max_distance: 10,
+            run_away_distance: 100.0,
+        };
+        let objects = vec![
+            Object { id: 1, position: Vec2f::new(0.0, 0.0), angle: 0.0, name: Some(String::from("gfx/borka/body")) },
+            Object { id: 2, position: Vec2f::new(10.0, 0.0), angle: 0.0, name: Some(String::from("gfx/borka/body")) },
+            Object { id: 3, position: Vec2f::new(50.0, 0.0), angle: 0.0, name: Some(String::from("gfx/kritter/bear/bear")) },
+            Object { id: 4, position: Vec2f::new(30.0, 0.0), angle: 0.0, name: Some(String::from("gfx/borka/body")) },
+            Object { id: 5, position: Vec2f::new(5.0, 0.0), angle: 0.0, name: Some(String::from("gfx/terobjs/trees/oak")) },
+        ];
+        let whitelist: BTreeSet<i64> = vec![2].into_iter().collect();
+        let player_pos = Vec2f::new(0.0, 0.0);
+        assert_eq!(find_threat(objects.iter(), 1, player_pos, 100.0, &config, &whitelist).map(|v| v.id), Some(4));
+        assert_eq!(find_threat(objects.iter(), 1, player_pos, 20.0, &config, &whitelist).map(|v| v.id), None);
+        assert_eq!(find_threat(objects.iter(), 1, player_pos, 100.0, &config, &BTreeSet::new()).map(|v| v.id), Some(2));
+    }
+
+    #[test]
+    fn get_run_away_position_should_move_away_from_threat() {
+        assert_eq!(
+            get_run_away_position(Vec2f::new(10.0, 0.0), Vec2f::new(0.0, 0.0), 100.0),
+            Vec2f::new(110.0, 0.0)
+        );
+        assert_eq!(
+            get_run_away_position(Vec2f::new(0.0, 0.0), Vec2f::new(0.0, 0.0), 100.0),
+            Vec2f::new(100.0, 0.0)
+        );
+    }
+}
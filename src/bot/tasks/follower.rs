@@ -0,0 +1,182 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use serde::Deserialize;
+
+use crate::bot::map::pos_to_map_pos;
+use crate::bot::math::as_score;
+use crate::bot::protocol::{Button, Event, Message, Modifier, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::path_finder::{PathFinder, PathFinderConfig, PathFinderParams};
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct FollowerConfig {
+    pub find_path_max_shortcut_length: f64,
+    pub find_path_max_iterations: usize,
+    pub max_next_point_shortcut_length: f64,
+    pub follow_distance: f64,
+    pub replan_distance: f64,
+}
+
+#[derive(Default, Deserialize)]
+pub struct FollowerParams {
+    pub object_id: Option<i64>,
+    pub name: Option<String>,
+}
+
+pub struct Follower {
+    object_id: Option<i64>,
+    name: Option<String>,
+    target_position: Option<Vec2f>,
+    target_removed: bool,
+    stopped: bool,
+    path_finder: Option<PathFinder>,
+    config: FollowerConfig,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Follower {
+    pub fn new(params: FollowerParams, config: FollowerConfig, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            object_id: params.object_id,
+            name: params.name,
+            target_position: None,
+            target_removed: false,
+            stopped: false,
+            path_finder: None,
+            config,
+            cancel,
+        }
+    }
+
+    fn stop(&mut self, world: &PlayerWorld) -> Message {
+        self.path_finder = None;
+        self.target_position = None;
+        self.stopped = true;
+        Message::WidgetMessage {
+            sender: world.map_view_id(),
+            kind: String::from("click"),
+            arguments: vec![
+                Value::from(Vec2i::zero()),
+                Value::from(pos_to_map_pos(world.player_position())),
+                Value::from(Button::LeftClick),
+                Value::from(Modifier::None),
+            ],
+        }
+    }
+}
+
+impl Task for Follower {
+    fn name(&self) -> &str {
+        "Follower"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        if self.stopped {
+            return Some(Message::Done { task: String::from("Follower") });
+        }
+        if self.target_removed {
+            task_debug!("Follower: target {:?} disappeared", self.object_id);
+            return Some(self.stop(world));
+        }
+        let player_pos = world.player_position();
+        if self.object_id.is_none() {
+            if let Some(name) = self.name.as_ref() {
+                self.object_id = world.iter_objects()
+                    .filter(|v| v.id != world.player_object_id() && v.name.as_ref() == Some(name))
+                    .min_by_key(|v| as_score(v.position.distance(player_pos)))
+                    .map(|v| v.id);
+                task_debug!("Follower: found target {:?} by name {:?}", self.object_id, name);
+            }
+        }
+        let target = match self.object_id.and_then(|id| world.get_object_by_id(id)) {
+            Some(v) => v,
+            None => {
+                if self.target_position.is_some() {
+                    task_debug!("Follower: target {:?} is lost", self.object_id);
+                    return Some(self.stop(world));
+                }
+                return None;
+            }
+        };
+        if target.position.distance(player_pos) <= self.config.follow_distance {
+            self.path_finder = None;
+            self.target_position = Some(target.position);
+            return None;
+        }
+        if let Some(position) = self.target_position {
+            if self.path_finder.is_some() && position.distance(target.position) > self.config.replan_distance {
+                task_debug!("Follower: target moved from {:?} to {:?}, replan", position, target.position);
+                self.path_finder = None;
+            }
+        }
+        if self.path_finder.is_none() {
+            self.target_position = Some(target.position);
+        }
+        let config = &self.config;
+        let cancel = &self.cancel;
+        let target_position = target.position;
+        let path_finder = self.path_finder.get_or_insert_with(|| PathFinder::new(
+            PathFinderParams { waypoints: Some(vec![target_position]), profile: None, destinations: None },
+            PathFinderConfig {
+                find_path_max_shortcut_length: config.find_path_max_shortcut_length,
+                find_path_max_iterations: config.find_path_max_iterations,
+                find_path_max_total_iterations: None,
+                max_next_point_shortcut_length: config.max_next_point_shortcut_length,
+                leg_timeout: None,
+                look_ahead_distance: None,
+            },
+            cancel.clone(),
+        ));
+        match path_finder.get_next_message(world, scene) {
+            Some(Message::Done { .. }) => {
+                task_debug!("Follower: reached target position {:?} but target is still too far", target_position);
+                self.path_finder = None;
+                None
+            }
+            None if !path_finder.has_destination() => {
+                task_debug!("Follower: path to {:?} is not found", target_position);
+                self.path_finder = None;
+                None
+            }
+            v => v,
+        }
+    }
+
+    fn update(&mut self, _: &PlayerWorld, update: &Update) {
+        match &update.event {
+            Event::GobMove { id, position, .. } if Some(*id) == self.object_id => {
+                if let Some(target_position) = self.target_position {
+                    if target_position.distance(*position) > self.config.replan_distance {
+                        self.path_finder = None;
+                    }
+                }
+            }
+            Event::GobRemove { id } if Some(*id) == self.object_id => {
+                self.target_removed = true;
+            }
+            _ => (),
+        }
+    }
+
+    fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn is_exclusive(&self) -> bool {
+        true
+    }
+
+    fn status(&self) -> TaskStatus {
+        let status = match self.path_finder.as_ref() {
+            Some(path_finder) => path_finder.status(),
+            None => TaskStatus::new("Follow"),
+        };
+        match (self.object_id, self.name.as_ref()) {
+            (Some(object_id), _) => status.with_target(format!("{}", object_id)),
+            (None, Some(name)) => status.with_target(name.clone()),
+            (None, None) => status,
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::leg_timer::LegTimeoutConfig;
+use crate::bot::map::{pos_to_map_pos, pos_to_tile_pos};
+use crate::bot::objects::{Object, ObjectCluster};
+use crate::bot::protocol::{Button, Message, Modifier, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::path_finder::{PathFinder, PathFinderConfig, PathFinderParams};
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct ForagerConfig {
+    pub names: BTreeSet<String>,
+    pub max_distance: f64,
+    pub pick_distance: f64,
+    pub pick_timeout: f64,
+    pub find_path_max_shortcut_length: f64,
+    pub find_path_max_iterations: usize,
+    pub max_next_point_shortcut_length: f64,
+    pub cluster_distance: Option<f64>,
+    pub leg_timeout: Option<LegTimeoutConfig>,
+    pub look_ahead_distance: Option<f64>,
+}
+
+#[derive(Default, Deserialize)]
+pub struct ForagerParams {
+    pub zone: Option<String>,
+    pub profile: Option<String>,
+    pub names: Option<BTreeSet<String>>,
+}
+
+struct Pick {
+    object_id: i64,
+    inventory_items: BTreeSet<i32>,
+    started: Instant,
+}
+
+pub struct Forager {
+    names: BTreeSet<String>,
+    zone: Option<String>,
+    profile: Option<String>,
+    target: Option<i64>,
+    cluster: Option<BTreeSet<i64>>,
+    path_finder: Option<PathFinder>,
+    pick: Option<Pick>,
+    skipped: HashSet<i64>,
+    inventory_full: bool,
+    config: ForagerConfig,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Forager {
+    pub fn new(params: ForagerParams, config: ForagerConfig, cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            names: params.names.unwrap_or_else(|| config.names.clone()),
+            zone: params.zone,
+            profile: params.profile,
+            target: None,
+            cluster: None,
+            path_finder: None,
+            pick: None,
+            skipped: HashSet::new(),
+            inventory_full: false,
+            config,
+            cancel,
+        }
+    }
+
+    fn skip_target(&mut self) {
+        if let Some(object_id) = self.target.take() {
+            task_debug!("Forager: skip object {}", object_id);
+            self.skipped.insert(object_id);
+        }
+        self.path_finder = None;
+    }
+}
+
+impl Task for Forager {
+    fn name(&self) -> &str {
+        "Forager"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        if let Some(pick) = self.pick.as_ref() {
+            let inventory_items: BTreeSet<i32> = world.player_inventory_items().keys().cloned().collect();
+            if inventory_items != pick.inventory_items {
+                task_debug!("Forager: picked object {}", pick.object_id);
+                self.pick = None;
+                self.target = None;
+                self.path_finder = None;
+            } else if Instant::now() - pick.started >= Duration::from_secs_f64(self.config.pick_timeout) {
+                task_debug!("Forager: inventory is not changed after picking object {}", pick.object_id);
+                self.pick = None;
+                self.skip_target();
+            } else {
+                task_debug!("Forager: wait for inventory change after picking object {}", pick.object_id);
+                return None;
+            }
+        }
+        if self.inventory_full {
+            task_debug!("Forager: inventory is full");
+            return Some(Message::Done { task: String::from("Forager") });
+        }
+        let player_position = world.player_position();
+        let mut skipped = self.skipped.clone();
+        if let Some(name) = self.zone.as_ref() {
+            let zone_tiles: BTreeSet<Vec2i> = world.iter_zone_tiles(name).collect();
+            skipped.extend(world.iter_objects()
+                .filter(|v| !zone_tiles.contains(&pos_to_tile_pos(v.position)))
+                .map(|v| v.id));
+        }
+        if let Some(cluster_distance) = self.config.cluster_distance {
+            let has_objects = self.cluster.as_ref()
+                .map(|cluster| cluster.iter().any(|id| !skipped.contains(id) && world.get_object_by_id(*id).is_some()))
+                .unwrap_or(false);
+            if !has_objects {
+                self.cluster = select_cluster(world.find_object_clusters(&self.names, cluster_distance),
+                                              player_position, &skipped, self.config.max_distance);
+                task_debug!("Forager: new cluster {:?}", self.cluster);
+            }
+        }
+        let cluster = &self.cluster;
+        let objects = world.iter_objects()
+            .filter(|v| cluster.as_ref().map(|cluster| cluster.contains(&v.id)).unwrap_or(true));
+        let object = match select_object(objects, player_position, &self.names,
+                                         &skipped, self.config.max_distance) {
+            Some(v) => v,
+            None => {
+                task_debug!("Forager: no objects to pick");
+                return Some(Message::Done { task: String::from("Forager") });
+            }
+        };
+        if self.target != Some(object.id) {
+            task_debug!("Forager: new target {:?} {} at {:?}", object.name, object.id, object.position);
+            self.target = Some(object.id);
+            self.path_finder = None;
+        }
+        if object.position.distance(player_position) <= self.config.pick_distance {
+            if !world.player_inventory_can_fit(Vec2i::new(1, 1)) {
+                task_debug!("Forager: no space in inventory to pick object {}", object.id);
+                return Some(Message::Done { task: String::from("Forager") });
+            }
+            task_debug!("Forager: pick object {} with {:?} free inventory slots", object.id, world.player_inventory_free_slots());
+            self.path_finder = None;
+            self.pick = Some(Pick {
+                object_id: object.id,
+                inventory_items: world.player_inventory_items().keys().cloned().collect(),
+                started: Instant::now(),
+            });
+            return Some(Message::WidgetMessage {
+                sender: world.map_view_id(),
+                kind: String::from("click"),
+                arguments: vec![
+                    Value::from(Vec2i::zero()),
+                    Value::from(pos_to_map_pos(object.position)),
+                    Value::from(Button::RightClick),
+                    Value::from(Modifier::None),
+                    Value::from(0i32),
+                    Value::from(object.id as i32),
+                    Value::from(pos_to_map_pos(object.position)),
+                    Value::from(0i32),
+                    Value::from(0i32),
+                ],
+            });
+        }
+        let config = &self.config;
+        let cancel = &self.cancel;
+        let profile = &self.profile;
+        let object_position = object.position;
+        let path_finder = self.path_finder.get_or_insert_with(|| PathFinder::new(
+            PathFinderParams { waypoints: Some(vec![object_position]), profile: profile.clone(), destinations: None },
+            PathFinderConfig {
+                find_path_max_shortcut_length: config.find_path_max_shortcut_length,
+                find_path_max_iterations: config.find_path_max_iterations,
+                find_path_max_total_iterations: None,
+                max_next_point_shortcut_length: config.max_next_point_shortcut_length,
+                leg_timeout: config.leg_timeout.clone(),
+                look_ahead_distance: config.look_ahead_distance,
+            },
+            cancel.clone(),
+        ));
+        match path_finder.get_next_message(world, scene) {
+            Some(Message::Done { .. }) => {
+                task_debug!("Forager: reached destination but object is still too far");
+                self.skip_target();
+                None
+            }
+            None if !path_finder.has_destination() => {
+                task_debug!("Forager: path to object is not found");
+                self.skip_target();
+                None
+            }
+            v => v,
+        }
+    }
+
+    fn update(&mut self, _: &PlayerWorld, _: &Update) {}
+
+    fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn on_inventory_full(&mut self, _: &PlayerWorld) {
+        self.inventory_full = true;
+    }
+
+    fn is_exclusive(&self) -> bool {
+        true
+    }
+
+    fn reconfigure(&mut self, params: &[u8]) -> Result<(), String> {
+        let params = if params.is_empty() {
+            ForagerParams::default()
+        } else {
+            serde_json::from_slice::<ForagerParams>(params)
+                .map_err(|e| format!("Failed to parse Forager params: {}", e))?
+        };
+        self.names = params.names.unwrap_or_else(|| self.config.names.clone());
+        self.zone = params.zone;
+        self.profile = params.profile;
+        self.target = None;
+        self.cluster = None;
+        self.path_finder = None;
+        self.skipped.clear();
+        task_debug!("Forager: reconfigured names={:?} zone={:?} profile={:?}", self.names, self.zone, self.profile);
+        Ok(())
+    }
+
+    fn status(&self) -> TaskStatus {
+        let state = if self.pick.is_some() {
+            "Pick"
+        } else if self.path_finder.is_some() {
+            "Walk"
+        } else {
+            "Search"
+        };
+        let status = TaskStatus::new(state)
+            .with_counter("skipped", self.skipped.len())
+            .with_counter("cluster", self.cluster.as_ref().map(|v| v.len()).unwrap_or(0));
+        match self.target {
+            Some(object_id) => status.with_target(format!("{}", object_id)),
+            None => status,
+        }
+    }
+}
+
+fn select_object<'a>(objects: impl Iterator<Item=&'a Object>, player_position: Vec2f, names: &BTreeSet<String>,
+                     skipped: &HashSet<i64>, max_distance: f64) -> Option<&'a Object> {
+    objects
+        .filter(|v| v.name.as_ref().map(|name| names.contains(name)).unwrap_or(false))
+        .filter(|v| !skipped.contains(&v.id))
+        .map(|v| (v.position.distance(player_position), v))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by(|(lhs, _), (rhs, _)| lhs.partial_cmp(rhs).unwrap())
+        .map(|(_, v)| v)
+}
+
+fn select_cluster(clusters: Vec<ObjectCluster>, player_position: Vec2f, skipped: &HashSet<i64>,
+                  max_distance: f64) -> Option<BTreeSet<i64>> {
+    clusters.into_iter()
+        .filter(|v| v.object_ids.iter().any(|id| !skipped.contains(id)))
+        .map(|v| (v.centroid.distance(player_position), v))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by(|(lhs, _), (rhs, _)| lhs.partial_cmp(rhs).unwrap())
+        .map(|(_, v)| v.object_ids.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::objects::make_object;
+
+    use super::*;
+
+    #[test]
+    fn select_object_should_return_nearest_configured_object() {
+        let objects = vec![
+            make_object(1, 100.0, 0.0, Some("gfx/terobjs/items/blueberry")),
+            make_object(2, 10.0, 0.0, Some("gfx/terobjs/trees/spruce")),
+            make_object(3, 50.0, 0.0, Some("gfx/terobjs/items/blueberry")),
+            make_object(4, 20.0, 0.0, Some("gfx/terobjs/items/blueberry")),
+            make_object(5, 500.0, 0.0, Some("gfx/terobjs/items/blueberry")),
+        ];
+        let names = vec![String::from("gfx/terobjs/items/blueberry")].into_iter().collect();
+        let select = |skipped: &HashSet<i64>| {
+            select_object(objects.iter(), Vec2f::zero(), &names, skipped, 200.0).map(|v| v.id)
+        };
+        assert_eq!(select(&HashSet::new()), Some(4));
+        assert_eq!(select(&vec![4].into_iter().collect()), Some(3));
+        assert_eq!(select(&vec![1, 3, 4].into_iter().collect()), None);
+    }
+
+    #[test]
+    fn select_cluster_should_return_nearest_cluster_with_not_skipped_objects() {
+        let clusters = vec![
+            ObjectCluster { centroid: Vec2f::new(10.0, 0.0), object_ids: vec![1, 2] },
+            ObjectCluster { centroid: Vec2f::new(50.0, 0.0), object_ids: vec![3] },
+            ObjectCluster { centroid: Vec2f::new(500.0, 0.0), object_ids: vec![4] },
+        ];
+        let select = |skipped: &HashSet<i64>| {
+            select_cluster(clusters.clone(), Vec2f::zero(), skipped, 200.0)
+        };
+        assert_eq!(select(&HashSet::new()), Some(vec![1, 2].into_iter().collect()));
+        assert_eq!(select(&vec![1, 2].into_iter().collect()), Some(vec![3].into_iter().collect()));
+        assert_eq!(select(&vec![1, 2, 3].into_iter().collect()), None);
+    }
+}
@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use serde::Deserialize;
+
+use crate::bot::map::pos_to_map_pos;
+use crate::bot::protocol::{Message, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::Vec2i;
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct IdlerConfig {
+    pub min_interval: f64,
+    pub max_interval: f64,
+    pub activity_gap: f64,
+    pub kind: String,
+}
+
+pub struct Idler {
+    deadline: Option<Instant>,
+    last_poll: Option<Instant>,
+    actions: usize,
+    rng: SmallRng,
+    config: IdlerConfig,
+}
+
+impl Idler {
+    pub fn new(config: IdlerConfig) -> Self {
+        Self {
+            deadline: None,
+            last_poll: None,
+            actions: 0,
+            rng: SeedableRng::from_entropy(),
+            config,
+        }
+    }
+
+    fn poll(&mut self, now: Instant) -> bool {
+        let activity_gap = Duration::from_secs_f64(self.config.activity_gap);
+        let was_active = self.last_poll.map(|v| now - v > activity_gap).unwrap_or(true);
+        self.last_poll = Some(now);
+        if !was_active && self.deadline.map(|v| now < v).unwrap_or(false) {
+            return false;
+        }
+        let max_interval = self.config.max_interval.max(self.config.min_interval);
+        let interval = Uniform::new_inclusive(self.config.min_interval, max_interval).sample(&mut self.rng);
+        self.deadline = Some(now + Duration::from_secs_f64(interval));
+        !was_active
+    }
+}
+
+impl Task for Idler {
+    fn name(&self) -> &str {
+        "Idler"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, _: &Scene) -> Option<Message> {
+        if !self.poll(Instant::now()) {
+            return None;
+        }
+        task_debug!("Idler: send {} at {:?}", self.config.kind, world.player_position());
+        self.actions += 1;
+        Some(Message::WidgetMessage {
+            sender: world.map_view_id(),
+            kind: self.config.kind.clone(),
+            arguments: vec![
+                Value::from(Vec2i::zero()),
+                Value::from(pos_to_map_pos(world.player_position())),
+            ],
+        })
+    }
+
+    fn update(&mut self, _: &PlayerWorld, _: &Update) {}
+
+    fn restore(&mut self, _: &PlayerWorld) {
+        self.deadline = None;
+        self.last_poll = None;
+    }
+
+    fn priority(&self, _: &PlayerWorld) -> i32 {
+        std::i32::MIN
+    }
+
+    fn status(&self) -> TaskStatus {
+        TaskStatus::new("Wait").with_counter("actions", self.actions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_should_wait_for_interval_without_other_activity() {
+        let mut idler = Idler::new(IdlerConfig { min_interval: 10.0, max_interval: 10.0, activity_gap: 1.0, kind: String::from("hover") });
+        let start = Instant::now();
+        let at = |seconds: f64| start + Duration::from_secs_f64(seconds);
+        assert!(!idler.poll(at(0.0)));
+        for i in 1..20 {
+            assert!(!idler.poll(at(i as f64 * 0.5)), "{}", i);
+        }
+        assert!(idler.poll(at(10.0)));
+        assert!(!idler.poll(at(10.5)));
+        assert!(!idler.poll(at(15.0)));
+        assert!(!idler.poll(at(25.5)));
+    }
+}
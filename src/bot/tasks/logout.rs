@@ -0,0 +1,222 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::destination::Destination;
+use crate::bot::map::pos_to_map_pos;
+use crate::bot::protocol::{Button, Message, Modifier, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::path_finder::{PathFinder, PathFinderConfig, PathFinderParams};
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct LogoutConfig {
+    pub safe_position: Option<Vec2f>,
+    pub arrival_distance: f64,
+    pub max_walk_attempts: usize,
+    pub enter_timeout: f64,
+    pub actions: Vec<Vec<String>>,
+}
+
+#[derive(Default, Deserialize)]
+pub struct LogoutParams {
+    pub safe_position: Option<Vec2f>,
+    pub destination: Option<Destination>,
+    pub house: Option<i64>,
+    pub profile: Option<String>,
+}
+
+enum Step {
+    Start,
+    Walk(PathFinder),
+    Verify,
+    EnterHouse,
+    WaitEnter { segment_id: i64, started: Instant },
+    Logout(usize),
+    Failed,
+}
+
+pub struct Logout {
+    safe_position: Option<Vec2f>,
+    destination: Option<Destination>,
+    house: Option<i64>,
+    profile: Option<String>,
+    walk_attempts: usize,
+    step: Step,
+    config: LogoutConfig,
+    path_finder_config: PathFinderConfig,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Logout {
+    pub fn new(params: LogoutParams, config: LogoutConfig, path_finder_config: PathFinderConfig,
+               cancel: Arc<AtomicBool>) -> Self {
+        Self {
+            safe_position: params.safe_position.or(config.safe_position),
+            destination: params.destination,
+            house: params.house,
+            profile: params.profile,
+            walk_attempts: 0,
+            step: Step::Start,
+            config,
+            path_finder_config,
+            cancel,
+        }
+    }
+
+    fn get_destination(&self, world: &PlayerWorld) -> Result<Option<Vec2f>, String> {
+        match self.house {
+            Some(object_id) => match world.get_object_by_id(object_id) {
+                Some(object) => Ok(Some(object.position)),
+                None => Err(format!("house object {} is not found", object_id)),
+            },
+            None => match self.destination.as_ref() {
+                Some(destination) => destination.get_position(world).map(Some),
+                None => Ok(self.safe_position),
+            },
+        }
+    }
+
+    fn fail(&mut self, message: String) -> Option<Message> {
+        task_debug!("Logout: {}", message);
+        self.step = Step::Failed;
+        Some(Message::Error { message })
+    }
+}
+
+impl Task for Logout {
+    fn name(&self) -> &str {
+        "Logout"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        loop {
+            match &mut self.step {
+                Step::Start => {
+                    let destination = match self.get_destination(world) {
+                        Ok(Some(v)) => v,
+                        Ok(None) => {
+                            task_debug!("Logout: no safe position, log out in place");
+                            self.step = Step::Logout(0);
+                            continue;
+                        }
+                        Err(e) => return self.fail(e),
+                    };
+                    if destination.distance(world.player_position()) <= self.config.arrival_distance {
+                        self.step = Step::Verify;
+                        continue;
+                    }
+                    if self.walk_attempts >= self.config.max_walk_attempts {
+                        return self.fail(format!("failed to reach safe position {:?} after {} attempts", destination, self.walk_attempts));
+                    }
+                    self.walk_attempts += 1;
+                    task_debug!("Logout: walk to {:?}, attempt {}", destination, self.walk_attempts);
+                    self.step = Step::Walk(PathFinder::new(
+                        PathFinderParams { waypoints: Some(vec![destination]), profile: self.profile.clone(), destinations: None },
+                        self.path_finder_config.clone(),
+                        self.cancel.clone(),
+                    ));
+                }
+                Step::Walk(path_finder) => {
+                    match path_finder.get_next_message(world, scene) {
+                        Some(Message::Done { .. }) => self.step = Step::Verify,
+                        None if !path_finder.has_destination() => {
+                            return self.fail(String::from("path to safe position is not found"));
+                        }
+                        v => return v,
+                    }
+                }
+                Step::Verify => {
+                    let destination = match self.get_destination(world) {
+                        Ok(v) => v,
+                        Err(e) => return self.fail(e),
+                    };
+                    match destination {
+                        Some(position) if position.distance(world.player_position()) > self.config.arrival_distance => {
+                            task_debug!("Logout: player is not at safe position {:?}", position);
+                            self.step = Step::Start;
+                        }
+                        _ if self.house.is_some() => self.step = Step::EnterHouse,
+                        _ => self.step = Step::Logout(0),
+                    }
+                }
+                Step::EnterHouse => {
+                    let object = match self.house.and_then(|id| world.get_object_by_id(id)) {
+                        Some(v) => v,
+                        None => return self.fail(format!("house object {:?} is not found", self.house)),
+                    };
+                    task_debug!("Logout: enter house {} {:?}", object.id, object.name);
+                    let message = Message::WidgetMessage {
+                        sender: world.map_view_id(),
+                        kind: String::from("click"),
+                        arguments: vec![
+                            Value::from(Vec2i::zero()),
+                            Value::from(pos_to_map_pos(object.position)),
+                            Value::from(Button::RightClick),
+                            Value::from(Modifier::None),
+                            Value::from(0i32),
+                            Value::from(object.id as i32),
+                            Value::from(pos_to_map_pos(object.position)),
+                            Value::from(0i32),
+                            Value::from(0i32),
+                        ],
+                    };
+                    self.step = Step::WaitEnter { segment_id: world.player_segment_id(), started: Instant::now() };
+                    return Some(message);
+                }
+                Step::WaitEnter { segment_id, started } => {
+                    if world.player_segment_id() != *segment_id {
+                        task_debug!("Logout: entered house");
+                        self.step = Step::Logout(0);
+                        continue;
+                    }
+                    if Instant::now() - *started > Duration::from_secs_f64(self.config.enter_timeout) {
+                        return self.fail(String::from("failed to enter house"));
+                    }
+                    return None;
+                }
+                Step::Logout(index) => {
+                    let action = match self.config.actions.get(*index) {
+                        Some(v) => v,
+                        None => return Some(Message::Done { task: String::from("Logout") }),
+                    };
+                    task_debug!("Logout: send action {:?}", action);
+                    *index += 1;
+                    return Some(Message::WidgetMessage {
+                        sender: world.game_ui_id(),
+                        kind: String::from("act"),
+                        arguments: action.iter().map(|v| Value::from(v.clone())).collect(),
+                    });
+                }
+                Step::Failed => return None,
+            }
+        }
+    }
+
+    fn update(&mut self, _: &PlayerWorld, _: &Update) {}
+
+    fn restore(&mut self, _: &PlayerWorld) {
+        if !matches!(self.step, Step::Failed) {
+            self.step = Step::Start;
+        }
+    }
+
+    fn is_exclusive(&self) -> bool {
+        true
+    }
+
+    fn status(&self) -> TaskStatus {
+        let state = match self.step {
+            Step::Start | Step::Verify => "Search",
+            Step::Walk(..) => "Walk",
+            Step::EnterHouse | Step::WaitEnter { .. } => "EnterHouse",
+            Step::Logout(..) => "Logout",
+            Step::Failed => "Failed",
+        };
+        TaskStatus::new(state).with_counter("walk_attempts", self.walk_attempts)
+    }
+}
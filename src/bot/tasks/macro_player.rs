@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::macros::{Macro, MacroWidget};
+use crate::bot::map::pos_to_map_pos;
+use crate::bot::protocol::{Message, TaskStatus, Update};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::Vec2i;
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct MacroPlayerConfig {
+    pub macros_path: String,
+    pub max_delay: f64,
+}
+
+#[derive(Deserialize)]
+pub struct MacroPlayerParams {
+    pub name: String,
+}
+
+pub struct MacroPlayer {
+    name: String,
+    value: Macro,
+    next_step: usize,
+    offset: Option<Vec2i>,
+    last_step: Instant,
+    config: MacroPlayerConfig,
+}
+
+impl MacroPlayer {
+    pub fn new(name: String, value: Macro, config: MacroPlayerConfig) -> Self {
+        Self {
+            name,
+            value,
+            next_step: 0,
+            offset: None,
+            last_step: Instant::now(),
+            config,
+        }
+    }
+}
+
+impl Task for MacroPlayer {
+    fn name(&self) -> &str {
+        "MacroPlayer"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, _: &Scene) -> Option<Message> {
+        let now = Instant::now();
+        let offset = match self.offset {
+            Some(v) => v,
+            None => {
+                let offset = pos_to_map_pos(world.player_position()) - self.value.origin;
+                task_debug!("MacroPlayer: play macro {:?} with {} steps and offset {:?}", self.name, self.value.steps.len(), offset);
+                self.offset = Some(offset);
+                self.last_step = now;
+                offset
+            }
+        };
+        let step = match self.value.steps.get(self.next_step) {
+            Some(v) => v,
+            None => {
+                task_debug!("MacroPlayer: macro {:?} is played", self.name);
+                return Some(Message::Done { task: String::from("MacroPlayer") });
+            }
+        };
+        let delay = Duration::from_secs_f64(step.delay.min(self.config.max_delay));
+        if now - self.last_step < delay {
+            return None;
+        }
+        task_debug!("MacroPlayer: play step {} of macro {:?}: {}", self.next_step, self.name, step.kind);
+        self.next_step += 1;
+        self.last_step = now;
+        Some(Message::WidgetMessage {
+            sender: match step.widget {
+                MacroWidget::MapView => world.map_view_id(),
+                MacroWidget::Id(id) => id,
+            },
+            kind: step.kind.clone(),
+            arguments: step.arguments_with_offset(offset),
+        })
+    }
+
+    fn update(&mut self, _: &PlayerWorld, _: &Update) {}
+
+    fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn is_exclusive(&self) -> bool {
+        true
+    }
+
+    fn status(&self) -> TaskStatus {
+        TaskStatus::new(if self.offset.is_some() { "Play" } else { "Start" })
+            .with_target(self.name.clone())
+            .with_percent(self.next_step, self.value.steps.len())
+            .with_counter("steps", self.value.steps.len())
+    }
+}
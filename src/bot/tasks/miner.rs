@@ -0,0 +1,325 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::map::{pos_to_map_pos, pos_to_tile_pos, rel_tile_pos_to_pos};
+use crate::bot::protocol::{Button, Event, Message, Modifier, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::drinker::DrinkerConfig;
+use crate::bot::tasks::path_finder::{PathFinder, PathFinderConfig, PathFinderParams};
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::Vec2i;
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct MinerConfig {
+    pub wall_tiles: BTreeSet<String>,
+    pub action: Vec<String>,
+    pub action_distance: f64,
+    pub mine_timeout: f64,
+    pub max_mine_attempts: usize,
+    pub rest_timeout: f64,
+}
+
+#[derive(Deserialize)]
+pub struct MinerParams {
+    pub from: Vec2i,
+    pub direction: Option<Vec2i>,
+    pub length: Option<usize>,
+    pub target: Option<i64>,
+}
+
+enum Step {
+    Plan,
+    Start,
+    Walk(PathFinder),
+    Mine,
+    Click,
+    WaitMined { started: Instant },
+    Rest { started: Instant },
+    Failed,
+}
+
+pub struct Miner {
+    from: Vec2i,
+    direction: Option<Vec2i>,
+    length: Option<usize>,
+    target: Option<i64>,
+    tiles: VecDeque<Vec2i>,
+    total_tiles: usize,
+    approach: Vec2i,
+    mine_attempts: usize,
+    terrain_updated: bool,
+    step: Step,
+    config: MinerConfig,
+    drinker_config: DrinkerConfig,
+    path_finder_config: PathFinderConfig,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Miner {
+    pub fn new(params: MinerParams, config: MinerConfig, drinker_config: DrinkerConfig,
+               path_finder_config: PathFinderConfig, cancel: Arc<AtomicBool>) -> Result<Self, String> {
+        match (params.direction, params.length, params.target) {
+            (Some(direction), Some(_), None) => {
+                if direction.x().abs() + direction.y().abs() != 1 {
+                    return Err(format!("Miner direction {:?} is not a unit axis step", direction));
+                }
+            }
+            (None, None, Some(_)) => (),
+            _ => return Err(String::from("Miner requires either direction and length or target")),
+        }
+        Ok(Self {
+            from: params.from,
+            direction: params.direction,
+            length: params.length,
+            target: params.target,
+            tiles: VecDeque::new(),
+            total_tiles: 0,
+            approach: params.from,
+            mine_attempts: 0,
+            terrain_updated: false,
+            step: Step::Plan,
+            config,
+            drinker_config,
+            path_finder_config,
+            cancel,
+        })
+    }
+
+    fn plan(&self, world: &PlayerWorld) -> Result<VecDeque<Vec2i>, String> {
+        match (self.direction, self.length, self.target) {
+            (Some(direction), Some(length), _) => Ok((1..=length as i32).map(|i| self.from + direction * i).collect()),
+            (_, _, Some(object_id)) => match world.get_object_by_id(object_id) {
+                Some(object) => Ok(make_tunnel(self.from, pos_to_tile_pos(object.position)).into_iter().collect()),
+                None => Err(format!("target object {} is not found", object_id)),
+            },
+            _ => Err(String::from("tunnel is not defined")),
+        }
+    }
+
+    fn is_wall(&self, world: &PlayerWorld, tile_pos: Vec2i) -> bool {
+        world.get_tile(tile_pos)
+            .and_then(|id| world.get_tile_by_id(id))
+            .map(|tile| self.config.wall_tiles.contains(&tile.name))
+            .unwrap_or(false)
+    }
+
+    fn next_tile(&mut self) {
+        if let Some(tile_pos) = self.tiles.pop_front() {
+            task_debug!("Miner: finished tile {:?}, {} tiles left", tile_pos, self.tiles.len());
+            self.approach = tile_pos;
+        }
+        self.mine_attempts = 0;
+        self.step = Step::Start;
+    }
+
+    fn fail(&mut self, message: String) -> Option<Message> {
+        task_debug!("Miner: {}", message);
+        self.step = Step::Failed;
+        Some(Message::Error { message })
+    }
+}
+
+impl Task for Miner {
+    fn name(&self) -> &str {
+        "Miner"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        let timeout = Duration::from_secs_f64(self.config.mine_timeout);
+        loop {
+            if matches!(self.step, Step::Start | Step::Walk(..) | Step::Mine)
+                && world.player_stamina() <= self.drinker_config.stamina_threshold {
+                task_debug!("Miner: stamina {} is low, rest", world.player_stamina());
+                self.step = Step::Rest { started: Instant::now() };
+            }
+            match &mut self.step {
+                Step::Plan => {
+                    self.tiles = match self.plan(world) {
+                        Ok(v) => v,
+                        Err(e) => return self.fail(e),
+                    };
+                    self.total_tiles = self.tiles.len();
+                    task_debug!("Miner: planned tunnel from {:?}: {:?}", self.from, self.tiles);
+                    self.step = Step::Start;
+                }
+                Step::Start => {
+                    let tile_pos = match self.tiles.front() {
+                        Some(v) => *v,
+                        None => {
+                            task_debug!("Miner: tunnel is finished");
+                            return Some(Message::Done { task: String::from("Miner") });
+                        }
+                    };
+                    if !self.is_wall(world, tile_pos) {
+                        task_debug!("Miner: tile {:?} is not a wall", tile_pos);
+                        self.next_tile();
+                        continue;
+                    }
+                    if rel_tile_pos_to_pos(tile_pos.center()).distance(world.player_position()) > self.config.action_distance {
+                        task_debug!("Miner: walk to {:?} to mine {:?}", self.approach, tile_pos);
+                        self.step = Step::Walk(PathFinder::new(
+                            PathFinderParams { waypoints: Some(vec![rel_tile_pos_to_pos(self.approach.center())]), profile: None, destinations: None },
+                            self.path_finder_config.clone(),
+                            self.cancel.clone(),
+                        ));
+                        continue;
+                    }
+                    self.step = Step::Mine;
+                }
+                Step::Walk(path_finder) => {
+                    match path_finder.get_next_message(world, scene) {
+                        Some(Message::Done { .. }) => self.step = Step::Mine,
+                        None if !path_finder.has_destination() => {
+                            let approach = self.approach;
+                            return self.fail(format!("path to tunnel tile {:?} is not found", approach));
+                        }
+                        v => return v,
+                    }
+                }
+                Step::Mine => {
+                    let tile_pos = *self.tiles.front().unwrap();
+                    if self.mine_attempts >= self.config.max_mine_attempts {
+                        return self.fail(format!("tile {:?} is not mined after {} attempts", tile_pos, self.mine_attempts));
+                    }
+                    self.mine_attempts += 1;
+                    task_debug!("Miner: mine tile {:?}, attempt {}", tile_pos, self.mine_attempts);
+                    self.step = Step::Click;
+                    return Some(Message::WidgetMessage {
+                        sender: world.game_ui_id(),
+                        kind: String::from("act"),
+                        arguments: self.config.action.iter().map(|v| Value::from(v.clone())).collect(),
+                    });
+                }
+                Step::Click => {
+                    let tile_pos = *self.tiles.front().unwrap();
+                    self.terrain_updated = false;
+                    self.step = Step::WaitMined { started: Instant::now() };
+                    return Some(Message::WidgetMessage {
+                        sender: world.map_view_id(),
+                        kind: String::from("click"),
+                        arguments: vec![
+                            Value::from(Vec2i::zero()),
+                            Value::from(pos_to_map_pos(rel_tile_pos_to_pos(tile_pos.center()))),
+                            Value::from(Button::LeftClick),
+                            Value::from(Modifier::None),
+                        ],
+                    });
+                }
+                Step::WaitMined { started } => {
+                    let started = *started;
+                    let tile_pos = *self.tiles.front().unwrap();
+                    if self.terrain_updated {
+                        self.terrain_updated = false;
+                        if !self.is_wall(world, tile_pos) {
+                            task_debug!("Miner: mined tile {:?}", tile_pos);
+                            self.next_tile();
+                            continue;
+                        }
+                    }
+                    if Instant::now() - started >= timeout {
+                        task_debug!("Miner: tile {:?} is not mined in time", tile_pos);
+                        self.step = Step::Start;
+                        continue;
+                    }
+                    return None;
+                }
+                Step::Rest { started } => {
+                    if world.player_stamina() > self.drinker_config.stamina_threshold {
+                        task_debug!("Miner: stamina {} is restored", world.player_stamina());
+                        self.step = Step::Start;
+                        continue;
+                    }
+                    if Instant::now() - *started >= Duration::from_secs_f64(self.config.rest_timeout) {
+                        let stamina = world.player_stamina();
+                        return self.fail(format!("stamina {} is not restored", stamina));
+                    }
+                    return None;
+                }
+                Step::Failed => return None,
+            }
+        }
+    }
+
+    fn update(&mut self, _: &PlayerWorld, update: &Update) {
+        if let Event::MapGridUpdate { .. } = &update.event {
+            self.terrain_updated = true;
+        }
+    }
+
+    fn restore(&mut self, _: &PlayerWorld) {
+        if !matches!(self.step, Step::Failed) {
+            self.step = Step::Plan;
+        }
+    }
+
+    fn is_exclusive(&self) -> bool {
+        !matches!(self.step, Step::Rest { .. } | Step::Failed)
+    }
+
+    fn status(&self) -> TaskStatus {
+        let state = match self.step {
+            Step::Plan | Step::Start => "Search",
+            Step::Walk(..) => "Walk",
+            Step::Mine | Step::Click | Step::WaitMined { .. } => "Mine",
+            Step::Rest { .. } => "Rest",
+            Step::Failed => "Failed",
+        };
+        let status = TaskStatus::new(state)
+            .with_percent(self.total_tiles - self.tiles.len(), self.total_tiles)
+            .with_counter("tiles_left", self.tiles.len())
+            .with_counter("mine_attempts", self.mine_attempts);
+        match self.tiles.front() {
+            Some(tile_pos) => status.with_target(format!("{:?}", tile_pos)),
+            None => status,
+        }
+    }
+}
+
+fn make_tunnel(from: Vec2i, to: Vec2i) -> Vec<Vec2i> {
+    let delta = to - from;
+    let steps = delta.x().abs() + delta.y().abs();
+    let mut result = Vec::with_capacity(steps as usize);
+    let mut current = from;
+    for _ in 0..steps {
+        let remaining = to - current;
+        let passed = current - from;
+        // Step along the axis that lags behind the straight line from -> to
+        let step_x = remaining.x() != 0 && (remaining.y() == 0
+            || (passed.x().abs() as i64 + 1) * delta.y().abs() as i64 <= (passed.y().abs() as i64 + 1) * delta.x().abs() as i64);
+        current = if step_x {
+            current + Vec2i::new(remaining.x().signum(), 0)
+        } else {
+            current + Vec2i::new(0, remaining.y().signum())
+        };
+        result.push(current);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_tunnel_should_build_adjacent_tiles_to_target() {
+        assert_eq!(
+            make_tunnel(Vec2i::new(0, 0), Vec2i::new(3, 0)),
+            vec![Vec2i::new(1, 0), Vec2i::new(2, 0), Vec2i::new(3, 0)]
+        );
+        let tunnel = make_tunnel(Vec2i::new(0, 0), Vec2i::new(-2, 3));
+        assert_eq!(tunnel.len(), 5);
+        assert_eq!(tunnel.last(), Some(&Vec2i::new(-2, 3)));
+        let mut prev = Vec2i::new(0, 0);
+        for &tile_pos in tunnel.iter() {
+            let step = tile_pos - prev;
+            assert_eq!(step.x().abs() + step.y().abs(), 1, "{:?}", tunnel);
+            prev = tile_pos;
+        }
+        assert_eq!(make_tunnel(Vec2i::new(1, 1), Vec2i::new(1, 1)), Vec::new());
+    }
+}
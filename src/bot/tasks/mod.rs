@@ -1,6 +1,19 @@
 pub mod task;
 pub mod explorer;
-pub mod exp_wnd_closer;
 pub mod new_character;
 pub mod path_finder;
 pub mod drinker;
+pub mod forager;
+pub mod follower;
+pub mod transferrer;
+pub mod macro_player;
+pub mod farmer;
+pub mod wasm_task;
+pub mod fleer;
+pub mod backtrack;
+pub mod ui_janitor;
+pub mod idler;
+pub mod crafter;
+pub mod logout;
+pub mod pipeline;
+pub mod miner;
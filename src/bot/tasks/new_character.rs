@@ -3,7 +3,7 @@ use std::collections::VecDeque;
 use serde::Deserialize;
 
 use crate::bot::map::{map_pos_to_pos, pos_to_map_pos};
-use crate::bot::protocol::{Button, Event, Message, Modifier, Update, Value};
+use crate::bot::protocol::{Button, Event, Message, Modifier, TaskStatus, Update, Value};
 use crate::bot::scene::Scene;
 use crate::bot::tasks::task::Task;
 use crate::bot::vec2::Vec2i;
@@ -53,25 +53,25 @@ impl NewCharacter {
 }
 
 impl Task for NewCharacter {
-    fn name(&self) -> &'static str {
+    fn name(&self) -> &str {
         "NewCharacter"
     }
 
     fn get_next_message(&mut self, world: &PlayerWorld, _: &Scene) -> Option<Message> {
         if self.state == State::HasName {
-            debug!("NewCharacter: has name");
+            task_debug!("NewCharacter: has name");
             return None;
         }
         if world.is_player_stuck() && self.change_name_window_id.is_none() && self.change_name_text_id.is_none() {
-            debug!("NewCharacter: find the name changer");
+            task_debug!("NewCharacter: find the name changer");
             self.state = State::FindNameChanger;
         }
         if self.state == State::WaitForName {
-            debug!("NewCharacter: waiting for a name");
+            task_debug!("NewCharacter: waiting for a name");
             return None;
         }
         if let Some(id) = self.change_name_text_id {
-            debug!("NewCharacter: change name");
+            task_debug!("NewCharacter: change name");
             self.state = State::WaitForName;
             return Some(Message::UIMessage {
                 id,
@@ -80,11 +80,11 @@ impl Task for NewCharacter {
             });
         }
         if self.state == State::WaitForChangeNameTextId {
-            debug!("NewCharacter: waiting for change name text widget");
+            task_debug!("NewCharacter: waiting for change name text widget");
             return None;
         }
         if let Some(object) = world.get_object_by_name(&self.name_changer) {
-            debug!("NewCharacter: go to the name changer");
+            task_debug!("NewCharacter: go to the name changer");
             self.state = State::WaitForChangeNameTextId;
             return Some(Message::WidgetMessage {
                 sender: world.map_view_id(),
@@ -111,7 +111,7 @@ impl Task for NewCharacter {
             self.map_pos_path.pop_front();
         }
         if let Some(map_pos) = self.map_pos_path.front() {
-            debug!("NewCharacter: go to the next path point: {:?}", map_pos);
+            task_debug!("NewCharacter: go to the next path point: {:?}", map_pos);
             return Some(Message::WidgetMessage {
                 sender: world.map_view_id(),
                 kind: String::from("click"),
@@ -136,13 +136,13 @@ impl Task for NewCharacter {
                                 return;
                             }
                             self.change_name_window_id = Some(*id);
-                            debug!("NewCharacter: got change name window id: {}", id);
+                            task_debug!("NewCharacter: got change name window id: {}", id);
                         }
                     }
                     "text" => {
                         if Some(parent) == self.change_name_window_id.as_ref() {
                             self.change_name_text_id = Some(*id);
-                            debug!("NewCharacter: got change name text widget id: {}", id);
+                            task_debug!("NewCharacter: got change name text widget id: {}", id);
                         }
                     }
                     _ => (),
@@ -151,16 +151,16 @@ impl Task for NewCharacter {
             Event::Destroy { id } => {
                 if self.change_name_window_id == Some(*id) {
                     self.change_name_window_id = None;
-                    debug!("NewCharacter: change name window {} is destroyed", id);
+                    task_debug!("NewCharacter: change name window {} is destroyed", id);
                 } else if self.change_name_text_id == Some(*id) {
                     self.change_name_text_id = None;
-                    debug!("NewCharacter: change name text widget {} is destroyed", id);
+                    task_debug!("NewCharacter: change name text widget {} is destroyed", id);
                 }
             }
             Event::UIMessage { id, msg, args: _ } => {
                 if Some(*id) == self.change_name_text_id && msg.as_str() == "settext" {
                     self.state = State::HasName;
-                    debug!("NewCharacter: got name");
+                    task_debug!("NewCharacter: got name");
                 }
             }
             _ => (),
@@ -168,4 +168,10 @@ impl Task for NewCharacter {
     }
 
     fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn status(&self) -> TaskStatus {
+        TaskStatus::new(&format!("{:?}", self.state))
+            .with_target(self.character_name.clone())
+            .with_counter("path_points", self.map_pos_path.len())
+    }
 }
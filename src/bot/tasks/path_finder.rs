@@ -1,122 +1,314 @@
 use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::AtomicBool;
+use std::time::Instant;
 
 use serde::Deserialize;
 
+use crate::bot::destination::Destination;
+use crate::bot::leg_timer::{LegCheck, LegTimeoutConfig, LegTimer};
 use crate::bot::map::{map_pos_to_tile_pos, pos_to_map_pos, pos_to_rel_tile_pos, pos_to_tile_pos, rel_tile_pos_to_pos, TILE_SIZE};
-use crate::bot::protocol::{Button, Event, Message, Modifier, Update, Value};
+use crate::bot::protocol::{Button, Event, Message, Modifier, TaskStatus, Update, Value};
+use crate::bot::roads::{make_road_profile, ROAD_PROFILE};
 use crate::bot::scene::{Layer, MapTransformArcNode, Node, Scene};
 use crate::bot::tasks::task::Task;
-use crate::bot::vec2::Vec2i;
-use crate::bot::world::{BTreeMapTileWeights, make_find_path_node, PlayerWorld, WorldConfig};
+use crate::bot::tile_profiles::{ICE_PROFILE, WATER_PROFILE};
+use crate::bot::traversal::{DoorOpener, Traversal};
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::{BTreeMapTileWeights, make_find_path_node, PathSearch, PathSearchState, PlayerWorld};
 
 #[derive(Clone, Deserialize)]
 pub struct PathFinderConfig {
     pub find_path_max_shortcut_length: f64,
     pub find_path_max_iterations: usize,
+    pub find_path_max_total_iterations: Option<usize>,
     pub max_next_point_shortcut_length: f64,
+    pub leg_timeout: Option<LegTimeoutConfig>,
+    pub look_ahead_distance: Option<f64>,
+}
+
+#[derive(Default, Deserialize)]
+pub struct PathFinderParams {
+    pub waypoints: Option<Vec<Vec2f>>,
+    pub profile: Option<String>,
+    pub destinations: Option<Vec<Destination>>,
 }
 
 pub struct PathFinder {
-    destination: Option<Vec2i>,
+    destinations: VecDeque<Vec2i>,
+    unresolved_waypoints: Vec<Vec2f>,
+    unresolved_destinations: Vec<Destination>,
     tile_pos_path: VecDeque<Vec2i>,
+    profile: Option<String>,
     find_path_layer: Option<Layer>,
+    find_path_node: Arc<Mutex<Node>>,
+    path_search: Option<PathSearch>,
+    danger_zones_revision: u64,
+    stuck_tiles_revision: u64,
+    avoidance_revision: u64,
+    path_revision: u64,
+    leg_timer: Option<LegTimer>,
+    door_opener: DoorOpener,
     config: PathFinderConfig,
     cancel: Arc<AtomicBool>,
 }
 
 impl PathFinder {
-    pub fn new(config: PathFinderConfig, cancel: Arc<AtomicBool>) -> Self {
+    pub fn new(params: PathFinderParams, config: PathFinderConfig, cancel: Arc<AtomicBool>) -> Self {
         Self {
-            destination: None,
+            destinations: params.waypoints.unwrap_or_default().into_iter()
+                .map(pos_to_tile_pos)
+                .collect(),
+            unresolved_waypoints: Vec::new(),
+            unresolved_destinations: params.destinations.unwrap_or_default(),
             tile_pos_path: VecDeque::new(),
+            profile: params.profile,
             find_path_layer: None,
+            find_path_node: make_find_path_node(),
+            path_search: None,
+            danger_zones_revision: 0,
+            stuck_tiles_revision: 0,
+            avoidance_revision: 0,
+            path_revision: 0,
+            leg_timer: config.leg_timeout.clone().map(LegTimer::new),
+            door_opener: DoorOpener::new(),
             config,
             cancel,
         }
     }
+
+    // Waypoints from task params are exported coordinates so they are resolved against world anchors
+    pub fn from_task_params(mut params: PathFinderParams, config: PathFinderConfig, cancel: Arc<AtomicBool>) -> Self {
+        let waypoints = params.waypoints.take().unwrap_or_default();
+        Self {
+            unresolved_waypoints: waypoints,
+            ..Self::new(params, config, cancel)
+        }
+    }
+
+    pub fn has_destination(&self) -> bool {
+        !self.destinations.is_empty() || !self.unresolved_waypoints.is_empty()
+            || !self.unresolved_destinations.is_empty()
+    }
+
+    fn resolve_destinations(&mut self, world: &PlayerWorld) -> Result<(), String> {
+        for waypoint in self.unresolved_waypoints.drain(..) {
+            let position = world.import_position(waypoint);
+            task_debug!("PathFinder: resolved waypoint {:?} to {:?}", waypoint, position);
+            self.destinations.push_back(pos_to_tile_pos(position));
+        }
+        for destination in self.unresolved_destinations.drain(..) {
+            let position = destination.get_position(world)?;
+            task_debug!("PathFinder: resolved destination {:?} to {:?}", destination, position);
+            self.destinations.push_back(pos_to_tile_pos(position));
+        }
+        Ok(())
+    }
+
+    fn get_click_target(&self, world: &PlayerWorld, player_pos: Vec2f, tile_weights: &BTreeMap<i32, f64>) -> Option<Vec2f> {
+        let current = rel_tile_pos_to_pos(self.tile_pos_path.front()?.center());
+        let look_ahead_distance = match self.config.look_ahead_distance {
+            Some(v) => v,
+            None => return Some(current),
+        };
+        let distance = current.distance(player_pos);
+        if distance >= look_ahead_distance || self.tile_pos_path.len() < 2 {
+            return Some(current);
+        }
+        let next = rel_tile_pos_to_pos(self.tile_pos_path[1].center());
+        let leg_length = current.distance(next);
+        if leg_length == 0.0 {
+            return Some(current);
+        }
+        let target = current + (next - current) * ((look_ahead_distance - distance).min(leg_length) / leg_length);
+        if world.is_valid_shortcut_by_rel_pos(
+            pos_to_rel_tile_pos(player_pos),
+            pos_to_rel_tile_pos(target),
+            &BTreeMapTileWeights(tile_weights),
+            self.config.max_next_point_shortcut_length,
+        ) {
+            task_debug!("PathFinder: click ahead of {:?} at {:?}", self.tile_pos_path[0], target);
+            Some(target)
+        } else {
+            Some(current)
+        }
+    }
 }
 
 impl Task for PathFinder {
-    fn name(&self) -> &'static str {
+    fn name(&self) -> &str {
         "PathFinder"
     }
 
     fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
-        if self.destination.is_none() {
-            debug!("PathFinder: destination is not set");
-            return None;
+        if let Err(e) = self.resolve_destinations(world) {
+            task_debug!("PathFinder: {}", e);
+            self.unresolved_waypoints.clear();
+            self.unresolved_destinations.clear();
+            self.destinations.clear();
+            return Some(Message::Error { message: e });
         }
-        let dst_tile_pos = self.destination.unwrap();
         let player_pos = world.player_position();
         let src_tile_pos = pos_to_tile_pos(player_pos);
+        while self.destinations.len() > 1 && self.destinations.front() == Some(&src_tile_pos) {
+            self.destinations.pop_front();
+            self.tile_pos_path.clear();
+            task_debug!("PathFinder: reached waypoint {:?}, {} left", src_tile_pos, self.destinations.len());
+        }
+        let dst_tile_pos = match self.destinations.front() {
+            Some(v) => *v,
+            None => {
+                task_debug!("PathFinder: destination is not set");
+                return None;
+            }
+        };
         if dst_tile_pos == src_tile_pos {
-            self.destination = None;
+            self.destinations.clear();
             self.find_path_layer = None;
-            debug!("PathFinder: reached destination");
+            if let Some(leg_timer) = self.leg_timer.as_mut() {
+                leg_timer.reset();
+            }
+            self.door_opener.reset();
+            task_debug!("PathFinder: reached destination");
             return Some(Message::Done { task: String::from("PathFinder") });
         }
         let player_tile = world.get_tile(src_tile_pos);
         if player_tile.is_none() {
-            debug!("PathFinder: player position {:?} is out of bounds", src_tile_pos);
+            task_debug!("PathFinder: player position {:?} is out of bounds", src_tile_pos);
             return None;
         }
         let dst_tile = world.get_tile(dst_tile_pos);
         if dst_tile.is_none() {
-            debug!("PathFinder: destination position {:?} is out of bounds", dst_tile_pos);
+            task_debug!("PathFinder: destination position {:?} is out of bounds", dst_tile_pos);
             return None;
         }
         let player_tile_name = world.get_tile_by_id(player_tile.unwrap())
             .map(|v| &v.name);
         if player_tile_name.is_none() {
-            debug!("PathFinder: player tile {:?} at {:?} has unknown type", player_tile, player_pos);
+            task_debug!("PathFinder: player tile {:?} at {:?} has unknown type", player_tile, player_pos);
             return None;
         }
         let dst_tile_name = world.get_tile_by_id(dst_tile.unwrap())
             .map(|v| &v.name);
         if dst_tile_name.is_none() {
-            debug!("PathFinder: destination tile {:?} at {:?} has unknown type", dst_tile, dst_tile_pos);
+            task_debug!("PathFinder: destination tile {:?} at {:?} has unknown type", dst_tile, dst_tile_pos);
             return None;
         }
-        let tile_costs = get_tile_costs(player_tile_name.unwrap(), world.config());
-        if tile_costs.is_none() {
-            debug!("PathFinder: tile set is not found for player tile {:?}", player_tile_name.unwrap());
+        let tile_costs = match self.profile.as_ref() {
+            Some(profile) => match get_tile_costs_by_profile(profile.as_str(), world) {
+                Some(v) => Some(v),
+                None => {
+                    task_debug!("PathFinder: movement profile {:?} is not found", profile);
+                    self.destinations.clear();
+                    self.tile_pos_path.clear();
+                    return Some(Message::Error { message: format!("movement profile {:?} is not found", profile) });
+                }
+            },
+            None => match get_road_tile_costs_for_travel(player_pos, dst_tile_pos, &[player_tile_name.unwrap(), dst_tile_name.unwrap()], world) {
+                Some(v) => Some(v),
+                None => get_tile_costs(player_tile_name.unwrap(), world),
+            },
+        };
+        let tile_costs = match tile_costs {
+            Some(v) => v,
+            None => {
+                task_debug!("PathFinder: tile set is not found for player tile {:?}", player_tile_name.unwrap());
+                return None;
+            }
+        };
+        if !tile_costs.contains_key(player_tile_name.unwrap()) {
+            task_debug!("PathFinder: player tile {:?} does not belong to movement profile {:?}",
+                   player_tile_name.unwrap(), self.profile);
             return None;
         }
-        if !tile_costs.unwrap().contains_key(dst_tile_name.unwrap()) {
-            debug!("PathFinder: destination tile {:?} does not belong to player tile set",
+        if !tile_costs.contains_key(dst_tile_name.unwrap()) {
+            task_debug!("PathFinder: destination tile {:?} does not belong to player tile set",
                    dst_tile_name.unwrap());
             return None;
         }
-        let tile_weights: BTreeMap<i32, f64> = tile_costs.unwrap().iter()
+        let tile_weights: BTreeMap<i32, f64> = tile_costs.iter()
             .filter_map(|(name, weight)| world.get_tile_id_by_name(name).map(|id| (id, *weight)))
             .collect();
+        if self.danger_zones_revision != world.danger_zones().revision() {
+            self.danger_zones_revision = world.danger_zones().revision();
+            if world.is_dangerous_path(player_pos, self.tile_pos_path.iter()) {
+                task_debug!("PathFinder: path crosses danger zone, replan");
+                self.tile_pos_path.clear();
+            }
+        }
+        if self.stuck_tiles_revision != world.stuck_tiles().revision() {
+            self.stuck_tiles_revision = world.stuck_tiles().revision();
+            if !self.tile_pos_path.is_empty() {
+                task_debug!("PathFinder: stuck tiles are changed, replan");
+                self.tile_pos_path.clear();
+            }
+        }
+        if self.avoidance_revision != world.avoidance().revision() {
+            self.avoidance_revision = world.avoidance().revision();
+            if world.is_avoided_path(player_pos, self.tile_pos_path.iter()) {
+                task_debug!("PathFinder: path crosses avoided tiles, replan");
+                self.tile_pos_path.clear();
+            }
+        }
+        if self.path_revision != world.revision() {
+            self.path_revision = world.revision();
+            if !world.is_valid_path(self.tile_pos_path.iter(), &BTreeMapTileWeights(&tile_weights)) {
+                task_debug!("PathFinder: path is not valid for world revision {}, replan", world.revision());
+                self.tile_pos_path.clear();
+            }
+        }
         if self.tile_pos_path.is_empty() {
-            let find_path_node = make_find_path_node();
-            self.find_path_layer = Some(Layer::new(
-                scene.clone(),
-                Arc::new(Mutex::new(
-                    Node::from(MapTransformArcNode {
-                        node: find_path_node.clone(),
-                    })
-                )),
-            ));
-            self.tile_pos_path = VecDeque::from(world.find_path(
-                src_tile_pos,
-                dst_tile_pos,
+            let mut path_search = match self.path_search.take() {
+                Some(v) if v.src_tile_pos() == src_tile_pos && v.dst_tile_pos() == dst_tile_pos
+                    && !world.is_stale_path_search(&v) => v,
+                v => {
+                    if v.is_some() {
+                        task_debug!("PathFinder: discard previous path search, restart from {:?} to {:?}", src_tile_pos, dst_tile_pos);
+                    }
+                    self.find_path_node = make_find_path_node();
+                    self.find_path_layer = Some(Layer::new(
+                        scene.clone(),
+                        Arc::new(Mutex::new(
+                            Node::from(MapTransformArcNode {
+                                node: self.find_path_node.clone(),
+                            })
+                        )),
+                    ));
+                    let max_iterations = self.config.find_path_max_total_iterations
+                        .unwrap_or(self.config.find_path_max_iterations);
+                    match world.start_path_search(src_tile_pos, dst_tile_pos, &BTreeMapTileWeights(&tile_weights), max_iterations) {
+                        Some(v) => v,
+                        None => {
+                            task_debug!("PathFinder: destination {:?} is not reachable from {:?}", dst_tile_pos, src_tile_pos);
+                            self.destinations.pop_front();
+                            return None;
+                        }
+                    }
+                }
+            };
+            match world.continue_path_search(
+                &mut path_search,
                 &BTreeMapTileWeights(&tile_weights),
                 self.config.find_path_max_shortcut_length,
                 self.config.find_path_max_iterations,
-                &find_path_node,
+                &self.find_path_node,
                 &self.cancel,
-            ));
+            ) {
+                PathSearchState::Found(path) => self.tile_pos_path = VecDeque::from(path),
+                PathSearchState::NotFound => (),
+                PathSearchState::InProgress => {
+                    task_debug!("PathFinder: path search from {:?} to {:?} continues after {} iterations",
+                           src_tile_pos, dst_tile_pos, path_search.iterations());
+                    self.path_search = Some(path_search);
+                    return None;
+                }
+            }
             if self.tile_pos_path.is_empty() {
-                debug!("PathFinder: path from {:?} to {:?} is not found by tiles {:?}",
+                task_debug!("PathFinder: path from {:?} to {:?} is not found by tiles {:?}",
                        src_tile_pos, dst_tile_pos, tile_costs);
-                self.destination = None;
+                self.destinations.pop_front();
             } else {
-                debug!("PathFinder: found path from {:?} to {:?} by tiles {:?}: {:?}",
+                task_debug!("PathFinder: found path from {:?} to {:?} by tiles {:?}: {:?}",
                        src_tile_pos, dst_tile_pos, tile_costs, self.tile_pos_path);
             }
         }
@@ -136,18 +328,44 @@ impl Task for PathFinder {
         while let Some(&tile_pos) = self.tile_pos_path.front() {
             let distance = rel_tile_pos_to_pos(tile_pos.center()).distance(player_pos);
             if distance > (2.0 * TILE_SIZE).sqrt() && tile_pos != pos_to_tile_pos(player_pos) {
-                debug!("PathFinder: distance to the next path point {:?}: {}", tile_pos, distance);
+                task_debug!("PathFinder: distance to the next path point {:?}: {}", tile_pos, distance);
                 break;
             }
             self.tile_pos_path.pop_front();
         }
-        if let Some(tile_pos) = self.tile_pos_path.front() {
+        if let Some(&tile_pos) = self.tile_pos_path.front() {
+            match self.door_opener.traverse(world, rel_tile_pos_to_pos(tile_pos.center()), Instant::now()) {
+                Traversal::Free => (),
+                Traversal::Wait => return None,
+                Traversal::Open(message) => return Some(message),
+            }
+        }
+        if let (Some(&tile_pos), Some(leg_timer)) = (self.tile_pos_path.front(), self.leg_timer.as_mut()) {
+            let target = rel_tile_pos_to_pos(tile_pos.center());
+            match leg_timer.check(target, player_pos, world.player_speed(), Instant::now()) {
+                LegCheck::InTime => (),
+                LegCheck::Replan => {
+                    task_debug!("PathFinder: path point {:?} is not reached in time, replan", tile_pos);
+                    self.tile_pos_path.clear();
+                    return None;
+                }
+                LegCheck::Abort => {
+                    task_debug!("PathFinder: path point {:?} is not reached after {} replans, abort", tile_pos, leg_timer.replans());
+                    leg_timer.reset();
+                    self.destinations.clear();
+                    self.tile_pos_path.clear();
+                    self.find_path_layer = None;
+                    return Some(Message::Error { message: format!("path point {:?} is not reached in time", tile_pos) });
+                }
+            }
+        }
+        if let Some(target) = self.get_click_target(world, player_pos, &tile_weights) {
             return Some(Message::WidgetMessage {
                 sender: world.map_view_id(),
                 kind: String::from("click"),
                 arguments: vec![
                     Value::from(Vec2i::zero()),
-                    Value::from(pos_to_map_pos(rel_tile_pos_to_pos(tile_pos.center()))),
+                    Value::from(pos_to_map_pos(target)),
                     Value::from(Button::LeftClick),
                     Value::from(Modifier::None),
                 ],
@@ -160,15 +378,21 @@ impl Task for PathFinder {
         match &update.event {
             Event::WidgetMessage { id, msg, args } => {
                 if *id == world.map_view_id() && msg.as_str() == "click" && args.len() >= 4
-                    && args[2] == Value::from(Button::LeftClick)
-                    && args[3] == Value::from(Modifier::Alt) {
+                    && args[2] == Value::from(Button::LeftClick) {
+                    let add_waypoint = args[3] == Value::Int { value: Modifier::Alt as i32 | Modifier::Shift as i32 };
+                    if !add_waypoint && args[3] != Value::from(Modifier::Alt) {
+                        return;
+                    }
                     match &args[1] {
                         Value::Coord { value } => {
-                            self.destination = Some(map_pos_to_tile_pos(*value));
-                            self.tile_pos_path.clear();
-                            debug!("PathFinder: set destination: {:?}", self.destination);
+                            if !add_waypoint {
+                                self.destinations.clear();
+                                self.tile_pos_path.clear();
+                            }
+                            self.destinations.push_back(map_pos_to_tile_pos(*value));
+                            task_debug!("PathFinder: set destinations: {:?}", self.destinations);
                         }
-                        v => warn!("PathFinder: invalid click args[1]: {:?}", v),
+                        v => task_warn!("PathFinder: invalid click args[1]: {:?}", v),
                     }
                 }
             }
@@ -177,14 +401,48 @@ impl Task for PathFinder {
     }
 
     fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn is_exclusive(&self) -> bool {
+        true
+    }
+
+    fn status(&self) -> TaskStatus {
+        match self.destinations.front() {
+            Some(destination) => TaskStatus::new("Walk")
+                .with_target(format!("{:?}", destination))
+                .with_counter("waypoints", self.destinations.len())
+                .with_counter("path_tiles", self.tile_pos_path.len())
+                .with_counter("search_iterations", self.path_search.as_ref().map(|v| v.iterations()).unwrap_or(0))
+                .with_counter("opened_doors", self.door_opener.opened()),
+            None => TaskStatus::new("Idle"),
+        }
+    }
 }
 
-fn get_tile_costs<'a>(tile: &String, config: &'a WorldConfig) -> Option<&'a HashMap<String, f64>> {
-    if config.ice_tiles.contains_key(tile) {
-        Some(&config.ice_tiles)
-    } else if config.water_tiles.contains_key(tile) {
-        Some(&config.water_tiles)
-    } else {
-        None
+pub fn get_tile_costs_by_profile(name: &str, world: &PlayerWorld) -> Option<Arc<HashMap<String, f64>>> {
+    world.tile_profiles().get(name)
+        .or_else(|| if name == ROAD_PROFILE { get_road_tile_costs(world) } else { None })
+}
+
+pub fn get_road_tile_costs(world: &PlayerWorld) -> Option<Arc<HashMap<String, f64>>> {
+    let config = &world.config().roads;
+    world.tile_profiles().get(config.base_profile.as_str())
+        .map(|base| Arc::new(make_road_profile(&base, world.iter_road_tile_names(), config)))
+}
+
+fn get_road_tile_costs_for_travel(player_pos: Vec2f, dst_tile_pos: Vec2i, tiles: &[&String],
+                                  world: &PlayerWorld) -> Option<Arc<HashMap<String, f64>>> {
+    let distance = player_pos.distance(rel_tile_pos_to_pos(dst_tile_pos.center()));
+    if distance < world.config().roads.min_travel_distance || world.nearest_road_tile().is_none() {
+        return None;
     }
+    task_debug!("PathFinder: use road profile for distance {}", distance);
+    get_tile_costs_by_profile(ROAD_PROFILE, world)
+        .filter(|tile_costs| tiles.iter().all(|tile| tile_costs.contains_key(*tile)))
+}
+
+pub fn get_tile_costs(tile: &String, world: &PlayerWorld) -> Option<Arc<HashMap<String, f64>>> {
+    [ICE_PROFILE, WATER_PROFILE].iter()
+        .filter_map(|name| world.tile_profiles().get(name))
+        .find(|tile_costs| tile_costs.contains_key(tile))
 }
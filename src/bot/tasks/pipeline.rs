@@ -0,0 +1,239 @@
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::bot::destination::Destination;
+use crate::bot::protocol::{Message, TaskStatus, Update};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::task::Task;
+use crate::bot::world::PlayerWorld;
+
+pub type TaskFactory = Box<dyn Fn(&str, &[u8]) -> Result<Arc<Mutex<dyn Task>>, String> + Send>;
+
+#[derive(Deserialize)]
+pub struct PipelineParams {
+    pub stages: Vec<PipelineStageParams>,
+    pub repeat: Option<usize>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PipelineStageParams {
+    pub task: String,
+    pub params: Option<serde_json::Value>,
+    pub destination: Option<Destination>,
+    pub condition: Option<StageCondition>,
+    pub repeat: Option<usize>,
+    pub on_failure: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum StageCondition {
+    Always,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Done,
+    Failed,
+}
+
+struct Stage {
+    index: usize,
+    runs: usize,
+    walking: bool,
+    task: Arc<Mutex<dyn Task>>,
+}
+
+pub struct Pipeline {
+    stages: Vec<PipelineStageParams>,
+    repeat: usize,
+    iteration: usize,
+    stage: Option<Stage>,
+    make_task: TaskFactory,
+}
+
+impl Pipeline {
+    pub fn new(params: PipelineParams, make_task: TaskFactory) -> Result<Self, String> {
+        if params.stages.is_empty() {
+            return Err(String::from("Pipeline has no stages"));
+        }
+        if let Some(index) = params.stages.iter().filter_map(|v| v.on_failure).find(|v| *v >= params.stages.len()) {
+            return Err(format!("Pipeline on_failure stage {} is out of range", index));
+        }
+        let mut result = Self {
+            stages: params.stages,
+            repeat: params.repeat.unwrap_or(1),
+            iteration: 0,
+            stage: None,
+            make_task,
+        };
+        result.start_stage(0, 0)?;
+        Ok(result)
+    }
+
+    fn start_stage(&mut self, index: usize, runs: usize) -> Result<(), String> {
+        let stage = &self.stages[index];
+        if let Some(destination) = stage.destination.as_ref() {
+            let params = serde_json::to_vec(&json!({"destinations": [destination]}))
+                .map_err(|e| format!("Failed to serialize {} destination: {}", stage.task, e))?;
+            task_debug!("Pipeline: walk to stage {} {} destination {:?}", index, stage.task, destination);
+            let task = (self.make_task)("PathFinder", &params)?;
+            self.stage = Some(Stage { index, runs, walking: true, task });
+            return Ok(());
+        }
+        self.start_stage_task(index, runs)
+    }
+
+    fn start_stage_task(&mut self, index: usize, runs: usize) -> Result<(), String> {
+        let stage = &self.stages[index];
+        let params = match stage.params.as_ref() {
+            Some(v) => serde_json::to_vec(v).map_err(|e| format!("Failed to serialize {} params: {}", stage.task, e))?,
+            None => Vec::new(),
+        };
+        task_debug!("Pipeline: start stage {} {} run {}", index, stage.task, runs + 1);
+        let task = (self.make_task)(stage.task.as_str(), &params)?;
+        self.stage = Some(Stage { index, runs, walking: false, task });
+        Ok(())
+    }
+
+    fn finish_stage(&mut self, outcome: Outcome) -> Option<Message> {
+        let stage = self.stage.take().unwrap();
+        task_debug!("Pipeline: stage {} {} finished with {:?}", stage.index, self.stages[stage.index].task, outcome);
+        let repeat = self.stages[stage.index].repeat.unwrap_or(1);
+        let next = match (outcome, self.stages[stage.index].on_failure) {
+            (Outcome::Done, _) if stage.runs + 1 < repeat => return self.start(stage.index, stage.runs + 1),
+            (Outcome::Failed, Some(index)) => return self.start(index, 0),
+            _ => get_next_stage(&self.stages, stage.index + 1, outcome),
+        };
+        match next {
+            Some(index) => self.start(index, 0),
+            None if self.iteration + 1 < self.repeat => {
+                self.iteration += 1;
+                task_debug!("Pipeline: start iteration {}", self.iteration + 1);
+                match get_next_stage(&self.stages, 0, outcome) {
+                    Some(index) => self.start(index, 0),
+                    None => self.finish(outcome),
+                }
+            }
+            None => self.finish(outcome),
+        }
+    }
+
+    fn start(&mut self, index: usize, runs: usize) -> Option<Message> {
+        match self.start_stage(index, runs) {
+            Ok(_) => None,
+            Err(e) => Some(Message::Error { message: e }),
+        }
+    }
+
+    fn finish(&self, outcome: Outcome) -> Option<Message> {
+        match outcome {
+            Outcome::Done => Some(Message::Done { task: String::from("Pipeline") }),
+            Outcome::Failed => Some(Message::Error { message: String::from("Pipeline last stage has failed") }),
+        }
+    }
+}
+
+impl Task for Pipeline {
+    fn name(&self) -> &str {
+        "Pipeline"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message> {
+        let stage = self.stage.as_ref()?;
+        let message = stage.task.lock().unwrap().get_next_message(world, scene);
+        match message {
+            Some(Message::Done { .. }) if stage.walking => {
+                let (index, runs) = (stage.index, stage.runs);
+                task_debug!("Pipeline: stage {} destination is reached", index);
+                match self.start_stage_task(index, runs) {
+                    Ok(_) => None,
+                    Err(e) => Some(Message::Error { message: e }),
+                }
+            }
+            Some(Message::Done { .. }) => self.finish_stage(Outcome::Done),
+            Some(Message::Error { message }) => {
+                task_debug!("Pipeline: stage {} failed: {}", stage.index, message);
+                self.finish_stage(Outcome::Failed)
+            }
+            v => v,
+        }
+    }
+
+    fn update(&mut self, world: &PlayerWorld, update: &Update) {
+        if let Some(stage) = self.stage.as_ref() {
+            stage.task.lock().unwrap().update(world, update);
+        }
+    }
+
+    fn restore(&mut self, world: &PlayerWorld) {
+        if let Some(stage) = self.stage.as_ref() {
+            stage.task.lock().unwrap().restore(world);
+        }
+    }
+
+    fn on_inventory_full(&mut self, world: &PlayerWorld) {
+        if let Some(stage) = self.stage.as_ref() {
+            stage.task.lock().unwrap().on_inventory_full(world);
+        }
+    }
+
+    fn priority(&self, world: &PlayerWorld) -> i32 {
+        self.stage.as_ref().map(|v| v.task.lock().unwrap().priority(world)).unwrap_or(0)
+    }
+
+    fn is_exclusive(&self) -> bool {
+        self.stage.as_ref().map(|v| v.task.lock().unwrap().is_exclusive()).unwrap_or(false)
+    }
+
+    fn status(&self) -> TaskStatus {
+        let result = TaskStatus::new(if self.stage.is_some() { "Running" } else { "Finished" })
+            .with_percent(self.stage.as_ref().map(|v| v.index).unwrap_or(self.stages.len()), self.stages.len())
+            .with_counter("iteration", self.iteration + 1)
+            .with_counter("repeat", self.repeat);
+        match self.stage.as_ref() {
+            Some(stage) => result
+                .with_counter("stage_run", stage.runs + 1)
+                .with_stage(
+                    stage.index,
+                    if stage.walking { String::from("PathFinder") } else { self.stages[stage.index].task.clone() },
+                    stage.task.lock().unwrap().status(),
+                ),
+            None => result,
+        }
+    }
+}
+
+fn get_next_stage(stages: &[PipelineStageParams], from: usize, outcome: Outcome) -> Option<usize> {
+    (from..stages.len()).find(|index| match stages[*index].condition.unwrap_or(StageCondition::Done) {
+        StageCondition::Always => true,
+        StageCondition::Done => outcome == Outcome::Done,
+        StageCondition::Failed => outcome == Outcome::Failed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_stage(task: &str, condition: Option<StageCondition>) -> PipelineStageParams {
+        PipelineStageParams { task: String::from(task), params: None, destination: None, condition, repeat: None, on_failure: None }
+    }
+
+    #[test]
+    fn get_next_stage_should_skip_stages_with_unsatisfied_condition() {
+        let stages = vec![
+            make_stage("Explorer", None),
+            make_stage("Drinker", Some(StageCondition::Done)),
+            make_stage("Fleer", Some(StageCondition::Failed)),
+            make_stage("Logout", Some(StageCondition::Always)),
+        ];
+        assert_eq!(get_next_stage(&stages, 1, Outcome::Done), Some(1));
+        assert_eq!(get_next_stage(&stages, 1, Outcome::Failed), Some(2));
+        assert_eq!(get_next_stage(&stages, 2, Outcome::Done), Some(3));
+        assert_eq!(get_next_stage(&stages, 4, Outcome::Done), None);
+    }
+}
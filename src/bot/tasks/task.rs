@@ -1,13 +1,31 @@
-use crate::bot::protocol::{Message, Update};
+use crate::bot::protocol::{Message, TaskStatus, Update};
 use crate::bot::scene::Scene;
 use crate::bot::world::PlayerWorld;
 
 pub trait Task: Send {
-    fn name(&self) -> &'static str;
+    fn name(&self) -> &str;
 
     fn get_next_message(&mut self, world: &PlayerWorld, scene: &Scene) -> Option<Message>;
 
     fn update(&mut self, world: &PlayerWorld, update: &Update);
 
     fn restore(&mut self, world: &PlayerWorld);
+
+    fn on_inventory_full(&mut self, _: &PlayerWorld) {}
+
+    fn priority(&self, _: &PlayerWorld) -> i32 {
+        0
+    }
+
+    fn is_exclusive(&self) -> bool {
+        false
+    }
+
+    fn status(&self) -> TaskStatus {
+        TaskStatus::new("Running")
+    }
+
+    fn reconfigure(&mut self, _: &[u8]) -> Result<(), String> {
+        Err(format!("{} task does not support reconfiguration", self.name()))
+    }
 }
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::player::Item;
+use crate::bot::protocol::{Event, Message, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::Vec2i;
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct TransferrerConfig {
+    pub transfer_timeout: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum TransferDirection {
+    ToContainer,
+    FromContainer,
+}
+
+#[derive(Deserialize)]
+pub struct TransferrerParams {
+    pub container: String,
+    pub direction: TransferDirection,
+    pub resources: BTreeSet<String>,
+    pub content: Option<String>,
+    pub min_quality: Option<f32>,
+}
+
+struct Transfer {
+    item_id: i32,
+    started: Instant,
+}
+
+pub struct Transferrer {
+    params: TransferrerParams,
+    transfer: Option<Transfer>,
+    transferred: BTreeSet<i32>,
+    skipped: BTreeSet<i32>,
+    config: TransferrerConfig,
+}
+
+impl Transferrer {
+    pub fn new(params: TransferrerParams, config: TransferrerConfig) -> Self {
+        Self {
+            params,
+            transfer: None,
+            transferred: BTreeSet::new(),
+            skipped: BTreeSet::new(),
+            config,
+        }
+    }
+}
+
+impl Task for Transferrer {
+    fn name(&self) -> &str {
+        "Transferrer"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, _: &Scene) -> Option<Message> {
+        if let Some(transfer) = self.transfer.as_ref() {
+            if self.transferred.contains(&transfer.item_id) {
+                task_debug!("Transferrer: transferred item {}", transfer.item_id);
+                self.transfer = None;
+            } else if Instant::now() - transfer.started >= Duration::from_secs_f64(self.config.transfer_timeout) {
+                task_debug!("Transferrer: item {} is not transferred", transfer.item_id);
+                self.skipped.insert(transfer.item_id);
+                self.transfer = None;
+            } else {
+                task_debug!("Transferrer: wait for item {} transfer", transfer.item_id);
+                return None;
+            }
+        }
+        let container_items = match find_container_items(world, &self.params.container) {
+            Some(v) => v,
+            None => {
+                task_debug!("Transferrer: container {:?} is not found", self.params.container);
+                return None;
+            }
+        };
+        let src_items = match self.params.direction {
+            TransferDirection::ToContainer => world.player_inventory_items(),
+            TransferDirection::FromContainer => container_items,
+        };
+        let items = src_items.values()
+            .filter(|item| !self.skipped.contains(&item.id))
+            .filter_map(|item| world.resources().get(&item.resource).map(|resource| (item, &resource.name)));
+        let item_id = match select_item(items, &self.params) {
+            Some(v) => v,
+            None => {
+                task_debug!("Transferrer: no items to transfer, transferred={} skipped={}",
+                       self.transferred.len(), self.skipped.len());
+                return Some(Message::Done { task: String::from("Transferrer") });
+            }
+        };
+        task_debug!("Transferrer: transfer item {} {:?}", item_id, self.params.direction);
+        self.transfer = Some(Transfer { item_id, started: Instant::now() });
+        Some(Message::WidgetMessage {
+            sender: item_id,
+            kind: String::from("transfer"),
+            arguments: vec![Value::from(Vec2i::zero()), Value::from(1i32)],
+        })
+    }
+
+    fn update(&mut self, _: &PlayerWorld, update: &Update) {
+        if let Event::Destroy { id } = &update.event {
+            if self.transfer.as_ref().map(|v| v.item_id == *id).unwrap_or(false) {
+                self.transferred.insert(*id);
+            }
+        }
+    }
+
+    fn restore(&mut self, _: &PlayerWorld) {}
+
+    fn status(&self) -> TaskStatus {
+        TaskStatus::new(if self.transfer.is_some() { "Transfer" } else { "Search" })
+            .with_target(self.params.container.clone())
+            .with_counter("transferred", self.transferred.len())
+            .with_counter("skipped", self.skipped.len())
+    }
+}
+
+fn find_container_items<'a>(world: &'a PlayerWorld, container: &String) -> Option<&'a BTreeMap<i32, Item>> {
+    let widgets = world.widgets();
+    let window_id = widgets.values()
+        .find(|widget| widget.kind == "wnd" && widget.cargs.len() >= 2 && widget.cargs[1] == container.as_str())
+        .map(|widget| widget.id)?;
+    widgets.values()
+        .find(|widget| widget.kind == "inv" && widget.parent == window_id)
+        .and_then(|widget| world.player_inventories().get(&widget.id))
+}
+
+fn select_item<'a, I>(items: I, params: &TransferrerParams) -> Option<i32>
+    where I: Iterator<Item=(&'a Item, &'a String)> {
+    items
+        .filter(|(_, resource)| params.resources.contains(*resource))
+        .filter(|(item, _)| {
+            params.content.as_ref()
+                .map(|name| item.content.as_ref().map(|content| content.name.contains(name)).unwrap_or(false))
+                .unwrap_or(true)
+        })
+        .filter(|(item, _)| {
+            params.min_quality
+                .map(|min_quality| item.content.as_ref().map(|content| content.quality >= min_quality).unwrap_or(false))
+                .unwrap_or(true)
+        })
+        .map(|(item, _)| item.id)
+        .next()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::player::Content;
+
+    use super::*;
+
+    fn make_item(id: i32, content: Option<(&str, f32)>) -> Item {
+        Item {
+            id,
+            resource: 1,
+            content: content.map(|(name, quality)| Content { name: String::from(name), quality }),
+            position: None,
+        }
+    }
+
+    fn make_params(content: Option<&str>, min_quality: Option<f32>) -> TransferrerParams {
+        TransferrerParams {
+            container: String::from("Cupboard"),
+            direction: TransferDirection::ToContainer,
+            resources: vec![String::from("gfx/invobjs/bucket")].into_iter().collect(),
+            content: content.map(String::from),
+            min_quality,
+        }
+    }
+
+    fn select(items: &Vec<(Item, String)>, params: &TransferrerParams) -> Option<i32> {
+        select_item(items.iter().map(|(item, resource)| (item, resource)), params)
+    }
+
+    #[test]
+    fn select_item_should_filter_by_resource() {
+        let items = vec![
+            (make_item(1, None), String::from("gfx/invobjs/waterskin")),
+            (make_item(2, None), String::from("gfx/invobjs/bucket")),
+        ];
+        assert_eq!(select(&items, &make_params(None, None)), Some(2));
+    }
+
+    #[test]
+    fn select_item_should_filter_by_content_and_quality() {
+        let bucket = String::from("gfx/invobjs/bucket");
+        let items = vec![
+            (make_item(1, None), bucket.clone()),
+            (make_item(2, Some(("10 l of Milk", 30.0))), bucket.clone()),
+            (make_item(3, Some(("10 l of Water", 10.0))), bucket.clone()),
+            (make_item(4, Some(("10 l of Water", 30.0))), bucket.clone()),
+        ];
+        assert_eq!(select(&items, &make_params(Some("Water"), None)), Some(3));
+        assert_eq!(select(&items, &make_params(Some("Water"), Some(20.0))), Some(4));
+        assert_eq!(select(&items, &make_params(None, Some(40.0))), None);
+    }
+}
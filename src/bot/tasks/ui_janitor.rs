@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+
+use crate::bot::player::Widget;
+use crate::bot::protocol::{Event, Message, TaskStatus, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::task::Task;
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct UiJanitorConfig {
+    pub rules: Vec<UiJanitorRule>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct UiJanitorRule {
+    pub kind: String,
+    #[serde(default)]
+    pub cargs: Option<String>,
+    pub action: UiJanitorAction,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum UiJanitorAction {
+    Close,
+    Click { button: usize },
+    Ignore,
+}
+
+#[derive(Default, Deserialize)]
+pub struct UiJanitorParams {
+    pub rules: Option<Vec<UiJanitorRule>>,
+}
+
+impl UiJanitorParams {
+    pub fn exp_wnd_closer() -> Self {
+        Self {
+            rules: Some(vec![UiJanitorRule {
+                kind: String::from("ui/expwnd:*"),
+                cargs: None,
+                action: UiJanitorAction::Close,
+            }]),
+        }
+    }
+}
+
+pub struct UiJanitor {
+    rules: Vec<UiJanitorRule>,
+    widgets: BTreeMap<i32, UiJanitorAction>,
+    handled: BTreeSet<i32>,
+}
+
+impl UiJanitor {
+    pub fn new(params: UiJanitorParams, config: UiJanitorConfig) -> Self {
+        Self {
+            rules: params.rules.unwrap_or(config.rules),
+            widgets: BTreeMap::new(),
+            handled: BTreeSet::new(),
+        }
+    }
+
+    fn add_widget(&mut self, id: i32, kind: &String, cargs: &Vec<Value>) {
+        match find_action(&self.rules, kind, cargs) {
+            Some(UiJanitorAction::Ignore) | None => (),
+            Some(action) => {
+                task_debug!("UiJanitor: got a new widget {} {:?} to {:?}", id, kind, action);
+                self.widgets.insert(id, action);
+            }
+        }
+    }
+
+    fn remove_widget(&mut self, id: i32) {
+        if self.widgets.remove(&id).is_some() {
+            task_debug!("UiJanitor: widget {} is removed", id);
+        }
+        self.handled.remove(&id);
+    }
+}
+
+impl Task for UiJanitor {
+    fn name(&self) -> &str {
+        "UiJanitor"
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, _: &Scene) -> Option<Message> {
+        let handled = &self.handled;
+        let (id, message) = self.widgets.iter()
+            .filter(|(id, _)| !handled.contains(id))
+            .find_map(|(id, action)| make_message(*id, *action, world.widgets()).map(|message| (*id, message)))?;
+        task_debug!("UiJanitor: handle widget {} with {:?}", id, message);
+        self.handled.insert(id);
+        Some(message)
+    }
+
+    fn update(&mut self, _: &PlayerWorld, update: &Update) {
+        match &update.event {
+            Event::NewWidget { id, kind, parent: _, pargs: _, cargs } => {
+                self.add_widget(*id, kind, cargs);
+            }
+            Event::WidgetMessage { id, msg, args: _ } => {
+                if msg.as_str() == "close" {
+                    self.remove_widget(*id);
+                }
+            }
+            Event::Destroy { id } => {
+                self.remove_widget(*id);
+            }
+            _ => (),
+        }
+    }
+
+    fn restore(&mut self, world: &PlayerWorld) {
+        for widget in world.widgets().values() {
+            self.add_widget(widget.id, &widget.kind, &widget.cargs);
+        }
+    }
+
+    fn status(&self) -> TaskStatus {
+        TaskStatus::new("Watch").with_counter("widgets", self.widgets.keys().filter(|v| !self.handled.contains(v)).count())
+    }
+}
+
+fn make_message(id: i32, action: UiJanitorAction, widgets: &BTreeMap<i32, Widget>) -> Option<Message> {
+    match action {
+        UiJanitorAction::Close => Some(Message::WidgetMessage {
+            sender: id,
+            kind: String::from("close"),
+            arguments: Vec::new(),
+        }),
+        UiJanitorAction::Click { button } => widgets.values()
+            .filter(|v| v.parent == id && v.kind.as_str() == "btn")
+            .nth(button)
+            .map(|v| Message::WidgetMessage {
+                sender: v.id,
+                kind: String::from("activate"),
+                arguments: Vec::new(),
+            }),
+        UiJanitorAction::Ignore => None,
+    }
+}
+
+fn find_action(rules: &Vec<UiJanitorRule>, kind: &String, cargs: &Vec<Value>) -> Option<UiJanitorAction> {
+    rules.iter()
+        .find(|rule| {
+            match_pattern(rule.kind.as_str(), kind.as_str())
+                && rule.cargs.as_ref()
+                .map(|pattern| cargs.iter().any(|v| match v {
+                    Value::Str { value } => match_pattern(pattern.as_str(), value.as_str()),
+                    _ => false,
+                }))
+                .unwrap_or(true)
+        })
+        .map(|rule| rule.action)
+}
+
+fn match_pattern(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !value.starts_with(first) {
+        return false;
+    }
+    let mut rest = &value[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(v) => v,
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_pattern_should_support_wildcards() {
+        assert!(match_pattern("ui/expwnd:*", "ui/expwnd:4"));
+        assert!(match_pattern("ui/expwnd", "ui/expwnd"));
+        assert!(!match_pattern("ui/expwnd", "ui/expwnd:4"));
+        assert!(match_pattern("*Feast*", "A Feast is ready"));
+        assert!(match_pattern("a*b*c", "abbc"));
+        assert!(!match_pattern("a*b*c", "acb"));
+        assert!(match_pattern("*", ""));
+    }
+
+    #[test]
+    fn find_action_should_use_first_matching_rule() {
+        let rules = vec![
+            UiJanitorRule { kind: String::from("wnd"), cargs: Some(String::from("Inventory")), action: UiJanitorAction::Ignore },
+            UiJanitorRule { kind: String::from("wnd"), cargs: Some(String::from("*?")), action: UiJanitorAction::Click { button: 1 } },
+            UiJanitorRule { kind: String::from("ui/expwnd:*"), cargs: None, action: UiJanitorAction::Close },
+        ];
+        let make_cargs = |title: &str| vec![Value::Nil, Value::Str { value: String::from(title) }];
+        assert_eq!(find_action(&rules, &String::from("wnd"), &make_cargs("Inventory")), Some(UiJanitorAction::Ignore));
+        assert_eq!(find_action(&rules, &String::from("wnd"), &make_cargs("Really leave?")), Some(UiJanitorAction::Click { button: 1 }));
+        assert_eq!(find_action(&rules, &String::from("wnd"), &make_cargs("Equipment")), None);
+        assert_eq!(find_action(&rules, &String::from("ui/expwnd:3"), &Vec::new()), Some(UiJanitorAction::Close));
+    }
+}
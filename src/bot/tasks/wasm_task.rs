@@ -0,0 +1,298 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use wasmi::{Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store};
+use wasmi::core::F64;
+
+use crate::bot::map::pos_to_map_pos;
+use crate::bot::math::as_score;
+use crate::bot::objects::{Object, Objects};
+use crate::bot::protocol::{Button, Message, Modifier, Update, Value};
+use crate::bot::scene::Scene;
+use crate::bot::tasks::task::Task;
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::PlayerWorld;
+
+const HOST_MODULE: &'static str = "hafen_bot";
+
+#[derive(Clone, Deserialize)]
+pub struct WasmTaskConfig {
+    pub modules_path: String,
+    pub max_fuel: u64,
+}
+
+#[derive(Default)]
+struct WorldView {
+    player_position: Vec2f,
+    player_segment_id: i64,
+    player_object_id: i64,
+    map_view_id: i32,
+    game_ui_id: i32,
+    is_player_stuck: bool,
+    objects: Arc<Objects>,
+}
+
+impl WorldView {
+    fn new(world: &PlayerWorld) -> Self {
+        Self {
+            player_position: world.player_position(),
+            player_segment_id: world.player_segment_id(),
+            player_object_id: world.player_object_id(),
+            map_view_id: world.map_view_id(),
+            game_ui_id: world.game_ui_id(),
+            is_player_stuck: world.is_player_stuck(),
+            objects: world.shared_objects(),
+        }
+    }
+
+    fn get_object(&self, id: i64) -> Option<&Object> {
+        self.objects.get_by_id(id)
+    }
+}
+
+#[derive(Default)]
+struct Host {
+    world: WorldView,
+    message: Option<Message>,
+    done: bool,
+}
+
+pub struct WasmTask {
+    name: String,
+    store: Store<Host>,
+    instance: Instance,
+    fuel: u64,
+    config: WasmTaskConfig,
+}
+
+impl WasmTask {
+    pub fn new(name: &str, params: &[u8], config: WasmTaskConfig) -> Result<Self, String> {
+        let module_path = get_module_path(&config.modules_path, name)
+            .ok_or_else(|| format!("Invalid wasm module name: {:?}", name))?;
+        let content = std::fs::read(&module_path)
+            .map_err(|e| format!("Failed to read wasm module {}: {}", module_path, e))?;
+        let mut engine_config = Config::default();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config);
+        let module = Module::new(&engine, &content[..])
+            .map_err(|e| format!("Failed to parse wasm module {}: {}", module_path, e))?;
+        let mut store = Store::new(&engine, Host::default());
+        let linker = make_linker(&engine)
+            .map_err(|e| format!("Failed to link wasm module {}: {}", module_path, e))?;
+        let instance = linker.instantiate(&mut store, &module)
+            .and_then(|v| v.start(&mut store))
+            .map_err(|e| format!("Failed to instantiate wasm module {}: {}", module_path, e))?;
+        let mut result = Self {
+            name: String::from(name),
+            store,
+            instance,
+            fuel: 0,
+            config,
+        };
+        if result.instance.get_func(&result.store, "init").is_some() {
+            result.call_with_data("init", params)?;
+        }
+        Ok(result)
+    }
+
+    fn refuel(&mut self) -> Result<(), String> {
+        let remaining = self.fuel - self.store.fuel_consumed().unwrap_or(0);
+        if remaining < self.config.max_fuel {
+            self.store.add_fuel(self.config.max_fuel - remaining).map_err(|e| format!("Failed to add fuel: {}", e))?;
+            self.fuel += self.config.max_fuel - remaining;
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str) -> Result<(), String> {
+        self.refuel()?;
+        let func = self.instance.get_typed_func::<(), ()>(&self.store, name)
+            .map_err(|e| format!("Failed to get {}: {}", name, e))?;
+        func.call(&mut self.store, ()).map_err(|e| format!("Failed to call {}: {}", name, e))
+    }
+
+    fn call_with_data(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        self.refuel()?;
+        let memory = self.get_memory()?;
+        let alloc = self.instance.get_typed_func::<i32, i32>(&self.store, "alloc")
+            .map_err(|e| format!("Failed to get alloc: {}", e))?;
+        let ptr = alloc.call(&mut self.store, data.len() as i32)
+            .map_err(|e| format!("Failed to call alloc: {}", e))?;
+        memory.write(&mut self.store, ptr as usize, data)
+            .map_err(|e| format!("Failed to write {} bytes at {}: {}", data.len(), ptr, e))?;
+        let func = self.instance.get_typed_func::<(i32, i32), ()>(&self.store, name)
+            .map_err(|e| format!("Failed to get {}: {}", name, e))?;
+        func.call(&mut self.store, (ptr, data.len() as i32)).map_err(|e| format!("Failed to call {}: {}", name, e))
+    }
+
+    fn get_memory(&self) -> Result<Memory, String> {
+        self.instance.get_memory(&self.store, "memory")
+            .ok_or_else(|| String::from("Memory is not exported"))
+    }
+}
+
+impl Task for WasmTask {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn get_next_message(&mut self, world: &PlayerWorld, _: &Scene) -> Option<Message> {
+        *self.store.data_mut() = Host { world: WorldView::new(world), message: None, done: false };
+        if let Err(e) = self.call("next") {
+            task_error!("WasmTask {}: {}", self.name, e);
+            return Some(Message::Done { task: self.name.clone() });
+        }
+        let host = std::mem::take(self.store.data_mut());
+        if host.done {
+            task_debug!("WasmTask {}: done", self.name);
+            return Some(Message::Done { task: self.name.clone() });
+        }
+        host.message
+    }
+
+    fn update(&mut self, world: &PlayerWorld, update: &Update) {
+        if self.instance.get_func(&self.store, "update").is_none() {
+            return;
+        }
+        *self.store.data_mut() = Host { world: WorldView::new(world), message: None, done: false };
+        if let Err(e) = self.call_with_data("update", &serde_json::to_vec(update).unwrap()) {
+            task_error!("WasmTask {}: {}", self.name, e);
+        }
+        // Release shared objects so world updates don't have to copy them
+        std::mem::take(self.store.data_mut());
+    }
+
+    fn restore(&mut self, _: &PlayerWorld) {}
+}
+
+pub fn get_module_path(path: &String, name: &str) -> Option<String> {
+    if name.is_empty() || !name.chars().all(|v| v.is_ascii_alphanumeric() || v == '_' || v == '-') {
+        return None;
+    }
+    Some(format!("{}/{}.wasm", path, name))
+}
+
+pub fn is_wasm_task(path: &String, name: &str) -> bool {
+    get_module_path(path, name).map(|v| Path::new(&v).is_file()).unwrap_or(false)
+}
+
+fn make_linker(engine: &Engine) -> Result<Linker<Host>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+    linker
+        .func_wrap(HOST_MODULE, "player_x", |caller: Caller<'_, Host>| -> F64 {
+            F64::from(caller.data().world.player_position.x())
+        })?
+        .func_wrap(HOST_MODULE, "player_y", |caller: Caller<'_, Host>| -> F64 {
+            F64::from(caller.data().world.player_position.y())
+        })?
+        .func_wrap(HOST_MODULE, "player_segment_id", |caller: Caller<'_, Host>| -> i64 {
+            caller.data().world.player_segment_id
+        })?
+        .func_wrap(HOST_MODULE, "player_object_id", |caller: Caller<'_, Host>| -> i64 {
+            caller.data().world.player_object_id
+        })?
+        .func_wrap(HOST_MODULE, "map_view_id", |caller: Caller<'_, Host>| -> i32 {
+            caller.data().world.map_view_id
+        })?
+        .func_wrap(HOST_MODULE, "game_ui_id", |caller: Caller<'_, Host>| -> i32 {
+            caller.data().world.game_ui_id
+        })?
+        .func_wrap(HOST_MODULE, "is_player_stuck", |caller: Caller<'_, Host>| -> i32 {
+            caller.data().world.is_player_stuck as i32
+        })?
+        .func_wrap(HOST_MODULE, "find_object", |caller: Caller<'_, Host>, ptr: i32, len: i32| -> i64 {
+            let name = match read_string(&caller, ptr, len) {
+                Some(v) => v,
+                None => return -1,
+            };
+            let world = &caller.data().world;
+            world.objects.iter()
+                .filter(|v| v.id != world.player_object_id && v.name.as_ref() == Some(&name))
+                .min_by_key(|v| as_score(v.position.distance(world.player_position)))
+                .map(|v| v.id)
+                .unwrap_or(-1)
+        })?
+        .func_wrap(HOST_MODULE, "object_x", |caller: Caller<'_, Host>, id: i64| -> F64 {
+            F64::from(caller.data().world.get_object(id).map(|v| v.position.x()).unwrap_or(f64::NAN))
+        })?
+        .func_wrap(HOST_MODULE, "object_y", |caller: Caller<'_, Host>, id: i64| -> F64 {
+            F64::from(caller.data().world.get_object(id).map(|v| v.position.y()).unwrap_or(f64::NAN))
+        })?
+        .func_wrap(HOST_MODULE, "click", |mut caller: Caller<'_, Host>, x: F64, y: F64| {
+            let sender = caller.data().world.map_view_id;
+            caller.data_mut().message = Some(Message::WidgetMessage {
+                sender,
+                kind: String::from("click"),
+                arguments: vec![
+                    Value::from(Vec2i::zero()),
+                    Value::from(pos_to_map_pos(Vec2f::new(x.to_float(), y.to_float()))),
+                    Value::from(Button::LeftClick),
+                    Value::from(Modifier::None),
+                ],
+            });
+        })?
+        .func_wrap(HOST_MODULE, "widget_message", |mut caller: Caller<'_, Host>, sender: i32,
+                                                    kind_ptr: i32, kind_len: i32, args_ptr: i32, args_len: i32| -> i32 {
+            let kind = match read_string(&caller, kind_ptr, kind_len) {
+                Some(v) => v,
+                None => return -1,
+            };
+            let arguments = match read_bytes(&caller, args_ptr, args_len)
+                .and_then(|v| serde_json::from_slice::<Vec<Value>>(&v).ok()) {
+                Some(v) => v,
+                None => return -1,
+            };
+            caller.data_mut().message = Some(Message::WidgetMessage { sender, kind, arguments });
+            0
+        })?
+        .func_wrap(HOST_MODULE, "done", |mut caller: Caller<'_, Host>| {
+            caller.data_mut().done = true;
+        })?
+        .func_wrap(HOST_MODULE, "log", |caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            if let Some(message) = read_string(&caller, ptr, len) {
+                task_debug!("WasmTask: {}", message);
+            }
+        })?;
+    Ok(linker)
+}
+
+fn read_bytes(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let mut buffer = vec![0; len.max(0) as usize];
+    memory.read(caller, ptr as usize, &mut buffer).ok()?;
+    Some(buffer)
+}
+
+fn read_string(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Option<String> {
+    read_bytes(caller, ptr, len).and_then(|v| String::from_utf8(v).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_module_path_should_reject_invalid_names() {
+        assert_eq!(get_module_path(&String::from("var/wasm"), "Miner"), Some(String::from("var/wasm/Miner.wasm")));
+        assert_eq!(get_module_path(&String::from("var/wasm"), "../Miner"), None);
+        assert_eq!(get_module_path(&String::from("var/wasm"), ""), None);
+    }
+
+    #[test]
+    fn wasm_task_should_be_named_after_module() {
+        let modules_path = std::env::temp_dir().join("hafen_bot_wasm_task");
+        std::fs::create_dir_all(&modules_path).unwrap();
+        std::fs::write(modules_path.join("Stepper.wasm"), wat::parse_str(r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "next")))
+        "#).unwrap()).unwrap();
+        let mut task = WasmTask::new("Stepper", &[], WasmTaskConfig {
+            modules_path: String::from(modules_path.to_str().unwrap()),
+            max_fuel: 1000,
+        }).unwrap();
+        assert_eq!(task.name(), "Stepper");
+        assert_eq!(task.reconfigure(&[]), Err(String::from("Stepper task does not support reconfiguration")));
+    }
+}
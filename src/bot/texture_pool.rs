@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::spawn;
+
+use image::{Rgba, RgbaImage};
+
+use crate::bot::map::{GRID_SIZE, tile_index_to_tile_pos, TILE_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GridTextureMode {
+    Tiles,
+    Heights,
+}
+
+impl GridTextureMode {
+    pub fn next(self) -> Self {
+        match self {
+            GridTextureMode::Tiles => GridTextureMode::Heights,
+            GridTextureMode::Heights => GridTextureMode::Tiles,
+        }
+    }
+}
+
+pub struct GridImageJob {
+    pub grid_id: i64,
+    pub revision: i64,
+    pub mode: GridTextureMode,
+    pub tiles: Vec<i32>,
+    pub heights: Vec<f32>,
+    pub colors: BTreeMap<i32, [u8; 4]>,
+    pub result: Sender<GridImage>,
+}
+
+pub struct GridImage {
+    pub grid_id: i64,
+    pub revision: i64,
+    pub mode: GridTextureMode,
+    pub image: RgbaImage,
+}
+
+#[derive(Clone)]
+pub struct TexturePool {
+    jobs: Sender<GridImageJob>,
+}
+
+impl TexturePool {
+    pub fn new(workers: usize) -> Self {
+        let (sender, receiver) = channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers.max(1) {
+            let receiver = receiver.clone();
+            spawn(move || run_worker(receiver));
+        }
+        Self { jobs: sender }
+    }
+
+    pub fn submit(&self, job: GridImageJob) -> bool {
+        self.jobs.send(job).is_ok()
+    }
+}
+
+fn run_worker(jobs: Arc<Mutex<Receiver<GridImageJob>>>) {
+    loop {
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(v) => v,
+            Err(_) => break,
+        };
+        let image = match job.mode {
+            GridTextureMode::Tiles => make_grid_image(&job.tiles, &job.colors),
+            GridTextureMode::Heights => make_heights_image(&job.heights),
+        };
+        job.result.send(GridImage { grid_id: job.grid_id, revision: job.revision, mode: job.mode, image }).ok();
+    }
+}
+
+fn make_grid_image(tiles: &Vec<i32>, colors: &BTreeMap<i32, [u8; 4]>) -> RgbaImage {
+    let mut image = RgbaImage::new(GRID_SIZE as u32, GRID_SIZE as u32);
+    for (index, tile_id) in tiles.iter().enumerate() {
+        let position = tile_index_to_tile_pos(index);
+        let color = colors.get(tile_id).cloned().unwrap_or([255, 255, 255, 255]);
+        image.put_pixel(position.x() as u32, position.y() as u32, Rgba(color));
+    }
+    image
+}
+
+fn make_heights_image(heights: &Vec<f32>) -> RgbaImage {
+    let mut image = RgbaImage::new(GRID_SIZE as u32, GRID_SIZE as u32);
+    if heights.len() != (GRID_SIZE * GRID_SIZE) as usize {
+        for pixel in image.pixels_mut() {
+            *pixel = Rgba([255, 0, 255, 255]);
+        }
+        return image;
+    }
+    let min = heights.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = heights.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    for index in 0..heights.len() {
+        let position = tile_index_to_tile_pos(index);
+        let relative = if max > min { (heights[index] - min) / (max - min) } else { 0.5 };
+        // Relative height gives the base brightness, hillshade adds relief on top of it
+        let value = get_hillshade(heights, position.x(), position.y()) * (0.5 + 0.5 * relative as f64);
+        let component = (value.max(0.0).min(1.0) * 255.0).round() as u8;
+        image.put_pixel(position.x() as u32, position.y() as u32, Rgba([component, component, component, 255]));
+    }
+    image
+}
+
+// Lambertian shading of a tile lit from north-west at 45 degrees above the horizon
+fn get_hillshade(heights: &Vec<f32>, x: i32, y: i32) -> f64 {
+    let get = |x: i32, y: i32| heights[(x.max(0).min(GRID_SIZE - 1) + y.max(0).min(GRID_SIZE - 1) * GRID_SIZE) as usize] as f64;
+    let dx = (get(x + 1, y) - get(x - 1, y)) / (2.0 * TILE_SIZE);
+    let dy = (get(x, y + 1) - get(x, y - 1)) / (2.0 * TILE_SIZE);
+    let light = (-1.0 / 2.0, -1.0 / 2.0, std::f64::consts::FRAC_1_SQRT_2);
+    let normal_length = (dx * dx + dy * dy + 1.0).sqrt();
+    ((-dx * light.0 - dy * light.1 + light.2) / normal_length).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_pool_should_send_grid_image_to_job_result() {
+        let pool = TexturePool::new(2);
+        let (sender, receiver) = channel();
+        let mut tiles = vec![0; (GRID_SIZE * GRID_SIZE) as usize];
+        tiles[1] = 1;
+        tiles[2] = 2;
+        let colors = vec![(0, [0, 0, 0, 255]), (1, [10, 20, 30, 255])].into_iter().collect();
+        let job = GridImageJob {
+            grid_id: 42,
+            revision: 13,
+            mode: GridTextureMode::Tiles,
+            tiles,
+            heights: Vec::new(),
+            colors,
+            result: sender,
+        };
+        assert!(pool.submit(job));
+        let result = receiver.recv().unwrap();
+        assert_eq!(result.grid_id, 42);
+        assert_eq!(result.revision, 13);
+        assert_eq!(result.mode, GridTextureMode::Tiles);
+        assert_eq!(result.image.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(result.image.get_pixel(1, 0), &Rgba([10, 20, 30, 255]));
+        assert_eq!(result.image.get_pixel(2, 0), &Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn make_heights_image_should_shade_slopes_by_light_direction() {
+        let mut heights = vec![0.0; (GRID_SIZE * GRID_SIZE) as usize];
+        for index in 0..heights.len() {
+            let position = tile_index_to_tile_pos(index);
+            if position.x() < GRID_SIZE / 2 {
+                heights[index] = 20.0;
+            }
+        }
+        let image = make_heights_image(&heights);
+        let flat_high = image.get_pixel(1, 1)[0];
+        let flat_low = image.get_pixel(GRID_SIZE as u32 - 2, 1)[0];
+        let cliff = image.get_pixel(GRID_SIZE as u32 / 2 - 1, 1)[0];
+        assert!(flat_low < flat_high, "{} {}", flat_low, flat_high);
+        assert!(cliff < flat_low, "{} {}", cliff, flat_low);
+    }
+
+    #[test]
+    fn get_hillshade_should_light_slopes_facing_north_west() {
+        let facing_west: Vec<f32> = (0..GRID_SIZE * GRID_SIZE)
+            .map(|index| tile_index_to_tile_pos(index as usize).x() as f32).collect();
+        let facing_east: Vec<f32> = facing_west.iter().map(|v| -v).collect();
+        let flat = vec![0.0; (GRID_SIZE * GRID_SIZE) as usize];
+        assert_eq!(get_hillshade(&flat, 10, 10), std::f64::consts::FRAC_1_SQRT_2);
+        assert!(get_hillshade(&facing_west, 10, 10) > get_hillshade(&flat, 10, 10));
+        assert!(get_hillshade(&facing_east, 10, 10) < get_hillshade(&flat, 10, 10));
+    }
+}
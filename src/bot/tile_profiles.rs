@@ -0,0 +1,82 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use crate::bot::world::WorldConfig;
+
+pub const WATER_PROFILE: &'static str = "water";
+pub const ICE_PROFILE: &'static str = "ice";
+
+pub struct TileProfiles {
+    values: RwLock<BTreeMap<String, Arc<HashMap<String, f64>>>>,
+}
+
+impl TileProfiles {
+    pub fn new(config: &WorldConfig) -> Self {
+        let mut values = BTreeMap::new();
+        values.insert(String::from(WATER_PROFILE), Arc::new(config.water_tiles.clone()));
+        values.insert(String::from(ICE_PROFILE), Arc::new(config.ice_tiles.clone()));
+        for (name, tiles) in config.movement_profiles.iter() {
+            values.insert(name.clone(), Arc::new(tiles.clone()));
+        }
+        Self { values: RwLock::new(values) }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<HashMap<String, f64>>> {
+        self.values.read().unwrap().get(name).map(Arc::clone)
+    }
+
+    pub fn get_all(&self) -> BTreeMap<String, BTreeMap<String, f64>> {
+        self.values.read().unwrap().iter()
+            .map(|(name, tiles)| (name.clone(), tiles.iter().map(|(k, v)| (k.clone(), *v)).collect()))
+            .collect()
+    }
+
+    pub fn set(&self, name: &str, tiles: HashMap<String, f64>) -> Result<(), String> {
+        validate_profile(name, &tiles)?;
+        self.values.write().unwrap().insert(String::from(name), Arc::new(tiles));
+        Ok(())
+    }
+
+    pub fn remove(&self, name: &str) -> Result<(), String> {
+        if name == WATER_PROFILE || name == ICE_PROFILE {
+            return Err(format!("Tile profile {:?} is builtin and can't be removed", name));
+        }
+        match self.values.write().unwrap().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(format!("Tile profile {:?} is not found", name)),
+        }
+    }
+}
+
+fn validate_profile(name: &str, tiles: &HashMap<String, f64>) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(String::from("Tile profile name is empty"));
+    }
+    if tiles.is_empty() {
+        return Err(format!("Tile profile {:?} has no tiles", name));
+    }
+    if let Some((tile, weight)) = tiles.iter().find(|(_, weight)| !weight.is_finite() || **weight <= 0.0) {
+        return Err(format!("Tile profile {:?} has invalid weight {} for tile {:?}", name, weight, tile));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_should_validate_and_replace_profile() {
+        let profiles = TileProfiles { values: RwLock::new(BTreeMap::new()) };
+        let tiles: HashMap<String, f64> = vec![(String::from("gfx/tiles/grass"), 1.0)].into_iter().collect();
+        assert_eq!(profiles.set("walk", tiles.clone()), Ok(()));
+        assert_eq!(profiles.get("walk").map(|v| v.as_ref().clone()), Some(tiles));
+        let invalid: HashMap<String, f64> = vec![(String::from("gfx/tiles/grass"), 0.0)].into_iter().collect();
+        assert!(profiles.set("walk", invalid).is_err());
+        assert!(profiles.set("empty", HashMap::new()).is_err());
+        assert_eq!(profiles.get_all().len(), 1);
+        assert!(profiles.remove(WATER_PROFILE).is_err());
+        assert_eq!(profiles.remove("walk"), Ok(()));
+        assert!(profiles.remove("walk").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::bot::map::pos_to_map_pos;
+use crate::bot::objects::Object;
+use crate::bot::protocol::{Button, Message, Modifier, Value};
+use crate::bot::vec2::{Vec2f, Vec2i};
+use crate::bot::world::PlayerWorld;
+
+#[derive(Clone, Deserialize)]
+pub struct TraversalConfig {
+    pub openable: HashMap<String, f64>,
+    pub open_distance: f64,
+    pub open_timeout: f64,
+}
+
+pub enum Traversal {
+    Free,
+    Wait,
+    Open(Message),
+}
+
+pub struct DoorOpener {
+    opening: Option<(i64, Instant)>,
+    opened: BTreeSet<i64>,
+}
+
+impl DoorOpener {
+    pub fn new() -> Self {
+        Self {
+            opening: None,
+            opened: BTreeSet::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.opening = None;
+        self.opened.clear();
+    }
+
+    pub fn opened(&self) -> usize {
+        self.opened.len()
+    }
+
+    pub fn traverse(&mut self, world: &PlayerWorld, target: Vec2f, now: Instant) -> Traversal {
+        let config = &world.config().traversal;
+        let player_position = world.player_position();
+        if let Some((object_id, started)) = self.opening {
+            let reached = world.get_object_by_id(object_id)
+                .map(|v| v.position.distance(player_position) <= config.open_distance)
+                .unwrap_or(true);
+            if !reached && now - started < Duration::from_secs_f64(config.open_timeout) {
+                return Traversal::Wait;
+            }
+            debug!("DoorOpener: object {} is opened", object_id);
+            self.opening = None;
+            self.opened.insert(object_id);
+        }
+        let opened = &self.opened;
+        match world.find_openable_objects(player_position, target).into_iter().find(|v| !opened.contains(&v.id)) {
+            Some(object) => {
+                debug!("DoorOpener: open object {} {:?} at {:?}", object.id, object.name, object.position);
+                self.opening = Some((object.id, now));
+                Traversal::Open(make_open_message(world, object))
+            }
+            None => Traversal::Free,
+        }
+    }
+}
+
+fn make_open_message(world: &PlayerWorld, object: &Object) -> Message {
+    Message::WidgetMessage {
+        sender: world.map_view_id(),
+        kind: String::from("click"),
+        arguments: vec![
+            Value::from(Vec2i::zero()),
+            Value::from(pos_to_map_pos(object.position)),
+            Value::from(Button::RightClick),
+            Value::from(Modifier::None),
+            Value::from(0i32),
+            Value::from(object.id as i32),
+            Value::from(pos_to_map_pos(object.position)),
+            Value::from(0i32),
+            Value::from(0i32),
+        ],
+    }
+}
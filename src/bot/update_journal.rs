@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::sync::Mutex;
+
+use crate::bot::protocol::Update;
+
+pub struct UpdateJournals {
+    path: String,
+    enabled: bool,
+    files: Mutex<HashMap<i64, File>>,
+}
+
+impl UpdateJournals {
+    pub fn new(path: String, enabled: bool) -> Self {
+        Self {
+            path,
+            enabled,
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn append(&self, update: &Update) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let mut files = self.files.lock().unwrap();
+        let file = match files.entry(update.session) {
+            Entry::Occupied(v) => v.into_mut(),
+            Entry::Vacant(v) => v.insert(open_journal(&self.path, update.session)?),
+        };
+        let mut line = serde_json::to_vec(update).unwrap();
+        line.push(b'\n');
+        file.write_all(&line)
+            .and_then(|_| file.sync_data())
+            .map_err(|e| format!("Failed to write update {} to journal of session {}: {}", update.number, update.session, e))
+    }
+
+    pub fn read(&self, session_id: i64, applied: i64) -> Result<Vec<Update>, String> {
+        if !self.enabled {
+            return Ok(Vec::new());
+        }
+        let _files = self.files.lock().unwrap();
+        read_journal(&get_journal_path(&self.path, session_id), applied)
+    }
+
+    pub fn compact(&self, session_id: i64, applied: i64) -> Result<usize, String> {
+        if !self.enabled {
+            return Ok(0);
+        }
+        let mut files = self.files.lock().unwrap();
+        let path = get_journal_path(&self.path, session_id);
+        let pending = read_journal(&path, applied)?;
+        files.remove(&session_id);
+        let mut content = Vec::new();
+        for update in pending.iter() {
+            content.extend(serde_json::to_vec(update).unwrap());
+            content.push(b'\n');
+        }
+        let tmp_path = format!("{}.tmp", path);
+        std::fs::write(&tmp_path, content)
+            .and_then(|_| std::fs::rename(&tmp_path, &path))
+            .map_err(|e| format!("Failed to compact journal {}: {}", path, e))?;
+        Ok(pending.len())
+    }
+
+    pub fn remove(&self, session_id: i64) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let mut files = self.files.lock().unwrap();
+        files.remove(&session_id);
+        let path = get_journal_path(&self.path, session_id);
+        match std::fs::remove_file(&path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to remove journal {}: {}", path, e)),
+        }
+    }
+}
+
+fn open_journal(path: &String, session_id: i64) -> Result<File, String> {
+    let journal_path = get_journal_path(path, session_id);
+    std::fs::create_dir_all(path)
+        .and_then(|_| OpenOptions::new().create(true).append(true).open(&journal_path))
+        .and_then(|file| {
+            let content = std::fs::read(&journal_path)?;
+            let written = content.iter().rposition(|v| *v == b'\n').map(|v| v + 1).unwrap_or(0);
+            if written < content.len() {
+                warn!("Truncate incomplete update at the end of journal {}", journal_path);
+                file.set_len(written as u64)?;
+            }
+            Ok(file)
+        })
+        .map_err(|e| format!("Failed to open journal of session {}: {}", session_id, e))
+}
+
+fn read_journal(path: &String, applied: i64) -> Result<Vec<Update>, String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read journal {}: {}", path, e)),
+    };
+    let written = &content[0..content.rfind('\n').map(|v| v + 1).unwrap_or(0)];
+    let mut result: Vec<Update> = Vec::new();
+    for line in written.lines().filter(|v| !v.is_empty()) {
+        match serde_json::from_str::<Update>(line) {
+            Ok(v) => if v.number > applied && result.last().map(|last| last.number < v.number).unwrap_or(true) {
+                result.push(v);
+            },
+            Err(e) => return Err(format!("Failed to parse update from {}: {}", path, e)),
+        }
+    }
+    Ok(result)
+}
+
+fn get_journal_path(path: &String, session_id: i64) -> String {
+    format!("{}/{}.journal.json", path, session_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::protocol::Event;
+
+    use super::*;
+
+    #[test]
+    fn journal_should_keep_only_unapplied_updates() {
+        let path = String::from(std::env::temp_dir().join("hafen_bot_update_journal").to_str().unwrap());
+        std::fs::remove_dir_all(&path).ok();
+        let journals = UpdateJournals::new(path.clone(), true);
+        let make_update = |number| Update { session: 1, number, event: Event::GetSessionData };
+        for number in &[1, 2, 2, 3, 4] {
+            assert_eq!(journals.append(&make_update(*number)), Ok(()));
+        }
+        std::fs::OpenOptions::new().append(true).open(get_journal_path(&path, 1)).unwrap()
+            .write_all(b"{\"session\":1,\"num").unwrap();
+        assert_eq!(journals.read(1, 1), Ok(vec![make_update(2), make_update(3), make_update(4)]));
+        assert_eq!(journals.compact(1, 3), Ok(1));
+        assert_eq!(journals.append(&make_update(5)), Ok(()));
+        assert_eq!(journals.read(1, 0), Ok(vec![make_update(4), make_update(5)]));
+        assert_eq!(journals.remove(1), Ok(()));
+        assert_eq!(journals.read(1, 0), Ok(Vec::new()));
+        std::fs::remove_dir_all(&path).ok();
+    }
+}
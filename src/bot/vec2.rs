@@ -46,6 +46,16 @@ impl Vec2f {
         (other - *self).norm()
     }
 
+    #[inline(always)]
+    pub fn dot(&self, other: Self) -> f64 {
+        self.x * other.x + self.y * other.y
+    }
+
+    #[inline(always)]
+    pub fn cross(&self, other: Self) -> f64 {
+        self.x * other.y - self.y * other.x
+    }
+
     #[inline(always)]
     pub fn signum(&self) -> Self {
         Self { x: self.x.signum(), y: self.y.signum() }
@@ -1,21 +1,23 @@
 use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap, VecDeque};
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
 use std::sync::{Arc, Mutex, RwLock};
-use std::thread::{JoinHandle, spawn};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{JoinHandle, sleep, spawn};
 use std::time::{Duration, Instant};
 
 use glutin_window::GlutinWindow;
-use graphics::{clear, Ellipse, Image, Rectangle, Transformed};
+use graphics::{clear, Ellipse, Graphics, Image, Rectangle, Transformed};
+use graphics::character::CharacterCache;
 use graphics::math::identity;
 use graphics::rectangle::{centered_square, square};
 use graphics::text::Text;
-use image::{Rgba, RgbaImage};
-use opengl_graphics::{Filter, GlGraphics, GlyphCache, OpenGL, Texture, TextureSettings};
-use piston::{EventLoop, RenderArgs, RenderEvent, UpdateArgs, UpdateEvent, Window};
+use opengl_graphics::{Filter, GlGraphics, GlyphCache, OpenGL, TextureSettings};
+use piston::{EventLoop, RenderEvent, UpdateEvent, Window};
 use piston::event_loop::{Events, EventSettings};
 use piston::input::{
     Button,
+    Key,
     MouseButton,
     MouseRelativeEvent,
     MouseScrollEvent,
@@ -26,12 +28,14 @@ use piston::window::WindowSettings;
 use sdl2_window::Sdl2Window;
 use serde::Deserialize;
 
-use crate::bot::map::{Grid, grid_pos_to_pos, GRID_SIZE, tile_index_to_tile_pos, TILE_SIZE};
+use crate::bot::map::{Grid, grid_pos_to_pos, GRID_SIZE, pos_to_tile_pos, TILE_SIZE};
 use crate::bot::map_db::MapDb;
+use crate::bot::message_queue::MessageQueue;
+use crate::bot::offscreen::{OffscreenGlyphCache, OffscreenGraphics};
 use crate::bot::process::{count_updates, UpdatesQueue};
-use crate::bot::protocol::Message;
-use crate::bot::scene::{CompositeVecNode, Context, DebugTextNode, EllipseNode, ImageNode, MapTransformBoxNode, Node, Scene, TextNode};
+use crate::bot::scene::{CompositeVecNode, Context, DebugTextNode, EllipseNode, ImageNode, MapTransformBoxNode, Node, Scene, SceneImage, SceneTexture, TextNode};
 use crate::bot::session::Session;
+use crate::bot::texture_pool::{GridImage, GridImageJob, GridTextureMode, TexturePool};
 use crate::bot::vec2::{Vec2f, Vec2i};
 use crate::bot::world::PlayerWorld;
 
@@ -39,53 +43,99 @@ use crate::bot::world::PlayerWorld;
 pub enum WindowType {
     Glutin,
     SDL2,
+    Offscreen,
 }
 
 #[derive(Clone, Deserialize)]
 pub struct VisualizationConfig {
     window_type: WindowType,
+    combined: bool,
+    texture_workers: usize,
+    offscreen: OffscreenConfig,
+}
+
+impl VisualizationConfig {
+    pub fn combined(&self) -> bool {
+        self.combined
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct OffscreenConfig {
+    width: u32,
+    height: u32,
+    frame_interval: f64,
 }
 
 pub fn start_visualize_session(session_id: i64, session: Arc<RwLock<Session>>, scene: Scene,
-                               updates: Arc<UpdatesQueue>, messages: Arc<Mutex<VecDeque<Message>>>,
+                               updates: Arc<UpdatesQueue>, messages: Arc<Mutex<MessageQueue>>,
                                map_db: Arc<Mutex<dyn MapDb + Send>>, config: VisualizationConfig) -> JoinHandle<()> {
-    spawn(move || visualize_session(session_id, session, scene.nodes(), updates, messages, map_db, config))
+    spawn(move || visualize_session(session_id, session, scene, updates, messages, map_db, config))
 }
 
-fn visualize_session(session_id: i64, session: Arc<RwLock<Session>>,
-                     layers: Arc<Mutex<BTreeMap<usize, Arc<Mutex<Node>>>>>,
-                     updates: Arc<UpdatesQueue>, messages: Arc<Mutex<VecDeque<Message>>>,
+fn visualize_session(session_id: i64, session: Arc<RwLock<Session>>, scene: Scene,
+                     updates: Arc<UpdatesQueue>, messages: Arc<Mutex<MessageQueue>>,
                      map_db: Arc<Mutex<dyn MapDb + Send>>, config: VisualizationConfig) {
+    let layers = scene.nodes();
+    let texture_pool = TexturePool::new(config.texture_workers);
     let opengl = OpenGL::V4_5;
     let settings = WindowSettings::new(format!("Session {}", session_id), [1920, 1080])
         .graphics_api(opengl)
         .exit_on_esc(true);
     match config.window_type {
         WindowType::Glutin => match settings.build::<GlutinWindow>() {
-            Ok(window) => visualize_loop(window, opengl, session_id, session, layers, updates, messages, map_db),
+            Ok(window) => visualize_loop(window, opengl, session_id, session, layers, updates, messages, map_db, texture_pool),
             Err(e) => error!("Failed to create visualization glutin window: {}", e),
         }
         WindowType::SDL2 => match settings.build::<Sdl2Window>() {
-            Ok(window) => visualize_loop(window, opengl, session_id, session, layers, updates, messages, map_db),
+            Ok(window) => visualize_loop(window, opengl, session_id, session, layers, updates, messages, map_db, texture_pool),
             Err(e) => error!("Failed to create visualization SDL2 window: {}", e),
         }
+        WindowType::Offscreen => visualize_offscreen(session_id, session, scene, updates, messages, map_db, texture_pool, config.offscreen),
     }
 }
 
+fn visualize_offscreen(session_id: i64, session: Arc<RwLock<Session>>, scene: Scene,
+                       updates: Arc<UpdatesQueue>, messages: Arc<Mutex<MessageQueue>>,
+                       map_db: Arc<Mutex<dyn MapDb + Send>>, texture_pool: TexturePool, config: OffscreenConfig) {
+    let frame_interval = Duration::from_secs_f64(config.frame_interval);
+    let layers = scene.nodes();
+    let mut visualizer = Visualizer::new(session_id, session, updates, messages, map_db, texture_pool);
+    let mut glyphs = OffscreenGlyphCache::new("fonts/UbuntuMono-R.ttf", (), TextureSettings::new())
+        .expect("Could not load font");
+    while Arc::strong_count(&visualizer.session) > 1 {
+        let start = Instant::now();
+        visualizer.update();
+        let mut g = OffscreenGraphics::new(config.width, config.height);
+        visualizer.render(graphics::Context::new_viewport(g.viewport()), &layers, &mut glyphs, &mut g);
+        scene.set_frame(g.into_frame());
+        if let Some(delay) = frame_interval.checked_sub(Instant::now() - start) {
+            sleep(delay);
+        }
+    }
+    info!("Stop offscreen visualization for session {}", session_id);
+}
+
 fn visualize_loop<W>(mut window: W, opengl: OpenGL, session_id: i64, session: Arc<RwLock<Session>>,
                      layers: Arc<Mutex<BTreeMap<usize, Arc<Mutex<Node>>>>>,
-                     updates: Arc<UpdatesQueue>, messages: Arc<Mutex<VecDeque<Message>>>,
-                     map_db: Arc<Mutex<dyn MapDb + Send>>) where W: Window {
+                     updates: Arc<UpdatesQueue>, messages: Arc<Mutex<MessageQueue>>,
+                     map_db: Arc<Mutex<dyn MapDb + Send>>, texture_pool: TexturePool) where W: Window {
     let mut events = Events::new(EventSettings::new().ups(60));
-    let mut visualizer = Visualizer::new(opengl, session_id, session, updates, messages, map_db);
+    let mut gl = GlGraphics::new(opengl);
+    let mut glyphs = GlyphCache::new(
+        "fonts/UbuntuMono-R.ttf",
+        (),
+        TextureSettings::new().filter(Filter::Linear),
+    ).expect("Could not load font");
+    let mut visualizer = Visualizer::new(session_id, session, updates, messages, map_db, texture_pool);
 
     while let Some(e) = events.next(&mut window) {
         if let Some(args) = e.render_args() {
-            visualizer.render(args, &layers);
+            gl.draw(args.viewport(), |context, g| visualizer.render(context, &layers, &mut glyphs, g));
         }
 
-        if let Some(args) = e.update_args() {
-            visualizer.update(args);
+        if e.update_args().is_some() {
+            visualizer.update();
         }
 
         if let Some(args) = e.press_args() {
@@ -106,13 +156,252 @@ fn visualize_loop<W>(mut window: W, opengl: OpenGL, session_id: i64, session: Ar
     }
 }
 
-struct Visualizer<'a> {
-    gl: GlGraphics,
-    glyphs: RefCell<GlyphCache<'a>>,
+struct VisualizedSession {
+    session: Arc<RwLock<Session>>,
+    scene: Scene,
+    updates: Arc<UpdatesQueue>,
+    messages: Arc<Mutex<MessageQueue>>,
+}
+
+#[derive(Default)]
+struct CombinedVisualizationState {
+    added: BTreeMap<i64, VisualizedSession>,
+    running: bool,
+}
+
+pub struct CombinedVisualization {
+    state: Arc<Mutex<CombinedVisualizationState>>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl CombinedVisualization {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CombinedVisualizationState::default())),
+            handle: Mutex::new(None),
+        }
+    }
+
+    pub fn add_session(&self, session_id: i64, session: Arc<RwLock<Session>>, scene: Scene,
+                       updates: Arc<UpdatesQueue>, messages: Arc<Mutex<MessageQueue>>,
+                       map_db: Arc<Mutex<dyn MapDb + Send>>, config: VisualizationConfig) {
+        let finished = {
+            let mut locked_state = self.state.lock().unwrap();
+            locked_state.added.insert(session_id, VisualizedSession { session, scene, updates, messages });
+            if locked_state.running {
+                return;
+            }
+            locked_state.running = true;
+            let state = self.state.clone();
+            self.handle.lock().unwrap()
+                .replace(spawn(move || visualize_sessions(state, map_db, config)))
+        };
+        if let Some(handle) = finished {
+            handle.join().ok();
+        }
+    }
+}
+
+struct CombinedSession {
+    scene: Scene,
+    layers: Arc<Mutex<BTreeMap<usize, Arc<Mutex<Node>>>>>,
+    visualizer: Visualizer,
+}
+
+struct CombinedSessions {
+    sessions: BTreeMap<i64, CombinedSession>,
+    selected: Option<i64>,
+    stopped: bool,
+    texture_pool: TexturePool,
+}
+
+impl CombinedSessions {
+    fn new(texture_pool: TexturePool) -> Self {
+        Self {
+            sessions: BTreeMap::new(),
+            selected: None,
+            stopped: false,
+            texture_pool,
+        }
+    }
+
+    fn sync(&mut self, state: &Arc<Mutex<CombinedVisualizationState>>, map_db: &Arc<Mutex<dyn MapDb + Send>>) -> bool {
+        let mut locked_state = state.lock().unwrap();
+        for (session_id, added) in std::mem::take(&mut locked_state.added) {
+            info!("Add session {} to combined visualization", session_id);
+            self.sessions.insert(session_id, CombinedSession {
+                layers: added.scene.nodes(),
+                scene: added.scene,
+                visualizer: Visualizer::new(session_id, added.session, added.updates, added.messages, map_db.clone(),
+                                            self.texture_pool.clone()),
+            });
+        }
+        self.sessions.retain(|session_id, v| {
+            let alive = Arc::strong_count(&v.visualizer.session) > 1;
+            if !alive {
+                info!("Remove session {} from combined visualization", session_id);
+            }
+            alive
+        });
+        if self.sessions.is_empty() {
+            locked_state.running = false;
+            self.stopped = true;
+            return false;
+        }
+        if self.selected.map(|v| !self.sessions.contains_key(&v)).unwrap_or(true) {
+            self.selected = self.sessions.keys().next().cloned();
+        }
+        let tabs = format_session_tabs(self.sessions.keys(), self.selected);
+        for session in self.sessions.values_mut() {
+            session.visualizer.session_tabs = Some(tabs.clone());
+        }
+        true
+    }
+
+    fn stop(&mut self, state: &Arc<Mutex<CombinedVisualizationState>>) {
+        if !self.stopped {
+            let mut locked_state = state.lock().unwrap();
+            locked_state.added.clear();
+            locked_state.running = false;
+            self.stopped = true;
+        }
+    }
+
+    fn select(&mut self, forward: bool) {
+        self.selected = get_next_session_id(self.sessions.keys().cloned().collect(), self.selected, forward);
+    }
+
+    fn selected_mut(&mut self) -> Option<&mut CombinedSession> {
+        let selected = self.selected?;
+        self.sessions.get_mut(&selected)
+    }
+}
+
+fn get_next_session_id(session_ids: Vec<i64>, selected: Option<i64>, forward: bool) -> Option<i64> {
+    let len = session_ids.len();
+    match selected.and_then(|v| session_ids.iter().position(|id| *id == v)) {
+        Some(position) if forward => session_ids.get((position + 1) % len).cloned(),
+        Some(position) => session_ids.get((position + len - 1) % len).cloned(),
+        None => session_ids.first().cloned(),
+    }
+}
+
+fn format_session_tabs<'a>(session_ids: impl Iterator<Item=&'a i64>, selected: Option<i64>) -> String {
+    session_ids
+        .map(|v| if Some(*v) == selected { format!("[{}]", v) } else { format!("{}", v) })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn visualize_sessions(state: Arc<Mutex<CombinedVisualizationState>>, map_db: Arc<Mutex<dyn MapDb + Send>>,
+                      config: VisualizationConfig) {
+    info!("Start combined visualization");
+    let mut sessions = CombinedSessions::new(TexturePool::new(config.texture_workers));
+    let opengl = OpenGL::V4_5;
+    let settings = WindowSettings::new("Sessions", [1920, 1080])
+        .graphics_api(opengl)
+        .exit_on_esc(true);
+    match config.window_type {
+        WindowType::Glutin => match settings.build::<GlutinWindow>() {
+            Ok(window) => visualize_sessions_loop(window, opengl, &state, &map_db, &mut sessions),
+            Err(e) => error!("Failed to create combined visualization glutin window: {}", e),
+        }
+        WindowType::SDL2 => match settings.build::<Sdl2Window>() {
+            Ok(window) => visualize_sessions_loop(window, opengl, &state, &map_db, &mut sessions),
+            Err(e) => error!("Failed to create combined visualization SDL2 window: {}", e),
+        }
+        WindowType::Offscreen => visualize_sessions_offscreen(&state, &map_db, &mut sessions, config.offscreen),
+    }
+    sessions.stop(&state);
+    info!("Stop combined visualization");
+}
+
+fn visualize_sessions_offscreen(state: &Arc<Mutex<CombinedVisualizationState>>, map_db: &Arc<Mutex<dyn MapDb + Send>>,
+                                sessions: &mut CombinedSessions, config: OffscreenConfig) {
+    let frame_interval = Duration::from_secs_f64(config.frame_interval);
+    let mut glyphs = OffscreenGlyphCache::new("fonts/UbuntuMono-R.ttf", (), TextureSettings::new())
+        .expect("Could not load font");
+    while sessions.sync(state, map_db) {
+        let start = Instant::now();
+        for session in sessions.sessions.values_mut() {
+            session.visualizer.update();
+            let mut g = OffscreenGraphics::new(config.width, config.height);
+            session.visualizer.render(graphics::Context::new_viewport(g.viewport()), &session.layers, &mut glyphs, &mut g);
+            session.scene.set_frame(g.into_frame());
+        }
+        if let Some(delay) = frame_interval.checked_sub(Instant::now() - start) {
+            sleep(delay);
+        }
+    }
+}
+
+fn visualize_sessions_loop<W>(mut window: W, opengl: OpenGL, state: &Arc<Mutex<CombinedVisualizationState>>,
+                              map_db: &Arc<Mutex<dyn MapDb + Send>>, sessions: &mut CombinedSessions) where W: Window {
+    let mut events = Events::new(EventSettings::new().ups(60));
+    let mut gl = GlGraphics::new(opengl);
+    let mut glyphs = GlyphCache::new(
+        "fonts/UbuntuMono-R.ttf",
+        (),
+        TextureSettings::new().filter(Filter::Linear),
+    ).expect("Could not load font");
+    let mut shift_pushed = false;
+
+    while let Some(e) = events.next(&mut window) {
+        if !sessions.sync(state, map_db) {
+            break;
+        }
+
+        if let Some(args) = e.render_args() {
+            if let Some(session) = sessions.selected_mut() {
+                let CombinedSession { layers, visualizer, .. } = session;
+                gl.draw(args.viewport(), |context, g| visualizer.render(context, layers, &mut glyphs, g));
+            }
+        }
+
+        if e.update_args().is_some() {
+            if let Some(session) = sessions.selected_mut() {
+                session.visualizer.update();
+            }
+        }
+
+        if let Some(args) = e.press_args() {
+            match args {
+                Button::Keyboard(Key::LShift) | Button::Keyboard(Key::RShift) => shift_pushed = true,
+                Button::Keyboard(Key::Tab) => sessions.select(!shift_pushed),
+                _ => if let Some(session) = sessions.selected_mut() {
+                    session.visualizer.press(args);
+                }
+            }
+        }
+
+        if let Some(args) = e.release_args() {
+            match args {
+                Button::Keyboard(Key::LShift) | Button::Keyboard(Key::RShift) => shift_pushed = false,
+                _ => if let Some(session) = sessions.selected_mut() {
+                    session.visualizer.release(args);
+                }
+            }
+        }
+
+        if let Some(args) = e.mouse_scroll_args() {
+            if let Some(session) = sessions.selected_mut() {
+                session.visualizer.mouse_scroll(args);
+            }
+        }
+
+        if let Some(args) = e.mouse_relative_args() {
+            if let Some(session) = sessions.selected_mut() {
+                session.visualizer.mouse_relative(args);
+            }
+        }
+    }
+}
+
+struct Visualizer {
     session_id: i64,
     session: Arc<RwLock<Session>>,
     updates: Arc<UpdatesQueue>,
-    messages: Arc<Mutex<VecDeque<Message>>>,
+    messages: Arc<Mutex<MessageQueue>>,
     map_db: Arc<Mutex<dyn MapDb + Send>>,
     frame_number: usize,
     fps: FpsMovingAverage,
@@ -126,22 +415,19 @@ struct Visualizer<'a> {
     last_world_revision: Option<u64>,
     world_scene: WorldScene,
     map_db_scene: MapDbScene,
+    texture_pool: TexturePool,
     world_node: RefCell<Node>,
     debug_node: RefCell<Node>,
     map_db_node: RefCell<Node>,
+    session_tabs: Option<String>,
+    grid_texture_mode: GridTextureMode,
 }
 
-impl Visualizer<'_> {
-    fn new(opengl: OpenGL, session_id: i64, session: Arc<RwLock<Session>>,
-           updates: Arc<UpdatesQueue>, messages: Arc<Mutex<VecDeque<Message>>>,
-           map_db: Arc<Mutex<dyn MapDb + Send>>) -> Self {
+impl Visualizer {
+    fn new(session_id: i64, session: Arc<RwLock<Session>>,
+           updates: Arc<UpdatesQueue>, messages: Arc<Mutex<MessageQueue>>,
+           map_db: Arc<Mutex<dyn MapDb + Send>>, texture_pool: TexturePool) -> Self {
         Self {
-            gl: GlGraphics::new(opengl),
-            glyphs: RefCell::new(GlyphCache::new(
-                "fonts/UbuntuMono-R.ttf",
-                (),
-                TextureSettings::new().filter(Filter::Linear),
-            ).expect("Could not load font")),
             session_id,
             session,
             updates,
@@ -159,18 +445,30 @@ impl Visualizer<'_> {
             last_world_revision: None,
             world_scene: WorldScene::default(),
             map_db_scene: MapDbScene::default(),
+            texture_pool,
             world_node: RefCell::new(Node::Empty),
             debug_node: RefCell::new(Node::Empty),
             map_db_node: RefCell::new(Node::Empty),
+            session_tabs: None,
+            grid_texture_mode: GridTextureMode::Tiles,
         }
     }
 
     fn press(&mut self, args: Button) {
-        if let Button::Mouse(MouseButton::Left) = args {
-            self.left_mouse_button_pushed = true;
+        match args {
+            Button::Mouse(MouseButton::Left) => self.left_mouse_button_pushed = true,
+            Button::Keyboard(Key::H) => self.set_grid_texture_mode(self.grid_texture_mode.next()),
+            _ => (),
         }
     }
 
+    fn set_grid_texture_mode(&mut self, mode: GridTextureMode) {
+        self.grid_texture_mode = mode;
+        self.world_scene.grids.set_mode(mode);
+        self.map_db_scene.grids.set_mode(mode);
+        self.last_world_revision = None;
+    }
+
     fn release(&mut self, args: Button) {
         if let Button::Mouse(MouseButton::Left) = args {
             self.left_mouse_button_pushed = false;
@@ -187,36 +485,33 @@ impl Visualizer<'_> {
         }
     }
 
-    fn render(&mut self, args: RenderArgs, nodes: &Arc<Mutex<BTreeMap<usize, Arc<Mutex<Node>>>>>) {
+    fn render<G, C>(&mut self, base_context: graphics::Context, nodes: &Arc<Mutex<BTreeMap<usize, Arc<Mutex<Node>>>>>,
+                    glyphs: &mut C, g: &mut G)
+        where G: Graphics, G::Texture: SceneTexture, C: CharacterCache<Texture = G::Texture> {
         let start = Instant::now();
-        let world_node = self.world_node.borrow();
-        let debug_node = self.debug_node.borrow();
-        let map_db_node = self.map_db_node.borrow();
-        let scale = self.scale;
-        let shift = self.shift;
-        let mut glyphs = self.glyphs.borrow_mut();
+        let context = &Context { base: &base_context, scale: self.scale, shift: self.shift };
         let mut nodes_count = 0;
-        self.gl.draw(args.viewport(), |base_context, g| {
-            clear([0.0, 0.0, 0.0, 1.0], g);
-            let context = &Context { base: &base_context, scale, shift };
-            nodes_count += map_db_node.draw(&context, base_context.transform, glyphs.deref_mut(), g);
-            nodes_count += world_node.draw(&context, base_context.transform, glyphs.deref_mut(), g);
-            for layer in nodes.lock().unwrap().values() {
-                nodes_count += layer.lock().unwrap().draw(&context, base_context.transform, glyphs.deref_mut(), g);
-            }
-            nodes_count += debug_node.draw(&context, base_context.transform, glyphs.deref_mut(), g);
-        });
+        clear([0.0, 0.0, 0.0, 1.0], g);
+        nodes_count += self.map_db_node.borrow().draw(&context, base_context.transform, glyphs, g);
+        nodes_count += self.world_node.borrow().draw(&context, base_context.transform, glyphs, g);
+        for layer in nodes.lock().unwrap().values() {
+            nodes_count += layer.lock().unwrap().draw(&context, base_context.transform, glyphs, g);
+        }
+        nodes_count += self.debug_node.borrow().draw(&context, base_context.transform, glyphs, g);
         let finish = Instant::now();
         self.render_duration.add(finish - start);
         self.fps.add(finish);
         self.nodes = nodes_count;
     }
 
-    fn update(&mut self, _args: UpdateArgs) {
+    fn update(&mut self) {
         let start = Instant::now();
         let mut debug_text = Vec::new();
         self.frame_number += 1;
         debug_text.push(format!("session: {}", self.session_id));
+        if let Some(tabs) = self.session_tabs.as_ref() {
+            debug_text.push(format!("sessions (Tab to switch): {}", tabs));
+        }
         debug_text.push(format!("frame: {}", self.frame_number));
         debug_text.push(format!("fps: {}", self.fps.get()));
         debug_text.push(format!("render duration: {}", self.render_duration.get()));
@@ -224,25 +519,37 @@ impl Visualizer<'_> {
         debug_text.push(format!("nodes: {}", self.nodes));
         debug_text.push(format!("updates: {}", count_updates(&self.updates)));
         debug_text.push(format!("messages: {}", self.messages.lock().unwrap().len()));
+        debug_text.push(format!("human in control: {}", self.session.read().unwrap().is_human_in_control()));
+        debug_text.push(format!("grid texture mode (H to switch): {:?}", self.grid_texture_mode));
         if let Some(world) = self.session.read().unwrap().get_player_world() {
             if self.last_player_segment_id != Some(world.player_segment_id()) {
                 self.shift = -world.player_position();
                 self.last_player_segment_id = Some(world.player_segment_id());
             }
-            if self.last_world_revision != Some(world.revision()) {
-                self.world_node = RefCell::new(self.world_scene.make_node(&world));
+            let world_textures_updated = self.world_scene.grids.receive();
+            if world_textures_updated || self.last_world_revision != Some(world.revision()) {
+                self.world_node = RefCell::new(self.world_scene.make_node(&world, &self.texture_pool));
                 self.last_world_revision = Some(world.revision());
             }
-            self.map_db_node = RefCell::new(self.map_db_scene.make_node(&self.map_db, &world));
+            self.map_db_scene.grids.receive();
+            self.map_db_node = RefCell::new(self.map_db_scene.make_node(&self.map_db, &world, &self.texture_pool));
             debug_text.push(format!("revision: {}", world.revision()));
             debug_text.push(format!("local grids: {}", self.world_scene.grids.len()));
             debug_text.push(format!("db grids: {}", self.map_db_scene.grids.len()));
+            debug_text.push(format!("pending grid textures: {}", self.world_scene.grids.pending() + self.map_db_scene.grids.pending()));
             debug_text.push(format!("objects: {}", world.objects_len()));
             debug_text.push(format!("player segment id: {}", world.player_segment_id()));
             debug_text.push(format!("player grid id: {:?}", world.player_grid_id()));
-            debug_text.push(format!("player position: {:?}", world.player_position()));
+            debug_text.push(format!("player position: {:?}", world.export_position(world.player_position())));
+            debug_text.push(format!("local player position: {:?}", world.player_position()));
+            debug_text.push(format!("player height: {:?}", world.get_height(pos_to_tile_pos(world.player_position()))));
             debug_text.push(format!("player object id: {:?}", world.player_object_id()));
             debug_text.push(format!("player stuck: {:?}", world.is_player_stuck()));
+            debug_text.push(format!("danger zones: {}", world.danger_zones().len()));
+            debug_text.push(format!("stuck tiles: {}", world.stuck_tiles().len()));
+            debug_text.push(format!("area cache grids: {}", world.area_cache().len()));
+            debug_text.push(format!("streamed grids: {}", world.ghost_objects().streamed_grids()));
+            debug_text.push(format!("obstacles: {}", world.obstacles().len()));
         } else {
             debug_text.push(format!("world is not configured"));
             self.last_player_segment_id = None;
@@ -261,7 +568,7 @@ impl Visualizer<'_> {
     }
 }
 
-fn make_rgba_color(value: i32) -> [u8; 4] {
+pub fn make_rgba_color(value: i32) -> [u8; 4] {
     [
         get_color_component(value, 2),
         get_color_component(value, 1),
@@ -276,19 +583,19 @@ fn get_color_component(value: i32, number: i32) -> u8 {
 
 #[derive(Default)]
 struct WorldScene {
-    grids: HashMap<i64, GridTexture>,
+    grids: GridTextures,
 }
 
 struct GridTexture {
     revision: i64,
-    value: Arc<Mutex<Texture>>,
+    value: Arc<SceneImage>,
 }
 
 impl WorldScene {
-    fn make_node(&mut self, world: &PlayerWorld) -> Node {
+    fn make_node(&mut self, world: &PlayerWorld, texture_pool: &TexturePool) -> Node {
         let mut nodes: Vec<Node> = Vec::new();
         for grid in world.iter_grids().filter(|grid| grid.segment_id == world.player_segment_id()) {
-            add_grid_node(grid, Vec2i::zero(), world, &mut self.grids, &mut nodes);
+            add_grid_node(grid, Vec2i::zero(), world, texture_pool, &mut self.grids, &mut nodes);
         }
         for object in world.iter_objects() {
             nodes.push(Node::from(EllipseNode {
@@ -316,6 +623,13 @@ impl WorldScene {
                 }));
             }
         }
+        for zone in world.danger_zones().iter() {
+            nodes.push(Node::from(EllipseNode {
+                value: Ellipse::new_border(world.danger_zones().config().color, 0.5),
+                rectangle: centered_square(0.0, 0.0, zone.radius),
+                transform: identity().trans(zone.position.x(), zone.position.y()),
+            }));
+        }
         Node::from(MapTransformBoxNode {
             node: Box::new(Node::from(CompositeVecNode { nodes })),
         })
@@ -324,11 +638,11 @@ impl WorldScene {
 
 #[derive(Default)]
 struct MapDbScene {
-    grids: HashMap<i64, GridTexture>,
+    grids: GridTextures,
 }
 
 impl MapDbScene {
-    fn make_node(&mut self, map_db: &Arc<Mutex<dyn MapDb + Send>>, world: &PlayerWorld) -> Node {
+    fn make_node(&mut self, map_db: &Arc<Mutex<dyn MapDb + Send>>, world: &PlayerWorld, texture_pool: &TexturePool) -> Node {
         let mut nodes: Vec<Node> = Vec::new();
         let locked_map_db = map_db.lock().unwrap();
         if let Some((shift, grid_ids)) = locked_map_db.get_grid_by_id(world.player_segment_id())
@@ -344,7 +658,7 @@ impl MapDbScene {
                 if world.get_grid_by_id(grid_id).is_none() {
                     if let Some(grid) = locked_map_db.get_grid_by_id(grid_id) {
                         let locked = grid.lock().unwrap();
-                        add_grid_node(locked.deref(), shift, world, &mut self.grids, &mut nodes);
+                        add_grid_node(locked.deref(), shift, world, texture_pool, &mut self.grids, &mut nodes);
                     }
                 }
             }
@@ -355,34 +669,113 @@ impl MapDbScene {
     }
 }
 
-fn add_grid_node(grid: &Grid, shift: Vec2i, world: &PlayerWorld, grids: &mut HashMap<i64, GridTexture>,
-                 nodes: &mut Vec<Node>) {
-    let cached = grids.entry(grid.id)
-        .or_insert_with(|| make_grid_texture(grid, world));
-    if cached.revision != grid.revision {
-        *cached = make_grid_texture(grid, world);
+fn add_grid_node(grid: &Grid, shift: Vec2i, world: &PlayerWorld, texture_pool: &TexturePool,
+                 grids: &mut GridTextures, nodes: &mut Vec<Node>) {
+    if let Some(image) = grids.get(grid, world, texture_pool) {
+        let grid_position = grid_pos_to_pos(grid.position + shift);
+        nodes.push(Node::from(ImageNode {
+            value: Image::new().rect(square(0.0, 0.0, GRID_SIZE as f64 * TILE_SIZE)),
+            image,
+            transform: identity().trans(grid_position.x(), grid_position.y()),
+        }));
+    }
+}
+
+struct GridTextures {
+    mode: GridTextureMode,
+    values: HashMap<i64, GridTexture>,
+    requested: HashMap<i64, i64>,
+    sender: Sender<GridImage>,
+    receiver: Receiver<GridImage>,
+}
+
+impl Default for GridTextures {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            mode: GridTextureMode::Tiles,
+            values: HashMap::new(),
+            requested: HashMap::new(),
+            sender,
+            receiver,
+        }
     }
-    let grid_position = grid_pos_to_pos(grid.position + shift);
-    nodes.push(Node::from(ImageNode {
-        value: Image::new().rect(square(0.0, 0.0, GRID_SIZE as f64 * TILE_SIZE)),
-        texture: cached.value.clone(),
-        transform: identity().trans(grid_position.x(), grid_position.y()),
-    }));
 }
 
-fn make_grid_texture(grid: &Grid, world: &PlayerWorld) -> GridTexture {
-    let mut image = RgbaImage::new(GRID_SIZE as u32, GRID_SIZE as u32);
-    for (index, tile_id) in grid.tiles.iter().enumerate() {
-        let position = tile_index_to_tile_pos(index);
-        let color = world.get_tile_by_id(*tile_id)
-            .map(|tile| make_rgba_color(tile.color))
-            .unwrap_or([255, 255, 255, 255]);
-        image.put_pixel(position.x() as u32, position.y() as u32, Rgba(color));
+impl GridTextures {
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn pending(&self) -> usize {
+        self.requested.len()
+    }
+
+    fn set_mode(&mut self, mode: GridTextureMode) {
+        if self.mode != mode {
+            self.mode = mode;
+            self.values.clear();
+            self.requested.clear();
+        }
+    }
+
+    fn receive(&mut self) -> bool {
+        let mut updated = false;
+        while let Ok(result) = self.receiver.try_recv() {
+            if result.mode != self.mode {
+                continue;
+            }
+            match self.requested.get(&result.grid_id) {
+                Some(revision) if *revision != result.revision => continue,
+                _ => (),
+            }
+            self.requested.remove(&result.grid_id);
+            self.values.insert(result.grid_id, GridTexture {
+                revision: result.revision,
+                value: Arc::new(SceneImage::new(result.image)),
+            });
+            updated = true;
+        }
+        updated
+    }
+
+    fn get(&mut self, grid: &Grid, world: &PlayerWorld, texture_pool: &TexturePool) -> Option<Arc<SceneImage>> {
+        let cached = self.values.get(&grid.id);
+        let outdated = cached.map(|v| v.revision != grid.revision).unwrap_or(true);
+        if outdated && self.requested.get(&grid.id) != Some(&grid.revision) {
+            let job = GridImageJob {
+                grid_id: grid.id,
+                revision: grid.revision,
+                mode: self.mode,
+                tiles: grid.tiles.clone(),
+                heights: match self.mode {
+                    GridTextureMode::Tiles => Vec::new(),
+                    GridTextureMode::Heights => grid.heights.clone(),
+                },
+                colors: match self.mode {
+                    GridTextureMode::Tiles => get_tile_colors(grid, world),
+                    GridTextureMode::Heights => BTreeMap::new(),
+                },
+                result: self.sender.clone(),
+            };
+            if texture_pool.submit(job) {
+                self.requested.insert(grid.id, grid.revision);
+            }
+        }
+        cached.map(|v| v.value.clone())
     }
-    GridTexture {
-        revision: grid.revision,
-        value: Arc::new(Mutex::new(Texture::from_image(&image, &TextureSettings::new().filter(Filter::Nearest)))),
+}
+
+fn get_tile_colors(grid: &Grid, world: &PlayerWorld) -> BTreeMap<i32, [u8; 4]> {
+    let mut result = BTreeMap::new();
+    for tile_id in grid.tiles.iter() {
+        if !result.contains_key(tile_id) {
+            if let Some(tile) = world.get_tile_by_id(*tile_id) {
+                result.insert(*tile_id, make_rgba_color(tile.color));
+            }
+        }
     }
+    result
 }
 
 pub struct FpsMovingAverage {
@@ -464,3 +857,20 @@ impl DurationMovingAverage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_next_session_id_should_cycle_over_sessions() {
+        let session_ids = vec![1, 3, 7];
+        assert_eq!(get_next_session_id(session_ids.clone(), None, true), Some(1));
+        assert_eq!(get_next_session_id(session_ids.clone(), Some(1), true), Some(3));
+        assert_eq!(get_next_session_id(session_ids.clone(), Some(7), true), Some(1));
+        assert_eq!(get_next_session_id(session_ids.clone(), Some(1), false), Some(7));
+        assert_eq!(get_next_session_id(session_ids.clone(), Some(3), false), Some(1));
+        assert_eq!(get_next_session_id(Vec::new(), Some(3), true), None);
+        assert_eq!(format_session_tabs(session_ids.iter(), Some(3)), "1 [3] 7");
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix_web_actors::ws;
+
+#[cfg(feature = "fault_injection")]
+use crate::bot::fault_injection::Faults;
+use crate::bot::message_queue::MessageQueue;
+use crate::bot::protocol::Message;
+use crate::bot::session::Session;
+use crate::bot::session_stats::DeliveryChannel;
+
+pub struct WebSocketConnection {
+    session_id: i64,
+    session: Arc<RwLock<Session>>,
+    messages: Arc<Mutex<MessageQueue>>,
+    #[cfg(feature = "fault_injection")]
+    faults: Arc<Faults>,
+}
+
+struct MessagesQueued;
+
+impl WebSocketConnection {
+    pub fn new(session_id: i64, session: Arc<RwLock<Session>>, messages: Arc<Mutex<MessageQueue>>,
+               #[cfg(feature = "fault_injection")] faults: Arc<Faults>) -> Self {
+        Self {
+            session_id,
+            session,
+            messages,
+            #[cfg(feature = "fault_injection")]
+            faults,
+        }
+    }
+
+    fn write_messages(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        #[cfg(feature = "fault_injection")]
+        {
+            if let Some(stall) = self.faults.get_messages_stall(std::time::Instant::now()) {
+                ctx.run_later(stall, |connection, ctx| connection.write_messages(ctx));
+                return;
+            }
+        }
+        let messages: Vec<Message> = self.messages.lock().unwrap().drain();
+        if messages.is_empty() {
+            return;
+        }
+        self.session.read().unwrap().add_delivered_messages(DeliveryChannel::WebSocket, messages.len());
+        for message in messages.iter() {
+            ctx.text(serde_json::to_string(message).unwrap());
+        }
+    }
+}
+
+impl Actor for WebSocketConnection {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        debug!("WebSocket for session {} is connected", self.session_id);
+        let notifications = self.messages.lock().unwrap().subscribe();
+        ctx.add_stream(futures::StreamExt::map(notifications, |_| MessagesQueued));
+        self.write_messages(ctx);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        debug!("WebSocket for session {} is disconnected", self.session_id);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketConnection {
+    fn handle(&mut self, message: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match message {
+            Ok(ws::Message::Ping(v)) => ctx.pong(&v),
+            Ok(ws::Message::Close(reason)) => {
+                debug!("WebSocket for session {} is closed by client", self.session_id);
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => (),
+            Err(e) => {
+                warn!("WebSocket for session {} protocol error: {}", self.session_id, e);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+impl StreamHandler<MessagesQueued> for WebSocketConnection {
+    fn handle(&mut self, _: MessagesQueued, ctx: &mut Self::Context) {
+        self.write_messages(ctx);
+    }
+}
@@ -1,19 +1,43 @@
 use std::collections::{BinaryHeap, BTreeMap, BTreeSet, HashMap};
+use std::mem::replace;
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 use graphics::{Line, Rectangle, Transformed};
 use graphics::math::identity;
 use graphics::rectangle::square;
 use serde::{Deserialize, Serialize};
 
-use crate::bot::map::{Grid, grid_pos_to_tile_pos, GridNeighbour, Map, MapData, pos_to_grid_pos, rel_tile_pos_to_pos, Tile, tile_pos_to_pos, TILE_SIZE, TileSet};
+use crate::bot::anchors::{Anchor, Anchors, AnchorsConfig, AnchorsData};
+use crate::bot::area_cache::AreaCache;
+use crate::bot::avoidance::{Avoidance, AvoidanceConfig};
+use crate::bot::breadcrumbs::{Breadcrumbs, BreadcrumbsConfig, BreadcrumbsData};
+use crate::bot::clusterization::make_distance_clusters;
+use crate::bot::containers::{Container, Containers, ContainersConfig};
+use crate::bot::danger_zones::{DangerZones, DangerZonesConfig};
+use crate::bot::day_time::DayTime;
+use crate::bot::fields::{Field, FieldsConfig, FieldTiles, make_field};
+use crate::bot::geometry::Segment;
+use crate::bot::ghost_objects::{GhostObjects, GhostObjectsConfig};
+use crate::bot::grids_of_interest::GridsOfInterest;
+use crate::bot::map::{Grid, grid_pos_to_pos, grid_pos_to_tile_pos, GridNeighbour, Map, MapData, MapObject, pos_to_grid_pos, pos_to_rel_tile_pos, pos_to_tile_pos, rel_tile_pos_to_pos, Tile, tile_pos_to_grid_pos, tile_pos_to_pos, TILE_SIZE, TileSet, TilesSnapshot};
 use crate::bot::map_db::MapDb;
+use crate::bot::metrics::Metrics;
 use crate::bot::math::as_score;
-use crate::bot::objects::{Object, Objects, ObjectsData};
+#[cfg(feature = "bench")]
+use crate::bot::navigator::{Areas, make_areas};
+use crate::bot::navigator::Navigator;
+use crate::bot::objects::{Object, ObjectCluster, Objects, ObjectsData, PersistentObjectsConfig};
+use crate::bot::obstacles::Obstacles;
 use crate::bot::player::{Item, Player, PlayerEquipment, Resource, Widget};
-use crate::bot::protocol::{Event, MapGrid, Update};
+use crate::bot::protocol::{Event, MapGrid, Update, Value};
+use crate::bot::reachability::{Landmarks, Reachability};
+use crate::bot::roads::RoadsConfig;
 use crate::bot::scene::{ArrowNode, CompositeBTreeMapNode, insert_to_composite_node_btree_map, Node, RectangleNode, remove_from_composite_node_btree_map};
+use crate::bot::stuck_tiles::{StuckTiles, StuckTilesConfig};
+use crate::bot::tile_profiles::TileProfiles;
+use crate::bot::traversal::TraversalConfig;
 use crate::bot::vec2::{Vec2f, Vec2i};
 use crate::bot::walk_grid::walk_grid;
 
@@ -21,35 +45,108 @@ use crate::bot::walk_grid::walk_grid;
 pub struct WorldConfig {
     pub water_tiles: HashMap<String, f64>,
     pub ice_tiles: HashMap<String, f64>,
+    pub movement_profiles: HashMap<String, HashMap<String, f64>>,
     pub report_iterations: usize,
+    pub hierarchical_path_min_distance: f64,
     pub found_transition_color: [f32; 4],
     pub path_transition_color: [f32; 4],
     pub shorten_path_transition_color: [f32; 4],
     pub direct_path_transition_color: [f32; 4],
+    pub danger_zones: DangerZonesConfig,
+    pub anchors: AnchorsConfig,
+    pub persistent_objects: PersistentObjectsConfig,
+    pub stuck_tiles: StuckTilesConfig,
+    pub breadcrumbs: BreadcrumbsConfig,
+    pub obstacles: HashMap<String, f64>,
+    pub traversal: TraversalConfig,
+    pub avoidance: AvoidanceConfig,
+    pub roads: RoadsConfig,
+    pub containers: ContainersConfig,
+    pub ghost_objects: GhostObjectsConfig,
+    pub fields: FieldsConfig,
+    pub max_height_delta: f64,
+    pub height_delta_weight: Option<f64>,
 }
 
+static NEXT_GRIDS_OF_INTEREST_OWNER: AtomicU64 = AtomicU64::new(1);
+
 pub struct World {
     revision: u64,
-    objects: Objects,
+    // Shared with tasks for the duration of a call, copied only when modified while shared
+    objects: Arc<Objects>,
     map: Map,
+    danger_zones: DangerZones,
+    grids_of_interest: GridsOfInterest,
+    // Map db is shared by sessions so each world pins grids of interest under own owner id
+    grids_of_interest_owner: u64,
+    pinned_grids_of_interest: Option<(u64, Option<(i64, Vec2i)>)>,
+    anchors: Anchors,
+    reachability: Reachability,
+    navigator: Navigator,
+    area_cache: Arc<AreaCache>,
+    stuck_tiles: StuckTiles,
+    obstacles: Obstacles,
+    avoidance: Avoidance,
+    breadcrumbs: Breadcrumbs,
+    containers: Containers,
+    ghost_objects: GhostObjects,
+    metrics: Arc<Metrics>,
+    tile_profiles: Arc<TileProfiles>,
     config: WorldConfig,
 }
 
 impl World {
-    pub fn new(config: WorldConfig, map_db: Arc<Mutex<dyn MapDb + Send>>) -> Self {
+    pub fn new(config: WorldConfig, map_db: Arc<Mutex<dyn MapDb + Send>>, metrics: Arc<Metrics>,
+               tile_profiles: Arc<TileProfiles>) -> Self {
+        let area_cache = Arc::new(AreaCache::new());
         Self {
             revision: 0,
-            objects: Objects::new(),
-            map: Map::new(map_db),
+            objects: Arc::new(Objects::new()),
+            map: Map::new(map_db, config.max_height_delta),
+            danger_zones: DangerZones::new(config.danger_zones.clone()),
+            grids_of_interest: GridsOfInterest::new(),
+            grids_of_interest_owner: NEXT_GRIDS_OF_INTEREST_OWNER.fetch_add(1, Ordering::Relaxed),
+            pinned_grids_of_interest: None,
+            anchors: Anchors::new(&config.anchors),
+            reachability: Reachability::new(),
+            navigator: Navigator::new(area_cache.clone()),
+            area_cache,
+            stuck_tiles: StuckTiles::new(config.stuck_tiles.clone()),
+            obstacles: Obstacles::new(&config.obstacles, &config.traversal.openable),
+            avoidance: Avoidance::new(config.avoidance.clone()),
+            breadcrumbs: Breadcrumbs::new(config.breadcrumbs.clone()),
+            containers: Containers::new(config.containers.clone()),
+            ghost_objects: GhostObjects::new(config.ghost_objects.clone()),
+            metrics,
+            tile_profiles,
             config,
         }
     }
 
-    pub fn from_world_data(data: WorldData, config: WorldConfig, map_db: Arc<Mutex<dyn MapDb + Send>>) -> Self {
+    pub fn from_world_data(data: WorldData, config: WorldConfig, map_db: Arc<Mutex<dyn MapDb + Send>>,
+                           metrics: Arc<Metrics>, tile_profiles: Arc<TileProfiles>) -> Self {
+        let objects = Objects::from_objects_data(data.objects);
+        let area_cache = Arc::new(AreaCache::new());
         Self {
             revision: data.revision,
-            objects: Objects::from_objects_data(data.objects),
-            map: Map::from_map_data(data.map, map_db),
+            obstacles: Obstacles::from_objects(&objects, &config.obstacles, &config.traversal.openable),
+            avoidance: Avoidance::from_objects(&objects, config.avoidance.clone()),
+            objects: Arc::new(objects),
+            map: Map::from_map_data(data.map, map_db, config.max_height_delta),
+            danger_zones: DangerZones::new(config.danger_zones.clone()),
+            grids_of_interest: GridsOfInterest::new(),
+            grids_of_interest_owner: NEXT_GRIDS_OF_INTEREST_OWNER.fetch_add(1, Ordering::Relaxed),
+            pinned_grids_of_interest: None,
+            anchors: Anchors::from_anchors_data(data.anchors, &config.anchors),
+            reachability: Reachability::new(),
+            navigator: Navigator::new(area_cache.clone()),
+            area_cache,
+            stuck_tiles: StuckTiles::new(config.stuck_tiles.clone()),
+            breadcrumbs: Breadcrumbs::from_breadcrumbs_data(data.breadcrumbs, config.breadcrumbs.clone()),
+            containers: Containers::new(config.containers.clone()),
+            ghost_objects: GhostObjects::new(config.ghost_objects.clone()),
+            metrics,
+            tile_profiles,
             config,
         }
     }
@@ -59,6 +156,35 @@ impl World {
             revision: self.revision,
             objects: self.objects.as_objects_data(),
             map: self.map.as_map_data(),
+            breadcrumbs: self.breadcrumbs.as_breadcrumbs_data(),
+            anchors: self.anchors.as_anchors_data(),
+        }
+    }
+
+    // Copy of the state used by path queries so they can run without holding the session lock
+    pub fn snapshot(&self) -> Self {
+        let objects = Objects::from_objects_data(self.objects.as_objects_data());
+        Self {
+            revision: self.revision,
+            obstacles: Obstacles::from_objects(&objects, &self.config.obstacles, &self.config.traversal.openable),
+            avoidance: Avoidance::from_objects(&objects, self.config.avoidance.clone()),
+            objects: Arc::new(objects),
+            map: self.map.snapshot(),
+            danger_zones: self.danger_zones.clone(),
+            grids_of_interest: GridsOfInterest::new(),
+            grids_of_interest_owner: NEXT_GRIDS_OF_INTEREST_OWNER.fetch_add(1, Ordering::Relaxed),
+            pinned_grids_of_interest: None,
+            anchors: Anchors::from_anchors_data(self.anchors.as_anchors_data(), &self.config.anchors),
+            reachability: Reachability::new(),
+            navigator: Navigator::new(self.area_cache.clone()),
+            area_cache: self.area_cache.clone(),
+            stuck_tiles: self.stuck_tiles.clone(),
+            breadcrumbs: Breadcrumbs::new(self.config.breadcrumbs.clone()),
+            containers: Containers::new(self.config.containers.clone()),
+            ghost_objects: GhostObjects::new(self.config.ghost_objects.clone()),
+            metrics: self.metrics.clone(),
+            tile_profiles: self.tile_profiles.clone(),
+            config: self.config.clone(),
         }
     }
 
@@ -66,6 +192,10 @@ impl World {
         &self.objects
     }
 
+    pub fn get_stored_resources(&self) -> Vec<Resource> {
+        self.map.get_resources()
+    }
+
     pub fn for_player<'a>(&'a self, player: &'a Player) -> Option<PlayerWorld<'a>> {
         if let (
             Some(map_view_id),
@@ -110,6 +240,21 @@ impl World {
                                 player_equipment,
                                 objects: &self.objects,
                                 map: &self.map,
+                                danger_zones: &self.danger_zones,
+                                grids_of_interest: &self.grids_of_interest,
+                                anchors: &self.anchors,
+                                reachability: &self.reachability,
+                                navigator: &self.navigator,
+                                area_cache: &self.area_cache,
+                                stuck_tiles: &self.stuck_tiles,
+                                obstacles: &self.obstacles,
+                                avoidance: &self.avoidance,
+                                breadcrumbs: &self.breadcrumbs,
+                                containers: &self.containers,
+                                ghost_objects: &self.ghost_objects,
+                                metrics: &self.metrics,
+                                tile_profiles: &self.tile_profiles,
+                                tiles_snapshot: None,
                                 config: &self.config,
                             }
                         })
@@ -120,7 +265,21 @@ impl World {
     }
 
     pub fn update(&mut self, update: Update) -> bool {
-        if self.apply_update(update) {
+        let number = update.number;
+        let mut updated = self.apply_update(update);
+        if self.remove_ghost_objects(number) {
+            updated = true;
+        }
+        if updated {
+            self.revision += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn update_danger_zones(&mut self, player: &Player, update: &Update) -> bool {
+        if self.danger_zones.update(&self.objects, player, update, Instant::now()) {
             self.revision += 1;
             true
         } else {
@@ -128,36 +287,256 @@ impl World {
         }
     }
 
+    pub fn update_containers(&mut self, player: &Player, update: &Update) -> bool {
+        let now = Instant::now();
+        let mut updated = match &update.event {
+            Event::WidgetMessage { id, msg, args } if Some(*id) == player.map_view_id() && msg == "click" => {
+                if let Some(Value::Int { value }) = args.get(5) {
+                    if let Some(object) = self.objects.get_by_id(*value as i64) {
+                        self.containers.interact(object, now);
+                    }
+                }
+                false
+            }
+            Event::NewWidget { id, kind, parent, .. } if kind == "inv" => {
+                player.widgets().get(parent).map(|v| v.kind == "wnd").unwrap_or(false)
+                    && Some(*id) != player.belt_inventory_id()
+                    && self.containers.open(*id, *parent, &self.objects, now)
+            }
+            Event::Destroy { id } => self.containers.close(*id),
+            _ => false,
+        };
+        let free_slots: Vec<(i32, usize)> = self.containers.open_inventory_ids()
+            .filter_map(|id| player.inventory_free_slots(id).map(|v| (id, v)))
+            .collect();
+        for (inventory_id, value) in free_slots {
+            if self.containers.set_free_slots(inventory_id, value) {
+                updated = true;
+            }
+        }
+        updated
+    }
+
+    pub fn update_stuck_tiles(&mut self, player: &Player, update: &Update) -> bool {
+        let now = Instant::now();
+        let mut updated = self.stuck_tiles.remove_expired(now);
+        if let Event::GobMove { id, .. } = &update.event {
+            if player.object_id() == Some(*id) && player.is_stuck() {
+                if let Some(world) = self.for_player(player) {
+                    let tile_pos = pos_to_tile_pos(world.player_position) + grid_pos_to_tile_pos(world.player_grid_offset);
+                    let segment_id = world.player_segment_id;
+                    debug!("World: player is stuck at tile {:?} in segment {}", tile_pos, segment_id);
+                    self.stuck_tiles.add(segment_id, tile_pos, now);
+                    updated = true;
+                }
+            }
+        }
+        if updated {
+            self.revision += 1;
+        }
+        updated
+    }
+
+    pub fn update_breadcrumbs(&mut self, player: &Player) -> bool {
+        if let Some(world) = self.for_player(player) {
+            let position = world.player_position + grid_pos_to_pos(world.player_grid_offset);
+            let segment_id = world.player_segment_id;
+            if self.breadcrumbs.add(segment_id, position) {
+                debug!("World: add breadcrumb at {:?} in segment {}, trail length is {}", position, segment_id, self.breadcrumbs.len());
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn saturate_stuck_tiles(&mut self, player: &Player) -> bool {
+        if let Some(world) = self.for_player(player) {
+            let tile_pos = pos_to_tile_pos(world.player_position) + grid_pos_to_tile_pos(world.player_grid_offset);
+            let segment_id = world.player_segment_id;
+            debug!("World: saturate stuck tiles at tile {:?} in segment {}", tile_pos, segment_id);
+            self.stuck_tiles.saturate(segment_id, tile_pos, Instant::now());
+            self.revision += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn set_grids_of_interest(&mut self, player: &Player, positions: Vec<Vec2i>) {
+        self.grids_of_interest.set(positions);
+        self.revision += 1;
+        if let Some((segment_id, grid_offset)) = self.get_player_segment(player) {
+            let map = &self.map;
+            let prefetched = self.grids_of_interest.iter()
+                .filter(|position| map.prefetch_grid(segment_id, **position + grid_offset))
+                .count();
+            debug!("World: prefetched {} of {} grids of interest", prefetched, self.grids_of_interest.len());
+        }
+        self.pin_grids_of_interest(player);
+    }
+
+    pub fn update_grids_of_interest(&mut self, player: &Player) -> Vec<(Vec2i, i64, i64)> {
+        self.pin_grids_of_interest(player);
+        let (segment_id, grid_offset) = match self.get_player_segment(player) {
+            Some(v) => v,
+            None => return Vec::new(),
+        };
+        let map = &self.map;
+        let grids_of_interest = &mut self.grids_of_interest;
+        let positions = grids_of_interest.iter().cloned().collect::<Vec<_>>();
+        positions.into_iter()
+            .filter_map(|position| {
+                map.get_grid(segment_id, position + grid_offset)
+                    .filter(|grid| grids_of_interest.update(position, grid))
+                    .map(|grid| (position, grid.id, grid.revision))
+            })
+            .collect()
+    }
+
+    // Prefetched grids of interest are kept in map db cache until replaced, repinned when player segment changes
+    fn pin_grids_of_interest(&mut self, player: &Player) {
+        let segment = self.get_player_segment(player);
+        let key = (self.grids_of_interest.revision(), segment);
+        if self.pinned_grids_of_interest == Some(key) || (segment.is_none() && self.grids_of_interest.len() > 0)
+            || (self.pinned_grids_of_interest.is_none() && self.grids_of_interest.len() == 0) {
+            return;
+        }
+        let (segment_id, grid_offset) = segment.unwrap_or((0, Vec2i::zero()));
+        let positions = self.grids_of_interest.iter().map(|v| *v + grid_offset).collect();
+        if self.map.set_pinned_grids(self.grids_of_interest_owner, segment_id, positions) {
+            debug!("World: pinned {} grids of interest in segment {}", self.grids_of_interest.len(), segment_id);
+            self.pinned_grids_of_interest = Some(key);
+        }
+    }
+
+    pub fn add_grid_anchor(&mut self, grid_id: i64) {
+        debug!("World: add anchor at grid {}", grid_id);
+        self.anchors.add(&self.map, Anchor { grid_id, offset: Vec2f::zero() });
+    }
+
+    pub fn add_object_anchor(&mut self, player: &Player, object_id: i64) -> Result<(), String> {
+        let (segment_id, grid_offset) = self.get_player_segment(player)
+            .ok_or_else(|| String::from("World is not configured"))?;
+        let position = self.objects.get_by_id(object_id).map(|v| v.position)
+            .ok_or_else(|| String::from("Object is not found"))?;
+        let grid_pos = pos_to_grid_pos(position);
+        let grid_id = self.map.get_grid(segment_id, grid_pos + grid_offset).map(|v| v.id)
+            .ok_or_else(|| String::from("Object grid is not found"))?;
+        debug!("World: add anchor at object {} in grid {}", object_id, grid_id);
+        self.anchors.add(&self.map, Anchor { grid_id, offset: position - grid_pos_to_pos(grid_pos) });
+        Ok(())
+    }
+
+    pub fn persist_objects(&self, player: &Player, update: &Update) {
+        let world = match self.for_player(player) {
+            Some(v) => v,
+            None => return,
+        };
+        let config = &self.config.persistent_objects;
+        match &update.event {
+            Event::GobAdd { id, position, name: Some(name), .. } => {
+                if !config.is_persistent(name) {
+                    return;
+                }
+                let grid_pos = pos_to_grid_pos(*position);
+                if let Some(grid) = self.map.get_grid(world.player_segment_id, grid_pos + world.player_grid_offset) {
+                    self.map.add_object(&MapObject {
+                        id: *id,
+                        name: name.clone(),
+                        grid_id: grid.id,
+                        offset: *position - grid_pos_to_pos(grid_pos),
+                    });
+                }
+            }
+            Event::GobRemove { id } => {
+                if let Some(object) = self.objects.get_by_id(*id) {
+                    if object.name.as_ref().map(|v| config.is_persistent(v)).unwrap_or(false)
+                        && object.position.distance(world.player_position) <= config.remove_distance {
+                        debug!("World: remove persistent object {} {:?}", object.id, object.name);
+                        self.map.remove_object(*id);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn get_player_segment(&self, player: &Player) -> Option<(i64, Vec2i)> {
+        self.for_player(player).map(|v| (v.player_segment_id, v.player_grid_offset))
+    }
+
     fn apply_update(&mut self, update: Update) -> bool {
+        let number = update.number;
         match update.event {
             Event::MapTile { id, version, name, color } => {
                 self.map.set_tile(Tile { id, version, name, color });
                 true
             }
+            Event::ResourceAdd { id, version, name } => {
+                self.map.set_resource(&Resource { id, version, name });
+                false
+            }
             Event::MapGridAdd { grid, neighbours } => {
+                let grid_id = grid.id;
+                self.ghost_objects.add_grid(grid_id, grid.position, number);
                 self.update_map(grid, neighbours);
+                self.invalidate_areas(grid_id);
                 true
             }
             Event::MapGridUpdate { grid } => {
-                self.update_map(grid, Vec::new());
-                true
+                let grid_id = grid.id;
+                let changed = self.update_map(grid, Vec::new());
+                if changed {
+                    self.invalidate_areas(grid_id);
+                }
+                changed
+            }
+            Event::MapGridRemove { id } => {
+                self.ghost_objects.remove_grid(id, number);
+                false
             }
             Event::GobAdd { id, position, angle, name } => {
-                self.objects.add(Object { id, position, angle, name });
+                let object = Object { id, position, angle, name };
+                self.obstacles.add(&object);
+                self.avoidance.add(&object);
+                let objects = Arc::make_mut(&mut self.objects);
+                objects.add(object);
+                objects.set_last_seen(id, number);
                 true
             }
             Event::GobRemove { id } => {
-                self.objects.remove(id)
+                let removed = Arc::make_mut(&mut self.objects).remove(id);
+                match self.objects.get_by_id(id) {
+                    Some(object) => {
+                        self.obstacles.add(object);
+                        self.avoidance.add(object);
+                    }
+                    None => {
+                        self.obstacles.remove(id);
+                        self.avoidance.remove(id);
+                    }
+                }
+                removed
             }
             Event::GobMove { id, position, angle } => {
-                self.objects.update(id, position, angle)
+                let objects = Arc::make_mut(&mut self.objects);
+                if !objects.update(id, position, angle) {
+                    return false;
+                }
+                objects.set_last_seen(id, number);
+                if let Some(object) = self.objects.get_by_id(id) {
+                    self.obstacles.add(object);
+                    self.avoidance.add(object);
+                }
+                true
             }
             _ => false,
         }
     }
 
-    fn update_map(&mut self, grid: MapGrid, neighbours: Vec<GridNeighbour>) {
-        if let Some(existing) = self.map.get_grid_by_id(grid.id) {
+    fn update_map(&mut self, grid: MapGrid, neighbours: Vec<GridNeighbour>) -> bool {
+        let grid_id = grid.id;
+        let changed = if let Some(existing) = self.map.get_grid_by_id(grid.id) {
             let map_grid = Grid {
                 id: existing.id,
                 segment_id: existing.segment_id,
@@ -166,7 +545,7 @@ impl World {
                 heights: grid.heights,
                 tiles: grid.tiles,
             };
-            self.map.update_grid(map_grid);
+            self.map.update_grid(map_grid)
         } else {
             let map_grid = Grid {
                 id: grid.id,
@@ -177,11 +556,51 @@ impl World {
                 tiles: grid.tiles,
             };
             self.map.add_grid(map_grid, neighbours);
+            true
+        };
+        self.map.set_grid_last_seen(grid_id, SystemTime::now());
+        changed
+    }
+
+    fn remove_ghost_objects(&mut self, number: i64) -> bool {
+        let mut removed = false;
+        for check in self.ghost_objects.take_due(number) {
+            for id in self.objects.get_ghosts(check.position, check.last_seen_before) {
+                debug!("World: remove ghost object {} from refreshed grid {}", id, check.grid_id);
+                Arc::make_mut(&mut self.objects).purge(id);
+                self.obstacles.remove(id);
+                self.avoidance.remove(id);
+                removed = true;
+            }
+        }
+        removed
+    }
+
+    fn invalidate_areas(&self, grid_id: i64) {
+        let grid = match self.map.get_grid_by_id(grid_id) {
+            Some(v) => v,
+            None => return,
+        };
+        for y in -1..=1 {
+            for x in -1..=1 {
+                if let Some(neighbour) = self.map.get_grid(grid.segment_id, grid.position + Vec2i::new(x, y)) {
+                    self.area_cache.invalidate(neighbour.id);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for World {
+    fn drop(&mut self) {
+        if self.pinned_grids_of_interest.is_some() {
+            self.map.set_pinned_grids(self.grids_of_interest_owner, 0, Vec::new());
         }
     }
 }
 
 #[allow(dead_code)]
+#[derive(Clone)]
 pub struct PlayerWorld<'a> {
     revision: u64,
     map_view_id: i32,
@@ -196,8 +615,23 @@ pub struct PlayerWorld<'a> {
     player_grid_offset: Vec2i,
     player_stamina: i32,
     player_equipment: PlayerEquipment<'a>,
-    objects: &'a Objects,
+    objects: &'a Arc<Objects>,
     map: &'a Map,
+    danger_zones: &'a DangerZones,
+    grids_of_interest: &'a GridsOfInterest,
+    anchors: &'a Anchors,
+    reachability: &'a Reachability,
+    navigator: &'a Navigator,
+    area_cache: &'a AreaCache,
+    stuck_tiles: &'a StuckTiles,
+    obstacles: &'a Obstacles,
+    avoidance: &'a Avoidance,
+    breadcrumbs: &'a Breadcrumbs,
+    containers: &'a Containers,
+    ghost_objects: &'a GhostObjects,
+    metrics: &'a Metrics,
+    tile_profiles: &'a TileProfiles,
+    tiles_snapshot: Option<&'a TilesSnapshot>,
     config: &'a WorldConfig,
 }
 
@@ -214,6 +648,10 @@ impl<'a> PlayerWorld<'a> {
         self.game_ui_id
     }
 
+    pub fn day_time(&self) -> Option<DayTime> {
+        self.player.day_time()
+    }
+
     pub fn player_object_id(&self) -> i64 {
         self.player_object_id
     }
@@ -234,14 +672,34 @@ impl<'a> PlayerWorld<'a> {
         self.player.is_stuck()
     }
 
+    pub fn player_speed(&self) -> Option<f64> {
+        self.player.speed()
+    }
+
     pub fn player_stamina(&self) -> i32 {
         self.player_stamina
     }
 
+    pub fn player_inventory_id(&self) -> i32 {
+        self.player_inventory_id
+    }
+
+    pub fn player_belt_inventory_id(&self) -> Option<i32> {
+        self.player.belt_inventory_id()
+    }
+
     pub fn player_inventory_items(&self) -> &BTreeMap<i32, Item> {
         &self.player.widget_inventories()[&self.player_inventory_id]
     }
 
+    pub fn player_inventory_free_slots(&self) -> Option<usize> {
+        self.player.free_slots()
+    }
+
+    pub fn player_inventory_can_fit(&self, item_size: Vec2i) -> bool {
+        self.player.can_fit(item_size)
+    }
+
     pub fn player_belt_items(&self) -> Option<&BTreeMap<i32, Item>> {
         self.player.belt_inventory_id()
             .map(|belt_id| &self.player.widget_inventories()[&belt_id])
@@ -271,6 +729,146 @@ impl<'a> PlayerWorld<'a> {
         self.config
     }
 
+    pub fn tile_profiles(&self) -> &TileProfiles {
+        self.tile_profiles
+    }
+
+    pub fn known_containers(&self) -> impl Iterator<Item=&Container> {
+        self.containers.iter()
+    }
+
+    pub fn find_nearest_non_full_container(&self) -> Option<&Container> {
+        self.containers.find_nearest_non_full(self.player_position)
+    }
+
+    pub fn danger_zones(&self) -> &DangerZones {
+        self.danger_zones
+    }
+
+    pub fn stuck_tiles(&self) -> &StuckTiles {
+        self.stuck_tiles
+    }
+
+    pub fn area_cache(&self) -> &AreaCache {
+        self.area_cache
+    }
+
+    pub fn ghost_objects(&self) -> &GhostObjects {
+        self.ghost_objects
+    }
+
+    pub fn obstacles(&self) -> &Obstacles {
+        self.obstacles
+    }
+
+    pub fn avoidance(&self) -> &Avoidance {
+        self.avoidance
+    }
+
+    pub fn get_backtrack(&self) -> Vec<Vec2f> {
+        let shift = grid_pos_to_pos(self.player_grid_offset);
+        self.breadcrumbs.get_backtrack(self.player_segment_id).into_iter()
+            .map(|position| position - shift)
+            .collect()
+    }
+
+    pub fn get_anchored_position(&self, pos: Vec2f) -> Option<Vec2f> {
+        self.anchors.get_position(self.map, self.player_segment_id)
+            .map(|anchor_pos| pos + grid_pos_to_pos(self.player_grid_offset) - anchor_pos)
+    }
+
+    pub fn get_position_by_anchored(&self, anchored_pos: Vec2f) -> Option<Vec2f> {
+        self.anchors.get_position(self.map, self.player_segment_id)
+            .map(|anchor_pos| anchored_pos + anchor_pos - grid_pos_to_pos(self.player_grid_offset))
+    }
+
+    // Coordinates reported outside of the bot are relative to the segment anchor when there is one
+    pub fn export_position(&self, pos: Vec2f) -> Vec2f {
+        self.get_anchored_position(pos).unwrap_or(pos)
+    }
+
+    pub fn import_position(&self, pos: Vec2f) -> Vec2f {
+        self.get_position_by_anchored(pos).unwrap_or(pos)
+    }
+
+    pub fn export_tile_pos(&self, tile_pos: Vec2i) -> Vec2i {
+        pos_to_tile_pos(self.export_position(rel_tile_pos_to_pos(tile_pos.center())))
+    }
+
+    pub fn import_tile_pos(&self, tile_pos: Vec2i) -> Vec2i {
+        pos_to_tile_pos(self.import_position(rel_tile_pos_to_pos(tile_pos.center())))
+    }
+
+    pub fn get_location_position(&self, name: &str) -> Option<Vec2f> {
+        self.anchors.get_location(name).and_then(|v| self.get_position_by_anchored(v))
+    }
+
+    pub fn get_bookmark_position(&self, name: &str) -> Option<Vec2f> {
+        self.map.get_bookmarks(self.player_segment_id).into_iter()
+            .find(|v| v.name == name)
+            .map(|v| v.position)
+    }
+
+    pub fn grids_of_interest(&self) -> &GridsOfInterest {
+        self.grids_of_interest
+    }
+
+    pub fn get_global_grid_pos(&self, tile_pos: Vec2i) -> Vec2i {
+        tile_pos_to_grid_pos(tile_pos) + self.player_grid_offset
+    }
+
+    pub fn is_grid_of_interest(&self, tile_pos: Vec2i) -> bool {
+        self.grids_of_interest.contains(tile_pos_to_grid_pos(tile_pos))
+    }
+
+    pub fn is_dangerous_path<'b>(&self, src: Vec2f, tile_pos_path: impl Iterator<Item=&'b Vec2i>) -> bool {
+        let mut prev = src;
+        for tile_pos in tile_pos_path {
+            let next = rel_tile_pos_to_pos(tile_pos.center());
+            if self.danger_zones.intersects(&Segment::new(prev, next)) {
+                return true;
+            }
+            prev = next;
+        }
+        false
+    }
+
+    pub fn is_avoided_path<'b>(&self, src: Vec2f, tile_pos_path: impl Iterator<Item=&'b Vec2i>) -> bool {
+        let mut prev = pos_to_rel_tile_pos(src);
+        for tile_pos in tile_pos_path {
+            let next = tile_pos.center();
+            if self.is_avoided_segment(prev, next) {
+                return true;
+            }
+            prev = next;
+        }
+        false
+    }
+
+    pub fn get_persistent_objects_in_rect(&self, min: Vec2f, max: Vec2f) -> Vec<Object> {
+        let shift = grid_pos_to_pos(self.player_grid_offset);
+        self.map.get_objects_in_rect(self.player_segment_id, min + shift, max + shift).into_iter()
+            .map(|(position, object)| Object { id: object.id, position: position - shift, angle: 0.0, name: Some(object.name) })
+            .collect()
+    }
+
+    pub fn get_object_by_id(&self, object_id: i64) -> Option<&Object> {
+        self.objects.get_by_id(object_id)
+    }
+
+    pub fn find_openable_objects(&self, src: Vec2f, dst: Vec2f) -> Vec<&Object> {
+        let mut object_ids = Vec::new();
+        walk_grid(pos_to_rel_tile_pos(src), pos_to_rel_tile_pos(dst), |position| {
+            for object_id in self.obstacles.get_openable_objects(Vec2i::from(position.floor())) {
+                if !object_ids.contains(&object_id) {
+                    object_ids.push(object_id);
+                }
+            }
+            true
+        });
+        object_ids.into_iter().filter_map(|id| self.objects.get_by_id(id)).collect()
+    }
+
     pub fn get_object_by_name(&self, name: &String) -> Option<&Object> {
         self.objects.get_by_name(name)
     }
@@ -284,24 +882,131 @@ impl<'a> PlayerWorld<'a> {
     }
 
     pub fn get_tile(&self, tile_pos: Vec2i) -> Option<i32> {
+        if let Some(tiles_snapshot) = self.tiles_snapshot {
+            return tiles_snapshot.get_tile(
+                self.map,
+                self.player_segment_id,
+                tile_pos + grid_pos_to_tile_pos(self.player_grid_offset),
+            );
+        }
         self.map.get_tile(
             self.player_segment_id,
             tile_pos + grid_pos_to_tile_pos(self.player_grid_offset),
         )
     }
 
+    pub fn zone_at(&self, tile_pos: Vec2i) -> Option<String> {
+        let segment_tile_pos = tile_pos + grid_pos_to_tile_pos(self.player_grid_offset);
+        self.map.get_zones(self.player_segment_id).into_iter()
+            .find(|v| v.contains(segment_tile_pos))
+            .map(|v| v.name)
+    }
+
+    pub fn iter_zone_tiles(&self, name: &String) -> impl Iterator<Item=Vec2i> {
+        let shift = grid_pos_to_tile_pos(self.player_grid_offset);
+        let tiles: Vec<Vec2i> = self.map.get_zones(self.player_segment_id).into_iter()
+            .find(|v| &v.name == name)
+            .map(|v| v.iter_tiles().map(|tile_pos| tile_pos - shift).collect())
+            .unwrap_or_default();
+        tiles.into_iter()
+    }
+
+    pub fn iter_road_tile_names(&self) -> impl Iterator<Item=&String> {
+        let roads = &self.config.roads;
+        self.map.iter_tiles()
+            .filter(move |v| roads.is_road_tile(v.name.as_str()))
+            .map(|v| &v.name)
+    }
+
+    pub fn nearest_road_tile(&self) -> Option<Vec2i> {
+        let road_tiles: BTreeSet<i32> = self.map.iter_tiles()
+            .filter(|v| self.config.roads.is_road_tile(v.name.as_str()))
+            .map(|v| v.id)
+            .collect();
+        if road_tiles.is_empty() {
+            return None;
+        }
+        let player_tile_pos = pos_to_tile_pos(self.player_position);
+        let radius = (self.config.roads.max_snap_distance / TILE_SIZE).ceil() as i32;
+        (-radius..=radius)
+            .flat_map(|y| (-radius..=radius).map(move |x| player_tile_pos + Vec2i::new(x, y)))
+            .filter(|tile_pos| self.get_tile(*tile_pos).map(|v| road_tiles.contains(&v)).unwrap_or(false))
+            .map(|tile_pos| (tile_pos, rel_tile_pos_to_pos(tile_pos.center()).distance(self.player_position)))
+            .filter(|(_, distance)| *distance <= self.config.roads.max_snap_distance)
+            .min_by(|(_, lhs), (_, rhs)| lhs.partial_cmp(rhs).unwrap())
+            .map(|(tile_pos, _)| tile_pos)
+    }
+
+    pub fn find_fields(&self) -> Vec<Field> {
+        let config = &self.config.fields;
+        let field_tiles = FieldTiles(self.map.iter_tiles()
+            .filter(|v| config.is_field_tile(v.name.as_str()))
+            .map(|v| v.id)
+            .collect());
+        if field_tiles.0.is_empty() {
+            return Vec::new();
+        }
+        let shift = grid_pos_to_tile_pos(self.player_grid_offset);
+        let is_field_tile = |tile_pos: Vec2i| {
+            self.map.get_tile(self.player_segment_id, tile_pos).map(|v| field_tiles.contains(v)).unwrap_or(false)
+        };
+        let mut result: Vec<Field> = self.navigator.get_components(self.map, self.player_segment_id, &field_tiles).iter()
+            .filter_map(|component| make_field(self.player_segment_id, component, shift, is_field_tile))
+            .filter(|v| v.tiles >= config.min_tiles)
+            .collect();
+        result.sort_by_key(|v| (-(v.tiles as i64), v.from));
+        for (index, field) in result.iter_mut().enumerate() {
+            field.zone.name = format!("field {}", index + 1);
+        }
+        result
+    }
+
+    pub fn get_height(&self, tile_pos: Vec2i) -> Option<f32> {
+        self.map.get_height(
+            self.player_segment_id,
+            tile_pos + grid_pos_to_tile_pos(self.player_grid_offset),
+        )
+    }
+
     pub fn iter_grids(&self) -> impl Iterator<Item=&Grid> {
         self.map.iter_grids()
     }
 
+    fn get_height_delta_weight(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i) -> Option<f64> {
+        let shift = grid_pos_to_tile_pos(self.player_grid_offset);
+        get_height_delta_weight(
+            self.map.get_height(self.player_segment_id, src_tile_pos + shift),
+            self.map.get_height(self.player_segment_id, dst_tile_pos + shift),
+            self.config.max_height_delta,
+            self.config.height_delta_weight,
+        )
+    }
+
     pub fn iter_objects(&self) -> impl Iterator<Item=&Object> {
         self.objects.iter()
     }
 
+    pub fn shared_objects(&self) -> Arc<Objects> {
+        self.objects.clone()
+    }
+
     pub fn objects_len(&self) -> usize {
         self.objects.len()
     }
 
+    pub fn find_object_clusters(&self, resource_names: &BTreeSet<String>, max_distance: f64) -> Vec<ObjectCluster> {
+        let objects: Vec<&Object> = self.objects.iter()
+            .filter(|v| v.name.as_ref().map(|name| resource_names.contains(name)).unwrap_or(false))
+            .collect();
+        let positions = objects.iter().map(|v| v.position).collect();
+        make_distance_clusters(&positions, max_distance).into_iter()
+            .map(|cluster| ObjectCluster {
+                centroid: cluster.iter().fold(Vec2f::zero(), |r, &i| r + objects[i].position) / cluster.len() as f64,
+                object_ids: cluster.iter().map(|&i| objects[i].id).collect(),
+            })
+            .collect()
+    }
+
     pub fn get_grid_by_id(&self, grid_id: i64) -> Option<&Grid> {
         self.map.get_grid_by_id(grid_id)
     }
@@ -310,12 +1015,209 @@ impl<'a> PlayerWorld<'a> {
         self.map.find_border_tiles(self.player_segment_id, weights)
     }
 
+    pub fn get_stale_grids(&self, max_age: Duration) -> Vec<Vec2i> {
+        self.map.get_grids_older_than(self.player_segment_id, SystemTime::now() - max_age)
+    }
+
     pub fn find_path(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i, weights: &impl TileWeights,
                      max_shortcut_length: f64, max_iterations: usize,
                      node: &Arc<Mutex<Node>>, cancel: &Arc<AtomicBool>) -> Vec<Vec2i> {
         if src_tile_pos == dst_tile_pos {
             return vec![dst_tile_pos];
         }
+        if !self.is_reachable(src_tile_pos, dst_tile_pos, weights) {
+            debug!("find_path src_tile_pos={:?} dst_tile_pos={:?} is not reachable", src_tile_pos, dst_tile_pos);
+            return Vec::new();
+        }
+        let started = Instant::now();
+        let corridor = self.find_corridor(src_tile_pos, dst_tile_pos, weights);
+        let tiles_snapshot = TilesSnapshot::new();
+        let world = PlayerWorld { tiles_snapshot: Some(&tiles_snapshot), ..self.clone() };
+        let path = world.find_path_in_snapshot(src_tile_pos, dst_tile_pos, weights, max_shortcut_length, max_iterations,
+                                               corridor.as_ref(), node, cancel);
+        debug!("find_path used {} grids snapshot at revision {}", tiles_snapshot.len(), self.revision);
+        self.metrics.add_path_finder_duration(Instant::now() - started);
+        path
+    }
+
+    pub fn start_path_search(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i, weights: &impl TileWeights,
+                             max_iterations: usize) -> Option<PathSearch> {
+        if src_tile_pos != dst_tile_pos && !self.is_reachable(src_tile_pos, dst_tile_pos, weights) {
+            debug!("start_path_search src_tile_pos={:?} dst_tile_pos={:?} is not reachable", src_tile_pos, dst_tile_pos);
+            return None;
+        }
+        let corridor = self.find_corridor(src_tile_pos, dst_tile_pos, weights);
+        Some(PathSearch::new(src_tile_pos, dst_tile_pos, corridor, max_iterations, self.path_search_revisions(),
+                             self.get_landmarks(weights)))
+    }
+
+    pub fn is_stale_path_search(&self, search: &PathSearch) -> bool {
+        search.revisions != self.path_search_revisions()
+    }
+
+    fn path_search_revisions(&self) -> PathSearchRevisions {
+        PathSearchRevisions {
+            map: self.map.revision(),
+            obstacles: self.obstacles.revision(),
+            danger_zones: self.danger_zones.revision(),
+            avoidance: self.avoidance.revision(),
+            stuck_tiles: self.stuck_tiles.revision(),
+        }
+    }
+
+    pub fn continue_path_search(&self, search: &mut PathSearch, weights: &impl TileWeights, max_shortcut_length: f64,
+                                iterations_budget: usize, node: &Arc<Mutex<Node>>, cancel: &Arc<AtomicBool>) -> PathSearchState {
+        if search.src_tile_pos == search.dst_tile_pos {
+            return PathSearchState::Found(vec![search.dst_tile_pos]);
+        }
+        let started = Instant::now();
+        let tiles_snapshot = replace(&mut search.tiles_snapshot, TilesSnapshot::new());
+        let world = PlayerWorld { tiles_snapshot: Some(&tiles_snapshot), ..self.clone() };
+        let mut transitions = Transitions::new(
+            node,
+            &self.config.direct_path_transition_color,
+            &self.config.found_transition_color,
+            &self.config.shorten_path_transition_color,
+        );
+        transitions.id_counter = search.transition_id;
+        if search.iterations == 0 {
+            transitions.add_direct_path(search.src_tile_pos, search.dst_tile_pos);
+        }
+        let mut state = world.continue_reversed_tiles_path(search, weights, iterations_budget, &mut transitions, cancel);
+        if state == PathSearchState::NotFound && search.corridor.is_some() && !cancel.load(Ordering::Relaxed) {
+            debug!("continue_path_search corridor search failed, fallback to full search");
+            search.reset_without_corridor();
+            state = PathSearchState::InProgress;
+        }
+        if let PathSearchState::Found(path) = state {
+            transitions.add_path(search.src_tile_pos, &path, true, self.config.path_transition_color);
+            let shorten_path = world.shorten_reversed_tiles_path(path, weights, max_shortcut_length);
+            transitions.add_shorten_path(search.src_tile_pos, &shorten_path);
+            state = PathSearchState::Found(shorten_path);
+        }
+        search.transition_id = transitions.id_counter;
+        debug!("continue_path_search used {} grids snapshot at revision {}", tiles_snapshot.len(), search.revisions.map);
+        search.tiles_snapshot = tiles_snapshot;
+        self.metrics.add_path_finder_duration(Instant::now() - started);
+        state
+    }
+
+    pub fn is_reachable(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i, allowed_tiles: &impl TileSet) -> bool {
+        let offset = grid_pos_to_tile_pos(self.player_grid_offset);
+        self.reachability.is_reachable(self.map, self.revision, self.player_segment_id, src_tile_pos + offset,
+                                       dst_tile_pos + offset, allowed_tiles)
+    }
+
+    pub fn filter_reachable(&self, src_tile_pos: Vec2i, dst_tile_positions: &[Vec2i], allowed_tiles: &impl TileSet) -> Vec<Vec2i> {
+        let offset = grid_pos_to_tile_pos(self.player_grid_offset);
+        let dst_tile_positions: Vec<Vec2i> = dst_tile_positions.iter().map(|v| *v + offset).collect();
+        self.reachability.filter_reachable(self.map, self.revision, self.player_segment_id, src_tile_pos + offset,
+                                           &dst_tile_positions, allowed_tiles)
+            .into_iter()
+            .map(|v| v - offset)
+            .collect()
+    }
+
+    pub fn estimate_path_cost(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i, allowed_tiles: &impl TileSet) -> Option<f64> {
+        let offset = grid_pos_to_tile_pos(self.player_grid_offset);
+        self.get_landmarks(allowed_tiles).estimate(src_tile_pos + offset, dst_tile_pos + offset)
+    }
+
+    fn get_landmarks(&self, allowed_tiles: &impl TileSet) -> Arc<Landmarks> {
+        self.reachability.get_landmarks(self.map, self.revision, self.player_segment_id, allowed_tiles)
+    }
+
+    pub fn get_path_costs(&self, src_tile_pos: Vec2i, dst_tile_positions: &[Vec2i], allowed_tiles: &impl TileSet) -> Vec<Option<f64>> {
+        let offset = grid_pos_to_tile_pos(self.player_grid_offset);
+        let dst_tile_positions: Vec<Vec2i> = dst_tile_positions.iter().map(|v| *v + offset).collect();
+        self.navigator.get_path_costs(self.map, self.player_segment_id, src_tile_pos + offset, &dst_tile_positions, allowed_tiles)
+    }
+
+    pub fn get_crossing_cost(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i, weights: &impl TileWeights) -> f64 {
+        let mut result = 0.0;
+        walk_grid(src_tile_pos.center(), dst_tile_pos.center(), |position| {
+            if let Some(weight) = self.get_tile(Vec2i::from(position.floor())).and_then(|tile| weights.get(tile)) {
+                result += weight;
+            }
+            true
+        });
+        result
+    }
+
+    pub fn has_line_of_sight(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i) -> bool {
+        walk_grid(src_tile_pos.center(), dst_tile_pos.center(), |position| {
+            let tile_pos = Vec2i::from(position.floor());
+            if tile_pos == src_tile_pos || tile_pos == dst_tile_pos {
+                return true;
+            }
+            self.get_tile(tile_pos).is_some() && !self.obstacles.contains(tile_pos)
+        })
+    }
+
+    pub fn get_snapshot(&self, radius: i32, weights: Option<&impl TileWeights>) -> WorldSnapshot {
+        let center = pos_to_tile_pos(self.player_position);
+        let min = center - Vec2i::new(radius, radius);
+        let size = (2 * radius + 1) as usize;
+        let mut ids = Vec::with_capacity(size);
+        let mut names = BTreeMap::new();
+        let mut tile_weights = Vec::with_capacity(size);
+        let mut heights = Vec::with_capacity(size);
+        for y in 0..size as i32 {
+            let mut ids_row = Vec::with_capacity(size);
+            let mut weights_row = Vec::with_capacity(size);
+            let mut heights_row = Vec::with_capacity(size);
+            for x in 0..size as i32 {
+                let tile_pos = min + Vec2i::new(x, y);
+                let tile = self.get_tile(tile_pos);
+                if let Some(tile) = tile.and_then(|v| self.get_tile_by_id(v)) {
+                    names.entry(tile.id).or_insert_with(|| tile.name.clone());
+                }
+                ids_row.push(tile);
+                weights_row.push(weights.and_then(|v| tile.and_then(|tile| v.get(tile))));
+                heights_row.push(self.get_height(tile_pos));
+            }
+            ids.push(ids_row);
+            tile_weights.push(weights_row);
+            heights.push(heights_row);
+        }
+        WorldSnapshot {
+            segment_id: self.player_segment_id,
+            center,
+            min,
+            ids,
+            names,
+            weights: weights.map(|_| tile_weights),
+            heights,
+        }
+    }
+
+    fn find_corridor(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i, allowed_tiles: &impl TileSet) -> Option<BTreeSet<Vec2i>> {
+        if src_tile_pos.center().distance(dst_tile_pos.center()) < self.config.hierarchical_path_min_distance {
+            return None;
+        }
+        let offset = grid_pos_to_tile_pos(self.player_grid_offset);
+        let corridor = self.navigator.find_corridor(self.map, self.player_segment_id, src_tile_pos + offset, dst_tile_pos + offset, allowed_tiles)?;
+        debug!("find_corridor src_tile_pos={:?} dst_tile_pos={:?} grids={}", src_tile_pos, dst_tile_pos, corridor.len());
+        Some(corridor.into_iter().map(|grid_pos| grid_pos - self.player_grid_offset).collect())
+    }
+
+    pub fn is_valid_path<'b>(&self, mut tile_pos_path: impl Iterator<Item=&'b Vec2i>, allowed_tiles: &impl TileSet) -> bool {
+        let mut prev = match tile_pos_path.next() {
+            Some(v) => *v,
+            None => return true,
+        };
+        for &tile_pos in tile_pos_path {
+            if !self.is_valid_shortcut(prev, tile_pos, allowed_tiles, std::f64::MAX) {
+                return false;
+            }
+            prev = tile_pos;
+        }
+        true
+    }
+
+    fn find_path_in_snapshot(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i, weights: &impl TileWeights,
+                             max_shortcut_length: f64, max_iterations: usize, corridor: Option<&BTreeSet<Vec2i>>,
+                             node: &Arc<Mutex<Node>>, cancel: &Arc<AtomicBool>) -> Vec<Vec2i> {
         let mut transitions = Transitions::new(
             node,
             &self.config.direct_path_transition_color,
@@ -323,7 +1225,11 @@ impl<'a> PlayerWorld<'a> {
             &self.config.shorten_path_transition_color,
         );
         transitions.add_direct_path(src_tile_pos, dst_tile_pos);
-        let path = self.find_reversed_tiles_path(src_tile_pos, dst_tile_pos, weights, max_iterations, &mut transitions, cancel);
+        let mut path = self.find_reversed_tiles_path(src_tile_pos, dst_tile_pos, weights, max_iterations, corridor, &mut transitions, cancel);
+        if path.is_empty() && corridor.is_some() && !cancel.load(Ordering::Relaxed) {
+            debug!("find_path_in_snapshot corridor search failed, fallback to full search");
+            path = self.find_reversed_tiles_path(src_tile_pos, dst_tile_pos, weights, max_iterations, None, &mut transitions, cancel);
+        }
         transitions.add_path(src_tile_pos, &path, true, self.config.path_transition_color);
         let shorten_path = self.shorten_reversed_tiles_path(path, weights, max_shortcut_length);
         transitions.add_shorten_path(src_tile_pos, &shorten_path);
@@ -331,17 +1237,18 @@ impl<'a> PlayerWorld<'a> {
     }
 
     fn find_reversed_tiles_path(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i,
-                                weights: &impl TileWeights, max_iterations: usize,
+                                weights: &impl TileWeights, max_iterations: usize, corridor: Option<&BTreeSet<Vec2i>>,
                                 transitions: &mut Transitions, cancel: &Arc<AtomicBool>) -> Vec<Vec2i> {
-        let mut ordered = BinaryHeap::new();
-        let mut costs: BTreeMap<Vec2i, f64> = BTreeMap::new();
-        let mut backtrack = BTreeMap::new();
-        let mut open_set = BTreeSet::new();
-
-        let initial_distance = src_tile_pos.center().distance(dst_tile_pos.center());
-        costs.insert(src_tile_pos, 0.0);
-        ordered.push((as_score(initial_distance), src_tile_pos));
+        let mut search = PathSearch::new(src_tile_pos, dst_tile_pos, corridor.cloned(), max_iterations, self.path_search_revisions(),
+                                         self.get_landmarks(weights));
+        match self.continue_reversed_tiles_path(&mut search, weights, max_iterations, transitions, cancel) {
+            PathSearchState::Found(path) => path,
+            PathSearchState::InProgress | PathSearchState::NotFound => Vec::new(),
+        }
+    }
 
+    fn continue_reversed_tiles_path(&self, search: &mut PathSearch, weights: &impl TileWeights, iterations_budget: usize,
+                                    transitions: &mut Transitions, cancel: &Arc<AtomicBool>) -> PathSearchState {
         const EDGES: &[(Vec2i, f64)] = &[
             (Vec2i::new(-1, -1), std::f64::consts::SQRT_2),
             (Vec2i::new(-1, 0), 1.0),
@@ -353,14 +1260,24 @@ impl<'a> PlayerWorld<'a> {
             (Vec2i::new(1, 1), std::f64::consts::SQRT_2),
         ];
 
-        let mut iterations: usize = 0;
-        let mut push_count: usize = 0;
-        let mut min_distance = src_tile_pos.center().distance(dst_tile_pos.center());
+        let src_tile_pos = search.src_tile_pos;
+        let dst_tile_pos = search.dst_tile_pos;
+        let iterations_limit = search.iterations.saturating_add(iterations_budget).min(search.max_iterations);
 
-        debug!("find_reversed_tiles_path src_tile_pos={:?} dst_tile_pos={:?} distance={}",
-               src_tile_pos, dst_tile_pos, min_distance);
+        debug!("find_reversed_tiles_path src_tile_pos={:?} dst_tile_pos={:?} distance={} iterations={}",
+               src_tile_pos, dst_tile_pos, search.min_distance, search.iterations);
 
-        let get_weight = |tile_pos| self.get_tile(tile_pos).and_then(|tile| weights.get(tile));
+        let now = Instant::now();
+        let tile_pos_offset = grid_pos_to_tile_pos(self.player_grid_offset);
+        let is_blocked = |tile_pos| {
+            tile_pos != src_tile_pos && tile_pos != dst_tile_pos && !self.obstacles.is_passable(tile_pos)
+        };
+        let get_weight = |tile_pos| {
+            if is_blocked(tile_pos) {
+                return None;
+            }
+            self.get_tile(tile_pos).and_then(|tile| weights.get(tile))
+        };
         let is_reachable = |tile_pos| {
             if let Some(tile) = self.get_tile(tile_pos) {
                 weights.get(tile).is_some()
@@ -370,33 +1287,45 @@ impl<'a> PlayerWorld<'a> {
         };
 
         if !is_reachable(dst_tile_pos) {
-            return Vec::new();
+            return PathSearchState::NotFound;
         }
 
-        while let Some((_, tile_pos)) = ordered.pop() {
-            min_distance = min_distance.min(tile_pos.center().distance(dst_tile_pos.center()));
+        while let Some((score, tile_pos)) = search.ordered.pop() {
+            search.min_distance = search.min_distance.min(tile_pos.center().distance(dst_tile_pos.center()));
             if tile_pos == dst_tile_pos {
                 debug!("find_reversed_tiles_path found iterations={} ordered={} costs={} push_count={} min_distance={}",
-                       iterations, ordered.len(), costs.len(), push_count, min_distance);
-                return reconstruct_path(src_tile_pos, dst_tile_pos, backtrack);
+                       search.iterations, search.ordered.len(), search.costs.len(), search.push_count, search.min_distance);
+                self.metrics.add_path_finder_iterations(search.iterations);
+                return PathSearchState::Found(reconstruct_path(src_tile_pos, dst_tile_pos, std::mem::take(&mut search.backtrack)));
             }
             if cancel.load(Ordering::Relaxed) {
                 debug!("find_reversed_tiles_path cancelled");
                 break;
             }
-            if iterations >= max_iterations {
+            if search.iterations >= search.max_iterations {
                 debug!("find_reversed_tiles_path reached max iterations");
                 break;
             }
-            open_set.remove(&tile_pos);
+            if search.iterations >= iterations_limit {
+                debug!("find_reversed_tiles_path paused iterations={} ordered={} costs={} push_count={} min_distance={}",
+                       search.iterations, search.ordered.len(), search.costs.len(), search.push_count, search.min_distance);
+                search.ordered.push((score, tile_pos));
+                return PathSearchState::InProgress;
+            }
+            search.open_set.remove(&tile_pos);
             if let Some(tile) = self.get_tile(tile_pos) {
                 if let Some(weight) = weights.get(tile) {
                     for &(shift, distance) in EDGES.iter() {
                         let next_tile_pos = tile_pos + shift;
+                        if search.corridor.as_ref().map(|v| !v.contains(&tile_pos_to_grid_pos(next_tile_pos))).unwrap_or(false) {
+                            continue;
+                        }
                         if let Some(next_weight) = get_weight(next_tile_pos) {
                             if distance != 1.0 {
-                                if !is_reachable(tile_pos + shift.with_x(0))
-                                    || !is_reachable(tile_pos + shift.with_y(0)) {
+                                let x_corner = tile_pos + shift.with_x(0);
+                                let y_corner = tile_pos + shift.with_y(0);
+                                if !is_reachable(x_corner) || !is_reachable(y_corner)
+                                    || is_blocked(x_corner) || is_blocked(y_corner) {
                                     continue;
                                 }
                             }
@@ -410,15 +1339,23 @@ impl<'a> PlayerWorld<'a> {
                                 || bottom != tile_pos && !is_reachable(bottom) {
                                 continue;
                             }
-                            let next_cost = costs[&tile_pos] + distance * (weight + next_weight) / 2.0;
-                            let other_cost = *costs.get(&next_tile_pos).unwrap_or(&std::f64::MAX);
+                            let height_weight = match self.get_height_delta_weight(tile_pos, next_tile_pos) {
+                                Some(v) => v,
+                                None => continue,
+                            };
+                            let danger_weight = self.danger_zones.get_weight(rel_tile_pos_to_pos(next_tile_pos.center()));
+                            let stuck_weight = self.stuck_tiles.get_weight(self.player_segment_id, next_tile_pos + tile_pos_offset, now);
+                            let open_weight = self.obstacles.get_open_cost(next_tile_pos).unwrap_or(0.0);
+                            let avoid_weight = self.avoidance.get_weight(next_tile_pos);
+                            let next_cost = search.costs[&tile_pos] + distance * ((weight + next_weight) / 2.0 + danger_weight + stuck_weight + height_weight + open_weight + avoid_weight);
+                            let other_cost = *search.costs.get(&next_tile_pos).unwrap_or(&std::f64::MAX);
                             if next_cost < other_cost {
-                                backtrack.insert(next_tile_pos, tile_pos);
-                                costs.insert(next_tile_pos, next_cost);
-                                if open_set.insert(next_tile_pos) {
-                                    let next_score = next_cost + next_tile_pos.center().distance(dst_tile_pos.center());
-                                    ordered.push((-as_score(next_score), next_tile_pos));
-                                    push_count += 1;
+                                search.backtrack.insert(next_tile_pos, tile_pos);
+                                search.costs.insert(next_tile_pos, next_cost);
+                                if search.open_set.insert(next_tile_pos) {
+                                    let next_score = next_cost + search.get_heuristic(next_tile_pos, tile_pos_offset);
+                                    search.ordered.push((-as_score(next_score), next_tile_pos));
+                                    search.push_count += 1;
                                 }
                             }
                             transitions.update_found(tile_pos, next_tile_pos);
@@ -426,17 +1363,18 @@ impl<'a> PlayerWorld<'a> {
                     }
                 }
             }
-            iterations += 1;
-            if iterations % self.config.report_iterations == 0 {
+            search.iterations += 1;
+            if search.iterations % self.config.report_iterations == 0 {
                 debug!("find_reversed_tiles_path iterations={} ordered={} costs={} push_count={} min_distance={}",
-                       iterations, ordered.len(), costs.len(), push_count, min_distance);
+                       search.iterations, search.ordered.len(), search.costs.len(), search.push_count, search.min_distance);
             }
         }
 
         debug!("find_reversed_tiles_path not found iterations={} ordered={} costs={} push_count={} min_distance={}",
-               iterations, ordered.len(), costs.len(), push_count, min_distance);
+               search.iterations, search.ordered.len(), search.costs.len(), search.push_count, search.min_distance);
+        self.metrics.add_path_finder_iterations(search.iterations);
 
-        Vec::new()
+        PathSearchState::NotFound
     }
 
     fn shorten_reversed_tiles_path(&self, reversed_tiles_path: Vec<Vec2i>,
@@ -451,12 +1389,12 @@ impl<'a> PlayerWorld<'a> {
 
         while last > 0 {
             let mut index = 0;
-            while index < last && !self.is_valid_shortcut(
+            while index < last && (!self.is_valid_shortcut(
                 current,
                 reversed_tiles_path[index],
                 allowed_tiles,
                 max_shortcut_length,
-            ) {
+            ) || self.is_avoided_segment(current.center(), reversed_tiles_path[index].center())) {
                 index += 1;
             }
             if index == last {
@@ -474,6 +1412,9 @@ impl<'a> PlayerWorld<'a> {
 
     pub fn is_valid_shortcut(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i,
                              allowed_tiles: &impl TileSet, max_length: f64) -> bool {
+        if self.danger_zones.intersects(&Segment::new(rel_tile_pos_to_pos(src_tile_pos.center()), rel_tile_pos_to_pos(dst_tile_pos.center()))) {
+            return false;
+        }
         if src_tile_pos.x() == dst_tile_pos.x() {
             self.is_valid_shortcut_by_x(src_tile_pos, dst_tile_pos, allowed_tiles, max_length)
         } else if src_tile_pos.y() == dst_tile_pos.y() {
@@ -490,13 +1431,21 @@ impl<'a> PlayerWorld<'a> {
 
     fn is_valid_shortcut_by_x(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i,
                               allowed_tiles: &impl TileSet, max_length: f64) -> bool {
+        let is_shortcut = is_shortcut(src_tile_pos, dst_tile_pos);
         let mut y = src_tile_pos.y();
         let shift = if y < dst_tile_pos.y() { 1 } else { -1 };
         while y != dst_tile_pos.y() {
             if (src_tile_pos.y() - y).abs() as f64 > max_length {
                 return false;
             }
-            if let Some(tile) = self.get_tile(src_tile_pos.with_y(y)) {
+            let tile_pos = src_tile_pos.with_y(y);
+            if tile_pos != src_tile_pos && !self.obstacles.is_passable(tile_pos) {
+                return false;
+            }
+            if !self.is_valid_height_step(tile_pos, tile_pos.with_y(y + shift), is_shortcut) {
+                return false;
+            }
+            if let Some(tile) = self.get_tile(tile_pos) {
                 if allowed_tiles.contains(tile) {
                     y += shift;
                     continue;
@@ -509,13 +1458,21 @@ impl<'a> PlayerWorld<'a> {
 
     fn is_valid_shortcut_by_y(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i,
                               allowed_tiles: &impl TileSet, max_length: f64) -> bool {
+        let is_shortcut = is_shortcut(src_tile_pos, dst_tile_pos);
         let mut x = src_tile_pos.x();
         let shift = if x < dst_tile_pos.x() { 1 } else { -1 };
         while x != dst_tile_pos.x() {
             if (src_tile_pos.x() - x).abs() as f64 > max_length {
                 return false;
             }
-            if let Some(tile) = self.get_tile(src_tile_pos.with_x(x)) {
+            let tile_pos = src_tile_pos.with_x(x);
+            if tile_pos != src_tile_pos && !self.obstacles.is_passable(tile_pos) {
+                return false;
+            }
+            if !self.is_valid_height_step(tile_pos, tile_pos.with_x(x + shift), is_shortcut) {
+                return false;
+            }
+            if let Some(tile) = self.get_tile(tile_pos) {
                 if allowed_tiles.contains(tile) {
                     x += shift;
                     continue;
@@ -528,7 +1485,16 @@ impl<'a> PlayerWorld<'a> {
 
     pub fn is_valid_shortcut_by_rel_pos(&self, src_rel_tile_pos: Vec2f, dst_rel_tile_pos: Vec2f,
                                         allowed_tiles: &impl TileSet, max_length: f64) -> bool {
+        if self.danger_zones.intersects(&Segment::new(rel_tile_pos_to_pos(src_rel_tile_pos), rel_tile_pos_to_pos(dst_rel_tile_pos))) {
+            return false;
+        }
+        let src_tile_pos = Vec2i::from(src_rel_tile_pos.floor());
+        let dst_tile_pos = Vec2i::from(dst_rel_tile_pos.floor());
+        let is_shortcut = is_shortcut(src_tile_pos, dst_tile_pos);
         let is_allowed = |tile_pos| {
+            if tile_pos != src_tile_pos && tile_pos != dst_tile_pos && !self.obstacles.is_passable(tile_pos) {
+                return false;
+            }
             if let Some(tile) = self.get_tile(tile_pos) {
                 allowed_tiles.contains(tile)
             } else {
@@ -550,11 +1516,73 @@ impl<'a> PlayerWorld<'a> {
                     || (shift.y() != 0 && !is_allowed(prev + shift.with_y(0))) {
                     return false;
                 }
+                if !self.is_valid_height_step(prev, tile_pos, is_shortcut) {
+                    return false;
+                }
             }
             prev_tile_pos = Some(tile_pos);
             true
         })
     }
+
+    // Single steps follow the path search limit, longer shortcuts must not cross height deltas the search would
+    // penalize, otherwise smoothing would cut through slopes the search went around
+    fn is_valid_height_step(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i, is_shortcut: bool) -> bool {
+        match self.get_height_delta_weight(src_tile_pos, dst_tile_pos) {
+            Some(weight) => !is_shortcut || weight == 0.0,
+            None => false,
+        }
+    }
+
+    fn is_avoided_segment(&self, src_rel_tile_pos: Vec2f, dst_rel_tile_pos: Vec2f) -> bool {
+        let src_tile_pos = Vec2i::from(src_rel_tile_pos.floor());
+        let dst_tile_pos = Vec2i::from(dst_rel_tile_pos.floor());
+        !walk_grid(src_rel_tile_pos, dst_rel_tile_pos, |position| {
+            let tile_pos = Vec2i::from(position.floor());
+            tile_pos == src_tile_pos || tile_pos == dst_tile_pos || self.avoidance.get_weight(tile_pos) == 0.0
+        })
+    }
+}
+
+#[cfg(feature = "bench")]
+impl<'a> PlayerWorld<'a> {
+    pub fn bench_find_reversed_tiles_path(&self, src_tile_pos: Vec2i, dst_tile_pos: Vec2i,
+                                          weights: &impl TileWeights, max_iterations: usize) -> Vec<Vec2i> {
+        let node = make_find_path_node();
+        let mut transitions = Transitions::new(
+            &node,
+            &self.config.direct_path_transition_color,
+            &self.config.found_transition_color,
+            &self.config.shorten_path_transition_color,
+        );
+        let tiles_snapshot = TilesSnapshot::new();
+        let world = PlayerWorld { tiles_snapshot: Some(&tiles_snapshot), ..self.clone() };
+        world.find_reversed_tiles_path(src_tile_pos, dst_tile_pos, weights, max_iterations, None, &mut transitions,
+                                       &Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn bench_shorten_reversed_tiles_path(&self, reversed_tiles_path: Vec<Vec2i>, allowed_tiles: &impl TileSet,
+                                             max_shortcut_length: f64) -> Vec<Vec2i> {
+        let tiles_snapshot = TilesSnapshot::new();
+        let world = PlayerWorld { tiles_snapshot: Some(&tiles_snapshot), ..self.clone() };
+        world.shorten_reversed_tiles_path(reversed_tiles_path, allowed_tiles, max_shortcut_length)
+    }
+
+    pub fn bench_make_areas(&self, allowed_tiles: &impl TileSet) -> Areas {
+        make_areas(self.map, self.player_segment_id, self.map.get_segment_fingerprint(self.player_segment_id), allowed_tiles,
+                   &AreaCache::new())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WorldSnapshot {
+    pub segment_id: i64,
+    pub center: Vec2i,
+    pub min: Vec2i,
+    pub ids: Vec<Vec<Option<i32>>>,
+    pub names: BTreeMap<i32, String>,
+    pub weights: Option<Vec<Vec<Option<f64>>>>,
+    pub heights: Vec<Vec<Option<f32>>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -562,6 +1590,109 @@ pub struct WorldData {
     revision: u64,
     objects: ObjectsData,
     map: MapData,
+    breadcrumbs: BreadcrumbsData,
+    #[serde(default)]
+    anchors: AnchorsData,
+}
+
+impl WorldData {
+    pub fn objects(&self) -> &ObjectsData {
+        &self.objects
+    }
+
+    pub fn map(&self) -> &MapData {
+        &self.map
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathSearchState {
+    Found(Vec<Vec2i>),
+    NotFound,
+    InProgress,
+}
+
+pub struct PathSearch {
+    src_tile_pos: Vec2i,
+    dst_tile_pos: Vec2i,
+    corridor: Option<BTreeSet<Vec2i>>,
+    ordered: BinaryHeap<(i32, Vec2i)>,
+    costs: BTreeMap<Vec2i, f64>,
+    backtrack: BTreeMap<Vec2i, Vec2i>,
+    open_set: BTreeSet<Vec2i>,
+    iterations: usize,
+    max_iterations: usize,
+    push_count: usize,
+    min_distance: f64,
+    transition_id: usize,
+    tiles_snapshot: TilesSnapshot,
+    revisions: PathSearchRevisions,
+    landmarks: Arc<Landmarks>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PathSearchRevisions {
+    map: u64,
+    obstacles: u64,
+    danger_zones: u64,
+    avoidance: u64,
+    stuck_tiles: u64,
+}
+
+impl PathSearch {
+    fn new(src_tile_pos: Vec2i, dst_tile_pos: Vec2i, corridor: Option<BTreeSet<Vec2i>>, max_iterations: usize,
+           revisions: PathSearchRevisions, landmarks: Arc<Landmarks>) -> Self {
+        let initial_distance = src_tile_pos.center().distance(dst_tile_pos.center());
+        let mut ordered = BinaryHeap::new();
+        ordered.push((as_score(initial_distance), src_tile_pos));
+        let mut costs = BTreeMap::new();
+        costs.insert(src_tile_pos, 0.0);
+        Self {
+            src_tile_pos,
+            dst_tile_pos,
+            corridor,
+            ordered,
+            costs,
+            backtrack: BTreeMap::new(),
+            open_set: BTreeSet::new(),
+            iterations: 0,
+            max_iterations,
+            push_count: 0,
+            min_distance: initial_distance,
+            transition_id: 0,
+            tiles_snapshot: TilesSnapshot::new(),
+            revisions,
+            landmarks,
+        }
+    }
+
+    pub fn src_tile_pos(&self) -> Vec2i {
+        self.src_tile_pos
+    }
+
+    pub fn dst_tile_pos(&self) -> Vec2i {
+        self.dst_tile_pos
+    }
+
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    fn reset_without_corridor(&mut self) {
+        let transition_id = self.transition_id;
+        *self = PathSearch::new(self.src_tile_pos, self.dst_tile_pos, None, self.max_iterations, self.revisions,
+                                self.landmarks.clone());
+        self.transition_id = transition_id;
+    }
+
+    // Landmarks use map tile positions while the search uses positions relative to the player grid
+    fn get_heuristic(&self, tile_pos: Vec2i, tile_pos_offset: Vec2i) -> f64 {
+        let distance = tile_pos.center().distance(self.dst_tile_pos.center());
+        match self.landmarks.estimate(tile_pos + tile_pos_offset, self.dst_tile_pos + tile_pos_offset) {
+            Some(estimate) => distance.max(estimate),
+            None => distance,
+        }
+    }
 }
 
 fn reconstruct_path(src_tile_pos: Vec2i, dst_tile_pos: Vec2i,
@@ -579,6 +1710,23 @@ fn reconstruct_path(src_tile_pos: Vec2i, dst_tile_pos: Vec2i,
     result
 }
 
+fn is_shortcut(src_tile_pos: Vec2i, dst_tile_pos: Vec2i) -> bool {
+    let shift = dst_tile_pos - src_tile_pos;
+    shift.x().abs() > 1 || shift.y().abs() > 1
+}
+
+fn get_height_delta_weight(src_height: Option<f32>, dst_height: Option<f32>, max_height_delta: f64,
+                           height_delta_weight: Option<f64>) -> Option<f64> {
+    let delta = match (src_height, dst_height) {
+        (Some(src), Some(dst)) => (dst - src).abs() as f64,
+        _ => return Some(0.0),
+    };
+    if delta <= max_height_delta {
+        return Some(0.0);
+    }
+    height_delta_weight.map(|v| v * (delta - max_height_delta))
+}
+
 pub trait TileWeights: TileSet {
     fn get(&self, tile: i32) -> Option<f64>;
 }
@@ -710,3 +1858,246 @@ impl<'a> Transitions<'a> {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+    use std::iter::repeat;
+
+    use rusqlite::Connection;
+
+    use crate::bot::map::{GRID_SIZE, tile_index_to_tile_pos};
+    use crate::bot::player::PlayerConfig;
+    use crate::bot::sqlite_map_db::SqliteMapDb;
+
+    use super::*;
+
+    struct AnyTile;
+
+    impl TileSet for AnyTile {
+        fn contains(&self, _: i32) -> bool {
+            true
+        }
+    }
+
+    fn make_world(updates_path: &str) -> (World, Player) {
+        let map_db = Arc::new(Mutex::new(SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default())));
+        make_world_with_map_db(updates_path, map_db)
+    }
+
+    fn make_world_with_map_db(updates_path: &str, map_db: Arc<Mutex<dyn MapDb + Send>>) -> (World, Player) {
+        let config: serde_yaml::Value = serde_yaml::from_reader(File::open("etc/config.yaml").unwrap()).unwrap();
+        let world_config: WorldConfig = serde_yaml::from_value(config["session"]["world"].clone()).unwrap();
+        let player_config: PlayerConfig = serde_yaml::from_value(config["session"]["player"].clone()).unwrap();
+        let tile_profiles = Arc::new(TileProfiles::new(&world_config));
+        let mut world = World::new(world_config, map_db, Arc::new(Metrics::new()), tile_profiles);
+        let mut player = Player::new(player_config);
+        for line in BufReader::new(File::open(updates_path).unwrap()).lines() {
+            let update = serde_json::from_str::<Update>(line.unwrap().as_str()).unwrap();
+            player.update(&world, &update);
+            world.update(update);
+        }
+        (world, player)
+    }
+
+    fn set_player_grid_heights(world: &mut World, player: &Player, get_height: impl Fn(Vec2i) -> f32) -> Vec2i {
+        let (grid, origin) = {
+            let player_world = world.for_player(player).unwrap();
+            let grid = player_world.map.get_grid_by_id(player_world.player_grid_id).unwrap().clone();
+            let origin = grid_pos_to_tile_pos(grid.position - player_world.player_grid_offset);
+            (grid, origin)
+        };
+        let heights = (0..grid.tiles.len()).map(|i| get_height(tile_index_to_tile_pos(i))).collect();
+        world.map.update_grid(Grid { heights, ..grid });
+        origin
+    }
+
+    fn make_grid(id: i64, height: f32) -> Grid {
+        Grid {
+            id,
+            revision: 1,
+            segment_id: id,
+            position: Vec2i::zero(),
+            heights: repeat(height).take((GRID_SIZE * GRID_SIZE) as usize).collect(),
+            tiles: repeat(1).take((GRID_SIZE * GRID_SIZE) as usize).collect(),
+        }
+    }
+
+    fn get_seam_path_costs(map: &Map, max_height_delta: f64, height_delta_weight: Option<f64>) -> Vec<Option<f64>> {
+        (GRID_SIZE - 3..GRID_SIZE + 2)
+            .map(|x| get_height_delta_weight(
+                map.get_height(1, Vec2i::new(x, 42)),
+                map.get_height(1, Vec2i::new(x + 1, 42)),
+                max_height_delta,
+                height_delta_weight,
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn height_path_cost_should_be_continuous_across_mismatched_seam() {
+        let mut map = Map::new(Arc::new(Mutex::new(SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default()))), 20.0);
+        map.add_grid(make_grid(1, 1.0), Vec::new());
+        map.add_grid(make_grid(2, 9.0), vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        assert_eq!(get_seam_path_costs(&map, 5.0, Some(1.0)), vec![Some(0.0); 5]);
+        assert_eq!(get_seam_path_costs(&map, 5.0, None), vec![Some(0.0); 5]);
+    }
+
+    #[test]
+    fn height_path_cost_should_reject_cliff_at_seam() {
+        let mut map = Map::new(Arc::new(Mutex::new(SqliteMapDb::new(Connection::open_in_memory().unwrap(), Default::default()))), 20.0);
+        map.add_grid(make_grid(1, 1.0), Vec::new());
+        map.add_grid(make_grid(2, 41.0), vec![GridNeighbour { id: 1, offset: Vec2i::new(-1, 0) }]);
+        assert_eq!(get_seam_path_costs(&map, 20.0, Some(1.0)), vec![Some(0.0), Some(0.0), Some(20.0), Some(0.0), Some(0.0)]);
+        assert_eq!(get_seam_path_costs(&map, 20.0, None), vec![Some(0.0), Some(0.0), None, Some(0.0), Some(0.0)]);
+    }
+
+    #[test]
+    fn shortcut_and_path_should_not_cross_cliff() {
+        let (mut world, player) = make_world("tests/input/init_session_lake.json");
+        let origin = set_player_grid_heights(&mut world, &player, |_| 0.0);
+        let (src, dst) = (origin + Vec2i::new(40, 50), origin + Vec2i::new(60, 50));
+        let (before, after) = (origin + Vec2i::new(49, 50), origin + Vec2i::new(50, 50));
+        {
+            let player_world = world.for_player(&player).unwrap();
+            assert!(player_world.is_valid_shortcut(src, dst, &AnyTile, std::f64::MAX));
+            assert!(player_world.is_valid_shortcut_by_rel_pos(src.center(), (dst + Vec2i::only_y(3)).center(), &AnyTile, std::f64::MAX));
+            assert!(player_world.is_valid_path([before, after].iter(), &AnyTile));
+        }
+        set_player_grid_heights(&mut world, &player, |v| if v.x() >= 50 { 100.0 } else { 0.0 });
+        let player_world = world.for_player(&player).unwrap();
+        assert!(!player_world.is_valid_shortcut(src, dst, &AnyTile, std::f64::MAX));
+        assert!(!player_world.is_valid_shortcut_by_rel_pos(src.center(), (dst + Vec2i::only_y(3)).center(), &AnyTile, std::f64::MAX));
+        assert!(!player_world.is_valid_path([before, after].iter(), &AnyTile));
+        assert!(player_world.is_valid_shortcut(src, before, &AnyTile, std::f64::MAX));
+    }
+
+    #[test]
+    fn snapshot_should_find_same_path_as_world() {
+        let (mut world, player) = make_world("tests/input/init_session_lake.json");
+        let origin = set_player_grid_heights(&mut world, &player, |v| if v.x() == 50 && v.y() != 10 { 100.0 } else { 0.0 });
+        let (src, dst) = (origin + Vec2i::new(40, 50), origin + Vec2i::new(60, 50));
+        let tile_weights: BTreeMap<i32, f64> = world.map.iter_tiles().map(|v| (v.id, 1.0)).collect();
+        let find_path = |world: &World| world.for_player(&player).unwrap().find_path(
+            src, dst, &BTreeMapTileWeights(&tile_weights), 10.0, 100000, &make_find_path_node(),
+            &Arc::new(AtomicBool::new(false)),
+        );
+        let snapshot = world.snapshot();
+        let path = find_path(&world);
+        assert!(path.len() > 2);
+        assert_eq!(find_path(&snapshot), path);
+    }
+
+    #[test]
+    fn interrupted_path_search_should_find_same_path_as_uninterrupted() {
+        let (mut world, player) = make_world("tests/input/init_session_lake.json");
+        let origin = set_player_grid_heights(&mut world, &player, |v| if v.x() == 50 && v.y() != 10 { 100.0 } else { 0.0 });
+        let (src, dst) = (origin + Vec2i::new(40, 50), origin + Vec2i::new(60, 50));
+        let tile_weights: BTreeMap<i32, f64> = world.map.iter_tiles().map(|v| (v.id, 1.0)).collect();
+        let weights = BTreeMapTileWeights(&tile_weights);
+        let node = make_find_path_node();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let player_world = world.for_player(&player).unwrap();
+        let path = player_world.find_path(src, dst, &weights, 10.0, 100000, &node, &cancel);
+        assert!(path.len() > 2);
+        let mut search = player_world.start_path_search(src, dst, &weights, 100000).unwrap();
+        let mut interruptions = 0;
+        let state = loop {
+            match player_world.continue_path_search(&mut search, &weights, 10.0, 10, &node, &cancel) {
+                PathSearchState::InProgress => interruptions += 1,
+                v => break v,
+            }
+        };
+        assert!(interruptions > 1);
+        assert_eq!(state, PathSearchState::Found(path));
+    }
+
+    #[test]
+    fn path_search_should_be_stale_after_world_change() {
+        let (mut world, player) = make_world("tests/input/init_session_lake.json");
+        let origin = set_player_grid_heights(&mut world, &player, |_| 0.0);
+        let (src, dst) = (origin + Vec2i::new(40, 50), origin + Vec2i::new(60, 50));
+        let tile_weights: BTreeMap<i32, f64> = world.map.iter_tiles().map(|v| (v.id, 1.0)).collect();
+        let start = |world: &World| world.for_player(&player).unwrap()
+            .start_path_search(src, dst, &BTreeMapTileWeights(&tile_weights), 100000).unwrap();
+        let is_stale = |world: &World, search: &PathSearch| world.for_player(&player).unwrap().is_stale_path_search(search);
+        let search = start(&world);
+        assert!(!is_stale(&world, &search));
+        world.avoidance.add(&Object {
+            id: i64::MAX,
+            position: tile_pos_to_pos(origin + Vec2i::new(50, 50)),
+            angle: 0.0,
+            name: Some(String::from("gfx/kritter/bear")),
+        });
+        assert!(is_stale(&world, &search));
+        let search = start(&world);
+        world.stuck_tiles.add(world.for_player(&player).unwrap().player_segment_id, src, Instant::now());
+        assert!(is_stale(&world, &search));
+        let search = start(&world);
+        world.update(Update {
+            session: 0,
+            number: 0,
+            event: Event::GobAdd {
+                id: i64::MAX - 1,
+                position: tile_pos_to_pos(origin + Vec2i::new(50, 50)),
+                angle: 0.0,
+                name: Some(String::from("gfx/terobjs/trees/oak")),
+            },
+        });
+        assert!(is_stale(&world, &search));
+        let search = start(&world);
+        set_player_grid_heights(&mut world, &player, |_| 1.0);
+        assert!(is_stale(&world, &search));
+    }
+
+    #[test]
+    fn path_search_should_not_be_stale_after_unrelated_object_move() {
+        let (mut world, player) = make_world("tests/input/init_session_lake.json");
+        let origin = set_player_grid_heights(&mut world, &player, |_| 0.0);
+        let (src, dst) = (origin + Vec2i::new(40, 50), origin + Vec2i::new(60, 50));
+        let tile_weights: BTreeMap<i32, f64> = world.map.iter_tiles().map(|v| (v.id, 1.0)).collect();
+        let search = world.for_player(&player).unwrap()
+            .start_path_search(src, dst, &BTreeMapTileWeights(&tile_weights), 100000).unwrap();
+        let revision = world.revision;
+        for event in vec![
+            Event::GobAdd {
+                id: i64::MAX,
+                position: tile_pos_to_pos(origin + Vec2i::new(50, 50)),
+                angle: 0.0,
+                name: Some(String::from("gfx/borka/body")),
+            },
+            Event::GobMove {
+                id: i64::MAX,
+                position: tile_pos_to_pos(origin + Vec2i::new(51, 50)),
+                angle: 0.0,
+            },
+        ] {
+            world.update(Update { session: 0, number: 0, event });
+        }
+        assert!(world.revision > revision);
+        assert!(!world.for_player(&player).unwrap().is_stale_path_search(&search));
+    }
+
+    #[test]
+    fn grids_of_interest_should_be_kept_in_map_db_cache_under_capacity_pressure() {
+        let map_db = Arc::new(Mutex::new(
+            SqliteMapDb::new(Connection::open_in_memory().unwrap(), Duration::from_secs(3600)).with_cache_capacity(1)
+        ));
+        let (mut world, player) = make_world_with_map_db("tests/input/init_session_start.json", map_db.clone());
+        world.set_grids_of_interest(&player, vec![Vec2i::new(-10, -10)]);
+        let grids = world.map.iter_grids().map(|v| v.id).collect::<Vec<_>>();
+        assert!(grids.len() > 2);
+        let misses = map_db.lock().unwrap().get_cache_stats().misses;
+        for grid_id in grids.iter() {
+            map_db.lock().unwrap().get_grid_by_id(*grid_id);
+        }
+        let stats = map_db.lock().unwrap().get_cache_stats();
+        assert_eq!(stats.misses, misses + grids.len() as u64);
+        assert_eq!(stats.grids, 2);
+        map_db.lock().unwrap().get_grid_by_id(5250373069530682842);
+        assert_eq!(map_db.lock().unwrap().get_cache_stats().misses, stats.misses);
+        world.set_grids_of_interest(&player, Vec::new());
+        assert_eq!(map_db.lock().unwrap().get_cache_stats().grids, 1);
+    }
+}
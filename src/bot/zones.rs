@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bot::geometry::{get_polygon_area, is_inside_polygon, Rect};
+use crate::bot::vec2::{Vec2f, Vec2i};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub segment_id: i64,
+    pub name: String,
+    pub points: Vec<Vec2i>,
+}
+
+impl Zone {
+    pub fn contains(&self, tile_pos: Vec2i) -> bool {
+        is_inside_polygon(&self.float_points(), tile_pos.center())
+    }
+
+    pub fn area(&self) -> f64 {
+        get_polygon_area(&self.float_points())
+    }
+
+    pub fn shifted(&self, segment_id: i64, shift: Vec2i) -> Self {
+        Self {
+            segment_id,
+            name: self.name.clone(),
+            points: self.points.iter().map(|v| *v + shift).collect(),
+        }
+    }
+
+    pub fn iter_tiles(&self) -> impl Iterator<Item=Vec2i> + '_ {
+        let points = self.float_points();
+        let (min, max) = Rect::from_points(points.iter())
+            .map(|v| (Vec2i::from(v.min), Vec2i::from(v.max)))
+            .unwrap_or_default();
+        (min.y()..max.y())
+            .flat_map(move |y| (min.x()..max.x()).map(move |x| Vec2i::new(x, y)))
+            .filter(move |v| is_inside_polygon(&points, v.center()))
+    }
+
+    fn float_points(&self) -> Vec<Vec2f> {
+        self.points.iter().map(|v| Vec2f::from(*v)).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ZoneInfo {
+    #[serde(flatten)]
+    pub zone: Zone,
+    pub area: f64,
+    pub tiles: usize,
+}
+
+impl ZoneInfo {
+    pub fn new(zone: Zone) -> Self {
+        Self {
+            area: zone.area(),
+            tiles: zone.iter_tiles().count(),
+            zone,
+        }
+    }
+}
+
+pub fn validate_zone(zone: &Zone) -> Result<(), String> {
+    if zone.name.is_empty() {
+        return Err(String::from("Zone name is empty"));
+    }
+    if zone.points.len() < 3 {
+        return Err(format!("Zone {} has less than 3 points", zone.name));
+    }
+    if zone.area() == 0.0 {
+        return Err(format!("Zone {} has zero area", zone.name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_zone(points: Vec<Vec2i>) -> Zone {
+        Zone { segment_id: 1, name: String::from("field"), points }
+    }
+
+    #[test]
+    fn zone_should_contain_tiles_with_center_inside_polygon() {
+        let zone = make_zone(vec![Vec2i::new(0, 0), Vec2i::new(4, 0), Vec2i::new(0, 4)]);
+        assert!(zone.contains(Vec2i::new(0, 0)));
+        assert!(zone.contains(Vec2i::new(1, 1)));
+        assert!(!zone.contains(Vec2i::new(3, 3)));
+        assert!(!zone.contains(Vec2i::new(-1, 0)));
+        assert_eq!(zone.area(), 8.0);
+        assert_eq!(zone.iter_tiles().count(), 6);
+        assert!(zone.iter_tiles().all(|v| zone.contains(v)));
+    }
+
+    #[test]
+    fn validate_zone_should_reject_degenerate_polygons() {
+        assert_eq!(validate_zone(&make_zone(vec![Vec2i::new(0, 0), Vec2i::new(2, 0), Vec2i::new(0, 2)])), Ok(()));
+        assert!(validate_zone(&make_zone(vec![Vec2i::new(0, 0), Vec2i::new(2, 0)])).is_err());
+        assert!(validate_zone(&make_zone(vec![Vec2i::new(0, 0), Vec2i::new(1, 0), Vec2i::new(2, 0)])).is_err());
+    }
+}
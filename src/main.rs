@@ -9,5 +9,8 @@ async fn main() -> std::io::Result<()> {
     env_logger::init();
     let path = args.get(1).map(|v| v.as_str()).unwrap_or("etc/config.yaml");
     info!("Read config from: {}", path);
-    run_server(read_config(path)?)?.await
+    let (server, shutdown) = run_server(read_config(path)?)?;
+    server.await?;
+    shutdown.run();
+    Ok(())
 }
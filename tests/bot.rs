@@ -1,10 +1,9 @@
-#[macro_use]
-extern crate hexf;
 extern crate portpicker;
 extern crate reqwest;
 
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
 use std::path::Path;
 use std::thread::sleep;
 use std::time::Duration;
@@ -12,10 +11,13 @@ use std::time::Duration;
 use futures::Future;
 use portpicker::{pick_unused_port, Port};
 use reqwest::Client;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderValue};
 use serde::Deserialize;
 use serde_json::{json, Value};
 
 use hafen_bot::bot::{run_server, ServerConfig};
+use hafen_bot::bot::coords::{RESOLUTION, TILE_SIZE, Vec2f, Vec2i};
+use hafen_bot::bot::sim::Simulator;
 
 #[actix_rt::test]
 async fn ping() {
@@ -27,6 +29,24 @@ async fn ping() {
     }).await;
 }
 
+#[actix_rt::test]
+async fn hello_should_negotiate_protocol_version_and_capabilities() {
+    with_bot_service(|bot_service| async move {
+        let hello = |version| json!({
+            "session": 1,
+            "number": 0,
+            "event": {"type": "Hello", "version": version, "capabilities": ["Alerts", "Teleport"]},
+        });
+        assert_eq!(
+            bot_service.push(&hello(1)).await,
+            r#"{"type":"Hello","version":1,"versions":[1],"capabilities":["Alerts"]}"#,
+            "BotService port={}", bot_service.port
+        );
+        let unsupported = parse_json(&bot_service.push(&hello(100)).await);
+        assert_eq!(unsupported["type"].as_str(), Some("Error"), "BotService port={}", bot_service.port);
+    }).await;
+}
+
 #[actix_rt::test]
 async fn no_sessions_by_default() {
     with_bot_service(|bot_service| async move {
@@ -227,7 +247,228 @@ async fn path_finder() {
 }
 
 #[actix_rt::test]
-async fn drinker() {
+async fn find_path_should_return_path_without_moving_player() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let position = parse_json(&bot_service.player_position(session_id).await);
+        let src = json!({
+            "x": (position["position"]["x"].as_f64().unwrap() / TILE_SIZE).floor() as i64,
+            "y": (position["position"]["y"].as_f64().unwrap() / TILE_SIZE).floor() as i64,
+        });
+        let dst = json!({"x": -890, "y": -977});
+        let result = parse_json(&bot_service.find_path(session_id, &json!({"src": src, "dst": dst, "tiles": "water"})).await);
+        assert_eq!(result["type"].as_str(), Some("FoundPath"), "BotService port={}", bot_service.port);
+        let path = result["value"].as_array().unwrap();
+        assert!(!path.is_empty(), "BotService port={}", bot_service.port);
+        assert_eq!(path.last(), Some(&dst), "BotService port={}", bot_service.port);
+        let result = parse_json(&bot_service.find_path(session_id, &json!({"src": src, "dst": dst, "tiles": "boat"})).await);
+        assert_eq!(result["type"].as_str(), Some("FoundPath"), "BotService port={}", bot_service.port);
+        assert_eq!(result["value"].as_array().unwrap().last(), Some(&dst), "BotService port={}", bot_service.port);
+        assert_eq!(
+            bot_service.find_path(session_id, &json!({"src": src, "dst": dst, "tiles": "lava"})).await,
+            r#"{"type":"Error","message":"Tile weights profile \"lava\" is not found"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn line_of_sight_should_be_blocked_by_unknown_tiles() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let position = parse_json(&bot_service.player_position(session_id).await);
+        let src = json!({
+            "x": (position["position"]["x"].as_f64().unwrap() / TILE_SIZE).floor() as i64,
+            "y": (position["position"]["y"].as_f64().unwrap() / TILE_SIZE).floor() as i64,
+        });
+        assert_eq!(
+            bot_service.line_of_sight(session_id, &json!({"src": src, "dst": src})).await,
+            r#"{"type":"LineOfSight","value":true,"distance":0.0}"#,
+            "BotService port={}", bot_service.port
+        );
+        let far = json!({"x": src["x"].as_i64().unwrap() + 1000, "y": src["y"]});
+        assert_eq!(
+            bot_service.line_of_sight(session_id, &json!({"src": src, "dst": far})).await,
+            r#"{"type":"LineOfSight","value":false,"distance":1000.0}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn fields_should_be_found_for_configured_session() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let fields = parse_json(&bot_service.fields(session_id).await);
+        assert_eq!(fields["type"].as_str(), Some("Fields"), "BotService port={}", bot_service.port);
+        assert!(fields["value"].is_array(), "BotService port={}", bot_service.port);
+        assert_eq!(
+            bot_service.fields(session_id + 1).await,
+            r#"{"type":"Error","message":"Session is not found"}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn containers_should_report_opened_containers_and_nearest_non_full() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        let mut events = vec![
+            json!({"type": "ResourceAdd", "id": 100000, "version": 1, "name": "gfx/invobjs/bucket"}),
+            json!({
+                "type": "GobAdd",
+                "id": 1,
+                "position": {"x": -9790.0, "y": -10747.0},
+                "angle": 0.0,
+                "name": "gfx/terobjs/cupboard",
+            }),
+            json!({
+                "type": "GobAdd",
+                "id": 2,
+                "position": {"x": -9800.0, "y": -10750.0},
+                "angle": 0.0,
+                "name": "gfx/terobjs/chest",
+            }),
+        ];
+        for (object_id, window_id, width) in [(1, 100010, 2), (2, 100020, 1)].iter() {
+            events.push(json!({
+                "type": "WidgetMessage",
+                "id": 7,
+                "msg": "click",
+                "args": [
+                    {"type": "Coord", "value": {"x": 0, "y": 0}},
+                    {"type": "Coord", "value": {"x": 0, "y": 0}},
+                    {"type": "Int", "value": 3},
+                    {"type": "Int", "value": 0},
+                    {"type": "Int", "value": 0},
+                    {"type": "Int", "value": object_id},
+                ],
+            }));
+            events.push(json!({
+                "type": "NewWidget",
+                "id": window_id,
+                "kind": "wnd",
+                "parent": 6,
+                "pargs": [],
+                "cargs": [{"type": "Coord", "value": {"x": 0, "y": 0}}, {"type": "Str", "value": "Container"}],
+            }));
+            events.push(json!({
+                "type": "NewWidget",
+                "id": window_id + 1,
+                "kind": "inv",
+                "parent": window_id,
+                "pargs": [],
+                "cargs": [{"type": "Coord", "value": {"x": width, "y": 1}}],
+            }));
+            events.push(json!({
+                "type": "NewWidget",
+                "id": window_id + 2,
+                "kind": "item",
+                "parent": window_id + 1,
+                "pargs": [{"type": "Coord", "value": {"x": 0, "y": 0}}],
+                "cargs": [{"type": "Int", "value": 100000}],
+            }));
+        }
+        for event in events.into_iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({"session": session_id, "number": number, "event": event})).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        wait_updates(&bot_service, session_id).await;
+        let containers = parse_json(&bot_service.containers(session_id).await);
+        assert_eq!(containers["type"].as_str(), Some("Containers"), "BotService port={}", bot_service.port);
+        assert_eq!(
+            containers["value"].as_array().unwrap().iter()
+                .map(|v| (v["object_id"].as_i64().unwrap(), v["free_slots"].as_i64()))
+                .collect::<Vec<_>>(),
+            vec![(1, Some(1)), (2, Some(0))],
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(containers["nearest_non_full"].as_i64(), Some(1), "BotService port={}", bot_service.port);
+        assert_eq!(
+            bot_service.containers(session_id + 1).await,
+            r#"{"type":"Error","message":"Session is not found"}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn world_snapshot_should_contain_tiles_around_player() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let snapshot = parse_json(&bot_service.world_snapshot(session_id, 2, "walk").await);
+        assert_eq!(snapshot["type"].as_str(), Some("WorldSnapshot"), "BotService port={}", bot_service.port);
+        let value = &snapshot["value"];
+        assert_eq!(value["ids"].as_array().map(|v| v.len()), Some(5), "BotService port={}", bot_service.port);
+        assert_eq!(value["weights"].as_array().map(|v| v.len()), Some(5), "BotService port={}", bot_service.port);
+        assert_eq!(value["heights"][2].as_array().map(|v| v.len()), Some(5), "BotService port={}", bot_service.port);
+        let center_id = value["ids"][2][2].as_i64().unwrap();
+        assert!(value["names"][center_id.to_string()].is_string(), "BotService port={}", bot_service.port);
+        assert_eq!(
+            value["min"]["x"].as_i64().map(|v| v + 2),
+            value["center"]["x"].as_i64(),
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            parse_json(&bot_service.world_snapshot(session_id, 1, "fly").await)["type"].as_str(),
+            Some("Error"),
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn macro_player_should_replay_recorded_widget_messages() {
     with_bot_service(|bot_service| async move {
         let mut session_id = 0;
         let mut number = 0;
@@ -239,20 +480,58 @@ async fn drinker() {
             session_id = update["session"].as_i64().unwrap();
             number = update["number"].as_i64().unwrap();
         }
+        wait_updates(&bot_service, session_id).await;
         assert_eq!(
             bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
             "BotService port={}", bot_service.port
         );
         assert_eq!(
-            bot_service.push(&make_set_meter(session_id, number + 1, 33, 80)).await,
-            r#"{"type":"Ok"}"#,
+            bot_service.start_recording(session_id, "drop_item").await, r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.start_recording(session_id, "drop_item").await,
+            r#"{"type":"Error","message":"Macro \"drop_item\" is already recording"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let click_args = json!([
+            {"type": "Coord", "value": {"x": 0, "y": 0}},
+            {"type": "Coord", "value": {"x": -10000, "y": -10500}},
+            {"type": "Int", "value": 1},
+            {"type": "Int", "value": 0},
+        ]);
+        let drop_args = json!([{"type": "Coord", "value": {"x": 1, "y": 0}}]);
+        for (id, msg, args) in [(7, "click", &click_args), (8, "drop", &drop_args)].iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({
+                    "session": session_id,
+                    "number": number,
+                    "event": {"type": "WidgetMessage", "id": id, "msg": msg, "args": args},
+                })).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        wait_updates(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.stop_recording(session_id).await, r#"{"type":"Ok"}"#,
             "BotService port={}", bot_service.port
         );
+        assert_eq!(
+            bot_service.stop_recording(session_id).await, r#"{"type":"Error","message":"Macro is not recording"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert!(Path::new(&format!("tests/var/{}/macros/drop_item.macro.json", bot_service.port)).exists());
         assert_eq!(
             bot_service.push(&json!({
                 "session": session_id,
-                "number": number + 2,
-                "event": {"type": "TaskAdd", "name": "Drinker", "params": []},
+                "number": number + 1,
+                "event": {
+                    "type": "TaskAdd",
+                    "name": "MacroPlayer",
+                    "params": serde_json::to_vec(&json!({"name": "drop_item"})).unwrap(),
+                },
             })).await,
             r#"{"type":"Ok"}"#,
             "BotService port={}", bot_service.port
@@ -260,81 +539,1887 @@ async fn drinker() {
         wait_updates(&bot_service, session_id).await;
         wait_for_message(&bot_service, session_id).await;
         assert_eq!(
-            bot_service.poll(session_id).await,
-            r#"{"type":"UIMessage","id":6,"kind":"add-task","arguments":[{"type":"Long","value":1},{"type":"Str","value":"Drinker"},{"type":"Bytes","value":[]}]}"#,
+            parse_json(&bot_service.poll(session_id).await)["kind"].as_str(), Some("add-task"),
             "BotService port={}", bot_service.port
         );
+        for (id, msg, args) in [(7, "click", &click_args), (8, "drop", &drop_args)].iter() {
+            wait_for_message(&bot_service, session_id).await;
+            assert_eq!(
+                parse_json(&bot_service.poll(session_id).await),
+                json!({"type": "WidgetMessage", "sender": id, "kind": msg, "arguments": args}),
+                "BotService port={}", bot_service.port
+            );
+        }
         wait_for_message(&bot_service, session_id).await;
         assert_eq!(
-            bot_service.poll(session_id).await,
-            r#"{"type":"LockWidget","value":"sm"}"#,
+            bot_service.poll(session_id).await, r#"{"type":"Done","task":"MacroPlayer"}"#,
             "BotService port={}", bot_service.port
         );
-        wait_for_message(&bot_service, session_id).await;
+    }).await;
+}
+
+#[actix_rt::test]
+async fn wasm_task_should_emit_messages_from_module() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
         assert_eq!(
-            bot_service.poll(session_id).await,
-            r#"{"type":"WidgetMessage","sender":13,"kind":"iact","arguments":[{"type":"Coord","value":{"x":0,"y":0}},{"type":"Int","value":0}]}"#,
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
             "BotService port={}", bot_service.port
         );
+        let modules_path = format!("tests/var/{}/wasm", bot_service.port);
+        std::fs::create_dir_all(&modules_path).unwrap();
+        std::fs::write(format!("{}/Stepper.wasm", modules_path), wat::parse_str(r#"
+            (module
+                (import "hafen_bot" "player_x" (func $player_x (result f64)))
+                (import "hafen_bot" "player_y" (func $player_y (result f64)))
+                (import "hafen_bot" "click" (func $click (param f64 f64)))
+                (import "hafen_bot" "done" (func $done))
+                (memory (export "memory") 1)
+                (global $steps (mut i32) (i32.const 0))
+                (func (export "next")
+                    (if (i32.ge_s (global.get $steps) (i32.const 1))
+                        (then (call $done) (return)))
+                    (global.set $steps (i32.add (global.get $steps) (i32.const 1)))
+                    (call $click (f64.add (call $player_x) (f64.const 1100)) (call $player_y))))
+        "#).unwrap()).unwrap();
+        number += 1;
         assert_eq!(
             bot_service.push(&json!({
                 "session": session_id,
-                "number": number + 3,
+                "number": number,
                 "event": {
-                    "type": "NewWidget",
-                    "id": 38,
-                    "kind": "sm",
-                    "parent": 65536,
-                    "pargs": [],
-                    "cargs": [
-                        {"type": "Str", "value": "Drink"},
-                        {"type": "Str", "value": "Sip"},
-                        {"type": "Str", "value": "Empty"},
-                    ],
+                    "type": "TaskAdd",
+                    "name": "Stepper",
+                    "params": [],
                 },
             })).await,
             r#"{"type":"Ok"}"#,
             "BotService port={}", bot_service.port
         );
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        assert_eq!(add_task["arguments"][1]["value"].as_str(), Some("Stepper"), "BotService port={}", bot_service.port);
+        wait_for_message(&bot_service, session_id).await;
+        let click = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(click["kind"].as_str(), Some("click"), "BotService port={}", bot_service.port);
+        assert_eq!(click["sender"].as_i64(), Some(7), "BotService port={}", bot_service.port);
+        let coord = get_map_click_coord(&click);
+        number += 1;
         assert_eq!(
-            bot_service.push(&json!({
-                "session": session_id,
-                "number": number + 4,
-                "event": {"type": "AddWidget", "id": 38, "parent": 0, "pargs": []},
-            })).await,
+            bot_service.push(&make_gob_move(session_id, number, 187896540, coord.x as f64 * RESOLUTION, coord.y as f64 * RESOLUTION)).await,
             r#"{"type":"Ok"}"#,
             "BotService port={}", bot_service.port
         );
+        wait_updates(&bot_service, session_id).await;
         wait_for_message(&bot_service, session_id).await;
         assert_eq!(
-            bot_service.poll(session_id).await,
-            r#"{"type":"WidgetMessage","sender":38,"kind":"cl","arguments":[{"type":"Int","value":0},{"type":"Int","value":0}]}"#,
+            bot_service.poll(session_id).await, r#"{"type":"Done","task":"Stepper"}"#,
             "BotService port={}", bot_service.port
         );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn path_finder_should_visit_waypoints_in_order() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let waypoints = [(-9790.0, -10747.0), (-10729.5, -10286.8)];
         assert_eq!(
             bot_service.push(&json!({
                 "session": session_id,
-                "number": number + 5,
-                "event": {"type": "UIMessage", "id": 38, "msg": "act", "args": []},
+                "number": number + 1,
+                "event": {
+                    "type": "TaskAdd",
+                    "name": "PathFinder",
+                    "params": serde_json::to_vec(&json!({
+                        "waypoints": waypoints.iter().map(|(x, y)| json!({"x": x, "y": y})).collect::<Vec<_>>(),
+                    })).unwrap(),
+                },
             })).await,
             r#"{"type":"Ok"}"#,
             "BotService port={}", bot_service.port
         );
+        number += 1;
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        let first_waypoint_tile = ((waypoints[0].0 / TILE_SIZE).floor(), (waypoints[0].1 / TILE_SIZE).floor());
+        let mut visited_first_waypoint = false;
+        let mut done = false;
+        for _ in 0..200usize {
+            wait_for_message(&bot_service, session_id).await;
+            let message = bot_service.poll(session_id).await;
+            if message == r#"{"type":"Done","task":"PathFinder"}"# {
+                done = true;
+                break;
+            }
+            let parsed = parse_json(&message);
+            let coord = get_map_click_coord(&parsed);
+            let x = coord.x as f64 * RESOLUTION;
+            let y = coord.y as f64 * RESOLUTION;
+            if ((x / TILE_SIZE).floor(), (y / TILE_SIZE).floor()) == first_waypoint_tile {
+                visited_first_waypoint = true;
+            }
+            number += 1;
+            assert_eq!(
+                bot_service.push(&make_gob_move(session_id, number, 187896540, x, y)).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            wait_updates(&bot_service, session_id).await;
+        }
+        assert!(visited_first_waypoint, "BotService port={}", bot_service.port);
+        assert!(done, "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn task_status_should_report_running_tasks() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
         assert_eq!(
-            bot_service.push(&make_set_meter(session_id, number + 6, 33, 100)).await,
-            r#"{"type":"Ok"}"#,
+            bot_service.task_status(session_id + 1).await, r#"{"type":"Error","message":"Session is not found"}"#,
             "BotService port={}", bot_service.port
         );
-        wait_for_message(&bot_service, session_id).await;
         assert_eq!(
-            bot_service.poll(session_id).await,
-            r#"{"type":"Done","task":"Drinker"}"#,
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
             "BotService port={}", bot_service.port
         );
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 1,
+                "event": {
+                    "type": "TaskAdd",
+                    "name": "PathFinder",
+                    "params": serde_json::to_vec(&json!({
+                        "waypoints": [{"x": -9790.0, "y": -10747.0}],
+                    })).unwrap(),
+                },
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        wait_for_message(&bot_service, session_id).await;
+        let task_status = parse_json(&bot_service.task_status(session_id).await);
+        assert_eq!(task_status["type"].as_str(), Some("TaskStatuses"), "BotService port={}", bot_service.port);
+        assert_eq!(task_status["value"][0]["name"].as_str(), Some("PathFinder"), "BotService port={}", bot_service.port);
+        assert_eq!(task_status["value"][0]["status"]["state"].as_str(), Some("Walk"), "BotService port={}", bot_service.port);
+        assert!(
+            task_status["value"][0]["status"]["counters"]["path_tiles"].as_u64().unwrap() > 0,
+            "BotService port={}", bot_service.port
+        );
+        let sessions = parse_json(&bot_service.sessions().await);
+        assert_eq!(
+            sessions["value"][0]["task_statuses"], task_status["value"],
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn task_status_should_report_pipeline_stage() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 1,
+                "event": {
+                    "type": "TaskAdd",
+                    "name": "Pipeline",
+                    "params": serde_json::to_vec(&json!({
+                        "stages": [
+                            {"task": "PathFinder", "params": {"waypoints": [{"x": -9790.0, "y": -10747.0}]}},
+                            {"task": "Logout", "condition": "Always"},
+                        ],
+                    })).unwrap(),
+                },
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        wait_for_message(&bot_service, session_id).await;
+        let task_status = parse_json(&bot_service.task_status(session_id).await);
+        let status = &task_status["value"][0]["status"];
+        assert_eq!(task_status["value"][0]["name"].as_str(), Some("Pipeline"), "BotService port={}", bot_service.port);
+        assert_eq!(status["stage"]["index"].as_u64(), Some(0), "BotService port={}", bot_service.port);
+        assert_eq!(status["stage"]["task"].as_str(), Some("PathFinder"), "BotService port={}", bot_service.port);
+        assert_eq!(status["stage"]["status"]["state"].as_str(), Some("Walk"), "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn stuck_recovery_should_apply_actions_and_report_failure() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        bot_service.hello(session_id, &["StuckRecovery"]).await;
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        number += 1;
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number,
+                "event": {
+                    "type": "TaskAdd",
+                    "name": "PathFinder",
+                    "params": serde_json::to_vec(&json!({
+                        "waypoints": [{"x": -10729.5, "y": -10286.8}],
+                    })).unwrap(),
+                },
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        for _ in 0..2 {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&make_gob_move(session_id, number, 187896540, -9790.0, -10747.0)).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            wait_updates(&bot_service, session_id).await;
+            sleep(Duration::from_millis(1100));
+        }
+        sleep(Duration::from_secs(2));
+        let mut messages = Vec::new();
+        for _ in 0..10 {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&make_gob_move(session_id, number, 1, 0.0, 0.0)).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            wait_updates(&bot_service, session_id).await;
+            loop {
+                let message = parse_json(&bot_service.poll(session_id).await);
+                if message["type"].as_str() == Some("Ok") {
+                    break;
+                }
+                messages.push(message);
+            }
+            if messages.iter().any(|v| v["type"].as_str() == Some("StuckRecovery")) {
+                break;
+            }
+        }
+        assert!(
+            messages.iter().any(|v| v["kind"].as_str() == Some("remove-task")),
+            "BotService port={} messages={:?}", bot_service.port, messages
+        );
+        assert_eq!(
+            messages.last().unwrap(),
+            &json!({"type": "StuckRecovery", "outcome": "Failed", "actions": ["RandomMove", "Replan", "CancelTask"]}),
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn follower_should_approach_target_and_finish_when_it_disappears() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let (target_x, target_y) = (-9790.0, -10747.0);
+        number += 1;
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number,
+                "event": {
+                    "type": "GobAdd",
+                    "id": 1,
+                    "position": {"x": target_x, "y": target_y},
+                    "angle": 0.0,
+                    "name": "gfx/borka/body",
+                },
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        number += 1;
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number,
+                "event": {
+                    "type": "TaskAdd",
+                    "name": "Follower",
+                    "params": serde_json::to_vec(&json!({"object_id": 1})).unwrap(),
+                },
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        let mut reached = false;
+        for _ in 0..200usize {
+            wait_for_message(&bot_service, session_id).await;
+            let parsed = parse_json(&bot_service.poll(session_id).await);
+            let coord = get_map_click_coord(&parsed);
+            let x = coord.x as f64 * RESOLUTION;
+            let y = coord.y as f64 * RESOLUTION;
+            number += 1;
+            assert_eq!(
+                bot_service.push(&make_gob_move(session_id, number, 187896540, x, y)).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            wait_updates(&bot_service, session_id).await;
+            if ((x - target_x).powi(2) + (y - target_y).powi(2)).sqrt() <= 22.0 {
+                reached = true;
+                break;
+            }
+        }
+        assert!(reached, "BotService port={}", bot_service.port);
+        number += 1;
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number,
+                "event": {"type": "GobRemove", "id": 1},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let mut done = false;
+        for _ in 0..10usize {
+            wait_for_message(&bot_service, session_id).await;
+            if bot_service.poll(session_id).await == r#"{"type":"Done","task":"Follower"}"# {
+                done = true;
+                break;
+            }
+        }
+        assert!(done, "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn fleer_should_alert_and_flee_from_hostile_object() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        bot_service.hello(session_id, &["Alerts"]).await;
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        number += 1;
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number,
+                "event": {
+                    "type": "TaskAdd",
+                    "name": "Fleer",
+                    "params": serde_json::to_vec(&json!({"safe_position": {"x": -9790.0, "y": -10747.0}})).unwrap(),
+                },
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        number += 1;
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number,
+                "event": {
+                    "type": "GobAdd",
+                    "id": 1,
+                    "position": {"x": -10729.5009765625, "y": -10186.826171875},
+                    "angle": 0.0,
+                    "name": "gfx/kritter/bear/bear",
+                },
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            parse_json(&bot_service.poll(session_id).await),
+            json!({"type": "Alert", "object_id": 1, "name": "gfx/kritter/bear/bear", "distance": 100.0}),
+            "BotService port={}", bot_service.port
+        );
+        wait_for_message(&bot_service, session_id).await;
+        let click = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(click["kind"].as_str(), Some("click"), "BotService port={} click={:?}", bot_service.port, click);
+        number += 1;
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number,
+                "event": {"type": "GobRemove", "id": 1},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        while bot_service.poll(session_id).await != r#"{"type":"Ok"}"# {}
+        sleep(Duration::from_secs(1));
+        assert_eq!(bot_service.poll(session_id).await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        let task_status = parse_json(&bot_service.task_status(session_id).await);
+        assert_eq!(
+            task_status["value"][0]["status"]["state"].as_str(), Some("Watch"),
+            "BotService port={} task_status={:?}", bot_service.port, task_status
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn drinker() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.push(&make_set_meter(session_id, number + 1, 33, 80)).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 2,
+                "event": {"type": "TaskAdd", "name": "Drinker", "params": []},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"UIMessage","id":6,"kind":"add-task","arguments":[{"type":"Long","value":1},{"type":"Str","value":"Drinker"},{"type":"Bytes","value":[]}]}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"LockWidget","value":"sm"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"WidgetMessage","sender":14,"kind":"iact","arguments":[{"type":"Coord","value":{"x":0,"y":0}},{"type":"Int","value":0}]}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 3,
+                "event": {
+                    "type": "NewWidget",
+                    "id": 38,
+                    "kind": "sm",
+                    "parent": 65536,
+                    "pargs": [],
+                    "cargs": [
+                        {"type": "Str", "value": "Drink"},
+                        {"type": "Str", "value": "Sip"},
+                        {"type": "Str", "value": "Empty"},
+                    ],
+                },
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 4,
+                "event": {"type": "AddWidget", "id": 38, "parent": 0, "pargs": []},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"WidgetMessage","sender":38,"kind":"cl","arguments":[{"type":"Int","value":0},{"type":"Int","value":0}]}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 5,
+                "event": {"type": "UIMessage", "id": 38, "msg": "act", "args": []},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.push(&make_set_meter(session_id, number + 6, 33, 100)).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"Done","task":"Drinker"}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn drinker_should_refill_empty_container_from_water() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let events = vec![
+            json!({"type": "UIMessage", "id": 13, "msg": "tt", "args": []}),
+            json!({"type": "UIMessage", "id": 14, "msg": "tt", "args": []}),
+            json!({
+                "type": "UIMessage",
+                "id": 33,
+                "msg": "set",
+                "args": [
+                    {"type": "Color", "value": {"r": 64, "g": 64, "b": 255, "a": 255}},
+                    {"type": "Int", "value": 80},
+                ],
+            }),
+            json!({"type": "TaskAdd", "name": "Drinker", "params": []}),
+        ];
+        for event in events.into_iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({"session": session_id, "number": number, "event": event})).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"WidgetMessage","sender":13,"kind":"take","arguments":[{"type":"Coord","value":{"x":1,"y":0}}]}"#,
+            "BotService port={}", bot_service.port
+        );
+        let content = json!([
+            {"type": "List", "value": [{"type": "Int", "value": 10050}]},
+            {"type": "List", "value": [{"type": "Int", "value": 10029}, {"type": "Float32", "value": 5.5}]},
+            {"type": "List", "value": [
+                {"type": "Int", "value": 10046},
+                {"type": "List", "value": [
+                    {"type": "List", "value": [{"type": "Int", "value": 10044}, {"type": "Str", "value": "3.00 l of Water"}]},
+                    {"type": "List", "value": [{"type": "Int", "value": 10029}, {"type": "Float32", "value": 20.0}]},
+                ]},
+            ]},
+        ]);
+        let events = vec![
+            json!({"type": "Destroy", "id": 13}),
+            json!({
+                "type": "NewWidget",
+                "id": 100000,
+                "kind": "item",
+                "parent": 6,
+                "pargs": [{"type": "Str", "value": "hand"}, {"type": "Coord", "value": {"x": 15, "y": 15}}],
+                "cargs": [{"type": "Int", "value": 2675}],
+            }),
+        ];
+        for event in events.into_iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({"session": session_id, "number": number, "event": event})).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let item_act = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(item_act["kind"].as_str(), Some("itemact"), "BotService port={}", bot_service.port);
+        let events = vec![
+            json!({"type": "UIMessage", "id": 100000, "msg": "tt", "args": content.clone()}),
+        ];
+        for event in events.into_iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({"session": session_id, "number": number, "event": event})).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"WidgetMessage","sender":8,"kind":"drop","arguments":[{"type":"Coord","value":{"x":1,"y":0}}]}"#,
+            "BotService port={}", bot_service.port
+        );
+        let events = vec![
+            json!({"type": "Destroy", "id": 100000}),
+            json!({
+                "type": "NewWidget",
+                "id": 100001,
+                "kind": "item",
+                "parent": 8,
+                "pargs": [{"type": "Coord", "value": {"x": 1, "y": 0}}],
+                "cargs": [{"type": "Int", "value": 2675}],
+            }),
+            json!({"type": "UIMessage", "id": 100001, "msg": "tt", "args": content}),
+        ];
+        for event in events.into_iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({"session": session_id, "number": number, "event": event})).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"LockWidget","value":"sm"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"WidgetMessage","sender":100001,"kind":"iact","arguments":[{"type":"Coord","value":{"x":0,"y":0}},{"type":"Int","value":0}]}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn transferrer_should_transfer_matching_items_from_container() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let events = vec![
+            json!({"type": "ResourceAdd", "id": 100000, "version": 1, "name": "gfx/invobjs/bucket"}),
+            json!({"type": "ResourceAdd", "id": 100001, "version": 1, "name": "gfx/invobjs/waterskin"}),
+            json!({
+                "type": "NewWidget",
+                "id": 100010,
+                "kind": "wnd",
+                "parent": 6,
+                "pargs": [],
+                "cargs": [{"type": "Coord", "value": {"x": 0, "y": 0}}, {"type": "Str", "value": "Cupboard"}],
+            }),
+            json!({
+                "type": "NewWidget",
+                "id": 100011,
+                "kind": "inv",
+                "parent": 100010,
+                "pargs": [],
+                "cargs": [{"type": "Coord", "value": {"x": 4, "y": 4}}],
+            }),
+            json!({
+                "type": "NewWidget",
+                "id": 100012,
+                "kind": "item",
+                "parent": 100011,
+                "pargs": [{"type": "Coord", "value": {"x": 0, "y": 0}}],
+                "cargs": [{"type": "Int", "value": 100001}],
+            }),
+            json!({
+                "type": "NewWidget",
+                "id": 100013,
+                "kind": "item",
+                "parent": 100011,
+                "pargs": [{"type": "Coord", "value": {"x": 1, "y": 0}}],
+                "cargs": [{"type": "Int", "value": 100000}],
+            }),
+            json!({
+                "type": "TaskAdd",
+                "name": "Transferrer",
+                "params": serde_json::to_vec(&json!({
+                    "container": "Cupboard",
+                    "direction": "FromContainer",
+                    "resources": ["gfx/invobjs/bucket"],
+                })).unwrap(),
+            }),
+        ];
+        for event in events.into_iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({"session": session_id, "number": number, "event": event})).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"WidgetMessage","sender":100013,"kind":"transfer","arguments":[{"type":"Coord","value":{"x":0,"y":0}},{"type":"Int","value":1}]}"#,
+            "BotService port={}", bot_service.port
+        );
+        number += 1;
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number,
+                "event": {"type": "Destroy", "id": 100013},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.poll(session_id).await,
+            r#"{"type":"Done","task":"Transferrer"}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn throttled_task_message_should_be_delivered_later() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let events = vec![
+            json!({
+                "type": "TaskAdd",
+                "name": "UiJanitor",
+                "params": serde_json::to_vec(&json!({
+                    "rules": [{"kind": "ui/expwnd:*", "action": "Close"}],
+                    "rate_limit": {"max_messages": 1, "interval": 2.0},
+                })).unwrap(),
+            }),
+            json!({"type": "NewWidget", "id": 100020, "kind": "ui/expwnd:1", "parent": 6, "pargs": [], "cargs": []}),
+            json!({"type": "NewWidget", "id": 100021, "kind": "ui/expwnd:1", "parent": 6, "pargs": [], "cargs": []}),
+        ];
+        for event in events.into_iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({"session": session_id, "number": number, "event": event})).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let add_task = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(add_task["kind"].as_str(), Some("add-task"), "BotService port={}", bot_service.port);
+        for id in [100020, 100021].iter() {
+            wait_for_message(&bot_service, session_id).await;
+            assert_eq!(
+                bot_service.poll(session_id).await,
+                format!(r#"{{"type":"WidgetMessage","sender":{},"kind":"close","arguments":[]}}"#, id),
+                "BotService port={}", bot_service.port
+            );
+        }
+    }).await;
+}
+
+#[actix_rt::test]
+async fn session_stats_should_be_written_on_close() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 1,
+                "event": {"type": "Close"},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        let mut stats = parse_json(&bot_service.session_stats(session_id).await);
+        while stats["value"]["finished"].is_null() {
+            sleep(Duration::from_secs(1));
+            stats = parse_json(&bot_service.session_stats(session_id).await);
+        }
+        assert_eq!(stats["type"].as_str(), Some("SessionStats"), "BotService port={}", bot_service.port);
+        assert_eq!(stats["value"]["session"].as_i64(), Some(session_id), "BotService port={}", bot_service.port);
+        assert!(
+            Path::new(&format!("tests/var/{}/sessions/{}.stats.json", bot_service.port, session_id)).exists(),
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn saved_session_should_be_restored_for_unknown_session() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 1,
+                "event": {"type": "Close"},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let sessions_path = format!("tests/var/{}/sessions", bot_service.port);
+        let saved_path = format!("{}/{}.session.json", sessions_path, session_id);
+        while !Path::new(&saved_path).exists() {
+            sleep(Duration::from_millis(100));
+        }
+        let saved = parse_json(&std::fs::read_to_string(&saved_path).unwrap());
+        let restored_session_id = session_id + 1;
+        std::fs::copy(&saved_path, format!("{}/{}.session.json", sessions_path, restored_session_id)).unwrap();
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": restored_session_id,
+                "number": number + 2,
+                "event": {"type": "GetSessionData"},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let restored = parse_json(&bot_service.get_session(restored_session_id).await);
+        assert_eq!(restored["type"].as_str(), Some("Session"), "BotService port={}", bot_service.port);
+        assert_eq!(restored["value"]["world"]["revision"], saved["world"]["revision"], "BotService port={}", bot_service.port);
+        assert_eq!(restored["value"]["player"], saved["player"], "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn session_diff_should_be_empty_for_own_session_data() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        let session = parse_json(&bot_service.get_session(session_id).await);
+        assert_eq!(session["type"].as_str(), Some("Session"), "BotService port={}", bot_service.port);
+        let diff = parse_json(&bot_service.session_diff(session_id, &session["value"]).await);
+        assert_eq!(diff["type"].as_str(), Some("SessionDiff"), "BotService port={}", bot_service.port);
+        assert_eq!(diff["value"]["missing_widgets"], json!([]), "BotService port={}", bot_service.port);
+        assert_eq!(diff["value"]["unexpected_objects"], json!([]), "BotService port={}", bot_service.port);
+        assert_eq!(diff["value"]["grid_revision_mismatches"], json!([]), "BotService port={}", bot_service.port);
+        assert_eq!(
+            bot_service.session_diff(session_id + 1, &session["value"]).await,
+            r#"{"type":"Error","message":"Session is not found"}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn journaled_updates_should_be_replayed_for_unknown_session() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        let updates = read_updates("tests/input/init_session_start.json");
+        for update in updates.iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let expected = parse_json(&bot_service.get_session(session_id).await);
+        let restored_session_id = session_id + 1;
+        let mut journal = String::new();
+        for update in updates.iter() {
+            let mut journaled = update.clone();
+            journaled["session"] = json!(restored_session_id);
+            journal.push_str(&format!("{}\n", journaled));
+        }
+        let sessions_path = format!("tests/var/{}/sessions", bot_service.port);
+        std::fs::create_dir_all(&sessions_path).unwrap();
+        std::fs::write(format!("{}/{}.journal.json", sessions_path, restored_session_id), journal).unwrap();
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": restored_session_id,
+                "number": number + 1,
+                "event": {"type": "GetSessionData"},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, restored_session_id).await;
+        let restored = parse_json(&bot_service.get_session(restored_session_id).await);
+        assert_eq!(restored["type"].as_str(), Some("Session"), "BotService port={}", bot_service.port);
+        assert_eq!(restored["value"]["last_update"].as_i64(), Some(number + 1), "BotService port={}", bot_service.port);
+        assert_eq!(restored["value"]["world"]["revision"], expected["value"]["world"]["revision"], "BotService port={}", bot_service.port);
+        assert_eq!(restored["value"]["player"], expected["value"]["player"], "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn journaled_updates_included_in_saved_session_should_be_skipped_on_restore() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        let updates = read_updates("tests/input/init_session_start.json");
+        for update in updates.iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 1,
+                "event": {"type": "Close"},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let sessions_path = format!("tests/var/{}/sessions", bot_service.port);
+        let saved_path = format!("{}/{}.session.json", sessions_path, session_id);
+        while !Path::new(&saved_path).exists() {
+            sleep(Duration::from_millis(100));
+        }
+        let saved = parse_json(&std::fs::read_to_string(&saved_path).unwrap());
+        assert_eq!(saved["last_update"].as_i64(), Some(number), "BotService port={}", bot_service.port);
+        let restored_session_id = session_id + 1;
+        std::fs::copy(&saved_path, format!("{}/{}.session.json", sessions_path, restored_session_id)).unwrap();
+        // Journal is not compacted yet and still holds updates already included into the saved session
+        let mut journal = String::new();
+        for update in updates.iter() {
+            let mut journaled = update.clone();
+            journaled["session"] = json!(restored_session_id);
+            journal.push_str(&format!("{}\n", journaled));
+        }
+        std::fs::write(format!("{}/{}.journal.json", sessions_path, restored_session_id), journal).unwrap();
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": restored_session_id,
+                "number": number + 2,
+                "event": {"type": "GetSessionData"},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, restored_session_id).await;
+        let restored = parse_json(&bot_service.get_session(restored_session_id).await);
+        assert_eq!(restored["type"].as_str(), Some("Session"), "BotService port={}", bot_service.port);
+        assert_eq!(restored["value"]["last_update"].as_i64(), Some(number + 2), "BotService port={}", bot_service.port);
+        assert_eq!(restored["value"]["world"]["revision"], saved["world"]["revision"], "BotService port={}", bot_service.port);
+        assert_eq!(restored["value"]["player"], saved["player"], "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn session_log_should_be_replayed_into_new_session() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let mut log = parse_json(&bot_service.session_log(session_id, number - 1).await);
+        while log["value"].as_array().map(|v| v.len()).unwrap_or(0) < 2 {
+            sleep(Duration::from_millis(100));
+            log = parse_json(&bot_service.session_log(session_id, number - 1).await);
+        }
+        assert_eq!(log["type"].as_str(), Some("SessionLog"), "BotService port={}", bot_service.port);
+        let numbers: Vec<i64> = log["value"].as_array().unwrap().iter()
+            .map(|v| v["number"].as_i64().unwrap())
+            .collect();
+        assert_eq!(numbers, vec![number - 1, number], "BotService port={}", bot_service.port);
+        let target = session_id + 1;
+        assert_eq!(
+            bot_service.replay(session_id, target).await, r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.replay(session_id, target).await, r#"{"type":"Error","message":"Target session already exists"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.push(&json!({"session": target, "number": number + 1, "event": {"type": "Close"}})).await,
+            r#"{"type":"Error","message":"Session is a replay"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, target).await;
+        assert_eq!(
+            bot_service.poll(target).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let expected = bot_service.player_position(session_id).await;
+        assert_eq!(parse_json(&expected)["type"].as_str(), Some("PlayerPosition"), "BotService port={}", bot_service.port);
+        let mut replayed = bot_service.player_position(target).await;
+        for _ in 0..50usize {
+            if replayed == expected {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+            replayed = bot_service.player_position(target).await;
+        }
+        assert_eq!(replayed, expected, "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn grid_of_interest_change_should_be_reported() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        bot_service.hello(session_id, &["GridsOfInterest"]).await;
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 1,
+                "event": {"type": "GridsOfInterest", "positions": [{"x": -10, "y": -10}]},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        let mut message = parse_json(&bot_service.poll(session_id).await);
+        while message["type"].as_str() != Some("GridOfInterestChanged") && message["type"].as_str() != Some("Ok") {
+            message = parse_json(&bot_service.poll(session_id).await);
+        }
+        assert_eq!(message["type"].as_str(), Some("GridOfInterestChanged"), "BotService port={}", bot_service.port);
+        assert_eq!(message["position"], json!({"x": -10, "y": -10}), "BotService port={}", bot_service.port);
+        assert_eq!(message["grid_id"].as_i64(), Some(5250373069530682842), "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn grid_of_interest_update_should_be_reported_after_it_was_set() {
+    with_bot_service(|bot_service| async move {
+        let updates = read_updates("tests/input/init_session_start.json");
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in updates.iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        bot_service.hello(session_id, &["GridsOfInterest"]).await;
+        let mut grid = updates.iter()
+            .find(|v| v["event"]["type"].as_str() == Some("MapGridAdd")
+                && v["event"]["grid"]["id"].as_i64() == Some(5250373069530682842))
+            .map(|v| v["event"]["grid"].clone())
+            .unwrap();
+        grid["heights"] = json!(vec![1.0; grid["heights"].as_array().unwrap().len()]);
+        let events = vec![
+            json!({"type": "GridsOfInterest", "positions": [{"x": -10, "y": -10}]}),
+            json!({"type": "MapGridUpdate", "grid": grid}),
+        ];
+        let mut revisions = Vec::new();
+        for event in events.into_iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({"session": session_id, "number": number, "event": event})).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            wait_updates(&bot_service, session_id).await;
+            let mut message = parse_json(&bot_service.poll(session_id).await);
+            while message["type"].as_str() != Some("GridOfInterestChanged") && message["type"].as_str() != Some("Ok") {
+                message = parse_json(&bot_service.poll(session_id).await);
+            }
+            assert_eq!(message["type"].as_str(), Some("GridOfInterestChanged"), "BotService port={}", bot_service.port);
+            assert_eq!(message["grid_id"].as_i64(), Some(5250373069530682842), "BotService port={}", bot_service.port);
+            revisions.push(message["revision"].as_i64().unwrap());
+        }
+        assert!(revisions[0] < revisions[1], "BotService port={} revisions={:?}", bot_service.port, revisions);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn grid_of_interest_change_should_not_be_reported_without_negotiated_capability() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.push(&json!({
+                "session": session_id,
+                "number": number + 1,
+                "event": {"type": "GridsOfInterest", "positions": [{"x": -10, "y": -10}]},
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        wait_updates(&bot_service, session_id).await;
+        let mut message = parse_json(&bot_service.poll(session_id).await);
+        while message["type"].as_str() != Some("GridOfInterestChanged") && message["type"].as_str() != Some("Ok") {
+            message = parse_json(&bot_service.poll(session_id).await);
+        }
+        assert_eq!(message["type"].as_str(), Some("Ok"), "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[cfg(feature = "fault_injection")]
+#[actix_rt::test]
+async fn injected_messages_stall_should_delay_poll() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").into_iter() {
+            assert_eq!(bot_service.push(&update).await, r#"{"type":"Ok"}"#);
+            session_id = update["session"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.inject_faults(&json!({
+                "drop_updates": 0,
+                "delay_updates": 0,
+                "corrupt_grids": 0,
+                "fail_map_db_writes": 0,
+                "stall_messages": 60,
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(bot_service.poll(session_id).await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        assert_eq!(
+            bot_service.inject_faults(&json!({
+                "drop_updates": 0,
+                "delay_updates": 0,
+                "corrupt_grids": 0,
+                "fail_map_db_writes": 0,
+                "stall_messages": 0,
+            })).await,
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn player_position_should_be_relative_to_anchor() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let local_snapshot = parse_json(&bot_service.world_snapshot(session_id, 0, "walk").await);
+        assert_eq!(
+            bot_service.add_anchor(session_id, 5250373069530682842).await, r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let position = parse_json(&bot_service.player_position(session_id).await);
+        assert_eq!(position["type"].as_str(), Some("PlayerPosition"), "BotService port={}", bot_service.port);
+        let grid_size = 100.0 * 11.0;
+        assert_eq!(
+            position["anchored"]["x"].as_f64(), position["position"]["x"].as_f64().map(|v| v + 10.0 * grid_size),
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            position["anchored"]["y"].as_f64(), position["position"]["y"].as_f64().map(|v| v + 10.0 * grid_size),
+            "BotService port={}", bot_service.port
+        );
+        let anchored_snapshot = parse_json(&bot_service.world_snapshot(session_id, 0, "walk").await);
+        assert_eq!(
+            anchored_snapshot["value"]["center"]["x"].as_i64(),
+            local_snapshot["value"]["center"]["x"].as_i64().map(|v| v + 10 * 100),
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            anchored_snapshot["value"]["center"]["y"].as_i64(),
+            local_snapshot["value"]["center"]["y"].as_i64().map(|v| v + 10 * 100),
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn area_objects_should_be_counted_by_name() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let position = parse_json(&bot_service.player_position(session_id).await);
+        let x = position["position"]["x"].as_f64().unwrap();
+        let y = position["position"]["y"].as_f64().unwrap();
+        let area = json!({
+            "type": "Rectangle",
+            "min": {"x": x - 110.0, "y": y - 110.0},
+            "max": {"x": x + 110.0, "y": y + 110.0},
+        });
+        let result = parse_json(&bot_service.area_objects(session_id, &area).await);
+        assert_eq!(result["type"].as_str(), Some("AreaObjects"), "BotService port={}", bot_service.port);
+        assert_eq!(result["value"]["tiles"].as_f64(), Some(400.0), "BotService port={}", bot_service.port);
+        assert_eq!(result["value"]["total"].as_u64(), Some(1), "BotService port={}", bot_service.port);
+        assert_eq!(
+            result["value"]["names"]["gfx/borka/wisp"]["count"].as_u64(), Some(1),
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn exported_map_should_be_png_image() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let (content_type, body) = bot_service.export_map(5250373069530682842).await;
+        assert_eq!(content_type, "image/png", "BotService port={}", bot_service.port);
+        assert_eq!(&body[0..8], b"\x89PNG\r\n\x1a\n", "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn visualization_should_serve_offscreen_frame_as_png() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        assert_eq!(
+            bot_service.add_visualization(session_id).await, r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let mut frame = bot_service.visualization(session_id).await;
+        for _ in 0..50 {
+            if frame.0 == "image/png" {
+                break;
+            }
+            sleep(Duration::from_millis(100));
+            frame = bot_service.visualization(session_id).await;
+        }
+        let (content_type, body) = frame;
+        assert_eq!(content_type, "image/png", "BotService port={}", bot_service.port);
+        assert_eq!(&body[0..8], b"\x89PNG\r\n\x1a\n", "BotService port={}", bot_service.port);
+        assert_eq!(&body[16..24], &[0, 0, 1, 64, 0, 0, 0, 240], "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn map_grids_and_tile_should_be_read_from_map_db() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let position = parse_json(&bot_service.player_position(session_id).await);
+        let segment_id = position["segment_id"].as_i64().unwrap();
+        let grids = parse_json(&bot_service.map_grids(segment_id).await);
+        assert_eq!(grids["type"].as_str(), Some("MapGrids"), "BotService port={}", bot_service.port);
+        let grid = &grids["value"][0];
+        let x = grid["position"]["x"].as_i64().unwrap() * 100 + 1;
+        let y = grid["position"]["y"].as_i64().unwrap() * 100 + 2;
+        let tile = parse_json(&bot_service.map_tile(segment_id, x, y).await);
+        assert_eq!(tile["type"].as_str(), Some("MapTile"), "BotService port={}", bot_service.port);
+        assert_eq!(tile["value"]["grid_id"], grid["id"], "BotService port={}", bot_service.port);
+        assert!(tile["value"]["name"].is_string(), "BotService port={}", bot_service.port);
+        let missing = parse_json(&bot_service.map_grids(segment_id + 1).await);
+        assert_eq!(missing["type"].as_str(), Some("Error"), "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn map_tile_stats_should_be_aggregated_from_map_db() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let position = parse_json(&bot_service.player_position(session_id).await);
+        let segment_id = position["segment_id"].as_i64().unwrap();
+        let grids = parse_json(&bot_service.map_grids(segment_id).await)["value"].as_array().unwrap().len() as i64;
+        let stats = parse_json(&bot_service.map_tile_stats(segment_id).await);
+        assert_eq!(stats["type"].as_str(), Some("TileStats"), "BotService port={}", bot_service.port);
+        let counts = stats["value"].as_array().unwrap().iter()
+            .map(|v| v["count"].as_i64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(counts.iter().sum::<i64>(), grids * 100 * 100, "BotService port={}", bot_service.port);
+        assert!(counts.windows(2).all(|v| v[0] >= v[1]), "BotService port={} counts={:?}", bot_service.port, counts);
+        let missing = parse_json(&bot_service.map_tile_stats(segment_id + 1).await);
+        assert_eq!(missing["type"].as_str(), Some("Error"), "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn map_segment_should_be_split_and_merged_back() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let position = parse_json(&bot_service.player_position(session_id).await);
+        let segment_id = position["segment_id"].as_i64().unwrap();
+        let grids = parse_json(&bot_service.map_grids(segment_id).await)["value"].as_array().unwrap().clone();
+        let grid = grids.iter().find(|v| v["id"].as_i64() != Some(segment_id)).unwrap();
+        let grid_id = grid["id"].as_i64().unwrap();
+        let split = parse_json(&bot_service.map_split_segment(segment_id, &vec![grid_id]).await);
+        assert_eq!(split["type"].as_str(), Some("MapGrids"), "BotService port={} split={}", bot_service.port, split);
+        assert_eq!(split["value"][0]["segment_id"].as_i64(), Some(grid_id), "BotService port={}", bot_service.port);
+        assert_eq!(split["value"][0]["position"], json!({"x": 0, "y": 0}), "BotService port={}", bot_service.port);
+        assert_eq!(split["value"][0]["revision"].as_i64(), Some(grid["revision"].as_i64().unwrap() + 1), "BotService port={}", bot_service.port);
+        let overlap = parse_json(&bot_service.map_merge_segments(grid_id, segment_id, 0, 0).await);
+        assert_eq!(overlap["type"].as_str(), Some("Error"), "BotService port={}", bot_service.port);
+        let merged = parse_json(&bot_service.map_merge_segments(
+            grid_id,
+            segment_id,
+            grid["position"]["x"].as_i64().unwrap(),
+            grid["position"]["y"].as_i64().unwrap(),
+        ).await);
+        assert_eq!(merged["type"].as_str(), Some("MapGrids"), "BotService port={}", bot_service.port);
+        let positions = |grids: &Vec<Value>| grids.iter()
+            .map(|v| (v["id"].as_i64().unwrap(), v["position"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(positions(merged["value"].as_array().unwrap()), positions(&grids), "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn map_prune_should_keep_grids_within_retention_policy() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let position = parse_json(&bot_service.player_position(session_id).await);
+        let segment_id = position["segment_id"].as_i64().unwrap();
+        let grids = parse_json(&bot_service.map_grids(segment_id).await)["value"].as_array().unwrap().len();
+        assert_eq!(
+            bot_service.map_prune().await,
+            r#"{"type":"MapPruned","value":{"grids":0,"segments":0}}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            parse_json(&bot_service.map_grids(segment_id).await)["value"].as_array().unwrap().len(), grids,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn zones_should_be_set_listed_and_removed() {
+    with_bot_service(|bot_service| async move {
+        let zone = json!({
+            "segment_id": 1,
+            "name": "field",
+            "points": [{"x": 0, "y": 0}, {"x": 4, "y": 0}, {"x": 4, "y": 2}, {"x": 0, "y": 2}],
+        });
+        assert_eq!(bot_service.set_zone(&zone).await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        let invalid = json!({"segment_id": 1, "name": "line", "points": [{"x": 0, "y": 0}, {"x": 4, "y": 0}]});
+        let error = parse_json(&bot_service.set_zone(&invalid).await);
+        assert_eq!(error["type"].as_str(), Some("Error"), "BotService port={}", bot_service.port);
+        let zones = parse_json(&bot_service.zones(1).await);
+        assert_eq!(zones["type"].as_str(), Some("Zones"), "BotService port={}", bot_service.port);
+        assert_eq!(zones["value"].as_array().map(|v| v.len()), Some(1), "BotService port={}", bot_service.port);
+        assert_eq!(zones["value"][0]["name"].as_str(), Some("field"), "BotService port={}", bot_service.port);
+        assert_eq!(zones["value"][0]["area"].as_f64(), Some(8.0), "BotService port={}", bot_service.port);
+        assert_eq!(zones["value"][0]["tiles"].as_i64(), Some(8), "BotService port={}", bot_service.port);
+        assert_eq!(bot_service.remove_zone(1, "field").await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        let missing = parse_json(&bot_service.remove_zone(1, "field").await);
+        assert_eq!(missing["type"].as_str(), Some("Error"), "BotService port={}", bot_service.port);
+        assert_eq!(bot_service.zones(1).await, r#"{"type":"Zones","value":[]}"#, "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn stored_segment_coordinates_should_be_relative_to_global_anchor() {
+    let configure = |config: &mut serde_yaml::Value| {
+        config["session"]["world"]["anchors"]["grids"] = serde_yaml::from_str("[5250373069530682842]").unwrap();
+    };
+    with_configured_bot_service(configure, |bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let position = parse_json(&bot_service.player_position(session_id).await);
+        let segment_id = position["segment_id"].as_i64().unwrap();
+        let grids = parse_json(&bot_service.map_grids(segment_id).await);
+        let anchor = grids["value"].as_array().unwrap().iter()
+            .find(|v| v["id"].as_i64() == Some(5250373069530682842))
+            .cloned();
+        assert_eq!(anchor.map(|v| v["position"].clone()), Some(json!({"x": 0, "y": 0})), "BotService port={}", bot_service.port);
+        let tile = parse_json(&bot_service.map_tile(segment_id, 1, 2).await);
+        assert_eq!(tile["value"]["grid_id"].as_i64(), Some(5250373069530682842), "BotService port={}", bot_service.port);
+        assert_eq!(tile["value"]["position"], json!({"x": 1, "y": 2}), "BotService port={}", bot_service.port);
+        let bookmark = json!({"segment_id": segment_id, "name": "mine", "position": {"x": 11.5, "y": -22.0}});
+        assert_eq!(bot_service.set_bookmark(&bookmark).await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        let bookmarks = parse_json(&bot_service.bookmarks(segment_id).await);
+        assert_eq!(bookmarks["value"][0]["position"], bookmark["position"], "BotService port={}", bot_service.port);
+        let zone = json!({"segment_id": segment_id, "name": "field", "points": [{"x": 0, "y": 0}, {"x": 2, "y": 0}, {"x": 2, "y": 2}]});
+        assert_eq!(bot_service.set_zone(&zone).await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        let zones = parse_json(&bot_service.zones(segment_id).await);
+        assert_eq!(zones["value"][0]["points"], zone["points"], "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn bookmarks_should_be_set_listed_and_removed() {
+    with_bot_service(|bot_service| async move {
+        let bookmark = json!({"segment_id": 1, "name": "mine", "position": {"x": 11.5, "y": -22.0}});
+        assert_eq!(bot_service.set_bookmark(&bookmark).await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        let invalid = json!({"segment_id": 1, "name": "", "position": {"x": 0.0, "y": 0.0}});
+        let error = parse_json(&bot_service.set_bookmark(&invalid).await);
+        assert_eq!(error["type"].as_str(), Some("Error"), "BotService port={}", bot_service.port);
+        assert_eq!(
+            bot_service.bookmarks(1).await,
+            r#"{"type":"Bookmarks","value":[{"segment_id":1,"name":"mine","position":{"x":11.5,"y":-22.0}}]}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(bot_service.bookmarks(2).await, r#"{"type":"Bookmarks","value":[]}"#, "BotService port={}", bot_service.port);
+        assert_eq!(bot_service.remove_bookmark(1, "mine").await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        let missing = parse_json(&bot_service.remove_bookmark(1, "mine").await);
+        assert_eq!(missing["type"].as_str(), Some("Error"), "BotService port={}", bot_service.port);
+        assert_eq!(bot_service.bookmarks(1).await, r#"{"type":"Bookmarks","value":[]}"#, "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn metrics_should_be_exposed_in_prometheus_text_format() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/init_session_start.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        wait_updates(&bot_service, session_id).await;
+        let metrics = bot_service.metrics().await;
+        let updates = metrics.lines()
+            .find(|v| v.starts_with(format!("hafen_bot_session_updates_total{{session=\"{}\"}} ", session_id).as_str()))
+            .and_then(|v| v.split(' ').last())
+            .and_then(|v| v.parse::<u64>().ok());
+        assert!(updates.unwrap_or(0) > 0, "BotService port={}", bot_service.port);
+        assert!(metrics.contains("# TYPE hafen_bot_path_finder_iterations histogram"), "BotService port={}", bot_service.port);
+        assert!(metrics.contains("hafen_bot_map_db_cache_hits_total "), "BotService port={}", bot_service.port);
+        assert!(metrics.contains("hafen_bot_map_db_cache_evictions_total "), "BotService port={}", bot_service.port);
+        assert!(metrics.contains("hafen_bot_map_db_cache_capacity 1000"), "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn ws_should_push_messages_and_count_deliveries() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        for update in read_updates("tests/input/new_session.json").into_iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+        }
+        let mut stream = bot_service.ws(session_id);
+        assert_eq!(
+            read_ws_text_frame(&mut stream), r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            parse_json(&bot_service.session_stats(session_id).await)["value"]["delivered_messages"],
+            json!({"WebSocket": 1}),
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn mutating_endpoints_should_require_auth_token() {
+    with_bot_service(|bot_service| async move {
+        let update = read_updates("tests/input/new_session.json").into_iter().next().unwrap();
+        let push = |token: Option<&'static str>| {
+            let mut request = Client::new()
+                .put(bot_service.url("push").as_str())
+                .body(serde_json::to_string(&update).unwrap())
+                .timeout(Duration::from_secs(5));
+            if let Some(token) = token {
+                request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+            request.send()
+        };
+        let response = push(None).await.unwrap();
+        assert_eq!(response.status(), 401, "BotService port={}", bot_service.port);
+        assert_eq!(
+            parse_json(&response.text().await.unwrap())["type"], "Error",
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(push(Some("invalid")).await.unwrap().status(), 401, "BotService port={}", bot_service.port);
+        assert_eq!(
+            push(Some(TEST_TOKEN)).await.unwrap().text().await.unwrap(), r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+        assert_eq!(
+            Client::new().get(bot_service.url("ping").as_str()).send().await.unwrap().text().await.unwrap(),
+            r#"{"type":"Ok"}"#,
+            "BotService port={}", bot_service.port
+        );
+    }).await;
+}
+
+#[actix_rt::test]
+async fn chat_commands_should_control_tasks_and_reply_to_chat() {
+    with_bot_service(|bot_service| async move {
+        let mut session_id = 0;
+        let mut number = 0;
+        for update in read_updates("tests/input/init_session_lake.json").iter() {
+            assert_eq!(
+                bot_service.push(&update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+            session_id = update["session"].as_i64().unwrap();
+            number = update["number"].as_i64().unwrap();
+        }
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let chat_message = |direction: &str, text: &str| json!({
+            "type": "WidgetMessage",
+            "id": 100000,
+            "msg": "msg",
+            "args": [{"type": "Str", "value": direction}, {"type": "Str", "value": text}],
+        });
+        let events = vec![
+            json!({
+                "type": "NewWidget",
+                "id": 100000,
+                "kind": "pmchat",
+                "parent": 5,
+                "pargs": [],
+                "cargs": [],
+            }),
+            chat_message("in", "!task add UiJanitor"),
+            chat_message("in", "!task clear"),
+            chat_message("out", "!task add UiJanitor"),
+            chat_message("out", "!task list"),
+            chat_message("out", "!task remove 1"),
+            chat_message("out", "!walk"),
+        ];
+        for event in events.into_iter() {
+            number += 1;
+            assert_eq!(
+                bot_service.push(&json!({"session": session_id, "number": number, "event": event})).await,
+                r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        wait_updates(&bot_service, session_id).await;
+        let mut replies = Vec::new();
+        while replies.len() < 4 {
+            wait_for_message(&bot_service, session_id).await;
+            let message = parse_json(&bot_service.poll(session_id).await);
+            if message["type"] == "UIMessage" && message["id"] == 100000 {
+                assert_eq!(message["kind"], "msg", "BotService port={}", bot_service.port);
+                replies.push(String::from(message["arguments"][1]["value"].as_str().unwrap()));
+            }
+        }
+        assert_eq!(replies[0], "Added task UiJanitor as 1", "BotService port={}", bot_service.port);
+        assert!(replies[1].starts_with("1 UiJanitor: "), "BotService port={} reply={}", bot_service.port, replies[1]);
+        assert_eq!(replies[2], "Removed task 1", "BotService port={}", bot_service.port);
+        assert_eq!(replies[3], r#"Unknown command: "walk""#, "BotService port={}", bot_service.port);
+    }).await;
+}
+
+#[actix_rt::test]
+async fn path_finder_should_bypass_obstacle_in_simulated_world() {
+    with_bot_service(|bot_service| async move {
+        let mut simulator = Simulator::new(1234)
+            .with_tiles("gfx/tiles/deep", Vec2i::new(55, 30), Vec2i::new(55, 70), true);
+        let session_id = simulator.session();
+        for update in simulator.start().iter() {
+            assert_eq!(
+                bot_service.push(update).await, r#"{"type":"Ok"}"#,
+                "BotService port={}", bot_service.port
+            );
+        }
+        assert_eq!(
+            bot_service.poll(session_id).await, r#"{"type":"GetSessionData"}"#,
+            "BotService port={}", bot_service.port
+        );
+        let task_add = json!({
+            "session": session_id,
+            "number": simulator.next_number(),
+            "event": {
+                "type": "TaskAdd",
+                "name": "PathFinder",
+                "params": [],
+            },
+        });
+        assert_eq!(bot_service.push(&task_add).await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        wait_updates(&bot_service, session_id).await;
+        wait_for_message(&bot_service, session_id).await;
+        let message = parse_json(&bot_service.poll(session_id).await);
+        assert_eq!(message["kind"], "add-task", "BotService port={}", bot_service.port);
+        let dst = Vec2f::new(60.5, 50.5) * TILE_SIZE;
+        let click = make_map_click(
+            session_id,
+            simulator.next_number(),
+            (dst.x() / RESOLUTION).floor() as i64,
+            (dst.y() / RESOLUTION).floor() as i64,
+        );
+        assert_eq!(bot_service.push(&click).await, r#"{"type":"Ok"}"#, "BotService port={}", bot_service.port);
+        for _ in 0..100usize {
+            wait_for_message(&bot_service, session_id).await;
+            let message = bot_service.poll(session_id).await;
+            if parse_json(&message)["type"] == "Done" {
+                break;
+            }
+            assert_eq!(simulator.handle(&message), Ok(()), "BotService port={}", bot_service.port);
+            while simulator.is_moving() {
+                for update in simulator.step(0.5).iter() {
+                    assert_eq!(
+                        bot_service.push(update).await, r#"{"type":"Ok"}"#,
+                        "BotService port={}", bot_service.port
+                    );
+                }
+            }
+            wait_updates(&bot_service, session_id).await;
+        }
+        assert!(
+            simulator.player_position().distance(dst) < TILE_SIZE,
+            "BotService port={} position={:?}", bot_service.port, simulator.player_position()
+        );
     }).await;
 }
 
-async fn with_bot_service<R: Future<Output=()>>(mut f: impl FnMut(BotService) -> R) {
+async fn with_bot_service<R: Future<Output=()>>(f: impl FnMut(BotService) -> R) {
+    with_configured_bot_service(|_| (), f).await
+}
+
+async fn with_configured_bot_service<R: Future<Output=()>>(configure: impl Fn(&mut serde_yaml::Value),
+                                                           mut f: impl FnMut(BotService) -> R) {
     std::env::set_var("RUST_LOG", "error");
     match env_logger::try_init() {
         _ => (),
@@ -344,18 +2429,21 @@ async fn with_bot_service<R: Future<Output=()>>(mut f: impl FnMut(BotService) ->
         _ => (),
     }
     std::fs::create_dir_all(format!("tests/var/{}", port)).unwrap();
-    let server = run_server(make_config(port)).unwrap();
+    let (server, shutdown) = run_server(make_config(port, configure)).unwrap();
     f(BotService { port }).await;
-    server.stop(true).await;
+    shutdown.stop_server(&server).await;
+    shutdown.run();
 }
 
+const TEST_TOKEN: &str = "test-token";
+
 struct BotService {
     port: Port,
 }
 
 impl BotService {
     async fn ping(&self) -> String {
-        Client::builder().build().unwrap()
+        self.client()
             .get(self.url("ping").as_str())
             .timeout(Duration::from_secs(5))
             .send().await.unwrap()
@@ -363,7 +2451,7 @@ impl BotService {
     }
 
     async fn sessions(&self) -> String {
-        Client::builder().build().unwrap()
+        self.client()
             .get(self.url("sessions").as_str())
             .timeout(Duration::from_secs(5))
             .send().await.unwrap()
@@ -371,7 +2459,7 @@ impl BotService {
     }
 
     async fn push(&self, update: &Value) -> String {
-        Client::builder().build().unwrap()
+        self.client()
             .put(self.url("push").as_str())
             .body(serde_json::to_string(update).unwrap())
             .timeout(Duration::from_secs(5))
@@ -379,8 +2467,26 @@ impl BotService {
             .text().await.unwrap()
     }
 
+    async fn hello(&self, session: i64, capabilities: &[&str]) {
+        let response = parse_json(&self.push(&json!({
+            "session": session,
+            "number": 0,
+            "event": {"type": "Hello", "version": 1, "capabilities": capabilities},
+        })).await);
+        assert_eq!(response["type"].as_str(), Some("Hello"), "BotService port={}", self.port);
+    }
+
+    async fn task_status(&self, session: i64) -> String {
+        self.client()
+            .get(self.url("task_status").as_str())
+            .query(&[("session", session)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
     async fn poll(&self, session: i64) -> String {
-        Client::builder().build().unwrap()
+        self.client()
             .get(self.url("poll").as_str())
             .query(&[("session", session)])
             .timeout(Duration::from_secs(5))
@@ -388,8 +2494,27 @@ impl BotService {
             .text().await.unwrap()
     }
 
+    async fn get_session(&self, session: i64) -> String {
+        self.client()
+            .get(self.url("get_session").as_str())
+            .query(&[("session", session)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn session_diff(&self, session: i64, session_data: &Value) -> String {
+        self.client()
+            .post(self.url("session_diff").as_str())
+            .query(&[("session", session)])
+            .body(serde_json::to_string(session_data).unwrap())
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
     async fn add_visualization(&self, session: i64) -> String {
-        Client::builder().build().unwrap()
+        self.client()
             .get(self.url("add_visualization").as_str())
             .query(&[("session", session)])
             .timeout(Duration::from_secs(5))
@@ -397,23 +2522,339 @@ impl BotService {
             .text().await.unwrap()
     }
 
+    async fn session_stats(&self, session: i64) -> String {
+        self.client()
+            .get(self.url("session_stats").as_str())
+            .query(&[("session", session)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn session_log(&self, session: i64, from: i64) -> String {
+        self.client()
+            .get(self.url("session_log").as_str())
+            .query(&[("session", session), ("from", from)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn replay(&self, session: i64, target: i64) -> String {
+        self.client()
+            .post(self.url("replay").as_str())
+            .query(&[("session", session), ("target", target)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    #[cfg(feature = "fault_injection")]
+    async fn inject_faults(&self, params: &Value) -> String {
+        self.client()
+            .post(self.url("inject_faults").as_str())
+            .body(serde_json::to_string(params).unwrap())
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn add_anchor(&self, session: i64, grid_id: i64) -> String {
+        self.client()
+            .post(self.url("add_anchor").as_str())
+            .query(&[("session", session), ("grid_id", grid_id)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn start_recording(&self, session: i64, name: &str) -> String {
+        self.client()
+            .post(self.url("start_recording").as_str())
+            .query(&[("session", session.to_string()), ("name", String::from(name))])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn stop_recording(&self, session: i64) -> String {
+        self.client()
+            .post(self.url("stop_recording").as_str())
+            .query(&[("session", session)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn player_position(&self, session: i64) -> String {
+        self.client()
+            .get(self.url("player_position").as_str())
+            .query(&[("session", session)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn area_objects(&self, session: i64, area: &Value) -> String {
+        self.client()
+            .post(self.url("area_objects").as_str())
+            .query(&[("session", session)])
+            .body(serde_json::to_string(area).unwrap())
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn find_path(&self, session: i64, params: &Value) -> String {
+        self.client()
+            .post(self.url("find_path").as_str())
+            .query(&[("session", session)])
+            .body(serde_json::to_string(params).unwrap())
+            .timeout(Duration::from_secs(30))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn line_of_sight(&self, session: i64, params: &Value) -> String {
+        self.client()
+            .post(self.url("line_of_sight").as_str())
+            .query(&[("session", session)])
+            .body(serde_json::to_string(params).unwrap())
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn world_snapshot(&self, session: i64, radius: i32, profile: &str) -> String {
+        self.client()
+            .get(self.url("world_snapshot").as_str())
+            .query(&[("session", session.to_string()), ("radius", radius.to_string()), ("profile", profile.to_string())])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn fields(&self, session: i64) -> String {
+        self.client()
+            .get(self.url("fields").as_str())
+            .query(&[("session", session)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn containers(&self, session: i64) -> String {
+        self.client()
+            .get(self.url("containers").as_str())
+            .query(&[("session", session)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn export_map(&self, segment_id: i64) -> (String, Vec<u8>) {
+        let response = self.client()
+            .get(self.url("export_map").as_str())
+            .query(&[("segment_id", segment_id)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap();
+        let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+        (content_type, response.bytes().await.unwrap().to_vec())
+    }
+
+    async fn visualization(&self, session: i64) -> (String, Vec<u8>) {
+        let response = self.client()
+            .get(self.url("visualization").as_str())
+            .query(&[("session", session)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap();
+        let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+        (content_type, response.bytes().await.unwrap().to_vec())
+    }
+
+    async fn map_grids(&self, segment: i64) -> String {
+        self.client()
+            .get(self.url("map/grids").as_str())
+            .query(&[("segment", segment)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn map_tile(&self, segment: i64, x: i64, y: i64) -> String {
+        self.client()
+            .get(self.url("map/tile").as_str())
+            .query(&[("segment", segment), ("x", x), ("y", y)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn map_merge_segments(&self, src: i64, dst: i64, shift_x: i64, shift_y: i64) -> String {
+        self.client()
+            .post(self.url("map/merge_segments").as_str())
+            .query(&[("src", src), ("dst", dst), ("shift_x", shift_x), ("shift_y", shift_y)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn map_tile_stats(&self, segment: i64) -> String {
+        self.client()
+            .get(self.url("map/tile_stats").as_str())
+            .query(&[("segment", segment)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn map_split_segment(&self, segment: i64, grid_ids: &Vec<i64>) -> String {
+        self.client()
+            .post(self.url("map/split_segment").as_str())
+            .query(&[("segment", segment)])
+            .body(serde_json::to_string(grid_ids).unwrap())
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn map_prune(&self) -> String {
+        self.client()
+            .post(self.url("map/prune").as_str())
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn zones(&self, segment: i64) -> String {
+        self.client()
+            .get(self.url("zones").as_str())
+            .query(&[("segment", segment)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn set_zone(&self, zone: &Value) -> String {
+        self.client()
+            .put(self.url("zones").as_str())
+            .body(serde_json::to_string(zone).unwrap())
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn remove_zone(&self, segment: i64, name: &str) -> String {
+        self.client()
+            .delete(self.url("zones").as_str())
+            .query(&[("segment", segment.to_string().as_str()), ("name", name)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn bookmarks(&self, segment: i64) -> String {
+        self.client()
+            .get(self.url("bookmarks").as_str())
+            .query(&[("segment", segment)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn set_bookmark(&self, bookmark: &Value) -> String {
+        self.client()
+            .put(self.url("bookmarks").as_str())
+            .body(serde_json::to_string(bookmark).unwrap())
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn remove_bookmark(&self, segment: i64, name: &str) -> String {
+        self.client()
+            .delete(self.url("bookmarks").as_str())
+            .query(&[("segment", segment.to_string().as_str()), ("name", name)])
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    async fn metrics(&self) -> String {
+        self.client()
+            .get(self.url("metrics").as_str())
+            .timeout(Duration::from_secs(5))
+            .send().await.unwrap()
+            .text().await.unwrap()
+    }
+
+    fn ws(&self, session: i64) -> TcpStream {
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", self.port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(format!(
+            "GET /ws?session={} HTTP/1.1\r\nHost: 127.0.0.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Authorization: Bearer {}\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            session, TEST_TOKEN
+        ).as_bytes()).unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        assert!(String::from_utf8(response).unwrap().starts_with("HTTP/1.1 101"));
+        stream
+    }
+
+    fn client(&self) -> Client {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(format!("Bearer {}", TEST_TOKEN).as_str()).unwrap());
+        Client::builder().default_headers(headers).build().unwrap()
+    }
+
     fn url(&self, endpoint: &str) -> String {
         format!("http://127.0.0.1:{}/{}", self.port, endpoint)
     }
 }
 
-fn make_config(port: Port) -> ServerConfig {
-    serde_yaml::from_str(format!(r"---
+fn make_config(port: Port, configure: impl Fn(&mut serde_yaml::Value)) -> ServerConfig {
+    let mut config: serde_yaml::Value = serde_yaml::from_str(format!(r"---
 bind_addr: '127.0.0.1:{0}'
-map_db_path: tests/var/{0}/map.db
-map_cache_ttl: 1
+grpc:
+  bind_addr: null
+  poll_interval: 0.01
+map_db:
+  backend: Sqlite
+  path: tests/var/{0}/map.db
+  url: postgres://hafen_bot@localhost/hafen_bot
+  cache_ttl: 1
+  cache_capacity: 1000
+  write_behind:
+    flush_interval: 0.05
+    batch_size: 100
+  retention:
+    interval: 3600
+    max_grids_per_segment: 100000
+    max_age: null
+    pinned_segments: []
+    vacuum: false
 process:
   sessions_path: tests/var/{0}/sessions
   write_updates_log: true
+  write_task_log: true
+  write_session_stats: true
   poll_timeout: 0.01
+  autosave_session: true
+  autosave_interval: 60
+  journal_updates: true
+  message_queue:
+    max_size: 1000
+    deduplicate_kinds: []
+    coalesce_repeated: true
 session:
   world:
     report_iterations: 100000
+    hierarchical_path_min_distance: 200
     found_transition_color: [ 1.0, 1.0, 1.0, 0.2 ]
     path_transition_color: [ 0.6, 0.8, 0.6, 0.8 ]
     shorten_path_transition_color: [ 0.4, 0.8, 0.4, 0.9 ]
@@ -425,6 +2866,102 @@ session:
       gfx/tiles/water: 3
     ice_tiles:
       gfx/tiles/ice: 1
+    movement_profiles:
+      walk:
+        gfx/tiles/beach: 1
+        gfx/tiles/dirt: 1
+        gfx/tiles/field: 1
+        gfx/tiles/grass: 1
+        gfx/tiles/heath: 1
+        gfx/tiles/leaf: 1
+        gfx/tiles/moor: 1
+        gfx/tiles/mountain: 2
+        gfx/tiles/ice: 1
+        gfx/tiles/owater: 3
+        gfx/tiles/swamp: 3
+      swim:
+        gfx/tiles/deep: 3
+        gfx/tiles/odeep: 3
+        gfx/tiles/owater: 1
+        gfx/tiles/water: 1
+      boat:
+        gfx/tiles/deep: 1
+        gfx/tiles/odeep: 1
+        gfx/tiles/owater: 3
+        gfx/tiles/water: 3
+    danger_zones:
+      animals: [ gfx/kritter/ ]
+      combat_widget: frv
+      aggro_distance: 110
+      min_approach: 1
+      radius: 55
+      ttl: 60
+      weight: 10
+      color: [ 1.0, 0.0, 0.0, 0.8 ]
+    obstacles:
+      gfx/terobjs/trees/: 5
+      gfx/terobjs/bumlings/: 7
+      gfx/terobjs/arch/palisade: 6
+      gfx/terobjs/arch/brickwall: 6
+      gfx/terobjs/arch/hwall: 5
+      gfx/terobjs/villa: 30
+    traversal:
+      openable:
+        gfx/terobjs/arch/palisadegate: 5
+        gfx/terobjs/arch/palisadebiggate: 5
+        gfx/terobjs/arch/brickwallgate: 5
+      open_distance: 16
+      open_timeout: 5
+    avoidance:
+      objects:
+        - gfx/kritter/bear
+        - gfx/kritter/boar
+        - gfx/kritter/lynx
+        - gfx/kritter/wolf
+        - gfx/kritter/badger
+        - gfx/kritter/moose
+        - gfx/kritter/troll
+        - gfx/kritter/mammoth
+        - gfx/kritter/wolverine
+        - gfx/kritter/walrus
+      radius: 44
+      weight: 20
+    roads:
+      tiles: [ gfx/tiles/paving/, gfx/tiles/road ]
+      base_profile: walk
+      cost_factor: 0.5
+      min_travel_distance: 1100
+      max_snap_distance: 220
+    containers:
+      objects:
+        - gfx/terobjs/cupboard
+        - gfx/terobjs/chest
+        - gfx/terobjs/largechest
+        - gfx/terobjs/crate
+      interaction_timeout: 5
+    stuck_tiles:
+      weight: 5
+      max_weight: 50
+      min_weight: 0.1
+      radius: 1
+      half_life: 300
+    breadcrumbs:
+      min_distance: 55
+      max_len: 1000
+    ghost_objects:
+      enabled: true
+      grace_updates: 50
+    fields:
+      tiles: [ gfx/tiles/field ]
+      min_tiles: 4
+    max_height_delta: 20
+    height_delta_weight: null
+    anchors:
+      grids: []
+      locations: {{}}
+    persistent_objects:
+      names: [ gfx/terobjs/ ]
+      remove_distance: 110
   player:
     meters:
       stamina: gfx/hud/meter/stam
@@ -434,18 +2971,49 @@ session:
       content: ui/tt/cont
       content_name: ui/tt/cn
       quality: ui/tt/q/quality
+    stuck_detector:
+      window: 1
+      min_displacement: 1
+      min_updates: 2
+      max_update_gap: 2
+      min_speed: 5
+  human_control:
+    idle_timeout: 3
+    bot_message_ttl: 5
+    actions: [ click, iact, itemact, take, drop, transfer, cl ]
+  chat_commands:
+    prefix: '!'
+    widget_kinds: [ pmchat ]
+  cooldowns:
+    buffs: {{}}
+  stuck_recovery:
+    delay: 2
+    interval: 0
+    move_distance: 22
+    actions: [ RandomMove, Replan, CancelTask ]
+  night:
+    paused_tasks: [ Explorer ]
   tasks:
     path_finder:
       find_path_max_shortcut_length: 25
       find_path_max_iterations: 100000
+      find_path_max_total_iterations: null
       max_next_point_shortcut_length: 50
     explorer:
       find_path_max_shortcut_length: 25
       find_path_max_iterations: 1000000
       max_next_point_shortcut_length: 50
+      frontier_path_cost_weight: 1
+      frontier_water_weight: 0.5
+      claim_ttl: 60
+      stale_grid_age: 3600
     drinker:
       open_belt_timeout: 1.0
       sip_timeout: 1.0
+      refill_timeout: 1.0
+      refill_retry_interval: 10.0
+      refill_max_distance: 50
+      refill_distance: 22
       max_stamina: 100
       stamina_threshold: 95
       liquid_containers:
@@ -461,9 +3029,109 @@ session:
         - name: Water
           action: Drink
           wait_interval: 3
+    forager:
+      names:
+        - gfx/terobjs/items/blueberry
+      max_distance: 1100
+      pick_distance: 15
+      pick_timeout: 3.0
+      find_path_max_shortcut_length: 25
+      find_path_max_iterations: 1000000
+      max_next_point_shortcut_length: 50
+      cluster_distance: 55
+    follower:
+      find_path_max_shortcut_length: 25
+      find_path_max_iterations: 1000000
+      max_next_point_shortcut_length: 50
+      follow_distance: 22
+      replan_distance: 33
+    transferrer:
+      transfer_timeout: 1
+    macro_player:
+      macros_path: tests/var/{0}/macros
+      max_delay: 0.1
+    wasm:
+      modules_path: tests/var/{0}/wasm
+      max_fuel: 1000000
+    farmer:
+      action_distance: 15
+      action_timeout: 1
+      crops:
+        carrot:
+          object: gfx/terobjs/plants/carrot
+          seed: gfx/invobjs/carrot
+          harvest_action: Harvest
+    fleer:
+      hostile_objects:
+        - gfx/kritter/bear/bear
+        - gfx/kritter/boar/boar
+        - gfx/kritter/lynx/lynx
+      player_object: gfx/borka/body
+      detect_distance: 220
+      safe_distance: 330
+      arrival_distance: 22
+      water_max_distance: 50
+      run_away_distance: 220
+    ui_janitor:
+      rules:
+        - kind: ui/expwnd:*
+          action: Close
+    idler:
+      min_interval: 60
+      max_interval: 180
+      activity_gap: 1
+      kind: hover
+    crafter:
+      recipes_path: tests/var/{0}/recipes
+      menu_widget: scm
+      make_widget: make
+      menu_timeout: 5
+      craft_timeout: 30
+    logout:
+      safe_position: null
+      arrival_distance: 22
+      max_walk_attempts: 3
+      enter_timeout: 10
+      actions:
+        - [lo]
+    miner:
+      wall_tiles:
+        - gfx/tiles/rocks
+      action: [mine]
+      action_distance: 15
+      mine_timeout: 1
+      max_mine_attempts: 3
+      rest_timeout: 30
+    rate_limits: {{}}
+map_replication:
+  role: Standalone
+  master_url: ''
+  sync_interval: 10
+  batch_size: 100
+  token: null
+alerting:
+  webhooks: []
+  rate_limit:
+    max_messages: 10
+    interval: 60
+  connection_lost_timeout: 60
+auth:
+  tokens: [test-token]
+  open_read_only: true
+schedule:
+  interval: 0.1
+  entries: []
 visualization:
-  window_type: SDL2
-", port).as_str()).unwrap()
+  window_type: Offscreen
+  combined: false
+  texture_workers: 2
+  offscreen:
+    width: 320
+    height: 240
+    frame_interval: 0.1
+", port).as_str()).unwrap();
+    configure(&mut config);
+    serde_yaml::from_value(config).unwrap()
 }
 
 fn read_updates<P: AsRef<Path>>(path: P) -> Vec<Value> {
@@ -506,6 +3174,28 @@ async fn wait_for_message(bot_service: &BotService, session_id: i64) {
     }
 }
 
+fn read_ws_text_frame(stream: &mut TcpStream) -> String {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).unwrap();
+    assert_eq!(header[0], 0x81);
+    let length = match header[1] {
+        126 => {
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).unwrap();
+            u16::from_be_bytes(length) as usize
+        }
+        127 => {
+            let mut length = [0u8; 8];
+            stream.read_exact(&mut length).unwrap();
+            u64::from_be_bytes(length) as usize
+        }
+        v => v as usize,
+    };
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).unwrap();
+    String::from_utf8(payload).unwrap()
+}
+
 fn parse_json(text: &String) -> Value {
     serde_json::from_str::<Value>(text).unwrap()
 }
@@ -521,9 +3211,6 @@ fn get_map_click_coord(value: &Value) -> Coord {
     Coord { x: coord["x"].as_i64().unwrap(), y: coord["y"].as_i64().unwrap() }
 }
 
-const TILE_SIZE: f64 = 11.0;
-const RESOLUTION: f64 = hexf64!("0x1.0p-10") * TILE_SIZE;
-
 fn make_map_click(session_id: i64, number: i64, x: i64, y: i64) -> Value {
     json!({
         "session": session_id,